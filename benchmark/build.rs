@@ -2,6 +2,7 @@ extern crate capnpc;
 
 fn main() {
     ::capnpc::CompilerCommand::new()
+        .builtin_compiler()
         .file("eval.capnp")
         .file("catrank.capnp")
         .file("carsales.capnp")
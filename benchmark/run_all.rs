@@ -23,7 +23,25 @@ extern crate capnp;
 
 use std::{env, process, time};
 
-fn run_one(executable: &str, case: &str, mode: &str, scratch: &str, compression: &str, iteration_count: u64) {
+/// The outcome of running one configuration of one benchmark case.
+struct RunResult {
+    case: String,
+    mode: String,
+    compression: String,
+    scratch: String,
+    iteration_count: u64,
+    elapsed: time::Duration,
+}
+
+impl RunResult {
+    fn ops_per_sec(&self) -> f64 {
+        self.iteration_count as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+fn run_one(executable: &str, case: &str, mode: &str, scratch: &str, compression: &str, iteration_count: u64)
+           -> RunResult
+{
     let mut command = process::Command::new(executable);
     command.arg(case)
         .arg(mode)
@@ -40,23 +58,45 @@ fn run_one(executable: &str, case: &str, mode: &str, scratch: &str, compression:
         panic!("failed to run test case");
     }
 
-    println!("{}\n", elapsed.as_secs_f64());
+    let result = RunResult {
+        case: case.to_string(),
+        mode: mode.to_string(),
+        compression: compression.to_string(),
+        scratch: scratch.to_string(),
+        iteration_count,
+        elapsed,
+    };
+    println!("{} s, {:.0} ops/sec\n", elapsed.as_secs_f64(), result.ops_per_sec());
+    result
 }
 
-fn run_case(executable: &str, case: &str, scratch_options: &[&str], iteration_count: u64) {
+fn run_case(executable: &str, case: &str, scratch_options: &[&str], iteration_count: u64,
+            results: &mut Vec<RunResult>) {
     for scratch in scratch_options {
-        run_one(executable, case, "object", scratch, "none", iteration_count);
+        results.push(run_one(executable, case, "object", scratch, "none", iteration_count));
     }
 
     for mode in &["bytes", "pipe"] {
         for compression in &["none", "packed"] {
             for scratch in scratch_options {
-                run_one(executable, case, mode, scratch, compression, iteration_count);
+                results.push(run_one(executable, case, mode, scratch, compression, iteration_count));
             }
         }
     }
 }
 
+/// Prints one line per run, in a format that lines up with the output of the
+/// capnproto-c++ benchmark runner so that the numbers can be compared directly.
+fn print_summary(results: &[RunResult]) {
+    println!("{:<10} {:<8} {:<8} {:<10} {:>12} {:>12} {:>14}",
+             "case", "mode", "packing", "reuse", "iterations", "seconds", "ops/sec");
+    for r in results {
+        println!("{:<10} {:<8} {:<8} {:<10} {:>12} {:>12.3} {:>14.0}",
+                 r.case, r.mode, r.compression, r.scratch, r.iteration_count,
+                 r.elapsed.as_secs_f64(), r.ops_per_sec());
+    }
+}
+
 fn try_main() -> ::capnp::Result<()> {
     let args: Vec<String> = env::args().collect();
 
@@ -71,15 +111,18 @@ fn try_main() -> ::capnp::Result<()> {
     };
 
     let executable = &*args[1];
+    let mut results = Vec::new();
 
     println!("running carsales with {} iterations", carsales_iters);
-    run_case(executable, "carsales", &["reuse", "no-reuse"], carsales_iters);
+    run_case(executable, "carsales", &["reuse", "no-reuse"], carsales_iters, &mut results);
 
     println!("running catrank with {} iterations", catrank_iters);
-    run_case(executable, "catrank", &["no-reuse"], catrank_iters);
+    run_case(executable, "catrank", &["no-reuse"], catrank_iters, &mut results);
 
     println!("running eval with {} iterations", eval_iters);
-    run_case(executable, "eval", &["no-reuse"], eval_iters);
+    run_case(executable, "eval", &["no-reuse"], eval_iters, &mut results);
+
+    print_summary(&results);
 
     Ok(())
 }
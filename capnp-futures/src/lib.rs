@@ -24,5 +24,6 @@ pub use read_stream::ReadStream;
 pub use write_queue::{write_queue, Sender};
 
 pub mod serialize;
+pub mod shared_memory;
 mod read_stream;
 mod write_queue;
@@ -53,7 +53,7 @@ async fn read_segment_table<R>(mut reader: R,
             reader.read_exact(&mut buf[n..]).await?;
         }
     }
-    let (segment_count, first_segment_length) = parse_segment_table_first(&buf[..])?;
+    let (segment_count, first_segment_length) = parse_segment_table_first(&buf[..], options)?;
 
     let mut segment_lengths_builder = SegmentLengthsBuilder::with_capacity(segment_count);
     segment_lengths_builder.push_segment(first_segment_length);
@@ -107,13 +107,11 @@ async fn read_segments<R>(mut read: R,
 ///
 /// Returns the segment count and first segment length, or a state if the
 /// read would block.
-fn parse_segment_table_first(buf: &[u8]) -> Result<(usize, usize)>
+fn parse_segment_table_first(buf: &[u8], options: message::ReaderOptions) -> Result<(usize, usize)>
 {
-    let segment_count = u32::from_le_bytes(buf[0..4].try_into().unwrap()).wrapping_add(1);
-    if segment_count >= 512 {
+    let segment_count = u32::from_le_bytes(buf[0..4].try_into().unwrap()) as u64 + 1;
+    if segment_count > options.segment_count_limit as u64 {
         return Err(Error::failed(format!("Too many segments: {}", segment_count)))
-    } else if segment_count == 0 {
-        return Err(Error::failed(format!("Too few segments: {}", segment_count)))
     }
 
     let first_segment_len = u32::from_le_bytes(buf[4..8].try_into().unwrap());
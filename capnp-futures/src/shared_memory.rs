@@ -0,0 +1,572 @@
+// Copyright (c) 2013-2016 Sandstorm Development Group, Inc. and contributors
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! A single-producer, single-consumer ring buffer for passing messages between processes
+//! through shared memory.
+//!
+//! The caller is responsible for mapping a region of memory into both processes (e.g. with
+//! `shm_open()` and `mmap()`), for calling `initialize()` on it once, and for providing a
+//! `Signal` that lets each side wake the other. One side then constructs a `Producer` over the
+//! region and the other a `Consumer`. For two-way communication, use two regions.
+//!
+//! Messages can be passed in two ways:
+//!
+//!   * `Producer::write_message()` places each message in the ring as a single record, and
+//!     `Consumer::read_message()` returns a `ReceivedMessage` that reads it in place, without
+//!     copying. The space is released when the `ReceivedMessage` is dropped.
+//!   * `Producer` implements `AsyncWrite` and `Consumer` implements `AsyncRead`, so the ring can
+//!     serve as a byte stream for `capnp_futures::serialize` or for `capnp_rpc::twoparty`.
+//!
+//! The two styles should not be mixed on the same ring.
+//!
+//! Both processes must trust each other: the consumer validates record lengths, but a
+//! producer that modifies a record after publishing it can cause the consumer to observe
+//! inconsistent data.
+
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+
+use capnp::{message, Error, Result};
+use capnp::serialize::SliceSegments;
+
+use futures::{AsyncRead, AsyncWrite};
+
+use crate::serialize::AsOutputSegments;
+
+// The header holds the consumer's position, then (on a separate cache line) the producer's
+// position and a flag that the producer sets when it is done writing.
+const HEAD_OFFSET: usize = 0;
+const TAIL_OFFSET: usize = 64;
+const CLOSED_OFFSET: usize = 72;
+const HEADER_BYTES: usize = 128;
+
+// Each record is a little-endian u64 length followed by that many bytes, padded to a multiple
+// of eight. Records never wrap around the end of the ring; if one doesn't fit, the producer
+// fills the rest of the ring with a padding record and starts again at the beginning.
+const RECORD_HEADER_BYTES: usize = 8;
+const PADDING_RECORD: u64 = u64::max_value();
+
+/// Cross-process notification, supplied by the user of a `Producer` or `Consumer`. Typical
+/// implementations wrap an eventfd, a futex, or a pipe.
+pub trait Signal {
+    /// Wakes the other side. The producer calls this after publishing data, and the consumer
+    /// calls it after releasing space.
+    fn notify(&self);
+
+    /// Returns `Ready` if the other side has called `notify()` since the last time this
+    /// returned `Ready`. Otherwise, arranges for `cx` to be woken when it does and returns
+    /// `Pending`.
+    fn poll_wait(&self, cx: &mut Context) -> Poll<()>;
+}
+
+/// Prepares `len` bytes at `ptr` to be used as a ring. This should be done exactly once, before
+/// either side attaches.
+///
+/// UNSAFETY ALERT: `ptr` must be valid for writes of `len` bytes.
+pub unsafe fn initialize(ptr: *mut u8, len: usize) -> Result<()> {
+    let ring = Ring::new(ptr, len)?;
+    ring.head().store(0, Ordering::Relaxed);
+    ring.tail().store(0, Ordering::Relaxed);
+    ring.closed().store(0, Ordering::Release);
+    Ok(())
+}
+
+struct Ring {
+    base: *mut u8,
+    capacity: usize,
+}
+
+impl Ring {
+    unsafe fn new(ptr: *mut u8, len: usize) -> Result<Ring> {
+        if ptr as usize % 8 != 0 {
+            return Err(Error::failed("shared memory region must be 8-byte aligned".to_string()));
+        }
+        if len < HEADER_BYTES + 4 * RECORD_HEADER_BYTES {
+            return Err(Error::failed(format!("shared memory region of {} bytes is too small", len)));
+        }
+        Ok(Ring { base: ptr, capacity: (len - HEADER_BYTES) & !7 })
+    }
+
+    fn word(&self, offset: usize) -> &AtomicU64 {
+        unsafe { &*(self.base.add(offset) as *const AtomicU64) }
+    }
+
+    fn head(&self) -> &AtomicU64 { self.word(HEAD_OFFSET) }
+    fn tail(&self) -> &AtomicU64 { self.word(TAIL_OFFSET) }
+    fn closed(&self) -> &AtomicU64 { self.word(CLOSED_OFFSET) }
+
+    fn data(&self, offset: usize) -> *mut u8 {
+        unsafe { self.base.add(HEADER_BYTES + offset) }
+    }
+}
+
+fn record_size(len: usize) -> usize {
+    RECORD_HEADER_BYTES + ((len + 7) & !7)
+}
+
+/// The writing end of a ring.
+pub struct Producer<S> where S: Signal {
+    ring: Ring,
+    signal: S,
+}
+
+impl <S> Producer<S> where S: Signal {
+    /// Attaches to a ring that has been set up with `initialize()`.
+    ///
+    /// UNSAFETY ALERT: `ptr` must be valid for reads and writes of `len` bytes for as long as the
+    /// `Producer` exists, and no other `Producer` may be attached to the same ring.
+    pub unsafe fn new(ptr: *mut u8, len: usize, signal: S) -> Result<Producer<S>> {
+        Ok(Producer { ring: Ring::new(ptr, len)?, signal: signal })
+    }
+
+    /// The largest payload that can be placed in a single record. Records take up at most half
+    /// the ring, so that once the consumer has caught up, a record always fits, either after the
+    /// previous one or, behind a padding record, at the beginning of the ring.
+    pub fn max_record_len(&self) -> usize {
+        (self.ring.capacity / 2 & !7) - RECORD_HEADER_BYTES
+    }
+
+    /// Reserves space for a record with `len` bytes of payload, returning the record's
+    /// position, or `None` if there isn't enough free space.
+    fn try_reserve(&mut self, len: usize) -> Option<u64> {
+        let capacity = self.ring.capacity as u64;
+        let needed = record_size(len) as u64;
+        let mut tail = self.ring.tail().load(Ordering::Relaxed);
+        let head = self.ring.head().load(Ordering::Acquire);
+        let free = capacity - (tail - head);
+        let contiguous = capacity - tail % capacity;
+        if contiguous < needed {
+            if free < contiguous + needed {
+                return None;
+            }
+            unsafe {
+                (self.ring.data((tail % capacity) as usize) as *mut u64).write(PADDING_RECORD.to_le());
+            }
+            tail += contiguous;
+            self.ring.tail().store(tail, Ordering::Release);
+        } else if free < needed {
+            return None;
+        }
+        Some(tail)
+    }
+
+    /// Publishes a record previously reserved with `try_reserve()`.
+    fn commit(&mut self, position: u64, len: usize) {
+        let offset = (position % self.ring.capacity as u64) as usize;
+        unsafe {
+            (self.ring.data(offset) as *mut u64).write((len as u64).to_le());
+        }
+        self.ring.tail().store(position + record_size(len) as u64, Ordering::Release);
+        self.signal.notify();
+    }
+
+    fn poll_reserve(&mut self, cx: &mut Context, len: usize) -> Poll<u64> {
+        loop {
+            if let Some(position) = self.try_reserve(len) {
+                return Poll::Ready(position);
+            }
+            match self.signal.poll_wait(cx) {
+                Poll::Ready(()) => continue,
+                Poll::Pending => {
+                    // Space may have been released before `poll_wait()` registered our waker.
+                    return match self.try_reserve(len) {
+                        Some(position) => Poll::Ready(position),
+                        None => Poll::Pending,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Places `message` in the ring as a single record, waiting for space if necessary.
+    pub async fn write_message<M>(&mut self, message: M) -> Result<()> where M: AsOutputSegments {
+        let segments = message.as_output_segments();
+        let table_len = (segments.len() / 2 + 1) * 8;
+        let len = table_len + segments.iter().map(|s| s.len()).sum::<usize>();
+        if len > self.max_record_len() {
+            return Err(Error::failed(
+                format!("Message of {} bytes does not fit in a shared memory ring of {} bytes.",
+                        len, self.ring.capacity)));
+        }
+        let position = futures::future::poll_fn(|cx| self.poll_reserve(cx, len)).await;
+        let offset = (position % self.ring.capacity as u64) as usize;
+        let mut slot = unsafe {
+            std::slice::from_raw_parts_mut(self.ring.data(offset + RECORD_HEADER_BYTES), len)
+        };
+        capnp::serialize::write_message_segments(&mut slot, &segments)?;
+        self.commit(position, len);
+        Ok(())
+    }
+
+    /// Tells the consumer that no more data will be written. After it has read everything that
+    /// was written before this call, the consumer sees end-of-stream.
+    pub fn finish(&mut self) {
+        self.ring.closed().store(1, Ordering::Release);
+        self.signal.notify();
+    }
+}
+
+impl <S> AsyncWrite for Producer<S> where S: Signal + Unpin {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        // Write as much as fits in one record, which also lets the consumer start on one record
+        // while we write the next.
+        let len = std::cmp::min(buf.len(), this.max_record_len());
+        let position = match this.poll_reserve(cx, len) {
+            Poll::Ready(position) => position,
+            Poll::Pending => return Poll::Pending,
+        };
+        let offset = (position % this.ring.capacity as u64) as usize;
+        unsafe {
+            std::ptr::copy_nonoverlapping(buf.as_ptr(), this.ring.data(offset + RECORD_HEADER_BYTES), len);
+        }
+        this.commit(position, len);
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        self.get_mut().finish();
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// The reading end of a ring.
+pub struct Consumer<S> where S: Signal {
+    ring: Ring,
+    signal: S,
+
+    // Number of payload bytes of the current record already returned by `poll_read()`.
+    partial: usize,
+}
+
+impl <S> Consumer<S> where S: Signal {
+    /// Attaches to a ring that has been set up with `initialize()`.
+    ///
+    /// UNSAFETY ALERT: `ptr` must be valid for reads and writes of `len` bytes for as long as the
+    /// `Consumer` exists, and no other `Consumer` may be attached to the same ring.
+    pub unsafe fn new(ptr: *mut u8, len: usize, signal: S) -> Result<Consumer<S>> {
+        Ok(Consumer { ring: Ring::new(ptr, len)?, signal: signal, partial: 0 })
+    }
+
+    /// Returns the offset and length of the payload of the next record, `Ok(None)` if the
+    /// producer has closed the ring and everything has been read, or `Err(None)` if the ring
+    /// is currently empty.
+    fn try_next_record(&mut self) -> ::std::result::Result<Option<(usize, usize)>, Option<Error>> {
+        let capacity = self.ring.capacity as u64;
+        loop {
+            let head = self.ring.head().load(Ordering::Relaxed);
+            // Read `closed` before `tail`, so that we can't miss data written just before closing.
+            let closed = self.ring.closed().load(Ordering::Acquire) != 0;
+            let tail = self.ring.tail().load(Ordering::Acquire);
+            if head == tail {
+                return if closed { Ok(None) } else { Err(None) };
+            }
+            let offset = (head % capacity) as usize;
+            let len = u64::from_le(unsafe { (self.ring.data(offset) as *const u64).read() });
+            let contiguous = capacity - head % capacity;
+            if len == PADDING_RECORD {
+                self.ring.head().store(head + contiguous, Ordering::Release);
+                continue;
+            }
+            if len > contiguous - RECORD_HEADER_BYTES as u64 ||
+                record_size(len as usize) as u64 > tail - head
+            {
+                return Err(Some(Error::failed(
+                    format!("Shared memory ring contains a corrupt record of length {}.", len))));
+            }
+            return Ok(Some((offset + RECORD_HEADER_BYTES, len as usize)));
+        }
+    }
+
+    fn poll_next_record(&mut self, cx: &mut Context) -> Poll<Result<Option<(usize, usize)>>> {
+        loop {
+            match self.try_next_record() {
+                Ok(r) => return Poll::Ready(Ok(r)),
+                Err(Some(e)) => return Poll::Ready(Err(e)),
+                Err(None) => (),
+            }
+            match self.signal.poll_wait(cx) {
+                Poll::Ready(()) => continue,
+                Poll::Pending => {
+                    // Data may have been published before `poll_wait()` registered our waker.
+                    return match self.try_next_record() {
+                        Ok(r) => Poll::Ready(Ok(r)),
+                        Err(Some(e)) => Poll::Ready(Err(e)),
+                        Err(None) => Poll::Pending,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Frees the space of the record at the head of the ring.
+    fn release(&mut self, len: usize) {
+        let head = self.ring.head().load(Ordering::Relaxed);
+        self.ring.head().store(head + record_size(len) as u64, Ordering::Release);
+        self.partial = 0;
+        self.signal.notify();
+    }
+
+    /// Waits for the next record, which should have been written by `Producer::write_message()`.
+    /// Returns `None` if the producer has closed the ring.
+    pub async fn read_message<'a>(&'a mut self) -> Result<Option<ReceivedMessage<'a, S>>> {
+        if self.partial != 0 {
+            return Err(Error::failed(
+                "Cannot read a message from a shared memory ring in the middle of a record.".to_string()));
+        }
+        let next = futures::future::poll_fn(|cx| self.poll_next_record(cx)).await?;
+        Ok(next.map(move |(offset, len)| {
+            let bytes = unsafe { std::slice::from_raw_parts(self.ring.data(offset) as *const u8, len) };
+            ReceivedMessage { consumer: self, bytes: bytes }
+        }))
+    }
+}
+
+impl <S> AsyncRead for Consumer<S> where S: Signal + Unpin {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let (offset, len) = match this.poll_next_record(cx) {
+            Poll::Ready(Ok(Some(r))) => r,
+            Poll::Ready(Ok(None)) => return Poll::Ready(Ok(0)),
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(io::Error::new(io::ErrorKind::InvalidData, e))),
+            Poll::Pending => return Poll::Pending,
+        };
+        let n = std::cmp::min(buf.len(), len - this.partial);
+        unsafe {
+            std::ptr::copy_nonoverlapping(this.ring.data(offset + this.partial), buf.as_mut_ptr(), n);
+        }
+        this.partial += n;
+        if this.partial == len {
+            this.release(len);
+        }
+        Poll::Ready(Ok(n))
+    }
+}
+
+/// A message that is still in the ring. Its space is released when this is dropped.
+pub struct ReceivedMessage<'a, S> where S: Signal {
+    consumer: &'a mut Consumer<S>,
+    bytes: &'a [u8],
+}
+
+impl <'a, S> ReceivedMessage<'a, S> where S: Signal {
+    /// Returns the message's bytes in the standard stream framing.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.bytes
+    }
+
+    /// Reads the message in place.
+    pub fn get(&self, options: message::ReaderOptions) -> Result<message::Reader<SliceSegments<'_>>> {
+        let mut bytes = self.bytes;
+        capnp::serialize::read_message_from_flat_slice(&mut bytes, options)
+    }
+}
+
+impl <'a, S> Drop for ReceivedMessage<'a, S> where S: Signal {
+    fn drop(&mut self) {
+        let len = self.bytes.len();
+        self.consumer.release(len);
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::task::{Context, Poll};
+
+    use futures::task::AtomicWaker;
+    use futures::{AsyncReadExt, AsyncWriteExt, FutureExt};
+
+    use capnp::{any_pointer, message, primitive_list};
+
+    use super::{initialize, Consumer, Producer, Signal};
+
+    /// An in-process stand-in for a cross-process signal.
+    #[derive(Clone, Default)]
+    struct TestSignal {
+        // Each side waits on its own flag and notifies the other's.
+        mine: Arc<(AtomicBool, AtomicWaker)>,
+        theirs: Arc<(AtomicBool, AtomicWaker)>,
+    }
+
+    impl TestSignal {
+        fn pair() -> (TestSignal, TestSignal) {
+            let a = TestSignal::default();
+            let b = TestSignal { mine: a.theirs.clone(), theirs: a.mine.clone() };
+            (a, b)
+        }
+    }
+
+    impl Signal for TestSignal {
+        fn notify(&self) {
+            self.theirs.0.store(true, Ordering::SeqCst);
+            self.theirs.1.wake();
+        }
+
+        fn poll_wait(&self, cx: &mut Context) -> Poll<()> {
+            self.mine.1.register(cx.waker());
+            if self.mine.0.swap(false, Ordering::SeqCst) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        }
+    }
+
+    fn ring(words: &mut Vec<u64>) -> (Producer<TestSignal>, Consumer<TestSignal>) {
+        let ptr = words.as_mut_ptr() as *mut u8;
+        let len = words.len() * 8;
+        let (a, b) = TestSignal::pair();
+        unsafe {
+            initialize(ptr, len).unwrap();
+            (Producer::new(ptr, len, a).unwrap(), Consumer::new(ptr, len, b).unwrap())
+        }
+    }
+
+    fn build_message(n: u32) -> message::Builder<message::HeapAllocator> {
+        let mut message = message::Builder::new_default();
+        {
+            let root: any_pointer::Builder = message.init_root();
+            let mut list: primitive_list::Builder<u32> = root.initn_as(n);
+            for i in 0..n {
+                list.set(i, n + i);
+            }
+        }
+        message
+    }
+
+    fn check_message(message: message::Reader<impl message::ReaderSegments>, n: u32) {
+        let root: any_pointer::Reader = message.get_root().unwrap();
+        let list: primitive_list::Reader<u32> = root.get_as().unwrap();
+        assert_eq!(list.len(), n);
+        for i in 0..n {
+            assert_eq!(list.get(i), n + i);
+        }
+    }
+
+    #[test]
+    fn zero_copy_messages() {
+        // Small enough that the producer has to wait for the consumer and wrap around.
+        let mut words = vec![0u64; 64];
+        let (mut producer, mut consumer) = ring(&mut words);
+
+        let write = async move {
+            for n in 0..100 {
+                producer.write_message(&build_message(n % 30)).await.unwrap();
+            }
+            assert!(producer.write_message(&build_message(200)).await.is_err());
+            producer.finish();
+        };
+        let read = async move {
+            let mut count = 0;
+            while let Some(received) = consumer.read_message().await.unwrap() {
+                check_message(received.get(Default::default()).unwrap(), count % 30);
+                count += 1;
+            }
+            assert_eq!(count, 100);
+        };
+        futures::executor::block_on(futures::future::join(write, read));
+    }
+
+    #[test]
+    fn byte_stream() {
+        let mut words = vec![0u64; 40];
+        let (mut producer, mut consumer) = ring(&mut words);
+
+        let write = async move {
+            for n in 0..50 {
+                crate::serialize::write_message(&mut producer, &build_message(n)).await.unwrap();
+            }
+            producer.close().await.unwrap();
+        };
+        let read = async move {
+            for n in 0..50 {
+                let message = crate::serialize::read_message(&mut consumer, Default::default())
+                    .await.unwrap().unwrap();
+                check_message(message, n);
+            }
+            let mut rest = Vec::new();
+            assert_eq!(consumer.read_to_end(&mut rest).await.unwrap(), 0);
+        };
+        futures::executor::block_on(futures::future::join(write, read).map(|_| ()));
+    }
+
+    #[test]
+    fn corrupt_record() {
+        let mut words = vec![0u64; 32];
+        let ptr = words.as_mut_ptr();
+        let (_producer, mut consumer) = ring(&mut words);
+        unsafe {
+            // tail = 16, with a record claiming more bytes than that.
+            *(ptr as *mut u8).add(64).cast::<u64>() = 16;
+            *(ptr as *mut u8).add(128).cast::<u64>() = 100;
+        }
+        let result = futures::executor::block_on(consumer.read_message());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn max_size_messages() {
+        // After a small record and a maximum-size one, the next maximum-size record doesn't fit
+        // before the end of the ring, so it has to go behind a padding record at the beginning.
+        let mut words = vec![0u64; 144];
+        let (mut producer, mut consumer) = ring(&mut words);
+        let max_len = producer.max_record_len();
+        let n = ((max_len - 16) / 4) as u32;
+
+        let write = async move {
+            producer.write_message(&build_message(0)).await.unwrap();
+            producer.write_message(&build_message(n)).await.unwrap();
+            producer.write_message(&build_message(n)).await.unwrap();
+            assert!(producer.write_message(&build_message(n + 2)).await.is_err());
+            producer.finish();
+        };
+        let read = async move {
+            for &expected in &[0, n, n] {
+                let received = consumer.read_message().await.unwrap().unwrap();
+                if expected == n {
+                    assert_eq!(received.as_bytes().len(), max_len);
+                }
+                check_message(received.get(Default::default()).unwrap(), expected);
+            }
+            assert!(consumer.read_message().await.unwrap().is_none());
+        };
+        futures::executor::block_on(futures::future::join(write, read));
+    }
+
+    #[test]
+    fn region_checks() {
+        let mut words = vec![0u64; 4];
+        unsafe {
+            assert!(initialize(words.as_mut_ptr() as *mut u8, 32).is_err());
+            assert!(initialize((words.as_mut_ptr() as *mut u8).add(1), 31).is_err());
+        }
+    }
+}
@@ -2,6 +2,7 @@ extern crate capnpc;
 
 fn main() {
     ::capnpc::CompilerCommand::new()
+        .builtin_compiler()
         .file("addressbook.capnp")
         .run()
         .unwrap();
@@ -1,5 +1,5 @@
 extern crate capnpc;
 
 fn main() {
-    ::capnpc::CompilerCommand::new().file("calculator.capnp").run().unwrap();
+    ::capnpc::CompilerCommand::new().builtin_compiler().file("calculator.capnp").run().unwrap();
 }
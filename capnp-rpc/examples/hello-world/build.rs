@@ -1,5 +1,5 @@
 extern crate capnpc;
 
 fn main() {
-    ::capnpc::CompilerCommand::new().file("hello_world.capnp").run().unwrap();
+    ::capnpc::CompilerCommand::new().builtin_compiler().file("hello_world.capnp").run().unwrap();
 }
@@ -0,0 +1,258 @@
+// Copyright (c) 2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! A server-side cache for the results of getter-like methods.
+//!
+//! A client that makes the same call over and over, for example `getFoo()` pipelined on every
+//! capability it receives, would otherwise make the server redo the work each time. Mark such
+//! methods in the schema with `$Rust.cacheable`, and wrap the capability with `new_client()`,
+//! passing the generated `CACHEABLE_METHODS` constant of each interface, before exporting it.
+//! The wrapper answers a call to a cacheable method with the results of an earlier call that
+//! had the same parameters and arrived on the same connection, if there was one, and otherwise
+//! forwards it and remembers its results. Calls that are made while an identical call is still
+//! running share its results. Calls to other methods, calls whose parameters contain
+//! capabilities, and failed calls are not cached.
+//!
+//! Entries are kept until `Cache::clear()` is called, until the connection that they belong to
+//! is disconnected, or until the cache is full and they are the least recently used, so the
+//! cache suits capabilities whose cacheable results never change, or that know when they do.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
+
+use futures::{FutureExt, TryFutureExt};
+use futures::future::Shared;
+
+use capnp::{any_pointer, message, Error};
+use capnp::capability::{self, FromClientHook, Params, Promise, Response, Results};
+use capnp::private::capability::ClientHook;
+
+// The connection the call arrived on (`None` for local calls), the method, and the canonical
+// encoding of the parameters.
+type Key = (Option<u64>, u64, u16, Vec<u8>);
+
+#[derive(Clone)]
+struct Entry {
+    // Tells the entry apart from later ones for the same key.
+    id: u64,
+
+    // When the entry was last used, on the same clock as `id`.
+    last_used: u64,
+
+    results: Shared<Promise<Rc<Response<any_pointer::Owned>>, Error>>,
+}
+
+struct Entries {
+    map: HashMap<Key, Entry>,
+    capacity: usize,
+    clock: u64,
+
+    // The connections that will remove their entries when they are disconnected.
+    connections: HashSet<u64>,
+}
+
+impl Entries {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn insert(&mut self, key: Key, entry: Entry) {
+        while self.map.len() >= self.capacity {
+            let oldest = self.map.iter().min_by_key(|&(_, entry)| entry.last_used).map(|(key, _)| key.clone());
+            match oldest {
+                Some(oldest) => { self.map.remove(&oldest); }
+                None => break,
+            }
+        }
+        if self.capacity > 0 {
+            self.map.insert(key, entry);
+        }
+    }
+}
+
+/// The default for the number of results that a `Cache` remembers.
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// The results remembered by a caching client. Cloning a `Cache` gives another handle on the
+/// same entries.
+#[derive(Clone)]
+pub struct Cache {
+    entries: Rc<RefCell<Entries>>,
+}
+
+impl Cache {
+    /// Creates a cache that remembers the results of up to `DEFAULT_CAPACITY` calls.
+    pub fn new() -> Cache {
+        Cache::with_capacity(DEFAULT_CAPACITY)
+    }
+
+    /// Creates a cache that remembers the results of up to `capacity` calls, forgetting the
+    /// least recently used ones to make room for more.
+    pub fn with_capacity(capacity: usize) -> Cache {
+        Cache {
+            entries: Rc::new(RefCell::new(Entries {
+                map: HashMap::new(),
+                capacity: capacity,
+                clock: 0,
+                connections: HashSet::new(),
+            })),
+        }
+    }
+
+    /// Forgets all results, so that the next call to each method is forwarded again.
+    pub fn clear(&self) {
+        self.entries.borrow_mut().map.clear();
+    }
+
+    /// The number of calls whose results are remembered, or are being waited for.
+    pub fn len(&self) -> usize {
+        self.entries.borrow().map.len()
+    }
+
+    // Makes sure that the entries for `connection_id` go away with the connection. Returns
+    // false if the connection is already gone.
+    fn watch_connection(&self, connection_id: u64) -> bool {
+        if self.entries.borrow().connections.contains(&connection_id) {
+            return true;
+        }
+        let entries = Rc::downgrade(&self.entries);
+        let watching = crate::rpc::on_disconnect(connection_id, Box::new(move || {
+            forget_connection(&entries, connection_id)
+        }));
+        if watching {
+            self.entries.borrow_mut().connections.insert(connection_id);
+        }
+        watching
+    }
+}
+
+impl Default for Cache {
+    fn default() -> Cache {
+        Cache::new()
+    }
+}
+
+fn forget_connection(entries: &Weak<RefCell<Entries>>, connection_id: u64) {
+    if let Some(entries) = entries.upgrade() {
+        let mut entries = entries.borrow_mut();
+        entries.connections.remove(&connection_id);
+        entries.map.retain(|key, _| key.0 != Some(connection_id));
+    }
+}
+
+/// Wraps `client` so that the results of calls to the methods in `cacheable_methods`, as
+/// (interface id, method ordinal) pairs, are remembered in `cache`.
+pub fn new_client<C>(client: capability::Client, cacheable_methods: &[(u64, u16)], cache: Cache) -> C
+    where C: FromClientHook
+{
+    let server = CachingServer {
+        target: client.hook,
+        cacheable_methods: cacheable_methods.to_vec(),
+        cache: cache,
+    };
+    FromClientHook::new(Box::new(crate::local::Client::new(Box::new(server))))
+}
+
+struct CachingServer {
+    target: Box<dyn ClientHook>,
+    cacheable_methods: Vec<(u64, u16)>,
+    cache: Cache,
+}
+
+// The canonical encoding of `params`, or `None` if they contain capabilities, which can't be
+// compared.
+fn cache_key_params(params: any_pointer::Reader) -> ::capnp::Result<Option<Vec<u8>>> {
+    if params.target_size()?.cap_count > 0 {
+        return Ok(None);
+    }
+    let mut message = message::Builder::new_default();
+    message.set_root_canonical(params)?;
+    Ok(Some(::capnp::serialize::write_message_to_words(&message)))
+}
+
+impl capability::Server for CachingServer {
+    fn dispatch_call(&mut self, interface_id: u64, method_id: u16,
+                     params: Params<any_pointer::Owned>,
+                     mut results: Results<any_pointer::Owned>)
+                     -> Promise<(), Error>
+    {
+        let key_params = if self.cacheable_methods.contains(&(interface_id, method_id)) {
+            pry!(cache_key_params(pry!(params.get())))
+        } else {
+            None
+        };
+        let key_params = match key_params {
+            Some(key_params) => key_params,
+            None => return self.target.call(interface_id, method_id, params.hook, results.hook),
+        };
+        let connection_id = params.caller().map(|caller| caller.connection_id);
+        if let Some(connection_id) = connection_id {
+            if !self.cache.watch_connection(connection_id) {
+                return self.target.call(interface_id, method_id, params.hook, results.hook);
+            }
+        }
+        let key = (connection_id, interface_id, method_id, key_params);
+
+        let existing = {
+            let mut entries = self.cache.entries.borrow_mut();
+            let now = entries.tick();
+            entries.map.get_mut(&key).map(|entry| {
+                entry.last_used = now;
+                entry.clone()
+            })
+        };
+        let entry = match existing {
+            Some(entry) => entry,
+            None => {
+                let mut request = self.target.new_call(interface_id, method_id, None);
+                pry!(request.get().set_as(pry!(params.get())));
+                let mut entries = self.cache.entries.borrow_mut();
+                let id = entries.tick();
+                let entry = Entry {
+                    id: id,
+                    last_used: id,
+                    results: Promise::from_future(request.send().promise.map_ok(Rc::new)).shared(),
+                };
+                entries.insert(key.clone(), entry.clone());
+                entry
+            }
+        };
+        drop(params);
+
+        let entries = self.cache.entries.clone();
+        let id = entry.id;
+        Promise::from_future(entry.results.map(move |result| {
+            match result {
+                Ok(response) => results.get().set_as(response.get()?),
+                Err(e) => {
+                    // Let the next call try again, unless it already has.
+                    let mut entries = entries.borrow_mut();
+                    if entries.map.get(&key).map(|entry| entry.id) == Some(id) {
+                        entries.map.remove(&key);
+                    }
+                    Err(e)
+                }
+            }
+        }))
+    }
+}
@@ -0,0 +1,79 @@
+// Copyright (c) 2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Application-level handshakes on new connections.
+//!
+//! A `ConnectionHook` registered with `RpcSystem::set_connection_hook()` gets each new
+//! connection before the RPC system has read or written anything on it. The hook can exchange
+//! its own messages with the peer, for example to present and check a token, and then either
+//! hand the connection back, optionally with an identity for the peer, or reject it by failing.
+//! Method handlers see the identity as `CallerInfo::peer_identity`, via `Params::caller()`.
+//!
+//! Both ends of a connection need to run matching hooks, since the RPC system on the other end
+//! would not understand the handshake messages.
+
+use capnp::Error;
+use capnp::capability::Promise;
+
+/// A handshake to run on each new connection of an `RpcSystem`.
+pub trait ConnectionHook<VatId> {
+    /// Runs the handshake on `connection`, which is freshly accepted or connected. Resolves to
+    /// the connection, which the RPC system then takes over, along with the identity of the peer,
+    /// if the handshake established one. An error rejects the connection.
+    fn handshake(&mut self, connection: Box<dyn crate::Connection<VatId>>)
+                 -> Promise<(Box<dyn crate::Connection<VatId>>, Option<String>), Error>;
+}
+
+/// A connection whose peer identity was established by a handshake.
+pub(crate) struct IdentifiedConnection<VatId> {
+    inner: Box<dyn crate::Connection<VatId>>,
+    peer_identity: String,
+}
+
+impl <VatId> IdentifiedConnection<VatId> {
+    pub(crate) fn new(inner: Box<dyn crate::Connection<VatId>>, peer_identity: String)
+                      -> IdentifiedConnection<VatId>
+    {
+        IdentifiedConnection { inner: inner, peer_identity: peer_identity }
+    }
+}
+
+impl <VatId> crate::Connection<VatId> for IdentifiedConnection<VatId> {
+    fn get_peer_vat_id(&self) -> VatId {
+        self.inner.get_peer_vat_id()
+    }
+
+    fn get_peer_identity(&self) -> Option<String> {
+        Some(self.peer_identity.clone())
+    }
+
+    fn new_outgoing_message(&mut self, first_segment_word_size: u32) -> Box<dyn crate::OutgoingMessage> {
+        self.inner.new_outgoing_message(first_segment_word_size)
+    }
+
+    fn receive_incoming_message(&mut self) -> Promise<Option<Box<dyn crate::IncomingMessage>>, Error> {
+        self.inner.receive_incoming_message()
+    }
+
+    fn shutdown(&mut self, result: ::capnp::Result<()>) -> Promise<(), Error> {
+        self.inner.shutdown(result)
+    }
+}
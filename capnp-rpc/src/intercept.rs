@@ -0,0 +1,117 @@
+// Copyright (c) 2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Middleware for client calls.
+//!
+//! `new_client()` wraps a capability in a chain of `Interceptor`s, which see every call made
+//! through the wrapper, whatever its interface. Interceptors run in order on the way out, where
+//! they can inspect and modify the parameters, and in reverse order on the way back, where they
+//! can inspect and modify the results or the error. Typical uses are logging, timing, and adding
+//! metadata to parameters.
+//!
+//! The wrapper copies the parameters into a new request to the wrapped capability, and copies the
+//! results back, so interceptors always get a builder to work with.
+
+use std::rc::Rc;
+use std::time::Instant;
+
+use capnp::{any_pointer, Error};
+use capnp::capability::{self, FromClientHook, Params, Promise, Results};
+use capnp::private::capability::ClientHook;
+
+/// Identifies a call passing through an interceptor chain.
+#[derive(Clone, Copy, Debug)]
+pub struct CallInfo {
+    pub interface_id: u64,
+    pub method_id: u16,
+
+    /// When the call entered the chain.
+    pub start: Instant,
+}
+
+/// A hook on the calls made through a client. All methods do nothing by default.
+pub trait Interceptor {
+    /// Called before the call is sent, with its parameters. An error fails the call without
+    /// sending it.
+    fn on_request(&self, _call: &CallInfo, _params: any_pointer::Builder) -> ::capnp::Result<()> {
+        Ok(())
+    }
+
+    /// Called when the call has returned successfully, with its results. An error fails the call.
+    fn on_response(&self, _call: &CallInfo, _results: any_pointer::Builder) -> ::capnp::Result<()> {
+        Ok(())
+    }
+
+    /// Called when the call has failed. Returns the error to pass on.
+    fn on_error(&self, _call: &CallInfo, error: Error) -> Error {
+        error
+    }
+}
+
+/// Wraps `client` so that each call made through the result passes through `interceptors`.
+pub fn new_client<C>(client: capability::Client, interceptors: Vec<Box<dyn Interceptor>>) -> C
+    where C: FromClientHook
+{
+    let server = InterceptingServer {
+        target: client.hook,
+        interceptors: Rc::new(interceptors),
+    };
+    FromClientHook::new(Box::new(crate::local::Client::new(Box::new(server))))
+}
+
+struct InterceptingServer {
+    target: Box<dyn ClientHook>,
+    interceptors: Rc<Vec<Box<dyn Interceptor>>>,
+}
+
+impl capability::Server for InterceptingServer {
+    fn dispatch_call(&mut self, interface_id: u64, method_id: u16,
+                     params: Params<any_pointer::Owned>,
+                     mut results: Results<any_pointer::Owned>)
+                     -> Promise<(), Error>
+    {
+        let call = CallInfo { interface_id: interface_id, method_id: method_id, start: Instant::now() };
+        let interceptors = self.interceptors.clone();
+        let mut request = self.target.new_call(interface_id, method_id, None);
+        pry!(request.get().set_as(pry!(params.get())));
+        for interceptor in interceptors.iter() {
+            pry!(interceptor.on_request(&call, request.get()));
+        }
+        drop(params);
+
+        let response = request.send().promise;
+        Promise::from_future(async move {
+            let result = match response.await {
+                Ok(response) => {
+                    results.get().set_as(response.get()?)?;
+                    let mut result = Ok(());
+                    for interceptor in interceptors.iter().rev() {
+                        result = interceptor.on_response(&call, results.get());
+                        if result.is_err() { break }
+                    }
+                    result
+                }
+                Err(e) => Err(e),
+            };
+            result.map_err(|e| interceptors.iter().rev().fold(e, |e, interceptor| interceptor.on_error(&call, e)))
+        })
+    }
+}
@@ -0,0 +1,200 @@
+// Copyright (c) 2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Keepalive pings and idle timeouts for RPC connections.
+//!
+//! A peer that goes away without closing its connection, for example because its host lost
+//! power or a NAT box forgot about the TCP session, leaves every outstanding call waiting forever.
+//! `KeepaliveVatNetwork` wraps another `VatNetwork` and watches the incoming side of each of its
+//! connections. When nothing has arrived for `ping_interval`, it sends the peer a ping, which the
+//! peer's `KeepaliveVatNetwork` answers with a pong. When nothing at all has arrived for
+//! `idle_timeout`, the connection fails with a `Disconnected` error, which fails all calls
+//! that are still waiting on it.
+//!
+//! Pings and pongs are not RPC messages, so both ends of a connection need to wrap their networks
+//! if pings are enabled. An idle timeout without pings works against any peer, but then only
+//! ordinary RPC traffic keeps the connection alive.
+//!
+//! This crate does not depend on any particular runtime, so the caller supplies the timer, as a
+//! function that returns a promise that resolves after a given duration.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use capnp::{text, Error};
+use capnp::capability::Promise;
+use futures::TryFutureExt;
+use futures::future::{self, Either};
+
+const PING: &str = "capnp-rpc keepalive ping";
+const PONG: &str = "capnp-rpc keepalive pong";
+
+/// Settings for a `KeepaliveVatNetwork`.
+#[derive(Clone, Copy, Debug)]
+pub struct Options {
+    /// How long a connection may go without receiving anything before it is aborted.
+    pub idle_timeout: Duration,
+
+    /// How long to wait for incoming traffic before pinging the peer. `None` disables pings.
+    pub ping_interval: Option<Duration>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            idle_timeout: Duration::from_secs(60),
+            ping_interval: Some(Duration::from_secs(20)),
+        }
+    }
+}
+
+type Sleep = Rc<dyn Fn(Duration) -> Promise<(), Error>>;
+
+/// A `VatNetwork` that pings idle peers and aborts connections that stay silent for too long.
+pub struct KeepaliveVatNetwork<VatId> {
+    inner: Box<dyn crate::VatNetwork<VatId>>,
+    options: Options,
+    sleep: Sleep,
+}
+
+impl <VatId> KeepaliveVatNetwork<VatId> {
+    /// Wraps `inner`. `sleep(d)` must return a promise that resolves after `d` has elapsed.
+    pub fn new<F>(inner: Box<dyn crate::VatNetwork<VatId>>, options: Options, sleep: F)
+                  -> KeepaliveVatNetwork<VatId>
+        where F: Fn(Duration) -> Promise<(), Error> + 'static
+    {
+        KeepaliveVatNetwork { inner: inner, options: options, sleep: Rc::new(sleep) }
+    }
+}
+
+fn wrap<VatId>(inner: Box<dyn crate::Connection<VatId>>, options: Options, sleep: Sleep)
+               -> Box<dyn crate::Connection<VatId>>
+    where VatId: 'static
+{
+    Box::new(KeepaliveConnection { inner: Rc::new(RefCell::new(inner)), options: options, sleep: sleep })
+}
+
+impl <VatId> crate::VatNetwork<VatId> for KeepaliveVatNetwork<VatId> where VatId: 'static {
+    fn connect(&mut self, host_id: VatId) -> Option<Box<dyn crate::Connection<VatId>>> {
+        let options = self.options;
+        let sleep = self.sleep.clone();
+        self.inner.connect(host_id).map(move |inner| wrap(inner, options, sleep))
+    }
+
+    fn accept(&mut self) -> Promise<Box<dyn crate::Connection<VatId>>, Error> {
+        let options = self.options;
+        let sleep = self.sleep.clone();
+        Promise::from_future(self.inner.accept().map_ok(move |inner| wrap(inner, options, sleep)))
+    }
+
+    fn drive_until_shutdown(&mut self) -> Promise<(), Error> {
+        self.inner.drive_until_shutdown()
+    }
+}
+
+struct KeepaliveConnection<VatId> {
+    inner: Rc<RefCell<Box<dyn crate::Connection<VatId>>>>,
+    options: Options,
+    sleep: Sleep,
+}
+
+fn send_text<VatId>(connection: &RefCell<Box<dyn crate::Connection<VatId>>>, value: &str)
+                    -> ::capnp::Result<()>
+{
+    let mut message = connection.borrow_mut().new_outgoing_message(8);
+    message.get_body()?.set_as(value)?;
+    // Like other sends on a connection, this completes in the background.
+    let _ = message.send();
+    Ok(())
+}
+
+// A keepalive message has a text root, which no RPC message has.
+fn keepalive_text(message: &dyn crate::IncomingMessage) -> Option<&'static str> {
+    let value = message.get_body().ok()?.get_as::<text::Reader>().ok()?;
+    if value == PING {
+        Some(PING)
+    } else if value == PONG {
+        Some(PONG)
+    } else {
+        None
+    }
+}
+
+impl <VatId> crate::Connection<VatId> for KeepaliveConnection<VatId> where VatId: 'static {
+    fn get_peer_vat_id(&self) -> VatId {
+        self.inner.borrow().get_peer_vat_id()
+    }
+
+    fn get_peer_identity(&self) -> Option<String> {
+        self.inner.borrow().get_peer_identity()
+    }
+
+    fn new_outgoing_message(&mut self, first_segment_word_size: u32) -> Box<dyn crate::OutgoingMessage> {
+        self.inner.borrow_mut().new_outgoing_message(first_segment_word_size)
+    }
+
+    fn receive_incoming_message(&mut self) -> Promise<Option<Box<dyn crate::IncomingMessage>>, Error> {
+        let inner = self.inner.clone();
+        let options = self.options;
+        let sleep = self.sleep.clone();
+        Promise::from_future(async move {
+            let mut receive = inner.borrow_mut().receive_incoming_message();
+            let mut idle = Duration::from_secs(0);
+            loop {
+                let remaining = options.idle_timeout.checked_sub(idle).unwrap_or_default();
+                let wait = match options.ping_interval {
+                    Some(interval) if interval < remaining => interval,
+                    _ => remaining,
+                };
+                match future::select(receive, sleep(wait)).await {
+                    Either::Left((result, _)) => {
+                        let message = match result? {
+                            Some(message) => message,
+                            None => return Ok(None),
+                        };
+                        match keepalive_text(&*message) {
+                            None => return Ok(Some(message)),
+                            Some(PING) => send_text(&inner, PONG)?,
+                            Some(_) => (),
+                        }
+                        receive = inner.borrow_mut().receive_incoming_message();
+                        idle = Duration::from_secs(0);
+                    }
+                    Either::Right((timer, pending_receive)) => {
+                        timer?;
+                        idle += wait;
+                        if idle >= options.idle_timeout {
+                            return Err(Error::disconnected(
+                                format!("Nothing received from peer for {:?}.", idle)));
+                        }
+                        send_text(&inner, PING)?;
+                        receive = pending_receive;
+                    }
+                }
+            }
+        })
+    }
+
+    fn shutdown(&mut self, result: ::capnp::Result<()>) -> Promise<(), Error> {
+        self.inner.borrow_mut().shutdown(result)
+    }
+}
@@ -70,7 +70,7 @@ use futures::channel::oneshot;
 use capnp::Error;
 use capnp::capability::Promise;
 use capnp::private::capability::{ClientHook};
-use std::cell::{RefCell};
+use std::cell::{Cell, RefCell};
 use std::rc::{Rc};
 
 use crate::task_set::TaskSet;
@@ -84,6 +84,10 @@ pub mod rpc_capnp;
 /// (https://github.com/sandstorm-io/capnproto/blob/master/c%2B%2B/src/capnp/rpc-twoparty.capnp).
 pub mod rpc_twoparty_capnp;
 
+/// Code generated from [persistent.capnp]
+/// (https://github.com/sandstorm-io/capnproto/blob/master/c%2B%2B/src/capnp/persistent.capnp).
+pub mod persistent_capnp;
+
 /// Like `try!()`, but for functions that return a `Promise<T, E>` rather than a `Result<T, E>`.
 ///
 /// Unwraps a `Result<T, E>`. In the case of an error `Err(e)`, immediately returns from the
@@ -107,7 +111,17 @@ mod attach;
 mod sender_queue;
 mod split;
 mod task_set;
+pub mod cache;
+pub mod handshake;
+pub mod intercept;
+pub mod keepalive;
+pub mod persistent;
+pub mod reconnect;
+pub mod retry;
+pub mod record;
+pub mod thread;
 pub mod twoparty;
+pub mod websocket;
 
 pub trait OutgoingMessage {
     fn get_body<'a>(&'a mut self) -> ::capnp::Result<::capnp::any_pointer::Builder<'a>>;
@@ -124,10 +138,23 @@ pub trait OutgoingMessage {
 
 pub trait IncomingMessage {
     fn get_body<'a>(&'a self) -> ::capnp::Result<::capnp::any_pointer::Reader<'a>>;
+
+    /// Returns the raw segments of the message, if the implementation has them. This allows
+    /// the message to be copied verbatim, e.g. by `record::RecordingVatNetwork`.
+    fn get_segments(&self) -> Option<&dyn ::capnp::message::ReaderSegments> {
+        None
+    }
 }
 
 pub trait Connection<VatId> {
     fn get_peer_vat_id(&self) -> VatId;
+
+    /// Returns the identity of the peer, if the transport established one. Servers see it
+    /// through `Params::caller()`.
+    fn get_peer_identity(&self) -> Option<String> {
+        None
+    }
+
     fn new_outgoing_message(&mut self, first_segment_word_size: u32) -> Box<dyn OutgoingMessage>;
 
     /// Waits for a message to be received and returns it.  If the read stream cleanly terminates,
@@ -149,6 +176,30 @@ pub trait VatNetwork<VatId> {
     fn drive_until_shutdown(&mut self) -> Promise<(), Error>;
 }
 
+struct Handshake<VatId> where VatId: 'static {
+    hook: Option<Box<dyn handshake::ConnectionHook<VatId>>>,
+
+    // The handshake on the first connection, shared with anyone else who connects while it runs.
+    pending: Option<::futures::future::Shared<Promise<Rc<rpc::ConnectionState<VatId>>, Error>>>,
+}
+
+/// Limits on the calls that a peer can have outstanding at once, set by
+/// `RpcSystem::set_call_limits()`. A call is outstanding from its arrival until the capability
+/// it was made on has finished with it. A call that would exceed a limit fails straight away
+/// with an `Overloaded` error, without reaching the capability, so that a peer cannot exhaust
+/// the server's memory by flooding it with calls, pipelined or not.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CallLimits {
+    /// The most calls outstanding on a connection.
+    pub per_connection: Option<u32>,
+
+    /// The most calls outstanding on any one capability, counting only calls that arrived on
+    /// the same connection. Capabilities are told apart by `ClientHook::get_ptr()`, so calls
+    /// pipelined on a result that has not been returned yet count separately from calls made
+    /// on the capability after it has been.
+    pub per_capability: Option<u32>,
+}
+
 /// A portal to objects available on the network.
 ///
 /// The RPC implemententation sits on top of an implementation of `VatNetwork`, which
@@ -172,6 +223,10 @@ pub struct RpcSystem<VatId> where VatId: 'static {
     // to connection states.
     connection_state: Rc<RefCell<Option<Rc<rpc::ConnectionState<VatId>>>>>,
 
+    handshake: Rc<RefCell<Handshake<VatId>>>,
+
+    call_limits: Rc<Cell<CallLimits>>,
+
     tasks: TaskSet<Error>,
     handle: crate::task_set::TaskSetHandle<Error>
 }
@@ -210,6 +265,8 @@ impl <VatId> RpcSystem <VatId> {
             network: network,
             bootstrap_cap: bootstrap_cap,
             connection_state: Rc::new(RefCell::new(None)),
+            handshake: Rc::new(RefCell::new(Handshake { hook: None, pending: None })),
+            call_limits: Rc::new(Cell::new(CallLimits::default())),
 
             tasks: tasks,
             handle: handle.clone(),
@@ -221,6 +278,18 @@ impl <VatId> RpcSystem <VatId> {
         result
     }
 
+    /// Sets a hook that runs a handshake on each new connection before the RPC system uses it.
+    /// Must be called before the `RpcSystem` is first polled, and before `bootstrap()`.
+    pub fn set_connection_hook(&mut self, hook: Box<dyn handshake::ConnectionHook<VatId>>) {
+        self.handshake.borrow_mut().hook = Some(hook);
+    }
+
+    /// Limits the number of calls that peers can have outstanding at once. Applies to calls
+    /// that arrive after it is called. By default there are no limits.
+    pub fn set_call_limits(&mut self, limits: CallLimits) {
+        self.call_limits.set(limits);
+    }
+
     /// Connects to the given vat and returns its bootstrap interface.
     pub fn bootstrap<T>(&mut self, vat_id: VatId) -> T
         where T: ::capnp::capability::FromClientHook
@@ -231,9 +300,23 @@ impl <VatId> RpcSystem <VatId> {
                 return T::new(self.bootstrap_cap.clone());
             }
         };
+        if self.handshake.borrow().hook.is_some() && self.connection_state.borrow().is_none() {
+            // Calls on the bootstrap capability queue up until the handshake is done.
+            let client = RpcSystem::connection_state_after_handshake(self.handshake.clone(),
+                                                                    self.connection_state.clone(),
+                                                                    self.bootstrap_cap.clone(),
+                                                                    self.call_limits.clone(),
+                                                                    connection,
+                                                                    self.handle.clone())
+                .map_ok(|connection_state| {
+                    ::capnp::capability::Client::new(rpc::ConnectionState::bootstrap(connection_state))
+                });
+            return T::new(new_promise_client_hook(client));
+        }
         let connection_state =
             RpcSystem::get_connection_state(self.connection_state.clone(),
                                             self.bootstrap_cap.clone(),
+                                            self.call_limits.clone(),
                                             connection, self.handle.clone());
 
         let hook = rpc::ConnectionState::bootstrap(connection_state.clone());
@@ -242,19 +325,65 @@ impl <VatId> RpcSystem <VatId> {
 
     // not really a loop, because it doesn't need to be for the two party case
     fn accept_loop(&mut self) -> Promise<(), Error> {
+        let handshake = self.handshake.clone();
         let connection_state_ref = self.connection_state.clone();
         let bootstrap_cap = self.bootstrap_cap.clone();
+        let call_limits = self.call_limits.clone();
         let handle = self.handle.clone();
-        Promise::from_future(self.network.accept().map_ok(move |connection| {
-            RpcSystem::get_connection_state(connection_state_ref,
-                                            bootstrap_cap,
-                                            connection,
-                                            handle);
+        Promise::from_future(self.network.accept().and_then(move |connection| {
+            let mut handle1 = handle.clone();
+            RpcSystem::connection_state_after_handshake(handshake,
+                                                        connection_state_ref,
+                                                        bootstrap_cap,
+                                                        call_limits,
+                                                        connection,
+                                                        handle).map(move |r| {
+                if let Err(e) = r {
+                    // The handshake rejected the connection. In a two-party network there are
+                    // no others, so the whole system is done.
+                    handle1.terminate(Err(e));
+                }
+                Ok(())
+            })
         }))
     }
 
+    // Runs the connection hook, if any, and then sets up the connection state. The first caller
+    // runs the handshake; later callers, which in the two-party case hold another handle on the
+    // same connection, wait for it to finish.
+    fn connection_state_after_handshake(handshake: Rc<RefCell<Handshake<VatId>>>,
+                                        connection_state_ref: Rc<RefCell<Option<Rc<rpc::ConnectionState<VatId>>>>>,
+                                        bootstrap_cap: Box<dyn ClientHook>,
+                                        call_limits: Rc<Cell<CallLimits>>,
+                                        connection: Box<dyn crate::Connection<VatId>>,
+                                        handle: crate::task_set::TaskSetHandle<Error>)
+                                        -> Promise<Rc<rpc::ConnectionState<VatId>>, Error>
+    {
+        let mut handshake = handshake.borrow_mut();
+        if let Some(ref pending) = handshake.pending {
+            return Promise::from_future(pending.clone());
+        }
+        let connection = match handshake.hook.as_mut() {
+            None => Promise::ok(connection),
+            Some(hook) => Promise::from_future(hook.handshake(connection).map_ok(|(connection, identity)| {
+                match identity {
+                    Some(identity) =>
+                        Box::new(handshake::IdentifiedConnection::new(connection, identity))
+                            as Box<dyn crate::Connection<VatId>>,
+                    None => connection,
+                }
+            })),
+        };
+        let pending = Promise::from_future(connection.map_ok(move |connection| {
+            RpcSystem::get_connection_state(connection_state_ref, bootstrap_cap, call_limits, connection, handle)
+        })).shared();
+        handshake.pending = Some(pending.clone());
+        Promise::from_future(pending)
+    }
+
     fn get_connection_state(connection_state_ref: Rc<RefCell<Option<Rc<rpc::ConnectionState<VatId>>>>>,
                             bootstrap_cap: Box<dyn ClientHook>,
+                            call_limits: Rc<Cell<CallLimits>>,
                             connection: Box<dyn crate::Connection<VatId>>,
                             mut handle: crate::task_set::TaskSetHandle<Error>)
                             -> Rc<rpc::ConnectionState<VatId>>
@@ -276,7 +405,7 @@ impl <VatId> RpcSystem <VatId> {
                         Err(e) => Promise::err(Error::failed(format!("{}", e))),
                     }
                 }));
-                rpc::ConnectionState::new(bootstrap_cap, connection, on_disconnect_fulfiller)
+                rpc::ConnectionState::new(bootstrap_cap, call_limits, connection, on_disconnect_fulfiller)
             }
         };
         *connection_state_ref.borrow_mut() = Some(result.clone());
@@ -304,6 +433,15 @@ pub fn new_client<C, S>(s: S) -> C where C: capnp::capability::FromServer<S> {
         local::Client::new(Box::new(<C as capnp::capability::FromServer::<S>>::from_server(s)))))
 }
 
+/// Creates a new local RPC client of type `C` out of a server whose dispatch table has already
+/// been set up, for example with `ServerDispatch::with_dispatch()` to add the methods of a base
+/// interface that the generated code of `C` cannot name.
+pub fn new_client_from_dispatch<C, S>(dispatch: <C as capnp::capability::FromServer<S>>::Dispatch) -> C
+    where C: capnp::capability::FromServer<S>
+{
+    capnp::capability::FromClientHook::new(Box::new(local::Client::new(Box::new(dispatch))))
+}
+
 /// Converts a promise for a client into a client that queues up any calls that arrive
 /// before the promise resolves.
 // TODO: figure out a better way to allow construction of promise clients.
@@ -311,6 +449,13 @@ pub fn new_promise_client<T, F>(client_promise: F) -> T
     where T: ::capnp::capability::FromClientHook,
           F: ::futures::Future<Output=Result<capnp::capability::Client,Error>>,
           F: 'static + Unpin
+{
+    T::new(new_promise_client_hook(client_promise))
+}
+
+pub(crate) fn new_promise_client_hook<F>(client_promise: F) -> Box<dyn ClientHook>
+    where F: ::futures::Future<Output=Result<capnp::capability::Client,Error>>,
+          F: 'static + Unpin
 {
     let mut queued_client = crate::queued::Client::new(None);
     let weak_client = Rc::downgrade(&queued_client.inner);
@@ -322,7 +467,7 @@ pub fn new_promise_client<T, F>(client_promise: F) -> T
         Promise::ok(())
     }));
 
-    T::new(Box::new(queued_client))
+    Box::new(queued_client)
 }
 
 struct SystemTaskReaper;
@@ -0,0 +1,128 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Helpers for the standard `Persistent` interface, through which a capability can be saved as
+//! a SturdyRef and later restored, possibly by a different connection.
+//!
+//! The contents of a SturdyRef, and of the `Owner` that a SturdyRef may be sealed for, are
+//! defined by the application through the `SturdyRefIssuer` and `Restorer` traits. The
+//! `SturdyRef` and `Owner` type parameters are the Cap'n Proto types used to represent them,
+//! for example `capnp::data::Owned` or a generated struct's `Owned`.
+
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+use capnp::Error;
+use capnp::any_pointer;
+use capnp::capability::{self, FromClientHook, Params, Promise, Request, Response, Results};
+use capnp::traits::{HasTypeId, Owned};
+
+pub use crate::persistent_capnp::persistent;
+use crate::persistent_capnp::persistent::{save_params, save_results};
+
+/// Decides what goes into the SturdyRefs handed out for capabilities wrapped by `new_client()`.
+pub trait SturdyRefIssuer<SturdyRef, Owner>
+    where SturdyRef: for<'c> Owned<'c>, Owner: for<'c> Owned<'c>
+{
+    /// Issues a SturdyRef for `cap` and writes it to the `sturdyRef` field of `results`. If
+    /// `sealFor` is set in `params`, the SturdyRef should only be restorable by that owner.
+    fn issue(&mut self,
+             cap: capability::Client,
+             params: Params<save_params::Owned<SturdyRef, Owner>>,
+             results: Results<save_results::Owned<SturdyRef, Owner>>)
+             -> Promise<(), Error>;
+}
+
+/// Turns SturdyRefs issued by a `SturdyRefIssuer` back into live capabilities.
+pub trait Restorer<SturdyRef> where SturdyRef: for<'c> Owned<'c> {
+    /// Looks up the capability designated by `sturdy_ref`. Should fail with
+    /// `ErrorKind::Failed` if the SturdyRef is unknown or has been revoked.
+    fn restore<'a>(&mut self, sturdy_ref: <SturdyRef as Owned<'a>>::Reader)
+                   -> Promise<capability::Client, Error>;
+}
+
+/// Restores `sturdy_ref` using `restorer`. Calls on the returned client are queued until the
+/// restoration completes, and fail with its error if it does not succeed.
+pub fn restore<'a, T, SturdyRef>(restorer: &mut dyn Restorer<SturdyRef>,
+                                 sturdy_ref: <SturdyRef as Owned<'a>>::Reader) -> T
+    where T: FromClientHook, SturdyRef: for<'c> Owned<'c>
+{
+    crate::new_promise_client(restorer.restore(sturdy_ref))
+}
+
+/// Wraps `cap` so that it also implements `Persistent`. Calls to `save()` are handed to
+/// `issuer`; all other calls are forwarded to `cap`.
+pub fn new_client<C, SturdyRef, Owner, I>(cap: capability::Client, issuer: Rc<RefCell<I>>) -> C
+    where C: FromClientHook,
+          SturdyRef: for<'c> Owned<'c> + 'static,
+          Owner: for<'c> Owned<'c> + 'static,
+          I: SturdyRefIssuer<SturdyRef, Owner> + 'static,
+{
+    let server = PersistentServer { inner: cap, issuer: issuer, marker: PhantomData };
+    FromClientHook::new(Box::new(crate::local::Client::new(Box::new(server))))
+}
+
+/// Creates a `save()` request to `cap`, which must implement `Persistent`. Set `sealFor` on the
+/// request to restrict who may restore the resulting SturdyRef.
+pub fn save_request<SturdyRef, Owner>(cap: &capability::Client)
+    -> Request<save_params::Owned<SturdyRef, Owner>, save_results::Owned<SturdyRef, Owner>>
+    where SturdyRef: for<'c> Owned<'c>, Owner: for<'c> Owned<'c>
+{
+    let client: persistent::Client<SturdyRef, Owner> = FromClientHook::new(cap.hook.add_ref());
+    client.save_request()
+}
+
+/// Asks `cap` for an unsealed SturdyRef. The SturdyRef is the `sturdyRef` field of the response.
+pub fn save<SturdyRef, Owner>(cap: &capability::Client)
+    -> Promise<Response<save_results::Owned<SturdyRef, Owner>>, Error>
+    where SturdyRef: for<'c> Owned<'c> + 'static + Unpin,
+          Owner: for<'c> Owned<'c> + 'static + Unpin,
+{
+    save_request::<SturdyRef, Owner>(cap).send().promise
+}
+
+struct PersistentServer<SturdyRef, Owner, I> {
+    inner: capability::Client,
+    issuer: Rc<RefCell<I>>,
+    marker: PhantomData<(SturdyRef, Owner)>,
+}
+
+impl <SturdyRef, Owner, I> capability::Server for PersistentServer<SturdyRef, Owner, I>
+    where SturdyRef: for<'c> Owned<'c>,
+          Owner: for<'c> Owned<'c>,
+          I: SturdyRefIssuer<SturdyRef, Owner>,
+{
+    fn dispatch_call(&mut self, interface_id: u64, method_id: u16,
+                     params: Params<any_pointer::Owned>,
+                     results: Results<any_pointer::Owned>)
+                     -> Promise<(), Error>
+    {
+        if interface_id == persistent::Client::<SturdyRef, Owner>::type_id() && method_id == 0 {
+            self.issuer.borrow_mut().issue(
+                capability::Client::new(self.inner.hook.add_ref()),
+                ::capnp::private::capability::internal_get_typed_params(params),
+                ::capnp::private::capability::internal_get_typed_results(results))
+        } else {
+            self.inner.hook.call(interface_id, method_id, params.hook, results.hook)
+        }
+    }
+}
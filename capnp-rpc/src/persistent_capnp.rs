@@ -0,0 +1,423 @@
+// @generated by the capnpc-rust plugin to the Cap'n Proto schema compiler.
+// DO NOT EDIT.
+// source: capnp-rpc/schema/persistent.capnp
+
+
+
+pub mod persistent { /* (SturdyRef,Owner) */
+  pub type SaveParams<SturdyRef,Owner,> = ::capnp::capability::Params<crate::persistent_capnp::persistent::save_params::Owned<SturdyRef,Owner>>;
+  pub type SaveResults<SturdyRef,Owner,> = ::capnp::capability::Results<crate::persistent_capnp::persistent::save_results::Owned<SturdyRef,Owner>>;
+  pub const IDEMPOTENT_METHODS: &'static [(u64, u16)] = &[];
+  pub const METHODS: &'static [::capnp::capability::MethodInfo] = &[
+    ::capnp::capability::MethodInfo { interface_id: _private::TYPE_ID, method_id: 0, name: "save", param_type_id: 0xf76f_ba59_1830_73a5, result_type_id: 0xb768_48c1_8c40_efbf },
+  ];
+
+  pub struct Client<SturdyRef,Owner> {
+    pub client: ::capnp::capability::Client,
+    _phantom: ::core::marker::PhantomData<(SturdyRef,Owner)>
+  }
+  impl <SturdyRef,Owner> ::capnp::capability::FromClientHook for Client<SturdyRef,Owner> {
+    #[allow(unused_parens)]
+    fn new(hook: Box<dyn (::capnp::private::capability::ClientHook)>) -> Client<SturdyRef,Owner> {
+      Client { client: ::capnp::capability::Client::new(hook), _phantom: ::core::marker::PhantomData, }
+    }
+  }
+  #[derive(Copy, Clone)]
+  pub struct Owned<SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+    _phantom: ::core::marker::PhantomData<(SturdyRef,Owner)>
+  }
+  impl <'a, SturdyRef,Owner> ::capnp::traits::Owned<'a> for Owned <SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  { type Reader = Client<SturdyRef,Owner>; type Builder = Client<SturdyRef,Owner>; }
+  impl <SturdyRef,Owner> ::capnp::traits::Pipelined for Owned <SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  { type Pipeline = Client<SturdyRef,Owner>; }
+  impl <'a,SturdyRef,Owner> ::capnp::traits::FromPointerReader<'a> for Client<SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+    fn get_from_pointer(reader: &::capnp::private::layout::PointerReader<'a>, _default: ::core::option::Option<&'a [capnp::Word]>) -> ::capnp::Result<Client<SturdyRef,Owner>> {
+      ::core::result::Result::Ok(::capnp::capability::FromClientHook::new(reader.get_capability()?))
+    }
+  }
+  impl <'a,SturdyRef,Owner> ::capnp::traits::FromPointerBuilder<'a> for Client<SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+    fn init_pointer(_builder: ::capnp::private::layout::PointerBuilder<'a>, _size: u32) -> Client<SturdyRef,Owner> {
+      unimplemented!()
+    }
+    fn get_from_pointer(builder: ::capnp::private::layout::PointerBuilder<'a>, _default: ::core::option::Option<&'a [capnp::Word]>) -> ::capnp::Result<Client<SturdyRef,Owner>> {
+      ::core::result::Result::Ok(::capnp::capability::FromClientHook::new(builder.get_capability()?))
+    }
+  }
+
+  impl <SturdyRef,Owner> ::capnp::traits::SetPointerBuilder<Client<SturdyRef,Owner>> for Client<SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+    fn set_pointer_builder(pointer: ::capnp::private::layout::PointerBuilder, from: Client<SturdyRef,Owner>, _canonicalize: bool) -> ::capnp::Result<()> {
+      pointer.set_capability(from.client.hook);
+      ::core::result::Result::Ok(())
+    }
+  }
+  impl <SturdyRef,Owner> ::capnp::traits::HasTypeId for Client<SturdyRef,Owner> {
+    #[inline]
+    fn type_id() -> u64 { _private::TYPE_ID }
+  }
+  impl <SturdyRef,Owner> Clone for Client<SturdyRef,Owner> {
+    fn clone(&self) -> Client<SturdyRef,Owner> {
+      Client { client: ::capnp::capability::Client::new(self.client.hook.add_ref()), _phantom: ::core::marker::PhantomData, }
+    }
+  }
+  impl <SturdyRef,Owner> Client<SturdyRef,Owner> {
+    pub fn save_request(&self) -> ::capnp::capability::Request<crate::persistent_capnp::persistent::save_params::Owned<SturdyRef,Owner>,crate::persistent_capnp::persistent::save_results::Owned<SturdyRef,Owner>> {
+      self.client.new_call(_private::TYPE_ID, 0, None)
+    }
+  }
+  pub trait Server<SturdyRef,Owner>  where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+    fn save(&mut self, _: SaveParams<SturdyRef,Owner,>, _: SaveResults<SturdyRef,Owner,>) -> ::capnp::capability::Promise<(), ::capnp::Error> { ::capnp::capability::Promise::err(::capnp::Error::unimplemented("Method not implemented: capnp-rpc/schema/persistent.capnp:Persistent.save".to_string())) }
+  }
+  pub struct ServerDispatch<_T,SturdyRef,Owner> {
+    pub server: _T,
+    dispatch: ::capnp::capability::DispatchTable<_T>,
+    _phantom: ::core::marker::PhantomData<(SturdyRef,Owner)>
+  }
+  impl <_S: Server<SturdyRef,Owner> + 'static, SturdyRef,Owner> ::capnp::capability::FromServer<_S> for Client<SturdyRef,Owner> where SturdyRef:'static + for<'c> ::capnp::traits::Owned<'c>, Owner:'static + for<'c> ::capnp::traits::Owned<'c>   {
+    type Dispatch = ServerDispatch<_S, SturdyRef,Owner>;
+    fn from_server(s: _S) -> ServerDispatch<_S, SturdyRef,Owner> {
+      ServerDispatch::<_S, SturdyRef,Owner>::new(s)
+    }
+  }
+  impl <SturdyRef,Owner, _T: Server<SturdyRef,Owner>> ::core::ops::Deref for ServerDispatch<_T,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+    type Target = _T;
+    fn deref(&self) -> &_T { &self.server}
+  }
+  impl <SturdyRef,Owner, _T: Server<SturdyRef,Owner>> ::core::ops::DerefMut for ServerDispatch<_T,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+    fn deref_mut(&mut self) -> &mut _T { &mut self.server}
+  }
+  impl <SturdyRef,Owner, _T: Server<SturdyRef,Owner>> ::capnp::capability::Server for ServerDispatch<_T,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+    fn dispatch_call(&mut self, interface_id: u64, method_id: u16, params: ::capnp::capability::Params<::capnp::any_pointer::Owned>, results: ::capnp::capability::Results<::capnp::any_pointer::Owned>) -> ::capnp::capability::Promise<(), ::capnp::Error> {
+      match self.dispatch.find(interface_id) {
+        ::core::option::Option::Some(dispatch) => dispatch(&mut self.server, method_id, params, results),
+        ::core::option::Option::None => { ::capnp::capability::Promise::err(::capnp::Error::unimplemented("Method not implemented.".to_string())) }
+      }
+    }
+  }
+  impl <SturdyRef,Owner, _T: Server<SturdyRef,Owner>> ServerDispatch<_T,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+    pub fn dispatch_call_internal(server: &mut _T, method_id: u16, params: ::capnp::capability::Params<::capnp::any_pointer::Owned>, results: ::capnp::capability::Results<::capnp::any_pointer::Owned>) -> ::capnp::capability::Promise<(), ::capnp::Error> {
+      match method_id {
+        0 => server.save(::capnp::private::capability::internal_get_typed_params(params), ::capnp::private::capability::internal_get_typed_results(results)),
+        _ => { ::capnp::capability::Promise::err(::capnp::Error::unimplemented("Method not implemented.".to_string())) }
+      }
+    }
+    pub fn new(server: _T) -> Self {
+      let mut dispatch = ::capnp::capability::DispatchTable::new();
+      ServerDispatch::<_T, SturdyRef,Owner>::register_dispatch(&mut dispatch);
+      ServerDispatch { server: server, dispatch: dispatch, _phantom: ::core::marker::PhantomData, }
+    }
+    pub fn register_dispatch(table: &mut ::capnp::capability::DispatchTable<_T>) {
+      table.register(_private::TYPE_ID, ServerDispatch::<_T, SturdyRef,Owner>::dispatch_call_internal);
+    }
+    pub fn with_dispatch(mut self, register: fn(&mut ::capnp::capability::DispatchTable<_T>)) -> Self {
+      register(&mut self.dispatch);
+      self
+    }
+  }
+  pub mod _private {
+    pub const TYPE_ID: u64 = 0xc8cb_212f_cd9f_5691;
+  }
+
+  pub mod save_params { /* SturdyRef,Owner */
+    #[derive(Copy, Clone)]
+    pub struct Owned<SturdyRef,Owner> {
+      _phantom: ::core::marker::PhantomData<(SturdyRef,Owner)>
+    }
+    impl <'a, SturdyRef,Owner> ::capnp::traits::Owned<'a> for Owned <SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  { type Reader = Reader<'a, SturdyRef,Owner>; type Builder = Builder<'a, SturdyRef,Owner>; }
+    impl <'a, SturdyRef,Owner> ::capnp::traits::OwnedStruct<'a> for Owned <SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  { type Reader = Reader<'a, SturdyRef,Owner>; type Builder = Builder<'a, SturdyRef,Owner>; }
+    impl <SturdyRef,Owner> ::capnp::traits::Pipelined for Owned<SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  { type Pipeline = Pipeline<SturdyRef,Owner>; }
+
+    #[derive(Clone, Copy)]
+    pub struct Reader<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      reader: ::capnp::private::layout::StructReader<'a>,
+      _phantom: ::core::marker::PhantomData<(SturdyRef,Owner)>
+    }
+
+    impl <'a,SturdyRef,Owner> ::capnp::traits::HasTypeId for Reader<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      #[inline]
+      fn type_id() -> u64 { _private::TYPE_ID }
+    }
+    impl <'a,SturdyRef,Owner> ::capnp::traits::FromStructReader<'a> for Reader<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      fn new(reader: ::capnp::private::layout::StructReader<'a>) -> Reader<'a,SturdyRef,Owner> {
+        Reader { reader, _phantom: ::core::marker::PhantomData, }
+      }
+    }
+
+    impl <'a,SturdyRef,Owner> ::capnp::traits::FromPointerReader<'a> for Reader<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      fn get_from_pointer(reader: &::capnp::private::layout::PointerReader<'a>, default: ::core::option::Option<&'a [capnp::Word]>) -> ::capnp::Result<Reader<'a,SturdyRef,Owner>> {
+        ::core::result::Result::Ok(::capnp::traits::FromStructReader::new(reader.get_struct(default)?))
+      }
+    }
+
+    impl <'a,SturdyRef,Owner> ::capnp::traits::IntoInternalStructReader<'a> for Reader<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      fn into_internal_struct_reader(self) -> ::capnp::private::layout::StructReader<'a> {
+        self.reader
+      }
+    }
+
+    impl <'a,SturdyRef,Owner> ::capnp::traits::Imbue<'a> for Reader<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      fn imbue(&mut self, cap_table: &'a ::capnp::private::layout::CapTable) {
+        self.reader.imbue(::capnp::private::layout::CapTableReader::Plain(cap_table))
+      }
+    }
+
+    impl <'a,SturdyRef,Owner> Reader<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      pub fn reborrow(&self) -> Reader<'_,SturdyRef,Owner> {
+        Reader { .. *self }
+      }
+
+      pub fn total_size(&self) -> ::capnp::Result<::capnp::MessageSize> {
+        self.reader.total_size()
+      }
+      #[inline]
+      pub fn get_seal_for(self) -> ::capnp::Result<<Owner as ::capnp::traits::Owned<'a>>::Reader> {
+        ::capnp::traits::FromPointerReader::get_from_pointer(&self.reader.get_pointer_field(0), ::core::option::Option::None)
+      }
+      pub fn has_seal_for(&self) -> bool {
+        !self.reader.get_pointer_field(0).is_null()
+      }
+    }
+
+    pub struct Builder<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      builder: ::capnp::private::layout::StructBuilder<'a>,
+      _phantom: ::core::marker::PhantomData<(SturdyRef,Owner)>
+    }
+    impl <'a,SturdyRef,Owner> ::capnp::traits::HasStructSize for Builder<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      #[inline]
+      fn struct_size() -> ::capnp::private::layout::StructSize { _private::STRUCT_SIZE }
+    }
+    impl <'a,SturdyRef,Owner> ::capnp::traits::HasTypeId for Builder<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      #[inline]
+      fn type_id() -> u64 { _private::TYPE_ID }
+    }
+    impl <'a,SturdyRef,Owner> ::capnp::traits::FromStructBuilder<'a> for Builder<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      fn new(builder: ::capnp::private::layout::StructBuilder<'a>) -> Builder<'a, SturdyRef,Owner> {
+        Builder { builder, _phantom: ::core::marker::PhantomData, }
+      }
+    }
+
+    impl <'a,SturdyRef,Owner> ::capnp::traits::ImbueMut<'a> for Builder<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      fn imbue_mut(&mut self, cap_table: &'a mut ::capnp::private::layout::CapTable) {
+        self.builder.imbue(::capnp::private::layout::CapTableBuilder::Plain(cap_table))
+      }
+    }
+
+    impl <'a,SturdyRef,Owner> ::capnp::traits::FromPointerBuilder<'a> for Builder<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      fn init_pointer(builder: ::capnp::private::layout::PointerBuilder<'a>, _size: u32) -> Builder<'a,SturdyRef,Owner> {
+        ::capnp::traits::FromStructBuilder::new(builder.init_struct(_private::STRUCT_SIZE))
+      }
+      fn get_from_pointer(builder: ::capnp::private::layout::PointerBuilder<'a>, default: ::core::option::Option<&'a [capnp::Word]>) -> ::capnp::Result<Builder<'a,SturdyRef,Owner>> {
+        ::core::result::Result::Ok(::capnp::traits::FromStructBuilder::new(builder.get_struct(_private::STRUCT_SIZE, default)?))
+      }
+    }
+
+    impl <'a,SturdyRef,Owner> ::capnp::traits::SetPointerBuilder<Builder<'a,SturdyRef,Owner>> for Reader<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      fn set_pointer_builder<'b>(pointer: ::capnp::private::layout::PointerBuilder<'b>, value: Reader<'a,SturdyRef,Owner>, canonicalize: bool) -> ::capnp::Result<()> { pointer.set_struct(&value.reader, canonicalize) }
+    }
+
+    impl <'a,SturdyRef,Owner> Builder<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      pub fn into_reader(self) -> Reader<'a,SturdyRef,Owner> {
+        ::capnp::traits::FromStructReader::new(self.builder.into_reader())
+      }
+      pub fn reborrow(&mut self) -> Builder<'_,SturdyRef,Owner> {
+        Builder { .. *self }
+      }
+      pub fn reborrow_as_reader(&self) -> Reader<'_,SturdyRef,Owner> {
+        ::capnp::traits::FromStructReader::new(self.builder.into_reader())
+      }
+
+      pub fn total_size(&self) -> ::capnp::Result<::capnp::MessageSize> {
+        self.builder.into_reader().total_size()
+      }
+      #[inline]
+      pub fn get_seal_for(self) -> ::capnp::Result<<Owner as ::capnp::traits::Owned<'a>>::Builder> {
+        ::capnp::traits::FromPointerBuilder::get_from_pointer(self.builder.get_pointer_field(0), ::core::option::Option::None)
+      }
+      #[inline]
+      pub fn initn_seal_for(self, length: u32) -> <Owner as ::capnp::traits::Owned<'a>>::Builder {
+        ::capnp::any_pointer::Builder::new(self.builder.get_pointer_field(0)).initn_as(length)
+      }
+      #[inline]
+      pub fn set_seal_for<SPB: ::capnp::traits::SetPointerBuilder<<Owner as ::capnp::traits::Owned<'a>>::Builder>>(&mut self, value: SPB) -> ::capnp::Result<()> {
+        ::capnp::traits::SetPointerBuilder::set_pointer_builder(self.builder.get_pointer_field(0), value, false)
+      }
+      #[inline]
+      pub fn init_seal_for(self, ) -> <Owner as ::capnp::traits::Owned<'a>>::Builder {
+        ::capnp::any_pointer::Builder::new(self.builder.get_pointer_field(0)).init_as()
+      }
+      pub fn has_seal_for(&self) -> bool {
+        !self.builder.get_pointer_field(0).is_null()
+      }
+    }
+
+    pub struct Pipeline<SturdyRef,Owner> {
+      _typeless: ::capnp::any_pointer::Pipeline,
+      _phantom: ::core::marker::PhantomData<(SturdyRef,Owner)>
+    }
+    impl<SturdyRef,Owner> ::capnp::capability::FromTypelessPipeline for Pipeline<SturdyRef,Owner> {
+      fn new(typeless: ::capnp::any_pointer::Pipeline) -> Pipeline<SturdyRef,Owner> {
+        Pipeline { _typeless: typeless, _phantom: ::core::marker::PhantomData, }
+      }
+    }
+    impl<SturdyRef,Owner> Pipeline<SturdyRef,Owner> where SturdyRef: ::capnp::traits::Pipelined, <SturdyRef as ::capnp::traits::Pipelined>::Pipeline: ::capnp::capability::FromTypelessPipeline, Owner: ::capnp::traits::Pipelined, <Owner as ::capnp::traits::Pipelined>::Pipeline: ::capnp::capability::FromTypelessPipeline  {
+      pub fn get_seal_for(&self) -> <Owner as ::capnp::traits::Pipelined>::Pipeline {
+        ::capnp::capability::FromTypelessPipeline::new(self._typeless.get_pointer_field(0))
+      }
+    }
+    mod _private {
+      use capnp::private::layout;
+      pub const STRUCT_SIZE: layout::StructSize = layout::StructSize { data: 0, pointers: 1 };
+      pub const TYPE_ID: u64 = 0xf76f_ba59_1830_73a5;
+    }
+  }
+
+  pub mod save_results { /* SturdyRef,Owner */
+    #[derive(Copy, Clone)]
+    pub struct Owned<SturdyRef,Owner> {
+      _phantom: ::core::marker::PhantomData<(SturdyRef,Owner)>
+    }
+    impl <'a, SturdyRef,Owner> ::capnp::traits::Owned<'a> for Owned <SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  { type Reader = Reader<'a, SturdyRef,Owner>; type Builder = Builder<'a, SturdyRef,Owner>; }
+    impl <'a, SturdyRef,Owner> ::capnp::traits::OwnedStruct<'a> for Owned <SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  { type Reader = Reader<'a, SturdyRef,Owner>; type Builder = Builder<'a, SturdyRef,Owner>; }
+    impl <SturdyRef,Owner> ::capnp::traits::Pipelined for Owned<SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  { type Pipeline = Pipeline<SturdyRef,Owner>; }
+
+    #[derive(Clone, Copy)]
+    pub struct Reader<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      reader: ::capnp::private::layout::StructReader<'a>,
+      _phantom: ::core::marker::PhantomData<(SturdyRef,Owner)>
+    }
+
+    impl <'a,SturdyRef,Owner> ::capnp::traits::HasTypeId for Reader<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      #[inline]
+      fn type_id() -> u64 { _private::TYPE_ID }
+    }
+    impl <'a,SturdyRef,Owner> ::capnp::traits::FromStructReader<'a> for Reader<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      fn new(reader: ::capnp::private::layout::StructReader<'a>) -> Reader<'a,SturdyRef,Owner> {
+        Reader { reader, _phantom: ::core::marker::PhantomData, }
+      }
+    }
+
+    impl <'a,SturdyRef,Owner> ::capnp::traits::FromPointerReader<'a> for Reader<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      fn get_from_pointer(reader: &::capnp::private::layout::PointerReader<'a>, default: ::core::option::Option<&'a [capnp::Word]>) -> ::capnp::Result<Reader<'a,SturdyRef,Owner>> {
+        ::core::result::Result::Ok(::capnp::traits::FromStructReader::new(reader.get_struct(default)?))
+      }
+    }
+
+    impl <'a,SturdyRef,Owner> ::capnp::traits::IntoInternalStructReader<'a> for Reader<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      fn into_internal_struct_reader(self) -> ::capnp::private::layout::StructReader<'a> {
+        self.reader
+      }
+    }
+
+    impl <'a,SturdyRef,Owner> ::capnp::traits::Imbue<'a> for Reader<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      fn imbue(&mut self, cap_table: &'a ::capnp::private::layout::CapTable) {
+        self.reader.imbue(::capnp::private::layout::CapTableReader::Plain(cap_table))
+      }
+    }
+
+    impl <'a,SturdyRef,Owner> Reader<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      pub fn reborrow(&self) -> Reader<'_,SturdyRef,Owner> {
+        Reader { .. *self }
+      }
+
+      pub fn total_size(&self) -> ::capnp::Result<::capnp::MessageSize> {
+        self.reader.total_size()
+      }
+      #[inline]
+      pub fn get_sturdy_ref(self) -> ::capnp::Result<<SturdyRef as ::capnp::traits::Owned<'a>>::Reader> {
+        ::capnp::traits::FromPointerReader::get_from_pointer(&self.reader.get_pointer_field(0), ::core::option::Option::None)
+      }
+      pub fn has_sturdy_ref(&self) -> bool {
+        !self.reader.get_pointer_field(0).is_null()
+      }
+    }
+
+    pub struct Builder<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      builder: ::capnp::private::layout::StructBuilder<'a>,
+      _phantom: ::core::marker::PhantomData<(SturdyRef,Owner)>
+    }
+    impl <'a,SturdyRef,Owner> ::capnp::traits::HasStructSize for Builder<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      #[inline]
+      fn struct_size() -> ::capnp::private::layout::StructSize { _private::STRUCT_SIZE }
+    }
+    impl <'a,SturdyRef,Owner> ::capnp::traits::HasTypeId for Builder<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      #[inline]
+      fn type_id() -> u64 { _private::TYPE_ID }
+    }
+    impl <'a,SturdyRef,Owner> ::capnp::traits::FromStructBuilder<'a> for Builder<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      fn new(builder: ::capnp::private::layout::StructBuilder<'a>) -> Builder<'a, SturdyRef,Owner> {
+        Builder { builder, _phantom: ::core::marker::PhantomData, }
+      }
+    }
+
+    impl <'a,SturdyRef,Owner> ::capnp::traits::ImbueMut<'a> for Builder<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      fn imbue_mut(&mut self, cap_table: &'a mut ::capnp::private::layout::CapTable) {
+        self.builder.imbue(::capnp::private::layout::CapTableBuilder::Plain(cap_table))
+      }
+    }
+
+    impl <'a,SturdyRef,Owner> ::capnp::traits::FromPointerBuilder<'a> for Builder<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      fn init_pointer(builder: ::capnp::private::layout::PointerBuilder<'a>, _size: u32) -> Builder<'a,SturdyRef,Owner> {
+        ::capnp::traits::FromStructBuilder::new(builder.init_struct(_private::STRUCT_SIZE))
+      }
+      fn get_from_pointer(builder: ::capnp::private::layout::PointerBuilder<'a>, default: ::core::option::Option<&'a [capnp::Word]>) -> ::capnp::Result<Builder<'a,SturdyRef,Owner>> {
+        ::core::result::Result::Ok(::capnp::traits::FromStructBuilder::new(builder.get_struct(_private::STRUCT_SIZE, default)?))
+      }
+    }
+
+    impl <'a,SturdyRef,Owner> ::capnp::traits::SetPointerBuilder<Builder<'a,SturdyRef,Owner>> for Reader<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      fn set_pointer_builder<'b>(pointer: ::capnp::private::layout::PointerBuilder<'b>, value: Reader<'a,SturdyRef,Owner>, canonicalize: bool) -> ::capnp::Result<()> { pointer.set_struct(&value.reader, canonicalize) }
+    }
+
+    impl <'a,SturdyRef,Owner> Builder<'a,SturdyRef,Owner> where SturdyRef: for<'c> ::capnp::traits::Owned<'c>, Owner: for<'c> ::capnp::traits::Owned<'c>  {
+      pub fn into_reader(self) -> Reader<'a,SturdyRef,Owner> {
+        ::capnp::traits::FromStructReader::new(self.builder.into_reader())
+      }
+      pub fn reborrow(&mut self) -> Builder<'_,SturdyRef,Owner> {
+        Builder { .. *self }
+      }
+      pub fn reborrow_as_reader(&self) -> Reader<'_,SturdyRef,Owner> {
+        ::capnp::traits::FromStructReader::new(self.builder.into_reader())
+      }
+
+      pub fn total_size(&self) -> ::capnp::Result<::capnp::MessageSize> {
+        self.builder.into_reader().total_size()
+      }
+      #[inline]
+      pub fn get_sturdy_ref(self) -> ::capnp::Result<<SturdyRef as ::capnp::traits::Owned<'a>>::Builder> {
+        ::capnp::traits::FromPointerBuilder::get_from_pointer(self.builder.get_pointer_field(0), ::core::option::Option::None)
+      }
+      #[inline]
+      pub fn initn_sturdy_ref(self, length: u32) -> <SturdyRef as ::capnp::traits::Owned<'a>>::Builder {
+        ::capnp::any_pointer::Builder::new(self.builder.get_pointer_field(0)).initn_as(length)
+      }
+      #[inline]
+      pub fn set_sturdy_ref<SPB: ::capnp::traits::SetPointerBuilder<<SturdyRef as ::capnp::traits::Owned<'a>>::Builder>>(&mut self, value: SPB) -> ::capnp::Result<()> {
+        ::capnp::traits::SetPointerBuilder::set_pointer_builder(self.builder.get_pointer_field(0), value, false)
+      }
+      #[inline]
+      pub fn init_sturdy_ref(self, ) -> <SturdyRef as ::capnp::traits::Owned<'a>>::Builder {
+        ::capnp::any_pointer::Builder::new(self.builder.get_pointer_field(0)).init_as()
+      }
+      pub fn has_sturdy_ref(&self) -> bool {
+        !self.builder.get_pointer_field(0).is_null()
+      }
+    }
+
+    pub struct Pipeline<SturdyRef,Owner> {
+      _typeless: ::capnp::any_pointer::Pipeline,
+      _phantom: ::core::marker::PhantomData<(SturdyRef,Owner)>
+    }
+    impl<SturdyRef,Owner> ::capnp::capability::FromTypelessPipeline for Pipeline<SturdyRef,Owner> {
+      fn new(typeless: ::capnp::any_pointer::Pipeline) -> Pipeline<SturdyRef,Owner> {
+        Pipeline { _typeless: typeless, _phantom: ::core::marker::PhantomData, }
+      }
+    }
+    impl<SturdyRef,Owner> Pipeline<SturdyRef,Owner> where SturdyRef: ::capnp::traits::Pipelined, <SturdyRef as ::capnp::traits::Pipelined>::Pipeline: ::capnp::capability::FromTypelessPipeline, Owner: ::capnp::traits::Pipelined, <Owner as ::capnp::traits::Pipelined>::Pipeline: ::capnp::capability::FromTypelessPipeline  {
+      pub fn get_sturdy_ref(&self) -> <SturdyRef as ::capnp::traits::Pipelined>::Pipeline {
+        ::capnp::capability::FromTypelessPipeline::new(self._typeless.get_pointer_field(0))
+      }
+    }
+    mod _private {
+      use capnp::private::layout;
+      pub const STRUCT_SIZE: layout::StructSize = layout::StructSize { data: 0, pointers: 1 };
+      pub const TYPE_ID: u64 = 0xb768_48c1_8c40_efbf;
+    }
+  }
+}
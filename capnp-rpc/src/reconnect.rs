@@ -0,0 +1,113 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! A client that re-establishes its connection when it is lost.
+//!
+//! The application supplies a `connect` function that opens a new connection and returns the
+//! target capability, usually by restoring a SturdyRef (see the `persistent` module). The
+//! function is first called when the wrapper receives its first call, and again on the next call
+//! after any call fails with `ErrorKind::Disconnected`. Calls that were in flight when the
+//! connection dropped fail with that error; they are not retried, because they may already
+//! have taken effect.
+
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+
+use capnp::{any_pointer, Error};
+use capnp::capability::{self, FromClientHook, Params, Promise, Results};
+use capnp::private::capability::ClientHook;
+use futures::TryFutureExt;
+
+/// Creates a client that forwards calls to the capability returned by `connect`, calling
+/// `connect` again whenever the previous connection has been lost.
+///
+/// `connect` is responsible for driving the `RpcSystem` of each connection that it makes, for
+/// example by spawning it. If `connect` fails, the calls waiting on it fail with its error, and
+/// the next call tries again.
+pub fn new_client<C, F>(connect: F) -> C
+    where C: FromClientHook,
+          F: FnMut() -> Promise<capability::Client, Error> + 'static,
+{
+    let server = ReconnectingServer {
+        connect: connect,
+        current: Rc::new(RefCell::new(None)),
+        generation: 0,
+    };
+    FromClientHook::new(Box::new(crate::local::Client::new(Box::new(server))))
+}
+
+/// The current connection's capability, and the number of the `connect()` call that produced it.
+type Current = RefCell<Option<(u64, Box<dyn ClientHook>)>>;
+
+/// Forgets the current connection, if it is still the one numbered `generation`.
+fn reset(current: &Weak<Current>, generation: u64) {
+    if let Some(current) = current.upgrade() {
+        let mut current = current.borrow_mut();
+        if let Some((g, _)) = *current {
+            if g == generation {
+                *current = None;
+            }
+        }
+    }
+}
+
+struct ReconnectingServer<F> {
+    connect: F,
+    current: Rc<Current>,
+    generation: u64,
+}
+
+impl <F> ReconnectingServer<F> where F: FnMut() -> Promise<capability::Client, Error> {
+    fn get_current(&mut self) -> (u64, Box<dyn ClientHook>) {
+        if let Some((generation, ref hook)) = *self.current.borrow() {
+            return (generation, hook.add_ref());
+        }
+
+        self.generation += 1;
+        let generation = self.generation;
+        let weak_current = Rc::downgrade(&self.current);
+        let hook = crate::new_promise_client_hook((self.connect)().map_err(move |e| {
+            reset(&weak_current, generation);
+            e
+        }));
+        *self.current.borrow_mut() = Some((generation, hook.add_ref()));
+        (generation, hook)
+    }
+}
+
+impl <F> capability::Server for ReconnectingServer<F>
+    where F: FnMut() -> Promise<capability::Client, Error>
+{
+    fn dispatch_call(&mut self, interface_id: u64, method_id: u16,
+                     params: Params<any_pointer::Owned>,
+                     results: Results<any_pointer::Owned>)
+                     -> Promise<(), Error>
+    {
+        let (generation, hook) = self.get_current();
+        let weak_current = Rc::downgrade(&self.current);
+        Promise::from_future(hook.call(interface_id, method_id, params.hook, results.hook).map_err(move |e| {
+            if e.kind == ::capnp::ErrorKind::Disconnected {
+                reset(&weak_current, generation);
+            }
+            e
+        }))
+    }
+}
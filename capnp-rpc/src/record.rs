@@ -0,0 +1,384 @@
+// Copyright (c) 2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Recording of RPC sessions, and replaying of recorded sessions into a server.
+//!
+//! `RecordingVatNetwork` wraps another `VatNetwork` and logs every message that passes through
+//! its connections. The log is a sequence of entries, each consisting of two messages in the
+//! [standard stream framing](https://capnproto.org/encoding.html#serialization-over-a-stream):
+//!
+//!   1. a header, whose root is a `List(UInt64)` containing the direction
+//!      (0 = incoming, 1 = outgoing) and the time in microseconds since the Unix epoch;
+//!   2. the RPC message itself, whose root is an `rpc_capnp::message`.
+//!
+//! `read_entry()` reads one entry back. `ReplayVatNetwork` feeds the incoming messages of a
+//! recorded session to a local `RpcSystem`, and collects the messages it sends in response so
+//! that they can be compared against the recording.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use capnp::{any_pointer, message, primitive_list, serialize, Error};
+use capnp::capability::Promise;
+use capnp::message::{HeapAllocator, ReaderOptions, ReaderSegments};
+use futures::{FutureExt, TryFutureExt};
+use futures::channel::oneshot;
+
+use crate::twoparty::VatId;
+
+/// Which way a recorded message was travelling, from the point of view of the recording vat.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Incoming = 0,
+    Outgoing = 1,
+}
+
+/// A message read from a session log.
+pub struct Entry {
+    pub direction: Direction,
+    pub timestamp: SystemTime,
+    pub message: message::Reader<serialize::OwnedSegments>,
+}
+
+/// Reads the next entry from a session log. Returns `None` at the end of the log.
+pub fn read_entry<R>(mut read: R, options: ReaderOptions) -> ::capnp::Result<Option<Entry>>
+    where R: std::io::Read
+{
+    let header = match serialize::try_read_message(&mut read, options)? {
+        Some(h) => h,
+        None => return Ok(None),
+    };
+    let fields: primitive_list::Reader<u64> = header.get_root::<any_pointer::Reader>()?.get_as()?;
+    if fields.len() < 2 {
+        return Err(Error::failed("Session log entry has a malformed header.".to_string()));
+    }
+    let direction = match fields.get(0) {
+        0 => Direction::Incoming,
+        1 => Direction::Outgoing,
+        d => return Err(Error::failed(format!("Session log entry has unknown direction {}.", d))),
+    };
+    let timestamp = UNIX_EPOCH + Duration::from_micros(fields.get(1));
+    let message = serialize::read_message(&mut read, options)?;
+    Ok(Some(Entry { direction: direction, timestamp: timestamp, message: message }))
+}
+
+fn write_entry<W, R>(log: &RefCell<W>, direction: Direction, segments: &R) -> ::capnp::Result<()>
+    where W: std::io::Write, R: ReaderSegments + ?Sized
+{
+    let micros = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_micros() as u64).unwrap_or(0);
+    let mut header = message::Builder::new_default();
+    {
+        let mut fields: primitive_list::Builder<u64> = header.init_root::<any_pointer::Builder>().initn_as(2);
+        fields.set(0, direction as u64);
+        fields.set(1, micros);
+    }
+    let mut log = log.borrow_mut();
+    serialize::write_message(&mut *log, &header)?;
+    serialize::write_message_segments(&mut *log, segments)
+}
+
+fn record_incoming<W>(log: &RefCell<W>, message: &dyn crate::IncomingMessage) -> ::capnp::Result<()>
+    where W: std::io::Write
+{
+    match message.get_segments() {
+        Some(segments) => write_entry(log, Direction::Incoming, segments),
+        None => {
+            // Capability pointers in the body can't be copied without a cap table,
+            // so this only works for messages that don't contain any.
+            let mut copy = message::Builder::new_default();
+            copy.set_root(message.get_body()?)?;
+            write_entry(log, Direction::Incoming, &copy)
+        }
+    }
+}
+
+/// A `VatNetwork` that logs all messages sent and received on the connections of another
+/// `VatNetwork`.
+pub struct RecordingVatNetwork<VatId, W> where W: std::io::Write {
+    inner: Box<dyn crate::VatNetwork<VatId>>,
+    log: Rc<RefCell<W>>,
+}
+
+impl <VatId, W> RecordingVatNetwork<VatId, W> where W: std::io::Write {
+    /// Wraps `inner`, writing the log to `log`. Writes are unbuffered and synchronous, so
+    /// `log` should usually be a `BufWriter`.
+    pub fn new(inner: Box<dyn crate::VatNetwork<VatId>>, log: W) -> RecordingVatNetwork<VatId, W> {
+        RecordingVatNetwork { inner: inner, log: Rc::new(RefCell::new(log)) }
+    }
+}
+
+impl <VatId, W> crate::VatNetwork<VatId> for RecordingVatNetwork<VatId, W>
+    where VatId: 'static, W: std::io::Write + 'static
+{
+    fn connect(&mut self, host_id: VatId) -> Option<Box<dyn crate::Connection<VatId>>> {
+        let log = self.log.clone();
+        self.inner.connect(host_id).map(|inner| {
+            Box::new(RecordingConnection { inner: inner, log: log }) as Box<dyn crate::Connection<VatId>>
+        })
+    }
+
+    fn accept(&mut self) -> Promise<Box<dyn crate::Connection<VatId>>, Error> {
+        let log = self.log.clone();
+        Promise::from_future(self.inner.accept().map_ok(move |inner| {
+            Box::new(RecordingConnection { inner: inner, log: log }) as Box<dyn crate::Connection<VatId>>
+        }))
+    }
+
+    fn drive_until_shutdown(&mut self) -> Promise<(), Error> {
+        self.inner.drive_until_shutdown()
+    }
+}
+
+struct RecordingConnection<VatId, W> where W: std::io::Write {
+    inner: Box<dyn crate::Connection<VatId>>,
+    log: Rc<RefCell<W>>,
+}
+
+impl <VatId, W> crate::Connection<VatId> for RecordingConnection<VatId, W>
+    where W: std::io::Write + 'static
+{
+    fn get_peer_vat_id(&self) -> VatId {
+        self.inner.get_peer_vat_id()
+    }
+
+    fn get_peer_identity(&self) -> Option<String> {
+        self.inner.get_peer_identity()
+    }
+
+    fn new_outgoing_message(&mut self, first_segment_word_size: u32) -> Box<dyn crate::OutgoingMessage> {
+        Box::new(RecordingOutgoingMessage {
+            inner: self.inner.new_outgoing_message(first_segment_word_size),
+            log: self.log.clone(),
+        })
+    }
+
+    fn receive_incoming_message(&mut self) -> Promise<Option<Box<dyn crate::IncomingMessage>>, Error> {
+        let log = self.log.clone();
+        Promise::from_future(self.inner.receive_incoming_message().map(move |r| {
+            let maybe_message = r?;
+            if let Some(ref message) = maybe_message {
+                record_incoming(&log, &**message)?;
+            }
+            Ok(maybe_message)
+        }))
+    }
+
+    fn shutdown(&mut self, result: ::capnp::Result<()>) -> Promise<(), Error> {
+        self.inner.shutdown(result)
+    }
+}
+
+struct RecordingOutgoingMessage<W> where W: std::io::Write {
+    inner: Box<dyn crate::OutgoingMessage>,
+    log: Rc<RefCell<W>>,
+}
+
+impl <W> crate::OutgoingMessage for RecordingOutgoingMessage<W> where W: std::io::Write {
+    fn get_body<'a>(&'a mut self) -> ::capnp::Result<any_pointer::Builder<'a>> {
+        self.inner.get_body()
+    }
+
+    fn get_body_as_reader<'a>(&'a self) -> ::capnp::Result<any_pointer::Reader<'a>> {
+        self.inner.get_body_as_reader()
+    }
+
+    fn send(self: Box<Self>)
+            -> (Promise<Rc<message::Builder<HeapAllocator>>, Error>, Rc<message::Builder<HeapAllocator>>)
+    {
+        let RecordingOutgoingMessage { inner, log } = *self;
+        let (promise, message) = inner.send();
+        match write_entry(&log, Direction::Outgoing, &*message) {
+            Ok(()) => (promise, message),
+            Err(e) => (Promise::err(e), message),
+        }
+    }
+
+    fn take(self: Box<Self>) -> message::Builder<HeapAllocator> {
+        self.inner.take()
+    }
+}
+
+/// A two-party `VatNetwork` whose single connection replays the incoming messages of a recorded
+/// session, as fast as the local vat will accept them. Timestamps are ignored. Messages that the
+/// local vat sends are not delivered anywhere, but are kept for inspection via `sent_messages()`.
+pub struct ReplayVatNetwork {
+    connection: Option<ReplayConnection>,
+    side: VatId,
+    sent: Rc<RefCell<Vec<Rc<message::Builder<HeapAllocator>>>>>,
+    recorded_outgoing: Vec<message::Reader<serialize::OwnedSegments>>,
+    execution_driver: futures::future::Shared<Promise<(), Error>>,
+}
+
+impl ReplayVatNetwork {
+    /// Reads the log of a session that was recorded by the vat on `side`.
+    pub fn new<R>(mut log: R, side: VatId, options: ReaderOptions) -> ::capnp::Result<ReplayVatNetwork>
+        where R: std::io::Read
+    {
+        let mut incoming = VecDeque::new();
+        let mut recorded_outgoing = Vec::new();
+        while let Some(entry) = read_entry(&mut log, options)? {
+            match entry.direction {
+                Direction::Incoming => incoming.push_back(entry.message),
+                Direction::Outgoing => recorded_outgoing.push(entry.message),
+            }
+        }
+
+        let (fulfiller, disconnect_promise) = oneshot::channel();
+        let execution_driver = Promise::from_future(disconnect_promise.map(|_| Ok(()))).shared();
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        let peer = match side {
+            VatId::Client => VatId::Server,
+            VatId::Server => VatId::Client,
+        };
+        Ok(ReplayVatNetwork {
+            connection: Some(ReplayConnection {
+                incoming: incoming,
+                peer: peer,
+                sent: sent.clone(),
+                on_disconnect_fulfiller: Some(fulfiller),
+            }),
+            side: side,
+            sent: sent,
+            recorded_outgoing: recorded_outgoing,
+            execution_driver: execution_driver,
+        })
+    }
+
+    /// Returns a handle to the messages sent by the local vat so far. The handle remains valid
+    /// after the network has been moved into an `RpcSystem`.
+    pub fn sent_messages(&self) -> Rc<RefCell<Vec<Rc<message::Builder<HeapAllocator>>>>> {
+        self.sent.clone()
+    }
+
+    /// Returns the messages that were sent by the vat that made the recording.
+    pub fn take_recorded_outgoing(&mut self) -> Vec<message::Reader<serialize::OwnedSegments>> {
+        std::mem::replace(&mut self.recorded_outgoing, Vec::new())
+    }
+}
+
+impl crate::VatNetwork<VatId> for ReplayVatNetwork {
+    fn connect(&mut self, host_id: VatId) -> Option<Box<dyn crate::Connection<VatId>>> {
+        if host_id == self.side {
+            None
+        } else {
+            match self.connection.take() {
+                Some(c) => Some(Box::new(c)),
+                None => panic!("tried to reconnect a replay vat network."),
+            }
+        }
+    }
+
+    fn accept(&mut self) -> Promise<Box<dyn crate::Connection<VatId>>, Error> {
+        match self.connection.take() {
+            Some(c) => Promise::ok(Box::new(c) as Box<dyn crate::Connection<VatId>>),
+            None => Promise::from_future(::futures::future::pending()),
+        }
+    }
+
+    fn drive_until_shutdown(&mut self) -> Promise<(), Error> {
+        Promise::from_future(self.execution_driver.clone())
+    }
+}
+
+struct ReplayConnection {
+    incoming: VecDeque<message::Reader<serialize::OwnedSegments>>,
+    peer: VatId,
+    sent: Rc<RefCell<Vec<Rc<message::Builder<HeapAllocator>>>>>,
+    on_disconnect_fulfiller: Option<oneshot::Sender<()>>,
+}
+
+impl Drop for ReplayConnection {
+    fn drop(&mut self) {
+        if let Some(fulfiller) = self.on_disconnect_fulfiller.take() {
+            let _ = fulfiller.send(());
+        }
+    }
+}
+
+impl crate::Connection<VatId> for ReplayConnection {
+    fn get_peer_vat_id(&self) -> VatId {
+        self.peer
+    }
+
+    fn new_outgoing_message(&mut self, _first_segment_word_size: u32) -> Box<dyn crate::OutgoingMessage> {
+        Box::new(ReplayOutgoingMessage {
+            message: message::Builder::new_default(),
+            sent: self.sent.clone(),
+        })
+    }
+
+    fn receive_incoming_message(&mut self) -> Promise<Option<Box<dyn crate::IncomingMessage>>, Error> {
+        Promise::ok(self.incoming.pop_front().map(|message| {
+            Box::new(ReplayIncomingMessage { message: message }) as Box<dyn crate::IncomingMessage>
+        }))
+    }
+
+    fn shutdown(&mut self, _result: ::capnp::Result<()>) -> Promise<(), Error> {
+        if let Some(fulfiller) = self.on_disconnect_fulfiller.take() {
+            let _ = fulfiller.send(());
+        }
+        Promise::ok(())
+    }
+}
+
+struct ReplayIncomingMessage {
+    message: message::Reader<serialize::OwnedSegments>,
+}
+
+impl crate::IncomingMessage for ReplayIncomingMessage {
+    fn get_body<'a>(&'a self) -> ::capnp::Result<any_pointer::Reader<'a>> {
+        self.message.get_root()
+    }
+
+    fn get_segments(&self) -> Option<&dyn ReaderSegments> {
+        Some(self.message.get_segments())
+    }
+}
+
+struct ReplayOutgoingMessage {
+    message: message::Builder<HeapAllocator>,
+    sent: Rc<RefCell<Vec<Rc<message::Builder<HeapAllocator>>>>>,
+}
+
+impl crate::OutgoingMessage for ReplayOutgoingMessage {
+    fn get_body<'a>(&'a mut self) -> ::capnp::Result<any_pointer::Builder<'a>> {
+        self.message.get_root()
+    }
+
+    fn get_body_as_reader<'a>(&'a self) -> ::capnp::Result<any_pointer::Reader<'a>> {
+        self.message.get_root_as_reader()
+    }
+
+    fn send(self: Box<Self>)
+            -> (Promise<Rc<message::Builder<HeapAllocator>>, Error>, Rc<message::Builder<HeapAllocator>>)
+    {
+        let ReplayOutgoingMessage { message, sent } = *self;
+        let message = Rc::new(message);
+        sent.borrow_mut().push(message.clone());
+        (Promise::ok(message.clone()), message)
+    }
+
+    fn take(self: Box<Self>) -> message::Builder<HeapAllocator> {
+        self.message
+    }
+}
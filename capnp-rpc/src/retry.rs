@@ -0,0 +1,110 @@
+// Copyright (c) 2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! A client that retries failed calls to idempotent methods.
+//!
+//! Only methods that are safe to run more than once are retried. Mark them in the schema with
+//! `$Rust.idempotent` and pass the generated `IDEMPOTENT_METHODS` constant of each interface to
+//! `new_client()`. Calls to other methods are forwarded unchanged.
+
+use std::rc::Rc;
+
+use capnp::{any_pointer, Error, ErrorKind};
+use capnp::capability::{self, FromClientHook, Params, Promise, Results};
+use capnp::private::capability::ClientHook;
+
+/// Decides whether, and when, to retry a failed call.
+pub trait RetryPolicy {
+    /// Called when attempt number `attempt`, counting from 1, of a call failed with `error`.
+    /// Returns `None` to give up and fail the call with `error`, or a promise that resolves when
+    /// the call should be sent again.
+    fn retry(&self, attempt: u32, error: &Error) -> Option<Promise<(), Error>>;
+}
+
+/// Retries immediately, up to a total of `max_attempts` attempts, when a call fails with
+/// `ErrorKind::Disconnected` or `ErrorKind::Overloaded`. Other errors come from the callee
+/// itself and are not retried.
+#[derive(Clone, Copy, Debug)]
+pub struct MaxAttempts(pub u32);
+
+impl RetryPolicy for MaxAttempts {
+    fn retry(&self, attempt: u32, error: &Error) -> Option<Promise<(), Error>> {
+        match error.kind {
+            ErrorKind::Disconnected | ErrorKind::Overloaded if attempt < self.0 => Some(Promise::ok(())),
+            _ => None,
+        }
+    }
+}
+
+/// Wraps `client` so that calls to the methods listed in `idempotent_methods`, as
+/// (interface id, method ordinal) pairs, are retried according to `policy`.
+///
+/// Each retry sends a fresh request to `client` with a copy of the original parameters. If
+/// `client` itself is broken for good, for example because its connection is gone, every
+/// attempt fails the same way; combine this with `reconnect::new_client()` to get a new one.
+pub fn new_client<C, P>(client: capability::Client, idempotent_methods: &[(u64, u16)], policy: P) -> C
+    where C: FromClientHook,
+          P: RetryPolicy + 'static,
+{
+    let server = RetryingServer {
+        target: client.hook,
+        idempotent_methods: idempotent_methods.to_vec(),
+        policy: Rc::new(policy),
+    };
+    FromClientHook::new(Box::new(crate::local::Client::new(Box::new(server))))
+}
+
+struct RetryingServer<P> {
+    target: Box<dyn ClientHook>,
+    idempotent_methods: Vec<(u64, u16)>,
+    policy: Rc<P>,
+}
+
+impl <P> capability::Server for RetryingServer<P> where P: RetryPolicy + 'static {
+    fn dispatch_call(&mut self, interface_id: u64, method_id: u16,
+                     params: Params<any_pointer::Owned>,
+                     mut results: Results<any_pointer::Owned>)
+                     -> Promise<(), Error>
+    {
+        if !self.idempotent_methods.contains(&(interface_id, method_id)) {
+            return self.target.call(interface_id, method_id, params.hook, results.hook);
+        }
+
+        let target = self.target.add_ref();
+        let policy = self.policy.clone();
+        Promise::from_future(async move {
+            let mut attempt = 1;
+            loop {
+                let mut request = target.new_call(interface_id, method_id, None);
+                request.get().set_as(params.get()?)?;
+                let error = match request.send().promise.await {
+                    Ok(response) => return results.get().set_as(response.get()?),
+                    Err(e) => e,
+                };
+                match policy.retry(attempt, &error) {
+                    Some(delay) => delay.await?,
+                    None => return Err(error),
+                }
+                attempt += 1;
+            }
+        })
+    }
+}
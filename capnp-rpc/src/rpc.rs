@@ -24,7 +24,7 @@ use std::task::{Context, Poll};
 
 use capnp::{any_pointer};
 use capnp::Error;
-use capnp::capability::Promise;
+use capnp::capability::{CallerInfo, Promise};
 use capnp::private::capability::{ClientHook, ParamsHook, PipelineHook, PipelineOp,
                                  RequestHook, ResponseHook, ResultsHook};
 
@@ -36,6 +36,7 @@ use std::collections::hash_map::HashMap;
 use std::collections::binary_heap::BinaryHeap;
 use std::cell::{Cell, RefCell};
 use std::rc::{Rc, Weak};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::{cmp, mem};
 
 use crate::rpc_capnp::{call, cap_descriptor, disembargo, exception,
@@ -377,8 +378,37 @@ impl <VatId> crate::task_set::TaskReaper<capnp::Error> for ConnectionErrorHandle
     }
 }
 
+// Calls outstanding on a connection, in total and by the `get_ptr()` of their target.
+#[derive(Default)]
+struct OutstandingCalls {
+    total: u32,
+    by_capability: HashMap<usize, u32>,
+}
+
+// Counts a call as outstanding until dropped.
+struct OutstandingCall {
+    calls: Rc<RefCell<OutstandingCalls>>,
+    capability: usize,
+}
+
+impl Drop for OutstandingCall {
+    fn drop(&mut self) {
+        let mut calls = self.calls.borrow_mut();
+        calls.total -= 1;
+        let remove = match calls.by_capability.get_mut(&self.capability) {
+            Some(count) => { *count -= 1; *count == 0 }
+            None => false,
+        };
+        if remove {
+            calls.by_capability.remove(&self.capability);
+        }
+    }
+}
+
 pub struct ConnectionState<VatId> where VatId: 'static {
     bootstrap_cap: Box<dyn ClientHook>,
+    call_limits: Rc<Cell<crate::CallLimits>>,
+    outstanding_calls: Rc<RefCell<OutstandingCalls>>,
     exports: RefCell<ExportTable<Export>>,
     questions: RefCell<ExportTable<Question<VatId>>>,
     answers: RefCell<ImportTable<Answer<VatId>>>,
@@ -393,17 +423,63 @@ pub struct ConnectionState<VatId> where VatId: 'static {
     disconnect_fulfiller: RefCell<Option<oneshot::Sender<Promise<(), Error>>>>,
 
     client_downcast_map: RefCell<HashMap<usize, WeakClient<VatId>>>,
+
+    caller: CallerInfo,
+}
+
+impl <VatId> Drop for ConnectionState<VatId> {
+    fn drop(&mut self) {
+        // Usually already done by `disconnect()`.
+        run_disconnect_callbacks(self.caller.connection_id);
+    }
+}
+
+/// Source of `CallerInfo::connection_id`.
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// Callbacks to run when each connection on this thread is disconnected, keyed by
+    /// `CallerInfo::connection_id`. Only connections that are still up have an entry.
+    static DISCONNECT_CALLBACKS: RefCell<HashMap<u64, Vec<Box<dyn FnOnce()>>>> = RefCell::new(HashMap::new());
+}
+
+/// Arranges for `callback` to be run when the connection identified by `connection_id` is
+/// disconnected. Returns false, and drops `callback`, if there is no such connection on this
+/// thread, for example because it is already gone.
+pub(crate) fn on_disconnect(connection_id: u64, callback: Box<dyn FnOnce()>) -> bool {
+    DISCONNECT_CALLBACKS.with(|callbacks| {
+        match callbacks.borrow_mut().get_mut(&connection_id) {
+            Some(list) => { list.push(callback); true }
+            None => false,
+        }
+    })
+}
+
+fn run_disconnect_callbacks(connection_id: u64) {
+    // The table may already be gone if this happens while the thread is exiting.
+    let callbacks = DISCONNECT_CALLBACKS.try_with(|callbacks| callbacks.borrow_mut().remove(&connection_id));
+    for callback in callbacks.ok().and_then(|c| c).unwrap_or_default() {
+        callback();
+    }
 }
 
 impl <VatId> ConnectionState<VatId> {
     pub fn new(
         bootstrap_cap: Box<dyn ClientHook>,
+        call_limits: Rc<Cell<crate::CallLimits>>,
         connection: Box<dyn crate::Connection<VatId>>,
         disconnect_fulfiller: oneshot::Sender<Promise<(), Error>>)
         -> (TaskSet<Error>, Rc<ConnectionState<VatId>>)
     {
+        let caller = CallerInfo {
+            connection_id: NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
+            peer_identity: connection.get_peer_identity(),
+        };
+        DISCONNECT_CALLBACKS.with(|callbacks| callbacks.borrow_mut().insert(caller.connection_id, Vec::new()));
         let state = Rc::new(ConnectionState {
             bootstrap_cap: bootstrap_cap,
+            call_limits: call_limits,
+            outstanding_calls: Rc::new(RefCell::new(OutstandingCalls::default())),
             exports: RefCell::new(ExportTable::new()),
             questions: RefCell::new(ExportTable::new()),
             answers: RefCell::new(ImportTable::new()),
@@ -414,6 +490,7 @@ impl <VatId> ConnectionState<VatId> {
             connection: RefCell::new(Ok(connection)),
             disconnect_fulfiller: RefCell::new(Some(disconnect_fulfiller)),
             client_downcast_map: RefCell::new(HashMap::new()),
+            caller: caller,
         });
         let (mut handle, tasks) = TaskSet::new(Box::new(ConnectionErrorHandler::new(Rc::downgrade(&state))));
 
@@ -422,6 +499,29 @@ impl <VatId> ConnectionState<VatId> {
         (tasks, state)
     }
 
+    // Counts a call on `capability` as outstanding, unless that would exceed the call limits.
+    fn admit_call(&self, capability: &Box<dyn ClientHook>) -> ::capnp::Result<OutstandingCall> {
+        let limits = self.call_limits.get();
+        let ptr = capability.get_ptr();
+        let mut calls = self.outstanding_calls.borrow_mut();
+        if let Some(limit) = limits.per_connection {
+            if calls.total >= limit {
+                return Err(Error::overloaded(
+                    format!("too many calls outstanding on this connection (limit {})", limit)));
+            }
+        }
+        let on_capability = calls.by_capability.get(&ptr).cloned().unwrap_or(0);
+        if let Some(limit) = limits.per_capability {
+            if on_capability >= limit {
+                return Err(Error::overloaded(
+                    format!("too many calls outstanding on this capability (limit {})", limit)));
+            }
+        }
+        calls.total += 1;
+        calls.by_capability.insert(ptr, on_capability + 1);
+        Ok(OutstandingCall { calls: self.outstanding_calls.clone(), capability: ptr })
+    }
+
     fn new_outgoing_message(&self, first_segment_words: u32) -> capnp::Result<Box<dyn crate::OutgoingMessage>> {
         match self.connection.borrow_mut().as_mut() {
             Err(e) => Err(e.clone()),
@@ -531,6 +631,7 @@ impl <VatId> ConnectionState<VatId> {
             }
             Err(_) => unreachable!(),
         }
+        run_disconnect_callbacks(self.caller.connection_id);
     }
 
     // Transform a future into a promise that gets executed even if it is never polled.
@@ -747,7 +848,7 @@ impl <VatId> ConnectionState<VatId> {
                         format!("Received a new call on in-use question id {}", question_id)));
                 }
 
-                let params = Params::new(message, cap_table_array);
+                let params = Params::new(message, cap_table_array, connection_state.caller.clone());
 
                 let answer = Answer::new();
 
@@ -774,10 +875,16 @@ impl <VatId> ConnectionState<VatId> {
                     answer.active = true;
                 }
 
+                // A call over the limits goes to a broken capability, which fails it the usual way.
+                let (capability, outstanding) = match connection_state.admit_call(&capability) {
+                    Ok(outstanding) => (capability, Some(outstanding)),
+                    Err(e) => (broken::new_cap(e), None),
+                };
                 let call_promise = capability.call(interface_id, method_id, Box::new(params), Box::new(results));
                 let (pipeline_sender, mut pipeline) = queued::Pipeline::new();
 
                 let promise = call_promise.then(move |call_result| {
+                    drop(outstanding);
                     results_inner_promise.then(move |result| {
                         future::ready(ResultsDone::from_results_inner(result, call_result, pipeline_sender))
                     })
@@ -1413,6 +1520,8 @@ impl <VatId> ConnectionState<VatId> {
                 Ok(Some(ConnectionState::import(state, sender_promise, true)))
             }
             cap_descriptor::ReceiverHosted(receiver_hosted) => {
+                // One of our own exports coming back. Hand out the underlying local client, so
+                // that calls on it are dispatched directly instead of going over the connection.
                 if let Some(ref mut exp) = state.exports.borrow_mut().find(receiver_hosted) {
                     Ok(Some(exp.client_hook.add_ref()))
                 } else {
@@ -1932,16 +2041,19 @@ impl <VatId> PipelineHook for Pipeline<VatId> {
 pub struct Params {
     request: Box<dyn crate::IncomingMessage>,
     cap_table: Vec<Option<Box<dyn ClientHook>>>,
+    caller: CallerInfo,
 }
 
 impl Params {
     fn new(request: Box<dyn crate::IncomingMessage>,
-           cap_table: Vec<Option<Box<dyn ClientHook>>>)
+           cap_table: Vec<Option<Box<dyn ClientHook>>>,
+           caller: CallerInfo)
            -> Params
     {
         Params {
             request: request,
             cap_table: cap_table,
+            caller: caller,
         }
     }
 }
@@ -1961,6 +2073,10 @@ impl ParamsHook for Params {
             }
         }
     }
+
+    fn caller(&self) -> Option<CallerInfo> {
+        Some(self.caller.clone())
+    }
 }
 
 enum ResultsVariant {
@@ -44,7 +44,7 @@ pub mod message {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -223,10 +223,10 @@ pub mod message {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -573,7 +573,7 @@ pub mod bootstrap {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -631,10 +631,10 @@ pub mod bootstrap {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -721,7 +721,7 @@ pub mod call {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -802,10 +802,10 @@ pub mod call {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -950,7 +950,7 @@ pub mod call {
     }
 
     impl <'a,> Reader<'a,>  {
-      pub fn reborrow(&self) -> Reader<> {
+      pub fn reborrow(&self) -> Reader<'_,> {
         Reader { .. *self }
       }
 
@@ -1022,10 +1022,10 @@ pub mod call {
       pub fn into_reader(self) -> Reader<'a,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
-      pub fn reborrow(&mut self) -> Builder<> {
+      pub fn reborrow(&mut self) -> Builder<'_,> {
         Builder { .. *self }
       }
-      pub fn reborrow_as_reader(&self) -> Reader<> {
+      pub fn reborrow_as_reader(&self) -> Reader<'_,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
 
@@ -1138,7 +1138,7 @@ pub mod return_ {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -1241,10 +1241,10 @@ pub mod return_ {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -1421,7 +1421,7 @@ pub mod finish {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -1476,10 +1476,10 @@ pub mod finish {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -1560,7 +1560,7 @@ pub mod resolve {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -1635,10 +1635,10 @@ pub mod resolve {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -1759,7 +1759,7 @@ pub mod release {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -1814,10 +1814,10 @@ pub mod release {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -1896,7 +1896,7 @@ pub mod disembargo {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -1954,10 +1954,10 @@ pub mod disembargo {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -2052,7 +2052,7 @@ pub mod disembargo {
     }
 
     impl <'a,> Reader<'a,>  {
-      pub fn reborrow(&self) -> Reader<> {
+      pub fn reborrow(&self) -> Reader<'_,> {
         Reader { .. *self }
       }
 
@@ -2125,10 +2125,10 @@ pub mod disembargo {
       pub fn into_reader(self) -> Reader<'a,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
-      pub fn reborrow(&mut self) -> Builder<> {
+      pub fn reborrow(&mut self) -> Builder<'_,> {
         Builder { .. *self }
       }
-      pub fn reborrow_as_reader(&self) -> Reader<> {
+      pub fn reborrow_as_reader(&self) -> Reader<'_,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
 
@@ -2245,7 +2245,7 @@ pub mod provide {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -2310,10 +2310,10 @@ pub mod provide {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -2418,7 +2418,7 @@ pub mod accept {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -2480,10 +2480,10 @@ pub mod accept {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -2578,7 +2578,7 @@ pub mod join {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -2643,10 +2643,10 @@ pub mod join {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -2753,7 +2753,7 @@ pub mod message_target {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -2820,10 +2820,10 @@ pub mod message_target {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -2927,7 +2927,7 @@ pub mod payload {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -2988,10 +2988,10 @@ pub mod payload {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -3087,7 +3087,7 @@ pub mod cap_descriptor {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -3178,10 +3178,10 @@ pub mod cap_descriptor {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -3337,7 +3337,7 @@ pub mod promised_answer {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -3395,10 +3395,10 @@ pub mod promised_answer {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -3485,7 +3485,7 @@ pub mod promised_answer {
     }
 
     impl <'a,> Reader<'a,>  {
-      pub fn reborrow(&self) -> Reader<> {
+      pub fn reborrow(&self) -> Reader<'_,> {
         Reader { .. *self }
       }
 
@@ -3548,10 +3548,10 @@ pub mod promised_answer {
       pub fn into_reader(self) -> Reader<'a,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
-      pub fn reborrow(&mut self) -> Builder<> {
+      pub fn reborrow(&mut self) -> Builder<'_,> {
         Builder { .. *self }
       }
-      pub fn reborrow_as_reader(&self) -> Reader<> {
+      pub fn reborrow_as_reader(&self) -> Reader<'_,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
 
@@ -3646,7 +3646,7 @@ pub mod third_party_cap_descriptor {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -3704,10 +3704,10 @@ pub mod third_party_cap_descriptor {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -3794,7 +3794,7 @@ pub mod exception {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -3860,10 +3860,10 @@ pub mod exception {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
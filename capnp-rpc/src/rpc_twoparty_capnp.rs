@@ -67,7 +67,7 @@ pub mod vat_id {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -118,10 +118,10 @@ pub mod vat_id {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -192,7 +192,7 @@ pub mod provision_id {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -243,10 +243,10 @@ pub mod provision_id {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -317,7 +317,7 @@ pub mod recipient_id {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -364,10 +364,10 @@ pub mod recipient_id {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -430,7 +430,7 @@ pub mod third_party_cap_id {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -477,10 +477,10 @@ pub mod third_party_cap_id {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -543,7 +543,7 @@ pub mod join_key_part {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -602,10 +602,10 @@ pub mod join_key_part {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -692,7 +692,7 @@ pub mod join_result {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -754,10 +754,10 @@ pub mod join_result {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -0,0 +1,127 @@
+// Copyright (c) 2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Sharing capabilities with other threads.
+//!
+//! A `capability::Client` belongs to the thread whose event loop created it: clients are neither
+//! `Send` nor `Sync`, because their hooks are reference counted with `Rc` and their calls run on
+//! that event loop. To use a capability from another thread, call `fork_for_thread()` on the
+//! owning thread. It returns a `ThreadClient`, which is `Send` and can be cloned, and a future that
+//! must be run on the owning thread's event loop for as long as other threads make calls; it
+//! completes once every `ThreadClient` for the capability, and every client obtained from one,
+//! has been dropped.
+//!
+//! Calls made through a `ThreadClient` are passed to the owning thread over a channel. Their
+//! parameters are copied into a message that can be sent between threads, and their results are
+//! copied back, so neither may contain capabilities. If the owning thread stops running the
+//! future, calls fail with a `Disconnected` error.
+
+use futures::channel::{mpsc, oneshot};
+use futures::{Future, FutureExt, StreamExt};
+
+use capnp::{any_pointer, message, Error};
+use capnp::capability::{self, FromClientHook, Params, Promise, Results};
+use capnp::private::capability::ClientHook;
+
+type CallResult = ::capnp::Result<message::Builder<message::HeapAllocator>>;
+
+struct Call {
+    interface_id: u64,
+    method_id: u16,
+    params: message::Builder<message::HeapAllocator>,
+    fulfiller: oneshot::Sender<CallResult>,
+}
+
+/// A handle on a capability owned by another thread. See the module documentation.
+#[derive(Clone)]
+pub struct ThreadClient {
+    sender: mpsc::UnboundedSender<Call>,
+}
+
+impl ThreadClient {
+    /// Returns a client, for use on the current thread, whose calls go to the capability.
+    pub fn client<C>(&self) -> C where C: FromClientHook {
+        let server = ThreadServer { sender: self.sender.clone() };
+        FromClientHook::new(Box::new(crate::local::Client::new(Box::new(server))))
+    }
+}
+
+/// Makes `client` available to other threads. Returns the handle to give to them, and a future
+/// that must be run on the current thread's event loop, which makes the calls that they send.
+pub fn fork_for_thread(client: capability::Client) -> (ThreadClient, impl Future<Output=()>) {
+    let (sender, receiver) = mpsc::unbounded::<Call>();
+    let hook = client.hook;
+    let serve = receiver.for_each_concurrent(None, move |call| {
+        let Call { interface_id, method_id, params, fulfiller } = call;
+        forward(hook.add_ref(), interface_id, method_id, params).map(move |result| {
+            // The caller might have given up on the call.
+            let _ = fulfiller.send(result);
+        })
+    });
+    (ThreadClient { sender: sender }, serve)
+}
+
+fn copy_without_caps(value: any_pointer::Reader) -> CallResult {
+    if value.target_size()?.cap_count > 0 {
+        return Err(Error::failed(
+            "capabilities cannot be passed to or from a capability on another thread".to_string()));
+    }
+    let mut message = message::Builder::new_default();
+    message.set_root(value)?;
+    Ok(message)
+}
+
+async fn forward(hook: Box<dyn ClientHook>, interface_id: u64, method_id: u16,
+                 params: message::Builder<message::HeapAllocator>) -> CallResult {
+    let mut request = hook.new_call(interface_id, method_id, None);
+    request.get().set_as(params.get_root_as_reader::<any_pointer::Reader>()?)?;
+    drop(params);
+    let response = request.send().promise.await?;
+    copy_without_caps(response.get()?)
+}
+
+struct ThreadServer {
+    sender: mpsc::UnboundedSender<Call>,
+}
+
+impl capability::Server for ThreadServer {
+    fn dispatch_call(&mut self, interface_id: u64, method_id: u16,
+                     params: Params<any_pointer::Owned>,
+                     mut results: Results<any_pointer::Owned>)
+                     -> Promise<(), Error>
+    {
+        let params = pry!(copy_without_caps(pry!(params.get())));
+        let (fulfiller, result) = oneshot::channel();
+        let call = Call { interface_id: interface_id, method_id: method_id,
+                          params: params, fulfiller: fulfiller };
+        if let Err(_) = self.sender.unbounded_send(call) {
+            return Promise::err(owner_gone());
+        }
+        Promise::from_future(async move {
+            let message = result.await.map_err(|_| owner_gone())??;
+            results.get().set_as(message.get_root_as_reader::<any_pointer::Reader>()?)
+        })
+    }
+}
+
+fn owner_gone() -> Error {
+    Error::disconnected("the thread that owns the capability stopped serving it".to_string())
+}
@@ -45,6 +45,10 @@ impl crate::IncomingMessage for IncomingMessage {
     fn get_body<'a>(&'a self) -> ::capnp::Result<::capnp::any_pointer::Reader<'a>> {
         self.message.get_root()
     }
+
+    fn get_segments(&self) -> Option<&dyn ::capnp::message::ReaderSegments> {
+        Some(self.message.get_segments())
+    }
 }
 
 struct OutgoingMessage {
@@ -85,6 +89,7 @@ struct ConnectionInner<T> where T: AsyncRead + 'static {
     side: crate::rpc_twoparty_capnp::Side,
     receive_options: ReaderOptions,
     on_disconnect_fulfiller: Option<oneshot::Sender<()>>,
+    peer_identity: Option<String>,
 }
 
 struct Connection<T> where T: AsyncRead + 'static {
@@ -120,6 +125,7 @@ impl <T> Connection<T> where T: AsyncRead {
                     side: side,
                     receive_options: receive_options,
                     on_disconnect_fulfiller: Some(on_disconnect_fulfiller),
+                    peer_identity: None,
                 })),
         }
     }
@@ -132,6 +138,10 @@ impl <T> crate::Connection<crate::rpc_twoparty_capnp::Side> for Connection<T>
         self.inner.borrow().side
     }
 
+    fn get_peer_identity(&self) -> Option<String> {
+        self.inner.borrow().peer_identity.clone()
+    }
+
     fn new_outgoing_message(&mut self, _first_segment_word_size: u32) -> Box<dyn crate::OutgoingMessage> {
         Box::new(OutgoingMessage {
             message: ::capnp::message::Builder::new_default(),
@@ -218,6 +228,15 @@ impl <T> VatNetwork<T> where T: AsyncRead + Unpin {
             side: side,
         }
     }
+
+    /// Records the identity of the peer, as established by whatever set up the streams (for
+    /// example, a TLS handshake). Method handlers on this side can read it from
+    /// `Params::caller()`. Must be called before the network is passed to `RpcSystem::new()`.
+    pub fn set_peer_identity(&mut self, identity: String) {
+        if let Some(inner) = self.weak_connection_inner.upgrade() {
+            inner.borrow_mut().peer_identity = Some(identity);
+        }
+    }
 }
 
 impl <T> crate::VatNetwork<VatId> for VatNetwork<T>
@@ -0,0 +1,165 @@
+// Copyright (c) 2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Runs a two-party RPC connection over WebSocket, or over any other transport that delivers
+//! discrete binary frames.
+//!
+//! Each outgoing RPC message is sent as exactly one frame, in the standard stream framing.
+//! Incoming frames are concatenated, so a peer may also split messages across frames.
+//!
+//! This module does not implement the WebSocket protocol itself. Instead, the caller supplies
+//! two callbacks: one that returns a promise for the next incoming binary frame (or `None` when
+//! the connection is closed), and one that sends a frame.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use capnp::Error;
+use capnp::capability::Promise;
+use capnp::message::ReaderOptions;
+use futures::{AsyncRead, AsyncWrite, Future};
+
+/// Creates a two-party vat network whose messages are carried in frames.
+///
+/// `read_frame` is called whenever the previous frame has been consumed. `write_frame` is
+/// called once per outgoing message, and is not called again until the promise it returned has
+/// resolved.
+pub fn new_vat_network<R, W>(read_frame: R,
+                             write_frame: W,
+                             side: crate::rpc_twoparty_capnp::Side,
+                             receive_options: ReaderOptions)
+                             -> crate::twoparty::VatNetwork<FrameReader<R>>
+    where R: FnMut() -> Promise<Option<Vec<u8>>, Error> + Unpin + 'static,
+          W: FnMut(Vec<u8>) -> Promise<(), Error> + Unpin + 'static,
+{
+    crate::twoparty::VatNetwork::new(FrameReader::new(read_frame),
+                                     FrameWriter::new(write_frame),
+                                     side,
+                                     receive_options)
+}
+
+fn to_io_error(e: Error) -> io::Error {
+    let kind = match e.kind {
+        capnp::ErrorKind::Disconnected => io::ErrorKind::ConnectionAborted,
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, e.description)
+}
+
+/// Presents a sequence of frames as a byte stream.
+pub struct FrameReader<R> where R: FnMut() -> Promise<Option<Vec<u8>>, Error> {
+    read_frame: R,
+    pending: Option<Promise<Option<Vec<u8>>, Error>>,
+    frame: Vec<u8>,
+    pos: usize,
+    eof: bool,
+}
+
+impl <R> FrameReader<R> where R: FnMut() -> Promise<Option<Vec<u8>>, Error> {
+    pub fn new(read_frame: R) -> FrameReader<R> {
+        FrameReader { read_frame: read_frame, pending: None, frame: Vec::new(), pos: 0, eof: false }
+    }
+}
+
+impl <R> AsyncRead for FrameReader<R> where R: FnMut() -> Promise<Option<Vec<u8>>, Error> + Unpin {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        while this.pos == this.frame.len() && !this.eof {
+            let mut pending = match this.pending.take() {
+                Some(p) => p,
+                None => (this.read_frame)(),
+            };
+            match Pin::new(&mut pending).poll(cx) {
+                Poll::Pending => {
+                    this.pending = Some(pending);
+                    return Poll::Pending;
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(to_io_error(e))),
+                Poll::Ready(Ok(None)) => this.eof = true,
+                Poll::Ready(Ok(Some(frame))) => {
+                    this.frame = frame;
+                    this.pos = 0;
+                }
+            }
+        }
+        let n = std::cmp::min(buf.len(), this.frame.len() - this.pos);
+        buf[..n].copy_from_slice(&this.frame[this.pos..this.pos + n]);
+        this.pos += n;
+        Poll::Ready(Ok(n))
+    }
+}
+
+/// Collects the bytes written between flushes into a single frame.
+pub struct FrameWriter<W> where W: FnMut(Vec<u8>) -> Promise<(), Error> {
+    write_frame: W,
+    pending: Option<Promise<(), Error>>,
+    buffer: Vec<u8>,
+}
+
+impl <W> FrameWriter<W> where W: FnMut(Vec<u8>) -> Promise<(), Error> {
+    pub fn new(write_frame: W) -> FrameWriter<W> {
+        FrameWriter { write_frame: write_frame, pending: None, buffer: Vec::new() }
+    }
+
+    fn poll_pending(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        if let Some(ref mut pending) = self.pending {
+            match Pin::new(pending).poll(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(r) => {
+                    self.pending = None;
+                    r.map_err(to_io_error)?;
+                }
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl <W> AsyncWrite for FrameWriter<W> where W: FnMut(Vec<u8>) -> Promise<(), Error> + Unpin {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        match this.poll_pending(cx) {
+            Poll::Ready(Ok(())) => (),
+            r => return r.map_ok(|()| 0),
+        }
+        this.buffer.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.poll_pending(cx) {
+            Poll::Ready(Ok(())) => (),
+            r => return r,
+        }
+        if !this.buffer.is_empty() {
+            let frame = std::mem::replace(&mut this.buffer, Vec::new());
+            this.pending = Some((this.write_frame)(frame));
+            return this.poll_pending(cx);
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
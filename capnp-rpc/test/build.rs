@@ -1,5 +1,8 @@
 extern crate capnpc;
 
 fn main() {
-    ::capnpc::CompilerCommand::new().file("test.capnp").run().unwrap();
+    ::capnpc::CompilerCommand::new().builtin_compiler().file("test.capnp").file("test-base.capnp").run().unwrap();
+
+    // Compiled on its own, as if test-base.capnp belonged to another crate.
+    ::capnpc::CompilerCommand::new().builtin_compiler().file("test-indirect.capnp").run().unwrap();
 }
@@ -19,10 +19,13 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 
-use crate::test_capnp::{bootstrap, test_handle, test_interface, test_extends, test_pipeline,
-                        test_call_order, test_more_stuff};
+use crate::test_capnp::{bootstrap, test_handle, test_interface, test_extends, test_extends2,
+                        test_pipeline, test_call_order, test_more_stuff};
 
 
+use crate::test_base_capnp::test_base;
+use crate::test_indirect_capnp::test_indirect;
+
 use capnp::Error;
 use capnp::capability::Promise;
 
@@ -58,10 +61,13 @@ impl bootstrap::Server for Bootstrap {
 
     fn test_extends2(&mut self,
                     _params: bootstrap::TestExtends2Params,
-                    _results: bootstrap::TestExtends2Results)
+                    mut results: bootstrap::TestExtends2Results)
                     -> Promise<(), Error>
     {
-        unimplemented!()
+        {
+            results.get().set_cap(capnp_rpc::new_client(TestExtends));
+        }
+        Promise::ok(())
     }
 
     fn test_pipeline(&mut self,
@@ -220,6 +226,8 @@ impl test_extends::Server for TestExtends {
     }
 }
 
+impl test_extends2::Server for TestExtends {}
+
 struct TestPipeline;
 
 impl test_pipeline::Server for TestPipeline {
@@ -258,6 +266,16 @@ impl test_pipeline::Server for TestPipeline {
     {
         Promise::ok(())
     }
+
+    fn get_deep_cap(&mut self,
+                    params: test_pipeline::GetDeepCapParams,
+                    mut results: test_pipeline::GetDeepCapResults)
+                    -> Promise<(), Error>
+    {
+        let cap = pry!(pry!(params.get()).get_in_cap());
+        results.get().init_nest().init_boxed().init_box().set_cap(cap);
+        Promise::ok(())
+    }
 }
 
 pub struct TestCallOrder {
@@ -583,3 +601,29 @@ impl test_interface::Server for TestCapDestructor {
     }
 }
 
+
+/// Implements `TestIndirect` and, separately, its base `TestBase`, which the generated code for
+/// `TestIndirect` has no path to.
+pub struct TestIndirect;
+
+impl test_base::Server for TestIndirect {
+    fn ping(&mut self,
+            _params: test_base::PingParams,
+            mut results: test_base::PingResults)
+            -> Promise<(), Error>
+    {
+        results.get().set_n(1);
+        Promise::ok(())
+    }
+}
+
+impl test_indirect::Server for TestIndirect {
+    fn pong(&mut self,
+            _params: test_indirect::PongParams,
+            mut results: test_indirect::PongResults)
+            -> Promise<(), Error>
+    {
+        results.get().set_n(2);
+        Promise::ok(())
+    }
+}
@@ -30,18 +30,27 @@ extern crate futures;
 
 use capnp::Error;
 use capnp::capability::Promise;
-use capnp_rpc::{RpcSystem, rpc_twoparty_capnp, twoparty};
+use capnp_rpc::{RpcSystem, keepalive, rpc_twoparty_capnp, twoparty};
 
 use futures::{Future, FutureExt, TryFutureExt};
 use futures::channel::oneshot;
 
 use futures::{AsyncReadExt};
 
+#[deny(warnings)]
 pub mod test_capnp {
   include!(concat!(env!("OUT_DIR"), "/test_capnp.rs"));
 }
 
 
+pub mod test_base_capnp {
+  include!(concat!(env!("OUT_DIR"), "/test_base_capnp.rs"));
+}
+
+pub mod test_indirect_capnp {
+  include!(concat!(env!("OUT_DIR"), "/test_indirect_capnp.rs"));
+}
+
 pub mod impls;
 pub mod test_util;
 
@@ -159,10 +168,448 @@ fn disconnector_disconnects() {
     });
 }
 
+struct CallerReporter;
+
+impl test_capnp::test_interface::Server for CallerReporter {
+    fn foo(&mut self,
+           params: test_capnp::test_interface::FooParams,
+           mut results: test_capnp::test_interface::FooResults)
+           -> Promise<(), Error>
+    {
+        let x = match params.caller() {
+            Some(caller) => format!("{}#{}", caller.peer_identity.unwrap_or("anonymous".to_string()),
+                                    caller.connection_id),
+            None => "local".to_string(),
+        };
+        results.get().set_x(&x);
+        Promise::ok(())
+    }
+}
+
+// Fails the first `failures` calls of each method with a disconnect.
+struct FlakyServer {
+    failures: u32,
+    calls: std::rc::Rc<std::cell::Cell<u32>>,
+}
+
+impl FlakyServer {
+    fn attempt(&mut self) -> Result<(), Error> {
+        let calls = self.calls.get() + 1;
+        self.calls.set(calls);
+        if calls <= self.failures {
+            Err(Error::disconnected(format!("flaky call #{}", calls)))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl test_capnp::test_interface::Server for FlakyServer {
+    fn foo(&mut self,
+           params: test_capnp::test_interface::FooParams,
+           mut results: test_capnp::test_interface::FooResults)
+           -> Promise<(), Error>
+    {
+        pry!(self.attempt());
+        results.get().set_x(&format!("{}", pry!(params.get()).get_i()));
+        Promise::ok(())
+    }
+
+    fn bar(&mut self,
+           _params: test_capnp::test_interface::BarParams,
+           _results: test_capnp::test_interface::BarResults)
+           -> Promise<(), Error>
+    {
+        pry!(self.attempt());
+        Promise::ok(())
+    }
+}
+
+#[test]
+fn retry_idempotent_methods() {
+    use capnp_rpc::retry;
+
+    assert_eq!(test_capnp::test_interface::IDEMPOTENT_METHODS,
+               &[(test_capnp::test_interface::_private::TYPE_ID, 0)]);
+
+    fn flaky_client(failures: u32) -> (test_capnp::test_interface::Client, std::rc::Rc<std::cell::Cell<u32>>) {
+        let calls = std::rc::Rc::new(std::cell::Cell::new(0));
+        let flaky: test_capnp::test_interface::Client =
+            capnp_rpc::new_client(FlakyServer { failures: failures, calls: calls.clone() });
+        let client = retry::new_client(flaky.client, test_capnp::test_interface::IDEMPOTENT_METHODS,
+                                       retry::MaxAttempts(3));
+        (client, calls)
+    }
+
+    async_std::task::block_on(async move {
+        let (client, calls) = flaky_client(2);
+        let response = client.foo(7, false).promise.await.unwrap();
+        assert_eq!(response.get().unwrap().get_x().unwrap(), "7");
+        assert_eq!(calls.get(), 3);
+
+        let (client, calls) = flaky_client(3);
+        let error = client.foo(7, false).promise.await.err().expect("should give up");
+        assert_eq!(error.kind, capnp::ErrorKind::Disconnected);
+        assert_eq!(calls.get(), 3);
+
+        // bar() is not idempotent, so its failure is final.
+        let (client, calls) = flaky_client(1);
+        assert!(client.bar_request().send().promise.await.is_err());
+        assert_eq!(calls.get(), 1);
+    });
+}
+
+#[test]
+fn dynamic_calls() {
+    use capnpc::dynamic::{self, Value};
+
+    let request = capnpc::compiler::compile(&["test.capnp"], &[], &[]).unwrap();
+    let mut loader = capnpc::schema_loader::SchemaLoader::new();
+    loader.load_request(request.into_reader()).unwrap();
+
+    let typed: test_capnp::test_interface::Client =
+        capnp_rpc::new_client(FlakyServer { failures: 0, calls: Default::default() });
+    let cap = dynamic::Capability::new(&loader, loader.find("TestInterface").unwrap(), typed.client).unwrap();
+    assert!(cap.new_request("nonexistent").is_err());
+
+    // Inherited methods are found through the superclass.
+    let other: test_capnp::test_interface::Client =
+        capnp_rpc::new_client(FlakyServer { failures: 0, calls: Default::default() });
+    let extends = dynamic::Capability::new(&loader, loader.find("TestExtends").unwrap(), other.client).unwrap();
+    let (interface_id, method_id, _) = extends.find_method("foo").unwrap();
+    assert_eq!((interface_id, method_id), (test_capnp::test_interface::_private::TYPE_ID, 0));
+
+    async_std::task::block_on(async {
+        let mut request = cap.new_request("foo").unwrap();
+        request.get().unwrap().set("i", Value::Uint32(123)).unwrap();
+        let response = request.send().await.unwrap();
+        match response.get().unwrap().get("x").unwrap() {
+            Value::Text(x) => assert_eq!(x, "123"),
+            _ => panic!("expected text"),
+        }
+    });
+}
+
+#[test]
+fn json_gateway() {
+    let request = capnpc::compiler::compile(&["test.capnp"], &[], &[]).unwrap();
+    let mut loader = capnpc::schema_loader::SchemaLoader::new();
+    loader.load_request(request.into_reader()).unwrap();
+
+    let mut gateway = capnpc::gateway::Gateway::new(&loader);
+    let working: test_capnp::test_interface::Client =
+        capnp_rpc::new_client(FlakyServer { failures: 0, calls: Default::default() });
+    gateway.add_service("working", "TestInterface", working.client).unwrap();
+    let broken: test_capnp::test_interface::Client =
+        capnp_rpc::new_client(FlakyServer { failures: 1, calls: Default::default() });
+    gateway.add_service("broken", "TestInterface", broken.client).unwrap();
+
+    async_std::task::block_on(async {
+        let response = gateway.handle("POST", "/working/foo", r#"{"i": 42, "j": true}"#).await;
+        assert_eq!((response.status, &response.body[..]), (200, r#"{"x":"42"}"#));
+
+        let response = gateway.handle("POST", "/working/bar", "").await;
+        assert_eq!((response.status, &response.body[..]), (200, "{}"));
+
+        assert_eq!(gateway.handle("GET", "/working/foo", "").await.status, 405);
+        assert_eq!(gateway.handle("POST", "/missing/foo", "").await.status, 404);
+        assert_eq!(gateway.handle("POST", "/working/missing", "").await.status, 404);
+        assert_eq!(gateway.handle("POST", "/working/foo", r#"{"i": "#).await.status, 400);
+
+        let response = gateway.handle("POST", "/broken/bar", "").await;
+        assert_eq!((response.status, &response.body[..]),
+                   (503, r#"{"error":"flaky call #1","kind":"disconnected"}"#));
+    });
+}
+
+// Records what it sees, and tags the parameters and results of foo() calls.
+struct Tagger {
+    name: &'static str,
+    log: std::rc::Rc<std::cell::RefCell<Vec<String>>>,
+}
+
+impl capnp_rpc::intercept::Interceptor for Tagger {
+    fn on_request(&self, call: &capnp_rpc::intercept::CallInfo, params: capnp::any_pointer::Builder)
+                  -> capnp::Result<()>
+    {
+        self.log.borrow_mut().push(format!("{} request {}", self.name, call.method_id));
+        if call.method_id == 0 {
+            let mut params: test_capnp::test_interface::foo_params::Builder = params.get_as()?;
+            let i = params.reborrow().get_i();
+            params.set_i(i * 10);
+        }
+        Ok(())
+    }
+
+    fn on_response(&self, call: &capnp_rpc::intercept::CallInfo, results: capnp::any_pointer::Builder)
+                   -> capnp::Result<()>
+    {
+        self.log.borrow_mut().push(format!("{} response {}", self.name, call.method_id));
+        if call.method_id == 0 {
+            let mut results: test_capnp::test_interface::foo_results::Builder = results.get_as()?;
+            let x = format!("{}{}", &*results.reborrow().get_x()?, self.name);
+            results.set_x(&x);
+        }
+        Ok(())
+    }
+
+    fn on_error(&self, call: &capnp_rpc::intercept::CallInfo, error: Error) -> Error {
+        self.log.borrow_mut().push(format!("{} error {}", self.name, call.method_id));
+        error
+    }
+}
+
+#[test]
+fn intercepted_calls() {
+    let log = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    let flaky: test_capnp::test_interface::Client =
+        capnp_rpc::new_client(FlakyServer { failures: 1, calls: Default::default() });
+    let client: test_capnp::test_interface::Client = capnp_rpc::intercept::new_client(
+        flaky.client,
+        vec![Box::new(Tagger { name: "a", log: log.clone() }),
+             Box::new(Tagger { name: "b", log: log.clone() })]);
+
+    async_std::task::block_on(async move {
+        assert!(client.bar_request().send().promise.await.is_err());
+        assert_eq!(*log.borrow(), ["a request 1", "b request 1", "b error 1", "a error 1"]);
+        log.borrow_mut().clear();
+
+        let response = client.foo(2, false).promise.await.unwrap();
+        assert_eq!(response.get().unwrap().get_x().unwrap(), "200ba");
+        assert_eq!(*log.borrow(), ["a request 0", "b request 0", "b response 0", "a response 0"]);
+    });
+}
+
+#[test]
+fn caller_info() {
+    let (client_stream, server_stream) = async_std::os::unix::net::UnixStream::pair().expect("socket pair");
+    let (client_reader, client_writer) = client_stream.split();
+    let client_network =
+        Box::new(twoparty::VatNetwork::new(client_reader, client_writer,
+                                           rpc_twoparty_capnp::Side::Client,
+                                           Default::default()));
+    let mut client_rpc_system = RpcSystem::new(client_network, None);
+
+    let (server_reader, server_writer) = server_stream.split();
+    let mut server_network =
+        Box::new(twoparty::VatNetwork::new(server_reader, server_writer,
+                                           rpc_twoparty_capnp::Side::Server,
+                                           Default::default()));
+    server_network.set_peer_identity("alice".to_string());
+    let bootstrap: test_capnp::test_interface::Client = capnp_rpc::new_client(CallerReporter);
+    let server_rpc_system = RpcSystem::new(server_network, Some(bootstrap.client));
+
+    let client: test_capnp::test_interface::Client =
+        client_rpc_system.bootstrap(rpc_twoparty_capnp::Side::Server);
+    let disconnector = client_rpc_system.get_disconnector();
+
+    async_std::task::block_on(async move {
+        spawn(client_rpc_system);
+        spawn(server_rpc_system);
+
+        let first = client.foo(0, false).promise.await.unwrap();
+        let first = first.get().unwrap().get_x().unwrap();
+        assert!(first.starts_with("alice#"), "{}", first);
+
+        // Calls on the same connection report the same connection id.
+        let second = client.foo(0, false).promise.await.unwrap();
+        assert_eq!(second.get().unwrap().get_x().unwrap(), first);
+
+        let local: test_capnp::test_interface::Client = capnp_rpc::new_client(CallerReporter);
+        let response = local.foo(0, false).promise.await.unwrap();
+        assert_eq!(response.get().unwrap().get_x().unwrap(), "local");
+
+        disconnector.await.unwrap();
+    });
+}
+
+// The client presents a token; the server checks it and answers with a greeting.
+struct TokenHook {
+    token: &'static str,
+    is_server: bool,
+}
+
+type TwoPartyConnection = Box<dyn capnp_rpc::Connection<rpc_twoparty_capnp::Side>>;
+
+fn send_text(connection: &mut TwoPartyConnection, text: &str) -> Promise<(), Error> {
+    let mut message = connection.new_outgoing_message(8);
+    pry!(pry!(message.get_body()).set_as(text));
+    Promise::from_future(message.send().0.map_ok(|_| ()))
+}
+
+async fn receive_text(connection: &mut TwoPartyConnection) -> Result<String, Error> {
+    match connection.receive_incoming_message().await? {
+        Some(message) => Ok(message.get_body()?.get_as::<capnp::text::Reader>()?.to_string()),
+        None => Err(Error::disconnected("connection closed during handshake".to_string())),
+    }
+}
+
+impl capnp_rpc::handshake::ConnectionHook<rpc_twoparty_capnp::Side> for TokenHook {
+    fn handshake(&mut self, mut connection: TwoPartyConnection)
+                 -> Promise<(TwoPartyConnection, Option<String>), Error>
+    {
+        let token = self.token;
+        if self.is_server {
+            Promise::from_future(async move {
+                let presented = receive_text(&mut connection).await?;
+                if presented != token {
+                    return Err(Error::failed(format!("bad token: {}", presented)));
+                }
+                send_text(&mut connection, "welcome").await?;
+                Ok((connection, Some(format!("holder of {}", token))))
+            })
+        } else {
+            Promise::from_future(async move {
+                send_text(&mut connection, token).await?;
+                let greeting = receive_text(&mut connection).await?;
+                assert_eq!(greeting, "welcome");
+                Ok((connection, None))
+            })
+        }
+    }
+}
+
+fn handshake_setup(client_token: &'static str)
+                   -> (RpcSystem<rpc_twoparty_capnp::Side>, RpcSystem<rpc_twoparty_capnp::Side>)
+{
+    let (client_stream, server_stream) = async_std::os::unix::net::UnixStream::pair().expect("socket pair");
+    let (client_reader, client_writer) = client_stream.split();
+    let client_network =
+        Box::new(twoparty::VatNetwork::new(client_reader, client_writer,
+                                           rpc_twoparty_capnp::Side::Client,
+                                           Default::default()));
+    let mut client_rpc_system = RpcSystem::new(client_network, None);
+    client_rpc_system.set_connection_hook(Box::new(TokenHook { token: client_token, is_server: false }));
+
+    let (server_reader, server_writer) = server_stream.split();
+    let server_network =
+        Box::new(twoparty::VatNetwork::new(server_reader, server_writer,
+                                           rpc_twoparty_capnp::Side::Server,
+                                           Default::default()));
+    let bootstrap: test_capnp::test_interface::Client = capnp_rpc::new_client(CallerReporter);
+    let mut server_rpc_system = RpcSystem::new(server_network, Some(bootstrap.client));
+    server_rpc_system.set_connection_hook(Box::new(TokenHook { token: "open sesame", is_server: true }));
+
+    (client_rpc_system, server_rpc_system)
+}
+
+#[test]
+fn handshake_accepts_good_token() {
+    let (mut client_rpc_system, server_rpc_system) = handshake_setup("open sesame");
+    let client: test_capnp::test_interface::Client =
+        client_rpc_system.bootstrap(rpc_twoparty_capnp::Side::Server);
+    let disconnector = client_rpc_system.get_disconnector();
+
+    async_std::task::block_on(async move {
+        spawn(client_rpc_system);
+        spawn(server_rpc_system);
+
+        let response = client.foo(0, false).promise.await.unwrap();
+        let x = response.get().unwrap().get_x().unwrap();
+        assert!(x.starts_with("holder of open sesame#"), "{}", x);
+
+        disconnector.await.unwrap();
+    });
+}
+
+#[test]
+fn handshake_rejects_bad_token() {
+    let (mut client_rpc_system, server_rpc_system) = handshake_setup("let me in");
+    let client: test_capnp::test_interface::Client =
+        client_rpc_system.bootstrap(rpc_twoparty_capnp::Side::Server);
+
+    async_std::task::block_on(async move {
+        let (tx, rx) = oneshot::channel();
+        async_std::task::spawn_local(client_rpc_system.map(|_| ()));
+        async_std::task::spawn_local(server_rpc_system.map(move |r| { let _ = tx.send(r); }));
+
+        // The server's RpcSystem finishes with the handshake's error...
+        let server_result = rx.await.unwrap();
+        assert!(server_result.unwrap_err().description.contains("bad token"));
+
+        // ... and the client never gets to make a call.
+        assert!(client.foo(0, false).promise.await.is_err());
+    });
+}
+
+fn keepalive_network<T>(stream: T, side: rpc_twoparty_capnp::Side, options: keepalive::Options)
+                        -> Box<dyn capnp_rpc::VatNetwork<rpc_twoparty_capnp::Side>>
+    where T: futures::AsyncRead + futures::AsyncWrite + Unpin + 'static
+{
+    let (reader, writer) = stream.split();
+    let network = Box::new(twoparty::VatNetwork::new(reader, writer, side, Default::default()));
+    Box::new(keepalive::KeepaliveVatNetwork::new(network, options, |duration| {
+        Promise::from_future(async_std::task::sleep(duration).map(Ok))
+    }))
+}
+
+#[test]
+fn keepalive_pings_keep_quiet_connection_open() {
+    let options = keepalive::Options {
+        idle_timeout: std::time::Duration::from_millis(100),
+        ping_interval: Some(std::time::Duration::from_millis(20)),
+    };
+    let (client_stream, server_stream) = async_std::os::unix::net::UnixStream::pair().expect("socket pair");
+    let mut client_rpc_system =
+        RpcSystem::new(keepalive_network(client_stream, rpc_twoparty_capnp::Side::Client, options), None);
+    let bootstrap: test_capnp::test_interface::Client = capnp_rpc::new_client(CallerReporter);
+    let server_rpc_system =
+        RpcSystem::new(keepalive_network(server_stream, rpc_twoparty_capnp::Side::Server, options),
+                       Some(bootstrap.client));
+
+    let client: test_capnp::test_interface::Client =
+        client_rpc_system.bootstrap(rpc_twoparty_capnp::Side::Server);
+    let disconnector = client_rpc_system.get_disconnector();
+
+    async_std::task::block_on(async move {
+        spawn(client_rpc_system);
+        spawn(server_rpc_system);
+
+        client.foo(0, false).promise.await.unwrap();
+
+        // Several idle timeouts pass without any calls.
+        async_std::task::sleep(std::time::Duration::from_millis(300)).await;
+        client.foo(0, false).promise.await.unwrap();
+
+        disconnector.await.unwrap();
+    });
+}
+
+#[test]
+fn keepalive_idle_timeout_fails_outstanding_calls() {
+    let options = keepalive::Options {
+        idle_timeout: std::time::Duration::from_millis(50),
+        ping_interval: None,
+    };
+    // Nobody ever reads from or writes to the other end.
+    let (client_stream, _silent_stream) = async_std::os::unix::net::UnixStream::pair().expect("socket pair");
+    let mut client_rpc_system =
+        RpcSystem::new(keepalive_network(client_stream, rpc_twoparty_capnp::Side::Client, options), None);
+    let client: test_capnp::test_interface::Client =
+        client_rpc_system.bootstrap(rpc_twoparty_capnp::Side::Server);
+
+    async_std::task::block_on(async move {
+        async_std::task::spawn_local(client_rpc_system.map(|_| ()));
+        let error = match client.foo(0, false).promise.await {
+            Ok(_) => panic!("expected the call to fail"),
+            Err(e) => e,
+        };
+        assert_eq!(error.kind, capnp::ErrorKind::Disconnected);
+    });
+}
+
 fn rpc_top_level<F, G>(main: F)
     where F: FnOnce(test_capnp::bootstrap::Client) -> G,
           F: Send + 'static,
           G: Future<Output=Result<(), Error>> + 'static
+{
+    rpc_top_level_with_limits(Default::default(), main)
+}
+
+fn rpc_top_level_with_limits<F, G>(limits: capnp_rpc::CallLimits, main: F)
+    where F: FnOnce(test_capnp::bootstrap::Client) -> G,
+          F: Send + 'static,
+          G: Future<Output=Result<(), Error>> + 'static
 {
     let (client_stream, server_stream) = async_std::os::unix::net::UnixStream::pair().expect("socket pair");
 
@@ -175,7 +622,8 @@ fn rpc_top_level<F, G>(main: F)
                                                Default::default()));
 
         let bootstrap: test_capnp::bootstrap::Client = capnp_rpc::new_client(impls::Bootstrap);
-        let rpc_system = RpcSystem::new(network, Some(bootstrap.client));
+        let mut rpc_system = RpcSystem::new(network, Some(bootstrap.client));
+        rpc_system.set_call_limits(limits);
         async_std::task::block_on(rpc_system).unwrap();
     });
 
@@ -251,6 +699,44 @@ fn basic_rpc_calls() {
     });
 }
 
+#[test]
+fn inline_params_calls() {
+    rpc_top_level(|client| async move {
+        let response = client.test_interface_request().send().promise.await?;
+        let client = response.get()?.get_cap()?;
+
+        let response = client.foo(123, true).promise.await?;
+        assert_eq!(response.get()?.get_x()?, "foo");
+
+        // The server rejects any other arguments.
+        assert!(client.foo(124, true).promise.await.is_err());
+
+        // bar() has no parameters, and is not implemented by the server.
+        assert!(client.bar().promise.await.is_err());
+        Ok(())
+    });
+}
+
+#[test]
+fn params_from_tuples() {
+    use capnp::traits::SetFromTuple;
+    use crate::test_capnp::test_tail_callee;
+
+    fn sets_from<B: SetFromTuple<T>, T>() {}
+    sets_from::<test_tail_callee::foo_params::Builder, (i32, &str)>();
+
+    rpc_top_level(|client| async move {
+        let response = client.test_interface_request().send().promise.await?;
+        let client = response.get()?.get_cap()?;
+
+        let mut request = client.foo_request();
+        request.get().set_from_tuple((123, true));
+        let response = request.send().promise.await?;
+        assert_eq!(response.get()?.get_x()?, "foo");
+        Ok(())
+    });
+}
+
 #[test]
 fn basic_pipelining() {
     rpc_top_level(|client| async move {
@@ -295,6 +781,33 @@ fn basic_pipelining() {
     });
 }
 
+#[test]
+fn deep_pipelining() {
+    rpc_top_level(|client| async move {
+        let response = client.test_pipeline_request().send().promise.await?;
+        let client = response.get()?.get_cap()?;
+
+        let server = impls::TestInterface::new();
+        let call_count = server.get_call_count();
+        let mut request = client.get_deep_cap_request();
+        request.get().set_in_cap(capnp_rpc::new_client(server));
+        let promise = request.send();
+
+        // Three pointer hops, one of them through a group that is a union member.
+        let mut pipeline_request =
+            promise.pipeline.get_nest().get_boxed().get_box().get_cap().foo_request();
+        pipeline_request.get().set_i(123);
+        pipeline_request.get().set_j(true);
+        let pipeline_promise = pipeline_request.send();
+        drop(promise);
+
+        let response = pipeline_promise.promise.await?;
+        assert_eq!(response.get()?.get_x()?, "foo");
+        assert_eq!(call_count.get(), 1);
+        Ok(())
+    });
+}
+
 #[test]
 fn pipelining_return_null() {
     rpc_top_level(|client| async move {
@@ -369,6 +882,44 @@ fn release_simple() {
     });
 }
 
+#[test]
+fn call_limits() {
+    fn never_return(client: &test_capnp::test_more_stuff::Client) -> Promise<(), Error> {
+        let mut request = client.never_return_request();
+        request.get().set_cap(capnp_rpc::new_client(impls::TestInterface::new()));
+        Promise::from_future(request.send().promise.map_ok(|_| ()))
+    }
+
+    fn expect_overloaded<T>(result: Result<T, Error>) -> Result<(), Error> {
+        match result {
+            Err(ref e) if e.kind == ::capnp::ErrorKind::Overloaded => Ok(()),
+            Err(e) => Err(Error::failed(format!("expected an overloaded error, got {}", e))),
+            Ok(_) => Err(Error::failed("expected the call to fail".to_string())),
+        }
+    }
+
+    let limits = capnp_rpc::CallLimits { per_connection: Some(3), per_capability: Some(1) };
+    rpc_top_level_with_limits(limits, |client| async move {
+        let more_stuff = client.test_more_stuff_request().send().promise.await?.get()?.get_cap()?;
+        let _pending1 = never_return(&more_stuff);
+
+        // One call on `more_stuff` is outstanding already.
+        let call_order = test_capnp::test_call_order::Client {
+            client: ::capnp::capability::Client::new(more_stuff.client.hook.add_ref())
+        };
+        expect_overloaded(call_order.get_call_sequence_request().send().promise.await)?;
+
+        let more_stuff2 = client.test_more_stuff_request().send().promise.await?.get()?.get_cap()?;
+        let _pending2 = never_return(&more_stuff2);
+        let more_stuff3 = client.test_more_stuff_request().send().promise.await?.get()?.get_cap()?;
+        let _pending3 = never_return(&more_stuff3);
+
+        // Three calls are outstanding on the connection.
+        expect_overloaded(client.test_more_stuff_request().send().promise.await)?;
+        Ok(())
+    });
+}
+
 /*
 #[test]
 fn release_on_cancel() {
@@ -849,3 +1400,612 @@ fn capability_list() {
     })
 }
 
+
+type FrameSender = futures::channel::mpsc::UnboundedSender<Vec<u8>>;
+type FrameReceiver = futures::channel::mpsc::UnboundedReceiver<Vec<u8>>;
+
+fn websocket_network(tx: FrameSender, rx: FrameReceiver, side: rpc_twoparty_capnp::Side)
+                     -> Box<dyn capnp_rpc::VatNetwork<rpc_twoparty_capnp::Side>>
+{
+    use futures::StreamExt;
+
+    let rx = ::std::rc::Rc::new(::std::cell::RefCell::new(rx));
+    let read_frame = move || {
+        let rx = rx.clone();
+        Promise::from_future(async move { Ok(rx.borrow_mut().next().await) })
+    };
+    let write_frame = move |frame| {
+        Promise::from_future(futures::future::ready(
+            tx.unbounded_send(frame).map_err(|_| Error::disconnected("peer went away".to_string()))))
+    };
+    Box::new(capnp_rpc::websocket::new_vat_network(read_frame, write_frame, side, Default::default()))
+}
+
+#[test]
+fn websocket_rpc_calls() {
+    let (client_tx, server_rx) = futures::channel::mpsc::unbounded();
+    let (server_tx, client_rx) = futures::channel::mpsc::unbounded();
+
+    let join_handle = ::std::thread::spawn(move || {
+        let network = websocket_network(server_tx, server_rx, rpc_twoparty_capnp::Side::Server);
+        let bootstrap: test_capnp::bootstrap::Client = capnp_rpc::new_client(impls::Bootstrap);
+        let rpc_system = RpcSystem::new(network, Some(bootstrap.client));
+        async_std::task::block_on(rpc_system).unwrap();
+    });
+
+    let network = websocket_network(client_tx, client_rx, rpc_twoparty_capnp::Side::Client);
+    let mut rpc_system = RpcSystem::new(network, None);
+    let client: test_capnp::bootstrap::Client = rpc_system.bootstrap(rpc_twoparty_capnp::Side::Server);
+
+    async_std::task::block_on(async move {
+        let disconnector = rpc_system.get_disconnector();
+        spawn(rpc_system);
+
+        let response = client.test_interface_request().send().promise.await?;
+        let client = response.get()?.get_cap()?;
+        let mut request = client.foo_request();
+        request.get().set_i(123);
+        request.get().set_j(true);
+        let response = request.send().promise.await?;
+        assert_eq!(response.get()?.get_x()?, "foo");
+
+        disconnector.await
+    }).unwrap();
+    join_handle.join().expect("thread exited unsuccessfully");
+}
+
+#[derive(Clone)]
+struct SharedLog(::std::rc::Rc<::std::cell::RefCell<Vec<u8>>>);
+
+impl ::std::io::Write for SharedLog {
+    fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> ::std::io::Result<()> { Ok(()) }
+}
+
+#[test]
+fn record_and_replay() {
+    let (client_tx, server_rx) = futures::channel::mpsc::unbounded();
+    let (server_tx, client_rx) = futures::channel::mpsc::unbounded();
+
+    let join_handle = ::std::thread::spawn(move || {
+        let log = SharedLog(Default::default());
+        let network = capnp_rpc::record::RecordingVatNetwork::new(
+            websocket_network(server_tx, server_rx, rpc_twoparty_capnp::Side::Server),
+            log.clone());
+        let bootstrap: test_capnp::bootstrap::Client = capnp_rpc::new_client(impls::Bootstrap);
+        let rpc_system = RpcSystem::new(Box::new(network), Some(bootstrap.client));
+        async_std::task::block_on(rpc_system).unwrap();
+        let bytes = log.0.borrow().clone();
+        bytes
+    });
+
+    let network = websocket_network(client_tx, client_rx, rpc_twoparty_capnp::Side::Client);
+    let mut rpc_system = RpcSystem::new(network, None);
+    let client: test_capnp::bootstrap::Client = rpc_system.bootstrap(rpc_twoparty_capnp::Side::Server);
+
+    async_std::task::block_on(async move {
+        let disconnector = rpc_system.get_disconnector();
+        spawn(rpc_system);
+
+        let response = client.test_interface_request().send().promise.await?;
+        let client = response.get()?.get_cap()?;
+        let mut request = client.foo_request();
+        request.get().set_i(123);
+        request.get().set_j(true);
+        let response = request.send().promise.await?;
+        assert_eq!(response.get()?.get_x()?, "foo");
+
+        disconnector.await
+    }).unwrap();
+    let log = join_handle.join().expect("thread exited unsuccessfully");
+
+    // Replaying the session into a fresh server should produce the same responses.
+    let mut network = capnp_rpc::record::ReplayVatNetwork::new(
+        &log[..], rpc_twoparty_capnp::Side::Server, Default::default()).unwrap();
+    let recorded = network.take_recorded_outgoing();
+    let sent = network.sent_messages();
+    let bootstrap: test_capnp::bootstrap::Client = capnp_rpc::new_client(impls::Bootstrap);
+    let rpc_system = RpcSystem::new(Box::new(network), Some(bootstrap.client));
+    async_std::task::block_on(rpc_system).unwrap();
+
+    let sent = sent.borrow();
+    assert!(!recorded.is_empty());
+    assert!(sent.len() >= recorded.len());
+    for (expected, actual) in recorded.iter().zip(sent.iter()) {
+        assert_eq!(capnp::serialize::write_message_segments_to_words(expected.get_segments()),
+                   capnp::serialize::write_message_to_words(&**actual));
+    }
+}
+
+#[test]
+fn local_cap_loopback() {
+    let (client_tx, server_rx) = futures::channel::mpsc::unbounded();
+    let (server_tx, client_rx) = futures::channel::mpsc::unbounded();
+
+    let join_handle = ::std::thread::spawn(move || {
+        let network = websocket_network(server_tx, server_rx, rpc_twoparty_capnp::Side::Server);
+        let bootstrap: test_capnp::bootstrap::Client = capnp_rpc::new_client(impls::Bootstrap);
+        let rpc_system = RpcSystem::new(network, Some(bootstrap.client));
+        async_std::task::block_on(rpc_system).unwrap();
+    });
+
+    let log = SharedLog(Default::default());
+    let network = capnp_rpc::record::RecordingVatNetwork::new(
+        websocket_network(client_tx, client_rx, rpc_twoparty_capnp::Side::Client),
+        log.clone());
+    let mut rpc_system = RpcSystem::new(Box::new(network), None);
+    let client: test_capnp::bootstrap::Client = rpc_system.bootstrap(rpc_twoparty_capnp::Side::Server);
+
+    async_std::task::block_on(async move {
+        let disconnector = rpc_system.get_disconnector();
+        spawn(rpc_system);
+
+        let response = client.test_more_stuff_request().send().promise.await?;
+        let client = response.get()?.get_cap()?;
+
+        let local: test_capnp::test_call_order::Client = capnp_rpc::new_client(impls::TestCallOrder::new());
+        let mut echo_request = client.echo_request();
+        echo_request.get().set_cap(local.clone());
+        let response = echo_request.send().promise.await?;
+        let echoed = response.get()?.get_cap()?;
+
+        // The peer handed back our own capability, so we get the local object itself...
+        assert_eq!(echoed.client.hook.get_ptr(), local.client.hook.get_ptr());
+
+        // ... and calling it doesn't touch the connection.
+        let logged = log.0.borrow().len();
+        for i in 0..3 {
+            let response = get_call_sequence(&echoed, i).promise.await?;
+            assert_eq!(response.get()?.get_n(), i);
+        }
+        assert_eq!(log.0.borrow().len(), logged);
+
+        disconnector.await
+    }).unwrap();
+    join_handle.join().expect("thread exited unsuccessfully");
+}
+
+struct TextSturdyRefs {
+    caps: ::std::collections::HashMap<String, capnp::capability::Client>,
+}
+
+impl capnp_rpc::persistent::SturdyRefIssuer<capnp::text::Owned, capnp::any_pointer::Owned> for TextSturdyRefs {
+    fn issue(&mut self,
+             cap: capnp::capability::Client,
+             _params: capnp::capability::Params<
+                     capnp_rpc::persistent::persistent::save_params::Owned<capnp::text::Owned, capnp::any_pointer::Owned>>,
+             mut results: capnp::capability::Results<
+                     capnp_rpc::persistent::persistent::save_results::Owned<capnp::text::Owned, capnp::any_pointer::Owned>>)
+             -> Promise<(), Error>
+    {
+        let token = format!("ref{}", self.caps.len());
+        pry!(results.get().set_sturdy_ref(&token[..]));
+        self.caps.insert(token, cap);
+        Promise::ok(())
+    }
+}
+
+struct TextRestorer(::std::rc::Rc<::std::cell::RefCell<TextSturdyRefs>>);
+
+impl capnp_rpc::persistent::Restorer<capnp::text::Owned> for TextRestorer {
+    fn restore<'a>(&mut self, sturdy_ref: capnp::text::Reader<'a>) -> Promise<capnp::capability::Client, Error> {
+        match self.0.borrow().caps.get(sturdy_ref) {
+            Some(cap) => Promise::ok(capnp::capability::Client::new(cap.hook.add_ref())),
+            None => Promise::err(Error::failed(format!("unknown sturdy ref: {}", sturdy_ref))),
+        }
+    }
+}
+
+#[test]
+fn persistent_save_and_restore() {
+    let sturdy_refs = ::std::rc::Rc::new(::std::cell::RefCell::new(TextSturdyRefs { caps: Default::default() }));
+    let server = crate::impls::TestInterface::new();
+    let call_count = server.get_call_count();
+    let inner: crate::test_capnp::test_interface::Client = capnp_rpc::new_client(server);
+    let client: crate::test_capnp::test_interface::Client =
+        capnp_rpc::persistent::new_client::<_, capnp::text::Owned, capnp::any_pointer::Owned, _>(
+            inner.client, sturdy_refs.clone());
+
+    async_std::task::block_on(async move {
+        let response = capnp_rpc::persistent::save::<capnp::text::Owned, capnp::any_pointer::Owned>(&client.client).await?;
+        let sturdy_ref = response.get()?.get_sturdy_ref()?.to_string();
+
+        let mut restorer = TextRestorer(sturdy_refs);
+        let restored: crate::test_capnp::test_interface::Client =
+            capnp_rpc::persistent::restore(&mut restorer, &sturdy_ref[..]);
+        let mut request = restored.foo_request();
+        request.get().set_i(123);
+        request.get().set_j(true);
+        let response = request.send().promise.await?;
+        assert_eq!(response.get()?.get_x()?, "foo");
+        assert_eq!(call_count.get(), 1);
+
+        let unknown: crate::test_capnp::test_interface::Client =
+            capnp_rpc::persistent::restore(&mut restorer, "no such ref");
+        let mut request = unknown.foo_request();
+        request.get().set_i(123);
+        request.get().set_j(true);
+        assert!(request.send().promise.await.is_err());
+        Ok::<(), Error>(())
+    }).unwrap();
+}
+
+#[test]
+fn reconnecting_client() {
+    let connections = ::std::rc::Rc::new(::std::cell::RefCell::new(Vec::new()));
+    let connections1 = connections.clone();
+    let client: test_capnp::bootstrap::Client = capnp_rpc::reconnect::new_client(move || {
+        let (mut client_rpc_system, server_rpc_system) = disconnector_setup();
+        let bootstrap: test_capnp::bootstrap::Client =
+            client_rpc_system.bootstrap(rpc_twoparty_capnp::Side::Server);
+        connections1.borrow_mut().push(client_rpc_system.get_disconnector());
+        async_std::task::spawn_local(client_rpc_system.map(|_| ()));
+        async_std::task::spawn_local(server_rpc_system.map(|_| ()));
+        Promise::ok(bootstrap.client)
+    });
+
+    async_std::task::block_on(async move {
+        client.test_interface_request().send().promise.await?;
+        assert_eq!(connections.borrow().len(), 1);
+
+        let disconnector = connections.borrow_mut().pop().unwrap();
+        disconnector.await?;
+
+        // The first call after the connection drops fails...
+        match client.test_interface_request().send().promise.await {
+            Err(ref e) if e.kind == ::capnp::ErrorKind::Disconnected => (),
+            _ => panic!("Should have gotten a 'disconnected' error."),
+        }
+
+        // ...and the next one reconnects.
+        client.test_interface_request().send().promise.await?;
+        assert_eq!(connections.borrow().len(), 1);
+        Ok::<(), Error>(())
+    }).unwrap();
+}
+
+#[test]
+fn broken_cap() {
+    let client: test_capnp::test_interface::Client = ::capnp::capability::FromClientHook::new(
+        ::capnp::capability::broken_cap(Error::failed("not available".to_string())).hook);
+
+    async_std::task::block_on(async move {
+        match client.client.when_resolved().await {
+            Err(e) => assert_eq!(e.description, "not available"),
+            Ok(()) => panic!("expected when_resolved() to fail"),
+        }
+
+        let mut request = client.foo_request();
+        request.get().set_i(123);
+        request.get().set_j(true);
+        match request.send().promise.await {
+            Err(e) => assert_eq!(e.description, "not available"),
+            Ok(_) => panic!("expected call on broken capability to fail"),
+        }
+    });
+}
+
+struct FooOnly;
+
+impl test_capnp::test_interface::Server for FooOnly {
+    fn foo(&mut self,
+           _params: test_capnp::test_interface::FooParams,
+           mut results: test_capnp::test_interface::FooResults)
+           -> Promise<(), Error>
+    {
+        results.get().set_x("foo");
+        Promise::ok(())
+    }
+}
+
+#[test]
+fn unimplemented_method_default() {
+    let client: test_capnp::test_interface::Client = capnp_rpc::new_client(FooOnly);
+
+    async_std::task::block_on(async move {
+        match client.bar_request().send().promise.await {
+            Err(e) => {
+                assert_eq!(e.kind, ::capnp::ErrorKind::Unimplemented);
+                assert!(e.description.ends_with("TestInterface.bar"), "{}", e.description);
+            }
+            Ok(_) => panic!("expected bar() to fail"),
+        }
+    });
+}
+
+#[test]
+fn call_method_of_indirect_base() {
+    rpc_top_level(|client| async move {
+        let response = client.test_extends2_request().send().promise.await?;
+        let cap = response.get()?.get_cap()?;
+
+        // TestExtends2 extends TestExtends, which extends TestInterface.
+        let client = test_capnp::test_interface::Client { client: cap.client };
+        let mut request = client.foo_request();
+        request.get().set_i(321);
+        request.get().set_j(false);
+        let response = request.send().promise.await?;
+        if response.get()?.get_x()? != "bar" {
+            return Err(Error::failed("expected x to equal 'bar'".to_string()));
+        }
+        Ok(())
+    });
+}
+
+#[test]
+fn serialize_capability_outside_rpc() {
+    use capnp::traits::ImbueMut;
+    use crate::test_capnp::test_pipeline;
+
+    let mut cap_table = Vec::new();
+    let mut message = capnp::message::Builder::new_default();
+    {
+        let mut root: capnp::any_pointer::Builder = message.init_root();
+        root.imbue_mut(&mut cap_table);
+        let mut boxed = root.init_as::<test_pipeline::box_::Builder>();
+        boxed.set_cap(capnp_rpc::new_client(impls::TestInterface::new()));
+        assert!(boxed.has_cap());
+    }
+
+    // The capability table is not part of the serialized message.
+    let words = capnp::serialize::write_message_to_words(&message);
+    let reader = capnp::serialize::read_message(&mut &words[..], capnp::message::ReaderOptions::new()).unwrap();
+    let boxed = reader.get_root::<test_pipeline::box_::Reader>().unwrap();
+    assert!(boxed.has_cap());
+    match boxed.get_cap() {
+        Err(e) => assert!(e.description.contains("has no capability table"), "{}", e.description),
+        Ok(_) => panic!("expected an error"),
+    }
+
+    let mut copy = capnp::message::Builder::new_default();
+    assert!(copy.set_root(boxed).is_err());
+
+    let mut boxed = message.get_root::<capnp::any_pointer::Builder>().unwrap()
+        .get_as::<test_pipeline::box_::Builder>().unwrap();
+    boxed.clear_cap();
+    assert!(!boxed.has_cap());
+    copy.set_root(boxed.into_reader()).unwrap();
+}
+
+#[test]
+fn thread_client() {
+    fn assert_send<T: Send>(_: &T) {}
+
+    let server = crate::impls::TestInterface::new();
+    let call_count = server.get_call_count();
+    let client: test_capnp::test_interface::Client = capnp_rpc::new_client(server);
+    let (thread_client, serve) = capnp_rpc::thread::fork_for_thread(client.client);
+    assert_send(&thread_client);
+
+    let join_handle = ::std::thread::spawn(move || {
+        let client: test_capnp::test_interface::Client = thread_client.client();
+        drop(thread_client);
+        let mut exec = futures::executor::LocalPool::new();
+        let mut request = client.foo_request();
+        request.get().set_i(123);
+        request.get().set_j(true);
+        let response = exec.run_until(request.send().promise).unwrap();
+        assert_eq!(response.get().unwrap().get_x().unwrap(), "foo");
+
+        let mut request = client.foo_request();
+        request.get().set_i(1);
+        assert!(exec.run_until(request.send().promise).is_err());
+    });
+
+    // Completes when the other thread drops its client.
+    futures::executor::block_on(serve);
+    join_handle.join().unwrap();
+    assert_eq!(call_count.get(), 2);
+}
+
+#[test]
+fn thread_client_owner_gone() {
+    let client: test_capnp::test_interface::Client =
+        capnp_rpc::new_client(crate::impls::TestInterface::new());
+    let (thread_client, serve) = capnp_rpc::thread::fork_for_thread(client.client);
+    drop(serve);
+
+    let client: test_capnp::test_interface::Client = thread_client.client();
+    let error = futures::executor::block_on(client.foo_request().send().promise).err().unwrap();
+    assert_eq!(error.kind, ::capnp::ErrorKind::Disconnected);
+}
+
+#[test]
+fn release_params_before_returning() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    struct Releaser {
+        read: Rc<Cell<bool>>,
+    }
+
+    impl test_capnp::test_call_order::Server for Releaser {}
+
+    impl test_capnp::test_more_stuff::Server for Releaser {
+        fn never_return(&mut self,
+                        params: test_capnp::test_more_stuff::NeverReturnParams,
+                        _results: test_capnp::test_more_stuff::NeverReturnResults)
+                        -> Promise<(), Error>
+        {
+            let read = self.read.clone();
+            Promise::from_future(async move {
+                params.get()?.get_cap()?;
+                read.set(true);
+                params.release();
+                futures::future::pending().await
+            })
+        }
+    }
+
+    let read = Rc::new(Cell::new(false));
+    let client: test_capnp::test_more_stuff::Client =
+        capnp_rpc::new_client(Releaser { read: read.clone() });
+    let (fulfiller, destroyed) = oneshot::channel::<()>();
+    let mut request = client.never_return_request();
+    request.get().set_cap(capnp_rpc::new_client(impls::TestCapDestructor::new(fulfiller)));
+    let response = request.send().promise;
+
+    // The call never returns, but the capability in its parameters is released. Without the
+    // call to `release()`, this would wait forever.
+    let mut exec = futures::executor::LocalPool::new();
+    match exec.run_until(futures::future::select(response, destroyed)) {
+        futures::future::Either::Right((destroyed, _)) => destroyed.unwrap(),
+        futures::future::Either::Left(_) => panic!("the call should not return"),
+    }
+    assert!(read.get());
+}
+
+struct CountingSquarer {
+    calls: std::rc::Rc<std::cell::Cell<u32>>,
+}
+
+impl test_capnp::test_interface::Server for CountingSquarer {
+    fn square(&mut self,
+              params: test_capnp::test_interface::SquareParams,
+              mut results: test_capnp::test_interface::SquareResults)
+              -> Promise<(), Error>
+    {
+        self.calls.set(self.calls.get() + 1);
+        let n = pry!(params.get()).get_n();
+        results.get().set_result(n * n);
+        Promise::ok(())
+    }
+}
+
+fn square(client: &test_capnp::test_interface::Client, n: i64) -> Promise<i64, Error> {
+    let mut request = client.square_request();
+    request.get().set_n(n);
+    Promise::from_future(request.send().promise.map_ok(|response| response.get().unwrap().get_result()))
+}
+
+#[test]
+fn cached_calls() {
+    let calls = std::rc::Rc::new(std::cell::Cell::new(0));
+    let cache = capnp_rpc::cache::Cache::new();
+    let squarer: test_capnp::test_interface::Client =
+        capnp_rpc::new_client(CountingSquarer { calls: calls.clone() });
+    let client: test_capnp::test_interface::Client = capnp_rpc::cache::new_client(
+        squarer.client, test_capnp::test_interface::CACHEABLE_METHODS, cache.clone());
+
+    let square = |n| square(&client, n);
+
+    let mut exec = futures::executor::LocalPool::new();
+
+    // Identical calls made at the same time share one call to the server.
+    let (a, b) = exec.run_until(futures::future::try_join(square(3), square(3))).unwrap();
+    assert_eq!((a, b), (9, 9));
+    assert_eq!(calls.get(), 1);
+
+    assert_eq!(exec.run_until(square(3)).unwrap(), 9);
+    assert_eq!(calls.get(), 1);
+    assert_eq!(exec.run_until(square(4)).unwrap(), 16);
+    assert_eq!(calls.get(), 2);
+    assert_eq!(cache.len(), 2);
+
+    // Methods that are not cacheable are forwarded every time.
+    assert!(exec.run_until(client.bar_request().send().promise).is_err());
+    assert!(exec.run_until(client.bar_request().send().promise).is_err());
+    assert_eq!(cache.len(), 2);
+
+    cache.clear();
+    assert_eq!(exec.run_until(square(3)).unwrap(), 9);
+    assert_eq!(calls.get(), 3);
+}
+
+#[test]
+fn cache_capacity() {
+    let calls = std::rc::Rc::new(std::cell::Cell::new(0));
+    let cache = capnp_rpc::cache::Cache::with_capacity(2);
+    let squarer: test_capnp::test_interface::Client =
+        capnp_rpc::new_client(CountingSquarer { calls: calls.clone() });
+    let client: test_capnp::test_interface::Client = capnp_rpc::cache::new_client(
+        squarer.client, test_capnp::test_interface::CACHEABLE_METHODS, cache.clone());
+
+    let mut exec = futures::executor::LocalPool::new();
+    for &n in &[1, 2, 1, 3] {
+        exec.run_until(square(&client, n)).unwrap();
+    }
+    assert_eq!(calls.get(), 3);
+    assert_eq!(cache.len(), 2);
+
+    // Making room for 3 forgot 2, which had been used less recently than 1.
+    exec.run_until(square(&client, 1)).unwrap();
+    assert_eq!(calls.get(), 3);
+    exec.run_until(square(&client, 2)).unwrap();
+    assert_eq!(calls.get(), 4);
+}
+
+#[test]
+fn cache_forgets_disconnected_callers() {
+    let (client_stream, server_stream) = async_std::os::unix::net::UnixStream::pair().expect("socket pair");
+    let (client_reader, client_writer) = client_stream.split();
+    let client_network =
+        Box::new(twoparty::VatNetwork::new(client_reader, client_writer,
+                                           rpc_twoparty_capnp::Side::Client,
+                                           Default::default()));
+    let mut client_rpc_system = RpcSystem::new(client_network, None);
+
+    let (server_reader, server_writer) = server_stream.split();
+    let server_network =
+        Box::new(twoparty::VatNetwork::new(server_reader, server_writer,
+                                           rpc_twoparty_capnp::Side::Server,
+                                           Default::default()));
+    let calls = std::rc::Rc::new(std::cell::Cell::new(0));
+    let cache = capnp_rpc::cache::Cache::new();
+    let squarer: test_capnp::test_interface::Client =
+        capnp_rpc::new_client(CountingSquarer { calls: calls.clone() });
+    let bootstrap: test_capnp::test_interface::Client = capnp_rpc::cache::new_client(
+        squarer.client, test_capnp::test_interface::CACHEABLE_METHODS, cache.clone());
+    let server_rpc_system = RpcSystem::new(server_network, Some(bootstrap.client));
+
+    let client: test_capnp::test_interface::Client =
+        client_rpc_system.bootstrap(rpc_twoparty_capnp::Side::Server);
+    let disconnector = client_rpc_system.get_disconnector();
+
+    async_std::task::block_on(async move {
+        spawn(client_rpc_system);
+
+        let served = async_std::task::spawn_local(server_rpc_system);
+        assert_eq!(square(&client, 5).await.unwrap(), 25);
+        assert_eq!(square(&client, 5).await.unwrap(), 25);
+        assert_eq!(calls.get(), 1);
+        assert_eq!(cache.len(), 1);
+
+        disconnector.await.unwrap();
+        let _ = served.await;
+        assert_eq!(cache.len(), 0);
+    });
+}
+
+#[test]
+fn call_method_of_base_without_a_path() {
+    use crate::test_base_capnp::test_base;
+    use crate::test_indirect_capnp::test_indirect;
+
+    // TestIndirect's generated code can't name TestBase, so TestBase's entries are added to the
+    // dispatch table explicitly.
+    let dispatch = test_indirect::ServerDispatch::new(impls::TestIndirect)
+        .with_dispatch(test_base::ServerDispatch::<impls::TestIndirect>::register_dispatch);
+    let client: test_indirect::Client = capnp_rpc::new_client_from_dispatch(dispatch);
+    let without_base: test_indirect::Client = capnp_rpc::new_client(impls::TestIndirect);
+
+    async_std::task::block_on(async move {
+        let response = client.pong_request().send().promise.await.unwrap();
+        assert_eq!(response.get().unwrap().get_n(), 2);
+
+        let base = test_base::Client { client: client.client };
+        let response = base.ping_request().send().promise.await.unwrap();
+        assert_eq!(response.get().unwrap().get_n(), 1);
+
+        let base = test_base::Client { client: without_base.client };
+        match base.ping_request().send().promise.await {
+            Err(ref e) if e.kind == capnp::ErrorKind::Unimplemented => (),
+            Err(e) => panic!("wrong kind of error: {:?}", e),
+            Ok(_) => panic!("expected an error"),
+        }
+    });
+}
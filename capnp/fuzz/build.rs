@@ -2,6 +2,7 @@ extern crate capnpc;
 
 fn main() {
     ::capnpc::CompilerCommand::new()
+        .builtin_compiler()
         .file("fuzzers/test.capnp")
         .src_prefix("fuzzers")
         .run()
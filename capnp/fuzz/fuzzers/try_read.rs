@@ -0,0 +1,8 @@
+#![no_main]
+extern crate libfuzzer_sys;
+extern crate capnp;
+
+#[export_name="rust_fuzzer_test_input"]
+pub extern fn go(data: &[u8]) {
+    let _ = capnp::fuzz::try_read(data);
+}
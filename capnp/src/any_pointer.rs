@@ -44,7 +44,7 @@ impl crate::traits::Pipelined for Owned {
 
 #[derive(Copy, Clone)]
 pub struct Reader<'a> {
-    reader: PointerReader<'a>
+    pub(crate) reader: PointerReader<'a>
 }
 
 impl <'a> Reader<'a> {
@@ -68,6 +68,12 @@ impl <'a> Reader<'a> {
         FromPointerReader::get_from_pointer(&self.reader, None)
     }
 
+    /// Reads the pointer as text, returning its bytes without checking that they are valid
+    /// UTF-8.
+    pub fn get_text_bytes(&self) -> Result<&'a [u8]> {
+        self.reader.get_text_bytes(None)
+    }
+
     pub fn get_as_capability<T: FromClientHook>(&self) -> Result<T> {
         Ok(FromClientHook::new(self.reader.get_capability()?))
     }
@@ -114,7 +120,7 @@ impl <'a> crate::traits::Imbue<'a> for Reader<'a> {
 }
 
 pub struct Builder<'a> {
-    builder: PointerBuilder<'a>
+    pub(crate) builder: PointerBuilder<'a>
 }
 
 impl <'a> Builder<'a> {
@@ -152,6 +158,14 @@ impl <'a> Builder<'a> {
         SetPointerBuilder::<To>::set_pointer_builder(self.builder, value, false)
     }
 
+    /// Sets the pointer to a copy of `value`. When `value` is canonical, for example because it
+    /// is the root of a message that was built with `set_root_canonical()`, its words are copied
+    /// in one block instead of object by object, which is much faster for large payloads.
+    /// Returns whether that happened.
+    pub fn splice_from(&mut self, value: Reader) -> Result<bool> {
+        self.builder.splice_from(value.reader)
+    }
+
     // XXX value should be a user client.
     pub fn set_as_capability(&mut self, value: Box<dyn ClientHook>) {
         self.builder.set_capability(value);
@@ -188,6 +202,8 @@ impl <'a> crate::traits::ImbueMut<'a> for Builder<'a> {
     }
 }
 
+/// A promised pointer that can be followed through struct pointer fields only; see
+/// `PipelineOp` for why list elements are out of reach.
 pub struct Pipeline {
     // XXX this should not be public
     pub hook: Box<dyn PipelineHook>,
@@ -24,6 +24,8 @@
 //! Roughly corresponds to capability.h in the C++ implementation.
 
 use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
 use core::future::{Future};
 use core::pin::{Pin};
 use core::marker::{PhantomData, Unpin};
@@ -165,6 +167,36 @@ where Results: Pipelined + for<'a> Owned<'a> + 'static + Unpin,
     }
 }
 
+/// Describes a method of an interface. Generated code provides a `METHODS` table of these for
+/// each interface, so that ids can be translated to names without the schema at hand.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MethodInfo {
+    pub interface_id: u64,
+    pub method_id: u16,
+
+    /// The method's name as written in the schema.
+    pub name: &'static str,
+
+    /// The id of the parameter struct type. For a method declared with a parameter list rather
+    /// than a named struct, this is the id of the implicit struct that the compiler generated.
+    pub param_type_id: u64,
+
+    /// The id of the result struct type, which may likewise be implicit.
+    pub result_type_id: u64,
+}
+
+/// Describes the connection that a method call arrived on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallerInfo {
+    /// Identifies the connection within this process. Calls that arrive on the same connection
+    /// have the same id.
+    pub connection_id: u64,
+
+    /// The identity of the peer, if the transport established one, e.g. from a TLS client
+    /// certificate.
+    pub peer_identity: Option<String>,
+}
+
 /// The values of the parameters passed to a method call, as seen by the server.
 pub struct Params<T> {
     pub marker: PhantomData<T>,
@@ -180,6 +212,19 @@ impl <T> Params <T> {
     {
         Ok(self.hook.get()?.get_as()?)
     }
+
+    /// Returns information about the connection that the call arrived on, or None if the call
+    /// was made locally, within this process.
+    pub fn caller(&self) -> Option<CallerInfo> {
+        self.hook.caller()
+    }
+
+    /// Releases the parameters, together with the message that carried them and any
+    /// capabilities they refer to. This happens anyway when `Params` is dropped, but a method
+    /// that runs for a long time after reading its parameters should do it explicitly, before it
+    /// starts producing its results, so that a large request does not stay in memory, and
+    /// capabilities passed in it are not kept alive, for the rest of the call.
+    pub fn release(self) {}
 }
 
 /// The return values of a method, written in-place by the method body.
@@ -242,6 +287,14 @@ impl Client {
     }
 }
 
+/// Creates a capability on which every call fails with `error`, as does `when_resolved()`.
+///
+/// Useful as a stand-in for a dependency that is unavailable, or to report why a capability
+/// could not be obtained. To get a typed client, wrap the hook: `T::new(broken_cap(e).hook)`.
+pub fn broken_cap(error: Error) -> Client {
+    Client::new(crate::private::broken::new_cap(error))
+}
+
 /// An untyped server.
 pub trait Server {
     fn dispatch_call(&mut self, interface_id: u64, method_id: u16,
@@ -250,6 +303,46 @@ pub trait Server {
                      -> Promise<(), Error>;
 }
 
+/// Dispatches a call to one of the methods of a particular interface, implemented by a server
+/// of type `T`. Generated `ServerDispatch` types look these up by interface ID, so that a server
+/// also handles calls to the methods of every interface that its interface extends.
+pub type DispatchFn<T> = fn(&mut T, u16, Params<any_pointer::Owned>, Results<any_pointer::Owned>)
+                            -> Promise<(), Error>;
+
+/// The dispatch functions of a server of type `T`, keyed by interface ID.
+///
+/// Each generated `ServerDispatch` holds one of these. Its `register_dispatch()` adds the entry
+/// for its own interface and for each base interface that the generated code can name; an entry
+/// for any other base, such as one defined in a file that isn't imported directly, can be added
+/// with `ServerDispatch::with_dispatch()`. Calls are then looked up by interface ID alone.
+pub struct DispatchTable<T> {
+    entries: Vec<(u64, DispatchFn<T>)>,
+}
+
+impl <T> DispatchTable<T> {
+    pub fn new() -> DispatchTable<T> {
+        DispatchTable { entries: Vec::new() }
+    }
+
+    /// Adds the dispatch function for the interface `interface_id`. Does nothing if the table
+    /// already has one, as happens when an interface is reached through more than one base.
+    pub fn register(&mut self, interface_id: u64, dispatch: DispatchFn<T>) {
+        if self.find(interface_id).is_none() {
+            self.entries.push((interface_id, dispatch));
+        }
+    }
+
+    pub fn find(&self, interface_id: u64) -> Option<DispatchFn<T>> {
+        self.entries.iter().find(|&&(id, _)| id == interface_id).map(|&(_, dispatch)| dispatch)
+    }
+}
+
+impl <T> Default for DispatchTable<T> {
+    fn default() -> DispatchTable<T> {
+        DispatchTable::new()
+    }
+}
+
 /// Trait to track the relationship between generated Server traits and Client structs.
 pub trait FromServer<S> : FromClientHook {
     // Implemented by the generated ServerDispatch struct.
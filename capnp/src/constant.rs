@@ -18,17 +18,41 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 
-//! Helper type for generated Struct and List constants.
+//! Helper type for generated Struct and List constants, and for messages embedded in the binary.
 //!
 //! `constant::Reader` does not do bounds-checking, so it is unsafe to
-//! manually construct one of these.
+//! manually construct one of these other than through `Reader::new()`.
+//!
+//! To embed a message, write it in canonical form without a segment table, for example with
+//! `capnp convert text:canonical foo.capnp Config < config.txt > config.bin`, and then:
+//!
+//! ```ignore
+//! let config = capnp::constant::Reader::<foo_capnp::config::Owned>::new(
+//!     capnp::include_words!("config.bin")?)?;
+//! let value: foo_capnp::config::Reader<'static> = config.get()?;
+//! ```
 
 use core::marker::PhantomData;
 
+use alloc::string::ToString;
+
 use crate::any_pointer;
+use crate::message;
 use crate::private::layout::PointerReader;
 use crate::traits::Owned;
-use crate::{Result};
+use crate::{Error, Result, Word};
+
+/// Includes a file as a `&'static [Word]`, aligned for reading in place. Evaluates to a
+/// `Result`, which is an error if the file's length is not a multiple of eight bytes.
+#[macro_export]
+macro_rules! include_words {
+    ($path:expr) => {{
+        #[repr(C, align(8))]
+        struct Aligned<B: ?Sized>(B);
+        static ALIGNED: &'static Aligned<[u8]> = &Aligned(*include_bytes!($path));
+        $crate::Word::try_bytes_to_words(&ALIGNED.0)
+    }};
+}
 
 #[derive(Copy, Clone)]
 #[repr(C, align(8))]
@@ -40,6 +64,29 @@ pub struct Reader<T> {
     pub words: &'static [crate::Word],
 }
 
+impl <T> Reader<T> {
+    /// Wraps `words`, a message in canonical form without a segment table. Reading from the
+    /// result does no bounds checks, so this first checks that the message is canonical, which
+    /// guarantees that every pointer in it stays within `words`.
+    pub fn new(words: &'static [Word]) -> Result<Reader<T>> {
+        let segments = [Word::words_to_bytes(words)];
+        let message = message::Reader::new(message::SegmentArray::new(&segments), message::ReaderOptions::new());
+        if !message.is_canonical()? {
+            return Err(Error::failed("embedded message is not canonical".to_string()));
+        }
+        Ok(Reader { phantom: PhantomData, words: words })
+    }
+
+    /// Like `new()`, but without the check, for use when `words` is known to be canonical.
+    ///
+    /// # Safety
+    ///
+    /// `words` must be a canonical message; otherwise reads may go out of bounds.
+    pub const unsafe fn new_unchecked(words: &'static [Word]) -> Reader<T> {
+        Reader { phantom: PhantomData, words: words }
+    }
+}
+
 impl <T> Reader<T> where T: for<'a> Owned<'a> {
     /// Retrieve the value.
     pub fn get(&self) -> Result<<T as Owned<'static>>::Reader> {
@@ -20,6 +20,10 @@
 // THE SOFTWARE.
 
 //! Sequence of bytes.
+//!
+//! A `Reader` is a plain slice pointing into the message, so a large value can be processed
+//! piecewise with `chunks()` without being copied, and `fill_from()` writes a large value
+//! straight into a message from a stream.
 
 use crate::private::layout::{PointerBuilder, PointerReader};
 use crate::Result;
@@ -46,6 +50,13 @@ impl <'a> crate::traits::FromPointerReader<'a> for Reader<'a> {
 
 pub type Builder<'a> = &'a mut [u8];
 
+/// Fills `builder`, such as the result of initializing a field to the length of the value, with
+/// bytes read from `read`. The bytes are written directly into the message. Fails if `read` ends
+/// before `builder` is full.
+pub fn fill_from<R>(builder: Builder, mut read: R) -> Result<()> where R: crate::io::Read {
+    read.read_exact(builder)
+}
+
 pub fn new_builder<'a>(p : *mut u8, len : u32) -> Builder<'a> {
     unsafe { ::core::slice::from_raw_parts_mut(p, len as usize) }
 }
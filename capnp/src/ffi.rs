@@ -0,0 +1,230 @@
+// Copyright (c) 2018 the capnproto-rust contributors
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+
+//! Zero-copy handoff of messages to and from Cap'n Proto C++ in the same process.
+//!
+//! A message is passed across the language boundary as an array of `Segment`s, without being
+//! serialized to a stream. This relies on the following layout guarantees:
+//!
+//!  * `Word` is eight bytes with eight-byte alignment, just like `capnp::word` in C++, and the
+//!    segments hold the standard little-endian wire format.
+//!  * `Segment` has the same layout as `kj::ArrayPtr<const capnp::word>`: a pointer followed by
+//!    a length in words, both pointer-sized. An array of them can therefore be handed to
+//!    `capnp::SegmentArrayMessageReader` by reinterpreting it as
+//!    `kj::ArrayPtr<const kj::ArrayPtr<const capnp::word>>`.
+//!  * `ExportedMessage` is a plain C struct. Its `segments` stay valid and unchanged until it is
+//!    passed to `capnp_rust_exported_message_free()`, which must happen exactly once.
+//!
+//! The C declarations are:
+//!
+//! ```c
+//! struct capnp_rust_segment { const uint64_t *words; size_t len; };
+//! struct capnp_rust_exported_message {
+//!   const struct capnp_rust_segment *segments;
+//!   size_t segment_count;
+//!   void *owner;
+//! };
+//! void capnp_rust_exported_message_free(struct capnp_rust_exported_message message);
+//! ```
+//!
+//! In the other direction, messages built in C++ can be read in place with `import()`, from an
+//! `extern "C"` function that the C++ side calls with the output of
+//! `MessageBuilder::getSegmentsForOutput()`:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub unsafe extern "C" fn handle_request(segments: *const capnp::ffi::Segment, count: usize) -> i32 {
+//!     let reader = match capnp::ffi::import(segments, count, Default::default()) { ... };
+//!     ...
+//! }
+//! ```
+//!
+//! Alternatively, the C++ side can turn its segments into a handle that Rust code reads later,
+//! with `capnp_rust_message_import()`, and release it with `capnp_rust_imported_message_free()`:
+//!
+//! ```c
+//! struct capnp_rust_imported_message;
+//! struct capnp_rust_imported_message *capnp_rust_message_import(
+//!     const struct capnp_rust_segment *segments, size_t segment_count);
+//! void capnp_rust_imported_message_free(struct capnp_rust_imported_message *message);
+//! ```
+//!
+//! All of this is only available with the "ffi" feature, so that the exported symbols are not
+//! linked into programs that don't need them.
+
+use alloc::boxed::Box;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::ffi::c_void;
+
+use crate::message::{self, Allocator, ReaderOptions, ReaderSegments};
+use crate::{Error, Result, Word};
+
+/// One segment of a message, laid out like `kj::ArrayPtr<const capnp::word>`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Segment {
+    pub words: *const Word,
+
+    /// The length of the segment, in words.
+    pub len: usize,
+}
+
+/// A message handed over to foreign code by `export()`.
+#[repr(C)]
+#[derive(Debug)]
+pub struct ExportedMessage {
+    pub segments: *const Segment,
+    pub segment_count: usize,
+
+    /// Keeps the message alive; only for use by `capnp_rust_exported_message_free()`.
+    pub owner: *mut c_void,
+}
+
+struct Owner {
+    _message: Box<dyn Any>,
+    segments: Vec<Segment>,
+}
+
+/// Takes ownership of `message` and exposes its segments for foreign code to read in place.
+pub fn export<A>(message: message::Builder<A>) -> ExportedMessage
+    where A: Allocator + 'static
+{
+    // The segments are heap-allocated by `A`, so moving the builder into a box leaves them
+    // where they are.
+    let message = Box::new(message);
+    let segments: Vec<Segment> = message.get_segments_for_output().iter().map(|s| Segment {
+        words: s.as_ptr() as *const Word,
+        len: s.len() / 8,
+    }).collect();
+    let owner = Box::new(Owner { _message: message, segments: segments });
+    ExportedMessage {
+        segments: owner.segments.as_ptr(),
+        segment_count: owner.segments.len(),
+        owner: Box::into_raw(owner) as *mut c_void,
+    }
+}
+
+/// Frees a message returned by `export()`.
+#[no_mangle]
+pub unsafe extern "C" fn capnp_rust_exported_message_free(message: ExportedMessage) {
+    if !message.owner.is_null() {
+        drop(Box::from_raw(message.owner as *mut Owner));
+    }
+}
+
+/// Segments owned by foreign code, as accepted by `import()`.
+pub struct ForeignSegments<'a> {
+    segments: &'a [Segment],
+}
+
+impl <'a> ReaderSegments for ForeignSegments<'a> {
+    fn get_segment<'b>(&'b self, idx: u32) -> Option<&'b [u8]> {
+        self.segments.get(idx as usize).map(|s| {
+            if s.len == 0 {
+                &[][..]
+            } else {
+                unsafe { core::slice::from_raw_parts(s.words as *const u8, s.len * 8) }
+            }
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.segments.len()
+    }
+}
+
+/// Reads a message in place from `count` segments owned by foreign code, such as the result of
+/// `capnp::MessageBuilder::getSegmentsForOutput()` in C++.
+///
+/// # Safety
+///
+/// `segments` must point to `count` valid `Segment`s, each of which must point to `len` words
+/// that stay valid and unmodified for `'a`.
+pub unsafe fn import<'a>(segments: *const Segment, count: usize, options: ReaderOptions)
+                         -> Result<message::Reader<ForeignSegments<'a>>>
+{
+    Ok(message::Reader::new(ForeignSegments { segments: check_segments(segments, count)? }, options))
+}
+
+unsafe fn check_segments<'a>(segments: *const Segment, count: usize) -> Result<&'a [Segment]> {
+    if count == 0 {
+        return Err(Error::failed("message has no segments".to_string()));
+    }
+    if segments.is_null() {
+        return Err(Error::failed("segment array is null".to_string()));
+    }
+    let segments = core::slice::from_raw_parts(segments, count);
+    for (idx, segment) in segments.iter().enumerate() {
+        if segment.words.is_null() && segment.len > 0 {
+            return Err(Error::failed(format!("segment {} is null", idx)));
+        }
+    }
+    Ok(segments)
+}
+
+/// A message handed over by foreign code through `capnp_rust_message_import()`.
+pub struct ImportedMessage {
+    segments: Vec<Segment>,
+}
+
+impl ImportedMessage {
+    /// Borrows the message behind a handle returned by `capnp_rust_message_import()`.
+    ///
+    /// # Safety
+    ///
+    /// `message` must be such a handle that has not yet been passed to
+    /// `capnp_rust_imported_message_free()`, and the words of its segments must stay valid and
+    /// unmodified for `'a`.
+    pub unsafe fn from_raw<'a>(message: *const ImportedMessage) -> Option<&'a ImportedMessage> {
+        message.as_ref()
+    }
+
+    pub fn get_reader(&self, options: ReaderOptions) -> message::Reader<ForeignSegments<'_>> {
+        message::Reader::new(ForeignSegments { segments: &self.segments }, options)
+    }
+}
+
+/// Takes a copy of the array of `count` segments, for Rust code to read in place later. Returns
+/// null if the array is empty or contains a null segment.
+///
+/// # Safety
+///
+/// `segments` must point to `count` valid `Segment`s. The array itself may be freed once this
+/// returns, but the words that it points to must outlive the returned handle.
+#[no_mangle]
+pub unsafe extern "C" fn capnp_rust_message_import(segments: *const Segment, count: usize)
+                                                   -> *mut ImportedMessage
+{
+    match check_segments(segments, count) {
+        Ok(segments) => Box::into_raw(Box::new(ImportedMessage { segments: segments.to_vec() })),
+        Err(_) => core::ptr::null_mut(),
+    }
+}
+
+/// Frees a handle returned by `capnp_rust_message_import()`. Does nothing if `message` is null.
+#[no_mangle]
+pub unsafe extern "C" fn capnp_rust_imported_message_free(message: *mut ImportedMessage) {
+    if !message.is_null() {
+        drop(Box::from_raw(message));
+    }
+}
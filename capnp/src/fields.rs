@@ -0,0 +1,291 @@
+// Copyright (c) 2013-2017 Sandstorm Development Group, Inc. and contributors
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+
+//! Static tables describing the fields of generated struct types.
+//!
+//! Every generated struct implements `traits::HasFields`, and its `Reader` has a
+//! `get_field_by_ordinal()` method, so that code such as serialization frameworks can iterate
+//! over the fields of a struct without loading its schema. `get_field_by_ordinal()` returns
+//! `None` if the struct has no field with the ordinal, or if the field is an inactive union
+//! member; the fields of a group are found through the group's own reader.
+//!
+//! Each generated struct module also has a `field_info` module with a `FieldLayout` constant
+//! per field, such as `foo::field_info::BAR_BAZ` for the field `barBaz`, for checking the
+//! layout of a message against another implementation's by hand.
+
+use crate::{any_pointer, data, text};
+use crate::private::layout::{ElementSize, StructReader};
+
+/// The type of a field, without its parameters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TypeTag {
+    Void,
+    Bool,
+    Int8,
+    Int16,
+    Int32,
+    Int64,
+    UInt8,
+    UInt16,
+    UInt32,
+    UInt64,
+    Float32,
+    Float64,
+    Text,
+    Data,
+    List,
+    Enum,
+    Struct,
+    Interface,
+    AnyPointer,
+    Group,
+}
+
+/// A field of a struct, as declared in its schema.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FieldInfo {
+    pub name: &'static str,
+
+    /// The number after the `@` in the field's declaration, or `None` for a group.
+    pub ordinal: Option<u16>,
+
+    /// The location of the field in the struct: in units of the field's size in the data
+    /// section for primitives and enums, or an index into the pointer section for pointers.
+    /// Zero for voids and groups.
+    pub offset: u32,
+
+    pub type_tag: TypeTag,
+
+    /// If the field is a member of the struct's union, the value the discriminant takes when
+    /// the field is active.
+    pub discriminant_value: Option<u16>,
+
+    /// The bits of the field's default value, which the stored bits of a data field are XORed
+    /// with. Zero for pointer fields and groups.
+    pub default_bits: u64,
+}
+
+/// Where a field is stored in its struct. Groups have no layout of their own.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FieldLayout {
+    /// The number after the `@` in the field's declaration.
+    pub ordinal: u16,
+
+    /// As `FieldInfo::offset`: in units of `element_size` in the data section, or an index
+    /// into the pointer section.
+    pub offset: u32,
+
+    /// The size of the field's value, as it would be for an element of a list. Every field in
+    /// the pointer section, including struct fields, has size `Pointer`.
+    pub element_size: ElementSize,
+}
+
+impl FieldLayout {
+    /// The position of the field's first bit in the data section, or `None` for fields in the
+    /// pointer section and voids.
+    pub fn data_bit_offset(&self) -> Option<u64> {
+        let bits = match self.element_size {
+            ElementSize::Bit => 1,
+            ElementSize::Byte => 8,
+            ElementSize::TwoBytes => 16,
+            ElementSize::FourBytes => 32,
+            ElementSize::EightBytes => 64,
+            ElementSize::Void | ElementSize::Pointer | ElementSize::InlineComposite => return None,
+        };
+        Some(self.offset as u64 * bits)
+    }
+}
+
+/// The value of a field, as returned by `get_field_by_ordinal()`.
+#[derive(Clone, Copy)]
+pub enum FieldValue<'a> {
+    Void,
+    Bool(bool),
+    Int8(i8),
+    Int16(i16),
+    Int32(i32),
+    Int64(i64),
+    UInt8(u8),
+    UInt16(u16),
+    UInt32(u32),
+    UInt64(u64),
+    Float32(f32),
+    Float64(f64),
+    Text(text::Reader<'a>),
+    Data(data::Reader<'a>),
+
+    /// The numeric value of an enum, which might not have an enumerant in the schema.
+    Enum(u16),
+
+    /// A list, struct, interface or any-pointer field, which can be read with `get_as()`.
+    Pointer(any_pointer::Reader<'a>),
+}
+
+/// A type that a data field can be read as by `struct_list::Reader::collect_field()`.
+pub trait ColumnValue: Sized {
+    /// Whether a field of the given type holds values of this type.
+    fn matches(type_tag: TypeTag) -> bool;
+
+    /// Reads the field at `offset`, in units of the field's size, undoing the XOR with
+    /// `default_bits`.
+    fn read(reader: &StructReader, offset: usize, default_bits: u64) -> Self;
+}
+
+macro_rules! column_value(
+    ($t:ident, $mask:ident, $($tag:ident)|+) => (
+        impl ColumnValue for $t {
+            fn matches(type_tag: TypeTag) -> bool {
+                match type_tag { $(TypeTag::$tag)|+ => true, _ => false }
+            }
+
+            #[inline]
+            fn read(reader: &StructReader, offset: usize, default_bits: u64) -> $t {
+                reader.get_data_field_mask::<$t>(offset, default_bits as $mask)
+            }
+        }
+    )
+);
+
+column_value!(i8, i8, Int8);
+column_value!(i16, i16, Int16);
+column_value!(i32, i32, Int32);
+column_value!(i64, i64, Int64);
+column_value!(u8, u8, UInt8);
+column_value!(u16, u16, UInt16 | Enum);
+column_value!(u32, u32, UInt32);
+column_value!(u64, u64, UInt64);
+column_value!(f32, u32, Float32);
+column_value!(f64, u64, Float64);
+
+impl ColumnValue for bool {
+    fn matches(type_tag: TypeTag) -> bool {
+        type_tag == TypeTag::Bool
+    }
+
+    #[inline]
+    fn read(reader: &StructReader, offset: usize, default_bits: u64) -> bool {
+        reader.get_bool_field_mask(offset, default_bits != 0)
+    }
+}
+
+impl TypeTag {
+    /// The number of bits a field of this type occupies in the data section, or `None` for
+    /// types that are stored in the pointer section or that occupy no space of their own.
+    pub fn data_bits(self) -> Option<u32> {
+        match self {
+            TypeTag::Bool => Some(1),
+            TypeTag::Int8 | TypeTag::UInt8 => Some(8),
+            TypeTag::Int16 | TypeTag::UInt16 | TypeTag::Enum => Some(16),
+            TypeTag::Int32 | TypeTag::UInt32 | TypeTag::Float32 => Some(32),
+            TypeTag::Int64 | TypeTag::UInt64 | TypeTag::Float64 => Some(64),
+            _ => None,
+        }
+    }
+
+    /// Whether a field of this type is stored in the pointer section.
+    pub fn is_pointer(self) -> bool {
+        match self {
+            TypeTag::Text | TypeTag::Data | TypeTag::List | TypeTag::Struct |
+            TypeTag::Interface | TypeTag::AnyPointer => true,
+            _ => false,
+        }
+    }
+}
+
+/// Checks that the fields of a struct, and its union discriminant if it has one, fit into a
+/// struct of the given size, and that no two fields outside of the union overlap. Generated code
+/// calls this from a test for each struct, so that a disagreement between the offsets baked into
+/// accessors and the struct's size is caught by `cargo test` rather than as corrupted data.
+pub fn check_layout(size: crate::private::layout::StructSize, discriminant_offset: Option<u32>,
+                    fields: &[FieldInfo]) -> crate::Result<()> {
+    // (name, first bit, bit count) in the data section, or (name, index, 1) in the pointer section.
+    let mut data = alloc::vec::Vec::new();
+    let mut pointers = alloc::vec::Vec::new();
+    if let Some(offset) = discriminant_offset {
+        if (offset as u64 + 1) * 16 > size.data as u64 * 64 {
+            return Err(crate::Error::failed(format!(
+                "union discriminant at bit offset {} does not fit into a data section of {} words",
+                offset as u64 * 16, size.data)));
+        }
+        data.push(("union discriminant", offset as u64 * 16, 16));
+    }
+    for field in fields {
+        if let Some(bits) = field.type_tag.data_bits() {
+            let start = field.offset as u64 * bits as u64;
+            if start + bits as u64 > size.data as u64 * 64 {
+                return Err(crate::Error::failed(format!(
+                    "field {} at bit offset {} does not fit into a data section of {} words",
+                    field.name, start, size.data)));
+            }
+            if field.discriminant_value.is_none() {
+                data.push((field.name, start, bits as u64));
+            }
+        } else if field.type_tag.is_pointer() {
+            if field.offset >= size.pointers as u32 {
+                return Err(crate::Error::failed(format!(
+                    "field {} at pointer index {} does not fit into a pointer section of {} pointers",
+                    field.name, field.offset, size.pointers)));
+            }
+            if field.discriminant_value.is_none() {
+                pointers.push((field.name, field.offset as u64, 1));
+            }
+        }
+    }
+    for section in &mut [data, pointers] {
+        section.sort_by_key(|&(_, start, _)| start);
+        for pair in section.windows(2) {
+            let ((first, first_start, first_len), (second, second_start, _)) = (pair[0], pair[1]);
+            if first_start + first_len > second_start {
+                return Err(crate::Error::failed(format!(
+                    "fields {} and {} overlap", first, second)));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::private::layout::StructSize;
+    use super::{check_layout, FieldInfo, TypeTag};
+
+    fn field(name: &'static str, offset: u32, type_tag: TypeTag, discriminant_value: Option<u16>) -> FieldInfo {
+        FieldInfo { name: name, ordinal: Some(0), offset: offset, type_tag: type_tag,
+                    discriminant_value: discriminant_value, default_bits: 0 }
+    }
+
+    #[test]
+    fn check_layout_errors() {
+        let size = StructSize { data: 1, pointers: 1 };
+        let fields = [field("a", 0, TypeTag::UInt32, None), field("b", 1, TypeTag::UInt16, Some(0)),
+                      field("c", 1, TypeTag::UInt16, Some(1)), field("d", 0, TypeTag::Text, None)];
+        check_layout(size, Some(3), &fields).unwrap();
+
+        // Overlapping "a", and out of bounds.
+        assert!(check_layout(size, Some(1), &fields).is_err());
+        assert!(check_layout(size, Some(4), &fields).is_err());
+
+        assert!(check_layout(StructSize { data: 1, pointers: 0 }, None, &fields).is_err());
+        assert!(check_layout(size, None, &[field("e", 1, TypeTag::Float64, None)]).is_err());
+        assert!(check_layout(size, None, &[field("f", 63, TypeTag::Bool, None),
+                                           field("g", 7, TypeTag::UInt8, None)]).is_err());
+    }
+}
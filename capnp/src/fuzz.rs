@@ -0,0 +1,162 @@
+// Copyright (c) 2020 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Entry points for fuzzing the read path.
+//!
+//! `try_read()` takes arbitrary bytes, parses them as a message using the standard stream
+//! framing and the packed encoding, and then walks every pointer reachable from the root,
+//! decoding structs, lists, text, and data along the way. It should never panic, no matter
+//! what input it is given; an `Err` simply means that the input was not a valid message.
+//!
+//! A libFuzzer harness that calls this function lives in the `fuzz` directory of the
+//! capnp crate, along with a seed corpus.
+
+use crate::message;
+use crate::private::layout::{ElementSize, ListReader, PointerReader, PointerType, PrimitiveElement,
+                             StructReader};
+use crate::{serialize, serialize_packed, Result, Word};
+
+/// Traversal limit used for fuzzing. Keeps the cost of any single input small.
+const TRAVERSAL_LIMIT_IN_WORDS: u64 = 8 * 1024;
+
+/// Reads `bytes` as a message, first with the standard framing and then with the packed
+/// framing, and traverses everything reachable from the root of each.
+///
+/// Returns the first error encountered by the unpacked read, or, if that succeeded, by the
+/// packed read.
+pub fn try_read(bytes: &[u8]) -> Result<()> {
+    let unpacked = try_read_unpacked(bytes);
+    let packed = try_read_packed(bytes);
+    unpacked.and(packed)
+}
+
+fn reader_options() -> message::ReaderOptions {
+    *message::ReaderOptions::new().traversal_limit_in_words(TRAVERSAL_LIMIT_IN_WORDS)
+}
+
+fn try_read_unpacked(bytes: &[u8]) -> Result<()> {
+    // The input might not be aligned, so copy it into words first.
+    let words = Word::words_from_bytes(bytes);
+    let mut slice = &Word::words_to_bytes(&words[..])[..bytes.len()];
+    let message = serialize::read_message_from_flat_slice(&mut slice, reader_options())?;
+    traverse_message(&message)?;
+    if message.is_canonical()? {
+        message.canonicalize()?;
+    }
+    Ok(())
+}
+
+fn try_read_packed(mut bytes: &[u8]) -> Result<()> {
+    let message = serialize_packed::read_message(&mut bytes, reader_options())?;
+    traverse_message(&message)
+}
+
+fn traverse_message<S>(message: &message::Reader<S>) -> Result<()>
+    where S: message::ReaderSegments
+{
+    let root: crate::any_pointer::Reader = message.get_root()?;
+    root.target_size()?;
+    traverse_pointer(root.reader)
+}
+
+fn traverse_pointer(pointer: PointerReader) -> Result<()> {
+    match pointer.get_pointer_type()? {
+        PointerType::Null | PointerType::Capability => Ok(()),
+        PointerType::Struct => traverse_struct(pointer.get_struct(None)?),
+        PointerType::List => {
+            let list = pointer.get_list_any_size(core::ptr::null())?;
+            if list.get_element_size() == ElementSize::Byte {
+                // Exercise NUL-termination and UTF-8 checking. Byte lists that are not text are
+                // perfectly valid, so errors here are not reported.
+                let _ = pointer.get_text(None);
+                pointer.get_data(None)?;
+            }
+            traverse_list(list)
+        }
+    }
+}
+
+fn traverse_struct(st: StructReader) -> Result<()> {
+    st.get_data_section_as_blob();
+    for idx in 0..st.get_pointer_section_size() {
+        traverse_pointer(st.get_pointer_field(idx as usize))?;
+    }
+    Ok(())
+}
+
+fn traverse_list(list: ListReader) -> Result<()> {
+    match list.get_element_size() {
+        // Void lists can claim to be arbitrarily long without containing any data.
+        ElementSize::Void => (),
+        ElementSize::Bit => for idx in 0..list.len() { <bool as PrimitiveElement>::get(&list, idx); },
+        ElementSize::Byte => for idx in 0..list.len() { <u8 as PrimitiveElement>::get(&list, idx); },
+        ElementSize::TwoBytes => for idx in 0..list.len() { <u16 as PrimitiveElement>::get(&list, idx); },
+        ElementSize::FourBytes => for idx in 0..list.len() { <u32 as PrimitiveElement>::get(&list, idx); },
+        ElementSize::EightBytes => for idx in 0..list.len() { <u64 as PrimitiveElement>::get(&list, idx); },
+        ElementSize::Pointer => {
+            for idx in 0..list.len() {
+                traverse_pointer(list.get_pointer_element(idx))?;
+            }
+        }
+        ElementSize::InlineComposite => {
+            for idx in 0..list.len() {
+                traverse_struct(list.get_struct_element(idx))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use quickcheck::{quickcheck, TestResult};
+
+    use crate::message;
+    use super::try_read;
+
+    #[test]
+    fn try_read_builder_output() {
+        let mut message = message::Builder::new_default();
+        {
+            let root: crate::any_pointer::Builder = message.init_root();
+            let mut list = root.initn_as::<crate::text_list::Builder>(2);
+            list.set(0, "hello");
+            list.set(1, "world");
+        }
+        let mut bytes = crate::serialize::write_message_to_words(&message);
+        super::try_read_unpacked(&bytes[..]).unwrap();
+
+        bytes.clear();
+        crate::serialize_packed::write_message(&mut bytes, &message).unwrap();
+        super::try_read_packed(&bytes[..]).unwrap();
+    }
+
+    #[test]
+    fn try_read_never_panics() {
+        fn prop(bytes: Vec<u8>) -> TestResult {
+            let _ = try_read(&bytes[..]);
+            TestResult::passed()
+        }
+        quickcheck(prop as fn(Vec<u8>) -> TestResult);
+    }
+}
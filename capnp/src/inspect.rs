@@ -0,0 +1,224 @@
+// Copyright (c) 2018 the capnproto-rust contributors
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+
+//! An annotated dump of the words of a message, for debugging hand-rolled encoders and
+//! corrupted streams.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::message::ReaderSegments;
+use crate::private::layout::ElementSize;
+use crate::raw::DecodedPointer;
+
+/// Guards against deeply nested or cyclic far pointers.
+const DEPTH_LIMIT: u32 = 64;
+
+struct Inspector<'a> {
+    segments: Vec<&'a [u8]>,
+    notes: Vec<Vec<Option<String>>>,
+}
+
+impl <'a> Inspector<'a> {
+    fn word(&self, segment_id: u32, index: i64) -> Option<[u8; 8]> {
+        let segment = self.segments.get(segment_id as usize)?;
+        if index < 0 { return None }
+        let bytes = segment.get(index as usize * 8..index as usize * 8 + 8)?;
+        let mut word = [0; 8];
+        word.copy_from_slice(bytes);
+        Some(word)
+    }
+
+    fn in_bounds(&self, segment_id: u32, start: i64, len: u64) -> bool {
+        match self.segments.get(segment_id as usize) {
+            Some(segment) => start >= 0 && start as u64 + len <= (segment.len() / 8) as u64,
+            None => false,
+        }
+    }
+
+    /// Attaches `note` to a word, unless it already has one. Returns whether it did.
+    fn note(&mut self, segment_id: u32, index: i64, note: String) -> bool {
+        let slot = &mut self.notes[segment_id as usize][index as usize];
+        if slot.is_some() {
+            false
+        } else {
+            *slot = Some(note);
+            true
+        }
+    }
+
+    fn visit_pointer(&mut self, segment_id: u32, index: i64, label: &str, depth: u32) {
+        let word = match self.word(segment_id, index) {
+            Some(w) => w,
+            None => return,
+        };
+        let pointer = DecodedPointer::decode(word);
+        if !self.note(segment_id, index, format!("{}: {}", label, pointer)) {
+            return;
+        }
+        if depth >= DEPTH_LIMIT {
+            self.notes[segment_id as usize][index as usize].as_mut().unwrap().push_str(" (too deep)");
+            return;
+        }
+        match pointer {
+            DecodedPointer::Struct { offset, .. } | DecodedPointer::List { offset, .. } => {
+                self.visit_object(segment_id, index + 1 + offset as i64, pointer, segment_id, index, depth);
+            }
+            DecodedPointer::Far { double_far: false, position, segment_id: pad_segment } => {
+                self.visit_pointer(pad_segment, position as i64, "landing pad", depth + 1);
+                if !self.in_bounds(pad_segment, position as i64, 1) {
+                    self.mark_bad(segment_id, index, "out of bounds");
+                }
+            }
+            DecodedPointer::Far { double_far: true, position, segment_id: pad_segment } => {
+                if !self.in_bounds(pad_segment, position as i64, 2) {
+                    self.mark_bad(segment_id, index, "out of bounds");
+                    return;
+                }
+                let pad = DecodedPointer::decode(self.word(pad_segment, position as i64).unwrap());
+                let tag = DecodedPointer::decode(self.word(pad_segment, position as i64 + 1).unwrap());
+                self.note(pad_segment, position as i64, format!("double-far landing pad: {}", pad));
+                self.note(pad_segment, position as i64 + 1, format!("tag: {}", tag));
+                match pad {
+                    DecodedPointer::Far { double_far: false, position: content, segment_id: content_segment } => {
+                        self.visit_object(content_segment, content as i64, tag,
+                                          pad_segment, position as i64 + 1, depth + 1);
+                    }
+                    _ => self.mark_bad(pad_segment, position as i64, "expected a single-far pointer"),
+                }
+            }
+            DecodedPointer::Null | DecodedPointer::Capability { .. } | DecodedPointer::Unknown(_) => {}
+        }
+    }
+
+    fn mark_bad(&mut self, segment_id: u32, index: i64, problem: &str) {
+        if let Some(note) = self.notes[segment_id as usize][index as usize].as_mut() {
+            note.push_str(&format!(" ({})", problem));
+        }
+    }
+
+    /// Visits the object starting at word `start` described by `pointer`, which lives
+    /// at `pointer_index` of `pointer_segment`.
+    fn visit_object(&mut self, segment_id: u32, start: i64, pointer: DecodedPointer,
+                    pointer_segment: u32, pointer_index: i64, depth: u32) {
+        match pointer {
+            DecodedPointer::Struct { data_words, pointer_count, .. } => {
+                let size = data_words as u64 + pointer_count as u64;
+                if !self.in_bounds(segment_id, start, size) {
+                    self.mark_bad(pointer_segment, pointer_index, "out of bounds");
+                    return;
+                }
+                self.visit_struct(segment_id, start, data_words, pointer_count, "", depth);
+            }
+            DecodedPointer::List { element_size: ElementSize::InlineComposite, element_count: word_count, .. } => {
+                if !self.in_bounds(segment_id, start, word_count as u64 + 1) {
+                    self.mark_bad(pointer_segment, pointer_index, "out of bounds");
+                    return;
+                }
+                let tag = DecodedPointer::decode(self.word(segment_id, start).unwrap());
+                let word = self.word(segment_id, start).unwrap();
+                let element_count = u32::from_le_bytes([word[0], word[1], word[2], word[3]]) >> 2;
+                if !self.note(segment_id, start, format!("tag: {} elements, {}", element_count, tag)) {
+                    return;
+                }
+                if let DecodedPointer::Struct { data_words, pointer_count, .. } = tag {
+                    let step = data_words as u64 + pointer_count as u64;
+                    if step * element_count as u64 > word_count as u64 {
+                        self.mark_bad(segment_id, start, "elements overrun the list");
+                        return;
+                    }
+                    for i in 0..element_count as i64 {
+                        self.visit_struct(segment_id, start + 1 + i * step as i64, data_words, pointer_count,
+                                          &format!("element {} ", i), depth);
+                    }
+                }
+            }
+            DecodedPointer::List { element_size, element_count, .. } => {
+                let bits = match element_size {
+                    ElementSize::Void => 0,
+                    ElementSize::Bit => 1,
+                    ElementSize::Byte => 8,
+                    ElementSize::TwoBytes => 16,
+                    ElementSize::FourBytes => 32,
+                    ElementSize::EightBytes | ElementSize::Pointer => 64,
+                    ElementSize::InlineComposite => unreachable!(),
+                };
+                let word_count = (element_count as u64 * bits + 63) / 64;
+                if !self.in_bounds(segment_id, start, word_count) {
+                    self.mark_bad(pointer_segment, pointer_index, "out of bounds");
+                    return;
+                }
+                for i in 0..word_count as i64 {
+                    if element_size == ElementSize::Pointer {
+                        self.visit_pointer(segment_id, start + i, &format!("element {}", i), depth + 1);
+                    } else {
+                        self.note(segment_id, start + i, String::from("list data"));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn visit_struct(&mut self, segment_id: u32, start: i64, data_words: u16, pointer_count: u16,
+                    prefix: &str, depth: u32) {
+        for i in 0..data_words as i64 {
+            self.note(segment_id, start + i, format!("{}data word {}", prefix, i));
+        }
+        for i in 0..pointer_count as i64 {
+            self.visit_pointer(segment_id, start + data_words as i64 + i,
+                               &format!("{}pointer {}", prefix, i), depth + 1);
+        }
+    }
+}
+
+/// Produces an annotated dump of a message: each segment's words in hex, with the root
+/// pointer and every pointer reachable from it decoded, the words of each object labeled,
+/// and words that no pointer reaches flagged as unreachable. Corrupt pointers are flagged
+/// rather than followed, so this works on messages that fail to read.
+pub fn inspect<S: ReaderSegments>(segments: &S) -> String {
+    let mut inspector = Inspector { segments: Vec::new(), notes: Vec::new() };
+    for i in 0..segments.len() {
+        let segment = segments.get_segment(i as u32).unwrap_or(&[]);
+        inspector.segments.push(segment);
+        inspector.notes.push(vec![None; segment.len() / 8]);
+    }
+    if !inspector.segments.is_empty() {
+        inspector.visit_pointer(0, 0, "root", 0);
+    }
+
+    let mut result = String::new();
+    for (segment_id, segment) in inspector.segments.iter().enumerate() {
+        let _ = writeln!(result, "segment {} ({} words)", segment_id, segment.len() / 8);
+        for (index, word) in segment.chunks(8).enumerate() {
+            let _ = write!(result, "  {:04x}:", index);
+            for byte in word {
+                let _ = write!(result, " {:02x}", byte);
+            }
+            match inspector.notes[segment_id].get(index) {
+                Some(Some(note)) => { let _ = writeln!(result, "  {}", note); }
+                _ => { let _ = writeln!(result, "  unreachable"); }
+            }
+        }
+    }
+    result
+}
@@ -45,10 +45,138 @@ pub trait Write {
     fn write_all(&mut self, buf: &[u8]) -> Result<()>;
 }
 
+/// The default buffer size of `BufferedInputStream` and `BufferedOutputStream`.
+pub const DEFAULT_BUFFER_SIZE: usize = 8192;
+
+/// Adds buffering to a `Read`, like kj's `BufferedInputStreamWrapper`. Since it implements
+/// `BufRead`, it can be passed to `serialize_packed::read_message()`, which then decodes
+/// straight out of the buffer instead of reading a few bytes at a time; this works without std.
+///
+/// Reads that are at least as large as the buffer bypass it while it is empty, so that the
+/// body of a message read by `serialize::read_message()` is still copied only once.
+pub struct BufferedInputStream<R> where R: Read {
+    inner: R,
+    buf: alloc::vec::Vec<u8>,
+    pos: usize,
+    end: usize,
+}
+
+impl <R> BufferedInputStream<R> where R: Read {
+    pub fn new(inner: R) -> BufferedInputStream<R> {
+        BufferedInputStream::with_capacity(DEFAULT_BUFFER_SIZE, inner)
+    }
+
+    pub fn with_capacity(capacity: usize, inner: R) -> BufferedInputStream<R> {
+        BufferedInputStream { inner: inner, buf: alloc::vec![0; capacity], pos: 0, end: 0 }
+    }
+
+    /// Gets the buffered bytes that have not been consumed yet, reading more from the underlying
+    /// stream first if there are none. Returns an empty slice at the end of the stream.
+    pub fn try_get_read_buffer(&mut self) -> Result<&[u8]> {
+        if self.pos == self.end {
+            self.end = self.inner.read(&mut self.buf)?;
+            self.pos = 0;
+        }
+        Ok(&self.buf[self.pos..self.end])
+    }
+
+    /// Discards any buffered bytes and returns the underlying stream.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn read_buffered(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.pos == self.end && buf.len() >= self.buf.len() {
+            return self.inner.read(buf);
+        }
+        let available = self.try_get_read_buffer()?;
+        let amt = core::cmp::min(available.len(), buf.len());
+        buf[..amt].copy_from_slice(&available[..amt]);
+        self.pos += amt;
+        Ok(amt)
+    }
+
+    fn consume_buffered(&mut self, amt: usize) {
+        self.pos = core::cmp::min(self.pos + amt, self.end);
+    }
+}
+
+/// Adds buffering to a `Write`, like kj's `BufferedOutputStreamWrapper`, so that the many small
+/// writes of a segment table and of short segments become few writes to the underlying stream.
+/// `get_write_buffer()` lets an encoder write into the buffer directly.
+///
+/// Buffered bytes are written out by `flush()`, by `into_inner()`, and, ignoring any error, when
+/// the stream is dropped. Writes that do not fit into the buffer bypass it after flushing it.
+pub struct BufferedOutputStream<W> where W: Write {
+    inner: Option<W>,
+    buf: alloc::vec::Vec<u8>,
+    len: usize,
+}
+
+impl <W> BufferedOutputStream<W> where W: Write {
+    pub fn new(inner: W) -> BufferedOutputStream<W> {
+        BufferedOutputStream::with_capacity(DEFAULT_BUFFER_SIZE, inner)
+    }
+
+    pub fn with_capacity(capacity: usize, inner: W) -> BufferedOutputStream<W> {
+        BufferedOutputStream { inner: Some(inner), buf: alloc::vec![0; capacity], len: 0 }
+    }
+
+    /// Gets the unused part of the buffer, flushing it first if it is full. Bytes written into
+    /// it are added to the stream by calling `advance()`.
+    pub fn get_write_buffer(&mut self) -> Result<&mut [u8]> {
+        if self.len == self.buf.len() {
+            self.flush()?;
+        }
+        Ok(&mut self.buf[self.len..])
+    }
+
+    /// Adds the first `amt` bytes of the slice returned by `get_write_buffer()` to the stream.
+    pub fn advance(&mut self, amt: usize) {
+        assert!(self.len + amt <= self.buf.len(), "advanced past the end of the write buffer");
+        self.len += amt;
+    }
+
+    /// Writes the buffered bytes to the underlying stream.
+    pub fn flush(&mut self) -> Result<()> {
+        if self.len > 0 {
+            self.inner.as_mut().expect("stream was taken").write_all(&self.buf[..self.len])?;
+            self.len = 0;
+        }
+        Ok(())
+    }
+
+    /// Flushes the buffer and returns the underlying stream.
+    pub fn into_inner(mut self) -> Result<W> {
+        self.flush()?;
+        Ok(self.inner.take().expect("stream was taken"))
+    }
+
+    fn write_buffered(&mut self, buf: &[u8]) -> Result<()> {
+        if buf.len() > self.buf.len() - self.len {
+            self.flush()?;
+            if buf.len() >= self.buf.len() {
+                return self.inner.as_mut().expect("stream was taken").write_all(buf);
+            }
+        }
+        self.buf[self.len..(self.len + buf.len())].copy_from_slice(buf);
+        self.len += buf.len();
+        Ok(())
+    }
+}
+
+impl <W> Drop for BufferedOutputStream<W> where W: Write {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.flush();
+        }
+    }
+}
+
 #[cfg(feature="std")]
 mod std_impls {
     use crate::{Result};
-    use crate::io::{Read, BufRead, Write};
+    use crate::io::{Read, BufRead, Write, BufferedInputStream, BufferedOutputStream};
 
     impl <R> Read for R where R: std::io::Read {
         fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
@@ -77,13 +205,41 @@ mod std_impls {
             Ok(())
         }
     }
+
+    // With std, the buffered streams implement the std traits, and so get ours through the
+    // impls above.
+
+    impl <R> std::io::Read for BufferedInputStream<R> where R: Read {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.read_buffered(buf).map_err(std::io::Error::from)
+        }
+    }
+
+    impl <R> std::io::BufRead for BufferedInputStream<R> where R: Read {
+        fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+            self.try_get_read_buffer().map_err(std::io::Error::from)
+        }
+        fn consume(&mut self, amt: usize) {
+            self.consume_buffered(amt)
+        }
+    }
+
+    impl <W> std::io::Write for BufferedOutputStream<W> where W: Write {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.write_buffered(buf).map_err(std::io::Error::from)?;
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            BufferedOutputStream::flush(self).map_err(std::io::Error::from)
+        }
+    }
 }
 
 #[cfg(not(feature="std"))]
 mod no_std_impls {
     use alloc::string::ToString;
     use crate::{Error, Result};
-    use crate::io::{Read, BufRead, Write};
+    use crate::io::{Read, BufRead, Write, BufferedInputStream, BufferedOutputStream};
 
     impl <'a> Write for &'a mut [u8] {
         fn write_all(&mut self, buf: &[u8]) -> Result<()> {
@@ -137,6 +293,27 @@ mod no_std_impls {
         }
     }
 
+    impl <R> Read for BufferedInputStream<R> where R: Read {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            self.read_buffered(buf)
+        }
+    }
+
+    impl <R> BufRead for BufferedInputStream<R> where R: Read {
+        fn fill_buf(&mut self) -> Result<&[u8]> {
+            self.try_get_read_buffer()
+        }
+        fn consume(&mut self, amt: usize) {
+            self.consume_buffered(amt)
+        }
+    }
+
+    impl <W> Write for BufferedOutputStream<W> where W: Write {
+        fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+            self.write_buffered(buf)
+        }
+    }
+
     impl <R: ?Sized> BufRead for &mut R where R: BufRead {
         fn fill_buf(&mut self) -> Result<&[u8]> {
             (**self).fill_buf()
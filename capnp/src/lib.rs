@@ -25,6 +25,12 @@
 //! [Cap'n Proto](https://capnproto.org) messages in Rust. It is intended to
 //! be used in conjunction with code generated by the
 //! [capnpc-rust](https://github.com/capnproto/capnproto-rust/capnpc) crate.
+//!
+//! Apart from `message_log`, which needs the "std" feature, nothing in the crate depends on a
+//! filesystem or on native I/O, so it also builds for `wasm32-unknown-unknown`, with or without
+//! the "std" feature. There, messages are read from
+//! and written to byte buffers with `serialize::read_message_from_flat_slice()`,
+//! `serialize::write_message_to_words()` and their `serialize_packed` equivalents.
 
 #![cfg_attr(feature = "rpc_try", feature(try_trait))]
 #![cfg_attr(not(feature = "std"), no_std)]
@@ -40,19 +46,31 @@ pub mod constant;
 pub mod data;
 pub mod data_list;
 pub mod enum_list;
+#[cfg(feature="ffi")]
+pub mod ffi;
+pub mod fields;
+pub mod fuzz;
+mod inspect;
 pub mod io;
 pub mod list_list;
 pub mod message;
+#[cfg(feature="std")]
+pub mod message_log;
+pub mod ordered_key;
 pub mod primitive_list;
 pub mod private;
 pub mod raw;
 pub mod serialize;
+pub mod serialize_indexed;
 pub mod serialize_packed;
+pub mod stringify;
 pub mod struct_list;
 pub mod text;
 pub mod text_list;
 pub mod traits;
 
+pub use crate::inspect::inspect;
+
 use alloc::string::String;
 use alloc::vec::Vec;
 
@@ -95,6 +113,49 @@ impl Word {
             core::slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, words.len() * 8)
         }
     }
+
+    /// Returns true if `bytes` starts on an 8-byte boundary.
+    pub fn is_aligned(bytes: &[u8]) -> bool {
+        bytes.as_ptr() as usize % core::mem::align_of::<Word>() == 0
+    }
+
+    /// Reinterprets `bytes` as a slice of words, without copying. Returns an error if `bytes`
+    /// is not 8-byte aligned or if its length is not a multiple of 8.
+    pub fn try_bytes_to_words<'a>(bytes: &'a [u8]) -> Result<&'a [Word]> {
+        Word::check_bytes_to_words(bytes)?;
+        unsafe {
+            Ok(core::slice::from_raw_parts(bytes.as_ptr() as *const Word, bytes.len() / 8))
+        }
+    }
+
+    /// Like `try_bytes_to_words()`, but for mutable slices.
+    pub fn try_bytes_to_words_mut<'a>(bytes: &'a mut [u8]) -> Result<&'a mut [Word]> {
+        Word::check_bytes_to_words(bytes)?;
+        unsafe {
+            Ok(core::slice::from_raw_parts_mut(bytes.as_mut_ptr() as *mut Word, bytes.len() / 8))
+        }
+    }
+
+    /// Copies `bytes` into a newly-allocated, 8-byte aligned vector of words. The last word is
+    /// zero-padded if the length of `bytes` is not a multiple of 8.
+    pub fn words_from_bytes(bytes: &[u8]) -> Vec<Word> {
+        let mut result = Word::allocate_zeroed_vec((bytes.len() + 7) / 8);
+        Word::words_to_bytes_mut(&mut result[..])[..bytes.len()].copy_from_slice(bytes);
+        result
+    }
+
+    fn check_bytes_to_words(bytes: &[u8]) -> Result<()> {
+        if !Word::is_aligned(bytes) {
+            Err(Error::failed(
+                format!("Cannot interpret bytes as words: slice is not 8-byte aligned.")))
+        } else if bytes.len() % 8 != 0 {
+            Err(Error::failed(
+                format!("Cannot interpret bytes as words: length {} is not a multiple of 8.",
+                        bytes.len())))
+        } else {
+            Ok(())
+        }
+    }
 }
 
 #[cfg(any(feature="quickcheck", test))]
@@ -149,6 +210,11 @@ impl ::std::error::Error for NotInSchema {
 pub type Result<T> = ::core::result::Result<T, Error>;
 
 /// Describes an arbitrary error that prevented an operation from completing.
+///
+/// This is the error type of the whole crate: decoding errors, errors from reading and writing
+/// streams (converted from `std::io::Error` with the "std" feature), and errors returned by
+/// remote calls all end up here, distinguished by their `kind`. Errors that carry more detail,
+/// such as `serialize::FramingError`, convert into it.
 #[derive(Debug, Clone)]
 pub struct Error {
     /// The general kind of the error. Code that decides how to respond to an error
@@ -196,6 +262,11 @@ impl Error {
     pub fn unimplemented(description: String) -> Error {
         Error { description: description, kind: ErrorKind::Unimplemented }
     }
+
+    /// The general kind of the error.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
 }
 
 #[cfg(feature="std")]
@@ -215,6 +286,21 @@ impl core::convert::From<::std::io::Error> for Error {
     }
 }
 
+/// The inverse of the conversion above, for code that implements `std::io` traits on top of
+/// Cap'n Proto operations.
+#[cfg(feature="std")]
+impl core::convert::From<Error> for ::std::io::Error {
+    fn from(err: Error) -> ::std::io::Error {
+        use std::io;
+        let kind = match err.kind {
+            ErrorKind::Overloaded => io::ErrorKind::TimedOut,
+            ErrorKind::Disconnected => io::ErrorKind::ConnectionReset,
+            ErrorKind::Failed | ErrorKind::Unimplemented => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, err.description)
+    }
+}
+
 impl core::convert::From<alloc::string::FromUtf8Error> for Error {
     fn from(err: alloc::string::FromUtf8Error) -> Error {
         Error::failed(format!("{}", err))
@@ -282,3 +368,36 @@ impl<'s> message::ReaderSegments for OutputSegments<'s> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::Word;
+
+    #[test]
+    fn try_bytes_to_words() {
+        let mut words = vec![crate::word(1, 2, 3, 4, 5, 6, 7, 8), crate::word(9, 0, 0, 0, 0, 0, 0, 0)];
+        {
+            let bytes = Word::words_to_bytes(&words[..]);
+            assert!(Word::is_aligned(bytes));
+            assert_eq!(&words[..], Word::try_bytes_to_words(bytes).unwrap());
+            assert!(Word::try_bytes_to_words(&bytes[1..9]).is_err());
+            assert!(Word::try_bytes_to_words(&bytes[..12]).is_err());
+        }
+        {
+            let bytes = Word::words_to_bytes_mut(&mut words[..]);
+            Word::try_bytes_to_words_mut(bytes).unwrap()[1] = crate::word(0, 0, 0, 0, 0, 0, 0, 10);
+        }
+        assert_eq!(words[1], crate::word(0, 0, 0, 0, 0, 0, 0, 10));
+    }
+
+    #[test]
+    fn words_from_unaligned_bytes() {
+        let bytes: Vec<u8> = (0..20).collect();
+        let words = Word::words_from_bytes(&bytes[1..]);
+        assert_eq!(3, words.len());
+        assert_eq!(&bytes[1..], &Word::words_to_bytes(&words[..])[..19]);
+        assert_eq!(0, Word::words_to_bytes(&words[..])[19]);
+    }
+}
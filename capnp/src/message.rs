@@ -21,6 +21,7 @@
 
 //! Untyped root container for a Cap'n Proto value.
 
+use alloc::string::ToString;
 use alloc::vec::Vec;
 use core::convert::From;
 
@@ -29,7 +30,7 @@ use crate::private::arena::{BuilderArenaImpl, ReaderArenaImpl, BuilderArena, Rea
 use crate::private::layout;
 use crate::private::units::BYTES_PER_WORD;
 use crate::traits::{FromPointerReader, FromPointerBuilder, SetPointerBuilder, Owned};
-use crate::{OutputSegments, Result};
+use crate::{Error, OutputSegments, Result};
 
 /// Options controlling how data is read.
 #[derive(Clone, Copy, Debug)]
@@ -61,10 +62,47 @@ pub struct ReaderOptions {
     /// being very large. The default limit of 64 is probably low enough to prevent any chance of
     /// stack overflow, yet high enough that it is never a problem in practice.
     pub nesting_limit: i32,
+
+    /// What to do with text that is not valid UTF-8. The raw bytes of text are always available
+    /// through `get_text_bytes()`, whatever the policy.
+    pub utf8_policy: Utf8Policy,
+
+    /// Limits how many segments a message read from a stream may have. Legitimate messages rarely
+    /// have more than a handful, while a long segment table costs memory before any of the
+    /// message has been read. The default of 511 matches the C++ implementation.
+    pub segment_count_limit: u32,
+
+    /// What to do when a pointer can't be decoded, e.g. because it is out of bounds or exceeds
+    /// the traversal or nesting limit.
+    pub pointer_error_policy: PointerErrorPolicy,
+}
+
+/// How reading text that is not valid UTF-8 is handled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Utf8Policy {
+    /// Reading the text returns an error. This is the default.
+    Strict,
+
+    /// Reading the text returns the part of it before the first invalid sequence. Useful for
+    /// pipelines that would rather lose part of a string than fail on it.
+    Lenient,
+}
+
+/// How a pointer that can't be decoded is handled when reading a struct, list, text or data field.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PointerErrorPolicy {
+    /// Reading the field returns an error. This is the default.
+    Strict,
+
+    /// Reading the field returns its default value, as if the pointer were null. Useful for
+    /// salvaging what can be read from partially corrupted messages. Capability pointers
+    /// are unaffected.
+    Lenient,
 }
 
 pub const DEFAULT_READER_OPTIONS: ReaderOptions =
-    ReaderOptions { traversal_limit_in_words: 8 * 1024 * 1024, nesting_limit: 64 };
+    ReaderOptions { traversal_limit_in_words: 8 * 1024 * 1024, nesting_limit: 64, utf8_policy: Utf8Policy::Strict,
+                    segment_count_limit: 511, pointer_error_policy: PointerErrorPolicy::Strict };
 
 
 impl Default for ReaderOptions {
@@ -85,6 +123,21 @@ impl ReaderOptions {
         self.traversal_limit_in_words = value;
         self
     }
+
+    pub fn utf8_policy<'a>(&'a mut self, value: Utf8Policy) -> &'a mut ReaderOptions {
+        self.utf8_policy = value;
+        self
+    }
+
+    pub fn segment_count_limit<'a>(&'a mut self, value: u32) -> &'a mut ReaderOptions {
+        self.segment_count_limit = value;
+        self
+    }
+
+    pub fn pointer_error_policy<'a>(&'a mut self, value: PointerErrorPolicy) -> &'a mut ReaderOptions {
+        self.pointer_error_policy = value;
+        self
+    }
 }
 
 /// An object that manages the buffers underlying a Cap'n Proto message reader.
@@ -150,6 +203,96 @@ impl <'b> ReaderSegments for [&'b [u8]] {
     }
 }
 
+impl <'b> ReaderSegments for [&'b [crate::Word]] {
+    fn get_segment<'a>(&'a self, id: u32) -> Option<&'a [u8]> {
+        self.get(id as usize).map(|slice| crate::Word::words_to_bytes(slice))
+    }
+
+    fn len(&self) -> usize {
+        self.len()
+    }
+}
+
+/// A segment in memory that is managed by the caller, such as a slot of a ring buffer that a
+/// networking stack reads into, or a buffer from a foreign allocator. The memory belongs to the
+/// value: dropping it hands the memory back, so a message read from `ExternalSegments` keeps
+/// its segments for exactly as long as the message is alive.
+pub trait ExternalSegment {
+    /// Gets the segment's bytes, which must be the same every time this is called.
+    fn as_bytes(&self) -> &[u8];
+}
+
+impl ExternalSegment for Vec<crate::Word> {
+    fn as_bytes(&self) -> &[u8] {
+        crate::Word::words_to_bytes(self)
+    }
+}
+
+impl ExternalSegment for alloc::boxed::Box<[crate::Word]> {
+    fn as_bytes(&self) -> &[u8] {
+        crate::Word::words_to_bytes(self)
+    }
+}
+
+/// A segment at a raw address, with a function that releases the memory when the segment is
+/// dropped.
+pub struct RawSegment {
+    ptr: *const u8,
+    len: usize,
+    release: Option<alloc::boxed::Box<dyn FnOnce()>>,
+}
+
+impl RawSegment {
+    /// # Safety
+    ///
+    /// `ptr` must point to `len` bytes that stay valid and unmodified until `release` is called.
+    pub unsafe fn new<F>(ptr: *const u8, len: usize, release: F) -> RawSegment
+        where F: FnOnce() + 'static
+    {
+        RawSegment { ptr: ptr, len: len, release: Some(alloc::boxed::Box::new(release)) }
+    }
+}
+
+impl ExternalSegment for RawSegment {
+    fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for RawSegment {
+    fn drop(&mut self) {
+        if let Some(release) = self.release.take() {
+            release();
+        }
+    }
+}
+
+/// Segments that are each owned by an `ExternalSegment`, to be read in place.
+pub struct ExternalSegments<T> where T: ExternalSegment {
+    segments: Vec<T>,
+}
+
+impl <T> ExternalSegments<T> where T: ExternalSegment {
+    pub fn new(segments: Vec<T>) -> ExternalSegments<T> {
+        ExternalSegments { segments: segments }
+    }
+
+    /// Gives the segments back, for example to reuse the buffers they own.
+    pub fn into_inner(self) -> Vec<T> {
+        self.segments
+    }
+}
+
+impl <T> ReaderSegments for ExternalSegments<T> where T: ExternalSegment {
+    fn get_segment<'a>(&'a self, id: u32) -> Option<&'a [u8]> {
+        self.segments.get(id as usize).map(|segment| segment.as_bytes())
+    }
+
+    fn len(&self) -> usize {
+        self.segments.len()
+    }
+}
+
 /// A container used to read a message.
 pub struct Reader<S> where S: ReaderSegments {
     arena: ReaderArenaImpl<S>,
@@ -176,6 +319,11 @@ impl <S> Reader<S> where S: ReaderSegments {
         self.get_root_internal()?.get_as()
     }
 
+    /// Returns the segments that the message is being read from.
+    pub fn get_segments(&self) -> &S {
+        self.arena.get_segments()
+    }
+
     pub fn into_segments(self) -> S {
         self.arena.into_segments()
     }
@@ -207,6 +355,9 @@ impl <S> Reader<S> where S: ReaderSegments {
     pub fn canonicalize(&self) -> Result<Vec<crate::Word>> {
         let root = self.get_root_internal()?;
         let size = root.target_size()?.word_count + 1;
+        if size > u32::max_value() as u64 {
+            return Err(Error::failed(format!("Message is too large to canonicalize: {} words", size)));
+        }
         let mut message = Builder::new(HeapAllocator::new().first_segment_words(size as u32));
         message.set_root_canonical(root)?;
         let output_segments = message.get_segments_for_output();
@@ -290,6 +441,24 @@ pub unsafe trait Allocator {
     fn deallocate_segment(&mut self, ptr: *mut u8, word_size: u32, words_used: u32);
 }
 
+/// Memory usage statistics for a message `Builder`, as returned by `Builder::stats()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BuilderStats {
+    /// Number of segments allocated so far.
+    pub segment_count: usize,
+
+    /// Total capacity of all segments, in words.
+    pub words_allocated: u64,
+
+    /// Words handed out to objects in the message. This is what gets serialized.
+    pub words_used: u64,
+
+    /// Words, included in `words_used`, that were occupied by objects that became unreachable
+    /// when the pointer to them was overwritten or cleared, e.g. by calling `set_text()` twice
+    /// on the same field. Cap'n Proto zeroes such objects but cannot reclaim their space.
+    pub words_wasted: u64,
+}
+
 /// A container used to build a message.
 pub struct Builder<A> where A: Allocator {
     arena: BuilderArenaImpl<A>,
@@ -315,8 +484,13 @@ impl <A> Builder<A> where A: Allocator {
     }
 
     fn get_root_internal<'a>(&'a mut self) -> any_pointer::Builder<'a> {
+        self.get_root_internal_with_size_hint(0)
+    }
+
+    fn get_root_internal_with_size_hint<'a>(&'a mut self, size_hint: u32) -> any_pointer::Builder<'a> {
         if self.arena.len() == 0 {
-            self.arena.allocate_segment(1).expect("allocate root pointer");
+            // One word for the root pointer, plus whatever the caller expects to put behind it.
+            self.arena.allocate_segment(size_hint.saturating_add(1)).expect("allocate root pointer");
             self.arena.allocate(0, 1).expect("allocate root pointer");
         }
         let (seg_start, _seg_len) = self.arena.get_segment_mut(0);
@@ -333,6 +507,16 @@ impl <A> Builder<A> where A: Allocator {
         root.init_as()
     }
 
+    /// Like `init_root()`, but if this is the first action taken on this `Builder`, asks the
+    /// allocator for a first segment with room for at least `words` words besides the root
+    /// pointer. A message that ends up no larger than that then occupies a single segment,
+    /// no matter how the allocator would otherwise have sized its segments. The hint is
+    /// ignored if the message already has a segment.
+    pub fn init_root_with_size_hint<'a, T: FromPointerBuilder<'a>>(&'a mut self, words: u32) -> T {
+        let root = self.get_root_internal_with_size_hint(words);
+        root.init_as()
+    }
+
     /// Gets the root, interpreting it as the given type.
     pub fn get_root<'a, T: FromPointerBuilder<'a>>(&'a mut self) -> Result<T> {
         let root = self.get_root_internal();
@@ -368,7 +552,10 @@ impl <A> Builder<A> where A: Allocator {
         let (seg_start, _seg_len) = self.arena.get_segment_mut(0);
         let pointer = layout::PointerBuilder::get_root(&self.arena, 0, seg_start);
         SetPointerBuilder::set_pointer_builder(pointer, value, true)?;
-        assert_eq!(self.get_segments_for_output().len(), 1);
+        if self.get_segments_for_output().len() != 1 {
+            return Err(Error::failed(
+                "Canonical message does not fit in the first segment. Allocate a larger first segment.".to_string()));
+        }
         Ok(())
     }
 
@@ -376,10 +563,19 @@ impl <A> Builder<A> where A: Allocator {
         self.arena.get_segments_for_output()
     }
 
+    /// Reports how much memory the message is using, and how much of that is wasted space
+    /// left behind by overwritten objects.
+    pub fn stats(&self) -> BuilderStats {
+        self.arena.get_stats()
+    }
+
     pub fn into_reader(self) -> Reader<Builder<A>> {
         Reader::new(self, ReaderOptions {
             traversal_limit_in_words: u64::max_value(),
-            nesting_limit: i32::max_value()
+            nesting_limit: i32::max_value(),
+            utf8_policy: Utf8Policy::Strict,
+            segment_count_limit: u32::max_value(),
+            pointer_error_policy: PointerErrorPolicy::Strict,
         })
     }
 
@@ -414,6 +610,11 @@ pub enum AllocationStrategy {
 pub const SUGGESTED_FIRST_SEGMENT_WORDS: u32 = 1024;
 pub const SUGGESTED_ALLOCATION_STRATEGY: AllocationStrategy = AllocationStrategy::GrowHeuristically;
 
+/// The largest segment that `HeapAllocator` will grow to when using
+/// `AllocationStrategy::GrowHeuristically`. Larger segments are still allocated on demand
+/// if `minimum_size` requires it.
+pub const MAX_SEGMENT_WORDS: u32 = 1 << 29;
+
 impl HeapAllocator {
     pub fn new() -> HeapAllocator {
         HeapAllocator { next_size: SUGGESTED_FIRST_SEGMENT_WORDS,
@@ -425,20 +626,48 @@ impl HeapAllocator {
         self
     }
 
+    /// Creates an allocator whose first segment holds a message of `words` words, which is
+    /// the size that `Builder::stats().words_used` reports for a finished message, so that such
+    /// a message needs no further segments.
+    pub fn expected_size(words: u32) -> HeapAllocator {
+        HeapAllocator::new().first_segment_words(core::cmp::max(words, 1))
+    }
+
+    /// Returns the size, in words, of the next segment that `allocate_segment()` will allocate,
+    /// unless it is asked for more. Before the first allocation, this is the size of the first
+    /// segment: `SUGGESTED_FIRST_SEGMENT_WORDS` unless set by `first_segment_words()`.
+    pub fn next_segment_words(&self) -> u32 {
+        self.next_size
+    }
+
     pub fn allocation_strategy(mut self, value : AllocationStrategy) -> HeapAllocator {
         self.allocation_strategy = value;
         self
     }
+
+    /// Configures the allocator so that every object gets a segment of its own, exactly as
+    /// large as the object. As a result, every pointer in a message built with this allocator
+    /// is a far pointer. This is wasteful, but useful for testing code that needs to handle
+    /// multi-segment messages.
+    pub fn small_segments(self) -> HeapAllocator {
+        self.first_segment_words(1).allocation_strategy(AllocationStrategy::FixedSize)
+    }
 }
 
 unsafe impl Allocator for HeapAllocator {
     fn allocate_segment(&mut self, minimum_size: u32) -> (*mut u8, u32) {
         let size = core::cmp::max(minimum_size, self.next_size);
-        let ptr = unsafe {
-            alloc::alloc::alloc_zeroed(alloc::alloc::Layout::from_size_align(size as usize * BYTES_PER_WORD, 8).unwrap())
-        };
+        let byte_size = (size as usize).checked_mul(BYTES_PER_WORD)
+            .expect("segment size overflows usize");
+        let layout = alloc::alloc::Layout::from_size_align(byte_size, 8).unwrap();
+        let ptr = unsafe { alloc::alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            alloc::alloc::handle_alloc_error(layout);
+        }
         match self.allocation_strategy {
-            AllocationStrategy::GrowHeuristically => { self.next_size += size; }
+            AllocationStrategy::GrowHeuristically => {
+                self.next_size = core::cmp::min(self.next_size.saturating_add(size), MAX_SEGMENT_WORDS);
+            }
             _ => { }
         }
         (ptr, size as u32)
@@ -457,6 +686,12 @@ impl Builder<HeapAllocator> {
     pub fn new_default() -> Builder<HeapAllocator> {
         Builder::new(HeapAllocator::new())
     }
+
+    /// Creates a `Builder` for messages expected to be `words` words long in total, root pointer
+    /// included. See `HeapAllocator::expected_size()`.
+    pub fn with_expected_size(words: u32) -> Builder<HeapAllocator> {
+        Builder::new(HeapAllocator::expected_size(words))
+    }
 }
 
 /// An Allocator whose first segment is a backed by a user-provided buffer.
@@ -475,7 +710,7 @@ impl <'a> ScratchSpaceHeapAllocator<'a> {
     pub fn new(scratch_space: &'a mut [u8]) -> ScratchSpaceHeapAllocator<'a> {
         #[cfg(not(feature = "unaligned"))]
         {
-            if scratch_space.as_ptr() as usize % BYTES_PER_WORD != 0 {
+            if !crate::Word::is_aligned(scratch_space) {
                 panic!("Scratch space must be 8-byte aligned, or you must enable the \"unaligned\" \
                         feature in the capnp crate");
             }
@@ -502,9 +737,11 @@ impl <'a> ScratchSpaceHeapAllocator<'a> {
 
 unsafe impl <'a> Allocator for ScratchSpaceHeapAllocator<'a> {
     fn allocate_segment(&mut self, minimum_size: u32) -> (*mut u8, u32) {
-        if (minimum_size as usize) < (self.scratch_space.len() / BYTES_PER_WORD) && !self.scratch_space_allocated {
+        let scratch_words = core::cmp::min(self.scratch_space.len() / BYTES_PER_WORD,
+                                           MAX_SEGMENT_WORDS as usize);
+        if (minimum_size as usize) < scratch_words && !self.scratch_space_allocated {
             self.scratch_space_allocated = true;
-            (self.scratch_space.as_mut_ptr(), (self.scratch_space.len() / BYTES_PER_WORD) as u32)
+            (self.scratch_space.as_mut_ptr(), scratch_words as u32)
         } else {
             self.allocator.allocate_segment(minimum_size)
         }
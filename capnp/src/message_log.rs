@@ -0,0 +1,176 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! An append-only file of messages, such as the event log of an event-sourced system.
+//!
+//! Records are messages in the
+//! [standard stream framing](https://capnproto.org/encoding.html#serialization-over-a-stream),
+//! one after another, so the file can also be read with `serialize::read_message()`. A crash
+//! in the middle of an append can leave a partial record at the end of the file;
+//! `MessageLog::open()` truncates it, so that appends continue from the last complete record.
+//! Records that are corrupt in other ways are reported as errors and never truncated.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::message;
+use crate::serialize::{self, OwnedSegments};
+use crate::{Error, Result};
+
+/// When appended records are forced to stable storage with `fsync`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Only when `MessageLog::sync()` is called.
+    Manual,
+    /// After every append.
+    Always,
+    /// After every `n` appends.
+    EveryN(u32),
+}
+
+/// Returns the length of the longest prefix of `bytes` that consists of complete records. The
+/// rest must be the beginning of a record that was cut short; a segment table that could not
+/// have been written by `serialize`, or that declares more segments than `options` allows, is
+/// reported as an error rather than being taken for the end of the log.
+fn complete_records_len(bytes: &[u8], options: message::ReaderOptions) -> Result<usize> {
+    let mut offset = 0;
+    loop {
+        let rest = &bytes[offset..];
+        if rest.len() < 4 {
+            return Ok(offset);
+        }
+        let segment_count = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) as u64 + 1;
+        if segment_count > options.segment_count_limit as u64 {
+            return Err(Error::failed(format!(
+                "corrupt message log record at offset {}: {} segments, but the limit is {}",
+                offset, segment_count, options.segment_count_limit)));
+        }
+        let segment_count = segment_count as usize;
+        let table_len = (segment_count / 2 + 1) * 8;
+        if rest.len() < table_len {
+            return Ok(offset);
+        }
+        let mut record_len = table_len as u64;
+        for idx in 0..segment_count {
+            let size = &rest[4 + idx * 4..8 + idx * 4];
+            record_len += u32::from_le_bytes([size[0], size[1], size[2], size[3]]) as u64 * 8;
+        }
+        if (rest.len() as u64) < record_len {
+            return Ok(offset);
+        }
+        offset += record_len as usize;
+    }
+}
+
+/// A file that messages are appended to.
+pub struct MessageLog {
+    file: File,
+    path: PathBuf,
+    len: u64,
+    truncated_bytes: u64,
+    policy: SyncPolicy,
+    unsynced: u32,
+}
+
+impl MessageLog {
+    /// Opens the log at `path`, creating it if it does not exist, and truncates any partial
+    /// record at its end. Fails, leaving the file alone, if a record is corrupt.
+    pub fn open<P>(path: P, policy: SyncPolicy) -> Result<MessageLog> where P: AsRef<Path> {
+        MessageLog::open_with_options(path, policy, message::ReaderOptions::new())
+    }
+
+    /// Like `open()`, but checks segment tables against `options.segment_count_limit` instead of
+    /// the default limit.
+    pub fn open_with_options<P>(path: P, policy: SyncPolicy, options: message::ReaderOptions)
+                                -> Result<MessageLog> where P: AsRef<Path>
+    {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new().read(true).write(true).create(true).open(&path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let len = complete_records_len(&bytes, options)? as u64;
+        let truncated_bytes = bytes.len() as u64 - len;
+        if truncated_bytes > 0 {
+            file.set_len(len)?;
+            file.sync_all()?;
+        }
+        file.seek(SeekFrom::Start(len))?;
+        Ok(MessageLog { file: file, path: path, len: len, truncated_bytes: truncated_bytes,
+                        policy: policy, unsynced: 0 })
+    }
+
+    /// Appends `message` as one record, then syncs if the policy says so. If writing fails, the
+    /// log is cut back to the end of the previous record.
+    pub fn append<A>(&mut self, message: &message::Builder<A>) -> Result<()> where A: message::Allocator {
+        let bytes = serialize::write_message_to_words(message);
+        if let Err(e) = self.file.write_all(&bytes) {
+            let _ = self.file.set_len(self.len).and_then(|()| self.file.seek(SeekFrom::Start(self.len)));
+            return Err(e.into());
+        }
+        self.len += bytes.len() as u64;
+        self.unsynced += 1;
+        let sync = match self.policy {
+            SyncPolicy::Manual => false,
+            SyncPolicy::Always => true,
+            SyncPolicy::EveryN(n) => self.unsynced >= n,
+        };
+        if sync {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Forces every appended record to stable storage.
+    pub fn sync(&mut self) -> Result<()> {
+        self.file.sync_data()?;
+        self.unsynced = 0;
+        Ok(())
+    }
+
+    /// Returns the number of bytes in the log.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns the number of bytes of partial record that `open()` cut off the end of the file.
+    pub fn truncated_bytes(&self) -> u64 {
+        self.truncated_bytes
+    }
+
+    /// Returns the number of appends since the log was last synced.
+    pub fn unsynced(&self) -> u32 {
+        self.unsynced
+    }
+
+    /// Reads every record in the log.
+    pub fn read_all(&self, options: message::ReaderOptions) -> Result<Vec<message::Reader<OwnedSegments>>> {
+        let mut file = File::open(&self.path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let mut slice = &bytes[..self.len as usize];
+        let mut messages = Vec::new();
+        while let Some(message) = serialize::try_read_message(&mut slice, options)? {
+            messages.push(message);
+        }
+        Ok(messages)
+    }
+}
@@ -0,0 +1,145 @@
+// Copyright (c) 2013-2017 Sandstorm Development Group, Inc. and contributors
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+
+//! Encoding fields as keys for ordered key-value stores.
+//!
+//! Field values are encoded so that comparing the encoded bytes lexicographically, as `memcmp`
+//! does, gives the same order as comparing the values. Integers are written big-endian, with the
+//! sign bit of signed integers flipped so that negative values come first; floats are written
+//! the same way after mapping their bits to an integer of the same order; and text and data are
+//! escaped and terminated so that no key is a prefix of another. A composite key is the
+//! concatenation of the encodings of its fields, and orders by its first field, then by its
+//! second, and so on.
+//!
+//! ```ignore
+//! let key = ordered_key::encode_fields(&[2, 0], |ordinal| reader.get_field_by_ordinal(ordinal))?;
+//! ```
+
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+use crate::fields::FieldValue;
+use crate::{Error, Result};
+
+/// Appends the key encoding of `value` to `out`. Fails for pointer fields other than text and
+/// data, which have no natural order.
+pub fn encode_value(value: FieldValue, out: &mut Vec<u8>) -> Result<()> {
+    match value {
+        FieldValue::Void => (),
+        FieldValue::Bool(b) => out.push(b as u8),
+        FieldValue::Int8(n) => out.push(n as u8 ^ 0x80),
+        FieldValue::Int16(n) => out.extend_from_slice(&(n as u16 ^ 0x8000).to_be_bytes()),
+        FieldValue::Int32(n) => out.extend_from_slice(&(n as u32 ^ 0x8000_0000).to_be_bytes()),
+        FieldValue::Int64(n) => out.extend_from_slice(&(n as u64 ^ 0x8000_0000_0000_0000).to_be_bytes()),
+        FieldValue::UInt8(n) => out.push(n),
+        FieldValue::UInt16(n) | FieldValue::Enum(n) => out.extend_from_slice(&n.to_be_bytes()),
+        FieldValue::UInt32(n) => out.extend_from_slice(&n.to_be_bytes()),
+        FieldValue::UInt64(n) => out.extend_from_slice(&n.to_be_bytes()),
+        FieldValue::Float32(n) => {
+            let bits = n.to_bits();
+            let bits = if bits & 0x8000_0000 != 0 { !bits } else { bits ^ 0x8000_0000 };
+            out.extend_from_slice(&bits.to_be_bytes());
+        }
+        FieldValue::Float64(n) => {
+            let bits = n.to_bits();
+            let bits = if bits & 0x8000_0000_0000_0000 != 0 { !bits } else { bits ^ 0x8000_0000_0000_0000 };
+            out.extend_from_slice(&bits.to_be_bytes());
+        }
+        FieldValue::Text(t) => encode_bytes(t.as_bytes(), out),
+        FieldValue::Data(d) => encode_bytes(d, out),
+        FieldValue::Pointer(_) => {
+            return Err(Error::failed("only scalar, text and data fields can be encoded as keys".to_string()))
+        }
+    }
+    Ok(())
+}
+
+/// Zero bytes are escaped as `00 ff` and the end is marked with `00 01`, so that a shorter
+/// string orders before any longer string it is a prefix of.
+fn encode_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    for &b in bytes {
+        out.push(b);
+        if b == 0 {
+            out.push(0xff);
+        }
+    }
+    out.extend_from_slice(&[0, 1]);
+}
+
+/// Encodes the fields with the given ordinals, in order, as a single key. `get_field` is
+/// usually a generated reader's `get_field_by_ordinal()`.
+pub fn encode_fields<'a, F>(ordinals: &[u16], get_field: F) -> Result<Vec<u8>>
+    where F: Fn(u16) -> Result<Option<FieldValue<'a>>>
+{
+    let mut out = Vec::new();
+    for &ordinal in ordinals {
+        match get_field(ordinal)? {
+            Some(value) => encode_value(value, &mut out)?,
+            None => return Err(Error::failed(
+                format!("field @{} does not exist or is not set", ordinal))),
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use crate::fields::FieldValue;
+    use super::encode_value;
+
+    fn key(value: FieldValue) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode_value(value, &mut out).unwrap();
+        out
+    }
+
+    fn assert_ordered(values: &[FieldValue]) {
+        for pair in values.windows(2) {
+            assert!(key(pair[0]) < key(pair[1]));
+        }
+    }
+
+    #[test]
+    fn integers() {
+        assert_ordered(&[FieldValue::Int8(-128), FieldValue::Int8(-1), FieldValue::Int8(0), FieldValue::Int8(127)]);
+        assert_ordered(&[FieldValue::Int64(i64::min_value()), FieldValue::Int64(-300),
+                         FieldValue::Int64(2), FieldValue::Int64(256), FieldValue::Int64(i64::max_value())]);
+        assert_ordered(&[FieldValue::UInt32(0), FieldValue::UInt32(255), FieldValue::UInt32(256)]);
+        assert_eq!(key(FieldValue::Int16(1)), [0x80, 0x01]);
+    }
+
+    #[test]
+    fn floats() {
+        assert_ordered(&[FieldValue::Float64(::core::f64::NEG_INFINITY), FieldValue::Float64(-2.5),
+                         FieldValue::Float64(-0.0), FieldValue::Float64(0.0), FieldValue::Float64(1e-300),
+                         FieldValue::Float64(3.0), FieldValue::Float64(::core::f64::INFINITY)]);
+        assert_ordered(&[FieldValue::Float32(-1.0), FieldValue::Float32(0.5), FieldValue::Float32(2.0)]);
+    }
+
+    #[test]
+    fn strings() {
+        assert_ordered(&[FieldValue::Text(""), FieldValue::Text("a"), FieldValue::Text("a\0"),
+                         FieldValue::Text("a\0b"), FieldValue::Text("ab"), FieldValue::Text("b")]);
+        assert_ordered(&[FieldValue::Data(&[]), FieldValue::Data(&[0]), FieldValue::Data(&[0, 0]),
+                         FieldValue::Data(&[1])]);
+    }
+}
@@ -24,7 +24,7 @@
 use core::{marker};
 
 use crate::traits::{FromPointerReader, FromPointerBuilder, IndexMove, ListIter};
-use crate::private::layout::{ListReader, ListBuilder, PointerReader, PointerBuilder,
+use crate::private::layout::{ElementSize, ListReader, ListBuilder, PointerReader, PointerBuilder,
                              PrimitiveElement};
 use crate::Result;
 
@@ -55,6 +55,42 @@ impl <'a, T: PrimitiveElement> Reader<'a, T> {
         let l = self.len();
         ListIter::new(self, l)
     }
+
+    /// Attempts to view the list's elements as a native slice, without copying.
+    ///
+    /// Cap'n Proto stores list elements in little-endian byte order, so this only succeeds
+    /// on little-endian targets. It also returns `None` for `Bool` and `Void` lists, for lists
+    /// whose stride differs from the size of `T` (as happens when a struct list is read as a
+    /// primitive list), and for lists whose data is not suitably aligned for `T`. Callers
+    /// should fall back to `get()` or `iter()` in that case.
+    pub fn as_slice(&self) -> Option<&'a [T]> {
+        if !native_layout::<T>(self.reader.get_element_size(), self.reader.get_step_size_in_bits()) {
+            return None;
+        }
+        if self.reader.len() == 0 {
+            return Some(&[]);
+        }
+        let bytes = self.reader.into_raw_bytes();
+        if bytes.as_ptr() as usize % core::mem::align_of::<T>() != 0 {
+            return None;
+        }
+        unsafe {
+            Some(core::slice::from_raw_parts(bytes.as_ptr() as *const T, self.len() as usize))
+        }
+    }
+}
+
+/// Returns true if the elements of a list with the given element size and stride are laid out
+/// exactly like a native `[T]`.
+fn native_layout<T: PrimitiveElement>(element_size: ElementSize, step_bits: u32) -> bool {
+    if cfg!(target_endian = "big") {
+        return false;
+    }
+    match T::element_size() {
+        ElementSize::Byte | ElementSize::TwoBytes | ElementSize::FourBytes | ElementSize::EightBytes => (),
+        _ => return false,
+    }
+    element_size == T::element_size() && step_bits as usize == core::mem::size_of::<T>() * 8
 }
 
 impl <'a, T: PrimitiveElement> FromPointerReader<'a> for Reader<'a, T> {
@@ -127,6 +163,28 @@ impl <'a, T : PrimitiveElement> Builder<'a, T> {
     pub fn reborrow<'b>(&'b self) -> Builder<'b, T> {
         Builder { .. *self }
     }
+
+    /// Attempts to view the list's elements as a mutable native slice, without copying.
+    /// Values written through the slice go directly into the message.
+    ///
+    /// Returns `None` under the same conditions as `Reader::as_slice()`, including on
+    /// big-endian targets, where the in-memory representation does not match the wire format.
+    pub fn as_mut_slice(&mut self) -> Option<&mut [T]> {
+        if !native_layout::<T>(self.builder.get_element_size(), self.builder.get_step_size_in_bits()) {
+            return None;
+        }
+        let len = self.len() as usize;
+        if len == 0 {
+            return Some(&mut []);
+        }
+        let bytes = self.builder.into_raw_bytes();
+        if bytes.as_ptr() as usize % core::mem::align_of::<T>() != 0 {
+            return None;
+        }
+        unsafe {
+            Some(core::slice::from_raw_parts_mut(bytes.as_mut_ptr() as *mut T, len))
+        }
+    }
 }
 
 impl <'a, T> crate::traits::SetPointerBuilder<Builder<'a, T>> for Reader<'a, T>
@@ -25,7 +25,7 @@ use core::u64;
 
 use crate::private::units::*;
 use crate::message;
-use crate::message::{Allocator, ReaderSegments};
+use crate::message::{Allocator, BuilderStats, ReaderSegments};
 use crate::{Error, OutputSegments, Result};
 
 pub type SegmentId = u32;
@@ -58,6 +58,8 @@ pub trait ReaderArena {
     fn check_offset(&self, segment_id: u32, start: *const u8, offset_in_words: i32) -> Result<*const u8>;
     fn contains_interval(&self, segment_id: u32, start: *const u8, size: usize) -> Result<()>;
     fn amplified_read(&self, virtual_amount: u64) -> Result<()>;
+    fn utf8_policy(&self) -> message::Utf8Policy;
+    fn pointer_error_policy(&self) -> message::PointerErrorPolicy;
 
     // TODO(version 0.9): Consider putting extract_cap(), inject_cap(), drop_cap() here
     //   and on message::Reader. Then we could get rid of Imbue and ImbueMut, and
@@ -67,6 +69,8 @@ pub trait ReaderArena {
 pub struct ReaderArenaImpl<S> {
     segments: S,
     read_limiter: ReadLimiter,
+    utf8_policy: message::Utf8Policy,
+    pointer_error_policy: message::PointerErrorPolicy,
 }
 
 impl <S> ReaderArenaImpl <S> where S: ReaderSegments {
@@ -78,9 +82,15 @@ impl <S> ReaderArenaImpl <S> where S: ReaderSegments {
         ReaderArenaImpl {
             segments: segments,
             read_limiter: limiter,
+            utf8_policy: options.utf8_policy,
+            pointer_error_policy: options.pointer_error_policy,
         }
     }
 
+    pub fn get_segments(&self) -> &S {
+        &self.segments
+    }
+
     pub fn into_segments(self) -> S {
         self.segments
     }
@@ -92,7 +102,7 @@ impl <S> ReaderArena for ReaderArenaImpl<S> where S: ReaderSegments {
             Some(seg) => {
                 #[cfg(not(feature = "unaligned"))]
                 {
-                    if seg.as_ptr() as usize % BYTES_PER_WORD != 0 {
+                    if !crate::Word::is_aligned(seg) {
                         return Err(Error::failed(
                             format!("Detected unaligned segment. You must either ensure all of your \
                                      segments are 8-byte aligned, or you must enable the \"unaligned\" \
@@ -100,7 +110,13 @@ impl <S> ReaderArena for ReaderArenaImpl<S> where S: ReaderSegments {
                     }
                 }
 
-                Ok((seg.as_ptr(), (seg.len() / BYTES_PER_WORD) as u32))
+                let word_len = seg.len() / BYTES_PER_WORD;
+                if word_len > u32::max_value() as usize {
+                    return Err(Error::failed(
+                        format!("Segment {} has {} words, which is more than can be addressed \
+                                 by a wire pointer", id, word_len)))
+                }
+                Ok((seg.as_ptr(), word_len as u32))
             }
             None => Err(Error::failed(format!("Invalid segment id: {}", id))),
         }
@@ -124,9 +140,11 @@ impl <S> ReaderArena for ReaderArenaImpl<S> where S: ReaderSegments {
         let this_start: usize = segment_start as usize;
         let this_size: usize = segment_len as usize * BYTES_PER_WORD;
         let start = start as usize;
-        let size = size_in_words * BYTES_PER_WORD;
 
-        if !(start >= this_start && start - this_start + size <= this_size) {
+        // Computed in 64 bits so that a large `size_in_words` cannot wrap on 32-bit targets.
+        let size = size_in_words as u64 * BYTES_PER_WORD as u64;
+
+        if !(start >= this_start && (start - this_start) as u64 + size <= this_size as u64) {
             Err(Error::failed(format!("message contained out-of-bounds pointer")))
         } else {
             self.read_limiter.can_read(size_in_words as u64)
@@ -136,6 +154,14 @@ impl <S> ReaderArena for ReaderArenaImpl<S> where S: ReaderSegments {
     fn amplified_read(&self, virtual_amount: u64) -> Result<()> {
         self.read_limiter.can_read(virtual_amount)
     }
+
+    fn utf8_policy(&self) -> message::Utf8Policy {
+        self.utf8_policy
+    }
+
+    fn pointer_error_policy(&self) -> message::PointerErrorPolicy {
+        self.pointer_error_policy
+    }
 }
 
 pub trait BuilderArena: ReaderArena {
@@ -149,6 +175,10 @@ pub trait BuilderArena: ReaderArena {
     fn allocate_anywhere(&self, amount: u32) -> (SegmentId, u32);
     fn get_segment_mut(&self, id: u32) -> (*mut u8, u32);
 
+    /// Notes that `amount` words were zeroed because the object occupying them became
+    /// unreachable. The space stays allocated, so it is reported by `message::Builder::stats()`.
+    fn record_wasted_words(&self, amount: u64);
+
     fn as_reader<'a>(&'a self) -> &'a dyn ReaderArena;
 }
 
@@ -163,6 +193,8 @@ pub struct BuilderArenaImplInner<A> where A: Allocator {
 
     // TODO(perf): Try using smallvec to avoid heap allocations in the single-segment case?
     segments: Vec<BuilderSegment>,
+
+    wasted_words: u64,
 }
 
 pub struct BuilderArenaImpl<A> where A: Allocator {
@@ -175,6 +207,7 @@ impl <A> BuilderArenaImpl<A> where A: Allocator {
             inner: RefCell::new(BuilderArenaImplInner {
                 allocator: Some(allocator),
                 segments: Vec::new(),
+                wasted_words: 0,
             }),
         }
     }
@@ -208,6 +241,21 @@ impl <A> BuilderArenaImpl<A> where A: Allocator {
         self.inner.borrow().segments.len()
     }
 
+    pub fn get_stats(&self) -> BuilderStats {
+        let reff = self.inner.borrow();
+        let mut stats = BuilderStats {
+            segment_count: reff.segments.len(),
+            words_allocated: 0,
+            words_used: 0,
+            words_wasted: reff.wasted_words,
+        };
+        for seg in &reff.segments {
+            stats.words_allocated += seg.capacity as u64;
+            stats.words_used += seg.allocated as u64;
+        }
+        stats
+    }
+
     pub fn into_allocator(self) -> A {
         let mut inner = self.inner.into_inner();
         inner.deallocate_all();
@@ -233,6 +281,14 @@ impl <A> ReaderArena for BuilderArenaImpl<A> where A: Allocator {
     fn amplified_read(&self, _virtual_amount: u64) -> Result<()> {
         Ok(())
     }
+
+    fn utf8_policy(&self) -> message::Utf8Policy {
+        message::Utf8Policy::Strict
+    }
+
+    fn pointer_error_policy(&self) -> message::PointerErrorPolicy {
+        message::PointerErrorPolicy::Strict
+    }
 }
 
 impl <A> BuilderArenaImplInner<A> where A: Allocator {
@@ -300,6 +356,10 @@ impl <A> BuilderArena for BuilderArenaImpl<A> where A: Allocator {
         self.inner.borrow_mut().get_segment_mut(id)
     }
 
+    fn record_wasted_words(&self, amount: u64) {
+        self.inner.borrow_mut().wasted_words += amount;
+    }
+
     fn as_reader<'a>(&'a self) -> &'a dyn ReaderArena {
         self
     }
@@ -329,6 +389,14 @@ impl ReaderArena for NullArena {
     fn amplified_read(&self, _virtual_amount: u64) -> Result<()> {
         Ok(())
     }
+
+    fn utf8_policy(&self) -> message::Utf8Policy {
+        message::Utf8Policy::Strict
+    }
+
+    fn pointer_error_policy(&self) -> message::PointerErrorPolicy {
+        message::PointerErrorPolicy::Strict
+    }
 }
 
 impl BuilderArena for NullArena {
@@ -344,6 +412,8 @@ impl BuilderArena for NullArena {
         (core::ptr::null_mut(), 0)
     }
 
+    fn record_wasted_words(&self, _amount: u64) {}
+
     fn as_reader<'a>(&'a self) -> &'a dyn ReaderArena {
         self
     }
@@ -86,6 +86,10 @@ pub trait ResultsHook {
 
 pub trait ParamsHook {
     fn get<'a>(&'a self) -> crate::Result<crate::any_pointer::Reader<'a>>;
+
+    fn caller(&self) -> Option<crate::capability::CallerInfo> {
+        None
+    }
 }
 
 // Where should this live?
@@ -118,6 +122,9 @@ impl Clone for Box<dyn PipelineHook> {
     }
 }
 
+/// A step in the path from a promised answer to a capability, mirroring `PromisedAnswer.Op`
+/// in rpc.capnp. The protocol has no op for indexing into a list, so list elements cannot be
+/// pipelined on; the generated `Pipeline` types omit list fields for that reason.
 #[derive(Clone, Copy)]
 pub enum PipelineOp {
     Noop,
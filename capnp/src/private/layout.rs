@@ -26,6 +26,7 @@ use core::ptr;
 use core::cell::Cell;
 
 use crate::data;
+use crate::message::{PointerErrorPolicy, Utf8Policy};
 use crate::text;
 use crate::private::capability::{ClientHook};
 use crate::private::arena::{BuilderArena, ReaderArena, NullArena, SegmentId};
@@ -51,7 +52,7 @@ pub enum ElementSize {
 }
 
 impl ElementSize {
-    fn from(val: u8) -> ElementSize {
+    pub(crate) fn from(val: u8) -> ElementSize {
         match val {
             0 => ElementSize::Void,
             1 => ElementSize::Bit,
@@ -336,8 +337,7 @@ mod wire_helpers {
     use crate::{Error, MessageSize, Result};
 
     pub struct SegmentAnd<T> {
-        #[allow(dead_code)]
-        segment_id: u32,
+        pub segment_id: u32,
         pub value: T,
     }
 
@@ -357,6 +357,16 @@ mod wire_helpers {
         ((bits + 63) / (BITS_PER_WORD as u64)) as WordCount32
     }
 
+    //# Computes the size of the body of an inline composite list. The product is
+    //# formed in 64 bits so that it cannot silently wrap before being range-checked.
+    #[inline]
+    pub fn inline_composite_list_word_count(element_count: ElementCount32,
+                                            words_per_element: WordCount32) -> WordCount32 {
+        let word_count = element_count as WordCount64 * words_per_element as WordCount64;
+        assert!(word_count < (1 << 29), "Inline composite lists are limited to 2**29 words");
+        word_count as WordCount32
+    }
+
     #[allow(dead_code)]
     #[inline]
     pub fn round_bits_up_to_bytes(bits: BitCount64) -> ByteCount32 {
@@ -477,6 +487,9 @@ mod wire_helpers {
         segment_id: u32)
         -> Result<(*const u8, *const WirePointer, u32)>
     {
+        #[cfg(feature = "sanitize")]
+        crate::private::sanitize::check_pointer(arena, segment_id, reff)?;
+
         if (*reff).kind() == WirePointerKind::Far {
             let far_segment_id = (*reff).far_segment_id();
 
@@ -488,12 +501,19 @@ mod wire_helpers {
 
             let pad: *const WirePointer = ptr as *const _;
 
+            #[cfg(feature = "sanitize")]
+            crate::private::sanitize::check_landing_pad(arena, segment_id, reff, far_segment_id, pad)?;
+
             if !(*reff).is_double_far() {
                 Ok(((*pad).target_from_segment(arena, far_segment_id)?, pad, far_segment_id))
             } else {
                 // Landing pad is another far pointer. It is followed by a tag describing the
                 // pointed-to object.
 
+                if (*pad).kind() != WirePointerKind::Far || (*pad).is_double_far() {
+                    return Err(Error::failed(
+                        "First word of a double-far landing pad must be a single-far pointer.".to_string()));
+                }
                 let tag = pad.offset(1);
                 let double_far_segment_id = (*pad).far_segment_id();
                 let (segment_start, _segment_len) = arena.get_segment(double_far_segment_id)?;
@@ -515,9 +535,13 @@ mod wire_helpers {
         //# reachable.
 
         match (*reff).kind() {
-            WirePointerKind::Struct | WirePointerKind::List | WirePointerKind::Other => {
+            WirePointerKind::Struct | WirePointerKind::List => {
                 zero_object_helper(arena, segment_id, reff, (*reff).mut_target())
             }
+            WirePointerKind::Other => {
+                // A capability pointer has no content in the message, only an index into
+                // the capability table, so there is nothing to zero.
+            }
             WirePointerKind::Far => {
                 let segment_id = (*reff).far_segment_id();
                 let (seg_start, _seg_len) = arena.get_segment_mut(segment_id);
@@ -535,10 +559,12 @@ mod wire_helpers {
                                        ptr);
 
                     ptr::write_bytes(pad, 0u8, 2);
+                    arena.record_wasted_words(2);
 
                 } else {
                     zero_object(arena, segment_id, pad);
                     ptr::write_bytes(pad, 0u8, 1);
+                    arena.record_wasted_words(1);
                 }
             }
         }
@@ -561,18 +587,20 @@ mod wire_helpers {
                     zero_object(arena, segment_id, pointer_section.offset(i));
                 }
                 ptr::write_bytes(ptr, 0u8, (*tag).struct_word_size() as usize * BYTES_PER_WORD);
+                arena.record_wasted_words((*tag).struct_word_size() as u64);
             }
             WirePointerKind::List => {
                 match (*tag).list_element_size() {
                     Void =>  { }
                     Bit | Byte | TwoBytes | FourBytes | EightBytes => {
+                        let word_count = round_bits_up_to_words((
+                            (*tag).list_element_count() *
+                                data_bits_per_element(
+                                    (*tag).list_element_size())) as u64);
                         ptr::write_bytes(
                             ptr, 0u8,
-                            BYTES_PER_WORD as usize *
-                            round_bits_up_to_words((
-                                    (*tag).list_element_count() *
-                                        data_bits_per_element(
-                                        (*tag).list_element_size())) as u64) as usize)
+                            BYTES_PER_WORD as usize * word_count as usize);
+                        arena.record_wasted_words(word_count as u64);
                     }
                     Pointer => {
                         let count = (*tag).list_element_count() as usize;
@@ -580,6 +608,7 @@ mod wire_helpers {
                             zero_object(arena, segment_id, ptr.offset(i * BYTES_PER_WORD as isize) as *mut _);
                         }
                         ptr::write_bytes(ptr, 0u8, count * BYTES_PER_WORD);
+                        arena.record_wasted_words(count as u64);
                     }
                     InlineComposite => {
                         let element_tag: *mut WirePointer = ptr as *mut _;
@@ -600,9 +629,9 @@ mod wire_helpers {
                                 }
                             }
                         }
-                        ptr::write_bytes(ptr, 0u8,
-                                         BYTES_PER_WORD as usize *
-                                         ((*element_tag).struct_word_size() * count + 1) as usize);
+                        let word_count = (*element_tag).struct_word_size() as u64 * count as u64 + 1;
+                        ptr::write_bytes(ptr, 0u8, BYTES_PER_WORD as usize * word_count as usize);
+                        arena.record_wasted_words(word_count);
                     }
                 }
             }
@@ -1125,7 +1154,7 @@ mod wire_helpers {
         let words_per_element = element_size.total();
 
         //# Allocate the list, prefixed by a single WirePointer.
-        let word_count: WordCount32 = element_count * words_per_element;
+        let word_count: WordCount32 = inline_composite_list_word_count(element_count, words_per_element);
         let (ptr, reff, segment_id) = allocate(arena,
                                                reff,
                                                segment_id,
@@ -1346,7 +1375,7 @@ mod wire_helpers {
             let new_data_size = ::core::cmp::max(old_data_size, element_size.data);
             let new_pointer_count = ::core::cmp::max(old_pointer_count, element_size.pointers);
             let new_step = new_data_size as u32 + new_pointer_count as u32 * WORDS_PER_POINTER as u32;
-            let total_size = new_step * element_count;
+            let total_size = inline_composite_list_word_count(element_count, new_step);
 
             // Don't let allocate() zero out the object just yet.
             zero_pointer_and_fars(arena, orig_segment_id, orig_ref)?;
@@ -1426,7 +1455,7 @@ mod wire_helpers {
                 }
 
                 let new_step = new_data_size as u32 + new_pointer_count as u32 * WORDS_PER_POINTER as u32;
-                let total_words = element_count * new_step;
+                let total_words = inline_composite_list_word_count(element_count, new_step);
 
                 // Don't let allocate() zero out the object just yet.
                 zero_pointer_and_fars(arena, orig_segment_id, orig_ref)?;
@@ -1716,7 +1745,7 @@ mod wire_helpers {
         value: ListReader,
         canonicalize: bool) -> Result<SegmentAnd<*mut u8>>
     {
-        let total_size = round_bits_up_to_words((value.element_count * value.step) as u64);
+        let total_size = round_bits_up_to_words(value.element_count as u64 * value.step as u64);
 
         if value.element_size != ElementSize::InlineComposite {
             //# List of non-structs.
@@ -1803,7 +1832,8 @@ mod wire_helpers {
                         ptr_count = local_ptr_count;
                     }
                 }
-                total_size = (data_size as u32 + ptr_count as u32) * value.element_count as u32;
+                total_size = inline_composite_list_word_count(value.element_count,
+                                                              data_size as u32 + ptr_count as u32);
             } else {
                 data_size = decl_data_size;
                 ptr_count = decl_pointer_count;
@@ -1979,6 +2009,11 @@ mod wire_helpers {
                     return Err(Error::failed("Cannot create a canonical message with a capability".to_string()));
                 }
                 match src_cap_table.extract_cap((*src).cap_index() as usize) {
+                    Some(_) if dst_cap_table.is_null() => {
+                        Err(Error::failed(
+                            "Cannot copy a capability into a message that has no capability table."
+                                .to_string()))
+                    }
                     Some(cap) => {
                         set_capability_pointer(dst_arena, dst_segment_id, dst_cap_table, dst, cap);
                         Ok(SegmentAnd { segment_id: dst_segment_id, value: ptr::null_mut() })
@@ -2051,6 +2086,9 @@ mod wire_helpers {
         reff: *const WirePointer,
         _nesting_limit: i32) -> Result<Box<dyn ClientHook>>
     {
+        #[cfg(feature = "sanitize")]
+        crate::private::sanitize::check_pointer(_arena, _segment_id, reff)?;
+
         if (*reff).is_null() {
             Err(Error::failed(
                 "Message contains null capability pointer.".to_string()))
@@ -2061,6 +2099,12 @@ mod wire_helpers {
             let n = (*reff).cap_index() as usize;
             match cap_table.extract_cap(n) {
                 Some(client_hook) => { Ok(client_hook) }
+                None if cap_table.is_null() => {
+                    Err(Error::failed(
+                        format!("Message contains a capability pointer but has no capability table. \
+                                 Capabilities can only be read from messages attached to an RPC \
+                                 system. Index: {}", n)))
+                }
                 None => {
                     Err(Error::failed(
                         format!("Message contains invalid capability pointer. Index: {}", n)))
@@ -2233,15 +2277,15 @@ mod wire_helpers {
     }
 
     #[inline]
-    pub unsafe fn read_text_pointer<'a>(
+    pub unsafe fn read_text_bytes_pointer<'a>(
         mut arena: &'a dyn ReaderArena,
         mut segment_id: u32,
         mut reff: *const WirePointer,
-        default: Option<&[crate::Word]>) -> Result<text::Reader<'a>>
+        default: Option<&[crate::Word]>) -> Result<&'a [u8]>
     {
         if (*reff).is_null() {
             match default {
-                None => return Ok(&""),
+                None => return Ok(&[]),
                 Some(d) => {
                     reff = d.as_ptr() as *const WirePointer;
                     arena = &super::NULL_ARENA;
@@ -2278,7 +2322,7 @@ mod wire_helpers {
                 "Message contains text that is not NUL-terminated".to_string()));
         }
 
-        text::new_reader(slice::from_raw_parts(str_ptr, size as usize -1))
+        Ok(slice::from_raw_parts(str_ptr, size as usize -1))
     }
 
     #[inline]
@@ -2338,6 +2382,12 @@ pub enum CapTableReader {
 }
 
 impl CapTableReader {
+    pub fn is_null(&self) -> bool {
+        match *self {
+            CapTableReader::Plain(hooks) => hooks.is_null(),
+        }
+    }
+
     pub fn extract_cap(&self, index: usize) -> Option<Box<dyn ClientHook>> {
         match *self {
             CapTableReader::Plain(hooks) => {
@@ -2371,6 +2421,12 @@ impl CapTableBuilder {
         }
     }
 
+    pub fn is_null(&self) -> bool {
+        match *self {
+            CapTableBuilder::Plain(hooks) => hooks.is_null(),
+        }
+    }
+
     pub fn extract_cap(&self, index: usize) -> Option<Box<dyn ClientHook>> {
         match *self {
             CapTableBuilder::Plain(hooks) => {
@@ -2482,20 +2538,31 @@ impl <'a> PointerReader<'a> {
         }
     }
 
+    /// Under `PointerErrorPolicy::Lenient`, replaces an error from reading this pointer with
+    /// the result of reading a null pointer instead, i.e. with the field's default.
+    fn apply_error_policy<T>(&self, result: Result<T>,
+                             read_null: impl FnOnce() -> Result<T>) -> Result<T> {
+        match result {
+            Err(_) if self.arena.pointer_error_policy() == PointerErrorPolicy::Lenient => read_null(),
+            result => result,
+        }
+    }
+
     pub fn get_struct(self, default: Option<&'a [crate::Word]>) -> Result<StructReader<'a>> {
         let reff: *const WirePointer = if self.pointer.is_null() { zero_pointer() } else { self.pointer };
-        unsafe {
+        let read = |reff| unsafe {
             wire_helpers::read_struct_pointer(self.arena,
                                               self.segment_id, self.cap_table, reff,
                                               default, self.nesting_limit)
-        }
+        };
+        self.apply_error_policy(read(reff), || read(zero_pointer()))
     }
 
     pub fn get_list(self, expected_element_size: ElementSize,
                     default: Option<&'a [crate::Word]>) -> Result<ListReader<'a>> {
         let default_value: *const u8 = match default { None => core::ptr::null(), Some(d) => d.as_ptr() as *const u8};
         let reff = if self.pointer.is_null() { zero_pointer() } else { self.pointer };
-        unsafe {
+        let read = |reff| unsafe {
             wire_helpers::read_list_pointer(
                 self.arena,
                 self.segment_id,
@@ -2503,12 +2570,13 @@ impl <'a> PointerReader<'a> {
                 reff,
                 default_value,
                 Some(expected_element_size), self.nesting_limit)
-        }
+        };
+        self.apply_error_policy(read(reff), || read(zero_pointer()))
     }
 
-    fn get_list_any_size(self, default_value: *const u8) -> Result<ListReader<'a>> {
+    pub(crate) fn get_list_any_size(self, default_value: *const u8) -> Result<ListReader<'a>> {
         let reff = if self.pointer.is_null() { zero_pointer() } else { self.pointer };
-        unsafe {
+        let read = |reff| unsafe {
             wire_helpers::read_list_pointer(
                 self.arena,
                 self.segment_id,
@@ -2516,21 +2584,39 @@ impl <'a> PointerReader<'a> {
                 reff,
                 default_value,
                 None, self.nesting_limit)
-        }
+        };
+        self.apply_error_policy(read(reff), || read(zero_pointer()))
     }
 
     pub fn get_text(self, default: Option<&[crate::Word]>) -> Result<text::Reader<'a>> {
         let reff = if self.pointer.is_null() { zero_pointer() } else { self.pointer };
-        unsafe {
-            wire_helpers::read_text_pointer(self.arena, self.segment_id, reff, default)
+        let read = |reff| unsafe {
+            wire_helpers::read_text_bytes_pointer(self.arena, self.segment_id, reff, default)
+        };
+        // Invalid UTF-8 is governed by the `Utf8Policy`, not by the `PointerErrorPolicy`.
+        let bytes = self.apply_error_policy(read(reff), || read(zero_pointer()))?;
+        match self.arena.utf8_policy() {
+            Utf8Policy::Strict => text::new_reader(bytes),
+            Utf8Policy::Lenient => Ok(text::new_reader_lenient(bytes)),
         }
     }
 
+    /// Gets the bytes of a text pointer, without its NUL terminator and without checking
+    /// that they are valid UTF-8.
+    pub fn get_text_bytes(self, default: Option<&'a [crate::Word]>) -> Result<&'a [u8]> {
+        let reff = if self.pointer.is_null() { zero_pointer() } else { self.pointer };
+        let read = |reff| unsafe {
+            wire_helpers::read_text_bytes_pointer(self.arena, self.segment_id, reff, default)
+        };
+        self.apply_error_policy(read(reff), || read(zero_pointer()))
+    }
+
     pub fn get_data(&self, default: Option<&'a [crate::Word]>) -> Result<data::Reader<'a>> {
         let reff = if self.pointer.is_null() { zero_pointer() } else { self.pointer };
-        unsafe {
+        let read = |reff| unsafe {
             wire_helpers::read_data_pointer(self.arena, self.segment_id, reff, default)
-        }
+        };
+        self.apply_error_policy(read(reff), || read(zero_pointer()))
     }
 
     pub fn get_capability(&self) -> Result<Box<dyn ClientHook>> {
@@ -2591,6 +2677,15 @@ impl <'a> PointerReader<'a> {
     }
 }
 
+/// Where `PointerBuilder::set_text_shareable()` wrote a text blob.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TextLocation {
+    arena: usize,
+    segment_id: u32,
+    offset: WordCount32,
+    byte_size: ByteCount32,
+}
+
 #[derive(Clone, Copy)]
 pub struct PointerBuilder<'a> {
     arena: &'a dyn BuilderArena,
@@ -2743,6 +2838,86 @@ impl <'a> PointerBuilder<'a> {
         }
     }
 
+    /// Like `set_text()`, but also returns where the text was written, so that `share_text()`
+    /// can point other pointers of the same message at it.
+    pub fn set_text_shareable(&self, value: &str) -> TextLocation {
+        unsafe {
+            let allocation = wire_helpers::set_text_pointer(self.arena, self.pointer, self.segment_id, value);
+            let (seg_start, _) = self.arena.get_segment_mut(allocation.segment_id);
+            let target = allocation.value.as_ptr();
+            TextLocation {
+                arena: self.arena_id(),
+                segment_id: allocation.segment_id,
+                offset: ((target as usize - seg_start as usize) / BYTES_PER_WORD) as u32,
+                byte_size: value.len() as u32 + 1,
+            }
+        }
+    }
+
+    /// Points this pointer at the text at `location`, provided that it is in this pointer's
+    /// message and still holds `value`. Returns false, leaving the pointer null, otherwise.
+    pub fn share_text(&self, location: TextLocation, value: &str) -> bool {
+        unsafe {
+            if !(*self.pointer).is_null() {
+                wire_helpers::zero_object(self.arena, self.segment_id, self.pointer);
+                ptr::write_bytes(self.pointer, 0, 1);
+            }
+            if location.arena != self.arena_id() || location.byte_size != value.len() as u32 + 1 {
+                return false;
+            }
+            let (seg_start, seg_len) = match self.arena.as_reader().get_segment(location.segment_id) {
+                Ok(segment) => segment,
+                Err(_) => return false,
+            };
+            let words = wire_helpers::round_bytes_up_to_words(location.byte_size);
+            if location.offset as u64 + words as u64 > seg_len as u64 {
+                return false;
+            }
+            let target = seg_start.offset(location.offset as isize * BYTES_PER_WORD as isize) as *mut u8;
+            let bytes = core::slice::from_raw_parts(target as *const u8, location.byte_size as usize);
+            if &bytes[..value.len()] != value.as_bytes() || bytes[value.len()] != 0 {
+                return false;
+            }
+            let mut tag: WirePointer = mem::zeroed();
+            tag.set_kind_with_zero_offset(WirePointerKind::List);
+            tag.set_list_size_and_count(ElementSize::Byte, location.byte_size);
+            wire_helpers::transfer_pointer_split(
+                self.arena, self.segment_id, self.pointer, location.segment_id, &mut tag, target);
+            true
+        }
+    }
+
+    /// Where the text or data that this pointer points to is, if it points to a byte list.
+    pub fn get_text_location(&self) -> Option<TextLocation> {
+        unsafe {
+            if (*self.pointer).is_null() {
+                return None;
+            }
+            let (ptr, reff, segment_id) = wire_helpers::follow_builder_fars(
+                self.arena, self.pointer, (*self.pointer).mut_target(), self.segment_id).ok()?;
+            if (*reff).kind() != WirePointerKind::List || (*reff).list_element_size() != ElementSize::Byte {
+                return None;
+            }
+            let (seg_start, _) = self.arena.get_segment_mut(segment_id);
+            Some(TextLocation {
+                arena: self.arena_id(),
+                segment_id: segment_id,
+                offset: ((ptr as usize - seg_start as usize) / BYTES_PER_WORD) as u32,
+                byte_size: (*reff).list_element_count(),
+            })
+        }
+    }
+
+    /// Nulls the pointer without zeroing the object that it points to, for objects that other
+    /// pointers share.
+    pub fn clear_shared(&self) {
+        unsafe { ptr::write_bytes(self.pointer, 0, 1); }
+    }
+
+    fn arena_id(&self) -> usize {
+        self.arena as *const dyn BuilderArena as *const u8 as usize
+    }
+
     pub fn set_data(&self, value: &[u8]) {
         unsafe {
             wire_helpers::set_data_pointer(self.arena, self.pointer, self.segment_id, value);
@@ -2773,6 +2948,30 @@ impl <'a> PointerBuilder<'a> {
         Ok(())
     }
 
+    /// Like `copy_from()`, but if `other` points to a canonical object, which lies in a single
+    /// contiguous block of words in preorder with no far pointers, copies that block as it is and
+    /// only writes a new pointer to it. Returns whether the words were spliced in this way.
+    pub fn splice_from(&mut self, other: PointerReader) -> Result<bool> {
+        if !other.is_null() && unsafe { (*other.pointer).is_positional() } {
+            let start = unsafe { (*other.pointer).target() };
+            let read_head = Cell::new(start);
+            if other.is_canonical(&read_head)? {
+                let words = (read_head.get() as usize - start as usize) / BYTES_PER_WORD;
+                if words > 0 && words <= u32::max_value() as usize {
+                    unsafe {
+                        let (ptr, reff, _) = wire_helpers::allocate(
+                            self.arena, self.pointer, self.segment_id, words as u32, (*other.pointer).kind());
+                        ptr::copy_nonoverlapping(start, ptr, words * BYTES_PER_WORD);
+                        ptr::copy_nonoverlapping(&(*other.pointer).upper32bits, &mut (*reff).upper32bits, 1);
+                    }
+                    return Ok(true);
+                }
+            }
+        }
+        self.copy_from(other, false)?;
+        Ok(false)
+    }
+
     pub fn clear(&mut self) {
         unsafe {
             wire_helpers::zero_object(self.arena, self.segment_id, self.pointer);
@@ -3204,14 +3403,29 @@ impl <'a> ListReader<'a> {
 
     #[inline]
     pub fn get_struct_element(&self, index: ElementCount32) -> StructReader<'a> {
-        let index_byte: ByteCount32 =
-            ((index as ElementCount64 * (self.step as BitCount64)) / BITS_PER_BYTE as u64) as u32;
+        #[cfg(feature = "sanitize")]
+        crate::private::sanitize::check_list_index(index, self.element_count);
 
-        let struct_data: *const u8 = unsafe { self.ptr.offset(index_byte as isize) };
+        let index_byte: ByteCount =
+            ((index as ElementCount64 * (self.step as BitCount64)) / BITS_PER_BYTE as u64) as usize;
 
-        let struct_pointers: *const WirePointer = unsafe {
-            struct_data.offset((self.struct_data_size as usize / BITS_PER_BYTE) as isize) as *const _
-        };
+        self.struct_element_at(unsafe { self.ptr.add(index_byte) })
+    }
+
+    /// Iterates over the list's elements as structs. Unlike repeated calls to
+    /// `get_struct_element()`, this computes the element offset incrementally.
+    pub fn struct_elements(self) -> StructElementIter<'a> {
+        StructElementIter {
+            next: self.ptr,
+            remaining: self.element_count,
+            list: self,
+        }
+    }
+
+    #[inline]
+    fn struct_element_at(&self, struct_data: *const u8) -> StructReader<'a> {
+        let struct_pointers: *const WirePointer =
+            struct_data.wrapping_add(self.struct_data_size as usize / BITS_PER_BYTE) as *const _;
 
         StructReader {
             arena: self.arena,
@@ -3227,12 +3441,15 @@ impl <'a> ListReader<'a> {
 
     #[inline]
     pub fn get_pointer_element(self, index: ElementCount32) -> PointerReader<'a> {
-        let offset = (index as u64 * self.step as u64 / BITS_PER_BYTE as u64) as u32;
+        #[cfg(feature = "sanitize")]
+        crate::private::sanitize::check_list_index(index, self.element_count);
+
+        let offset = (index as u64 * self.step as u64 / BITS_PER_BYTE as u64) as usize;
         PointerReader {
             arena: self.arena,
             segment_id: self.segment_id,
             cap_table: self.cap_table,
-            pointer: unsafe { self.ptr.offset(offset as isize) } as *const _,
+            pointer: unsafe { self.ptr.add(offset) } as *const _,
             nesting_limit: self.nesting_limit
         }
     }
@@ -3335,6 +3552,56 @@ impl <'a> ListReader<'a> {
     }
 }
 
+/// Iterator over the elements of a struct list, returned by `ListReader::struct_elements()`.
+#[derive(Clone, Copy)]
+pub struct StructElementIter<'a> {
+    list: ListReader<'a>,
+    next: *const u8,
+    remaining: ElementCount32,
+}
+
+impl <'a> Iterator for StructElementIter<'a> {
+    type Item = StructReader<'a>;
+
+    #[inline]
+    fn next(&mut self) -> Option<StructReader<'a>> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let data = self.next;
+        self.next = data.wrapping_add(self.list.step as usize / BITS_PER_BYTE);
+        self.remaining -= 1;
+        Some(self.list.struct_element_at(data))
+    }
+
+    fn nth(&mut self, n: usize) -> Option<StructReader<'a>> {
+        if n >= self.remaining as usize {
+            self.remaining = 0;
+            return None;
+        }
+        self.next = self.next.wrapping_add(n * (self.list.step as usize / BITS_PER_BYTE));
+        self.remaining -= n as u32;
+        self.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining as usize, Some(self.remaining as usize))
+    }
+}
+
+impl <'a> DoubleEndedIterator for StructElementIter<'a> {
+    fn next_back(&mut self) -> Option<StructReader<'a>> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let offset = self.remaining as usize * (self.list.step as usize / BITS_PER_BYTE);
+        Some(self.list.struct_element_at(self.next.wrapping_add(offset)))
+    }
+}
+
+impl <'a> ExactSizeIterator for StructElementIter<'a> {}
+
 #[derive(Clone, Copy)]
 pub struct ListBuilder<'a> {
     arena: &'a dyn BuilderArena,
@@ -3391,13 +3658,60 @@ impl <'a> ListBuilder<'a> {
     #[inline]
     pub fn len(&self) -> ElementCount32 { self.element_count }
 
+    pub(crate) fn get_step_size_in_bits(&self) -> u32 {
+        self.step
+    }
+
+    pub(crate) fn get_element_size(&self) -> ElementSize {
+        self.element_size
+    }
+
+    pub(crate) fn into_raw_bytes(self) -> &'a mut [u8] {
+        if self.element_count == 0 {
+            // Explictly handle this case to avoid forming a slice to a null pointer,
+            // which would be undefined behavior.
+            &mut []
+        } else {
+            let num_bytes = wire_helpers::round_bits_up_to_bytes(
+                self.step as u64 * self.element_count as u64) as usize;
+            unsafe {
+                ::core::slice::from_raw_parts_mut(self.ptr, num_bytes)
+            }
+        }
+    }
+
     #[inline]
     pub fn get_struct_element(self, index: ElementCount32) -> StructBuilder<'a> {
-        let index_byte = ((index as u64 * self.step as u64) / BITS_PER_BYTE as u64) as u32;
-        let struct_data = unsafe{ self.ptr.offset(index_byte as isize)};
-        let struct_pointers = unsafe {
-            struct_data.offset(((self.struct_data_size as usize) / BITS_PER_BYTE) as isize) as *mut _
-        };
+        #[cfg(feature = "sanitize")]
+        crate::private::sanitize::check_list_index(index, self.element_count);
+
+        let index_byte = ((index as u64 * self.step as u64) / BITS_PER_BYTE as u64) as usize;
+        self.struct_element_at(unsafe { self.ptr.add(index_byte) })
+    }
+
+    /// Copies each element of `source` into the element at the same index of this struct list,
+    /// walking both lists by stride. Structs that are larger than this list's elements are
+    /// truncated, as with `StructBuilder::copy_content_from()`.
+    pub fn copy_struct_elements_from(&self, source: &ListReader) -> Result<()> {
+        if source.len() > self.element_count {
+            return Err(crate::Error::failed(
+                format!("Cannot copy {} struct list elements into a list of length {}.",
+                        source.len(), self.element_count)));
+        }
+        let step_bytes = self.step as usize / BITS_PER_BYTE;
+        let mut data = self.ptr;
+        for element in source.struct_elements() {
+            let mut dest = self.struct_element_at(data);
+            dest.copy_content_from(&element)?;
+            data = data.wrapping_add(step_bytes);
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn struct_element_at(&self, struct_data: *mut u8) -> StructBuilder<'a> {
+        let struct_pointers =
+            struct_data.wrapping_add(self.struct_data_size as usize / BITS_PER_BYTE) as *mut _;
         StructBuilder {
             arena: self.arena,
             segment_id: self.segment_id,
@@ -3411,12 +3725,15 @@ impl <'a> ListBuilder<'a> {
 
     #[inline]
     pub fn get_pointer_element(self, index: ElementCount32) -> PointerBuilder<'a> {
-        let offset = (index as u64 * self.step as u64 / BITS_PER_BYTE as u64) as u32;
+        #[cfg(feature = "sanitize")]
+        crate::private::sanitize::check_list_index(index, self.element_count);
+
+        let offset = (index as u64 * self.step as u64 / BITS_PER_BYTE as u64) as usize;
         PointerBuilder {
             arena: self.arena,
             segment_id: self.segment_id,
             cap_table: self.cap_table,
-            pointer: unsafe { self.ptr.offset(offset as isize) } as *mut _,
+            pointer: unsafe { self.ptr.add(offset) } as *mut _,
         }
     }
 }
@@ -3432,27 +3749,36 @@ pub trait PrimitiveElement {
 impl <T : Primitive> PrimitiveElement for T {
     #[inline]
     fn get(list_reader: &ListReader, index: ElementCount32) -> Self {
-        let offset = (index as u64 * list_reader.step as u64 / BITS_PER_BYTE as u64) as u32;
+        #[cfg(feature = "sanitize")]
+        crate::private::sanitize::check_list_index(index, list_reader.element_count);
+
+        let offset = (index as u64 * list_reader.step as u64 / BITS_PER_BYTE as u64) as usize;
         unsafe {
-            let ptr: *const u8 = list_reader.ptr.offset(offset as isize);
+            let ptr: *const u8 = list_reader.ptr.add(offset);
             <Self as Primitive>::get(&*(ptr as *const <Self as Primitive>::Raw))
         }
     }
 
     #[inline]
     fn get_from_builder(list_builder: &ListBuilder, index: ElementCount32) -> Self {
-        let offset = (index as u64 * list_builder.step as u64 / BITS_PER_BYTE as u64) as u32;
+        #[cfg(feature = "sanitize")]
+        crate::private::sanitize::check_list_index(index, list_builder.element_count);
+
+        let offset = (index as u64 * list_builder.step as u64 / BITS_PER_BYTE as u64) as usize;
         unsafe {
-            let ptr: *mut <Self as Primitive>::Raw = list_builder.ptr.offset(offset as isize) as *mut _;
+            let ptr: *mut <Self as Primitive>::Raw = list_builder.ptr.add(offset) as *mut _;
             <Self as Primitive>::get(&*ptr)
         }
     }
 
     #[inline]
     fn set(list_builder: &ListBuilder, index: ElementCount32, value: Self) {
-        let offset = (index as u64 * list_builder.step as u64 / BITS_PER_BYTE as u64) as u32;
+        #[cfg(feature = "sanitize")]
+        crate::private::sanitize::check_list_index(index, list_builder.element_count);
+
+        let offset = (index as u64 * list_builder.step as u64 / BITS_PER_BYTE as u64) as usize;
         unsafe {
-            let ptr: *mut <Self as Primitive>::Raw = list_builder.ptr.offset(offset as isize) as *mut _;
+            let ptr: *mut <Self as Primitive>::Raw = list_builder.ptr.add(offset) as *mut _;
             <Self as Primitive>::set(&mut *ptr, value);
         }
     }
@@ -3472,22 +3798,31 @@ impl <T : Primitive> PrimitiveElement for T {
 impl PrimitiveElement for bool {
     #[inline]
     fn get(list: &ListReader, index: ElementCount32) -> bool {
+        #[cfg(feature = "sanitize")]
+        crate::private::sanitize::check_list_index(index, list.element_count);
+
         let bindex = index as u64 * list.step as u64;
         unsafe {
-            let b: *const u8 = list.ptr.offset((bindex / BITS_PER_BYTE as u64) as isize);
+            let b: *const u8 = list.ptr.add((bindex / BITS_PER_BYTE as u64) as usize);
             ((*b) & (1 << (bindex % BITS_PER_BYTE as u64))) != 0
         }
     }
     #[inline]
     fn get_from_builder(list: &ListBuilder, index: ElementCount32) -> bool {
+        #[cfg(feature = "sanitize")]
+        crate::private::sanitize::check_list_index(index, list.element_count);
+
         let bindex = index as u64 * list.step as u64;
-        let b = unsafe { list.ptr.offset((bindex / BITS_PER_BYTE as u64) as isize) };
+        let b = unsafe { list.ptr.add((bindex / BITS_PER_BYTE as u64) as usize) };
         unsafe { ((*b) & (1 << (bindex % BITS_PER_BYTE as u64 ))) != 0 }
     }
     #[inline]
     fn set(list: &ListBuilder, index: ElementCount32, value: bool) {
+        #[cfg(feature = "sanitize")]
+        crate::private::sanitize::check_list_index(index, list.element_count);
+
         let bindex = index as u64 * list.step as u64;
-        let b = unsafe { list.ptr.offset((bindex / BITS_PER_BYTE as u64) as isize) };
+        let b = unsafe { list.ptr.add((bindex / BITS_PER_BYTE as u64) as usize) };
 
         let bitnum = bindex % BITS_PER_BYTE as u64;
         unsafe { (*b) = ((*b) & !(1 << bitnum)) | ((value as u8) << bitnum) }
@@ -19,6 +19,8 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 
+use alloc::vec::Vec;
+
 use crate::private::layout::PointerReader;
 
 fn test_at_alignments(words: &[crate::Word], verify: &dyn Fn(PointerReader)) {
@@ -188,3 +190,71 @@ fn empty_struct_list_size() {
         assert_eq!(2, pointer_reader.total_size().unwrap().word_count);
     }
 }
+
+#[test]
+fn struct_list_elements() {
+    let data: &[crate::Word] = &[
+        crate::word(0x01, 0x00, 0x00, 0x00, 0x37, 0x00, 0x00, 0x00), // inline-composite list. 6 words long.
+        crate::word(0x0c, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00), // 3 elements, 1 data word, 1 pointer
+        crate::word(0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00),
+        crate::word(0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00),
+        crate::word(0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00),
+        crate::word(0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00),
+        crate::word(0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00),
+        crate::word(0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00),
+    ];
+
+    test_at_alignments(data, &verify);
+    fn verify(pointer_reader: PointerReader) {
+        use crate::private::layout::{ElementSize, StructSize};
+
+        let list = pointer_reader.get_list(ElementSize::InlineComposite, None).unwrap();
+        let values: Vec<u64> = list.struct_elements().map(|s| s.get_data_field::<u64>(0)).collect();
+        assert_eq!(values, [1, 2, 3]);
+        assert_eq!(list.struct_elements().len(), 3);
+        assert_eq!(list.struct_elements().nth(2).unwrap().get_data_field::<u64>(0), 3);
+        assert!(list.struct_elements().nth(3).is_none());
+        let reversed: Vec<u64> = list.struct_elements().rev().map(|s| s.get_data_field::<u64>(0)).collect();
+        assert_eq!(reversed, [3, 2, 1]);
+        let mut elements = list.struct_elements();
+        assert_eq!(elements.next().unwrap().get_data_field::<u64>(0), 1);
+        assert_eq!(elements.next_back().unwrap().get_data_field::<u64>(0), 3);
+        assert_eq!(elements.len(), 1);
+        assert_eq!(elements.next_back().unwrap().get_data_field::<u64>(0), 2);
+        assert!(elements.next().is_none());
+
+        let mut message = crate::message::Builder::new_default();
+        let root: crate::any_pointer::Builder = message.init_root();
+        let dest = root.builder.init_struct_list(4, StructSize { data: 2, pointers: 0 });
+        dest.copy_struct_elements_from(&list).unwrap();
+        let copied: Vec<u64> =
+            (0..4).map(|i| dest.get_struct_element(i).get_data_field::<u64>(0)).collect();
+        assert_eq!(copied, [1, 2, 3, 0]);
+
+        let mut message = crate::message::Builder::new_default();
+        let root: crate::any_pointer::Builder = message.init_root();
+        let too_short = root.builder.init_struct_list(2, StructSize { data: 1, pointers: 0 });
+        assert!(too_short.copy_struct_elements_from(&list).is_err());
+    }
+}
+
+#[test]
+#[should_panic(expected = "Inline composite lists are limited to 2**29 words")]
+fn struct_list_word_count_does_not_wrap() {
+    use crate::private::layout::StructSize;
+
+    // 2^20 elements of 2^12 words each is 2^32 words, which wraps to zero in 32-bit arithmetic.
+    let mut message = crate::message::Builder::new_default();
+    let root: crate::any_pointer::Builder = message.init_root();
+    root.builder.init_struct_list(1 << 20, StructSize { data: 1 << 12, pointers: 0 });
+}
+
+#[test]
+#[should_panic(expected = "Inline composite lists are limited to 2**29 words")]
+fn struct_list_word_count_at_limit() {
+    use crate::private::layout::StructSize;
+
+    let mut message = crate::message::Builder::new_default();
+    let root: crate::any_pointer::Builder = message.init_root();
+    root.builder.init_struct_list(1 << 17, StructSize { data: 1 << 12, pointers: 0 });
+}
@@ -24,10 +24,13 @@
 //! We still need to make this module visible so that generated code can use it.
 
 pub mod arena;
+pub mod broken;
 pub mod capability;
 mod primitive;
 pub mod layout;
 mod mask;
+#[cfg(feature = "sanitize")]
+mod sanitize;
 pub mod units;
 mod zero;
 
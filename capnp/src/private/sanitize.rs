@@ -0,0 +1,122 @@
+// Copyright (c) 2020 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Exhaustive pointer validation, enabled by the "sanitize" feature.
+//!
+//! The reader already performs every check that is needed for memory safety. The checks
+//! in this module are redundant with respect to those, but they catch malformed pointers
+//! closer to where they occur and describe their location in the message. They are meant
+//! to be turned on in CI and in fuzzing, and are compiled out of normal builds.
+
+use alloc::string::String;
+
+use crate::private::arena::ReaderArena;
+use crate::private::layout::{WirePointer, WirePointerKind};
+use crate::private::units::BYTES_PER_WORD;
+use crate::{Error, Result};
+
+/// Describes the location of `ptr` within the message, e.g. "segment 0, word 12".
+pub fn describe_location(arena: &dyn ReaderArena, segment_id: u32, ptr: *const u8) -> String {
+    match arena.get_segment(segment_id) {
+        Ok((start, len)) => {
+            let start = start as usize;
+            let ptr = ptr as usize;
+            if ptr < start || ptr >= start + len as usize * BYTES_PER_WORD {
+                format!("segment {}, outside of the segment's bounds", segment_id)
+            } else {
+                format!("segment {}, word {}", segment_id, (ptr - start) / BYTES_PER_WORD)
+            }
+        }
+        Err(_) => {
+            // Default values and unchecked messages live outside of any arena.
+            format!("unchecked memory at {:p}", ptr)
+        }
+    }
+}
+
+fn failed(arena: &dyn ReaderArena, segment_id: u32, reff: *const WirePointer, what: String) -> Error {
+    Error::failed(format!("sanitize: {} (pointer at {})",
+                          what, describe_location(arena, segment_id, reff as *const u8)))
+}
+
+/// Checks that the pointer `reff` itself lies within segment `segment_id` and that its kind
+/// is one that the reader knows how to handle.
+pub unsafe fn check_pointer(arena: &dyn ReaderArena,
+                            segment_id: u32,
+                            reff: *const WirePointer) -> Result<()> {
+    if let Ok((start, len)) = arena.get_segment(segment_id) {
+        let start = start as usize;
+        let end = start + len as usize * BYTES_PER_WORD;
+        let location = reff as usize;
+        if location < start || location + BYTES_PER_WORD > end {
+            return Err(failed(arena, segment_id, reff,
+                              "pointer lies outside of its segment".into()));
+        }
+        if (location - start) % BYTES_PER_WORD != 0 {
+            return Err(failed(arena, segment_id, reff,
+                              "pointer is not on a word boundary".into()));
+        }
+    }
+
+    if (*reff).kind() == WirePointerKind::Other && !(*reff).is_capability() {
+        return Err(failed(arena, segment_id, reff, "unknown pointer type".into()));
+    }
+    Ok(())
+}
+
+/// Checks the landing pad that the far pointer `reff` points to.
+pub unsafe fn check_landing_pad(arena: &dyn ReaderArena,
+                                segment_id: u32,
+                                reff: *const WirePointer,
+                                pad_segment_id: u32,
+                                pad: *const WirePointer) -> Result<()> {
+    let pad_location = describe_location(arena, pad_segment_id, pad as *const u8);
+    if !(*reff).is_double_far() {
+        if !(*pad).is_positional() {
+            return Err(failed(arena, segment_id, reff, format!(
+                "single-far landing pad at {} is not a struct or list pointer", pad_location)));
+        }
+    } else {
+        if (*pad).kind() != WirePointerKind::Far || (*pad).is_double_far() {
+            return Err(failed(arena, segment_id, reff, format!(
+                "double-far landing pad at {} is not a single-far pointer", pad_location)));
+        }
+        let tag = pad.offset(1);
+        if !(*tag).is_positional() {
+            return Err(failed(arena, segment_id, reff, format!(
+                "double-far tag at {} is not a struct or list pointer", pad_location)));
+        }
+        arena.get_segment((*pad).far_segment_id()).map_err(|e| {
+            failed(arena, segment_id, reff, format!(
+                "double-far landing pad at {} refers to a missing segment: {}",
+                pad_location, e.description))
+        })?;
+    }
+    Ok(())
+}
+
+/// Checks that `index` is in bounds for a list of `element_count` elements.
+#[inline]
+pub fn check_list_index(index: u32, element_count: u32) {
+    assert!(index < element_count,
+            "sanitize: list index {} is out of bounds for a list of {} elements",
+            index, element_count);
+}
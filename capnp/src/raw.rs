@@ -57,3 +57,134 @@ pub fn get_list_bytes<'a, T>(value: T) -> &'a [u8]
 {
     value.into_internal_list_reader().into_raw_bytes()
 }
+
+/// A wire pointer, decoded as described in the
+/// [encoding spec](https://capnproto.org/encoding.html).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DecodedPointer {
+    Null,
+
+    /// `offset` is in words, from the end of the pointer to the start of the struct.
+    Struct { offset: i32, data_words: u16, pointer_count: u16 },
+
+    /// `offset` is in words, from the end of the pointer to the start of the list. For
+    /// `InlineComposite` lists, `element_count` is the number of words after the tag.
+    List { offset: i32, element_size: crate::private::layout::ElementSize, element_count: u32 },
+
+    /// `position` is in words, from the start of segment `segment_id`.
+    Far { double_far: bool, position: u32, segment_id: u32 },
+
+    Capability { index: u32 },
+
+    /// A pointer of the reserved "other" kind that is not a capability.
+    Unknown(u64),
+}
+
+impl DecodedPointer {
+    /// Decodes a pointer from its eight little-endian bytes.
+    pub fn decode(word: [u8; 8]) -> DecodedPointer {
+        let raw = u64::from_le_bytes(word);
+        let lower = raw as u32;
+        let upper = (raw >> 32) as u32;
+        if raw == 0 {
+            return DecodedPointer::Null;
+        }
+        let offset = (lower as i32) >> 2;
+        match lower & 3 {
+            0 => DecodedPointer::Struct {
+                offset: offset,
+                data_words: upper as u16,
+                pointer_count: (upper >> 16) as u16,
+            },
+            1 => DecodedPointer::List {
+                offset: offset,
+                element_size: crate::private::layout::ElementSize::from((upper & 7) as u8),
+                element_count: upper >> 3,
+            },
+            2 => DecodedPointer::Far {
+                double_far: lower & 4 != 0,
+                position: lower >> 3,
+                segment_id: upper,
+            },
+            _ if lower == 3 => DecodedPointer::Capability { index: upper },
+            _ => DecodedPointer::Unknown(raw),
+        }
+    }
+}
+
+impl core::fmt::Display for DecodedPointer {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match *self {
+            DecodedPointer::Null => write!(f, "null"),
+            DecodedPointer::Struct { offset, data_words, pointer_count } =>
+                write!(f, "struct (offset {}, {} data words, {} pointers)", offset, data_words, pointer_count),
+            DecodedPointer::List { offset, element_size, element_count } =>
+                write!(f, "list (offset {}, {:?}, {} elements)", offset, element_size, element_count),
+            DecodedPointer::Far { double_far, position, segment_id } =>
+                write!(f, "{} (segment {}, word {})",
+                       if double_far { "double-far" } else { "far" }, segment_id, position),
+            DecodedPointer::Capability { index } => write!(f, "capability (index {})", index),
+            DecodedPointer::Unknown(raw) => write!(f, "unknown ({:#018x})", raw),
+        }
+    }
+}
+
+/// The words of a struct exactly as they appear in the message, for debugging. The `Display`
+/// implementation dumps the data section as hex and decodes each pointer.
+#[derive(Clone, Copy)]
+pub struct RawStructView<'a> {
+    /// The data section. May be shorter than a whole number of words if the struct is a list
+    /// element with sub-word data.
+    pub data: &'a [u8],
+
+    /// The pointer section, eight bytes per pointer.
+    pub pointers: &'a [u8],
+}
+
+impl <'a> RawStructView<'a> {
+    /// Decodes the pointer at `index` in the pointer section.
+    pub fn get_pointer(&self, index: usize) -> Option<DecodedPointer> {
+        let bytes = self.pointers.get(index * 8..index * 8 + 8)?;
+        let mut word = [0; 8];
+        word.copy_from_slice(bytes);
+        Some(DecodedPointer::decode(word))
+    }
+
+    /// Decodes every pointer in the pointer section.
+    pub fn decode_pointers(&self) -> impl Iterator<Item = DecodedPointer> + 'a {
+        let view = *self;
+        (0..self.pointers.len() / 8).map(move |i| view.get_pointer(i).unwrap())
+    }
+}
+
+impl <'a> core::fmt::Display for RawStructView<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        for (i, chunk) in self.data.chunks(8).enumerate() {
+            write!(f, "data[{}]:", i)?;
+            for byte in chunk {
+                write!(f, " {:02x}", byte)?;
+            }
+            writeln!(f)?;
+        }
+        for (i, (pointer, chunk)) in self.decode_pointers().zip(self.pointers.chunks(8)).enumerate() {
+            write!(f, "ptr[{}]:", i)?;
+            for byte in chunk {
+                write!(f, " {:02x}", byte)?;
+            }
+            writeln!(f, "  {}", pointer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Gets a view of the raw data and pointer words of a struct, for inspecting exactly what
+/// is on the wire.
+pub fn raw_struct_view<'a, T>(value: T) -> RawStructView<'a>
+    where T: IntoInternalStructReader<'a>
+{
+    let reader = value.into_internal_struct_reader();
+    RawStructView {
+        data: reader.get_data_section_as_blob(),
+        pointers: reader.get_pointer_section_as_list().into_raw_bytes(),
+    }
+}
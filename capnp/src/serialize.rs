@@ -22,6 +22,13 @@
 //! Reading and writing of messages using the
 //! [standard stream framing](https://capnproto.org/encoding.html#serialization-over-a-stream),
 //! where each message is preceded by a segment table indicating the size of its segments.
+//!
+//! Capabilities are not serialized. A capability pointer is written as-is, i.e. as an index
+//! into the capability table of the message it came from, and that table is not part of the
+//! output. When such a message is read back outside of RPC, `has_foo()` reports that the
+//! interface field `foo` is set, `get_foo()` returns an error, and `clear_foo()` can be used
+//! to drop the stale pointer. Copying a capability into a message that has no capability
+//! table, e.g. with `set_root()`, returns an error.
 
 use alloc::string::ToString;
 use alloc::vec::Vec;
@@ -62,6 +69,8 @@ impl <'a> message::ReaderSegments for SliceSegments<'a> {
 ///
 /// ALIGNMENT: If the "unaligned" feature is enabled, then there are no alignment requirements on `slice`.
 /// Otherwise, `slice` must be 8-byte aligned (attempts to read the message will trigger errors).
+/// A `&[Word]` can be passed via `Word::words_to_bytes()`, and bytes of unknown alignment can be
+/// copied into aligned memory with `Word::words_from_bytes()`.
 pub fn read_message_from_flat_slice<'a>(slice: &mut &'a [u8],
                                         options: message::ReaderOptions)
                                         -> Result<message::Reader<SliceSegments<'a>>> {
@@ -86,6 +95,68 @@ pub fn read_message_from_flat_slice<'a>(slice: &mut &'a [u8],
     }
 }
 
+/// Whether a buffer may hold more bytes after the message that is read from it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrailingBytes {
+    Allow,
+    Reject,
+}
+
+/// Segments of a message read by `read_message_from_bytes_at()`: borrowed from the buffer when
+/// the message could be read in place, or copied out of it when it was not 8-byte aligned.
+pub enum BufferSegments<'a> {
+    Borrowed(SliceSegments<'a>),
+    Copied(OwnedSegments),
+}
+
+impl <'a> message::ReaderSegments for BufferSegments<'a> {
+    fn get_segment<'b>(&'b self, id: u32) -> Option<&'b [u8]> {
+        match self {
+            BufferSegments::Borrowed(segments) => segments.get_segment(id),
+            BufferSegments::Copied(segments) => segments.get_segment(id),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            BufferSegments::Borrowed(segments) => segments.len(),
+            BufferSegments::Copied(segments) => segments.len(),
+        }
+    }
+}
+
+/// Reads a serialized message that starts at `offset` in `buf`, for messages embedded in some
+/// container format. Returns the message and the number of bytes it takes up, segment table
+/// included, so that whatever follows it starts at `offset` plus that length. If `trailing` is
+/// `TrailingBytes::Reject`, the message must extend to the end of `buf`.
+///
+/// The message is read in place if it is 8-byte aligned or if the "unaligned" feature is
+/// enabled, and is otherwise copied into aligned memory.
+pub fn read_message_from_bytes_at<'a>(buf: &'a [u8], offset: usize, trailing: TrailingBytes,
+                                      options: message::ReaderOptions)
+                                      -> Result<(message::Reader<BufferSegments<'a>>, usize)> {
+    if offset > buf.len() {
+        return Err(Error::failed(
+            format!("Offset {} is beyond the end of a buffer of {} bytes.", offset, buf.len())));
+    }
+    let mut remaining = &buf[offset..];
+    let message = read_message_from_flat_slice(&mut remaining, options)?;
+    let consumed = buf.len() - offset - remaining.len();
+    if trailing == TrailingBytes::Reject && !remaining.is_empty() {
+        return Err(Error::failed(
+            format!("Message is followed by {} unexpected bytes.", remaining.len())));
+    }
+
+    let aligned = cfg!(feature = "unaligned") || (buf[offset..].as_ptr() as usize) % BYTES_PER_WORD == 0;
+    let segments = if aligned {
+        BufferSegments::Borrowed(message.into_segments())
+    } else {
+        let copied = read_message(&buf[offset..(offset + consumed)], options)?;
+        BufferSegments::Copied(copied.into_segments())
+    };
+    Ok((message::Reader::new(segments, options), consumed))
+}
+
 /// Owned memory containing a message's segments sequentialized in a single contiguous buffer.
 /// The segments are guaranteed to be 8-byte aligned.
 pub struct OwnedSegments {
@@ -179,22 +250,93 @@ impl SegmentLengthsBuilder {
     }
 }
 
+/// Why a message could not be read from a stream by `try_read_framed_message()`. This lets a
+/// caller tell a broken stream, which should be closed, from a well-formed record that is merely
+/// over the configured limits, whose body is still sitting in the stream unread.
+#[derive(Debug)]
+pub enum FramingError {
+    /// The stream ended in the middle of the segment table or of the segments.
+    StreamTooShort,
+
+    /// The segment table declares more segments than `ReaderOptions::segment_count_limit` allows.
+    TooManySegments { count: u64, limit: u32 },
+
+    /// The segments add up to more words than the traversal limit in `ReaderOptions` allows, or
+    /// than can be addressed on this platform. `words` is the size of the whole message body, so
+    /// the record can be skipped by discarding `words * 8` bytes.
+    MessageTooLarge { words: u64, limit: u64 },
+
+    /// Reading from the stream failed.
+    Read(Error),
+}
+
+fn addressable_words() -> u64 {
+    (usize::max_value() / BYTES_PER_WORD) as u64
+}
+
+impl core::fmt::Display for FramingError {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::result::Result<(), core::fmt::Error> {
+        match self {
+            FramingError::StreamTooShort => write!(fmt, "Premature end of file"),
+            FramingError::TooManySegments { count, limit } =>
+                write!(fmt, "Too many segments: {}. The limit is {}; to increase it on the \
+                             receiving end, see capnp::message::ReaderOptions.", count, limit),
+            FramingError::MessageTooLarge { words, limit } if *limit == addressable_words() =>
+                write!(fmt, "Message has {} words, which is too large to address on this platform.",
+                       words),
+            FramingError::MessageTooLarge { words, .. } =>
+                write!(fmt, "Message has {} words, which is too large. To increase the limit on the \
+                             receiving end, see capnp::message::ReaderOptions.", words),
+            FramingError::Read(e) => write!(fmt, "{}", e),
+        }
+    }
+}
+
+#[cfg(feature="std")]
+impl std::error::Error for FramingError {}
+
+impl core::convert::From<Error> for FramingError {
+    fn from(e: Error) -> FramingError {
+        FramingError::Read(e)
+    }
+}
+
+impl core::convert::From<FramingError> for Error {
+    fn from(e: FramingError) -> Error {
+        match e {
+            FramingError::Read(e) => e,
+            e => Error::failed(e.to_string()),
+        }
+    }
+}
+
 /// Reads a serialized message from a stream with the provided options.
 ///
-/// For optimal performance, `read` should be a buffered reader type.
-pub fn read_message<R>(mut read: R, options: message::ReaderOptions) -> Result<message::Reader<OwnedSegments>>
+/// The segment table is read first, so that a single buffer of the right size can be allocated
+/// for all of the segments, and then the body is read directly into that buffer. The body is
+/// therefore copied only once, from `read` into the message. Buffering `read` still helps with
+/// the small reads of the segment table; a `std::io::BufReader` passes reads that are larger
+/// than its buffer straight through, so it adds no copy of the body.
+pub fn read_message<R>(read: R, options: message::ReaderOptions) -> Result<message::Reader<OwnedSegments>>
 where R: Read {
-    let owned_segments_builder = match read_segment_table(&mut read, options)? {
-        Some(b) => b,
-        None => return Err(Error::failed("Premature end of file".to_string())),
-    };
-    read_segments(&mut read, owned_segments_builder.into_owned_segments(), options)
+    match try_read_framed_message(read, options)? {
+        Some(message) => Ok(message),
+        None => Err(FramingError::StreamTooShort.into()),
+    }
 }
 
 /// Like read_message(), but returns None instead of an error if there are zero bytes left in `read`.
 /// This is useful for reading a message stream of unknown length -- you call this function
 /// until it returns None.
-pub fn try_read_message<R>(mut read: R, options: message::ReaderOptions) -> Result<Option<message::Reader<OwnedSegments>>>
+pub fn try_read_message<R>(read: R, options: message::ReaderOptions) -> Result<Option<message::Reader<OwnedSegments>>>
+where R: Read {
+    Ok(try_read_framed_message(read, options)?)
+}
+
+/// Like `try_read_message()`, but reports failures as a `FramingError`, so that the caller can
+/// decide how to respond to each kind of failure.
+pub fn try_read_framed_message<R>(mut read: R, options: message::ReaderOptions)
+                                  -> core::result::Result<Option<message::Reader<OwnedSegments>>, FramingError>
 where R: Read {
     let owned_segments_builder = match read_segment_table(&mut read, options)? {
         Some(b) => b,
@@ -203,6 +345,20 @@ where R: Read {
     Ok(Some(read_segments(&mut read, owned_segments_builder.into_owned_segments(), options)?))
 }
 
+/// Fills `buf` from `read`, failing with `FramingError::StreamTooShort` if `read` runs dry first.
+fn read_exact_framed<R>(read: &mut R, mut buf: &mut [u8]) -> core::result::Result<(), FramingError>
+where R: Read {
+    while !buf.is_empty() {
+        let n = read.read(buf)?;
+        if n == 0 {
+            return Err(FramingError::StreamTooShort)
+        }
+        let tmp = buf;
+        buf = &mut tmp[n..];
+    }
+    Ok(())
+}
+
 /// Reads a segment table from `read` and returns the total number of words across all
 /// segments, as well as the segment offsets.
 ///
@@ -210,7 +366,7 @@ where R: Read {
 /// [encoding spec](https://capnproto.org/encoding.html)
 fn read_segment_table<R>(read: &mut R,
                          options: message::ReaderOptions)
-                         -> Result<Option<SegmentLengthsBuilder>>
+                         -> core::result::Result<Option<SegmentLengthsBuilder>, FramingError>
     where R: Read
 {
     // read the first Word, which contains segment_count and the 1st segment length
@@ -221,35 +377,43 @@ fn read_segment_table<R>(read: &mut R,
             // Clean EOF on message boundary
             return Ok(None)
         } else if n < 8 {
-            read.read_exact(&mut buf[n..])?;
+            read_exact_framed(read, &mut buf[n..])?;
         }
     }
 
-    let segment_count = u32::from_le_bytes(buf[0..4].try_into().unwrap()).wrapping_add(1) as usize;
+    let segment_count = u32::from_le_bytes(buf[0..4].try_into().unwrap()) as u64 + 1;
 
-    if segment_count >= 512 {
-        return Err(Error::failed(format!("Too many segments: {}", segment_count)))
-    } else if segment_count == 0 {
-        return Err(Error::failed(format!("Too few segments: {}", segment_count)))
+    if segment_count > options.segment_count_limit as u64 {
+        return Err(FramingError::TooManySegments { count: segment_count,
+                                                   limit: options.segment_count_limit })
     }
+    let segment_count = segment_count as usize;
 
+    // The total is computed in 64 bits so that it cannot wrap on 32-bit targets, where many
+    // segments of 2^32 - 1 words each could otherwise overflow a `usize`.
+    let first_length = u32::from_le_bytes(buf[4..8].try_into().unwrap());
+    let mut total_words = first_length as u64;
     let mut segment_lengths_builder = SegmentLengthsBuilder::with_capacity(segment_count);
-    segment_lengths_builder.push_segment(u32::from_le_bytes(buf[4..8].try_into().unwrap()) as usize);
-    if segment_count > 1 {
-        if segment_count < 4 {
-            read.read_exact(&mut buf)?;
-            for idx in 0..(segment_count - 1) {
-                let segment_len =
-                    u32::from_le_bytes(buf[(idx * 4)..(idx + 1) * 4].try_into().unwrap()) as usize;
-                segment_lengths_builder.push_segment(segment_len);
-            }
-        } else {
-            let mut segment_sizes = vec![0u8; (segment_count & !1) * 4];
-            read.read_exact(&mut segment_sizes[..])?;
-            for idx in 0..(segment_count - 1) {
-                let segment_len =
-                    u32::from_le_bytes(segment_sizes[(idx * 4)..(idx + 1) * 4].try_into().unwrap()) as usize;
-                segment_lengths_builder.push_segment(segment_len);
+    segment_lengths_builder.push_segment(first_length as usize);
+
+    // The rest of the table holds one length per remaining segment, padded to a whole word. It
+    // is read a chunk at a time into a buffer on the stack.
+    let mut table_bytes_left = (segment_count & !1) * 4;
+    let mut lengths_left = segment_count - 1;
+    let mut chunk = [0u8; 64];
+    while table_bytes_left > 0 {
+        let chunk_len = core::cmp::min(table_bytes_left, chunk.len());
+        read_exact_framed(read, &mut chunk[..chunk_len])?;
+        table_bytes_left -= chunk_len;
+        // Skips the padding after an odd number of remaining lengths.
+        for length in chunk[..chunk_len].chunks_exact(4).take(lengths_left) {
+            let length = u32::from_le_bytes(length.try_into().unwrap());
+            total_words += length as u64;
+            lengths_left -= 1;
+            // Once the total is too large the message is rejected below, so there's no need to
+            // keep pushing (and risk overflowing the builder's `usize` total).
+            if total_words <= addressable_words() {
+                segment_lengths_builder.push_segment(length as usize);
             }
         }
     }
@@ -257,10 +421,13 @@ fn read_segment_table<R>(read: &mut R,
     // Don't accept a message which the receiver couldn't possibly traverse without hitting the
     // traversal limit. Without this check, a malicious client could transmit a very large segment
     // size to make the receiver allocate excessive space and possibly crash.
-    if segment_lengths_builder.total_words() as u64 > options.traversal_limit_in_words  {
-        return Err(Error::failed(
-            format!("Message has {} words, which is too large. To increase the limit on the \
-             receiving end, see capnp::message::ReaderOptions.", segment_lengths_builder.total_words())))
+    if total_words > options.traversal_limit_in_words  {
+        return Err(FramingError::MessageTooLarge { words: total_words,
+                                                   limit: options.traversal_limit_in_words })
+    }
+
+    if total_words > addressable_words() {
+        return Err(FramingError::MessageTooLarge { words: total_words, limit: addressable_words() })
     }
 
     Ok(Some(segment_lengths_builder))
@@ -270,9 +437,9 @@ fn read_segment_table<R>(read: &mut R,
 fn read_segments<R>(read: &mut R,
                     mut owned_segments: OwnedSegments,
                     options: message::ReaderOptions)
-                    -> Result<message::Reader<OwnedSegments>>
+                    -> core::result::Result<message::Reader<OwnedSegments>, FramingError>
 where R: Read {
-    read.read_exact(&mut owned_segments[..])?;
+    read_exact_framed(read, &mut owned_segments[..])?;
     Ok(crate::message::Reader::new(owned_segments, options))
 }
 
@@ -295,7 +462,7 @@ fn flatten_segments<R: message::ReaderSegments + ?Sized>(segments: &R) -> Vec<u8
     let word_count = compute_serialized_size(segments);
     let segment_count = segments.len();
     let table_size = segment_count / 2 + 1;
-    let mut result = Vec::with_capacity(word_count);
+    let mut result = Vec::with_capacity(word_count * BYTES_PER_WORD);
     for _ in 0..(table_size * BYTES_PER_WORD) {
         result.push(0);
     }
@@ -326,7 +493,7 @@ pub fn write_message<W, A>(mut write: W, message: &message::Builder<A>) -> Resul
 /// Like `write_message()`, but takes a `ReaderSegments`, allowing it to be
 /// used on `message::Reader` objects (via `into_segments()`).
 pub fn write_message_segments<W, R>(mut write: W, segments: &R) -> Result<()>
- where W: Write, R: message::ReaderSegments {
+ where W: Write, R: message::ReaderSegments + ?Sized {
     write_segment_table_internal(&mut write, segments)?;
     write_segments(&mut write, segments)
 }
@@ -393,20 +560,100 @@ fn compute_serialized_size<R: message::ReaderSegments + ?Sized>(segments: &R) ->
     let mut size = (len / 2) + 1;
     for i in 0..len {
         let segment = segments.get_segment(i as u32).unwrap();
-        size += segment.len();
+        size += segment.len() / BYTES_PER_WORD;
     }
     size
 }
 
-/// Returns the number of words required to serialize the message.
+/// Returns the number of words required to serialize the message, including the segment
+/// table. This is exactly the amount of data that `write_message()` will emit, so it can be
+/// used to reserve an output buffer, write a length prefix, or enforce a size limit before
+/// doing any I/O.
 pub fn compute_serialized_size_in_words<A>(message: &crate::message::Builder<A>) -> usize
     where A: crate::message::Allocator
 {
     compute_serialized_size(&message.get_segments_for_output())
 }
 
+/// How far `AsyncWriteState::next_chunk()` got.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Progress {
+    /// This many bytes were copied into the buffer, and more of the message remains.
+    Partial(usize),
+
+    /// This many bytes were copied into the buffer, and the message is now fully written.
+    Done(usize),
+}
+
+/// Serializes a message, segment table included, into buffers supplied by the caller one chunk
+/// at a time. This lets a non-blocking sender write whatever its socket will take right now and
+/// pick up where it left off later, without first flattening the whole message into one buffer
+/// as `write_message_to_words()` does.
+///
+/// `segments` can be a `&message::Builder`, or any other `ReaderSegments`.
+pub struct AsyncWriteState<S> where S: message::ReaderSegments {
+    segments: S,
+    segment_table: Vec<u8>,
+
+    // Zero for the segment table, otherwise one more than the index of the current segment.
+    piece: usize,
+
+    // Bytes of the current piece that have already been handed out.
+    offset: usize,
+}
+
+impl <S> AsyncWriteState<S> where S: message::ReaderSegments {
+    pub fn new(segments: S) -> AsyncWriteState<S> {
+        let mut segment_table = Vec::new();
+        write_segment_table_internal(&mut segment_table, &segments)
+            .expect("writing to a Vec cannot fail");
+        AsyncWriteState { segments, segment_table, piece: 0, offset: 0 }
+    }
+
+    fn current_piece(&self) -> Option<&[u8]> {
+        if self.piece == 0 {
+            Some(&self.segment_table[..])
+        } else {
+            self.segments.get_segment((self.piece - 1) as u32)
+        }
+    }
+
+    /// Copies as much of the rest of the message as fits into `buf`.
+    pub fn next_chunk(&mut self, buf: &mut [u8]) -> Progress {
+        let mut written = 0;
+        loop {
+            let (piece_len, n) = match self.current_piece() {
+                None => return Progress::Done(written),
+                Some(piece) => {
+                    let n = core::cmp::min(piece.len() - self.offset, buf.len() - written);
+                    buf[written..written + n].copy_from_slice(&piece[self.offset..self.offset + n]);
+                    (piece.len(), n)
+                }
+            };
+            written += n;
+            self.offset += n;
+            if self.offset == piece_len {
+                self.piece += 1;
+                self.offset = 0;
+            } else {
+                return Progress::Partial(written);
+            }
+        }
+    }
+
+    /// Returns true once the whole message has been handed out by `next_chunk()`.
+    pub fn is_done(&self) -> bool {
+        self.current_piece().is_none()
+    }
+
+    pub fn into_segments(self) -> S {
+        self.segments
+    }
+}
+
 #[cfg(test)]
 pub mod test {
+    use alloc::string::ToString;
     use alloc::vec::Vec;
 
     use crate::io::{Write, Read};
@@ -416,7 +663,8 @@ pub mod test {
     use crate::message;
     use crate::message::ReaderSegments;
     use super::{read_message, try_read_message, read_message_from_flat_slice, flatten_segments,
-                read_segment_table, write_segment_table, write_segments};
+                read_segment_table, write_segment_table, write_segments, write_message,
+                compute_serialized_size, compute_serialized_size_in_words};
 
     /// Writes segments as if they were a Capnproto message.
     pub fn write_message_segments<W>(write: &mut W, segments: &Vec<Vec<crate::Word>>) where W: Write {
@@ -489,6 +737,22 @@ pub mod test {
         assert_eq!(200, segment_lengths_builder.total_words());
         assert_eq!(vec![(0,77), (77, 100), (100, 101), (101, 200)], segment_lengths_builder.to_segment_indices());
         buf.clear();
+
+        // A table that spans several reads of the stack buffer, followed by the first segment.
+        buf.extend([40,0,0,0].iter().cloned()); // 41 segments
+        for length in 1..42u32 {
+            buf.extend(length.to_le_bytes().iter().cloned());
+        }
+        buf.extend([0xff; 8].iter().cloned());
+        let mut read = &buf[..];
+        let segment_lengths_builder = read_segment_table(&mut read,
+                                                         message::ReaderOptions::new()).unwrap().unwrap();
+        assert_eq!(41 * 42 / 2, segment_lengths_builder.total_words());
+        let segment_indices = segment_lengths_builder.to_segment_indices();
+        assert_eq!(41, segment_indices.len());
+        assert_eq!((820, 861), segment_indices[40]);
+        assert_eq!(&[0xff; 8][..], read);
+        buf.clear();
     }
 
     struct MaxRead<R> where R: Read {
@@ -546,6 +810,64 @@ pub mod test {
         buf.clear();
     }
 
+    #[test]
+    fn framing_errors() {
+        use super::{try_read_framed_message, FramingError};
+
+        // Truncated inside the segment table, and inside the segments.
+        let buf: &[u8] = &[1,0,0,0, 1,0,0,0, 1,0];
+        match try_read_framed_message(buf, message::ReaderOptions::new()) {
+            Err(FramingError::StreamTooShort) => (),
+            r => panic!("expected StreamTooShort, got {:?}", r.map(|_| ())),
+        }
+        let buf: &[u8] = &[0,0,0,0, 2,0,0,0, 0,0,0,0,0,0,0,0];
+        match try_read_framed_message(buf, message::ReaderOptions::new()) {
+            Err(FramingError::StreamTooShort) => (),
+            r => panic!("expected StreamTooShort, got {:?}", r.map(|_| ())),
+        }
+
+        // Four segments, which is over a limit of three.
+        let buf: &[u8] = &[3,0,0,0, 1,0,0,0, 1,0,0,0, 1,0,0,0, 1,0,0,0, 0,0,0,0];
+        let mut options = message::ReaderOptions::new();
+        options.segment_count_limit(3);
+        match try_read_framed_message(buf, options) {
+            Err(FramingError::TooManySegments { count: 4, limit: 3 }) => (),
+            r => panic!("expected TooManySegments, got {:?}", r.map(|_| ())),
+        }
+        assert!(try_read_framed_message(buf, message::ReaderOptions::new()).is_err());
+
+        // A header of all ones claims 2^32 segments.
+        let buf: &[u8] = &[255,255,255,255, 0,0,0,0];
+        match try_read_framed_message(buf, message::ReaderOptions::new()) {
+            Err(FramingError::TooManySegments { count: 0x1_0000_0000, limit: 511 }) => (),
+            r => panic!("expected TooManySegments, got {:?}", r.map(|_| ())),
+        }
+
+        // The whole body is reported, even though the first segment is already over the limit.
+        let buf: &[u8] = &[1,0,0,0, 10,0,0,0, 20,0,0,0, 0,0,0,0];
+        let mut options = message::ReaderOptions::new();
+        options.traversal_limit_in_words(5);
+        match try_read_framed_message(buf, options) {
+            Err(e @ FramingError::MessageTooLarge { words: 30, limit: 5 }) => {
+                let e: crate::Error = e.into();
+                assert!(e.description.contains("too large"), "{}", e.description);
+            }
+            r => panic!("expected MessageTooLarge, got {:?}", r.map(|_| ())),
+        }
+
+        // Errors from the stream itself are passed through.
+        struct Failing;
+        impl Read for Failing {
+            fn read(&mut self, _buf: &mut [u8]) -> crate::Result<usize> {
+                Err(crate::Error::disconnected("gone".to_string()))
+            }
+        }
+        match try_read_framed_message(Failing, message::ReaderOptions::new()) {
+            Err(FramingError::Read(e)) => assert_eq!(e.kind, crate::ErrorKind::Disconnected),
+            r => panic!("expected Read, got {:?}", r.map(|_| ())),
+        }
+    }
+
     #[test]
     fn test_write_segment_table() {
 
@@ -645,6 +967,36 @@ pub mod test {
         quickcheck(round_trip as fn(Vec<Vec<crate::Word>>) -> TestResult);
     }
 
+    #[test]
+    #[cfg_attr(miri, ignore)] // miri takes a long time with quickcheck
+    fn check_compute_serialized_size() {
+        fn serialized_size(segments: Vec<Vec<crate::Word>>) -> TestResult {
+            if segments.len() == 0 { return TestResult::discard(); }
+            let borrowed_segments: &[&[u8]] = &segments.iter()
+                                                       .map(|segment| crate::Word::words_to_bytes(&segment[..]))
+                .collect::<Vec<_>>()[..];
+            let bytes = flatten_segments(borrowed_segments);
+            TestResult::from_bool(compute_serialized_size(borrowed_segments) * 8 == bytes.len())
+        }
+
+        quickcheck(serialized_size as fn(Vec<Vec<crate::Word>>) -> TestResult);
+    }
+
+    #[test]
+    fn compute_serialized_size_of_builder() {
+        let mut message = message::Builder::new(message::HeapAllocator::new().first_segment_words(1));
+        {
+            let root: crate::any_pointer::Builder = message.init_root();
+            let mut list: crate::primitive_list::Builder<u64> = root.initn_as(10);
+            list.set(9, 0x1234);
+        }
+        assert!(message.get_segments_for_output().len() > 1);
+
+        let mut buf: Vec<u8> = Vec::new();
+        write_message(&mut buf, &message).unwrap();
+        assert_eq!(compute_serialized_size_in_words(&message) * 8, buf.len());
+    }
+
     #[test]
     fn read_message_from_flat_slice_with_remainder() {
         let segments = vec![vec![123,0,0,0,0,0,0,0],
@@ -685,4 +1037,94 @@ pub mod test {
             assert!(read_message_from_flat_slice(&mut &bytes[..], message::ReaderOptions::new()).is_err());
         }
     }
+
+    #[test]
+    fn utf8_policy() {
+        use crate::{any_pointer, text};
+        let mut message = message::Builder::new_default();
+        message.init_root::<any_pointer::Builder>().set_as(&b"ok\xff!\0"[..]).unwrap();
+        let bytes = super::write_message_to_words(&message);
+
+        let strict = read_message_from_flat_slice(&mut &bytes[..], message::ReaderOptions::new()).unwrap();
+        let root = strict.get_root::<any_pointer::Reader>().unwrap();
+        assert!(root.get_as::<text::Reader>().is_err());
+        assert_eq!(root.get_text_bytes().unwrap(), b"ok\xff!");
+
+        let mut options = message::ReaderOptions::new();
+        options.utf8_policy(message::Utf8Policy::Lenient);
+        let lenient = read_message_from_flat_slice(&mut &bytes[..], options).unwrap();
+        let root = lenient.get_root::<any_pointer::Reader>().unwrap();
+        assert_eq!(root.get_as::<text::Reader>().unwrap(), "ok");
+        assert_eq!(root.get_text_bytes().unwrap(), b"ok\xff!");
+    }
+
+    #[test]
+    fn read_message_from_bytes_at() {
+        use crate::{any_pointer, text};
+        use super::{BufferSegments, TrailingBytes};
+        let mut builder = message::Builder::new_default();
+        builder.init_root::<any_pointer::Builder>().set_as("embedded").unwrap();
+        let words = super::write_message_to_words(&builder);
+
+        for &offset in &[8, 3] {
+            let mut buf: Vec<u8> = crate::Word::words_to_bytes(&crate::Word::allocate_zeroed_vec(8)).to_vec();
+            buf.truncate(offset);
+            buf.extend_from_slice(&words);
+            buf.extend_from_slice(b"trailer");
+            let (message, consumed) = super::read_message_from_bytes_at(
+                &buf, offset, TrailingBytes::Allow, message::ReaderOptions::new()).unwrap();
+            assert_eq!(consumed, words.len());
+            assert_eq!(&buf[offset + consumed..], b"trailer");
+            let root = message.get_root::<any_pointer::Reader>().unwrap();
+            assert_eq!(root.get_as::<text::Reader>().unwrap(), "embedded");
+            match message.into_segments() {
+                BufferSegments::Borrowed(_) => assert!(offset % 8 == 0 || cfg!(feature = "unaligned")),
+                BufferSegments::Copied(_) => assert!(offset % 8 != 0),
+            }
+            assert!(super::read_message_from_bytes_at(
+                &buf, offset, TrailingBytes::Reject, message::ReaderOptions::new()).is_err());
+        }
+        assert!(super::read_message_from_bytes_at(
+            &words, words.len() + 1, TrailingBytes::Allow, message::ReaderOptions::new()).is_err());
+    }
+
+    #[test]
+    fn async_write_state() {
+        use crate::{any_pointer, text_list};
+        use super::{AsyncWriteState, Progress};
+
+        let mut builder = message::Builder::new(message::HeapAllocator::new().small_segments());
+        {
+            let mut list = builder.init_root::<any_pointer::Builder>().initn_as::<text_list::Builder>(3);
+            list.set(0, "one");
+            list.set(1, "two");
+            list.set(2, "three, which is longer than a word");
+        }
+        let expected = super::write_message_to_words(&builder);
+
+        for &chunk_size in &[1, 3, 8, 13, 1000] {
+            let mut state = AsyncWriteState::new(&builder);
+            let mut out = Vec::new();
+            let mut buf = vec![0u8; chunk_size];
+            loop {
+                match state.next_chunk(&mut buf) {
+                    Progress::Partial(n) => {
+                        assert_eq!(n, chunk_size);
+                        out.extend_from_slice(&buf[..n]);
+                    }
+                    Progress::Done(n) => {
+                        out.extend_from_slice(&buf[..n]);
+                        break;
+                    }
+                }
+            }
+            assert!(state.is_done());
+            assert_eq!(state.next_chunk(&mut buf), Progress::Done(0));
+            assert_eq!(out, expected);
+        }
+
+        let mut state = AsyncWriteState::new(&builder);
+        assert_eq!(state.next_chunk(&mut []), Progress::Partial(0));
+        assert!(!state.is_done());
+    }
 }
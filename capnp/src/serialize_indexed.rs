@@ -0,0 +1,350 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Files containing many messages, with an index that allows random access to any of them.
+//!
+//! The layout of such a file is:
+//!
+//! ```text
+//! message 0
+//! message 1
+//! ...
+//! message n-1
+//! index: n entries of (offset: u64, length: u64), in bytes from the start of the file
+//! footer: index offset (u64), n (u64), flags (u32), magic "cpix" (4 bytes)
+//! ```
+//!
+//! All integers are little-endian. Each message is written with the
+//! [standard stream framing](https://capnproto.org/encoding.html#serialization-over-a-stream),
+//! or with the [packed encoding](https://capnproto.org/encoding.html#packing) if bit 0 of the
+//! flags is set.
+//!
+//! `IndexedReader` operates on a byte slice, such as a memory-mapped file. Unpacked messages
+//! start at word-aligned offsets, so if the slice is 8-byte aligned they can be read without
+//! copying.
+
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+use crate::io::Write;
+use crate::message;
+use crate::serialize::{self, OwnedSegments, SliceSegments};
+use crate::serialize_packed;
+use crate::{Error, Result};
+
+const MAGIC: [u8; 4] = *b"cpix";
+const FLAG_PACKED: u32 = 1;
+const INDEX_ENTRY_BYTES: u64 = 16;
+const FOOTER_BYTES: u64 = 24;
+
+/// Writes messages followed by an index. The file is not valid until `finish()` is called.
+pub struct IndexedWriter<W> where W: Write {
+    inner: W,
+    packed: bool,
+    offset: u64,
+    index: Vec<(u64, u64)>,
+}
+
+impl <W> IndexedWriter<W> where W: Write {
+    /// Creates a writer that uses the standard (unpacked) framing for each message.
+    pub fn new(write: W) -> IndexedWriter<W> {
+        IndexedWriter { inner: write, packed: false, offset: 0, index: Vec::new() }
+    }
+
+    /// Creates a writer that uses the packed encoding for each message.
+    pub fn new_packed(write: W) -> IndexedWriter<W> {
+        IndexedWriter { inner: write, packed: true, offset: 0, index: Vec::new() }
+    }
+
+    /// Returns the number of messages written so far.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Appends a message to the file.
+    pub fn write_message<A>(&mut self, message: &message::Builder<A>) -> Result<()>
+        where A: message::Allocator
+    {
+        let mut length = 0;
+        {
+            let counting = CountingWrite { inner: &mut self.inner, count: &mut length };
+            if self.packed {
+                serialize_packed::write_message(counting, message)?;
+            } else {
+                serialize::write_message(counting, message)?;
+            }
+        }
+        if self.packed && length % 8 != 0 {
+            // Keep every message word-aligned.
+            let padding = [0u8; 8];
+            let padding_len = 8 - (length % 8);
+            self.inner.write_all(&padding[..padding_len as usize])?;
+            self.index.push((self.offset, length));
+            self.offset += length + padding_len;
+        } else {
+            self.index.push((self.offset, length));
+            self.offset += length;
+        }
+        Ok(())
+    }
+
+    /// Writes the index and footer, and returns the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        let index_offset = self.offset;
+        for &(offset, length) in &self.index {
+            self.inner.write_all(&offset.to_le_bytes())?;
+            self.inner.write_all(&length.to_le_bytes())?;
+        }
+        self.inner.write_all(&index_offset.to_le_bytes())?;
+        self.inner.write_all(&(self.index.len() as u64).to_le_bytes())?;
+        let flags = if self.packed { FLAG_PACKED } else { 0 };
+        self.inner.write_all(&flags.to_le_bytes())?;
+        self.inner.write_all(&MAGIC)?;
+        Ok(self.inner)
+    }
+}
+
+struct CountingWrite<'a, W> where W: Write {
+    inner: &'a mut W,
+    count: &'a mut u64,
+}
+
+impl <'a, W> Write for CountingWrite<'a, W> where W: Write {
+    fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        self.inner.write_all(buf)?;
+        *self.count += buf.len() as u64;
+        Ok(())
+    }
+}
+
+/// Segments of a message read from an `IndexedReader`.
+pub enum IndexedSegments<'a> {
+    /// An unpacked message, referring directly to the underlying slice.
+    Slice(SliceSegments<'a>),
+
+    /// A packed message, which had to be decoded into owned memory.
+    Owned(OwnedSegments),
+}
+
+impl <'a> message::ReaderSegments for IndexedSegments<'a> {
+    fn get_segment<'b>(&'b self, id: u32) -> Option<&'b [u8]> {
+        match *self {
+            IndexedSegments::Slice(ref s) => s.get_segment(id),
+            IndexedSegments::Owned(ref s) => s.get_segment(id),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match *self {
+            IndexedSegments::Slice(ref s) => s.len(),
+            IndexedSegments::Owned(ref s) => s.len(),
+        }
+    }
+}
+
+/// Provides random access to the messages of a file written by `IndexedWriter`.
+pub struct IndexedReader<'a> {
+    data: &'a [u8],
+    index: &'a [u8],
+    packed: bool,
+    options: message::ReaderOptions,
+}
+
+impl <'a> IndexedReader<'a> {
+    /// Validates the footer and index of `data`. Individual messages are not read until
+    /// they are requested.
+    pub fn new(data: &'a [u8], options: message::ReaderOptions) -> Result<IndexedReader<'a>> {
+        let len = data.len() as u64;
+        if len < FOOTER_BYTES {
+            return Err(Error::failed("Indexed message file is too short to contain a footer.".to_string()));
+        }
+        let footer = &data[(len - FOOTER_BYTES) as usize..];
+        if footer[20..24] != MAGIC {
+            return Err(Error::failed("Indexed message file has an invalid footer.".to_string()));
+        }
+        let index_offset = read_u64(&footer[0..8]);
+        let count = read_u64(&footer[8..16]);
+        let flags = u32::from_le_bytes(footer[16..20].try_into().unwrap());
+        if flags & !FLAG_PACKED != 0 {
+            return Err(Error::failed(format!("Indexed message file has unknown flags: {:#x}", flags)));
+        }
+
+        let index_len = count.checked_mul(INDEX_ENTRY_BYTES);
+        match index_len.and_then(|n| n.checked_add(index_offset)) {
+            Some(end) if end == len - FOOTER_BYTES => (),
+            _ => return Err(Error::failed(
+                format!("Indexed message file claims {} messages, which does not match its size.", count))),
+        }
+        let index = &data[index_offset as usize..(len - FOOTER_BYTES) as usize];
+        for entry in index.chunks(INDEX_ENTRY_BYTES as usize) {
+            let offset = read_u64(&entry[0..8]);
+            let length = read_u64(&entry[8..16]);
+            match offset.checked_add(length) {
+                Some(end) if end <= index_offset => (),
+                _ => return Err(Error::failed(
+                    "Indexed message file contains an out-of-bounds index entry.".to_string())),
+            }
+        }
+
+        Ok(IndexedReader {
+            data: data,
+            index: index,
+            packed: flags & FLAG_PACKED != 0,
+            options: options,
+        })
+    }
+
+    /// Returns the number of messages in the file.
+    pub fn len(&self) -> usize {
+        self.index.len() / INDEX_ENTRY_BYTES as usize
+    }
+
+    /// Returns true if the messages use the packed encoding.
+    pub fn is_packed(&self) -> bool {
+        self.packed
+    }
+
+    /// Returns the encoded bytes of message `index`, as they were written.
+    pub fn get_bytes(&self, index: usize) -> Result<&'a [u8]> {
+        if index >= self.len() {
+            return Err(Error::failed(
+                format!("Message index {} is out of bounds for a file of {} messages.", index, self.len())));
+        }
+        let entry = &self.index[index * INDEX_ENTRY_BYTES as usize..];
+        let offset = read_u64(&entry[0..8]) as usize;
+        let length = read_u64(&entry[8..16]) as usize;
+        Ok(&self.data[offset..offset + length])
+    }
+
+    /// Reads message `index`. Unpacked messages are read in place, without copying.
+    pub fn get(&self, index: usize) -> Result<message::Reader<IndexedSegments<'a>>> {
+        let mut bytes = self.get_bytes(index)?;
+        let message = if self.packed {
+            let message = serialize_packed::read_message(&mut bytes, self.options)?;
+            IndexedSegments::Owned(message.into_segments())
+        } else {
+            let message = serialize::read_message_from_flat_slice(&mut bytes, self.options)?;
+            IndexedSegments::Slice(message.into_segments())
+        };
+        Ok(message::Reader::new(message, self.options))
+    }
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use crate::{any_pointer, message, primitive_list};
+    use super::{IndexedReader, IndexedWriter};
+
+    fn build_message(n: u32) -> message::Builder<message::HeapAllocator> {
+        let mut message = message::Builder::new_default();
+        {
+            let root: any_pointer::Builder = message.init_root();
+            let mut list: primitive_list::Builder<u32> = root.initn_as(n);
+            for i in 0..n {
+                list.set(i, i * 1000);
+            }
+        }
+        message
+    }
+
+    fn check_list(message: &message::Reader<super::IndexedSegments>, n: u32) {
+        let root: any_pointer::Reader = message.get_root().unwrap();
+        let list: primitive_list::Reader<u32> = root.get_as().unwrap();
+        assert_eq!(list.len(), n);
+        for i in 0..n {
+            assert_eq!(list.get(i), i * 1000);
+        }
+    }
+
+    fn round_trip(packed: bool) {
+        let buf: Vec<u8> = Vec::new();
+        let mut writer = if packed { IndexedWriter::new_packed(buf) } else { IndexedWriter::new(buf) };
+        for n in 0..20 {
+            writer.write_message(&build_message(n)).unwrap();
+        }
+        assert_eq!(writer.len(), 20);
+        let buf = writer.finish().unwrap();
+
+        let words = crate::Word::words_from_bytes(&buf);
+        let reader = IndexedReader::new(crate::Word::words_to_bytes(&words), Default::default()).unwrap();
+        assert_eq!(reader.len(), 20);
+        assert_eq!(reader.is_packed(), packed);
+        for n in (0..20).rev() {
+            check_list(&reader.get(n as usize).unwrap(), n);
+        }
+        assert!(reader.get(20).is_err());
+    }
+
+    #[test]
+    fn round_trip_unpacked() {
+        round_trip(false);
+    }
+
+    #[test]
+    fn round_trip_packed() {
+        round_trip(true);
+    }
+
+    #[test]
+    fn empty_file() {
+        let buf = IndexedWriter::new(Vec::new()).finish().unwrap();
+        assert_eq!(buf.len(), 24);
+        let reader = IndexedReader::new(&buf, Default::default()).unwrap();
+        assert_eq!(reader.len(), 0);
+    }
+
+    #[test]
+    fn corrupt_files() {
+        let mut writer = IndexedWriter::new(Vec::new());
+        writer.write_message(&build_message(3)).unwrap();
+        let buf = writer.finish().unwrap();
+        assert!(IndexedReader::new(&buf, Default::default()).is_ok());
+
+        // truncated
+        for len in 0..buf.len() {
+            assert!(IndexedReader::new(&buf[..len], Default::default()).is_err());
+        }
+
+        // bad magic
+        let mut bad = buf.clone();
+        *bad.last_mut().unwrap() = b'?';
+        assert!(IndexedReader::new(&bad, Default::default()).is_err());
+
+        // message count that doesn't match the size of the index
+        let mut bad = buf.clone();
+        let count_offset = bad.len() - 16;
+        bad[count_offset] = 2;
+        assert!(IndexedReader::new(&bad, Default::default()).is_err());
+
+        // index entry pointing past the messages
+        let mut bad = buf.clone();
+        let length_offset = bad.len() - 24 - 8;
+        bad[length_offset] = 0xff;
+        assert!(IndexedReader::new(&bad, Default::default()).is_err());
+    }
+}
@@ -23,6 +23,7 @@
 //! [packed stream encoding](https://capnproto.org/encoding.html#packing).
 
 use alloc::string::ToString;
+use alloc::vec::Vec;
 use core::{mem, ptr, slice};
 use crate::io::{Read, BufRead, Write};
 
@@ -216,6 +217,34 @@ pub fn try_read_message<R>(read: R,
     serialize::try_read_message(packed_read, options)
 }
 
+/// Reads a packed message from a byte buffer, for environments without streams such as
+/// `wasm32-unknown-unknown`. The message is unpacked into newly allocated segments.
+pub fn read_message_from_slice(bytes: &[u8],
+                               options: message::ReaderOptions)
+                               -> Result<crate::message::Reader<serialize::OwnedSegments>>
+{
+    read_message(bytes, options)
+}
+
+/// Loads the eight bytes starting at `ptr` as a little-endian word. `ptr` need not be aligned.
+#[inline]
+unsafe fn read_word(ptr: *const u8) -> u64 {
+    u64::from_le(ptr::read_unaligned(ptr as *const u64))
+}
+
+/// Returns a byte whose `n`th bit is set if and only if the `n`th byte of `word` is nonzero.
+///
+/// This is computed a word at a time rather than by looping over the bytes: adding 0x7f to the
+/// low seven bits of each byte carries into its high bit exactly when those bits are nonzero,
+/// and the multiplication then gathers the eight high bits into the top byte.
+#[inline]
+fn nonzero_byte_mask(word: u64) -> u8 {
+    const LOW_SEVEN_BITS: u64 = 0x7f7f7f7f7f7f7f7f;
+    const HIGH_BITS: u64 = 0x8080808080808080;
+    let high = (((word & LOW_SEVEN_BITS) + LOW_SEVEN_BITS) | word) & HIGH_BITS;
+    ((high >> 7).wrapping_mul(0x0102040810204080) >> 56) as u8
+}
+
 struct PackedWrite<W> where W: Write {
     inner: W,
 }
@@ -242,49 +271,12 @@ impl <W> Write for PackedWrite<W> where W: Write {
                 let tag_pos = buf_idx;
                 buf_idx += 1;
 
-                let bit0 = (*in_ptr != 0) as u8;
-                *buf.get_unchecked_mut(buf_idx) = *in_ptr;
-                buf_idx += bit0 as usize;
-                in_ptr = in_ptr.offset(1);
-
-                let bit1 = (*in_ptr != 0) as u8;
-                *buf.get_unchecked_mut(buf_idx) = *in_ptr;
-                buf_idx += bit1 as usize;
-                in_ptr = in_ptr.offset(1);
-
-                let bit2 = (*in_ptr != 0) as u8;
-                *buf.get_unchecked_mut(buf_idx) = *in_ptr;
-                buf_idx += bit2 as usize;
-                in_ptr = in_ptr.offset(1);
-
-                let bit3 = (*in_ptr != 0) as u8;
-                *buf.get_unchecked_mut(buf_idx) = *in_ptr;
-                buf_idx += bit3 as usize;
-                in_ptr = in_ptr.offset(1);
-
-                let bit4 = (*in_ptr != 0) as u8;
-                *buf.get_unchecked_mut(buf_idx) = *in_ptr;
-                buf_idx += bit4 as usize;
-                in_ptr = in_ptr.offset(1);
-
-                let bit5 = (*in_ptr != 0) as u8;
-                *buf.get_unchecked_mut(buf_idx) = *in_ptr;
-                buf_idx += bit5 as usize;
-                in_ptr = in_ptr.offset(1);
-
-                let bit6 = (*in_ptr != 0) as u8;
-                *buf.get_unchecked_mut(buf_idx) = *in_ptr;
-                buf_idx += bit6 as usize;
-                in_ptr = in_ptr.offset(1);
-
-                let bit7 = (*in_ptr != 0) as u8;
-                *buf.get_unchecked_mut(buf_idx) = *in_ptr;
-                buf_idx += bit7 as usize;
-                in_ptr = in_ptr.offset(1);
-
-                let tag: u8 = (bit0 << 0) | (bit1 << 1) | (bit2 << 2) | (bit3 << 3)
-                            | (bit4 << 4) | (bit5 << 5) | (bit6 << 6) | (bit7 << 7);
-
+                let tag = nonzero_byte_mask(read_word(in_ptr));
+                for n in 0..8 {
+                    *buf.get_unchecked_mut(buf_idx) = *in_ptr;
+                    buf_idx += ((tag >> n) & 1) as usize;
+                    in_ptr = in_ptr.offset(1);
+                }
 
                 *buf.get_unchecked_mut(tag_pos) = tag;
 
@@ -293,18 +285,17 @@ impl <W> Write for PackedWrite<W> where W: Write {
                     //# consecutive zero words (not including the first
                     //# one).
 
-                    let mut in_word : *const [u8; 8] = in_ptr as *const [u8; 8];
-                    let mut limit : *const [u8; 8] = in_end as *const [u8; 8];
-                    if ptr_sub(limit, in_word) > 255 {
-                        limit = in_word.offset(255);
+                    let run_start = in_ptr;
+                    let mut limit = in_end;
+                    if ptr_sub(limit, in_ptr) > 255 * 8 {
+                        limit = in_ptr.offset(255 * 8);
                     }
-                    while in_word < limit && *in_word == [0;8] {
-                        in_word = in_word.offset(1);
+                    while in_ptr < limit && read_word(in_ptr) == 0 {
+                        in_ptr = in_ptr.offset(8);
                     }
 
-                    *buf.get_unchecked_mut(buf_idx) = ptr_sub(in_word, in_ptr as *const [u8; 8]) as u8;
+                    *buf.get_unchecked_mut(buf_idx) = (ptr_sub(in_ptr, run_start) / 8) as u8;
                     buf_idx += 1;
-                    in_ptr = in_word as *const u8;
                 } else if tag == 0xff {
                     //# An all-nonzero word is followed by a count of
                     //# consecutive uncompressed words, followed by the
@@ -321,19 +312,12 @@ impl <W> Write for PackedWrite<W> where W: Write {
                     }
 
                     while in_ptr < limit {
-                        let mut c = 0;
-
-                        for _ in 0..8 {
-                            c += (*in_ptr == 0) as u8;
-                            in_ptr = in_ptr.offset(1);
-                        }
-
-                        if c >= 2 {
-                            //# Un-read the word with multiple zeros, since
-                            //# we'll want to compress that one.
-                            in_ptr = in_ptr.offset(-8);
+                        if nonzero_byte_mask(read_word(in_ptr)).count_ones() <= 6 {
+                            //# This word has multiple zeros, so we'll want
+                            //# to compress it.
                             break;
                         }
+                        in_ptr = in_ptr.offset(8);
                     }
 
                     let count: usize = ptr_sub(in_ptr, run_start);
@@ -360,6 +344,15 @@ pub fn write_message<W, A>(write: W, message: &crate::message::Builder<A>) -> Re
     serialize::write_message(packed_write, message)
 }
 
+/// Writes a packed message to a new byte buffer.
+pub fn write_message_to_words<A>(message: &crate::message::Builder<A>) -> Vec<u8>
+    where A: crate::message::Allocator
+{
+    let mut result = Vec::new();
+    write_message(&mut result, message).expect("writing to a Vec cannot fail");
+    result
+}
+
 #[cfg(test)]
 mod tests {
     use alloc::string::ToString;
@@ -437,6 +430,25 @@ mod tests {
         check_packing(&[0,0,0,0,0,0,0,0, 0,0,0,0,0,0,0,0, 0,0,0,0,0,0,0,0], &[0,2]);
     }
 
+    #[test]
+    fn nonzero_byte_mask() {
+        fn check(word: crate::Word) -> TestResult {
+            let bytes = crate::Word::words_to_bytes(core::slice::from_ref(&word));
+            let mut expected = 0u8;
+            for (n, b) in bytes.iter().enumerate() {
+                if *b != 0 { expected |= 1 << n; }
+            }
+            let word = u64::from_le_bytes(word.raw_content);
+            assert_eq!(expected, super::nonzero_byte_mask(word));
+            TestResult::passed()
+        }
+        quickcheck(check as fn(crate::Word) -> TestResult);
+
+        check(crate::word(0, 0, 0, 0, 0, 0, 0, 0));
+        check(crate::word(0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff));
+        check(crate::word(0x80, 0, 0x7f, 0, 1, 0, 0x81, 0));
+    }
+
     #[test]
     #[cfg_attr(miri, ignore)] // miri takes a long time with quickcheck
     fn check_round_trip() {
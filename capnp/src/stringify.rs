@@ -0,0 +1,231 @@
+// Copyright (c) 2013-2017 Sandstorm Development Group, Inc. and contributors
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+
+//! Writing values in the Cap'n Proto text format.
+//!
+//! Generated struct readers implement `core::fmt::Display` through `Stringify`, so that
+//! `format!("{}", reader)` gives the same text as the `capnp-rust decode` command, which can be
+//! read back with `capnp-rust encode`.
+
+use core::fmt::{self, Write};
+
+use crate::traits::{FromU16, OwnedStruct, Owned, FromPointerReader};
+use crate::private::layout::PrimitiveElement;
+use crate::capability::FromClientHook;
+use crate::{any_pointer, any_pointer_list, capability_list, data_list, enum_list, list_list,
+            primitive_list, struct_list, text_list, NotInSchema};
+
+/// A value that can be written in text format.
+pub trait Stringify {
+    fn stringify(&self, w: &mut dyn Write) -> fmt::Result;
+}
+
+/// Writes a float, spelling out the special values the way the schema language does.
+pub fn write_float(w: &mut dyn Write, n: f64) -> fmt::Result {
+    if n.is_nan() {
+        w.write_str("nan")
+    } else if n.is_infinite() {
+        w.write_str(if n < 0.0 { "-inf" } else { "inf" })
+    } else {
+        write!(w, "{}", n)
+    }
+}
+
+/// Writes a quoted string literal. Data is passed one byte per `char`, with `binary` set so that
+/// bytes outside of the ASCII range are escaped rather than taken for Latin-1 characters.
+pub fn write_string<I>(w: &mut dyn Write, chars: I, binary: bool) -> fmt::Result
+    where I: Iterator<Item = char>
+{
+    w.write_char('"')?;
+    for c in chars {
+        match c {
+            '\n' => w.write_str("\\n")?,
+            '\r' => w.write_str("\\r")?,
+            '\t' => w.write_str("\\t")?,
+            '\\' => w.write_str("\\\\")?,
+            '"' => w.write_str("\\\"")?,
+            c if (c as u32) < 0x20 || c as u32 == 0x7f || (binary && c as u32 > 0x7f) => {
+                write!(w, "\\x{:02x}", c as u32)?
+            }
+            c => w.write_char(c)?,
+        }
+    }
+    w.write_char('"')
+}
+
+/// Writes `name = value`, preceded by a separator unless `first` is set, which it clears.
+/// Used by generated code to write the fields of a struct.
+pub fn write_field(w: &mut dyn Write, first: &mut bool, name: &str, value: &dyn Stringify) -> fmt::Result {
+    if !*first {
+        w.write_str(", ")?;
+    }
+    *first = false;
+    w.write_str(name)?;
+    w.write_str(" = ")?;
+    value.stringify(w)
+}
+
+/// Stands in for an interface field, whose value cannot be written.
+pub struct Capability;
+
+impl Stringify for Capability {
+    fn stringify(&self, w: &mut dyn Write) -> fmt::Result {
+        w.write_str("<external capability>")
+    }
+}
+
+impl <'a> Stringify for any_pointer::Reader<'a> {
+    fn stringify(&self, w: &mut dyn Write) -> fmt::Result {
+        w.write_str("<opaque pointer>")
+    }
+}
+
+impl Stringify for () {
+    fn stringify(&self, w: &mut dyn Write) -> fmt::Result {
+        w.write_str("void")
+    }
+}
+
+impl Stringify for bool {
+    fn stringify(&self, w: &mut dyn Write) -> fmt::Result {
+        w.write_str(if *self { "true" } else { "false" })
+    }
+}
+
+macro_rules! stringify_integer {
+    ($($t:ty),*) => {
+        $(impl Stringify for $t {
+            fn stringify(&self, w: &mut dyn Write) -> fmt::Result {
+                write!(w, "{}", self)
+            }
+        })*
+    }
+}
+
+stringify_integer!(i8, i16, i32, i64, u8, u16, u32, u64);
+
+impl Stringify for f32 {
+    fn stringify(&self, w: &mut dyn Write) -> fmt::Result {
+        write_float(w, *self as f64)
+    }
+}
+
+impl Stringify for f64 {
+    fn stringify(&self, w: &mut dyn Write) -> fmt::Result {
+        write_float(w, *self)
+    }
+}
+
+impl <'a> Stringify for &'a str {
+    fn stringify(&self, w: &mut dyn Write) -> fmt::Result {
+        write_string(w, self.chars(), false)
+    }
+}
+
+impl <'a> Stringify for &'a [u8] {
+    fn stringify(&self, w: &mut dyn Write) -> fmt::Result {
+        write_string(w, self.iter().map(|&b| b as char), true)
+    }
+}
+
+impl <T> Stringify for crate::Result<T> where T: Stringify {
+    fn stringify(&self, w: &mut dyn Write) -> fmt::Result {
+        match self {
+            Ok(value) => value.stringify(w),
+            Err(e) => write!(w, "<error: {}>", e.description),
+        }
+    }
+}
+
+/// An enum value without an enumerant is written as its number.
+impl <T> Stringify for ::core::result::Result<T, NotInSchema> where T: Stringify {
+    fn stringify(&self, w: &mut dyn Write) -> fmt::Result {
+        match self {
+            Ok(value) => value.stringify(w),
+            Err(NotInSchema(n)) => write!(w, "{}", n),
+        }
+    }
+}
+
+fn write_list<F>(w: &mut dyn Write, len: u32, mut write_element: F) -> fmt::Result
+    where F: FnMut(&mut dyn Write, u32) -> fmt::Result
+{
+    w.write_char('[')?;
+    for idx in 0..len {
+        if idx > 0 {
+            w.write_str(", ")?;
+        }
+        write_element(w, idx)?;
+    }
+    w.write_char(']')
+}
+
+impl <'a, T> Stringify for primitive_list::Reader<'a, T> where T: PrimitiveElement + Stringify {
+    fn stringify(&self, w: &mut dyn Write) -> fmt::Result {
+        write_list(w, self.len(), |w, idx| self.get(idx).stringify(w))
+    }
+}
+
+impl <'a, T> Stringify for enum_list::Reader<'a, T> where T: FromU16 + Stringify {
+    fn stringify(&self, w: &mut dyn Write) -> fmt::Result {
+        write_list(w, self.len(), |w, idx| self.get(idx).stringify(w))
+    }
+}
+
+impl <'a> Stringify for text_list::Reader<'a> {
+    fn stringify(&self, w: &mut dyn Write) -> fmt::Result {
+        write_list(w, self.len(), |w, idx| self.get(idx).stringify(w))
+    }
+}
+
+impl <'a> Stringify for data_list::Reader<'a> {
+    fn stringify(&self, w: &mut dyn Write) -> fmt::Result {
+        write_list(w, self.len(), |w, idx| self.get(idx).stringify(w))
+    }
+}
+
+impl <'a, T> Stringify for struct_list::Reader<'a, T>
+    where T: for<'b> OwnedStruct<'b>, <T as OwnedStruct<'a>>::Reader: Stringify
+{
+    fn stringify(&self, w: &mut dyn Write) -> fmt::Result {
+        write_list(w, self.len(), |w, idx| self.get(idx).stringify(w))
+    }
+}
+
+impl <'a, T> Stringify for list_list::Reader<'a, T>
+    where T: for<'b> Owned<'b>, <T as Owned<'a>>::Reader: FromPointerReader<'a> + Stringify
+{
+    fn stringify(&self, w: &mut dyn Write) -> fmt::Result {
+        write_list(w, self.len(), |w, idx| self.get(idx).stringify(w))
+    }
+}
+
+impl <'a, T> Stringify for capability_list::Reader<'a, T> where T: FromClientHook {
+    fn stringify(&self, w: &mut dyn Write) -> fmt::Result {
+        write_list(w, self.len(), |w, _| Capability.stringify(w))
+    }
+}
+
+impl <'a> Stringify for any_pointer_list::Reader<'a> {
+    fn stringify(&self, w: &mut dyn Write) -> fmt::Result {
+        write_list(w, self.len(), |w, idx| self.get(idx).stringify(w))
+    }
+}
@@ -21,13 +21,16 @@
 
 //! List of structs.
 
+use alloc::vec::Vec;
 use core::marker::PhantomData;
 
-use crate::private::layout::{ListReader, ListBuilder, PointerReader, PointerBuilder, InlineComposite};
+use crate::fields::{ColumnValue, FieldInfo};
+use crate::private::layout::{ListReader, ListBuilder, PointerReader, PointerBuilder, InlineComposite,
+                             StructElementIter};
 use crate::traits::{FromPointerReader, FromPointerBuilder,
                     FromStructBuilder, FromStructReader, HasStructSize,
-                    IndexMove, ListIter};
-use crate::Result;
+                    IndexMove};
+use crate::{Error, Result};
 
 #[derive(Copy, Clone)]
 pub struct Owned<T> where T: for<'a> crate::traits::OwnedStruct<'a> {
@@ -58,8 +61,32 @@ impl <'a, T> Reader<'a, T> where T: for<'b> crate::traits::OwnedStruct<'b> {
 
     pub fn len(&self) -> u32 { self.reader.len() }
 
-    pub fn iter(self) -> ListIter<Reader<'a, T>, <T as crate::traits::OwnedStruct<'a>>::Reader> {
-        ListIter::new(self, self.len())
+    /// Iterates over the elements of the list. The element layout is computed once, so this
+    /// is cheaper than calling `get()` for each index.
+    pub fn iter(self) -> Iter<'a, T> {
+        Iter { marker: PhantomData, elements: self.reader.struct_elements() }
+    }
+
+    /// Reads the data field described by `field`, usually an entry of the element type's
+    /// `HasFields::fields()`, from every element in one pass over the list. `V` must match the
+    /// field's type, with `u16` for enums. A union member is read whether or not it is active.
+    pub fn collect_field<V: ColumnValue>(self, field: &FieldInfo) -> Result<Vec<V>> {
+        if !V::matches(field.type_tag) {
+            return Err(Error::failed(format!("field {} of type {:?} cannot be read as {}",
+                                             field.name, field.type_tag, core::any::type_name::<V>())));
+        }
+        let mut result = Vec::with_capacity(self.len() as usize);
+        for element in self.reader.struct_elements() {
+            result.push(V::read(&element, field.offset as usize, field.default_bits));
+        }
+        Ok(result)
+    }
+
+    /// Copies each element of this list into the element at the same index of `dest`, which
+    /// must be at least as long as this list. Has the same truncation caveat as
+    /// `Builder::set_with_caveats()`.
+    pub fn copy_to(self, dest: &mut Builder<'_, T>) -> Result<()> {
+        dest.builder.copy_struct_elements_from(&self.reader)
     }
 }
 
@@ -175,9 +202,44 @@ impl <'a, T> ::core::iter::IntoIterator for Reader<'a, T>
     where T: for<'b> crate::traits::OwnedStruct<'b>
 {
     type Item = <T as crate::traits::OwnedStruct<'a>>::Reader;
-    type IntoIter = ListIter<Reader<'a, T>, Self::Item>;
+    type IntoIter = Iter<'a, T>;
 
     fn into_iter(self) -> Self::IntoIter {
         self.iter()
     }
 }
+
+/// Iterator over the elements of a struct list.
+pub struct Iter<'a, T> where T: for<'b> crate::traits::OwnedStruct<'b> {
+    marker: PhantomData<T>,
+    elements: StructElementIter<'a>,
+}
+
+impl <'a, T> ::core::iter::Iterator for Iter<'a, T>
+    where T: for<'b> crate::traits::OwnedStruct<'b>
+{
+    type Item = <T as crate::traits::OwnedStruct<'a>>::Reader;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.elements.next().map(FromStructReader::new)
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.elements.nth(n).map(FromStructReader::new)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.elements.size_hint()
+    }
+}
+
+impl <'a, T> ::core::iter::DoubleEndedIterator for Iter<'a, T>
+    where T: for<'b> crate::traits::OwnedStruct<'b>
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.elements.next_back().map(FromStructReader::new)
+    }
+}
+
+impl <'a, T> ::core::iter::ExactSizeIterator for Iter<'a, T>
+    where T: for<'b> crate::traits::OwnedStruct<'b> {}
@@ -24,6 +24,7 @@
 use core::{convert, str, ops};
 
 use crate::{Error, Result};
+use crate::private::layout::TextLocation;
 
 #[derive(Copy, Clone)]
 pub struct Owned(());
@@ -43,6 +44,15 @@ pub fn new_reader<'a>(v : &'a [u8]) -> Result<Reader<'a>> {
     }
 }
 
+/// Like `new_reader()`, but instead of failing on invalid UTF-8, returns the text before the
+/// first invalid sequence.
+pub fn new_reader_lenient<'a>(v: &'a [u8]) -> Reader<'a> {
+    match str::from_utf8(v) {
+        Ok(v) => v,
+        Err(e) => str::from_utf8(&v[..e.valid_up_to()]).unwrap_or(""),
+    }
+}
+
 impl <'a> crate::traits::FromPointerReader<'a> for Reader<'a> {
     fn get_from_pointer(reader: &crate::private::layout::PointerReader<'a>,
                         default: Option<&'a [crate::Word]>) -> Result<Reader<'a>> {
@@ -80,6 +90,22 @@ impl <'a> Builder <'a> {
         self.pos += bytes.len();
     }
 
+    /// Fills the rest of the text with bytes read from `read`, writing them directly into the
+    /// message, and checks that the whole text is valid UTF-8. On failure, the text is cleared.
+    pub fn fill_from<R>(&mut self, mut read: R) -> Result<()> where R: crate::io::Read {
+        let result = read.read_exact(&mut self.bytes[self.pos..]).and_then(|()| {
+            match str::from_utf8(self.bytes) {
+                Ok(_) => Ok(()),
+                Err(e) => Err(Error::failed(format!("Text contains non-utf8 data: {:?}", e))),
+            }
+        });
+        self.pos = self.bytes.len();
+        if result.is_err() {
+            self.clear();
+        }
+        result
+    }
+
     pub fn clear(&mut self) {
         for ii in 0..self.pos {
             self.bytes[ii] = 0;
@@ -129,3 +155,58 @@ impl <'a> crate::traits::SetPointerBuilder<Builder<'a>> for Reader<'a> {
         Ok(())
     }
 }
+
+/// Shares text between the fields of a message that hold the same value, so that each distinct
+/// value is written only once. Useful when building denormalized records in which the same
+/// strings appear many times. Generated code has a `set_foo_interned()` setter for each text
+/// field `foo`, and `text_list::Builder` has `set_interned()`.
+///
+/// An interner should only be used with one message. Since the shared text is owned by every
+/// field that points to it, it must not be modified through a `text::Builder`, and a field
+/// that holds it must only be overwritten through the interner: setting or clearing the field
+/// directly zeroes the text for all of the fields that share it.
+pub struct Interner {
+    locations: alloc::collections::BTreeMap<alloc::string::String, TextLocation>,
+    shared: alloc::collections::BTreeSet<TextLocation>,
+}
+
+impl Interner {
+    pub fn new() -> Interner {
+        Interner {
+            locations: alloc::collections::BTreeMap::new(),
+            shared: alloc::collections::BTreeSet::new(),
+        }
+    }
+
+    /// Sets `pointer` to `value`, pointing it at a previous copy of `value` if there is one.
+    pub fn set(&mut self, pointer: crate::private::layout::PointerBuilder, value: Reader) {
+        let previous = self.locations.get(value).cloned();
+        if let Some(current) = pointer.get_text_location() {
+            if previous == Some(current) {
+                return;
+            }
+            if self.shared.contains(&current) {
+                // Other fields may point to the current text too, so leave it in place.
+                pointer.clear_shared();
+            }
+        }
+        if let Some(location) = previous {
+            if pointer.share_text(location, value) {
+                return;
+            }
+            self.shared.remove(&location);
+        }
+        let location = pointer.set_text_shareable(value);
+        self.locations.insert(value.into(), location);
+        self.shared.insert(location);
+    }
+
+    /// The number of distinct values seen so far.
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+}
+
+impl Default for Interner {
+    fn default() -> Interner { Interner::new() }
+}
@@ -92,6 +92,12 @@ impl <'a> Builder<'a> {
         self.builder.borrow().get_pointer_element(index).set_text(value);
     }
 
+    /// Like `set()`, but shares the text with other uses of `value` made through `interner`.
+    pub fn set_interned(&mut self, index: u32, value: crate::text::Reader, interner: &mut crate::text::Interner) {
+        assert!(index < self.len());
+        interner.set(self.builder.borrow().get_pointer_element(index), value);
+    }
+
     pub fn into_reader(self) -> Reader<'a> {
         Reader { reader: self.builder.into_reader() }
     }
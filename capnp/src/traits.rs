@@ -95,6 +95,27 @@ pub trait HasTypeId {
     fn type_id() -> u64;
 }
 
+/// Implemented by generated struct readers and builders, to give reflection APIs, such as the
+/// `dynamic` module of capnpc, access to the untyped struct underneath.
+pub trait Reflect<'a>: HasTypeId + Sized {
+    /// `StructReader` for readers and `StructBuilder` for builders.
+    type Internal;
+
+    fn into_internal(self) -> Self::Internal;
+    fn from_internal(internal: Self::Internal) -> Self;
+}
+
+pub trait HasFields {
+    fn fields() -> &'static [crate::fields::FieldInfo];
+}
+
+/// Sets every field of a struct from a tuple of plain values, in field order. Generated for
+/// the parameters of RPC methods whose parameters are all primitives or Text, so that a
+/// request can be filled in with `request.get().set_from_tuple((1, "foo"))`.
+pub trait SetFromTuple<T> {
+    fn set_from_tuple(&mut self, values: T);
+}
+
 pub trait ToU16 {
     fn to_u16(self) -> u16;
 }
@@ -0,0 +1,89 @@
+// Reading and writing messages through capnp::io::{BufferedInputStream, BufferedOutputStream}.
+
+#![cfg(feature = "std")]
+
+use std::io;
+
+use capnp::io::{BufRead, BufferedInputStream, BufferedOutputStream, Write};
+use capnp::message::{self, ReaderOptions};
+use capnp::{primitive_list, serialize, serialize_packed};
+
+/// Hands out at most three bytes per read, like a slow socket.
+struct Trickle<'a>(&'a [u8]);
+
+impl <'a> io::Read for Trickle<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let amt = std::cmp::min(3, buf.len());
+        io::Read::read(&mut self.0, &mut buf[..amt])
+    }
+}
+
+/// Counts the writes made to it.
+struct CountingWriter {
+    bytes: Vec<u8>,
+    writes: usize,
+}
+
+impl io::Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writes += 1;
+        self.bytes.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+fn build() -> message::Builder<message::HeapAllocator> {
+    let mut message = message::Builder::new(message::HeapAllocator::new().first_segment_words(8));
+    {
+        let mut list: primitive_list::Builder<u32> =
+            message.init_root::<capnp::any_pointer::Builder>().initn_as(200);
+        for idx in 0..200 {
+            list.set(idx, if idx % 7 == 0 { idx } else { 0 });
+        }
+    }
+    message
+}
+
+fn check<S: message::ReaderSegments>(reader: message::Reader<S>) {
+    let list = reader.get_root::<primitive_list::Reader<u32>>().unwrap();
+    assert_eq!(list.len(), 200);
+    assert_eq!(list.get(196), 196);
+    assert_eq!(list.get(197), 0);
+}
+
+#[test]
+fn packed_round_trip() {
+    let mut output = BufferedOutputStream::new(CountingWriter { bytes: Vec::new(), writes: 0 });
+    serialize_packed::write_message(&mut output, &build()).unwrap();
+    serialize_packed::write_message(&mut output, &build()).unwrap();
+    let written = output.into_inner().unwrap();
+    assert_eq!(written.writes, 1);
+
+    let mut input = BufferedInputStream::with_capacity(64, Trickle(&written.bytes));
+    check(serialize_packed::read_message(&mut input, ReaderOptions::new()).unwrap());
+    check(serialize_packed::read_message(&mut input, ReaderOptions::new()).unwrap());
+    assert!(input.fill_buf().unwrap().is_empty());
+}
+
+#[test]
+fn large_writes_bypass_the_buffer() {
+    let mut output = BufferedOutputStream::with_capacity(16, CountingWriter { bytes: Vec::new(), writes: 0 });
+    serialize::write_message(&mut output, &build()).unwrap();
+    let written = output.into_inner().unwrap();
+    assert_eq!(written.bytes, serialize::write_message_to_words(&build()));
+
+    let mut input = BufferedInputStream::with_capacity(16, &written.bytes[..]);
+    check(serialize::read_message(&mut input, ReaderOptions::new()).unwrap());
+}
+
+#[test]
+fn write_buffer() {
+    let mut output = BufferedOutputStream::with_capacity(4, Vec::new());
+    output.get_write_buffer().unwrap()[..3].copy_from_slice(b"abc");
+    output.advance(3);
+    output.write_all(b"de").unwrap();
+    output.get_write_buffer().unwrap()[0] = b'f';
+    output.advance(1);
+    assert_eq!(output.into_inner().unwrap(), b"abcdef");
+}
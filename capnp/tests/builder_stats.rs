@@ -0,0 +1,75 @@
+use capnp::{any_pointer, message, primitive_list};
+
+#[test]
+pub fn empty_builder() {
+    let message = message::Builder::new_default();
+    let stats = message.stats();
+    assert_eq!(stats.segment_count, 0);
+    assert_eq!(stats.words_allocated, 0);
+    assert_eq!(stats.words_used, 0);
+    assert_eq!(stats.words_wasted, 0);
+}
+
+#[test]
+pub fn overwritten_text_is_wasted() {
+    let mut message = message::Builder::new_default();
+    {
+        let root: any_pointer::Builder = message.init_root();
+        root.set_as("hello world, this is some text").unwrap();
+    }
+    let first = message.stats();
+    assert_eq!(first.segment_count, 1);
+    assert_eq!(first.words_used, 1 + 4); // root pointer + 31 bytes of text
+    assert!(first.words_allocated >= first.words_used);
+    assert_eq!(first.words_wasted, 0);
+
+    {
+        let root: any_pointer::Builder = message.get_root().unwrap();
+        root.set_as("hello world, this is some text").unwrap();
+    }
+    let second = message.stats();
+    assert_eq!(second.words_used, 1 + 4 + 4);
+    assert_eq!(second.words_wasted, 4);
+
+    {
+        let root: any_pointer::Builder = message.get_root().unwrap();
+        let _list: primitive_list::Builder<u16> = root.initn_as(9);
+    }
+    let third = message.stats();
+    assert_eq!(third.words_used, 1 + 4 + 4 + 3);
+    assert_eq!(third.words_wasted, 8);
+}
+
+#[test]
+pub fn size_hint_gives_exact_first_segment() {
+    let mut message = message::Builder::new(message::HeapAllocator::new().small_segments());
+    {
+        let root: any_pointer::Builder = message.init_root_with_size_hint(100);
+        assert!(root.is_null());
+    }
+    {
+        let root: any_pointer::Builder = message.get_root().unwrap();
+        let _list: primitive_list::Builder<u64> = root.initn_as(100);
+    }
+    let stats = message.stats();
+    assert_eq!(stats.segment_count, 1);
+    assert_eq!(stats.words_allocated, 1 + 100);
+    assert_eq!(stats.words_used, 1 + 100);
+}
+
+#[test]
+pub fn expected_size() {
+    assert_eq!(message::HeapAllocator::new().next_segment_words(),
+               message::SUGGESTED_FIRST_SEGMENT_WORDS);
+    let allocator = message::HeapAllocator::expected_size(101);
+    assert_eq!(allocator.next_segment_words(), 101);
+
+    let mut message = message::Builder::with_expected_size(1 + 100);
+    {
+        let root: any_pointer::Builder = message.init_root();
+        let _list: primitive_list::Builder<u64> = root.initn_as(100);
+    }
+    let stats = message.stats();
+    assert_eq!(stats.segment_count, 1);
+    assert_eq!(stats.words_allocated, stats.words_used);
+}
@@ -0,0 +1,28 @@
+use capnp::{constant, text, Word};
+
+static HELLO: constant::Reader<text::Owned> = unsafe {
+    constant::Reader::new_unchecked(&[
+        capnp::word(0x01, 0x00, 0x00, 0x00, 0x32, 0x00, 0x00, 0x00),
+        capnp::word(b'h', b'e', b'l', b'l', b'o', 0x00, 0x00, 0x00),
+    ])
+};
+
+#[test]
+pub fn include_canonical_message() {
+    let words: &'static [Word] = capnp::include_words!("data/hello.bin").unwrap();
+    let embedded = constant::Reader::<text::Owned>::new(words).unwrap();
+    let value: text::Reader<'static> = embedded.get().unwrap();
+    assert_eq!(value, "hello");
+    assert_eq!(HELLO.get().unwrap(), "hello");
+}
+
+#[test]
+pub fn reject_non_canonical_message() {
+    static WORDS: [Word; 3] = [
+        capnp::word(0x01, 0x00, 0x00, 0x00, 0x32, 0x00, 0x00, 0x00),
+        capnp::word(b'h', b'e', b'l', b'l', b'o', 0x00, 0x00, 0x00),
+        capnp::word(0, 0, 0, 0, 0, 0, 0, 0),
+    ];
+    assert!(constant::Reader::<text::Owned>::new(&WORDS).is_err());
+    assert!(constant::Reader::<text::Owned>::new(&WORDS[..1]).is_err());
+}
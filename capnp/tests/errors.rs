@@ -0,0 +1,18 @@
+#![cfg(feature = "std")]
+
+use capnp::{Error, ErrorKind};
+
+#[test]
+pub fn io_error_round_trip() {
+    for &(ref error, kind) in &[(Error::failed("f".to_string()), ErrorKind::Failed),
+                            (Error::overloaded("o".to_string()), ErrorKind::Overloaded),
+                            (Error::disconnected("d".to_string()), ErrorKind::Disconnected)] {
+        let io_error: std::io::Error = error.clone().into();
+        let back: Error = io_error.into();
+        assert_eq!(back.kind(), kind);
+        assert_eq!(back.description, error.description);
+    }
+
+    let io_error: std::io::Error = Error::unimplemented("u".to_string()).into();
+    assert_eq!(io_error.kind(), std::io::ErrorKind::Other);
+}
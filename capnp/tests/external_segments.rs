@@ -0,0 +1,59 @@
+// Messages read in place from segments whose memory the caller manages.
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use capnp::message::{self, ExternalSegments, RawSegment, ReaderOptions};
+use capnp::{primitive_list, Word};
+
+fn build() -> Vec<Vec<Word>> {
+    let mut message = message::Builder::new(message::HeapAllocator::new().first_segment_words(4));
+    {
+        let mut list: primitive_list::Builder<u32> =
+            message.init_root::<capnp::any_pointer::Builder>().initn_as(20);
+        for idx in 0..20 {
+            list.set(idx, idx * 3);
+        }
+    }
+    message.get_segments_for_output().iter().map(|segment| {
+        let mut words = Word::allocate_zeroed_vec(segment.len() / 8);
+        Word::words_to_bytes_mut(&mut words).copy_from_slice(segment);
+        words
+    }).collect()
+}
+
+fn check<S: message::ReaderSegments>(reader: &message::Reader<S>) {
+    let list = reader.get_root::<primitive_list::Reader<u32>>().unwrap();
+    assert_eq!(list.len(), 20);
+    assert_eq!(list.get(19), 57);
+}
+
+#[test]
+fn owned_buffers_are_given_back() {
+    let buffers = build();
+    let count = buffers.len();
+    assert!(count > 1);
+    let reader = message::Reader::new(ExternalSegments::new(buffers), ReaderOptions::new());
+    check(&reader);
+    let buffers = reader.into_segments().into_inner();
+    assert_eq!(buffers.len(), count);
+}
+
+#[test]
+fn raw_segments_are_released_with_the_message() {
+    // Stands in for slots of a ring buffer, which are released once the message is done with.
+    let slots = build();
+    let released = Rc::new(Cell::new(0));
+    let segments = slots.iter().map(|slot| {
+        let released = released.clone();
+        let bytes = Word::words_to_bytes(slot);
+        unsafe {
+            RawSegment::new(bytes.as_ptr(), bytes.len(), move || released.set(released.get() + 1))
+        }
+    }).collect();
+    let reader = message::Reader::new(ExternalSegments::new(segments), ReaderOptions::new());
+    check(&reader);
+    assert_eq!(released.get(), 0);
+    drop(reader);
+    assert_eq!(released.get(), slots.len());
+}
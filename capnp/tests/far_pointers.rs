@@ -0,0 +1,187 @@
+// Copyright (c) 2017 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+extern crate capnp;
+
+use capnp::{any_pointer, message, primitive_list, serialize, text, text_list, list_list};
+use capnp::message::ReaderOptions;
+
+const FAR: u8 = 2;
+const DOUBLE_FAR: u8 = 6;
+
+// Returns the kind bits of the root pointer, which is always the first word of segment zero.
+fn root_pointer_tag<A: message::Allocator>(message: &message::Builder<A>) -> u8 {
+    message.get_segments_for_output()[0][0] & 7
+}
+
+fn round_trip<A: message::Allocator>(message: &message::Builder<A>)
+                                     -> message::Reader<serialize::OwnedSegments>
+{
+    let words = serialize::write_message_to_words(message);
+    serialize::read_message(&mut &words[..], ReaderOptions::new()).unwrap()
+}
+
+#[test]
+fn small_segments_text_list() {
+    let mut message = message::Builder::new(message::HeapAllocator::new().small_segments());
+    {
+        let mut list = message.init_root::<any_pointer::Builder>().initn_as::<text_list::Builder>(3);
+        list.set(0, "foo");
+        list.set(1, "bar");
+        list.set(2, "a somewhat longer string that spans several words");
+    }
+
+    // The root pointer, the list, and each of the three texts live in segments of their own.
+    assert_eq!(message.get_segments_for_output().len(), 5);
+    assert_eq!(message.get_segments_for_output()[0].len(), 8);
+    assert_eq!(root_pointer_tag(&message), FAR);
+
+    let reader = round_trip(&message);
+    assert_eq!(capnp::message::ReaderSegments::len(reader.get_segments()), 5);
+    let list = reader.get_root::<text_list::Reader>().unwrap();
+    assert_eq!(list.len(), 3);
+    assert_eq!(list.get(0).unwrap(), "foo");
+    assert_eq!(list.get(1).unwrap(), "bar");
+    assert_eq!(list.get(2).unwrap(), "a somewhat longer string that spans several words");
+}
+
+#[test]
+fn small_segments_list_of_lists() {
+    let mut message = message::Builder::new(message::HeapAllocator::new().small_segments());
+    {
+        let mut outer = message.init_root::<any_pointer::Builder>()
+            .initn_as::<list_list::Builder<primitive_list::Owned<u32>>>(4);
+        for i in 0..4 {
+            let mut inner = outer.reborrow().init(i, i + 1);
+            for j in 0..(i + 1) {
+                inner.set(j, i * 100 + j);
+            }
+        }
+    }
+    assert_eq!(message.get_segments_for_output().len(), 6);
+
+    let reader = round_trip(&message);
+    let outer = reader.get_root::<list_list::Reader<primitive_list::Owned<u32>>>().unwrap();
+    assert_eq!(outer.len(), 4);
+    for i in 0..4 {
+        let inner = outer.get(i).unwrap();
+        assert_eq!(inner.len(), i + 1);
+        for j in 0..(i + 1) {
+            assert_eq!(inner.get(j), i * 100 + j);
+        }
+    }
+}
+
+#[test]
+fn small_segments_copy_from_single_segment() {
+    let mut source = message::Builder::new_default();
+    {
+        let mut list = source.init_root::<any_pointer::Builder>().initn_as::<text_list::Builder>(2);
+        list.set(0, "hello");
+        list.set(1, "world");
+    }
+    assert_eq!(source.get_segments_for_output().len(), 1);
+
+    let mut message = message::Builder::new(message::HeapAllocator::new().small_segments());
+    message.set_root(source.get_root_as_reader::<text_list::Reader>().unwrap()).unwrap();
+    assert_eq!(message.get_segments_for_output().len(), 4);
+    assert_eq!(root_pointer_tag(&message), FAR);
+
+    let list = message.get_root_as_reader::<text_list::Reader>().unwrap();
+    assert_eq!(list.get(0).unwrap(), "hello");
+    assert_eq!(list.get(1).unwrap(), "world");
+}
+
+#[test]
+fn read_single_far() {
+    let segment0: &[capnp::Word] = &[
+        // Far pointer to word 0 of segment 1.
+        capnp::word(FAR, 0, 0, 0, 1, 0, 0, 0),
+    ];
+    let segment1: &[capnp::Word] = &[
+        // Landing pad: list pointer, offset 0, byte elements, three of them.
+        capnp::word(0x01, 0, 0, 0, 0x1a, 0, 0, 0),
+        capnp::word(b'h', b'i', 0, 0, 0, 0, 0, 0),
+    ];
+
+    let segments = &[capnp::Word::words_to_bytes(segment0),
+                     capnp::Word::words_to_bytes(segment1)];
+    let message = message::Reader::new(message::SegmentArray::new(segments), ReaderOptions::new());
+    assert_eq!(message.get_root::<text::Reader>().unwrap(), "hi");
+}
+
+#[test]
+fn read_double_far() {
+    let segment0: &[capnp::Word] = &[
+        // Double-far pointer to word 1 of segment 1.
+        capnp::word(DOUBLE_FAR | (1 << 3), 0, 0, 0, 1, 0, 0, 0),
+    ];
+    let segment1: &[capnp::Word] = &[
+        capnp::word(0, 0, 0, 0, 0, 0, 0, 0),
+        // Landing pad: far pointer to word 0 of segment 2...
+        capnp::word(FAR, 0, 0, 0, 2, 0, 0, 0),
+        // ... followed by a tag: list pointer, byte elements, three of them.
+        capnp::word(0x01, 0, 0, 0, 0x1a, 0, 0, 0),
+    ];
+    let segment2: &[capnp::Word] = &[
+        capnp::word(b'h', b'i', 0, 0, 0, 0, 0, 0),
+    ];
+
+    let segments = &[capnp::Word::words_to_bytes(segment0),
+                     capnp::Word::words_to_bytes(segment1),
+                     capnp::Word::words_to_bytes(segment2)];
+    let message = message::Reader::new(message::SegmentArray::new(segments), ReaderOptions::new());
+    assert_eq!(message.get_root::<text::Reader>().unwrap(), "hi");
+
+    // The double-far survives a copy into a fresh builder, which flattens it.
+    let mut builder = message::Builder::new_default();
+    builder.set_root(message.get_root::<text::Reader>().unwrap()).unwrap();
+    assert_eq!(builder.get_segments_for_output().len(), 1);
+    assert_eq!(builder.get_root_as_reader::<text::Reader>().unwrap(), "hi");
+}
+
+#[test]
+fn double_far_landing_pad_out_of_bounds() {
+    let segment0: &[capnp::Word] = &[
+        // Double-far pointer to the last word of segment 1, leaving no room for the tag.
+        capnp::word(DOUBLE_FAR | (1 << 3), 0, 0, 0, 1, 0, 0, 0),
+    ];
+    let segment1: &[capnp::Word] = &[
+        capnp::word(0, 0, 0, 0, 0, 0, 0, 0),
+        capnp::word(FAR, 0, 0, 0, 0, 0, 0, 0),
+    ];
+
+    let segments = &[capnp::Word::words_to_bytes(segment0),
+                     capnp::Word::words_to_bytes(segment1)];
+    let message = message::Reader::new(message::SegmentArray::new(segments), ReaderOptions::new());
+    assert!(message.get_root::<text::Reader>().is_err());
+}
+
+#[test]
+fn far_pointer_to_missing_segment() {
+    let segment0: &[capnp::Word] = &[
+        capnp::word(FAR, 0, 0, 0, 7, 0, 0, 0),
+    ];
+
+    let segments = &[capnp::Word::words_to_bytes(segment0)];
+    let message = message::Reader::new(message::SegmentArray::new(segments), ReaderOptions::new());
+    assert!(message.get_root::<text::Reader>().is_err());
+}
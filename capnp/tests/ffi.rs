@@ -0,0 +1,51 @@
+#![cfg(feature = "ffi")]
+
+use capnp::ffi;
+use capnp::message::{self, HeapAllocator};
+
+#[test]
+pub fn export_and_import_multi_segment() {
+    let mut message = message::Builder::new(HeapAllocator::new().first_segment_words(1));
+    {
+        let mut list: capnp::primitive_list::Builder<u64> =
+            message.init_root::<capnp::any_pointer::Builder>().initn_as(40);
+        for idx in 0..40 {
+            list.set(idx, idx as u64 * 3);
+        }
+    }
+    let exported = ffi::export(message);
+    assert!(exported.segment_count > 1);
+
+    // This is what a foreign caller of an `extern "C"` entry point would pass in.
+    {
+        let reader = unsafe { ffi::import(exported.segments, exported.segment_count, Default::default()) }.unwrap();
+        let list = reader.get_root::<capnp::primitive_list::Reader<u64>>().unwrap();
+        assert_eq!(list.len(), 40);
+        assert_eq!(list.get(39), 117);
+    }
+
+    // And this is what it would do to hand the message over for reading later.
+    unsafe {
+        let handle = ffi::capnp_rust_message_import(exported.segments, exported.segment_count);
+        assert!(!handle.is_null());
+        {
+            let reader = ffi::ImportedMessage::from_raw(handle).unwrap().get_reader(Default::default());
+            let list = reader.get_root::<capnp::primitive_list::Reader<u64>>().unwrap();
+            assert_eq!(list.get(20), 60);
+        }
+        ffi::capnp_rust_imported_message_free(handle);
+    }
+    unsafe { ffi::capnp_rust_exported_message_free(exported) };
+}
+
+#[test]
+pub fn import_rejects_bad_arrays() {
+    unsafe {
+        assert!(ffi::import(std::ptr::null(), 1, Default::default()).is_err());
+        let segments = [ffi::Segment { words: std::ptr::null(), len: 1 }];
+        assert!(ffi::import(segments.as_ptr(), 0, Default::default()).is_err());
+        assert!(ffi::import(segments.as_ptr(), 1, Default::default()).is_err());
+        assert!(ffi::capnp_rust_message_import(segments.as_ptr(), 1).is_null());
+        assert!(ffi::capnp_rust_message_import(std::ptr::null(), 1).is_null());
+    }
+}
@@ -0,0 +1,41 @@
+use capnp::message::SegmentArray;
+use capnp::Word;
+
+#[test]
+pub fn inspect_annotates_reachable_words() {
+    let words: &[Word] = &[
+        capnp::word(0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00),
+        capnp::word(0x2a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00),
+        capnp::word(0x01, 0x00, 0x00, 0x00, 0x1a, 0x00, 0x00, 0x00),
+        capnp::word(b'h', b'i', 0x00, 0x00, 0x00, 0x00, 0x00, 0x00),
+        capnp::word(0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff),
+    ];
+    let segments = [Word::words_to_bytes(words)];
+    let dump = capnp::inspect(&SegmentArray::new(&segments));
+    let lines: Vec<&str> = dump.lines().collect();
+    assert_eq!(lines, [
+        "segment 0 (5 words)",
+        "  0000: 00 00 00 00 01 00 01 00  root: struct (offset 0, 1 data words, 1 pointers)",
+        "  0001: 2a 00 00 00 00 00 00 00  data word 0",
+        "  0002: 01 00 00 00 1a 00 00 00  pointer 0: list (offset 0, Byte, 3 elements)",
+        "  0003: 68 69 00 00 00 00 00 00  list data",
+        "  0004: ff ff ff ff ff ff ff ff  unreachable",
+    ]);
+}
+
+#[test]
+pub fn inspect_flags_bad_pointers() {
+    let segment0: &[Word] = &[
+        capnp::word(0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00),
+        capnp::word(0x04, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00), // struct, past the end
+        capnp::word(0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00), // far, to segment 1
+    ];
+    let segment1: &[Word] = &[
+        capnp::word(0xfc, 0xff, 0xff, 0xff, 0x00, 0x00, 0x01, 0x00), // struct pointing at itself
+    ];
+    let segments = [Word::words_to_bytes(segment0), Word::words_to_bytes(segment1)];
+    let dump = capnp::inspect(&SegmentArray::new(&segments));
+    assert!(dump.contains("pointer 0: struct (offset 1, 1 data words, 0 pointers) (out of bounds)"), "{}", dump);
+    assert!(dump.contains("pointer 1: far (segment 1, word 0)"), "{}", dump);
+    assert!(dump.contains("segment 1 (1 words)\n  0000: fc ff ff ff 00 00 01 00  landing pad: struct"), "{}", dump);
+}
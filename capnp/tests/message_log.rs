@@ -0,0 +1,113 @@
+// Appending to a capnp::message_log::MessageLog and recovering from torn writes.
+
+#![cfg(feature = "std")]
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+use capnp::message::{self, ReaderOptions};
+use capnp::message_log::{MessageLog, SyncPolicy};
+use capnp::{serialize, text};
+
+fn log_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("capnp-message-log-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn text_message(value: &str) -> message::Builder<message::HeapAllocator> {
+    let mut message = message::Builder::new_default();
+    message.set_root(value).unwrap();
+    message
+}
+
+fn read_texts(log: &MessageLog) -> Vec<String> {
+    log.read_all(ReaderOptions::new()).unwrap().iter()
+        .map(|m| m.get_root::<text::Reader>().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn append_and_reopen() {
+    let path = log_path("reopen");
+    {
+        let mut log = MessageLog::open(&path, SyncPolicy::Always).unwrap();
+        log.append(&text_message("first")).unwrap();
+        log.append(&text_message("second")).unwrap();
+        assert_eq!(log.unsynced(), 0);
+    }
+    let mut log = MessageLog::open(&path, SyncPolicy::Manual).unwrap();
+    assert_eq!(log.truncated_bytes(), 0);
+    log.append(&text_message("third")).unwrap();
+    assert_eq!(read_texts(&log), vec!["first", "second", "third"]);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn torn_write_is_truncated() {
+    let path = log_path("torn");
+    let complete_len = {
+        let mut log = MessageLog::open(&path, SyncPolicy::EveryN(2)).unwrap();
+        log.append(&text_message("kept")).unwrap();
+        assert_eq!(log.unsynced(), 1);
+        log.append(&text_message("also kept")).unwrap();
+        assert_eq!(log.unsynced(), 0);
+        log.len()
+    };
+
+    // Only part of the next record made it to disk.
+    let record = serialize::write_message_to_words(&text_message("lost"));
+    OpenOptions::new().append(true).open(&path).unwrap()
+        .write_all(&record[..record.len() - 3]).unwrap();
+
+    let mut log = MessageLog::open(&path, SyncPolicy::Manual).unwrap();
+    assert_eq!(log.truncated_bytes(), record.len() as u64 - 3);
+    assert_eq!(log.len(), complete_len);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), complete_len);
+
+    log.append(&text_message("after recovery")).unwrap();
+    assert_eq!(read_texts(&log), vec!["kept", "also kept", "after recovery"]);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn corrupt_record_is_an_error() {
+    let path = log_path("corrupt");
+    {
+        let mut log = MessageLog::open(&path, SyncPolicy::Manual).unwrap();
+        log.append(&text_message("first")).unwrap();
+    }
+    {
+        // A second record whose segment table claims far more segments than were ever
+        // written, followed by a third, intact record.
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        let mut record = serialize::write_message_to_words(&text_message("second"));
+        record[0..4].copy_from_slice(&1000u32.to_le_bytes());
+        file.write_all(&record).unwrap();
+        file.write_all(&serialize::write_message_to_words(&text_message("third"))).unwrap();
+    }
+    let len = std::fs::metadata(&path).unwrap().len();
+
+    assert!(MessageLog::open(&path, SyncPolicy::Manual).is_err());
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn segment_count_limit_applies() {
+    let path = log_path("limit");
+    let mut message = message::Builder::new(message::HeapAllocator::new().first_segment_words(1));
+    {
+        let mut list: capnp::primitive_list::Builder<u64> =
+            message.init_root::<capnp::any_pointer::Builder>().initn_as(3);
+        list.set(2, 7);
+    }
+    MessageLog::open(&path, SyncPolicy::Manual).unwrap().append(&message).unwrap();
+
+    let mut options = ReaderOptions::new();
+    options.segment_count_limit(1);
+    assert!(MessageLog::open_with_options(&path, SyncPolicy::Manual, options).is_err());
+    assert_eq!(MessageLog::open(&path, SyncPolicy::Manual).unwrap().truncated_bytes(), 0);
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,81 @@
+use capnp::{any_pointer, message, primitive_list};
+
+#[test]
+#[cfg(target_endian = "little")]
+pub fn as_slice_round_trip() {
+    let mut message = message::Builder::new_default();
+    {
+        let mut list: primitive_list::Builder<u32> =
+            message.init_root::<any_pointer::Builder>().initn_as(5);
+        list.set(0, 7);
+        {
+            let slice = list.as_mut_slice().unwrap();
+            assert_eq!(slice, &[7, 0, 0, 0, 0]);
+            for (i, x) in slice.iter_mut().enumerate().skip(1) {
+                *x = (i * 100) as u32;
+            }
+        }
+        assert_eq!(list.get(4), 400);
+    }
+
+    let list: primitive_list::Reader<u32> =
+        message.get_root_as_reader::<any_pointer::Reader>().unwrap().get_as().unwrap();
+    assert_eq!(list.as_slice(), Some(&[7, 100, 200, 300, 400][..]));
+}
+
+#[test]
+#[cfg(target_endian = "little")]
+pub fn as_slice_floats() {
+    let mut message = message::Builder::new_default();
+    {
+        let mut list: primitive_list::Builder<f64> =
+            message.init_root::<any_pointer::Builder>().initn_as(3);
+        list.as_mut_slice().unwrap().copy_from_slice(&[1.5, -2.0, 0.25]);
+    }
+    let list: primitive_list::Reader<f64> =
+        message.get_root_as_reader::<any_pointer::Reader>().unwrap().get_as().unwrap();
+    assert_eq!(list.as_slice().unwrap().iter().sum::<f64>(), -0.25);
+}
+
+#[test]
+pub fn as_slice_empty() {
+    let mut message = message::Builder::new_default();
+    {
+        let mut list: primitive_list::Builder<u64> =
+            message.init_root::<any_pointer::Builder>().initn_as(0);
+        assert_eq!(list.as_mut_slice().map(|s| s.len()), Some(0));
+    }
+    let list: primitive_list::Reader<u64> =
+        message.get_root_as_reader::<any_pointer::Reader>().unwrap().get_as().unwrap();
+    assert_eq!(list.as_slice().map(|s| s.len()), Some(0));
+}
+
+#[test]
+pub fn as_slice_not_available() {
+    let mut message = message::Builder::new_default();
+    {
+        let mut list: primitive_list::Builder<bool> =
+            message.init_root::<any_pointer::Builder>().initn_as(10);
+        assert!(list.as_mut_slice().is_none());
+    }
+    let list: primitive_list::Reader<bool> =
+        message.get_root_as_reader::<any_pointer::Reader>().unwrap().get_as().unwrap();
+    assert!(list.as_slice().is_none());
+
+    // A struct list read as a primitive list has a stride that doesn't match the element type.
+    let segment: &[capnp::Word] = &[
+        // inline composite list pointer, 3 words
+        capnp::word(0x01, 0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00),
+        // tag: 3 elements, 1 data word each
+        capnp::word(0x0c, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00),
+        capnp::word(0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00),
+        capnp::word(0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00),
+        capnp::word(0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00),
+    ];
+    let segments = &[capnp::Word::words_to_bytes(segment)];
+    let message = message::Reader::new(message::SegmentArray::new(segments), Default::default());
+    let list: primitive_list::Reader<u32> =
+        message.get_root::<any_pointer::Reader>().unwrap().get_as().unwrap();
+    assert_eq!(list.get(2), 3);
+    assert!(list.as_slice().is_none());
+}
@@ -0,0 +1,86 @@
+// Copyright (c) 2020 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Tests for the extra checks performed when the "sanitize" feature is enabled.
+#![cfg(feature = "sanitize")]
+
+extern crate capnp;
+
+use capnp::message;
+
+fn read_root_data(segments: &[&[capnp::Word]]) -> capnp::Result<()> {
+    let segments: Vec<&[u8]> = segments.iter().map(|s| capnp::Word::words_to_bytes(s)).collect();
+    let segment_array = message::SegmentArray::new(&segments[..]);
+    let message = message::Reader::new(segment_array, Default::default());
+    let root: capnp::any_pointer::Reader = message.get_root()?;
+    root.get_as::<capnp::data::Reader>()?;
+    Ok(())
+}
+
+#[test]
+fn unknown_pointer_type() {
+    let segment: &[capnp::Word] = &[
+        // "Other" pointer with a nonzero offset, which is not a capability.
+        capnp::word(0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00),
+    ];
+    let err = read_root_data(&[segment]).unwrap_err();
+    assert!(err.description.contains("sanitize: unknown pointer type"), "{}", err.description);
+    assert!(err.description.contains("segment 0, word 0"), "{}", err.description);
+}
+
+#[test]
+fn double_far_landing_pad_is_not_far() {
+    let segment0: &[capnp::Word] = &[
+        // Double-far pointer to segment 1, word 0.
+        capnp::word(0x06, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00),
+    ];
+    let segment1: &[capnp::Word] = &[
+        // Landing pad that should be a far pointer, but is a byte-list pointer.
+        capnp::word(0x01, 0x00, 0x00, 0x00, 0x12, 0x00, 0x00, 0x00),
+        // Tag.
+        capnp::word(0x01, 0x00, 0x00, 0x00, 0x12, 0x00, 0x00, 0x00),
+    ];
+    let err = read_root_data(&[segment0, segment1]).unwrap_err();
+    assert!(err.description.contains("double-far landing pad at segment 1, word 0"),
+            "{}", err.description);
+    assert!(err.description.contains("pointer at segment 0, word 0"), "{}", err.description);
+}
+
+#[test]
+fn well_formed_far_pointer() {
+    let segment0: &[capnp::Word] = &[
+        // Single-far pointer to segment 1, word 0.
+        capnp::word(0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00),
+    ];
+    let segment1: &[capnp::Word] = &[
+        // Byte list of length 2.
+        capnp::word(0x01, 0x00, 0x00, 0x00, 0x12, 0x00, 0x00, 0x00),
+        capnp::word(0xab, 0xcd, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00),
+    ];
+    read_root_data(&[segment0, segment1]).unwrap();
+}
+
+#[test]
+#[should_panic(expected = "sanitize: list index 0 is out of bounds for a list of 0 elements")]
+fn list_index_out_of_bounds() {
+    let list = capnp::private::layout::ListReader::new_default();
+    list.get_struct_element(0);
+}
@@ -0,0 +1,55 @@
+// Copyright (c) 2020 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+extern crate capnp;
+
+use capnp::message;
+
+#[test]
+fn segment_table_near_limit() {
+    // 511 segments, each claiming 2^32 - 1 words.
+    let mut buf: Vec<u8> = vec![0xfe, 0x01, 0, 0];
+    for _ in 0..511 {
+        buf.extend_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+    }
+
+    // The default traversal limit rejects this without allocating.
+    let result = capnp::serialize::read_message(&mut &buf[..], message::ReaderOptions::new());
+    assert!(result.is_err());
+
+    // Without a traversal limit, the total still must not wrap around. On 64-bit targets
+    // the segment table is accepted (and we then fail to read the body); on 32-bit targets
+    // the total cannot be addressed and is rejected up front.
+    let mut options = message::ReaderOptions::new();
+    options.traversal_limit_in_words(u64::max_value());
+    let mut slice = &buf[..];
+    let result = capnp::serialize::read_message_from_flat_slice(&mut slice, options);
+    match result {
+        Ok(_) => panic!("message should not have been accepted"),
+        Err(e) => {
+            if cfg!(target_pointer_width = "32") {
+                assert!(e.description.contains("too large to address"), "{}", e.description);
+            } else {
+                assert!(e.description.contains("Message ends prematurely"), "{}", e.description);
+            }
+        }
+    }
+}
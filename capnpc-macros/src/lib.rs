@@ -0,0 +1,89 @@
+// Copyright (c) 2020 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! A macro that generates code for a schema file in place, for projects that would rather not
+//! have a build script.
+//!
+//! ```ignore
+//! capnpc_macros::capnp_include!("schema/foo.capnp");
+//!
+//! fn main() {
+//!     let mut message = capnp::message::Builder::new_default();
+//!     let foo = message.init_root::<foo_capnp::foo::Builder>();
+//! }
+//! ```
+//!
+//! This expands to a `pub mod foo_capnp { ... }` holding the code that `capnpc::CompilerCommand`
+//! would have generated for `schema/foo.capnp`, compiled by the built-in schema compiler. The path
+//! is relative to the directory of the crate's `Cargo.toml`, and absolute imports are resolved
+//! relative to that directory too. Because the generated code refers to other generated modules as
+//! `crate::bar_capnp`, the macro must be invoked at the root of the crate, once for each schema
+//! file that is needed, imported ones included.
+
+extern crate proc_macro;
+
+use proc_macro::{TokenStream, TokenTree};
+use std::path::PathBuf;
+
+#[proc_macro]
+pub fn capnp_include(input: TokenStream) -> TokenStream {
+    match include(input) {
+        Ok(output) => output,
+        Err(message) => format!("compile_error!({:?});", message).parse().unwrap(),
+    }
+}
+
+fn include(input: TokenStream) -> Result<TokenStream, String> {
+    let tokens: Vec<TokenTree> = input.into_iter().collect();
+    let literal = match tokens.as_slice() {
+        [TokenTree::Literal(literal)] => literal.to_string(),
+        _ => return Err("capnp_include! expects a string literal".to_string()),
+    };
+    if !literal.starts_with('"') || !literal.ends_with('"') || literal.len() < 2 || literal.contains('\\') {
+        return Err(format!("capnp_include! expects a plain string literal, not {}", literal));
+    }
+    let relative_path = &literal[1..literal.len() - 1];
+
+    let manifest_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").map_err(|e| {
+        format!("could not access the `CARGO_MANIFEST_DIR` environment variable: {}", e)
+    })?);
+    let path = manifest_dir.join(relative_path);
+    let src_prefix = path.parent().unwrap_or(&manifest_dir).to_path_buf();
+
+    let files = capnpc::CompilerCommand::new()
+        .file(&path)
+        .src_prefix(&src_prefix)
+        .import_path(&manifest_dir)
+        .generate()
+        .map_err(|e| format!("could not compile {}: {}", relative_path, e.description))?;
+
+    let mut output = String::new();
+    for (file, text) in files {
+        let module = match file.file_stem() {
+            Some(stem) => stem.to_string_lossy().into_owned(),
+            None => return Err(format!("no module name for {}", file.display())),
+        };
+        // The include_bytes!() makes cargo rebuild the crate when the schema changes.
+        output.push_str(&format!("pub mod {} {{\n    const _SCHEMA: &[u8] = include_bytes!({:?});\n{}\n}}\n",
+                                 module, path.display().to_string(), text));
+    }
+    output.parse().map_err(|e| format!("generated code for {} does not parse: {:?}", relative_path, e))
+}
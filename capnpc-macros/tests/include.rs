@@ -0,0 +1,17 @@
+capnpc_macros::capnp_include!("tests/include.capnp");
+
+#[test]
+fn generated_module_is_usable() {
+    let mut message = capnp::message::Builder::new_default();
+    {
+        let path = message.init_root::<include_capnp::path::Builder>();
+        let mut points = path.init_points(2);
+        points.reborrow().get(1).set_x(-3);
+        points.reborrow().get(1).set_label("end");
+    }
+    let path = message.get_root_as_reader::<include_capnp::path::Reader>().unwrap();
+    let point = path.get_points().unwrap().get(1);
+    assert_eq!(point.get_x(), -3);
+    assert_eq!(point.get_y(), 0);
+    assert_eq!(point.get_label().unwrap(), "end");
+}
@@ -0,0 +1,220 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Self-describing messages, which carry the schema needed to decode them.
+//!
+//! An archive is a message whose root is a list of two pointers: the payload struct, and a
+//! `CodeGeneratorRequest` holding the payload's schema node first, followed by every node it
+//! needs, transitively. Reading an archive loads those nodes, so the payload can be read with the
+//! `dynamic` API long after the `.capnp` files it was written with are gone.
+
+use std::collections::HashSet;
+
+use capnp::{any_pointer, any_pointer_list, message, Error, Result};
+use crate::dynamic::StructReader;
+use crate::schema_capnp::{brand, code_generator_request, node, type_};
+use crate::schema_loader::SchemaLoader;
+
+fn add_brand(loader: &SchemaLoader, brand: brand::Reader, ids: &mut Vec<u64>, seen: &mut HashSet<u64>) -> Result<()> {
+    for scope in brand.get_scopes()?.iter() {
+        if let brand::scope::Bind(bindings) = scope.which()? {
+            for binding in bindings?.iter() {
+                if let brand::binding::Type(typ) = binding.which()? {
+                    add_type(loader, typ?, ids, seen)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn add_type(loader: &SchemaLoader, typ: type_::Reader, ids: &mut Vec<u64>, seen: &mut HashSet<u64>) -> Result<()> {
+    match typ.which()? {
+        type_::List(l) => add_type(loader, l.get_element_type()?, ids, seen),
+        type_::Enum(e) => add_node(loader, e.get_type_id(), ids, seen),
+        type_::Struct(s) => {
+            add_node(loader, s.get_type_id(), ids, seen)?;
+            add_brand(loader, s.get_brand()?, ids, seen)
+        }
+        type_::Interface(i) => {
+            add_node(loader, i.get_type_id(), ids, seen)?;
+            add_brand(loader, i.get_brand()?, ids, seen)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn add_node(loader: &SchemaLoader, id: u64, ids: &mut Vec<u64>, seen: &mut HashSet<u64>) -> Result<()> {
+    if !seen.insert(id) {
+        return Ok(());
+    }
+    ids.push(id);
+    let schema = loader.require(id)?;
+    match schema.which()? {
+        node::Struct(st) => {
+            for field in st.get_fields()?.iter() {
+                match field.which()? {
+                    crate::schema_capnp::field::Slot(slot) => add_type(loader, slot.get_type()?, ids, seen)?,
+                    crate::schema_capnp::field::Group(group) => add_node(loader, group.get_type_id(), ids, seen)?,
+                }
+            }
+        }
+        node::Interface(interface) => {
+            for superclass in interface.get_superclasses()?.iter() {
+                add_node(loader, superclass.get_id(), ids, seen)?;
+                add_brand(loader, superclass.get_brand()?, ids, seen)?;
+            }
+            for method in interface.get_methods()?.iter() {
+                add_node(loader, method.get_param_struct_type(), ids, seen)?;
+                add_node(loader, method.get_result_struct_type(), ids, seen)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Gets the IDs of the nodes needed to read a value of type `type_id`, starting with `type_id`.
+pub fn required_nodes(loader: &SchemaLoader, type_id: u64) -> Result<Vec<u64>> {
+    let mut ids = Vec::new();
+    add_node(loader, type_id, &mut ids, &mut HashSet::new())?;
+    Ok(ids)
+}
+
+/// Writes an archive of `payload`, a struct of type `type_id`, whose schema is in `loader`.
+pub fn write_archive(loader: &SchemaLoader, type_id: u64, payload: any_pointer::Reader)
+                     -> Result<message::Builder<message::HeapAllocator>>
+{
+    let ids = required_nodes(loader, type_id)?;
+    let mut schema = message::Builder::new_default();
+    {
+        let request = schema.init_root::<code_generator_request::Builder>();
+        let nodes = request.init_nodes(ids.len() as u32);
+        for (idx, &id) in ids.iter().enumerate() {
+            nodes.set_with_caveats(idx as u32, loader.require(id)?)?;
+        }
+    }
+
+    let mut archive = message::Builder::new_default();
+    {
+        let mut root = archive.init_root::<any_pointer::Builder>().initn_as::<any_pointer_list::Builder>(2);
+        root.reborrow().get(0).set_as(payload)?;
+        root.reborrow().get(1).set_as(schema.get_root_as_reader::<code_generator_request::Reader>()?)?;
+    }
+    Ok(archive)
+}
+
+/// An archive that has been read, along with the schema it carried.
+pub struct Archive<S> where S: message::ReaderSegments {
+    loader: SchemaLoader,
+    message: message::Reader<S>,
+    type_id: u64,
+}
+
+impl <S> Archive<S> where S: message::ReaderSegments {
+    /// Loads the schema carried by `message`, which must have been written by `write_archive()`.
+    pub fn new(message: message::Reader<S>) -> Result<Archive<S>> {
+        let mut schema = message::Builder::new_default();
+        let type_id = {
+            let root: any_pointer_list::Reader = message.get_root()?;
+            if root.len() != 2 {
+                return Err(Error::failed(format!("expected an archive of 2 pointers, got {}", root.len())));
+            }
+            let request: code_generator_request::Reader = root.get(1).get_as()?;
+            let nodes = request.get_nodes()?;
+            if nodes.len() == 0 {
+                return Err(Error::failed("archive carries no schema nodes".to_string()));
+            }
+            schema.set_root(request)?;
+            nodes.get(0).get_id()
+        };
+        let mut loader = SchemaLoader::new();
+        loader.load_request(schema.into_reader())?;
+        Ok(Archive { loader: loader, message: message, type_id: type_id })
+    }
+
+    pub fn get_loader(&self) -> &SchemaLoader { &self.loader }
+
+    /// Gets the ID of the payload's type.
+    pub fn get_type_id(&self) -> u64 { self.type_id }
+
+    /// Gets the payload, to be read with the archived schema.
+    pub fn get_payload(&self) -> Result<StructReader<'_>> {
+        let root: any_pointer_list::Reader = self.message.get_root()?;
+        StructReader::from_any_pointer(&self.loader, self.loader.require(self.type_id)?, root.get(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use capnp::{any_pointer, message, serialize};
+    use crate::dynamic::Value;
+    use crate::schema_loader::SchemaLoader;
+    use crate::text_format;
+    use super::{required_nodes, write_archive, Archive};
+
+    fn loader() -> SchemaLoader {
+        let message = crate::compiler::compile_with(&[PathBuf::from("log.capnp")], &[], &[], &|path: &Path| {
+            if path == Path::new("log.capnp") {
+                Ok("@0xc3e1a7d2b5f4098e;
+                    struct Entry {
+                      level @0 :Level;
+                      tags @1 :List(Tag);
+                      source :group { file @2 :Text; line @3 :UInt32; }
+                    }
+                    struct Tag { key @0 :Text; value @1 :Text; }
+                    enum Level { debug @0; warning @1; }
+                    struct Unrelated { x @0 :Int8; }".to_string())
+            } else {
+                Err(::capnp::Error::failed("no such file".to_string()))
+            }
+        }).unwrap();
+        let mut loader = SchemaLoader::new();
+        loader.load_request(message.into_reader()).unwrap();
+        loader
+    }
+
+    #[test]
+    fn archive_round_trip() {
+        let loader = loader();
+        let entry = loader.find("Entry").unwrap();
+        let text = "(level = warning, tags = [(key = \"k\", value = \"v\")], source = (file = \"a.rs\", line = 7))";
+
+        let ids = required_nodes(&loader, entry.get_id()).unwrap();
+        assert_eq!(ids.len(), 4);
+        assert!(!ids.contains(&loader.find("Unrelated").unwrap().get_id()));
+
+        let mut payload = message::Builder::new_default();
+        text_format::read_struct(&loader, entry, text, payload.init_root()).unwrap();
+        let archive = write_archive(&loader, entry.get_id(),
+                                    payload.get_root_as_reader::<any_pointer::Reader>().unwrap()).unwrap();
+        let bytes = serialize::write_message_to_words(&archive);
+        drop(loader);
+
+        let message = serialize::read_message_from_flat_slice(&mut &bytes[..], message::ReaderOptions::new()).unwrap();
+        let archive = Archive::new(message).unwrap();
+        let payload = archive.get_payload().unwrap();
+        assert_eq!(payload.get_schema().get_display_name().unwrap(), "log.capnp:Entry");
+        assert_eq!(text_format::to_string(Value::Struct(payload)).unwrap(), text);
+    }
+}
@@ -0,0 +1,191 @@
+// Copyright (c) 2013-2014 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! # Cap'n Proto Message Tool
+//!
+//! Converts messages between the binary and text formats, and evaluates constants, without
+//! needing generated code for their types:
+//!
+//! ```text
+//! capnp-rust decode [--packed] [--short] SCHEMA TYPE < message.bin
+//! capnp-rust encode [--packed] SCHEMA TYPE < message.txt
+//! capnp-rust eval [--short] SCHEMA NAME
+//! ```
+//!
+//! SCHEMA is either a `.capnp` file, which is compiled with the standard import paths
+//! /usr/local/include and /usr/include, or a file holding a serialized
+//! `CodeGeneratorRequest`, as written by `capnp compile -o- schema.capnp`. TYPE and NAME are names of nodes in the schema, for
+//! example `Person` or `Person.PhoneNumber`.
+
+extern crate capnp;
+extern crate capnpc;
+
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use capnp::{any_pointer, message, serialize, serialize_packed};
+use capnpc::compiler;
+use capnpc::dynamic::{StructReader, Value};
+use capnpc::schema_capnp::node;
+use capnpc::schema_loader::SchemaLoader;
+use capnpc::text_format;
+
+const USAGE: &'static str = "\
+usage: capnp-rust decode [--packed] [--short] SCHEMA TYPE
+       capnp-rust encode [--packed] SCHEMA TYPE
+       capnp-rust eval [--short] SCHEMA NAME";
+
+struct Options {
+    packed: bool,
+    short: bool,
+    schema: String,
+    name: String,
+}
+
+fn parse_args(args: &[String]) -> Option<Options> {
+    let mut options = Options { packed: false, short: false, schema: String::new(), name: String::new() };
+    let mut positional = Vec::new();
+    for arg in args {
+        match &arg[..] {
+            "--packed" => options.packed = true,
+            "--short" => options.short = true,
+            a if a.starts_with("--") => return None,
+            a => positional.push(a.to_string()),
+        }
+    }
+    if positional.len() != 2 {
+        return None;
+    }
+    options.name = positional.pop().unwrap();
+    options.schema = positional.pop().unwrap();
+    Some(options)
+}
+
+fn load_schema(path: &str) -> ::capnp::Result<SchemaLoader> {
+    let mut loader = SchemaLoader::new();
+    if path.ends_with(".capnp") {
+        let import_paths = [PathBuf::from("/usr/local/include"), PathBuf::from("/usr/include")];
+        let request = compiler::compile(&[path], &[], &import_paths)?;
+        loader.load_request(request.into_reader())?;
+        return Ok(loader);
+    }
+    let file = ::std::fs::File::open(path).map_err(|e| {
+        ::capnp::Error::failed(format!("could not open {}: {}", path, e))
+    })?;
+    loader.read_request(::std::io::BufReader::new(file))?;
+    Ok(loader)
+}
+
+fn find<'a>(loader: &'a SchemaLoader, name: &str) -> ::capnp::Result<node::Reader<'a>> {
+    loader.find(name).ok_or_else(|| ::capnp::Error::failed(format!("no such type: {}", name)))
+}
+
+fn format(value: Value, short: bool) -> ::capnp::Result<String> {
+    if short { text_format::to_string(value) } else { text_format::to_pretty_string(value) }
+}
+
+fn read_stdin() -> ::capnp::Result<Vec<u8>> {
+    let mut input = Vec::new();
+    ::std::io::stdin().read_to_end(&mut input).map_err(|e| {
+        ::capnp::Error::failed(format!("could not read standard input: {}", e))
+    })?;
+    Ok(input)
+}
+
+fn write_stdout(output: &[u8]) -> ::capnp::Result<()> {
+    ::std::io::stdout().write_all(output).map_err(|e| {
+        ::capnp::Error::failed(format!("could not write standard output: {}", e))
+    })
+}
+
+fn decode(options: &Options) -> ::capnp::Result<()> {
+    let loader = load_schema(&options.schema)?;
+    let schema = find(&loader, &options.name)?;
+    let input = read_stdin()?;
+    let mut remaining = &input[..];
+    loop {
+        let reader_options = message::ReaderOptions::new();
+        let message = if options.packed {
+            serialize_packed::try_read_message(&mut remaining, reader_options)?
+        } else {
+            serialize::try_read_message(&mut remaining, reader_options)?
+        };
+        let message = match message {
+            Some(message) => message,
+            None => return Ok(()),
+        };
+        let root = StructReader::from_any_pointer(
+            &loader, schema, message.get_root::<any_pointer::Reader>()?)?;
+        let text = format(Value::Struct(root), options.short)?;
+        write_stdout(format!("{}\n", text).as_bytes())?;
+    }
+}
+
+fn encode(options: &Options) -> ::capnp::Result<()> {
+    let loader = load_schema(&options.schema)?;
+    let schema = find(&loader, &options.name)?;
+    let input = String::from_utf8(read_stdin()?).map_err(|_| {
+        ::capnp::Error::failed("input is not valid UTF-8".to_string())
+    })?;
+    let mut message = message::Builder::new_default();
+    text_format::read_struct(&loader, schema, &input, message.init_root())?;
+    let mut output = Vec::new();
+    if options.packed {
+        serialize_packed::write_message(&mut output, &message)?;
+    } else {
+        serialize::write_message(&mut output, &message)?;
+    }
+    write_stdout(&output)
+}
+
+fn eval(options: &Options) -> ::capnp::Result<()> {
+    let loader = load_schema(&options.schema)?;
+    let constant = match find(&loader, &options.name)?.which()? {
+        node::Const(c) => c,
+        _ => return Err(::capnp::Error::failed(format!("{} is not a constant", options.name))),
+    };
+    let value = Value::from_schema_value(&loader, constant.get_type()?, constant.get_value()?)?;
+    write_stdout(format!("{}\n", format(value, options.short)?).as_bytes())
+}
+
+pub fn main() {
+    let args: Vec<String> = ::std::env::args().collect();
+    let command: fn(&Options) -> ::capnp::Result<()> = match args.get(1).map(|s| &s[..]) {
+        Some("decode") => decode,
+        Some("encode") => encode,
+        Some("eval") => eval,
+        _ => {
+            eprintln!("{}", USAGE);
+            ::std::process::exit(2);
+        }
+    };
+    let options = match parse_args(&args[2..]) {
+        Some(options) => options,
+        None => {
+            eprintln!("{}", USAGE);
+            ::std::process::exit(2);
+        }
+    };
+    if let Err(e) = command(&options) {
+        eprintln!("error: {}", e.description);
+        ::std::process::exit(1);
+    }
+}
@@ -0,0 +1,55 @@
+// Copyright (c) 2013-2014 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! # Cap'n Proto Schema Compatibility Checker
+//!
+//! Compares two versions of a schema and lists the changes that break compatibility between
+//! them. Each version is given as a file holding a serialized `CodeGeneratorRequest`, which
+//! can be produced with:
+//!
+//! ```text
+//! capnp compile -o- foo.capnp > foo.bin
+//! ```
+
+extern crate capnpc;
+
+pub fn main() {
+    let args: Vec<String> = ::std::env::args().collect();
+    if args.len() != 3 {
+        eprintln!("usage: {} OLD NEW", args[0]);
+        ::std::process::exit(2);
+    }
+
+    let open = |path: &str| {
+        ::std::fs::File::open(path).unwrap_or_else(|e| {
+            eprintln!("could not open {}: {}", path, e);
+            ::std::process::exit(2);
+        })
+    };
+    let problems = ::capnpc::compat::check_streams(open(&args[1]), open(&args[2]))
+        .expect("failed to compare schemas");
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    if !problems.is_empty() {
+        ::std::process::exit(1);
+    }
+}
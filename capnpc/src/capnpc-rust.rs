@@ -29,9 +29,20 @@ extern crate capnpc;
 
 pub fn main() {
     //! Generates Rust code according to a `schema_capnp::code_generator_request` read from stdin.
+    //! With `--check`, writes nothing and fails if any generated file would change.
+    //! With `--inline-tests`, gives each struct a module of round-trip tests for its fields.
+    //! With `--embed-schemas`, embeds the schema node of each struct in the generated code.
 
-    ::capnpc::codegen::generate_code(
-        ::std::io::stdin(),
-        ::std::path::Path::new("."))
-        .expect("failed to generate code");
+    let out_dir = ::std::path::Path::new(".");
+    let options = ::capnpc::codegen::GeneratorOptions {
+        inline_tests: ::std::env::args().skip(1).any(|arg| arg == "--inline-tests"),
+        embed_schemas: ::std::env::args().skip(1).any(|arg| arg == "--embed-schemas"),
+    };
+    if ::std::env::args().skip(1).any(|arg| arg == "--check") {
+        ::capnpc::codegen::check_code_with_options(::std::io::stdin(), out_dir, options)
+            .expect("generated code check failed");
+    } else {
+        ::capnpc::codegen::generate_code_with_options(::std::io::stdin(), out_dir, options)
+            .expect("failed to generate code");
+    }
 }
@@ -0,0 +1,324 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! CBOR (RFC 8949) encoding of Cap'n Proto values, for talking to peers that don't speak Cap'n
+//! Proto, such as small embedded devices.
+//!
+//! Structs are maps keyed by field name, lists are arrays, `Text` is a text string, `Data` a byte
+//! string, `Void` is null and enums are the names of their enumerants. Integers and floats are
+//! written in their smallest lossless encodings. When reading, enums may also be given as
+//! numbers, members whose value is null or undefined are skipped, and tags are ignored.
+//! Indefinite-length items are not supported.
+
+use capnp::{any_pointer, Error, Result};
+
+use crate::dynamic::{StructBuilder, Value};
+use crate::schema_capnp::node;
+use crate::schema_loader::SchemaLoader;
+use crate::self_describing::{self, Item};
+
+/// Encodes `value` as CBOR.
+pub fn to_vec(value: Value) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    write_item(&mut out, &self_describing::from_value("CBOR", value)?);
+    Ok(out)
+}
+
+/// Decodes `bytes`, a CBOR map, and writes it to `builder` as a struct of type `schema`.
+pub fn read_struct(loader: &SchemaLoader, schema: node::Reader, bytes: &[u8],
+                   builder: any_pointer::Builder) -> Result<()>
+{
+    read_struct_into(&mut StructBuilder::init_any_pointer(loader, schema, builder)?, bytes)
+}
+
+/// Decodes `bytes`, a CBOR map, and sets the fields that it names in `builder`.
+pub fn read_struct_into(builder: &mut StructBuilder, bytes: &[u8]) -> Result<()> {
+    let mut decoder = Decoder { bytes: bytes, pos: 0, depth: 0 };
+    let item = decoder.read_item()?;
+    if decoder.pos < bytes.len() {
+        return cbor_error("unexpected trailing bytes");
+    }
+    self_describing::fill_struct("CBOR", builder, item)
+}
+
+fn cbor_error<T>(message: &str) -> Result<T> {
+    Err(Error::failed(format!("CBOR: {}", message)))
+}
+
+fn write_head(out: &mut Vec<u8>, major: u8, n: u64) {
+    let major = major << 5;
+    if n < 24 {
+        out.push(major | n as u8);
+    } else if n <= 0xff {
+        out.push(major | 24);
+        out.push(n as u8);
+    } else if n <= 0xffff {
+        out.push(major | 25);
+        out.extend_from_slice(&(n as u16).to_be_bytes());
+    } else if n <= 0xffff_ffff {
+        out.push(major | 26);
+        out.extend_from_slice(&(n as u32).to_be_bytes());
+    } else {
+        out.push(major | 27);
+        out.extend_from_slice(&n.to_be_bytes());
+    }
+}
+
+fn write_item(out: &mut Vec<u8>, item: &Item) {
+    match *item {
+        Item::Null => out.push(0xf6),
+        Item::Bool(b) => out.push(if b { 0xf5 } else { 0xf4 }),
+        Item::Int(n) if n >= 0 => write_head(out, 0, n as u64),
+        Item::Int(n) => write_head(out, 1, (-1 - n) as u64),
+        Item::Float32(n) => {
+            out.push(0xfa);
+            out.extend_from_slice(&n.to_bits().to_be_bytes());
+        }
+        Item::Float64(n) if (n as f32) as f64 == n || n.is_nan() => write_item(out, &Item::Float32(n as f32)),
+        Item::Float64(n) => {
+            out.push(0xfb);
+            out.extend_from_slice(&n.to_bits().to_be_bytes());
+        }
+        Item::Bytes(ref b) => {
+            write_head(out, 2, b.len() as u64);
+            out.extend_from_slice(b);
+        }
+        Item::Text(ref s) => {
+            write_head(out, 3, s.len() as u64);
+            out.extend_from_slice(s.as_bytes());
+        }
+        Item::Array(ref items) => {
+            write_head(out, 4, items.len() as u64);
+            for item in items {
+                write_item(out, item);
+            }
+        }
+        Item::Map(ref members) => {
+            write_head(out, 5, members.len() as u64);
+            for &(ref key, ref value) in members {
+                write_item(out, key);
+                write_item(out, value);
+            }
+        }
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    depth: u32,
+}
+
+impl <'a> Decoder<'a> {
+    fn take(&mut self, n: u64) -> Result<&'a [u8]> {
+        if (self.bytes.len() - self.pos) as u64 >= n {
+            let result = &self.bytes[self.pos..self.pos + n as usize];
+            self.pos += n as usize;
+            Ok(result)
+        } else {
+            cbor_error("unexpected end of input")
+        }
+    }
+
+    fn read_uint(&mut self, width: usize) -> Result<u64> {
+        Ok(self.take(width as u64)?.iter().fold(0, |n, &b| n << 8 | b as u64))
+    }
+
+    /// Reads the argument of an item whose initial byte had additional information `info`.
+    fn read_argument(&mut self, info: u8) -> Result<u64> {
+        match info {
+            0..=23 => Ok(info as u64),
+            24 => self.read_uint(1),
+            25 => self.read_uint(2),
+            26 => self.read_uint(4),
+            27 => self.read_uint(8),
+            31 => cbor_error("indefinite-length items are not supported"),
+            _ => cbor_error(&format!("reserved additional information {}", info)),
+        }
+    }
+
+    /// Checks that `n` items of at least one byte each could follow, before allocating for them.
+    fn check_count(&self, n: u64) -> Result<usize> {
+        if n > (self.bytes.len() - self.pos) as u64 {
+            cbor_error("unexpected end of input")
+        } else {
+            Ok(n as usize)
+        }
+    }
+
+    fn read_item(&mut self) -> Result<Item> {
+        if self.depth >= self_describing::NESTING_LIMIT {
+            return cbor_error("nesting limit exceeded");
+        }
+        self.depth += 1;
+        let result = self.read_item_inner();
+        self.depth -= 1;
+        result
+    }
+
+    fn read_item_inner(&mut self) -> Result<Item> {
+        let initial = self.take(1)?[0];
+        let (major, info) = (initial >> 5, initial & 0x1f);
+        if major == 7 {
+            return match info {
+                20 => Ok(Item::Bool(false)),
+                21 => Ok(Item::Bool(true)),
+                22 | 23 => Ok(Item::Null),
+                25 => Ok(Item::Float32(half_to_f32(self.read_uint(2)? as u16))),
+                26 => Ok(Item::Float32(f32::from_bits(self.read_uint(4)? as u32))),
+                27 => Ok(Item::Float64(f64::from_bits(self.read_uint(8)?))),
+                _ => cbor_error(&format!("unsupported simple value {}", info)),
+            };
+        }
+        let n = self.read_argument(info)?;
+        Ok(match major {
+            0 => Item::Int(n as i128),
+            1 => Item::Int(-1 - n as i128),
+            2 => Item::Bytes(self.take(n)?.to_vec()),
+            3 => match String::from_utf8(self.take(n)?.to_vec()) {
+                Ok(s) => Item::Text(s),
+                Err(_) => return cbor_error("text string is not valid UTF-8"),
+            },
+            4 => {
+                let mut items = Vec::with_capacity(self.check_count(n)?);
+                for _ in 0..n {
+                    items.push(self.read_item()?);
+                }
+                Item::Array(items)
+            }
+            5 => {
+                let mut members = Vec::with_capacity(self.check_count(n)?);
+                for _ in 0..n {
+                    let key = self.read_item()?;
+                    members.push((key, self.read_item()?));
+                }
+                Item::Map(members)
+            }
+            _ => self.read_item()?, // A tag; the tagged item is used as is.
+        })
+    }
+}
+
+fn half_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((half >> 10) & 0x1f) as i32;
+    let mantissa = (half & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => std::f32::INFINITY,
+        31 => std::f32::NAN,
+        _ => (1024.0 + mantissa) * 2f32.powi(exponent - 25),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use capnp::{any_pointer, message};
+    use crate::dynamic::{StructReader, Value};
+    use crate::schema_loader::SchemaLoader;
+
+    fn load_schema() -> SchemaLoader {
+        let text = "@0xd6b3a1f2c4e5a708;
+                    struct Reading {
+                      sensor @0 :Text;
+                      value @1 :Float64;
+                      raw @2 :Data;
+                      offsets @3 :List(Int64);
+                      kind @4 :Kind;
+                    }
+                    enum Kind { temperature @0; humidity @1; }".to_string();
+        let message = crate::compiler::compile_with(&[PathBuf::from("reading.capnp")], &[], &[], &move |path: &Path| {
+            if path == Path::new("reading.capnp") {
+                Ok(text.clone())
+            } else {
+                Err(::capnp::Error::failed("no such file".to_string()))
+            }
+        }).unwrap();
+        let mut loader = SchemaLoader::new();
+        loader.load_request(message.into_reader()).unwrap();
+        loader
+    }
+
+    fn round_trip(loader: &SchemaLoader, bytes: &[u8]) -> (Vec<u8>, String) {
+        let schema = loader.find("Reading").unwrap();
+        let mut message = message::Builder::new_default();
+        super::read_struct(loader, schema, bytes, message.init_root()).unwrap();
+        let reader = StructReader::from_any_pointer(
+            loader, schema, message.get_root_as_reader::<any_pointer::Reader>().unwrap()).unwrap();
+        (super::to_vec(Value::Struct(reader.clone())).unwrap(), crate::json::to_string(Value::Struct(reader)).unwrap())
+    }
+
+    #[test]
+    fn encode_and_decode() {
+        let loader = load_schema();
+        let bytes = [
+            0xa5,
+            0x66, b's', b'e', b'n', b's', b'o', b'r', 0x62, b't', b'1',
+            0x65, b'v', b'a', b'l', b'u', b'e', 0xfb, 0x3f, 0xb9, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9a,
+            0x63, b'r', b'a', b'w', 0x42, 0x00, 0xff,
+            0x67, b'o', b'f', b'f', b's', b'e', b't', b's', 0x82, 0x38, 0x63, 0x1b, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            0x64, b'k', b'i', b'n', b'd', 0x68, b'h', b'u', b'm', b'i', b'd', b'i', b't', b'y',
+        ];
+        let (encoded, json) = round_trip(&loader, &bytes);
+        assert_eq!(&encoded[..], &bytes[..]);
+        assert_eq!(json, concat!(r#"{"sensor":"t1","value":0.1,"raw":[0,255],"#,
+                                 r#""offsets":["-100","9223372036854775807"],"kind":"humidity"}"#));
+
+        // Enum numbers, half floats, tags and nulls are all accepted.
+        let (_, json) = round_trip(&loader, &[0xa3, 0x64, b'k', b'i', b'n', b'd', 0x01,
+                                              0x65, b'v', b'a', b'l', b'u', b'e', 0xc1, 0xf9, 0x3e, 0x00,
+                                              0x63, b'r', b'a', b'w', 0xf6]);
+        assert_eq!(json, r#"{"value":1.5,"kind":"humidity"}"#);
+    }
+
+    #[test]
+    fn decode_errors() {
+        let loader = load_schema();
+        let schema = loader.find("Reading").unwrap();
+        let inputs: &[&[u8]] = &[
+            &[0xa1, 0x65, b'v', b'a', b'l', b'u', b'e'], // truncated
+            &[0x81, 0x01],                                // not a map
+            &[0xa1, 0x64, b'n', b'o', b'p', b'e', 0x01],  // no such field
+            &[0xa1, 0x63, b'r', b'a', b'w', 0x61, b'x'],  // text for Data
+            &[0xbf, 0xff],                                // indefinite length
+            &[0xa0, 0x00],                                // trailing bytes
+            &[0x9b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff], // absurd length
+        ];
+        for bytes in inputs {
+            let mut message = message::Builder::new_default();
+            assert!(super::read_struct(&loader, schema, bytes, message.init_root()).is_err(), "{:?}", bytes);
+        }
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let loader = load_schema();
+        let schema = loader.find("Reading").unwrap();
+        for &prefix in &[0x81, 0xc0] { // a one-element array, and a tag
+            let mut bytes = vec![prefix; 100_000];
+            bytes.push(0xa0);
+            let mut message = message::Builder::new_default();
+            assert!(super::read_struct(&loader, schema, &bytes, message.init_root()).is_err());
+        }
+    }
+}
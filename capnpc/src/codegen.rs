@@ -28,24 +28,52 @@ use capnp::Error;
 use crate::{convert_io_err};
 use crate::pointer_constants::generate_pointer_constant;
 use crate::schema_capnp;
-use crate::codegen_types::{ Leaf, RustTypeInfo, RustNodeInfo, TypeParameterTexts, do_branding };
+use crate::codegen_types::{ Leaf, RustTypeInfo, RustNodeInfo, TypeParameterTexts, do_branding, field_type_string };
+use crate::scope_path::{ItemKind, ScopePath};
 use self::FormattedText::{Indent, Line, Branch, BlankLine};
 
+/// Settings for code generation that the schemas themselves have no say in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GeneratorOptions {
+    /// Whether each struct gets a `#[cfg(test)]` module with a round-trip test for each of its
+    /// primitive, enum, Text and Data fields, which sets the field to its default value and
+    /// to values at the limits of its type, and reads it back through the builder and a reader.
+    pub inline_tests: bool,
+
+    /// Whether each struct embeds its schema node, which its module's `schema()` function
+    /// returns, and each file gets a `register_schemas()` function that passes the nodes of all
+    /// of its structs to a callback, such as the one `registry::Registry::register_schemas()`
+    /// supplies.
+    pub embed_schemas: bool,
+}
+
 pub struct GeneratorContext<'a> {
     pub request: schema_capnp::code_generator_request::Reader<'a>,
     pub node_map: collections::hash_map::HashMap<u64, schema_capnp::node::Reader<'a>>,
-    pub scope_map: collections::hash_map::HashMap<u64, Vec<String>>,
+    pub scope_map: collections::hash_map::HashMap<u64, ScopePath>,
+    pub options: GeneratorOptions,
 }
 
 impl <'a> GeneratorContext<'a> {
-    pub fn new(
-        message:&'a capnp::message::Reader<capnp::serialize::OwnedSegments>)
+    pub fn new<S>(
+        message:&'a capnp::message::Reader<S>)
+        -> ::capnp::Result<GeneratorContext<'a>>
+        where S: capnp::message::ReaderSegments
+    {
+        GeneratorContext::new_with_options(message, GeneratorOptions::default())
+    }
+
+    pub fn new_with_options<S>(
+        message:&'a capnp::message::Reader<S>,
+        options: GeneratorOptions)
         -> ::capnp::Result<GeneratorContext<'a>>
+        where S: capnp::message::ReaderSegments
     {
         let mut gen = GeneratorContext {
             request : message.get_root()?,
             node_map: collections::hash_map::HashMap::<u64, schema_capnp::node::Reader<'a>>::new(),
-            scope_map: collections::hash_map::HashMap::<u64, Vec<String>>::new(),
+            scope_map: collections::hash_map::HashMap::<u64, ScopePath>::new(),
+            options: options,
         };
 
         for node in gen.request.get_nodes()?.iter() {
@@ -63,7 +91,7 @@ impl <'a> GeneratorContext<'a> {
                     path_to_stem_string(importpath)?.replace("-", "_"));
                 populate_scope_map(&gen.node_map,
                                    &mut gen.scope_map,
-                                   vec!["crate".into()],
+                                   ScopePath::crate_root(),
                                    root_name,
                                    NameKind::Verbatim,
                                    import.get_id())?;
@@ -73,7 +101,7 @@ impl <'a> GeneratorContext<'a> {
             let root_mod = format!("{}_capnp", root_name.replace("-", "_"));
             populate_scope_map(&gen.node_map,
                                &mut gen.scope_map,
-                               vec!["crate".into()],
+                               ScopePath::crate_root(),
                                root_mod,
                                NameKind::Verbatim,
                                id)?;
@@ -84,9 +112,9 @@ impl <'a> GeneratorContext<'a> {
     fn get_last_name<'b>(&'b self, id: u64) -> ::capnp::Result<&'b str> {
         match self.scope_map.get(&id) {
             None => Err(Error::failed(format!("node not found: {}", id))),
-            Some(v) => match v.last() {
+            Some(path) => match path.last_name() {
                 None => Err(Error::failed(format!("node has no scope: {}", id))),
-                Some(n) => Ok(&n),
+                Some(n) => Ok(n),
             }
         }
     }
@@ -232,6 +260,14 @@ fn module_name(camel_case: &str) -> String {
 
 const NAME_ANNOTATION_ID: u64 = 0xc2fe4c6d100166d0;
 const PARENT_MODULE_ANNOTATION_ID: u64 = 0xabee386cd1450364;
+const INLINE_PARAMS_ANNOTATION_ID: u64 = 0x9c4db4d9ac1c7cc4;
+const IDEMPOTENT_ANNOTATION_ID: u64 = 0xd1e5b8a6f2c04e3b;
+const CACHEABLE_ANNOTATION_ID: u64 = 0xe7c91a4b3d2f6085;
+const CFG_ANNOTATION_ID: u64 = 0xe5a3c7b1d9f24680;
+const SERVER_CFG_ANNOTATION_ID: u64 = 0xb8d4f2a6c1e37095;
+const FLUENT_ANNOTATION_ID: u64 = 0xa4c8e2f6b0d13579;
+const RANGE_ANNOTATION_ID: u64 = 0xc6f1a3e5d7b92048;
+const NON_EMPTY_ANNOTATION_ID: u64 = 0x9b2d4f6e8a1c3057;
 
 fn name_annotation_value(annotation: schema_capnp::annotation::Reader) -> capnp::Result<&str> {
     if let schema_capnp::value::Text(t) = annotation.get_value()?.which()? {
@@ -293,8 +329,8 @@ fn capnp_name_to_rust_name(capnp_name: &str, name_kind: NameKind) -> String {
 }
 
 fn populate_scope_map(node_map: &collections::hash_map::HashMap<u64, schema_capnp::node::Reader>,
-                      scope_map: &mut collections::hash_map::HashMap<u64, Vec<String>>,
-                      mut ancestor_scope_names: Vec<String>,
+                      scope_map: &mut collections::hash_map::HashMap<u64, ScopePath>,
+                      mut ancestor_scope: ScopePath,
                       mut current_node_name: String,
                       current_name_kind: NameKind,
                       node_id: u64) -> ::capnp::Result<()> {
@@ -307,14 +343,18 @@ fn populate_scope_map(node_map: &collections::hash_map::HashMap<u64, schema_capn
                 current_node_name = name_annotation_value(annotation)?.to_string();
              }
         } else if annotation.get_id() == PARENT_MODULE_ANNOTATION_ID {
-            ancestor_scope_names.append(&mut get_parent_module(annotation)?);
+            ancestor_scope.segments.append(&mut get_parent_module(annotation)?);
         }
     }
 
-    let mut scope_names = ancestor_scope_names;
-    scope_names.push(capnp_name_to_rust_name(&current_node_name, current_name_kind));
+    let kind = match node_reader.which()? {
+        schema_capnp::node::Enum(_) => ItemKind::Type,
+        schema_capnp::node::Const(_) => ItemKind::Value,
+        _ => ItemKind::Module,
+    };
+    let scope = ancestor_scope.child(capnp_name_to_rust_name(&current_node_name, current_name_kind), kind);
 
-    scope_map.insert(node_id, scope_names.clone());
+    scope_map.insert(node_id, scope.clone());
 
     let nested_nodes = node_reader.get_nested_nodes()?;
     for nested_node in nested_nodes.iter(){
@@ -326,7 +366,7 @@ fn populate_scope_map(node_map: &collections::hash_map::HashMap<u64, schema_capn
                     Ok(schema_capnp::node::Enum(_enum_reader)) => {
                         populate_scope_map(node_map,
                                            scope_map,
-                                           scope_names.clone(),
+                                           scope.clone(),
                                            nested_node.get_name()?.to_string(),
                                            NameKind::Verbatim,
                                            nested_node_id)?;
@@ -334,7 +374,7 @@ fn populate_scope_map(node_map: &collections::hash_map::HashMap<u64, schema_capn
                     _ => {
                         populate_scope_map(node_map,
                                            scope_map,
-                                           scope_names.clone(),
+                                           scope.clone(),
                                            nested_node.get_name()?.to_string(),
                                            NameKind::Module,
                                            nested_node_id)?;
@@ -352,7 +392,7 @@ fn populate_scope_map(node_map: &collections::hash_map::HashMap<u64, schema_capn
                     Ok(schema_capnp::field::Group(group)) => {
                         populate_scope_map(node_map,
                                            scope_map,
-                                           scope_names.clone(),
+                                           scope.clone(),
                                            get_field_name(field)?.to_string(),
                                            NameKind::Module,
                                            group.get_type_id())?;
@@ -408,14 +448,9 @@ pub fn getter_text(gen: &GeneratorContext,
     use crate::schema_capnp::*;
 
     match field.which()? {
-        field::Group(group) => {
-            let the_mod = gen.scope_map[&group.get_type_id()].join("::");
-
-            let mut result_type = if is_reader {
-                format!("{}::Reader<'a>", the_mod)
-            } else {
-                format!("{}::Builder<'a>", the_mod)
-            };
+        field::Group(_) => {
+            let module = if is_reader { Leaf::Reader("'a") } else { Leaf::Builder("'a") };
+            let mut result_type = field_type_string(gen, *field, module)?;
 
             if is_fn {
                 result_type = format!("-> {}", result_type);
@@ -446,7 +481,7 @@ pub fn getter_text(gen: &GeneratorContext,
             }
 
             let raw_type = reg_field.get_type()?;
-            let typ = raw_type.type_string(gen, module)?;
+            let typ = field_type_string(gen, *field, module)?;
             let default_value = reg_field.get_default_value()?;
             let default = default_value.which()?;
             let default_name = format!("DEFAULT_{}", snake_to_upper_case(&camel_to_snake_case(get_field_name(*field)?)));
@@ -556,67 +591,95 @@ pub fn getter_text(gen: &GeneratorContext,
 }
 
 fn zero_fields_of_group(gen: &GeneratorContext, node_id: u64) -> ::capnp::Result<FormattedText> {
-    use crate::schema_capnp::{node, field, type_};
+    let mut result = Vec::new();
+    push_zero_fields_of_group(gen, node_id, &mut result)?;
+    Ok(Branch(result))
+}
+
+fn push_zero_fields_of_group(gen: &GeneratorContext, node_id: u64,
+                             result: &mut Vec<FormattedText>) -> ::capnp::Result<()> {
+    use crate::schema_capnp::node;
     match gen.node_map[&node_id].which()? {
         node::Struct(st) => {
-            let mut result = Vec::new();
             if st.get_discriminant_count() != 0 {
-                result.push(
-                    Line(format!("self.builder.set_data_field::<u16>({}, 0);",
-                                 st.get_discriminant_offset())));
+                let line = Line(format!("self.builder.set_data_field::<u16>({}, 0);",
+                                        st.get_discriminant_offset()));
+                if !result.contains(&line) { result.push(line) }
             }
             let fields = st.get_fields()?;
             for field in fields.iter() {
-                match field.which()? {
-                    field::Group(group) => {
-                        result.push(zero_fields_of_group(gen, group.get_type_id())?);
-                    }
-                    field::Slot(slot) => {
-                        let typ = slot.get_type()?.which()?;
-                        match typ {
-                            type_::Void(()) => {}
-                            type_::Bool(()) => {
-                                let line = Line(format!("self.builder.set_bool_field({}, false);",
-                                                        slot.get_offset()));
-                                // PERF could dedup more efficiently
-                                if !result.contains(&line) { result.push(line) }
-                            }
-                            type_::Int8(()) |
-                            type_::Int16(()) | type_::Int32(()) | type_::Int64(()) |
-                            type_::Uint8(()) | type_::Uint16(()) | type_::Uint32(()) |
-                            type_::Uint64(()) | type_::Float32(()) | type_::Float64(()) => {
-                                let line = Line(format!(
-                                    "self.builder.set_data_field::<{0}>({1}, 0{0});",
-                                    slot.get_type()?.type_string(gen, Leaf::Builder("'a"))?,
-                                    slot.get_offset()));
-                                // PERF could dedup more efficiently
-                                if !result.contains(&line) { result.push(line) }
-                            }
-                            type_::Enum(_) => {
-                                let line = Line(format!("self.builder.set_data_field::<u16>({}, 0u16);",
-                                                        slot.get_offset()));
-                                // PERF could dedup more efficiently
-                                if !result.contains(&line) { result.push(line) }
-                            }
-                            type_::Struct(_) | type_::List(_) | type_::Text(()) | type_::Data(()) |
-                            type_::AnyPointer(_) |
-                            type_::Interface(_) // Is this the right thing to do for interfaces?
-                                => {
-                                    let line = Line(format!("self.builder.get_pointer_field({}).clear();",
-                                                            slot.get_offset()));
-                                    // PERF could dedup more efficiently
-                                    if !result.contains(&line) { result.push(line) }
-                                }
-                        }
-                    }
-                }
+                push_zero_field(gen, field, result)?;
             }
-            Ok(Branch(result))
+            Ok(())
         }
         _ => Err(Error::failed(format!("zero_fields_of_groupd() expected a struct"))),
     }
 }
 
+fn push_zero_field(gen: &GeneratorContext, field: schema_capnp::field::Reader,
+                   result: &mut Vec<FormattedText>) -> ::capnp::Result<()> {
+    use crate::schema_capnp::{field, type_};
+    let line = match field.which()? {
+        field::Group(group) => {
+            return push_zero_fields_of_group(gen, group.get_type_id(), result);
+        }
+        field::Slot(slot) => {
+            let typ = slot.get_type()?.which()?;
+            match typ {
+                type_::Void(()) => return Ok(()),
+                type_::Bool(()) => {
+                    Line(format!("self.builder.set_bool_field({}, false);",
+                                 slot.get_offset()))
+                }
+                type_::Int8(()) |
+                type_::Int16(()) | type_::Int32(()) | type_::Int64(()) |
+                type_::Uint8(()) | type_::Uint16(()) | type_::Uint32(()) |
+                type_::Uint64(()) | type_::Float32(()) | type_::Float64(()) => {
+                    Line(format!(
+                        "self.builder.set_data_field::<{0}>({1}, 0{0});",
+                        slot.get_type()?.type_string(gen, Leaf::Builder("'a"))?,
+                        slot.get_offset()))
+                }
+                type_::Enum(_) => {
+                    Line(format!("self.builder.set_data_field::<u16>({}, 0u16);",
+                                 slot.get_offset()))
+                }
+                type_::Struct(_) | type_::List(_) | type_::Text(()) | type_::Data(()) |
+                type_::AnyPointer(_) |
+                type_::Interface(_) // Is this the right thing to do for interfaces?
+                    => {
+                        Line(format!("self.builder.get_pointer_field({}).clear();",
+                                     slot.get_offset()))
+                    }
+            }
+        }
+    };
+    // PERF could dedup more efficiently
+    if !result.contains(&line) { result.push(line) }
+    Ok(())
+}
+
+/// Zeroes the fields of a group that is a union member, along with the fields of all of its
+/// sibling variants, so that switching to the group leaves no data or pointers behind from
+/// whichever variant was previously active.
+fn zero_fields_of_union_group(gen: &GeneratorContext, field: schema_capnp::field::Reader,
+                              group_id: u64) -> ::capnp::Result<FormattedText> {
+    use crate::schema_capnp::{field, node};
+    let mut result = Vec::new();
+    let scope_id = gen.node_map[&group_id].get_scope_id();
+    if let node::Struct(st) = gen.node_map[&scope_id].which()? {
+        for sibling in st.get_fields()?.iter() {
+            if sibling.get_discriminant_value() != field::NO_DISCRIMINANT &&
+                sibling.get_code_order() != field.get_code_order()
+            {
+                push_zero_field(gen, sibling, &mut result)?;
+            }
+        }
+    }
+    push_zero_fields_of_group(gen, group_id, &mut result)?;
+    Ok(Branch(result))
+}
+
 fn generate_setter(gen: &GeneratorContext, discriminant_offset: u32,
                    styled_name: &str,
                    field: &schema_capnp::field::Reader) -> ::capnp::Result<FormattedText> {
@@ -642,20 +705,25 @@ fn generate_setter(gen: &GeneratorContext, discriminant_offset: u32,
         initn_interior.push(init_discrim);
     }
 
+    // Setters copy their argument, so it need not outlive the builder: unless the setter
+    // body has to name it, the argument's lifetime is left anonymous.
+    let setter_leaf = Leaf::Reader("'_");
+
     let mut setter_generic_param = String::new();
     let mut return_result = false;
     let mut result = Vec::new();
 
     let (maybe_reader_type, maybe_builder_type) : (Option<String>, Option<String>) = match field.which()? {
         field::Group(group) => {
-            let scope = &gen.scope_map[&group.get_type_id()];
-            let the_mod = scope.join("::");
-
-            initter_interior.push(zero_fields_of_group(gen, group.get_type_id())?);
+            if discriminant_value != field::NO_DISCRIMINANT {
+                initter_interior.push(zero_fields_of_union_group(gen, *field, group.get_type_id())?);
+            } else {
+                initter_interior.push(zero_fields_of_group(gen, group.get_type_id())?);
+            }
 
             initter_interior.push(Line(format!("::capnp::traits::FromStructBuilder::new(self.builder)")));
 
-            (None, Some(format!("{}::Builder<'a>", the_mod)))
+            (None, Some(field_type_string(gen, *field, Leaf::Builder("'a"))?))
         }
         field::Slot(reg_field) => {
             let offset = reg_field.get_offset() as usize;
@@ -663,7 +731,7 @@ fn generate_setter(gen: &GeneratorContext, discriminant_offset: u32,
             match typ.which().ok().expect("unrecognized type") {
                 type_::Void(()) => {
                     setter_param = "_value".to_string();
-                    (Some("()".to_string()), None)
+                    (Some(typ.type_string(gen, setter_leaf)?), None)
                 }
                 type_::Bool(()) => {
                     match prim_default(&reg_field.get_default_value()?)? {
@@ -675,10 +743,10 @@ fn generate_setter(gen: &GeneratorContext, discriminant_offset: u32,
                                 Line(format!("self.builder.set_bool_field_mask({}, value, {});", offset, s)));
                         }
                     }
-                    (Some("bool".to_string()), None)
+                    (Some(typ.type_string(gen, setter_leaf)?), None)
                 }
                 _ if typ.is_prim()? => {
-                    let tstr = typ.type_string(gen, Leaf::Reader("'a"))?;
+                    let tstr = typ.type_string(gen, setter_leaf)?;
                     match prim_default(&reg_field.get_default_value()?)? {
                         None => {
                             setter_interior.push(Line(format!("self.builder.set_data_field::<{}>({}, value);",
@@ -693,12 +761,22 @@ fn generate_setter(gen: &GeneratorContext, discriminant_offset: u32,
                     (Some(tstr), None)
                 }
                 type_::Text(()) => {
+                    let reader_type = typ.type_string(gen, setter_leaf)?;
+                    let mut interned_interior = setter_interior.clone();
+                    interned_interior.push(Line(format!("interner.set(self.builder.get_pointer_field({}), value);",
+                                                        offset)));
+                    result.push(Line("#[inline]".to_string()));
+                    result.push(Line(format!(
+                        "pub fn set_{}_interned(&mut self, value: {}, interner: &mut ::capnp::text::Interner) {{",
+                        styled_name, reader_type)));
+                    result.push(Indent(Box::new(Branch(interned_interior))));
+                    result.push(Line("}".to_string()));
                     setter_interior.push(Line(format!("self.builder.get_pointer_field({}).set_text(value);",
                                                       offset)));
                     initter_interior.push(Line(format!("self.builder.get_pointer_field({}).init_text(size)",
                                                        offset)));
                     initter_params.push("size: u32");
-                    (Some("::capnp::text::Reader".to_string()), Some("::capnp::text::Builder<'a>".to_string()))
+                    (Some(reader_type), Some(typ.type_string(gen, Leaf::Builder("'a"))?))
                 }
                 type_::Data(()) => {
                     setter_interior.push(Line(format!("self.builder.get_pointer_field({}).set_data(value);",
@@ -706,9 +784,9 @@ fn generate_setter(gen: &GeneratorContext, discriminant_offset: u32,
                     initter_interior.push(Line(format!("self.builder.get_pointer_field({}).init_data(size)",
                                                        offset)));
                     initter_params.push("size: u32");
-                    (Some("::capnp::data::Reader".to_string()), Some("::capnp::data::Builder<'a>".to_string()))
+                    (Some(typ.type_string(gen, setter_leaf)?), Some(typ.type_string(gen, Leaf::Builder("'a"))?))
                 }
-                type_::List(ot1) => {
+                type_::List(_) => {
                     return_result = true;
                     setter_interior.push(
                         Line(format!("::capnp::traits::SetPointerBuilder::set_pointer_builder(self.builder.get_pointer_field({}), value, false)",
@@ -718,32 +796,27 @@ fn generate_setter(gen: &GeneratorContext, discriminant_offset: u32,
                     initter_interior.push(
                         Line(format!("::capnp::traits::FromPointerBuilder::init_pointer(self.builder.get_pointer_field({}), size)", offset)));
 
-                    match ot1.get_element_type()?.which()? {
-                        type_::List(_) => {
-                            setter_generic_param = "<'b>".to_string();
-                            (Some(reg_field.get_type()?.type_string(gen, Leaf::Reader("'b"))?),
-                             Some(reg_field.get_type()?.type_string(gen, Leaf::Builder("'a"))?))
-                        }
-                        _ =>
-                            (Some(reg_field.get_type()?.type_string(gen, Leaf::Reader("'a"))?),
-                             Some(reg_field.get_type()?.type_string(gen, Leaf::Builder("'a"))?))
-                    }
+                    (Some(typ.type_string(gen, setter_leaf)?), Some(typ.type_string(gen, Leaf::Builder("'a"))?))
                 }
-                type_::Enum(e) => {
-                    let id = e.get_type_id();
-                    let the_mod = gen.scope_map[&id].join("::");
-                    setter_interior.push(
-                        Line(format!("self.builder.set_data_field::<u16>({}, value as u16)",
-                                     offset)));
-                    (Some(format!("{}", the_mod)), None)
+                type_::Enum(_) => {
+                    match reg_field.get_default_value()?.which()? {
+                        value::Enum(d) if d != 0 => setter_interior.push(
+                            Line(format!("self.builder.set_data_field_mask::<u16>({}, value as u16, {})",
+                                         offset, d))),
+                        _ => setter_interior.push(
+                            Line(format!("self.builder.set_data_field::<u16>({}, value as u16)",
+                                         offset))),
+                    }
+                    (Some(typ.type_string(gen, setter_leaf)?), None)
                 }
                 type_::Struct(_) => {
                     return_result = true;
-                    setter_generic_param = "<'b>".to_string();
                     initter_interior.push(
                       Line(format!("::capnp::traits::FromPointerBuilder::init_pointer(self.builder.get_pointer_field({}), 0)",
                                    offset)));
                     if typ.is_branded()? {
+                        // The body names the argument's type, so its lifetime needs a name too.
+                        setter_generic_param = "<'b>".to_string();
                         setter_interior.push(
                             Line(format!(
                                 "<{} as ::capnp::traits::SetPointerBuilder<{}>>::set_pointer_builder(self.builder.get_pointer_field({}), value, false)",
@@ -755,8 +828,8 @@ fn generate_setter(gen: &GeneratorContext, discriminant_offset: u32,
                     } else {
                         setter_interior.push(
                             Line(format!("::capnp::traits::SetPointerBuilder::set_pointer_builder(self.builder.get_pointer_field({}), value, false)", offset)));
-                        (Some(reg_field.get_type()?.type_string(gen, Leaf::Reader("'b"))?),
-                         Some(reg_field.get_type()?.type_string(gen, Leaf::Builder("'a"))?))
+                        (Some(typ.type_string(gen, setter_leaf)?),
+                         Some(typ.type_string(gen, Leaf::Builder("'a"))?))
                     }
                 }
                 type_::Interface(_) => {
@@ -913,10 +986,20 @@ fn generate_union(gen: &GeneratorContext,
 
     let field_name = if is_reader { "reader" } else { "builder" };
 
+    // However deeply the union is nested, its variants may only mention some of the
+    // enclosing type parameters, and a type alias must use every parameter it declares.
+    let used_params: Vec<&str> = params.expanded_list.iter()
+        .map(|p| &p[..])
+        .filter(|p| ty_args.iter().any(|arg| {
+            arg.split(|c: char| !(c.is_alphanumeric() || c == '_')).any(|token| token == *p)
+        }))
+        .collect();
     let concrete_type =
             format!("Which{}{}",
                     if is_reader {"Reader"} else {"Builder"},
-                    if ty_params.len() > 0 { format!("<'a,{}>", params.params) } else { "".to_string() });
+                    if ty_params.len() > 0 {
+                        format!("<'a{}>", used_params.iter().map(|p| format!(",{}", p)).collect::<String>())
+                    } else { "".to_string() });
 
     let typedef =
         Line(format!("pub type {} = Which{};",
@@ -939,6 +1022,504 @@ fn generate_union(gen: &GeneratorContext,
     Ok((result, getter_result, typedef, default_decls))
 }
 
+// The `default` argument that reader getters pass when reading the pointer field `field`.
+fn pointer_default(field: &schema_capnp::field::Reader,
+                   reg_field: schema_capnp::field::slot::Reader) -> ::capnp::Result<String> {
+    if reg_field.get_had_explicit_default() {
+        Ok(format!("Some(&_private::DEFAULT_{}[..])",
+                   snake_to_upper_case(&camel_to_snake_case(get_field_name(*field)?))))
+    } else {
+        Ok("::core::option::Option::None".to_string())
+    }
+}
+
+// For Text fields, generates a reader getter that skips UTF-8 validation.
+fn generate_text_bytes_getter(styled_name: &str,
+                              field: &schema_capnp::field::Reader) -> ::capnp::Result<Option<FormattedText>> {
+    use crate::schema_capnp::*;
+
+    let reg_field = match field.which()? {
+        field::Slot(reg_field) => reg_field,
+        _ => return Ok(None),
+    };
+    match reg_field.get_type()?.which()? {
+        type_::Text(()) => (),
+        _ => return Ok(None),
+    }
+    Ok(Some(Branch(vec!(
+        Line("#[inline]".to_string()),
+        Line(format!("pub fn get_{}_bytes(self) -> ::capnp::Result<&'a [u8]> {{", styled_name)),
+        Indent(Box::new(Line(format!("self.reader.get_pointer_field({}).get_text_bytes({})",
+                                     reg_field.get_offset(), pointer_default(field, reg_field)?)))),
+        Line("}".to_string())))))
+}
+
+// For Text, Data, List and Struct fields, generates a reader getter that returns the field's
+// default value instead of an error.
+fn generate_or_default_getter(gen: &GeneratorContext, styled_name: &str,
+                              field: &schema_capnp::field::Reader) -> ::capnp::Result<Option<FormattedText>> {
+    use crate::schema_capnp::*;
+
+    let reg_field = match field.which()? {
+        field::Slot(reg_field) => reg_field,
+        _ => return Ok(None),
+    };
+    let raw_type = reg_field.get_type()?;
+    match raw_type.which()? {
+        type_::Text(()) | type_::Data(()) | type_::List(_) | type_::Struct(_) => (),
+        type_::AnyPointer(_) if raw_type.is_parameter()? => (),
+        _ => return Ok(None),
+    }
+    Ok(Some(Branch(vec!(
+        Line("#[inline]".to_string()),
+        Line(format!("pub fn get_{}_or_default(self) -> {} {{",
+                     styled_name, raw_type.type_string(gen, Leaf::Reader("'a"))?)),
+        Indent(Box::new(Branch(vec!(
+            Line(format!("self.get_{}().unwrap_or_else(|_| {{", styled_name)),
+            Indent(Box::new(Line(format!(
+                "::capnp::traits::FromPointerReader::get_from_pointer(&::capnp::private::layout::PointerReader::new_default(), {}).expect(\"default value\")",
+                pointer_default(field, reg_field)?)))),
+            Line("})".to_string()))))),
+        Line("}".to_string())))))
+}
+
+// Generates the static table of a struct's fields, for the `_private` module, and the
+// `get_field_by_ordinal()` method of its reader.
+fn generate_field_table(gen: &GeneratorContext, discriminant_offset: u32,
+                        fields: ::capnp::struct_list::Reader<schema_capnp::field::Owned>)
+                        -> ::capnp::Result<(FormattedText, FormattedText)> {
+    use crate::schema_capnp::*;
+
+    let mut infos = Vec::new();
+    let mut arms = Vec::new();
+    for field in fields.iter() {
+        let ordinal = match field.get_ordinal().which()? {
+            field::ordinal::Explicit(n) => Some(n),
+            field::ordinal::Implicit(()) => None,
+        };
+        let discriminant_value = match field.get_discriminant_value() {
+            field::NO_DISCRIMINANT => None,
+            v => Some(v),
+        };
+        let default_bits = match field.which()? {
+            field::Slot(slot) => crate::dynamic::default_bits(slot.get_default_value()?)?,
+            field::Group(_) => 0,
+        };
+        let (offset, type_tag) = match field.which()? {
+            field::Group(_) => (0, "Group"),
+            field::Slot(slot) => (slot.get_offset(), match slot.get_type()?.which()? {
+                type_::Void(()) => "Void",
+                type_::Bool(()) => "Bool",
+                type_::Int8(()) => "Int8",
+                type_::Int16(()) => "Int16",
+                type_::Int32(()) => "Int32",
+                type_::Int64(()) => "Int64",
+                type_::Uint8(()) => "UInt8",
+                type_::Uint16(()) => "UInt16",
+                type_::Uint32(()) => "UInt32",
+                type_::Uint64(()) => "UInt64",
+                type_::Float32(()) => "Float32",
+                type_::Float64(()) => "Float64",
+                type_::Text(()) => "Text",
+                type_::Data(()) => "Data",
+                type_::List(_) => "List",
+                type_::Enum(_) => "Enum",
+                type_::Struct(_) => "Struct",
+                type_::Interface(_) => "Interface",
+                type_::AnyPointer(_) => "AnyPointer",
+            }),
+        };
+        infos.push(Line(format!(
+            "::capnp::fields::FieldInfo {{ name: \"{}\", ordinal: {:?}, offset: {}, type_tag: ::capnp::fields::TypeTag::{}, discriminant_value: {:?}, default_bits: {} }},",
+            field.get_name()?, ordinal, offset, type_tag, discriminant_value, default_bits)));
+
+        let (ordinal, slot) = match (ordinal, field.which()?) {
+            (Some(ordinal), field::Slot(slot)) => (ordinal, slot),
+            _ => continue,
+        };
+        let value = match type_tag {
+            "Void" => "::capnp::fields::FieldValue::Void".to_string(),
+            "Enum" => {
+                let default = match slot.get_default_value()?.which()? {
+                    value::Enum(d) => d,
+                    _ => return Err(Error::failed(format!("default value was of wrong type"))),
+                };
+                format!("::capnp::fields::FieldValue::Enum(self.reader.get_data_field_mask::<u16>({}, {}))",
+                        offset, default)
+            }
+            "List" | "Struct" | "Interface" | "AnyPointer" => format!(
+                "::capnp::fields::FieldValue::Pointer(::capnp::any_pointer::Reader::new(self.reader.get_pointer_field({})))",
+                offset),
+            _ => {
+                let expression = match getter_text(gen, &field, true, true)?.1 {
+                    Line(expression) => expression,
+                    _ => return Err(Error::failed(format!("expected a one-line getter"))),
+                };
+                let expression = if type_tag == "Text" || type_tag == "Data" {
+                    format!("{}?", expression)
+                } else {
+                    expression
+                };
+                format!("::capnp::fields::FieldValue::{}({})", type_tag, expression)
+            }
+        };
+        let mut arm = Vec::new();
+        if let Some(v) = discriminant_value {
+            arm.push(Line(format!(
+                "if self.reader.get_data_field::<u16>({}) != {} {{ return ::core::result::Result::Ok(::core::option::Option::None); }}",
+                discriminant_offset, v)));
+        }
+        arm.push(Line(format!("::core::result::Result::Ok(::core::option::Option::Some({}))", value)));
+        arms.push(Branch(vec!(
+            Line(format!("{} => {{", ordinal)),
+            Indent(Box::new(Branch(arm))),
+            Line("}".to_string()))));
+    }
+    arms.push(Line("_ => ::core::result::Result::Ok(::core::option::Option::None),".to_string()));
+
+    let table = Branch(vec!(
+        Line(format!("pub static FIELDS: [::capnp::fields::FieldInfo; {}] = [", infos.len())),
+        Indent(Box::new(Branch(infos))),
+        Line("];".to_string())));
+    let getter = Branch(vec!(
+        Line("pub fn get_field_by_ordinal(self, ordinal: u16) -> ::capnp::Result<::core::option::Option<::capnp::fields::FieldValue<'a>>> {".to_string()),
+        Indent(Box::new(Branch(vec!(
+            Line("match ordinal {".to_string()),
+            Indent(Box::new(Branch(arms))),
+            Line("}".to_string()))))),
+        Line("}".to_string())));
+    Ok((table, getter))
+}
+
+// Generates the `field_info` module, with a `::capnp::fields::FieldLayout` constant for each
+// field that is not a group.
+fn generate_field_layouts(fields: ::capnp::struct_list::Reader<schema_capnp::field::Owned>)
+                          -> ::capnp::Result<FormattedText>
+{
+    use crate::schema_capnp::field;
+    use capnp::private::layout::ElementSize;
+
+    let mut constants = Vec::new();
+    for field in fields.iter() {
+        let (slot, ordinal) = match (field.which()?, field.get_ordinal().which()?) {
+            (field::Slot(slot), field::ordinal::Explicit(ordinal)) => (slot, ordinal),
+            _ => continue,
+        };
+        let element_size = match crate::dynamic::element_size(slot.get_type()?)? {
+            ElementSize::InlineComposite => ElementSize::Pointer,
+            size => size,
+        };
+        constants.push(Line(format!(
+            "pub const {}: ::capnp::fields::FieldLayout = ::capnp::fields::FieldLayout {{ ordinal: {}, offset: {}, element_size: ::capnp::private::layout::ElementSize::{:?} }};",
+            snake_to_upper_case(&camel_to_snake_case(get_field_name(field)?)), ordinal, slot.get_offset(),
+            element_size)));
+    }
+    if constants.is_empty() {
+        return Ok(Branch(Vec::new()));
+    }
+    Ok(Branch(vec!(
+        Line("/// Where each field is stored, for comparing messages against other implementations'.".to_string()),
+        Line("pub mod field_info {".to_string()),
+        Indent(Box::new(Branch(constants))),
+        Line("}".to_string()))))
+}
+
+// Whether `typ` is a generic parameter or a list of them, whose values are written as opaque
+// pointers because nothing is known about their types.
+fn mentions_parameter(typ: schema_capnp::type_::Reader) -> ::capnp::Result<bool> {
+    match typ.which()? {
+        schema_capnp::type_::List(l) => mentions_parameter(l.get_element_type()?),
+        _ => typ.is_parameter(),
+    }
+}
+
+// Binds `value` to the value of `field`, read the way its reader getter reads it.
+fn typed_getter(gen: &GeneratorContext, field: &schema_capnp::field::Reader) -> ::capnp::Result<FormattedText> {
+    let (ty, get, _) = getter_text(gen, field, true, false)?;
+    match get {
+        Line(get) => Ok(Line(format!("let value: {} = {};", ty, get))),
+        get => Ok(Branch(vec!(Line(format!("let value: {} =", ty)), Indent(Box::new(get)), Line(";".to_string())))),
+    }
+}
+
+// Generates the reader's implementations of `Stringify` and `Display`, which write it in the
+// text format: every active field, except for null pointers.
+fn generate_stringify(gen: &GeneratorContext, discriminant_offset: u32,
+                      fields: ::capnp::struct_list::Reader<schema_capnp::field::Owned>,
+                      params: &TypeParameterTexts) -> ::capnp::Result<FormattedText> {
+    use crate::schema_capnp::*;
+
+    let mut interior = Vec::new();
+    for field in fields.iter() {
+        let mut conditions = Vec::new();
+        if field.get_discriminant_value() != field::NO_DISCRIMINANT {
+            conditions.push(format!("self.reader.get_data_field::<u16>({}) == {}",
+                                    discriminant_offset, field.get_discriminant_value()));
+        }
+        let value = match field.which()? {
+            field::Slot(slot) => {
+                let typ = slot.get_type()?;
+                let is_enum = match typ.which()? { type_::Enum(_) => true, _ => false };
+                if !typ.is_prim()? && !is_enum {
+                    conditions.push(format!("!self.reader.get_pointer_field({}).is_null()", slot.get_offset()));
+                }
+                match typ.which()? {
+                    type_::Interface(_) => None,
+                    _ if mentions_parameter(typ)? => Some(Line(format!(
+                        "let value = ::capnp::any_pointer::Reader::new(self.reader.get_pointer_field({}));",
+                        slot.get_offset()))),
+                    _ => Some(typed_getter(gen, &field)?),
+                }
+            }
+            field::Group(_) => Some(typed_getter(gen, &field)?),
+        };
+        let write = match value {
+            Some(value) => Branch(vec!(
+                value,
+                Line(format!("::capnp::stringify::write_field(w, &mut first, \"{}\", &value)?;", field.get_name()?)))),
+            None => Line(format!(
+                "::capnp::stringify::write_field(w, &mut first, \"{}\", &::capnp::stringify::Capability)?;",
+                field.get_name()?)),
+        };
+        let head = if conditions.is_empty() { "{".to_string() } else { format!("if {} {{", conditions.join(" && ")) };
+        interior.push(Branch(vec!(Line(head), Indent(Box::new(write)), Line("}".to_string()))));
+    }
+
+    let mut body = Vec::new();
+    if !interior.is_empty() {
+        body.push(Line("let mut first = true;".to_string()));
+    }
+    body.push(Line("w.write_str(\"(\")?;".to_string()));
+    body.push(Branch(interior));
+    body.push(Line("w.write_str(\")\")".to_string()));
+
+    Ok(Branch(vec!(
+        Line(format!("impl <'a,{0}> ::capnp::stringify::Stringify for Reader<'a,{0}> {1} {{",
+                     params.params, params.where_clause)),
+        Indent(Box::new(Branch(vec!(
+            // The parentheses keep `dyn ::core` from parsing as a path in 2015-edition crates.
+            Line("#[allow(unused_parens)]".to_string()),
+            Line("fn stringify(&self, w: &mut dyn (::core::fmt::Write)) -> ::core::fmt::Result {".to_string()),
+            Indent(Box::new(Branch(body))),
+            Line("}".to_string()))))),
+        Line("}".to_string()),
+        BlankLine,
+        Line(format!("impl <'a,{0}> ::core::fmt::Display for Reader<'a,{0}> {1} {{",
+                     params.params, params.where_clause)),
+        Indent(Box::new(Branch(vec!(
+            Line("fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {".to_string()),
+            Indent(Box::new(Line("::capnp::stringify::Stringify::stringify(self, f)".to_string()))),
+            Line("}".to_string()))))),
+        Line("}".to_string()))))
+}
+
+// Whether the struct or group `node_id` has fields with `range` or `nonEmpty` annotations,
+// directly or in its groups, and so gets a `validate()` method.
+fn has_constraints(gen: &GeneratorContext, node_id: u64) -> ::capnp::Result<bool> {
+    use crate::schema_capnp::{field, node};
+
+    let st = match gen.node_map.get(&node_id) {
+        Some(node) => match node.which()? {
+            node::Struct(st) => st,
+            _ => return Ok(false),
+        },
+        None => return Ok(false),
+    };
+    for field in st.get_fields()?.iter() {
+        let annotations = field.get_annotations()?;
+        if has_annotation(annotations, RANGE_ANNOTATION_ID) ||
+            has_annotation(annotations, NON_EMPTY_ANNOTATION_ID)
+        {
+            return Ok(true);
+        }
+        if let field::Group(group) = field.which()? {
+            if has_constraints(gen, group.get_type_id())? {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+// Generates the reader's `validate()` method, which checks the `range` and `nonEmpty`
+// annotations on its active fields and calls `validate()` on its groups that have any.
+fn generate_validate(gen: &GeneratorContext, node_id: u64, discriminant_offset: u32,
+                     fields: ::capnp::struct_list::Reader<schema_capnp::field::Owned>)
+                     -> ::capnp::Result<FormattedText>
+{
+    use crate::schema_capnp::*;
+
+    if !has_constraints(gen, node_id)? {
+        return Ok(Branch(Vec::new()));
+    }
+    // The struct's name in its file, such as `Foo.bar` for a group, as opposed to the last
+    // part only, which is what the display name prefix length marks.
+    let display_name = gen.node_map[&node_id].get_display_name()?;
+    let struct_name = match display_name.rfind(':') {
+        Some(i) => &display_name[i + 1..],
+        None => display_name,
+    };
+
+    let mut interior = Vec::new();
+    for field in fields.iter() {
+        let path = format!("{}.{}", struct_name, field.get_name()?);
+        let failure = |message: String| Line(format!(
+            "return ::core::result::Result::Err(::capnp::Error::failed(\"{} {}\".into()));", path, message));
+        let mut checks = Vec::new();
+        match field.which()? {
+            field::Slot(slot) => {
+                for annotation in field.get_annotations()?.iter() {
+                    match annotation.get_id() {
+                        RANGE_ANNOTATION_ID => {
+                            let range = match annotation.get_value()?.which()? {
+                                value::Struct(p) => p.get_as::<crate::dynamic::RawPointer>()?.0.get_struct(None)?,
+                                _ => return Err(Error::failed(format!(
+                                    "expected rust.range annotation value to be of type Range"))),
+                            };
+                            let (min, max) = (range.get_data_field::<f64>(0), range.get_data_field::<f64>(1));
+                            match slot.get_type()?.which()? {
+                                type_::Int8(()) | type_::Int16(()) | type_::Int32(()) | type_::Int64(()) |
+                                type_::Uint8(()) | type_::Uint16(()) | type_::Uint32(()) | type_::Uint64(()) |
+                                type_::Float32(()) | type_::Float64(()) => (),
+                                _ => return Err(Error::failed(format!(
+                                    "$Rust.range on {}, which is not a number", path))),
+                            }
+                            let mut conditions = Vec::new();
+                            if min.is_finite() {
+                                conditions.push(format!("{:?} <= value as f64", min));
+                            }
+                            if max.is_finite() {
+                                conditions.push(format!("value as f64 <= {:?}", max));
+                            }
+                            if !conditions.is_empty() {
+                                checks.push(Line(format!("if !({}) {{", conditions.join(" && "))));
+                                checks.push(Indent(Box::new(
+                                    failure(format!("must be in the range [{}, {}]", min, max)))));
+                                checks.push(Line("}".to_string()));
+                            }
+                        }
+                        NON_EMPTY_ANNOTATION_ID => {
+                            match slot.get_type()?.which()? {
+                                type_::Text(()) | type_::Data(()) | type_::List(_) => (),
+                                _ => return Err(Error::failed(format!(
+                                    "$Rust.nonEmpty on {}, which is not Text, Data or a List", path))),
+                            }
+                            checks.push(Line("if value?.len() == 0 {".to_string()));
+                            checks.push(Indent(Box::new(failure("must not be empty".to_string()))));
+                            checks.push(Line("}".to_string()));
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            field::Group(group) => {
+                if has_constraints(gen, group.get_type_id())? {
+                    checks.push(Line("value.validate()?;".to_string()));
+                }
+            }
+        }
+        if checks.is_empty() {
+            continue;
+        }
+        let head = if field.get_discriminant_value() != field::NO_DISCRIMINANT {
+            format!("if self.reader.get_data_field::<u16>({}) == {} {{",
+                    discriminant_offset, field.get_discriminant_value())
+        } else {
+            "{".to_string()
+        };
+        interior.push(Branch(vec!(
+            Line(head),
+            Indent(Box::new(Branch(vec!(typed_getter(gen, &field)?, Branch(checks))))),
+            Line("}".to_string()))));
+    }
+    interior.push(Line("::core::result::Result::Ok(())".to_string()));
+
+    Ok(Branch(vec!(
+        Line("/// Checks the constraints that the schema's `range` and `nonEmpty` annotations put on".to_string()),
+        Line("/// this struct's fields.".to_string()),
+        Line("pub fn validate(self) -> ::capnp::Result<()> {".to_string()),
+        Indent(Box::new(Branch(interior))),
+        Line("}".to_string()))))
+}
+
+// Typed shortcuts for an untyped AnyPointer field: `get_x_as::<T>()` for readers and
+// builders, plus `init_x_as::<T>()` and `set_x_as(value)` for builders. Getters are left out
+// for union members, whose values are read through `which()`.
+fn generate_any_pointer_accessors(discriminant_offset: u32,
+                                  styled_name: &str,
+                                  field: &schema_capnp::field::Reader,
+                                  is_reader: bool) -> ::capnp::Result<FormattedText> {
+    use crate::schema_capnp::*;
+
+    let slot = match field.which()? {
+        field::Slot(slot) => slot,
+        field::Group(_) => return Ok(Branch(Vec::new())),
+    };
+    let typ = slot.get_type()?;
+    match typ.which()? {
+        type_::AnyPointer(_) if !typ.is_parameter()? => (),
+        _ => return Ok(Branch(Vec::new())),
+    }
+
+    let offset = slot.get_offset();
+    let discriminant_value = field.get_discriminant_value();
+    let is_union_field = discriminant_value != field::NO_DISCRIMINANT;
+    let mut result = Vec::new();
+
+    if is_reader {
+        if !is_union_field {
+            result.push(Line("#[inline]".to_string()));
+            result.push(Line(format!(
+                "pub fn get_{}_as<T: ::capnp::traits::FromPointerReader<'a>>(self) -> ::capnp::Result<T> {{",
+                styled_name)));
+            result.push(Indent(Box::new(Line(format!(
+                "::capnp::any_pointer::Reader::new(self.reader.get_pointer_field({})).get_as()", offset)))));
+            result.push(Line("}".to_string()));
+        }
+        return Ok(Branch(result));
+    }
+
+    if !is_union_field {
+        result.push(Line("#[inline]".to_string()));
+        result.push(Line(format!(
+            "pub fn get_{}_as<T: ::capnp::traits::FromPointerBuilder<'a>>(self) -> ::capnp::Result<T> {{",
+            styled_name)));
+        result.push(Indent(Box::new(Line(format!(
+            "::capnp::any_pointer::Builder::new(self.builder.get_pointer_field({})).get_as()", offset)))));
+        result.push(Line("}".to_string()));
+    }
+
+    let mut initter_interior = Vec::new();
+    let mut setter_interior = Vec::new();
+    if is_union_field {
+        let set_discriminant = Line(format!("self.builder.set_data_field::<u16>({}, {});",
+                                            discriminant_offset as usize,
+                                            discriminant_value as usize));
+        initter_interior.push(set_discriminant.clone());
+        setter_interior.push(set_discriminant);
+    }
+    initter_interior.push(Line(format!(
+        "::capnp::any_pointer::Builder::new(self.builder.get_pointer_field({})).init_as()", offset)));
+    setter_interior.push(Line(format!(
+        "::capnp::traits::SetPointerBuilder::set_pointer_builder(self.builder.get_pointer_field({}), value, false)",
+        offset)));
+
+    result.push(Line("#[inline]".to_string()));
+    result.push(Line(format!(
+        "pub fn init_{}_as<T: ::capnp::traits::FromPointerBuilder<'a>>(self) -> T {{", styled_name)));
+    result.push(Indent(Box::new(Branch(initter_interior))));
+    result.push(Line("}".to_string()));
+
+    result.push(Line("#[inline]".to_string()));
+    result.push(Line(format!(
+        "pub fn set_{}_as<T, From: ::capnp::traits::SetPointerBuilder<T>>(&mut self, value: From) -> ::capnp::Result<()> {{",
+        styled_name)));
+    result.push(Indent(Box::new(Branch(setter_interior))));
+    result.push(Line("}".to_string()));
+
+    Ok(Branch(result))
+}
+
 fn generate_haser(discriminant_offset: u32,
                   styled_name: &str,
                   field: &schema_capnp::field::Reader,
@@ -963,7 +1544,7 @@ fn generate_haser(discriminant_offset: u32,
             match reg_field.get_type()?.which()? {
                 type_::Text(()) | type_::Data(()) |
                 type_::List(_) | type_::Struct(_) |
-                type_::AnyPointer(_) => {
+                type_::AnyPointer(_) | type_::Interface(_) => {
                     interior.push(
                         Line(format!("!self.{}.get_pointer_field({}).is_null()",
                                      member, reg_field.get_offset())));
@@ -975,6 +1556,26 @@ fn generate_haser(discriminant_offset: u32,
                 }
                 _ => {}
             }
+            let is_interface = match reg_field.get_type()?.which()? {
+                type_::Interface(_) => true,
+                _ => false,
+            };
+            if is_interface && !is_reader {
+                // Lets persistence layers drop capabilities that can't be serialized.
+                let mut clear_interior = Vec::new();
+                if discriminant_value != field::NO_DISCRIMINANT {
+                    clear_interior.push(
+                        Line(format!("if self.builder.get_data_field::<u16>({}) != {} {{ return; }}",
+                                     discriminant_offset as usize,
+                                     discriminant_value as usize)));
+                }
+                clear_interior.push(
+                    Line(format!("self.builder.get_pointer_field({}).clear();", reg_field.get_offset())));
+                result.push(Line("#[inline]".to_string()));
+                result.push(Line(format!("pub fn clear_{}(&mut self) {{", styled_name)));
+                result.push(Indent(Box::new(Branch(clear_interior))));
+                result.push(Line("}".to_string()));
+            }
         }
     }
 
@@ -988,12 +1589,11 @@ fn generate_pipeline_getter(gen: &GeneratorContext,
     let name = get_field_name(field)?;
 
     match field.which()? {
-        field::Group(group) => {
-            let the_mod = gen.scope_map[&group.get_type_id()].join("::");
+        field::Group(_) => {
             Ok(Branch(vec!(
-                Line(format!("pub fn get_{}(&self) -> {}::Pipeline {{",
+                Line(format!("pub fn get_{}(&self) -> {} {{",
                              camel_to_snake_case(name),
-                             the_mod)),
+                             field_type_string(gen, field, Leaf::Pipeline)?)),
                 Indent(
                     Box::new(Line("::capnp::capability::FromTypelessPipeline::new(self._typeless.noop())".to_string()))),
                 Line("}".to_string()))))
@@ -1122,48 +1722,548 @@ fn get_ty_params_of_brand_helper(gen: &GeneratorContext,
     Ok(())
 }
 
-fn generate_node(gen: &GeneratorContext,
-                 node_id: u64,
-                 node_name: &str,
-                 // Ugh. We need this to deal with the anonymous Params and Results
-                 // structs that go with RPC methods.
-                 parent_node_id: Option<u64>,
-                 ) -> ::capnp::Result<FormattedText> {
-    use crate::schema_capnp::*;
+/// The `#[cfg(...)]` attribute that the annotation `annotation_id` asks for on `node`, which is
+/// either its own or, for a top-level node, its file's.
+fn cfg_attribute(gen: &GeneratorContext, node: &schema_capnp::node::Reader, annotation_id: u64)
+                 -> ::capnp::Result<Option<FormattedText>>
+{
+    let mut nodes = vec![*node];
+    if let Some(parent) = gen.node_map.get(&node.get_scope_id()) {
+        if let schema_capnp::node::File(()) = parent.which()? {
+            nodes.push(*parent);
+        }
+    }
+    for node in nodes {
+        for annotation in node.get_annotations()?.iter() {
+            if annotation.get_id() == annotation_id {
+                return match annotation.get_value()?.which()? {
+                    schema_capnp::value::Text(t) => Ok(Some(Line(format!("#[cfg({})]", t?)))),
+                    _ => Err(Error::failed(format!("expected rust.cfg annotation value to be of type Text"))),
+                };
+            }
+        }
+    }
+    Ok(None)
+}
 
-    let mut output: Vec<FormattedText> = Vec::new();
-    let mut nested_output: Vec<FormattedText> = Vec::new();
+fn has_annotation(annotations: ::capnp::struct_list::Reader<schema_capnp::annotation::Owned>, id: u64) -> bool {
+    annotations.iter().any(|annotation| annotation.get_id() == id)
+}
 
-    let node_reader = &gen.node_map[&node_id];
-    let nested_nodes = node_reader.get_nested_nodes()?;
-    for nested_node in nested_nodes.iter() {
-        let id = nested_node.get_id();
-        nested_output.push(generate_node(gen, id, gen.get_last_name(id)?, None)?);
+// A float literal of type `typ` (`f32` or `f64`), spelled as a constant if it has no literal.
+fn float_literal(typ: &str, n: f64) -> String {
+    if n.is_nan() {
+        format!("::core::{}::NAN", typ)
+    } else if n == ::std::f64::INFINITY {
+        format!("::core::{}::INFINITY", typ)
+    } else if n == ::std::f64::NEG_INFINITY {
+        format!("::core::{}::NEG_INFINITY", typ)
+    } else {
+        format!("{:?}{}", n, typ)
     }
+}
 
-    match node_reader.which()? {
-        node::File(()) => {
-            output.push(Branch(nested_output));
+// The values that the round-trip test of a field of type `typ` with default `default` sets it
+// to, and the assertion that compares the value read back, as `expression`, with `value`.
+// `None` for the types that get no test.
+fn round_trip_values(gen: &GeneratorContext, typ: schema_capnp::type_::Reader,
+                     default: schema_capnp::value::Reader)
+                     -> ::capnp::Result<Option<(Vec<String>, fn(&str) -> String)>>
+{
+    use crate::schema_capnp::{node, type_, value};
+
+    fn equal(expression: &str) -> String { format!("assert_eq!({}, value);", expression) }
+    fn equal_bits(expression: &str) -> String { format!("assert_eq!({}.to_bits(), value.to_bits());", expression) }
+    fn equal_enum(expression: &str) -> String { format!("assert_eq!({}.unwrap() as u16, value as u16);", expression) }
+    fn equal_pointer(expression: &str) -> String { format!("assert_eq!(&*{}.unwrap(), value);", expression) }
+
+    macro_rules! integer {
+        ($variant:ident, $typ:expr) => {
+            match default.which()? {
+                value::$variant(n) => Some((vec![
+                    format!("{}{}", n, $typ),
+                    format!("::core::{}::MIN", $typ),
+                    format!("::core::{}::MAX", $typ)], equal as fn(&str) -> String)),
+                _ => return Err(Error::failed(format!("default value was of wrong type"))),
+            }
         }
-        node::Struct(struct_reader) => {
-            let params = node_reader.parameters_texts(gen, parent_node_id);
-            output.push(BlankLine);
+    }
+    macro_rules! float {
+        ($variant:ident, $typ:expr) => {
+            match default.which()? {
+                value::$variant(n) => Some((vec![
+                    float_literal($typ, n as f64),
+                    format!("::core::{}::MIN", $typ),
+                    format!("::core::{}::MAX", $typ),
+                    format!("::core::{}::NEG_INFINITY", $typ),
+                    format!("::core::{}::NAN", $typ)], equal_bits as fn(&str) -> String)),
+                _ => return Err(Error::failed(format!("default value was of wrong type"))),
+            }
+        }
+    }
 
-            let is_generic = node_reader.get_is_generic();
-            if is_generic {
-                output.push(Line(format!("pub mod {} {{ /* {} */", node_name, params.expanded_list.join(","))));
-            } else {
-                output.push(Line(format!("pub mod {} {{", node_name)));
+    Ok(match typ.which()? {
+        type_::Bool(()) => match default.which()? {
+            value::Bool(b) => Some((vec![b.to_string(), (!b).to_string()], equal as fn(&str) -> String)),
+            _ => return Err(Error::failed(format!("default value was of wrong type"))),
+        },
+        type_::Int8(()) => integer!(Int8, "i8"),
+        type_::Int16(()) => integer!(Int16, "i16"),
+        type_::Int32(()) => integer!(Int32, "i32"),
+        type_::Int64(()) => integer!(Int64, "i64"),
+        type_::Uint8(()) => integer!(Uint8, "u8"),
+        type_::Uint16(()) => integer!(Uint16, "u16"),
+        type_::Uint32(()) => integer!(Uint32, "u32"),
+        type_::Uint64(()) => integer!(Uint64, "u64"),
+        type_::Float32(()) => float!(Float32, "f32"),
+        type_::Float64(()) => float!(Float64, "f64"),
+        type_::Enum(e) => {
+            let enumerants = match gen.node_map[&e.get_type_id()].which()? {
+                node::Enum(e) => e.get_enumerants()?,
+                _ => return Err(Error::failed(format!("expected an enum"))),
+            };
+            if enumerants.len() == 0 {
+                // No value can be set.
+                return Ok(None);
             }
-            let bracketed_params = if params.params == "" { "".to_string() } else { format!("<{}>", params.params) };
+            let default = match default.which()? {
+                value::Enum(n) => n as u32,
+                _ => return Err(Error::failed(format!("default value was of wrong type"))),
+            };
+            let path = gen.scope_map[&e.get_type_id()].to_string();
+            let mut values = Vec::new();
+            for index in &[default, 0, enumerants.len() - 1] {
+                values.push(format!("{}::{}", path,
+                                    capitalize_first_letter(get_enumerant_name(enumerants.get(*index))?)));
+            }
+            Some((values, equal_enum as fn(&str) -> String))
+        }
+        type_::Text(()) => {
+            let default = match default.which()? {
+                value::Text(t) => t?,
+                _ => return Err(Error::failed(format!("default value was of wrong type"))),
+            };
+            Some((vec![format!("{:?}", default), "\"\"".to_string(), "\"\\u{1f980} \\0 end\"".to_string()],
+                  equal_pointer as fn(&str) -> String))
+        }
+        type_::Data(()) => {
+            let default = match default.which()? {
+                value::Data(d) => d?,
+                _ => return Err(Error::failed(format!("default value was of wrong type"))),
+            };
+            Some((vec![format!("&{:?}[..]", default), "&[][..]".to_string(), "&[0, 255][..]".to_string()],
+                  equal_pointer as fn(&str) -> String))
+        }
+        _ => None,
+    })
+}
 
-            let mut preamble = Vec::new();
-            let mut builder_members = Vec::new();
-            let mut reader_members = Vec::new();
-            let mut union_fields = Vec::new();
-            let mut which_enums = Vec::new();
-            let mut pipeline_impl_interior = Vec::new();
-            let mut private_mod_interior = Vec::new();
+// Adds to `tests` the round-trip tests for the fields of the struct or group `node_id`, whose
+// module is `module` as seen from the tests, and which is reached from the struct that the
+// tests are for through `builder_steps` and `reader_steps`. `prefix` is prepended to the test
+// names, and `in_union` says whether a union member lies on the way.
+fn collect_round_trip_tests(gen: &GeneratorContext, node_id: u64, module: &str, prefix: &str,
+                            builder_steps: &[String], reader_steps: &[String], in_union: bool,
+                            tests: &mut Vec<FormattedText>) -> ::capnp::Result<()>
+{
+    use crate::schema_capnp::{field, node, type_};
+
+    let fields = match gen.node_map[&node_id].which()? {
+        node::Struct(st) => st.get_fields()?,
+        _ => return Err(Error::failed(format!("expected a struct"))),
+    };
+    for field in fields.iter() {
+        let styled_name = camel_to_snake_case(get_field_name(field)?);
+        let variant = capitalize_first_letter(get_field_name(field)?);
+        let is_union_field = field.get_discriminant_value() != field::NO_DISCRIMINANT;
+        let mut name = format!("{}{}", prefix, styled_name);
+        if RUST_KEYWORDS.contains(&&*name) {
+            name.push('_');
+        }
+
+        let slot = match field.which()? {
+            field::Group(group) => {
+                let mut builder_steps = builder_steps.to_vec();
+                let mut reader_steps = reader_steps.to_vec();
+                if is_union_field {
+                    builder_steps.push(format!("b.init_{}()", styled_name));
+                    reader_steps.push(format!(
+                        "match r.which().unwrap() {{ {}::Which::{}(r) => r, _ => panic!(\"{} is not set\") }}",
+                        module, variant, field.get_name()?));
+                } else {
+                    builder_steps.push(format!("b.get_{}()", styled_name));
+                    reader_steps.push(format!("r.get_{}()", styled_name));
+                }
+                let group_module = format!("{}::{}", module, gen.get_last_name(group.get_type_id())?);
+                collect_round_trip_tests(gen, group.get_type_id(), &group_module, &format!("{}_", name),
+                                         &builder_steps, &reader_steps, in_union || is_union_field, tests)?;
+                continue;
+            }
+            field::Slot(slot) => slot,
+        };
+        let typ = slot.get_type()?;
+
+        let schema_name = field.get_name()?;
+        let mut body = Vec::new();
+        let read = |body: &mut Vec<FormattedText>, check: fn(&str) -> String| {
+            for step in reader_steps {
+                body.push(Line(format!("let r = {};", step)));
+            }
+            if is_union_field {
+                body.push(Line("match r.which().unwrap() {".to_string()));
+                body.push(Indent(Box::new(Line(format!("{}::Which::{}(v) => {{ {} }}", module, variant, check("v"))))));
+                body.push(Indent(Box::new(Line(format!("_ => panic!(\"{} is not set\"),", schema_name)))));
+                body.push(Line("}".to_string()));
+            } else {
+                body.push(Line(check(&format!("r.get_{}()", styled_name))));
+            }
+        };
+        let (values, check) = match typ.which()? {
+            type_::Void(()) if is_union_field => (vec!["()".to_string()], (|expression: &str| format!("let () = {};", expression)) as fn(&str) -> String),
+            _ => match round_trip_values(gen, typ, slot.get_default_value()?)? {
+                Some(values) => values,
+                None => continue,
+            },
+        };
+
+        if !in_union && !is_union_field {
+            body.push(Line("let message = ::capnp::message::Builder::new_default();".to_string()));
+            body.push(Line("let r = message.get_root_as_reader::<super::Reader>().unwrap();".to_string()));
+            body.push(Line(format!("let value = {};", values[0])));
+            read(&mut body, check);
+        }
+
+        let mut build = Vec::new();
+        let mut bindings = vec!["message.init_root::<super::Builder>()".to_string()];
+        bindings.extend(builder_steps.iter().cloned());
+        for (index, binding) in bindings.iter().enumerate() {
+            let keyword = if index + 1 == bindings.len() { "let mut" } else { "let" };
+            build.push(Line(format!("{} b = {};", keyword, binding)));
+        }
+        build.push(Line(format!("b.set_{}(value);", styled_name)));
+        if !is_union_field {
+            build.push(Line(check(&format!("b.reborrow().get_{}()", styled_name))));
+        }
+
+        let mut round_trip = vec![
+            Line("let mut message = ::capnp::message::Builder::new_default();".to_string()),
+            Line("{".to_string()),
+            Indent(Box::new(Branch(build))),
+            Line("}".to_string()),
+            Line("let r = message.get_root_as_reader::<super::Reader>().unwrap();".to_string()),
+        ];
+        read(&mut round_trip, check);
+        body.push(Line(format!("for &value in [{}].iter() {{", values.join(", "))));
+        body.push(Indent(Box::new(Branch(round_trip))));
+        body.push(Line("}".to_string()));
+
+        tests.push(Branch(vec!(
+            Line("#[test]".to_string()),
+            Line(format!("fn {}() {{", name)),
+            Indent(Box::new(Branch(body))),
+            Line("}".to_string()))));
+    }
+    Ok(())
+}
+
+// Generates the module of round-trip tests for the struct `node_id`, if the `inline_tests`
+// option is set and the struct is neither a group nor generic.
+fn generate_round_trip_tests(gen: &GeneratorContext, node_id: u64) -> ::capnp::Result<FormattedText> {
+    let node = gen.node_map[&node_id];
+    let is_group = match node.which()? {
+        schema_capnp::node::Struct(st) => st.get_is_group(),
+        _ => true,
+    };
+    if !gen.options.inline_tests || is_group || node.get_is_generic() {
+        return Ok(Branch(Vec::new()));
+    }
+    let mut tests = Vec::new();
+    collect_round_trip_tests(gen, node_id, "super", "", &[], &[], false, &mut tests)?;
+    if tests.is_empty() {
+        return Ok(Branch(Vec::new()));
+    }
+    Ok(Branch(vec!(
+        Line("#[cfg(test)]".to_string()),
+        Line("mod round_trip_tests {".to_string()),
+        Indent(Box::new(Branch(tests))),
+        Line("}".to_string()))))
+}
+
+// Whether the struct or group `node_id` gets a `Fluent` wrapper: it must not be generic, and it,
+// its file, or for a group the struct that contains it, must have the `fluent` annotation.
+fn is_fluent(gen: &GeneratorContext, node_id: u64) -> ::capnp::Result<bool> {
+    use crate::schema_capnp::node;
+
+    let node = match gen.node_map.get(&node_id) {
+        Some(node) => *node,
+        None => return Ok(false),
+    };
+    match node.which()? {
+        node::Struct(st) if !node.get_is_generic() => {
+            if st.get_is_group() {
+                return is_fluent(gen, node.get_scope_id());
+            }
+        }
+        _ => return Ok(false),
+    }
+    if has_annotation(node.get_annotations()?, FLUENT_ANNOTATION_ID) {
+        return Ok(true);
+    }
+    let mut scope_id = node.get_scope_id();
+    while let Some(scope) = gen.node_map.get(&scope_id) {
+        if let node::File(()) = scope.which()? {
+            return Ok(has_annotation(scope.get_annotations()?, FLUENT_ANNOTATION_ID));
+        }
+        scope_id = scope.get_scope_id();
+    }
+    Ok(false)
+}
+
+// The chained setter of `Fluent` for `field`, if it has one.
+fn generate_fluent_setter(gen: &GeneratorContext, field: schema_capnp::field::Reader)
+                          -> ::capnp::Result<Option<FormattedText>> {
+    use crate::schema_capnp::{field, type_};
+
+    let styled_name = camel_to_snake_case(get_field_name(field)?);
+    let method_name = module_name(get_field_name(field)?);
+    if method_name == "new" || method_name == "into_builder" || method_name == "with_builder" {
+        return Ok(None);
+    }
+    let nested = |id: u64| -> ::capnp::Result<Option<FormattedText>> {
+        if !is_fluent(gen, id)? {
+            return Ok(None);
+        }
+        let fluent = format!("{}::Fluent", gen.scope_map[&id]);
+        Ok(Some(Branch(vec![
+            Line(format!("pub fn {0}<F: FnOnce({1}<'_>) -> {1}<'_>>(mut self, f: F) -> Fluent<'a> {{",
+                         method_name, fluent)),
+            Indent(Box::new(Line(format!("f({}::new(self.builder.reborrow().init_{}()));", fluent, styled_name)))),
+            Indent(Box::new(Line("self".to_string()))),
+            Line("}".to_string())])))
+    };
+    let typ = match field.which()? {
+        field::Group(group) => return nested(group.get_type_id()),
+        field::Slot(slot) => slot.get_type()?,
+    };
+    let (param, body) = match typ.which()? {
+        type_::Void(()) => (String::new(), Line(format!("self.builder.set_{}(());", styled_name))),
+        type_::Struct(st) => return nested(st.get_type_id()),
+        type_::List(list) => {
+            let element_type = list.get_element_type()?;
+            match element_type.which()? {
+                type_::Void(()) | type_::Struct(_) | type_::List(_) |
+                type_::Interface(_) | type_::AnyPointer(_) => return Ok(None),
+                _ => (),
+            }
+            (format!("values: &[{}]", element_type.type_string(gen, Leaf::Reader("'_"))?),
+             Branch(vec![
+                 Line(format!("let mut list = self.builder.reborrow().init_{}(values.len() as u32);", styled_name)),
+                 Line("for (index, value) in values.iter().enumerate() {".to_string()),
+                 Indent(Box::new(Line("list.set(index as u32, *value);".to_string()))),
+                 Line("}".to_string())]))
+        }
+        type_::Interface(_) | type_::AnyPointer(_) => return Ok(None),
+        _ => (format!("value: {}", typ.type_string(gen, Leaf::Reader("'_"))?),
+              Line(format!("self.builder.set_{}(value);", styled_name))),
+    };
+    let param = if param.is_empty() { param } else { format!(", {}", param) };
+    Ok(Some(Branch(vec![
+        Line("#[inline]".to_string()),
+        Line(format!("pub fn {}(mut self{}) -> Fluent<'a> {{", method_name, param)),
+        Indent(Box::new(Branch(vec![body, Line("self".to_string())]))),
+        Line("}".to_string())])))
+}
+
+// The `Fluent` wrapper of a struct or group that `is_fluent()`, and for a struct, `build()`.
+fn generate_fluent(gen: &GeneratorContext, node_id: u64) -> ::capnp::Result<FormattedText> {
+    use crate::schema_capnp::node;
+
+    if !is_fluent(gen, node_id)? {
+        return Ok(Branch(Vec::new()));
+    }
+    let st = match gen.node_map[&node_id].which()? {
+        node::Struct(st) => st,
+        _ => return Ok(Branch(Vec::new())),
+    };
+    let mut methods = vec![
+        Line("pub fn new(builder: Builder<'a>) -> Fluent<'a> { Fluent { builder } }".to_string()),
+        Line("pub fn into_builder(self) -> Builder<'a> { self.builder }".to_string()),
+        Line("/// Lets `f` set whatever the chained setters cannot.".to_string()),
+        Line("pub fn with_builder<F: FnOnce(Builder<'_>)>(mut self, f: F) -> Fluent<'a> {".to_string()),
+        Indent(Box::new(Line("f(self.builder.reborrow());".to_string()))),
+        Indent(Box::new(Line("self".to_string()))),
+        Line("}".to_string()),
+    ];
+    for field in st.get_fields()?.iter() {
+        methods.extend(generate_fluent_setter(gen, field)?);
+    }
+    let mut result = vec![
+        BlankLine,
+        Line("/// Sets the fields of a `Builder` by chained calls.".to_string()),
+        Line("pub struct Fluent<'a> { builder: Builder<'a> }".to_string()),
+        Line("impl <'a> Fluent<'a> {".to_string()),
+        Indent(Box::new(Branch(methods))),
+        Line("}".to_string()),
+    ];
+    if !st.get_is_group() {
+        result.push(Branch(vec![
+            Line("/// Builds a message whose root is a struct of this type, set up by `f`.".to_string()),
+            Line("pub fn build<F>(f: F) -> ::capnp::message::Builder<::capnp::message::HeapAllocator>".to_string()),
+            Indent(Box::new(Line("where F: FnOnce(Fluent<'_>) -> Fluent<'_>".to_string()))),
+            Line("{".to_string()),
+            Indent(Box::new(Branch(vec![
+                Line("let mut message = ::capnp::message::Builder::new_default();".to_string()),
+                Line("f(Fluent::new(message.init_root()));".to_string()),
+                Line("message".to_string())]))),
+            Line("}".to_string())]));
+    }
+    Ok(Branch(result))
+}
+
+/// The names and types of the fields of a parameter struct, if they can all be passed as plain
+/// values: that is, if each is a primitive or Text, with `text_type` as the type of Text. Void
+/// fields are left out.
+fn plain_params(gen: &GeneratorContext, param_node: schema_capnp::node::Reader, text_type: &str)
+                -> ::capnp::Result<Option<Vec<(String, String)>>> {
+    use crate::schema_capnp::{field, node, type_};
+
+    let st = match param_node.which()? {
+        node::Struct(st) => st,
+        _ => return Ok(None),
+    };
+    if st.get_discriminant_count() > 0 {
+        return Ok(None)
+    }
+
+    let mut result = Vec::new();
+    for field in st.get_fields()?.iter() {
+        let typ = match field.which()? {
+            field::Slot(slot) => slot.get_type()?,
+            field::Group(_) => return Ok(None),
+        };
+        let ty = match typ.which()? {
+            type_::Void(()) => continue,
+            type_::Text(()) => text_type.to_string(),
+            _ if typ.is_prim()? => typ.type_string(gen, Leaf::Owned)?,
+            _ => return Ok(None),
+        };
+        result.push((get_field_name(field)?.to_string(), ty));
+    }
+    Ok(Some(result))
+}
+
+/// Generates a `Client` method that takes the parameters of an interface method as arguments and
+/// sends the request, for methods marked with the `inlineParams` annotation. Returns None if some
+/// parameter is not a primitive or Text, since those cannot be passed as plain values.
+fn generate_inline_params_method(gen: &GeneratorContext, method_name: &str,
+                                 param_node: schema_capnp::node::Reader,
+                                 result_type: &str) -> ::capnp::Result<Option<FormattedText>> {
+    let fields = match plain_params(gen, param_node, "&str")? {
+        Some(fields) => fields,
+        None => return Ok(None),
+    };
+
+    let mut args = Vec::new();
+    let mut setters = Vec::new();
+    for (name, ty) in fields {
+        let arg = module_name(&name);
+        args.push(format!("{}: {}", arg, ty));
+        setters.push(Line(format!("params.set_{}({});", camel_to_snake_case(&name), arg)));
+    }
+
+    let snake_name = camel_to_snake_case(method_name);
+    let body = if setters.is_empty() {
+        Line(format!("self.{}_request().send()", snake_name))
+    } else {
+        Branch(vec![
+            Line(format!("let mut request = self.{}_request();", snake_name)),
+            Line("{".to_string()),
+            Indent(Box::new(Branch(vec![
+                Line("let mut params = request.get();".to_string()),
+                Branch(setters)]))),
+            Line("}".to_string()),
+            Line("request.send()".to_string())])
+    };
+    Ok(Some(Branch(vec![
+        Line(format!("pub fn {}(&self{}) -> ::capnp::capability::RemotePromise<{}> {{",
+                     snake_name, args.iter().map(|arg| format!(", {}", arg)).collect::<String>(),
+                     result_type)),
+        Indent(Box::new(body)),
+        Line("}".to_string())])))
+}
+
+/// Generates an impl of `SetFromTuple` for the builder of an implicit parameter struct whose
+/// fields can all be passed as plain values. Returns None otherwise, or if there are no such
+/// fields.
+fn generate_set_from_tuple(gen: &GeneratorContext, param_node: schema_capnp::node::Reader,
+                           param_scope: &ScopePath) -> ::capnp::Result<Option<FormattedText>> {
+    let fields = match plain_params(gen, param_node, "&'b str")? {
+        Some(ref fields) if !fields.is_empty() => fields.clone(),
+        _ => return Ok(None),
+    };
+
+    let types: Vec<&str> = fields.iter().map(|f| &f.1[..]).collect();
+    let tuple_type = if types.len() == 1 { format!("({},)", types[0]) } else { format!("({})", types.join(", ")) };
+    let lifetimes = if types.contains(&"&'b str") { "'a,'b" } else { "'a" };
+    let setters = fields.iter().enumerate().map(|(index, &(ref name, _))| {
+        Line(format!("self.set_{}(values.{});", camel_to_snake_case(name), index))
+    }).collect();
+    Ok(Some(Branch(vec![
+        Line(format!("impl <{}> ::capnp::traits::SetFromTuple<{}> for {}::Builder<'a> {{",
+                     lifetimes, tuple_type, param_scope)),
+        Indent(Box::new(Branch(vec![
+            Line(format!("fn set_from_tuple(&mut self, values: {}) {{", tuple_type)),
+            Indent(Box::new(Branch(setters))),
+            Line("}".to_string())]))),
+        Line("}".to_string())])))
+}
+
+fn generate_node(gen: &GeneratorContext,
+                 node_id: u64,
+                 node_name: &str,
+                 // Ugh. We need this to deal with the anonymous Params and Results
+                 // structs that go with RPC methods.
+                 parent_node_id: Option<u64>,
+                 ) -> ::capnp::Result<FormattedText> {
+    use crate::schema_capnp::*;
+
+    let mut output: Vec<FormattedText> = Vec::new();
+    let mut nested_output: Vec<FormattedText> = Vec::new();
+
+    let node_reader = &gen.node_map[&node_id];
+    let cfg = Branch(cfg_attribute(gen, node_reader, CFG_ANNOTATION_ID)?.into_iter().collect());
+    let nested_nodes = node_reader.get_nested_nodes()?;
+    for nested_node in nested_nodes.iter() {
+        let id = nested_node.get_id();
+        nested_output.push(generate_node(gen, id, gen.get_last_name(id)?, None)?);
+    }
+
+    match node_reader.which()? {
+        node::File(()) => {
+            output.push(Branch(nested_output));
+            output.push(generate_consts_module(gen, node_id)?);
+            output.push(generate_register_schemas(gen, node_id)?);
+        }
+        node::Struct(struct_reader) => {
+            let params = node_reader.parameters_texts(gen, parent_node_id);
+            output.push(BlankLine);
+
+            let is_generic = node_reader.get_is_generic();
+            output.push(cfg.clone());
+            if is_generic {
+                output.push(Line(format!("pub mod {} {{ /* {} */", node_name, params.expanded_list.join(","))));
+            } else {
+                output.push(Line(format!("pub mod {} {{", node_name)));
+            }
+            let bracketed_params = if params.params == "" { "".to_string() } else { format!("<{}>", params.params) };
+
+            let mut preamble = Vec::new();
+            let mut builder_members = Vec::new();
+            let mut reader_members = Vec::new();
+            let mut union_fields = Vec::new();
+            let mut which_enums = Vec::new();
+            let mut pipeline_impl_interior = Vec::new();
+            let mut private_mod_interior = Vec::new();
 
             let data_size = struct_reader.get_data_word_count();
             let pointer_size = struct_reader.get_pointer_count();
@@ -1178,6 +2278,15 @@ fn generate_node(gen: &GeneratorContext,
                 let discriminant_value = field.get_discriminant_value();
                 let is_union_field = discriminant_value != field::NO_DISCRIMINANT;
 
+                // A group inside a union still has its pointer fields at fixed offsets, so we
+                // can pipeline through it. The caller is responsible for knowing which variant
+                // the promised struct will hold, just as for any other pipelined pointer.
+                if is_union_field {
+                    if let field::Group(_) = field.which()? {
+                        pipeline_impl_interior.push(generate_pipeline_getter(gen, field)?);
+                    }
+                }
+
                 if !is_union_field {
                     pipeline_impl_interior.push(generate_pipeline_getter(gen, field)?);
                     let (ty, get, default_decl) = getter_text(gen, &field, true, true)?;
@@ -1190,6 +2299,12 @@ fn generate_node(gen: &GeneratorContext,
                             Line(format!("pub fn get_{}(self) {} {{", styled_name, ty)),
                             Indent(Box::new(get)),
                             Line("}".to_string()))));
+                    if let Some(bytes_getter) = generate_text_bytes_getter(&styled_name, &field)? {
+                        reader_members.push(bytes_getter);
+                    }
+                    if let Some(or_default_getter) = generate_or_default_getter(gen, &styled_name, &field)? {
+                        reader_members.push(or_default_getter);
+                    }
 
                     let (ty_b, get_b, _) = getter_text(gen, &field, false, true)?;
                     builder_members.push(
@@ -1208,6 +2323,8 @@ fn generate_node(gen: &GeneratorContext,
 
                 reader_members.push(generate_haser(discriminant_offset, &styled_name, &field, true)?);
                 builder_members.push(generate_haser(discriminant_offset, &styled_name, &field, false)?);
+                reader_members.push(generate_any_pointer_accessors(discriminant_offset, &styled_name, &field, true)?);
+                builder_members.push(generate_any_pointer_accessors(discriminant_offset, &styled_name, &field, false)?);
 
                 match field.which() {
                     Ok(field::Group(group)) => {
@@ -1266,6 +2383,30 @@ fn generate_node(gen: &GeneratorContext,
             private_mod_interior.push(
                 Line(
                     format!("pub const TYPE_ID: u64 = {};", format_u64(node_id))));
+            let schema_getter = if gen.options.embed_schemas {
+                private_mod_interior.push(crate::pointer_constants::generate_schema_constant(*node_reader)?);
+                Branch(vec![
+                    BlankLine,
+                    Line("/// The schema node of this struct.".to_string()),
+                    Line("pub fn schema() -> ::capnp::Result<::capnp::any_pointer::Reader<'static>> { _private::SCHEMA.get() }".to_string()),
+                ])
+            } else {
+                Branch(Vec::new())
+            };
+            let (field_table, field_by_ordinal_getter) =
+                generate_field_table(gen, discriminant_offset, fields)?;
+            private_mod_interior.push(field_table);
+            reader_members.push(field_by_ordinal_getter);
+            reader_members.push(generate_validate(gen, node_id, discriminant_offset, fields)?);
+            private_mod_interior.push(Branch(vec!(
+                Line("#[cfg(test)]".to_string()),
+                Line("#[test]".to_string()),
+                Line("fn check_layout() {".to_string()),
+                Indent(Box::new(Line(format!(
+                    "::capnp::fields::check_layout(STRUCT_SIZE, {}, &FIELDS).unwrap();",
+                    if discriminant_count > 0 { format!("Some({})", discriminant_offset) }
+                    else { "None".to_string() })))),
+                Line("}".to_string()))));
 
 
             let from_pointer_builder_impl =
@@ -1328,6 +2469,11 @@ fn generate_node(gen: &GeneratorContext,
                         Indent(Box::new(Branch(vec!(Line("#[inline]".to_string()),
                                                Line("fn type_id() -> u64 { _private::TYPE_ID }".to_string()))))),
                     Line("}".to_string()))),
+                Branch(vec!(
+                        Line(format!("impl <'a,{0}> ::capnp::traits::HasFields for Reader<'a,{0}> {1} {{",
+                            params.params, params.where_clause)),
+                        Indent(Box::new(Line("fn fields() -> &'static [::capnp::fields::FieldInfo] { &_private::FIELDS }".to_string()))),
+                    Line("}".to_string()))),
                 Line(format!("impl <'a,{0}> ::capnp::traits::FromStructReader<'a> for Reader<'a,{0}> {1} {{",
                             params.params, params.where_clause)),
                 Indent(
@@ -1355,6 +2501,17 @@ fn generate_node(gen: &GeneratorContext,
                         Line("}".to_string()))))),
                 Line("}".to_string()),
                 BlankLine,
+                Line(format!("impl <'a,{0}> ::capnp::traits::Reflect<'a> for Reader<'a,{0}> {1} {{",
+                            params.params, params.where_clause)),
+                Indent(
+                    Box::new(Branch(vec!(
+                        Line("type Internal = ::capnp::private::layout::StructReader<'a>;".to_string()),
+                        Line("fn into_internal(self) -> ::capnp::private::layout::StructReader<'a> { self.reader }".to_string()),
+                        Line(format!("fn from_internal(reader: ::capnp::private::layout::StructReader<'a>) -> Reader<'a,{}> {{", params.params)),
+                        Indent(Box::new(Line(format!("Reader {{ reader, {} }}", params.phantom_data_value)))),
+                        Line("}".to_string()))))),
+                Line("}".to_string()),
+                BlankLine,
                 Line(format!("impl <'a,{0}> ::capnp::traits::Imbue<'a> for Reader<'a,{0}> {1} {{",
                     params.params, params.where_clause)),
                 Indent(
@@ -1367,7 +2524,7 @@ fn generate_node(gen: &GeneratorContext,
                 Line(format!("impl <'a,{0}> Reader<'a,{0}> {1} {{", params.params, params.where_clause)),
                 Indent(
                     Box::new(Branch(vec![
-                        Line(format!("pub fn reborrow(&self) -> Reader<{}> {{",params.params)),
+                        Line(format!("pub fn reborrow(&self) -> Reader<'_,{}> {{",params.params)),
                         Indent(Box::new(Line("Reader { .. *self }".to_string()))),
                         Line("}".to_string()),
                         BlankLine,
@@ -1377,6 +2534,8 @@ fn generate_node(gen: &GeneratorContext,
                 Indent(Box::new(Branch(reader_members))),
                 Line("}".to_string()),
                 BlankLine,
+                generate_stringify(gen, discriminant_offset, fields, &params)?,
+                BlankLine,
                 (if !is_generic {
                     Line("pub struct Builder<'a> { builder: ::capnp::private::layout::StructBuilder<'a> }".to_string())
                 } else {
@@ -1398,6 +2557,11 @@ fn generate_node(gen: &GeneratorContext,
                         Line("#[inline]".to_string()),
                         Line("fn type_id() -> u64 { _private::TYPE_ID }".to_string()))))),
                     Line("}".to_string()))),
+                Branch(vec!(
+                    Line(format!("impl <'a,{0}> ::capnp::traits::HasFields for Builder<'a,{0}> {1} {{",
+                                 params.params, params.where_clause)),
+                    Indent(Box::new(Line("fn fields() -> &'static [::capnp::fields::FieldInfo] { &_private::FIELDS }".to_string()))),
+                    Line("}".to_string()))),
                 Line(format!(
                     "impl <'a,{0}> ::capnp::traits::FromStructBuilder<'a> for Builder<'a,{0}> {1} {{",
                     params.params, params.where_clause)),
@@ -1408,6 +2572,17 @@ fn generate_node(gen: &GeneratorContext,
                         Line("}".to_string()))))),
                 Line("}".to_string()),
                 BlankLine,
+                Line(format!("impl <'a,{0}> ::capnp::traits::Reflect<'a> for Builder<'a,{0}> {1} {{",
+                             params.params, params.where_clause)),
+                Indent(
+                    Box::new(Branch(vec!(
+                        Line("type Internal = ::capnp::private::layout::StructBuilder<'a>;".to_string()),
+                        Line("fn into_internal(self) -> ::capnp::private::layout::StructBuilder<'a> { self.builder }".to_string()),
+                        Line(format!("fn from_internal(builder: ::capnp::private::layout::StructBuilder<'a>) -> Builder<'a,{}> {{", params.params)),
+                        Indent(Box::new(Line(format!("Builder {{ builder, {} }}", params.phantom_data_value)))),
+                        Line("}".to_string()))))),
+                Line("}".to_string()),
+                BlankLine,
                 Line(format!("impl <'a,{0}> ::capnp::traits::ImbueMut<'a> for Builder<'a,{0}> {1} {{",
                              params.params, params.where_clause)),
                 Indent(
@@ -1431,10 +2606,10 @@ fn generate_node(gen: &GeneratorContext,
                         Line(format!("pub fn into_reader(self) -> Reader<'a,{}> {{", params.params)),
                         Indent(Box::new(Line("::capnp::traits::FromStructReader::new(self.builder.into_reader())".to_string()))),
                         Line("}".to_string()),
-                        Line(format!("pub fn reborrow(&mut self) -> Builder<{}> {{", params.params)),
+                        Line(format!("pub fn reborrow(&mut self) -> Builder<'_,{}> {{", params.params)),
                         Indent(Box::new(Line("Builder { .. *self }".to_string()))),
                         Line("}".to_string()),
-                        Line(format!("pub fn reborrow_as_reader(&self) -> Reader<{}> {{", params.params)),
+                        Line(format!("pub fn reborrow_as_reader(&self) -> Reader<'_,{}> {{", params.params)),
                         Indent(Box::new(Line("::capnp::traits::FromStructReader::new(self.builder.into_reader())".to_string()))),
                         Line("}".to_string()),
 
@@ -1475,7 +2650,11 @@ fn generate_node(gen: &GeneratorContext,
             ];
 
             output.push(Indent(Box::new(Branch(vec!(Branch(accessors),
+                                                    schema_getter,
                                                     Branch(which_enums),
+                                                    generate_fluent(gen, node_id)?,
+                                                    generate_field_layouts(fields)?,
+                                                    generate_round_trip_tests(gen, node_id)?,
                                                     Branch(nested_output))))));
             output.push(Line("}".to_string()));
         }
@@ -1486,16 +2665,20 @@ fn generate_node(gen: &GeneratorContext,
 
             let mut members = Vec::new();
             let mut match_branches = Vec::new();
+            let mut name_branches = Vec::new();
             let enumerants = enum_reader.get_enumerants()?;
             for ii in 0..enumerants.len() {
                 let enumerant = capitalize_first_letter(get_enumerant_name(enumerants.get(ii))?);
                 members.push(Line(format!("{} = {},", enumerant, ii)));
                 match_branches.push(
                     Line(format!("{} => ::core::result::Result::Ok({}::{}),", ii, last_name, enumerant)));
+                name_branches.push(
+                    Line(format!("{}::{} => \"{}\",", last_name, enumerant, enumerants.get(ii).get_name()?)));
             }
             match_branches.push(Line("n => ::core::result::Result::Err(::capnp::NotInSchema(n)),".to_string()));
 
             output.push(Branch(vec!(
+                cfg.clone(),
                 Line("#[repr(u16)]".to_string()),
                 Line("#[derive(Clone, Copy, PartialEq)]".to_string()),
                 Line(format!("pub enum {} {{", last_name)),
@@ -1504,6 +2687,7 @@ fn generate_node(gen: &GeneratorContext,
 
             output.push(
                 Branch(vec!(
+                    cfg.clone(),
                     Line(format!("impl ::capnp::traits::FromU16 for {} {{", last_name)),
                     Indent(Box::new(Line("#[inline]".to_string()))),
                     Indent(
@@ -1519,6 +2703,7 @@ fn generate_node(gen: &GeneratorContext,
                                         ]))),
                             Line("}".to_string())]))),
                     Line("}".to_string()),
+                    cfg.clone(),
                     Line(format!("impl ::capnp::traits::ToU16 for {} {{", last_name)),
                     Indent(Box::new(Line("#[inline]".to_string()))),
                     Indent(
@@ -1527,11 +2712,29 @@ fn generate_node(gen: &GeneratorContext,
 
             output.push(
                 Branch(vec!(
+                    cfg.clone(),
                     Line(format!("impl ::capnp::traits::HasTypeId for {} {{", last_name)),
                     Indent(Box::new(Line("#[inline]".to_string()))),
                     Indent(
                         Box::new(Line(format!("fn type_id() -> u64 {{ {}u64 }}", format_u64(node_id)).to_string()))),
                     Line("}".to_string()))));
+
+            output.push(
+                Branch(vec!(
+                    cfg.clone(),
+                    Line(format!("impl ::capnp::stringify::Stringify for {} {{", last_name)),
+                    Indent(
+                        Box::new(Branch(vec![
+                            Line("#[allow(unused_parens)]".to_string()),
+                            Line("fn stringify(&self, w: &mut dyn (::core::fmt::Write)) -> ::core::fmt::Result {".to_string()),
+                            Indent(
+                                Box::new(Branch(vec![
+                                    Line("w.write_str(match *self {".to_string()),
+                                    Indent(Box::new(Branch(name_branches))),
+                                    Line("})".to_string())
+                                        ]))),
+                            Line("}".to_string())]))),
+                    Line("}".to_string()))));
         }
 
         node::Interface(interface) => {
@@ -1551,9 +2754,11 @@ fn generate_node(gen: &GeneratorContext,
 
             private_mod_interior.push(Line(format!("pub const TYPE_ID: u64 = {};", format_u64(node_id))));
 
-            mod_interior.push(Line ("#![allow(unused_variables)]".to_string()));
-
+            let interface_annotations = node_reader.get_annotations()?;
             let methods = interface.get_methods()?;
+            let mut idempotent_methods = Vec::new();
+            let mut cacheable_methods = Vec::new();
+            let mut method_infos = Vec::new();
             for ordinal in 0..methods.len() {
                 let method = methods.get(ordinal);
                 let name = method.get_name()?;
@@ -1562,32 +2767,32 @@ fn generate_node(gen: &GeneratorContext,
                 let param_id = method.get_param_struct_type();
                 let param_node = &gen.node_map[&param_id];
                 let (param_scopes, params_ty_params) = if param_node.get_scope_id() == 0 {
-                    let mut names = names.clone();
                     let local_name = module_name(&format!("{}Params", name));
                     nested_output.push(generate_node(gen, param_id, &*local_name, Some(node_id))?);
-                    names.push(local_name);
-                    (names, params.params.clone())
+                    let param_scope = names.child(local_name, ItemKind::Module);
+                    if !is_generic {
+                        nested_output.extend(generate_set_from_tuple(gen, *param_node, &param_scope)?);
+                    }
+                    (param_scope, params.params.clone())
                 } else {
                     (gen.scope_map[&param_node.get_id()].clone(),
                      get_ty_params_of_brand(gen, method.get_param_brand()?)?)
                 };
                 let param_type = do_branding(&gen, param_id, method.get_param_brand()?,
-                                             Leaf::Owned, param_scopes.join("::"), Some(node_id))?;
+                                             Leaf::Owned, param_scopes.to_string(), Some(node_id))?;
 
                 let result_id = method.get_result_struct_type();
                 let result_node = &gen.node_map[&result_id];
                 let (result_scopes, results_ty_params) = if result_node.get_scope_id() == 0 {
-                    let mut names = names.clone();
                     let local_name = module_name(&format!("{}Results", name));
                     nested_output.push(generate_node(gen, result_id, &*local_name, Some(node_id))?);
-                    names.push(local_name);
-                    (names, params.params.clone())
+                    (names.child(local_name, ItemKind::Module), params.params.clone())
                 } else {
                     (gen.scope_map[&result_node.get_id()].clone(),
                      get_ty_params_of_brand(gen, method.get_result_brand()?)?)
                 };
                 let result_type = do_branding(&gen, result_id, method.get_result_brand()?,
-                                              Leaf::Owned, result_scopes.join("::"), Some(node_id))?;
+                                              Leaf::Owned, result_scopes.to_string(), Some(node_id))?;
 
                 dispatch_arms.push(
                     Line(format!(
@@ -1603,10 +2808,11 @@ fn generate_node(gen: &GeneratorContext,
                         capitalize_first_letter(name), results_ty_params, result_type)));
                 server_interior.push(
                     Line(format!(
-                        "fn {}(&mut self, _: {}Params<{}>, _: {}Results<{}>) -> ::capnp::capability::Promise<(), ::capnp::Error> {{ ::capnp::capability::Promise::err(::capnp::Error::unimplemented(\"method not implemented\".to_string())) }}",
+                        "fn {}(&mut self, _: {}Params<{}>, _: {}Results<{}>) -> ::capnp::capability::Promise<(), ::capnp::Error> {{ ::capnp::capability::Promise::err(::capnp::Error::unimplemented(\"Method not implemented: {}.{}\".to_string())) }}",
                         module_name(name),
                         capitalize_first_letter(name), params_ty_params,
-                        capitalize_first_letter(name), results_ty_params
+                        capitalize_first_letter(name), results_ty_params,
+                        node_reader.get_display_name()?, name
                     )));
 
                 client_impl_interior.push(
@@ -1617,27 +2823,61 @@ fn generate_node(gen: &GeneratorContext,
                     Box::new(Line(format!("self.client.new_call(_private::TYPE_ID, {}, None)", ordinal)))));
                 client_impl_interior.push(Line("}".to_string()));
 
-                method.get_annotations()?;
+                if has_annotation(interface_annotations, INLINE_PARAMS_ANNOTATION_ID) ||
+                    has_annotation(method.get_annotations()?, INLINE_PARAMS_ANNOTATION_ID)
+                {
+                    if let Some(m) = generate_inline_params_method(gen, name, *param_node, &result_type)? {
+                        client_impl_interior.push(m);
+                    }
+                }
+
+                method_infos.push(Line(format!(
+                    "::capnp::capability::MethodInfo {{ interface_id: _private::TYPE_ID, method_id: {}, name: \"{}\", param_type_id: {}, result_type_id: {} }},",
+                    ordinal, name, format_u64(param_id), format_u64(result_id))));
+
+                if has_annotation(method.get_annotations()?, IDEMPOTENT_ANNOTATION_ID) {
+                    idempotent_methods.push(format!("(_private::TYPE_ID, {})", ordinal));
+                }
+                if has_annotation(method.get_annotations()?, CACHEABLE_ANNOTATION_ID) {
+                    cacheable_methods.push(format!("(_private::TYPE_ID, {})", ordinal));
+                }
             }
 
-            let mut base_dispatch_arms = Vec::new();
+            mod_interior.push(
+                Line(format!("pub const IDEMPOTENT_METHODS: &'static [(u64, u16)] = &[{}];",
+                             idempotent_methods.join(", "))));
+            mod_interior.push(
+                Line(format!("pub const CACHEABLE_METHODS: &'static [(u64, u16)] = &[{}];",
+                             cacheable_methods.join(", "))));
+            mod_interior.push(Line("pub const METHODS: &'static [::capnp::capability::MethodInfo] = &[".to_string()));
+            mod_interior.push(Indent(Box::new(Branch(method_infos))));
+            mod_interior.push(Line("];".to_string()));
+
+            // Calls are dispatched through a table keyed by interface ID. Each base that has a
+            // path here adds its own entries, and those of its bases, to the table. A base
+            // without one, such as an interface from a file that is only imported indirectly,
+            // doesn't become a supertrait of `Server`; the server's type implements its `Server`
+            // trait separately, and its entries are added with `ServerDispatch::with_dispatch()`.
+            let mut base_registrations = Vec::new();
             let server_base = {
                 let mut base_traits = Vec::new();
                 let extends = interface.get_superclasses()?;
                 for ii in 0..extends.len() {
                     let type_id = extends.get(ii).get_id();
                     let brand = extends.get(ii).get_brand()?;
-                    let the_mod = gen.scope_map[&type_id].join("::");
+                    let the_mod = match gen.scope_map.get(&type_id) {
+                        Some(path) => path.to_string(),
+                        None => continue,
+                    };
 
-                    base_dispatch_arms.push(Line(format!(
-                        "0x{:x} => {}::dispatch_call_internal(&mut self.server, method_id, params, results),",
-                        type_id,
+                    base_registrations.push(Line(format!(
+                        "{}::register_dispatch(table);",
                         do_branding(
                             gen, type_id, brand, Leaf::ServerDispatch, the_mod.clone(), None)?)));
                     base_traits.push(
                         do_branding(gen, type_id, brand, Leaf::Server, the_mod, None)?);
                 }
-                if extends.len() > 0 { format!(": {}", base_traits.join(" + ")) }
+                if base_traits.len() > 0 { format!(": {}", base_traits.join(" + ")) }
                 else { "".to_string() }
             };
 
@@ -1651,6 +2891,7 @@ fn generate_node(gen: &GeneratorContext,
             mod_interior.push(
                 Branch(vec!(
                     Line(format!("impl {} ::capnp::capability::FromClientHook for Client{} {{", bracketed_params, bracketed_params)),
+                    Indent(Box::new(Line("#[allow(unused_parens)]".to_string()))),
                     Indent(Box::new(Line(format!("fn new(hook: Box<dyn (::capnp::private::capability::ClientHook)>) -> Client{} {{", bracketed_params)))),
                     Indent(Box::new(Indent(Box::new(Line(format!("Client {{ client: ::capnp::capability::Client::new(hook), {} }}", params.phantom_data_value)))))),
                     Indent(Box::new(Line("}".to_string()))),
@@ -1739,12 +2980,14 @@ fn generate_node(gen: &GeneratorContext,
                             Indent(Box::new(Branch(client_impl_interior))),
                             Line("}".to_string()))));
 
+            let server_start = mod_interior.len();
             mod_interior.push(Branch(vec!(Line(format!("pub trait Server<{}> {} {} {{", params.params, server_base, params.where_clause)),
                                           Indent(Box::new(Branch(server_interior))),
                                           Line("}".to_string()))));
 
             mod_interior.push(Branch(vec!(Line(format!("pub struct ServerDispatch<_T,{}> {{", params.params)),
                                           Indent(Box::new(Line("pub server: _T,".to_string()))),
+                                          Indent(Box::new(Line("dispatch: ::capnp::capability::DispatchTable<_T>,".to_string()))),
                                           Indent(Box::new(Branch(if is_generic {
                                             vec!(Line(params.phantom_data_type.clone())) } else { vec!() } ))),
                                           Line("}".to_string()))));
@@ -1756,7 +2999,7 @@ fn generate_node(gen: &GeneratorContext,
                 Indent(Box::new(Branch(vec![
                     Line(format!("type Dispatch = ServerDispatch<_S, {}>;", params.params)),
                     Line(format!("fn from_server(s: _S) -> ServerDispatch<_S, {}> {{", params.params)),
-                    Indent(Box::new(Line(format!("ServerDispatch {{ server: s, {} }}", params.phantom_data_value)))),
+                    Indent(Box::new(Line(format!("ServerDispatch::<_S, {}>::new(s)", params.params)))),
                     Line("}".to_string()),
                 ]))),
                 Line("}".to_string()),
@@ -1793,11 +3036,10 @@ fn generate_node(gen: &GeneratorContext,
                         Line("impl <_T: Server> ::capnp::capability::Server for ServerDispatch<_T> {".to_string())
                     }),
                     Indent(Box::new(Line("fn dispatch_call(&mut self, interface_id: u64, method_id: u16, params: ::capnp::capability::Params<::capnp::any_pointer::Owned>, results: ::capnp::capability::Results<::capnp::any_pointer::Owned>) -> ::capnp::capability::Promise<(), ::capnp::Error> {".to_string()))),
-                    Indent(Box::new(Indent(Box::new(Line("match interface_id {".to_string()))))),
+                    Indent(Box::new(Indent(Box::new(Line("match self.dispatch.find(interface_id) {".to_string()))))),
                     Indent(Box::new(Indent(Box::new(Indent(
-                        Box::new(Line(format!("_private::TYPE_ID => ServerDispatch::<_T, {}>::dispatch_call_internal(&mut self.server, method_id, params, results),",params.params)))))))),
-                    Indent(Box::new(Indent(Box::new(Indent(Box::new(Branch(base_dispatch_arms))))))),
-                    Indent(Box::new(Indent(Box::new(Indent(Box::new(Line("_ => { ::capnp::capability::Promise::err(::capnp::Error::unimplemented(\"Method not implemented.\".to_string())) }".to_string()))))))),
+                        Box::new(Line("::core::option::Option::Some(dispatch) => dispatch(&mut self.server, method_id, params, results),".to_string()))))))),
+                    Indent(Box::new(Indent(Box::new(Indent(Box::new(Line("::core::option::Option::None => { ::capnp::capability::Promise::err(::capnp::Error::unimplemented(\"Method not implemented.\".to_string())) }".to_string()))))))),
                     Indent(Box::new(Indent(Box::new(Line("}".to_string()))))),
                     Indent(Box::new(Line("}".to_string()))),
                     Line("}".to_string()))));
@@ -1809,14 +3051,39 @@ fn generate_node(gen: &GeneratorContext,
                     } else {
                         Line("impl <_T :Server> ServerDispatch<_T> {".to_string())
                     }),
+                    // With no methods to dispatch to, the arguments go unused.
+                    Indent(Box::new(Branch(if methods.len() == 0 {
+                        vec![Line("#[allow(unused_variables)]".to_string())]
+                    } else {
+                        Vec::new()
+                    }))),
                     Indent(Box::new(Line("pub fn dispatch_call_internal(server: &mut _T, method_id: u16, params: ::capnp::capability::Params<::capnp::any_pointer::Owned>, results: ::capnp::capability::Results<::capnp::any_pointer::Owned>) -> ::capnp::capability::Promise<(), ::capnp::Error> {".to_string()))),
                     Indent(Box::new(Indent(Box::new(Line("match method_id {".to_string()))))),
                     Indent(Box::new(Indent(Box::new(Indent(Box::new(Branch(dispatch_arms))))))),
                     Indent(Box::new(Indent(Box::new(Indent(Box::new(Line("_ => { ::capnp::capability::Promise::err(::capnp::Error::unimplemented(\"Method not implemented.\".to_string())) }".to_string()))))))),
                     Indent(Box::new(Indent(Box::new(Line("}".to_string()))))),
                     Indent(Box::new(Line("}".to_string()))),
+                    Indent(Box::new(Line("pub fn new(server: _T) -> Self {".to_string()))),
+                    Indent(Box::new(Indent(Box::new(Line("let mut dispatch = ::capnp::capability::DispatchTable::new();".to_string()))))),
+                    Indent(Box::new(Indent(Box::new(Line(format!("ServerDispatch::<_T, {}>::register_dispatch(&mut dispatch);", params.params)))))),
+                    Indent(Box::new(Indent(Box::new(Line(format!("ServerDispatch {{ server: server, dispatch: dispatch, {} }}", params.phantom_data_value)))))),
+                    Indent(Box::new(Line("}".to_string()))),
+                    Indent(Box::new(Line("pub fn register_dispatch(table: &mut ::capnp::capability::DispatchTable<_T>) {".to_string()))),
+                    Indent(Box::new(Indent(Box::new(Line(format!("table.register(_private::TYPE_ID, ServerDispatch::<_T, {}>::dispatch_call_internal);", params.params)))))),
+                    Indent(Box::new(Indent(Box::new(Branch(base_registrations))))),
+                    Indent(Box::new(Line("}".to_string()))),
+                    Indent(Box::new(Line("pub fn with_dispatch(mut self, register: fn(&mut ::capnp::capability::DispatchTable<_T>)) -> Self {".to_string()))),
+                    Indent(Box::new(Indent(Box::new(Line("register(&mut self.dispatch);".to_string()))))),
+                    Indent(Box::new(Indent(Box::new(Line("self".to_string()))))),
+                    Indent(Box::new(Line("}".to_string()))),
                     Line("}".to_string()))));
 
+            if let Some(attribute) = cfg_attribute(gen, node_reader, SERVER_CFG_ANNOTATION_ID)? {
+                for item in &mut mod_interior[server_start..] {
+                    *item = Branch(vec![attribute.clone(), item.clone()]);
+                }
+            }
+
             mod_interior.push(
                 Branch(vec!(
                     Line("pub mod _private {".to_string()),
@@ -1827,6 +3094,7 @@ fn generate_node(gen: &GeneratorContext,
             mod_interior.push(Branch(vec!(Branch(nested_output))));
 
             output.push(BlankLine);
+            output.push(cfg.clone());
             if is_generic {
                 output.push(Line(format!("pub mod {} {{ /* ({}) */", node_name, params.expanded_list.join(","))));
             } else {
@@ -1903,7 +3171,7 @@ fn generate_node(gen: &GeneratorContext,
                 _ => { return Err(Error::failed(format!("type does not match value"))); }
             };
 
-            output.push(formatted_text);
+            output.push(Branch(vec![cfg, formatted_text]));
         }
 
         node::Annotation( _annotation_reader ) => (),
@@ -1912,10 +3180,150 @@ fn generate_node(gen: &GeneratorContext,
     Ok(Branch(output))
 }
 
+/// Files declaring at least this many constants, at any nesting depth, get a `consts` module.
+const CONSTS_MODULE_THRESHOLD: usize = 16;
+
+fn collect_constants(gen: &GeneratorContext, node_id: u64, result: &mut Vec<u64>) -> ::capnp::Result<()> {
+    let node = match gen.node_map.get(&node_id) { Some(node) => node, None => return Ok(()) };
+    if let schema_capnp::node::Const(_) = node.which()? {
+        result.push(node_id);
+    }
+    for nested_node in node.get_nested_nodes()?.iter() {
+        collect_constants(gen, nested_node.get_id(), result)?;
+    }
+    Ok(())
+}
+
+// Collects every constant of a file into a flat `consts` module, named by its scope
+// within the file. Values are not duplicated: primitive constants become statics, so
+// each has a single address, and pointer constants are re-exported, so they stay
+// `constant::Reader`s over the words embedded once at their declaration. Constants whose
+// flattened names collide, like those of `Foo.barBaz` and `FooBar.baz`, are left out.
+fn generate_consts_module(gen: &GeneratorContext, file_id: u64) -> ::capnp::Result<FormattedText> {
+    use crate::schema_capnp::{node, type_};
+
+    let mut const_ids = Vec::new();
+    collect_constants(gen, file_id, &mut const_ids)?;
+    if const_ids.len() < CONSTS_MODULE_THRESHOLD {
+        return Ok(Branch(Vec::new()));
+    }
+    for nested_node in gen.node_map[&file_id].get_nested_nodes()?.iter() {
+        if gen.get_last_name(nested_node.get_id()).ok() == Some("consts") {
+            // The name is taken by a schema item.
+            return Ok(Branch(Vec::new()));
+        }
+    }
+
+    let file_scope_len = gen.scope_map[&file_id].segments.len();
+    let flat_name = |id: u64| snake_to_upper_case(&gen.scope_map[&id].segments[file_scope_len..].join("_"));
+    let mut name_counts = collections::hash_map::HashMap::new();
+    for &id in &const_ids {
+        *name_counts.entry(flat_name(id)).or_insert(0) += 1;
+    }
+
+    let mut interior = Vec::new();
+    for id in const_ids {
+        let flat_name = flat_name(id);
+        if name_counts[&flat_name] > 1 {
+            continue;
+        }
+        let node = &gen.node_map[&id];
+        let scope = &gen.scope_map[&id];
+        let last_name = snake_to_upper_case(gen.get_last_name(id)?);
+        let path = format!("{}::{}", scope.parent(), last_name);
+
+        let mut cfgs: Vec<FormattedText> = Vec::new();
+        let mut scope_id = id;
+        while scope_id != file_id {
+            let scope_node = match gen.node_map.get(&scope_id) { Some(n) => n, None => break };
+            cfgs.extend(cfg_attribute(gen, scope_node, CFG_ANNOTATION_ID)?);
+            scope_id = scope_node.get_scope_id();
+        }
+
+        let typ = match node.which()? {
+            node::Const(c) => c.get_type()?,
+            _ => unreachable!(),
+        };
+        let declaration = match typ.which()? {
+            type_::Text(()) =>
+                Line(format!("pub static {}: &'static str = {};", flat_name, path)),
+            type_::Data(()) =>
+                Line(format!("pub static {}: &'static [u8] = {};", flat_name, path)),
+            type_::List(_) | type_::Struct(_) | type_::Interface(_) | type_::AnyPointer(_) =>
+                Line(format!("pub use {} as {};", path, flat_name)),
+            _ =>
+                Line(format!("pub static {}: {} = {};",
+                             flat_name, typ.type_string(gen, Leaf::Owned)?, path)),
+        };
+        interior.push(Branch(cfgs));
+        interior.push(declaration);
+    }
+
+    Ok(Branch(vec![
+        BlankLine,
+        Line("pub mod consts {".to_string()),
+        Indent(Box::new(Branch(interior))),
+        Line("}".to_string()),
+    ]))
+}
+
+fn collect_structs(gen: &GeneratorContext, node_id: u64, result: &mut Vec<u64>) -> ::capnp::Result<()> {
+    let node = match gen.node_map.get(&node_id) { Some(node) => node, None => return Ok(()) };
+    if let schema_capnp::node::Struct(struct_reader) = node.which()? {
+        result.push(node_id);
+        for field in struct_reader.get_fields()?.iter() {
+            if let schema_capnp::field::Group(group) = field.which()? {
+                collect_structs(gen, group.get_type_id(), result)?;
+            }
+        }
+    }
+    for nested_node in node.get_nested_nodes()?.iter() {
+        collect_structs(gen, nested_node.get_id(), result)?;
+    }
+    Ok(())
+}
+
+// Generates a file's `register_schemas()` function, if the `embed_schemas` option is set. It
+// covers the structs and groups declared in the file, but not the implicit parameter and
+// result structs of methods, which have no module path of their own.
+fn generate_register_schemas(gen: &GeneratorContext, file_id: u64) -> ::capnp::Result<FormattedText> {
+    if !gen.options.embed_schemas {
+        return Ok(Branch(Vec::new()));
+    }
+    let mut struct_ids = Vec::new();
+    collect_structs(gen, file_id, &mut struct_ids)?;
+
+    let register = if struct_ids.is_empty() { "_register" } else { "register" };
+    let mut interior = Vec::new();
+    for id in struct_ids {
+        let mut cfgs: Vec<FormattedText> = Vec::new();
+        let mut scope_id = id;
+        while scope_id != file_id {
+            let scope_node = match gen.node_map.get(&scope_id) { Some(n) => n, None => break };
+            cfgs.extend(cfg_attribute(gen, scope_node, CFG_ANNOTATION_ID)?);
+            scope_id = scope_node.get_scope_id();
+        }
+        interior.push(Branch(cfgs));
+        interior.push(Line(format!("register({}::schema()?)?;", gen.scope_map[&id])));
+    }
+    interior.push(Line("Ok(())".to_string()));
+
+    Ok(Branch(vec![
+        BlankLine,
+        Line("/// Passes the schema node of each struct declared in this file to `register`.".to_string()),
+        Line(format!("pub fn register_schemas({}: &mut dyn FnMut(::capnp::any_pointer::Reader<'static>) -> ::capnp::Result<()>)",
+                     register)),
+        Line("                        -> ::capnp::Result<()>".to_string()),
+        Line("{".to_string()),
+        Indent(Box::new(Branch(interior))),
+        Line("}".to_string()),
+    ]))
+}
+
 // The capnp crate defines a blanket impl of capnp::Read for R where R: std::io::Read,
 // but we can't use that here because it lives behind the "std" feature flag.
-struct ReadWrapper<R> where R: std::io::Read {
-    inner: R,
+pub(crate) struct ReadWrapper<R> where R: std::io::Read {
+    pub(crate) inner: R,
 }
 
 impl <R> capnp::io::Read for ReadWrapper<R> where R: std::io::Read {
@@ -1930,59 +3338,414 @@ impl <R> capnp::io::Read for ReadWrapper<R> where R: std::io::Read {
     }
 }
 
+// Looks for constructs in the requested files that code generation doesn't support, so
+// that all of them are reported at once, each with the display name of the offending
+// node, rather than as a failure partway through generation.
+fn check_supported(gen: &GeneratorContext) -> ::capnp::Result<()> {
+    let mut problems = Vec::new();
+    for requested_file in gen.request.get_requested_files()?.iter() {
+        collect_unsupported(gen, requested_file.get_id(), &mut problems)?;
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::unimplemented(format!("unsupported schema constructs:\n  {}", problems.join("\n  "))))
+    }
+}
+
+fn collect_unsupported(gen: &GeneratorContext, node_id: u64, problems: &mut Vec<String>) -> ::capnp::Result<()> {
+    use crate::schema_capnp::{field, node, type_};
+
+    let node = match gen.node_map.get(&node_id) { Some(node) => node, None => return Ok(()) };
+    let display_name = node.get_display_name()?;
+    match node.which()? {
+        node::Struct(st) => {
+            for field in st.get_fields()?.iter() {
+                match field.which()? {
+                    field::Slot(slot) => {
+                        if let Some(problem) = unsupported_type(slot.get_type()?)? {
+                            problems.push(format!("{}.{}: {}", display_name, field.get_name()?, problem));
+                        }
+                    }
+                    field::Group(group) => collect_unsupported(gen, group.get_type_id(), problems)?,
+                }
+            }
+        }
+        node::Interface(interface) => {
+            // Implicit parameter and result structs are scoped to no node, so they are not
+            // among the nested nodes.
+            for method in interface.get_methods()?.iter() {
+                for id in &[method.get_param_struct_type(), method.get_result_struct_type()] {
+                    if gen.node_map.get(id).map(|n| n.get_scope_id()) == Some(0) {
+                        collect_unsupported(gen, *id, problems)?;
+                    }
+                }
+            }
+        }
+        node::Const(c) => {
+            let problem = match c.get_type()?.which()? {
+                type_::Interface(_) => Some("interface constants are unsupported"),
+                type_::AnyPointer(_) => Some("AnyPointer constants are unsupported"),
+                _ => unsupported_type(c.get_type()?)?,
+            };
+            if let Some(problem) = problem {
+                problems.push(format!("{}: {}", display_name, problem));
+            }
+        }
+        _ => (),
+    }
+    for nested_node in node.get_nested_nodes()?.iter() {
+        collect_unsupported(gen, nested_node.get_id(), problems)?;
+    }
+    Ok(())
+}
+
+fn unsupported_type(typ: schema_capnp::type_::Reader) -> ::capnp::Result<Option<&'static str>> {
+    use crate::schema_capnp::type_;
+    match typ.which()? {
+        type_::List(list) => {
+            let element_type = list.get_element_type()?;
+            match element_type.which()? {
+                type_::AnyPointer(_) => Ok(Some("List(AnyPointer) is unsupported")),
+                _ => unsupported_type(element_type),
+            }
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Generates Rust code for each file requested by `message`, a `schema_capnp::code_generator_request`.
+/// Returns the path of each output file, relative to the output directory, along with its text.
+pub fn generate_files<S>(message: &capnp::message::Reader<S>) -> ::capnp::Result<Vec<(::std::path::PathBuf, String)>>
+    where S: capnp::message::ReaderSegments
+{
+    generate_files_with_options(message, GeneratorOptions::default())
+}
+
+/// Like `generate_files()`, but with `options` instead of the default options.
+pub fn generate_files_with_options<S>(message: &capnp::message::Reader<S>, options: GeneratorOptions)
+                                      -> ::capnp::Result<Vec<(::std::path::PathBuf, String)>>
+    where S: capnp::message::ReaderSegments
+{
+    let gen = GeneratorContext::new_with_options(message, options)?;
+    check_supported(&gen)?;
+
+    let mut result = Vec::new();
+    for requested_file in gen.request.get_requested_files()?.iter() {
+        result.push(generate_file(&gen, requested_file)?);
+    }
+    Ok(result)
+}
+
+fn generate_file(gen: &GeneratorContext,
+                 requested_file: schema_capnp::code_generator_request::requested_file::Reader)
+                 -> ::capnp::Result<(::std::path::PathBuf, String)>
+{
+    let id = requested_file.get_id();
+    let mut filepath = ::std::path::PathBuf::from(requested_file.get_filename()?);
+
+    let root_name = path_to_stem_string(&filepath)?.replace("-", "_");
+    filepath.set_file_name(&format!("{}_capnp.rs", root_name));
+
+    let lines = Branch(vec!(
+        Line("// @generated by the capnpc-rust plugin to the Cap'n Proto schema compiler.".to_string()),
+        Line("// DO NOT EDIT.".to_string()),
+        Line(format!("// source: {}", requested_file.get_filename()?)),
+        BlankLine,
+        generate_node(gen, id, &root_name, None)?));
+
+    Ok((filepath, stringify(&lines)))
+}
+
+/// The number of threads that `generate_code()` generates files on.
+pub const DEFAULT_THREADS: usize = 4;
+
 /// Generates Rust code according to a `schema_capnp::code_generator_request` read from `inp`.
 pub fn generate_code<T>(inp: T, out_dir: &::std::path::Path) -> ::capnp::Result<()>
     where T: ::std::io::Read
+{
+    generate_code_with_threads(inp, out_dir, DEFAULT_THREADS)
+}
+
+/// Like `generate_code()`, but generates files on up to `threads` threads. Each file is
+/// written as soon as it has been generated.
+pub fn generate_code_with_threads<T>(inp: T, out_dir: &::std::path::Path, threads: usize) -> ::capnp::Result<()>
+    where T: ::std::io::Read
+{
+    generate_code_internal(inp, out_dir, threads, false, GeneratorOptions::default()).map(|_| ())
+}
+
+/// Like `generate_code()`, but with `options` instead of the default options.
+pub fn generate_code_with_options<T>(inp: T, out_dir: &::std::path::Path, options: GeneratorOptions)
+                                     -> ::capnp::Result<()>
+    where T: ::std::io::Read
+{
+    generate_code_internal(inp, out_dir, DEFAULT_THREADS, false, options).map(|_| ())
+}
+
+/// Like `generate_code()`, but writes nothing, and instead fails if any generated file is
+/// missing from `out_dir` or differs from the one there.
+pub fn check_code<T>(inp: T, out_dir: &::std::path::Path) -> ::capnp::Result<()>
+    where T: ::std::io::Read
+{
+    check_code_with_options(inp, out_dir, GeneratorOptions::default())
+}
+
+/// Like `check_code()`, but compares against code generated with `options`.
+pub fn check_code_with_options<T>(inp: T, out_dir: &::std::path::Path, options: GeneratorOptions)
+                                  -> ::capnp::Result<()>
+    where T: ::std::io::Read
+{
+    let stale = generate_code_internal(inp, out_dir, DEFAULT_THREADS, true, options)?;
+    if stale.is_empty() {
+        Ok(())
+    } else {
+        let names: Vec<String> = stale.iter().map(|path| path.display().to_string()).collect();
+        Err(Error::failed(format!("generated code is out of date: {}", names.join(", "))))
+    }
+}
+
+// Generates the requested files and writes those that changed, or with `check`, only
+// collects them. Returns the paths of the files that changed, in request order.
+fn generate_code_internal<T>(inp: T, out_dir: &::std::path::Path, threads: usize, check: bool,
+                             options: GeneratorOptions)
+                             -> ::capnp::Result<Vec<::std::path::PathBuf>>
+    where T: ::std::io::Read
 {
     use capnp::serialize;
-    use std::io::Write;
 
     let message = serialize::read_message(ReadWrapper { inner: inp }, capnp::message::ReaderOptions::new())?;
+    let file_count = {
+        let gen = GeneratorContext::new(&message)?;
+        check_supported(&gen)?;
+        gen.request.get_requested_files()?.len() as usize
+    };
 
-    let gen = GeneratorContext::new(&message)?;
+    let workers = ::std::cmp::max(1, ::std::cmp::min(threads, file_count));
+    if workers == 1 {
+        return generate_and_write(&message, out_dir, 0, 1, check, options).map(|changed| {
+            changed.into_iter().map(|(_, path)| path).collect()
+        });
+    }
 
-    for requested_file in gen.request.get_requested_files()?.iter() {
-        let id = requested_file.get_id();
-        let mut filepath = out_dir.to_path_buf();
-        let requested = ::std::path::PathBuf::from(requested_file.get_filename()?);
-        filepath.push(requested);
-        if let Some(parent) = filepath.parent() {
-            ::std::fs::create_dir_all(parent).map_err(convert_io_err)?;
-        }
-
-        let root_name = path_to_stem_string(&filepath)?.replace("-", "_");
-        filepath.set_file_name(&format!("{}_capnp.rs", root_name));
-
-        let lines = Branch(vec!(
-            Line("// @generated by the capnpc-rust plugin to the Cap'n Proto schema compiler.".to_string()),
-            Line("// DO NOT EDIT.".to_string()),
-            Line(format!("// source: {}", requested_file.get_filename()?)),
-            BlankLine,
-            generate_node(&gen, id, &root_name, None)?));
-
-        let text = stringify(&lines);
-
-        let previous_text = ::std::fs::read(&filepath);
-        if previous_text.is_ok() && previous_text.unwrap() == text.as_bytes() {
-            // File is unchanged. Do not write it so that builds with the
-            // output as part of the source work in read-only filesystems
-            // and so timestamp-based build systems and watchers do not get
-            // confused.
-            continue;
+    // Readers can't cross threads, so each worker reads the request from its own copy of
+    // the bytes and builds its own context, and then takes every `workers`th file.
+    let bytes = ::std::sync::Arc::new(serialize::write_message_segments_to_words(message.get_segments()));
+    let handles: Vec<_> = (0..workers).map(|worker| {
+        let bytes = bytes.clone();
+        let out_dir = out_dir.to_path_buf();
+        ::std::thread::spawn(move || -> ::capnp::Result<Vec<(usize, ::std::path::PathBuf)>> {
+            let (message, _) = serialize::read_message_from_bytes_at(
+                &bytes, 0, serialize::TrailingBytes::Reject, capnp::message::ReaderOptions::new())?;
+            generate_and_write(&message, &out_dir, worker, workers, check, options)
+        })
+    }).collect();
+
+    let mut changed = Vec::new();
+    let mut first_error = None;
+    for handle in handles {
+        let worker_result = handle.join().unwrap_or_else(|_| {
+            Err(Error::failed("code generation thread panicked".to_string()))
+        });
+        match worker_result {
+            Ok(mut worker_changed) => changed.append(&mut worker_changed),
+            Err(e) => if first_error.is_none() { first_error = Some(e) },
         }
+    }
+    if let Some(e) = first_error {
+        return Err(e);
+    }
+    changed.sort();
+    Ok(changed.into_iter().map(|(_, path)| path).collect())
+}
 
-        // It would be simpler to use the ? operator instead of a pattern match, but then the error message
-        // would not include `filepath`.
-        match ::std::fs::File::create(&filepath) {
-            Ok(ref mut writer) => {
-                writer.write_all(text.as_bytes()).map_err(convert_io_err)?;
+// Generates each requested file whose index is `worker` modulo `workers`, and writes it
+// unless `check` is set. Returns the indices and paths of the files that changed.
+fn generate_and_write<S>(message: &capnp::message::Reader<S>, out_dir: &::std::path::Path,
+                         worker: usize, workers: usize, check: bool, options: GeneratorOptions)
+                         -> ::capnp::Result<Vec<(usize, ::std::path::PathBuf)>>
+    where S: capnp::message::ReaderSegments
+{
+    let gen = GeneratorContext::new_with_options(message, options)?;
+    let mut changed = Vec::new();
+    for (index, requested_file) in gen.request.get_requested_files()?.iter().enumerate() {
+        if index % workers == worker {
+            let (relative_path, text) = generate_file(&gen, requested_file)?;
+            if write_if_changed(&out_dir.join(&relative_path), &text, check)? {
+                changed.push((index, relative_path));
             }
-            Err(e) => {
-                let _ = writeln!(&mut ::std::io::stderr(),
-                                 "could not open file {:?} for writing: {}", filepath, e);
-                return Err(convert_io_err(e));
+        }
+    }
+    Ok(changed)
+}
+
+// Returns whether the file at `filepath` differed from `text`. With `check`, nothing is written.
+fn write_if_changed(filepath: &::std::path::Path, text: &str, check: bool) -> ::capnp::Result<bool> {
+    use std::io::Write;
+
+    let previous_text = ::std::fs::read(&filepath);
+    if previous_text.is_ok() && previous_text.unwrap() == text.as_bytes() {
+        // File is unchanged. Do not write it so that builds with the
+        // output as part of the source work in read-only filesystems
+        // and so timestamp-based build systems and watchers do not get
+        // confused.
+        return Ok(false);
+    }
+    if check {
+        return Ok(true);
+    }
+
+    if let Some(parent) = filepath.parent() {
+        ::std::fs::create_dir_all(parent).map_err(convert_io_err)?;
+    }
+
+    // It would be simpler to use the ? operator instead of a pattern match, but then the error message
+    // would not include `filepath`.
+    match ::std::fs::File::create(&filepath) {
+        Ok(ref mut writer) => {
+            writer.write_all(text.as_bytes()).map_err(convert_io_err)?;
+        }
+        Err(e) => {
+            let _ = writeln!(&mut ::std::io::stderr(),
+                             "could not open file {:?} for writing: {}", filepath, e);
+            return Err(convert_io_err(e));
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::compiler::compile_with;
+
+    #[test]
+    fn threaded_generation_matches_generate_files() {
+        let files: Vec<PathBuf> = (0..5).map(|i| PathBuf::from(format!("f{}.capnp", i))).collect();
+        let message = compile_with(&files, &[], &[], &|path: &Path| {
+            let stem = path.file_stem().unwrap().to_str().unwrap();
+            let index: u64 = stem[1..].parse().unwrap();
+            Ok(format!("@{:#x};\nstruct S{} {{ a @0 :Text; b @1 :List(UInt32); }}\n",
+                       0xd6f1b1e1f6b1c9a0u64 + index, index))
+        }).unwrap();
+        let bytes = ::capnp::serialize::write_message_to_words(&message);
+        let expected = super::generate_files(&message.into_reader()).unwrap();
+        assert_eq!(expected.len(), 5);
+
+        let out_dir = ::std::env::temp_dir().join(format!("capnpc-threaded-{}", ::std::process::id()));
+        super::generate_code_with_threads(&bytes[..], &out_dir, 3).unwrap();
+        for (path, text) in expected {
+            assert_eq!(::std::fs::read_to_string(out_dir.join(path)).unwrap(), text);
+        }
+        ::std::fs::remove_dir_all(&out_dir).unwrap();
+    }
+
+    #[test]
+    fn check_reports_stale_files_without_writing() {
+        let message = compile_with(&[PathBuf::from("foo.capnp")], &[], &[], &|_: &Path| {
+            Ok("@0xd6f1b1e1f6b1c9a5;\nstruct Foo { a @0 :Text; }\n".to_string())
+        }).unwrap();
+        let bytes = ::capnp::serialize::write_message_to_words(&message);
+
+        let out_dir = ::std::env::temp_dir().join(format!("capnpc-check-{}", ::std::process::id()));
+        let error = super::check_code(&bytes[..], &out_dir).err().unwrap();
+        assert_eq!(error.description, "generated code is out of date: foo_capnp.rs");
+        assert!(!out_dir.exists());
+
+        super::generate_code(&bytes[..], &out_dir).unwrap();
+        super::check_code(&bytes[..], &out_dir).unwrap();
+
+        let path = out_dir.join("foo_capnp.rs");
+        ::std::fs::write(&path, "stale").unwrap();
+        assert!(super::check_code(&bytes[..], &out_dir).is_err());
+        assert_eq!(::std::fs::read_to_string(&path).unwrap(), "stale");
+        ::std::fs::remove_dir_all(&out_dir).unwrap();
+    }
+
+    #[test]
+    fn inline_tests_skip_fields_of_empty_enums() {
+        let message = compile_with(&[PathBuf::from("foo.capnp")], &[], &[], &|_: &Path| {
+            Ok("@0xd6f1b1e1f6b1c9a5;\nenum Empty {}\nstruct Foo { e @0 :Empty; n @1 :UInt8; }\n".to_string())
+        }).unwrap();
+        let options = super::GeneratorOptions { inline_tests: true, ..Default::default() };
+        let files = super::generate_files_with_options(&message.into_reader(), options).unwrap();
+        assert!(files[0].1.contains("fn n() {"));
+        assert!(!files[0].1.contains("fn e() {"));
+    }
+
+    #[test]
+    fn union_discriminant_gaps() {
+        use crate::schema_capnp::{code_generator_request, node};
+
+        let mut message = compile_with(&[PathBuf::from("foo.capnp")], &[], &[], &|_: &Path| {
+            Ok("@0xd6f1b1e1f6b1c9a5;
+                struct Foo {
+                  union { a @0 :Void; b @1 :UInt32; c @2 :Text; }
+                  g :group { union { x @3 :Void; y @4 :Int8; } }
+                }".to_string())
+        }).unwrap();
+
+        // Renumbers the union members the way a schema would look once others were removed
+        // from between them: `a` = 2, `b` = 5, `c` = 9, `x` = 4 and `y` = 7.
+        let mut offsets = Vec::new();
+        {
+            let request = message.get_root::<code_generator_request::Builder>().unwrap();
+            let mut nodes = request.get_nodes().unwrap();
+            for i in 0..nodes.len() {
+                if let node::Which::Struct(mut st) = nodes.reborrow().get(i).which().unwrap() {
+                    if st.reborrow().get_discriminant_count() == 0 {
+                        continue;
+                    }
+                    offsets.push(st.reborrow().get_discriminant_offset());
+                    let mut fields = st.get_fields().unwrap();
+                    for j in 0..fields.len() {
+                        let mut field = fields.reborrow().get(j);
+                        let value = match &*field.reborrow().into_reader().get_name().unwrap() {
+                            "a" => 2, "b" => 5, "c" => 9, "x" => 4, "y" => 7,
+                            _ => continue,
+                        };
+                        field.set_discriminant_value(value);
+                    }
+                }
             }
         }
+        offsets.sort();
+        let (foo_offset, g_offset) = (offsets[0], offsets[1]);
+
+        let files = super::generate_files(&message.into_reader()).unwrap();
+        let text = &files[0].1;
+        for value in &[2, 5, 9] {
+            assert!(text.contains(&format!("{} => {{", value)));
+        }
+        assert!(text.contains(&format!("set_data_field::<u16>({}, 5);", foo_offset)));
+        assert!(text.contains(&format!("set_data_field::<u16>({}, 9);", foo_offset)));
+        for value in &[4, 7] {
+            assert!(text.contains(&format!("set_data_field::<u16>({}, {});", g_offset, value)));
+        }
+        // Initializing the group zeroes its discriminant, as on the wire.
+        assert!(text.contains(&format!("set_data_field::<u16>({}, 0);", g_offset)));
+    }
+
+    #[test]
+    fn unsupported_constructs_are_reported_together() {
+        let text = "@0xd6f1b1e1f6b1c9a5;
+            struct Foo {
+              a @0 :List(AnyPointer);
+              g :group { b @1 :List(List(AnyPointer)); }
+            }
+            interface Bar {
+              m @0 (p :List(AnyPointer)) -> ();
+            }";
+        let message = compile_with(&[PathBuf::from("foo.capnp")], &[], &[], &|path: &Path| {
+            assert_eq!(path, Path::new("foo.capnp"));
+            Ok(text.to_string())
+        }).unwrap();
+        let error = super::generate_files(&message.into_reader()).err().unwrap();
+        assert_eq!(error.kind, ::capnp::ErrorKind::Unimplemented);
+        assert_eq!(error.description, "unsupported schema constructs:
+  foo.capnp:Foo.a: List(AnyPointer) is unsupported
+  foo.capnp:Foo.g.b: List(AnyPointer) is unsupported
+  foo.capnp:Bar.m$Params.p: List(AnyPointer) is unsupported");
     }
-    Ok(())
 }
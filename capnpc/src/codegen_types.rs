@@ -19,7 +19,7 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 
-use crate::schema_capnp::{brand, node, type_};
+use crate::schema_capnp::{brand, field, node, type_};
 use capnp::Error;
 use crate::codegen;
 use crate::codegen::{GeneratorContext};
@@ -176,11 +176,11 @@ impl <'a> RustTypeInfo for type_::Reader<'a> {
             type_::Data(()) => Ok(format!("::capnp::data::{}", module)),
             type_::Struct(st) => {
                 do_branding(gen, st.get_type_id(), st.get_brand()?, module,
-                            gen.scope_map[&st.get_type_id()].join("::"), None)
+                            gen.scope_map[&st.get_type_id()].to_string(), None)
             }
             type_::Interface(interface) => {
                 do_branding(gen, interface.get_type_id(), interface.get_brand()?, module,
-                            gen.scope_map[&interface.get_type_id()].join("::"), None)
+                            gen.scope_map[&interface.get_type_id()].to_string(), None)
             }
             type_::List(ot1) => {
                 let element_type = ot1.get_element_type()?;
@@ -215,8 +215,7 @@ impl <'a> RustTypeInfo for type_::Reader<'a> {
                 }
             },
             type_::Enum(en) => {
-                let scope = &gen.scope_map[&en.get_type_id()];
-                Ok(scope.join("::").to_string())
+                Ok(gen.scope_map[&en.get_type_id()].to_string())
             },
             type_::AnyPointer(pointer) => {
                 match pointer.which()? {
@@ -297,6 +296,33 @@ impl <'a> RustTypeInfo for type_::Reader<'a> {
 
 ///
 ///
+/// Renders the type of a field. For a slot this is `type_string()` of its type. A group's
+/// generated types take the type parameters of every enclosing scope, however deeply the
+/// group is nested in other groups and unions.
+pub fn field_type_string(gen: &GeneratorContext,
+                         field: field::Reader,
+                         module: Leaf) -> Result<String, Error> {
+    match field.which()? {
+        field::Slot(slot) => slot.get_type()?.type_string(gen, module),
+        field::Group(group) => {
+            let id = group.get_type_id();
+            let the_mod = gen.scope_map[&id].to_string();
+            let params = gen.node_map[&id].parameters_texts(gen, None).params;
+            match module {
+                Leaf::Reader(lt) | Leaf::Builder(lt) => {
+                    let args = if params.is_empty() { "".to_string() } else { format!(",{}", params) };
+                    Ok(format!("{}::{}<{}{}>", the_mod, module.bare_name(), lt, args))
+                }
+                Leaf::Owned | Leaf::Pipeline => {
+                    let args = if params.is_empty() { "".to_string() } else { format!("<{}>", params) };
+                    Ok(format!("{}::{}{}", the_mod, module.bare_name(), args))
+                }
+                _ => Err(Error::failed(format!("groups have no {}", module.bare_name()))),
+            }
+        }
+    }
+}
+
 pub fn do_branding(gen: &GeneratorContext,
                    node_id: u64,
                    brand: brand::Reader,
@@ -0,0 +1,400 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Checks whether a new version of a schema can safely replace an old one.
+//!
+//! Both versions are given as `CodeGeneratorRequest`s, as produced by `capnp compile -o-`.
+//! Nodes are matched up by ID and struct fields by ordinal, and any change that would make
+//! messages or calls from one version misread by the other is reported. Adding new fields,
+//! enumerants, methods and types is always allowed, as is renaming things. Removing a whole
+//! type is not reported, because nothing in the new schema can refer to it.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::schema_capnp::{code_generator_request, field, node, type_, value};
+
+/// A change between two versions of a schema that breaks wire compatibility.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Incompatibility {
+    /// The display name of the affected node, as given in the old schema.
+    pub node: String,
+    pub description: String,
+}
+
+impl fmt::Display for Incompatibility {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}: {}", self.node, self.description)
+    }
+}
+
+/// Reads a serialized `CodeGeneratorRequest` for each version of a schema and compares them.
+/// See `check()`.
+pub fn check_streams<R1, R2>(old: R1, new: R2) -> ::capnp::Result<Vec<Incompatibility>>
+    where R1: std::io::Read, R2: std::io::Read
+{
+    use capnp::serialize;
+    use crate::codegen::ReadWrapper;
+    let options = capnp::message::ReaderOptions::new();
+    let old = serialize::read_message(ReadWrapper { inner: old }, options)?;
+    let new = serialize::read_message(ReadWrapper { inner: new }, options)?;
+    check(old.get_root()?, new.get_root()?)
+}
+
+/// Compares every node of `old` with the node of the same ID in `new`, returning the changes
+/// that break compatibility. An empty result means that `new` can replace `old`.
+pub fn check(old: code_generator_request::Reader,
+             new: code_generator_request::Reader) -> ::capnp::Result<Vec<Incompatibility>>
+{
+    let mut new_nodes = HashMap::new();
+    let mut new_names = HashMap::new();
+    for node in new.get_nodes()?.iter() {
+        new_nodes.insert(node.get_id(), node);
+        new_names.insert(node.get_display_name()?, node.get_id());
+    }
+
+    let mut checker = Checker { new_nodes: new_nodes, result: Vec::new() };
+    for old_node in old.get_nodes()?.iter() {
+        let name = old_node.get_display_name()?;
+        match checker.new_nodes.get(&old_node.get_id()) {
+            Some(&new_node) => checker.check_node(name, old_node, new_node)?,
+            None => {
+                if let Some(&new_id) = new_names.get(name) {
+                    checker.report(name, format!("type ID changed from @0x{:x} to @0x{:x}",
+                                                 old_node.get_id(), new_id));
+                }
+            }
+        }
+    }
+    Ok(checker.result)
+}
+
+struct Checker<'a> {
+    new_nodes: HashMap<u64, node::Reader<'a>>,
+    result: Vec<Incompatibility>,
+}
+
+impl <'a> Checker<'a> {
+    fn report(&mut self, node: &str, description: String) {
+        self.result.push(Incompatibility { node: node.to_string(), description: description });
+    }
+
+    fn check_node(&mut self, name: &str, old: node::Reader, new: node::Reader) -> ::capnp::Result<()> {
+        match (old.which()?, new.which()?) {
+            (node::Struct(old), node::Struct(new)) => self.check_struct(name, old, new),
+            (node::Enum(old), node::Enum(new)) => {
+                let old_enumerants = old.get_enumerants()?;
+                let new_enumerants = new.get_enumerants()?;
+                for idx in new_enumerants.len()..old_enumerants.len() {
+                    self.report(name, format!("enumerant {} (@{}) removed",
+                                              old_enumerants.get(idx).get_name()?, idx));
+                }
+                Ok(())
+            }
+            (node::Interface(old), node::Interface(new)) => self.check_interface(name, old, new),
+            (node::Const(old), node::Const(new)) => {
+                if !same_type(old.get_type()?, new.get_type()?)? {
+                    self.report(name, "type of constant changed".to_string());
+                }
+                Ok(())
+            }
+            (node::File(()), node::File(())) | (node::Annotation(_), node::Annotation(_)) => Ok(()),
+            _ => {
+                self.report(name, "kind of node changed".to_string());
+                Ok(())
+            }
+        }
+    }
+
+    fn check_struct(&mut self, name: &str,
+                    old: node::struct_::Reader, new: node::struct_::Reader) -> ::capnp::Result<()>
+    {
+        if !old.get_is_group() {
+            if new.get_data_word_count() < old.get_data_word_count() {
+                self.report(name, format!("data section shrank from {} to {} words",
+                                          old.get_data_word_count(), new.get_data_word_count()));
+            }
+            if new.get_pointer_count() < old.get_pointer_count() {
+                self.report(name, format!("pointer section shrank from {} to {} pointers",
+                                          old.get_pointer_count(), new.get_pointer_count()));
+            }
+        }
+        if old.get_discriminant_count() > 0 && new.get_discriminant_count() > 0 &&
+            old.get_discriminant_offset() != new.get_discriminant_offset()
+        {
+            self.report(name, "union discriminant moved".to_string());
+        }
+
+        let old_fields = old.get_fields()?;
+        let new_fields = new.get_fields()?;
+        for old_field in old_fields.iter() {
+            let old_name = old_field.get_name()?;
+            let new_field = match old_field.get_ordinal().which()? {
+                field::ordinal::Explicit(ordinal) => {
+                    new_fields.iter().find(|f| match f.get_ordinal().which() {
+                        Ok(field::ordinal::Explicit(o)) => o == ordinal,
+                        _ => false,
+                    })
+                }
+                // Group fields do not have ordinals of their own; match them by name instead.
+                field::ordinal::Implicit(()) => {
+                    new_fields.iter().find(|f| f.get_name().ok() == Some(old_name))
+                }
+            };
+            let new_field = match new_field {
+                Some(f) => f,
+                None => {
+                    self.report(name, format!("field {} removed", old_name));
+                    continue;
+                }
+            };
+            if old_field.get_discriminant_value() != new_field.get_discriminant_value() {
+                self.report(name, format!("field {} moved into or out of a union", old_name));
+            }
+            let problem = match (old_field.which()?, new_field.which()?) {
+                (field::Slot(old_slot), field::Slot(new_slot)) => {
+                    if old_slot.get_offset() != new_slot.get_offset() {
+                        Some("offset")
+                    } else if !compatible_type(old_slot.get_type()?, new_slot.get_type()?)? {
+                        Some("type")
+                    } else {
+                        if primitive_default(old_slot.get_default_value()?)? !=
+                            primitive_default(new_slot.get_default_value()?)?
+                        {
+                            self.report(name, format!("default value of field {} changed", old_name));
+                        }
+                        None
+                    }
+                }
+                (field::Group(_), field::Group(_)) => None,
+                _ => Some("kind"),
+            };
+            if let Some(problem) = problem {
+                let new_name = new_field.get_name()?;
+                if new_name != old_name {
+                    // A different field has taken over the old field's ordinal.
+                    self.report(name, format!("ordinal of field {} reused by field {}",
+                                              old_name, new_name));
+                } else {
+                    self.report(name, format!("{} of field {} changed", problem, old_name));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_interface(&mut self, name: &str,
+                       old: node::interface::Reader, new: node::interface::Reader)
+                       -> ::capnp::Result<()>
+    {
+        let old_methods = old.get_methods()?;
+        let new_methods = new.get_methods()?;
+        for idx in 0..old_methods.len() {
+            let old_method = old_methods.get(idx);
+            let method_name = old_method.get_name()?;
+            if idx >= new_methods.len() {
+                self.report(name, format!("method {} (@{}) removed", method_name, idx));
+                continue;
+            }
+            let new_method = new_methods.get(idx);
+            if old_method.get_param_struct_type() != new_method.get_param_struct_type() {
+                self.report(name, format!("parameter type of method {} changed", method_name));
+            }
+            if old_method.get_result_struct_type() != new_method.get_result_struct_type() {
+                self.report(name, format!("result type of method {} changed", method_name));
+            }
+        }
+
+        let new_superclasses = new.get_superclasses()?;
+        for old_superclass in old.get_superclasses()?.iter() {
+            let id = old_superclass.get_id();
+            if !new_superclasses.iter().any(|s| s.get_id() == id) {
+                let superclass_name = match self.new_nodes.get(&id) {
+                    Some(n) => n.get_display_name()?.to_string(),
+                    None => format!("@0x{:x}", id),
+                };
+                self.report(name, format!("no longer extends {}", superclass_name));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_pointer(t: type_::Reader) -> ::capnp::Result<bool> {
+    Ok(match t.which()? {
+        type_::Text(()) | type_::Data(()) | type_::List(_) | type_::Struct(_) |
+        type_::Interface(_) | type_::AnyPointer(_) => true,
+        _ => false,
+    })
+}
+
+/// Whether a field of type `old` may be changed to type `new`.
+fn compatible_type(old: type_::Reader, new: type_::Reader) -> ::capnp::Result<bool> {
+    if let type_::AnyPointer(_) = new.which()? {
+        // Generalizing a pointer field to AnyPointer is allowed.
+        return is_pointer(old);
+    }
+    same_type(old, new)
+}
+
+fn same_type(a: type_::Reader, b: type_::Reader) -> ::capnp::Result<bool> {
+    Ok(match (a.which()?, b.which()?) {
+        (type_::List(a), type_::List(b)) => same_type(a.get_element_type()?, b.get_element_type()?)?,
+        (type_::Enum(a), type_::Enum(b)) => a.get_type_id() == b.get_type_id(),
+        (type_::Struct(a), type_::Struct(b)) => a.get_type_id() == b.get_type_id(),
+        (type_::Interface(a), type_::Interface(b)) => a.get_type_id() == b.get_type_id(),
+        (type_::AnyPointer(_), type_::AnyPointer(_)) => true,
+        (a, b) => type_tag(&a) == type_tag(&b) && type_tag(&a).is_some(),
+    })
+}
+
+fn type_tag<A, B, C, D, E>(t: &type_::Which<A, B, C, D, E>) -> Option<u8> {
+    Some(match *t {
+        type_::Void(()) => 0,
+        type_::Bool(()) => 1,
+        type_::Int8(()) => 2,
+        type_::Int16(()) => 3,
+        type_::Int32(()) => 4,
+        type_::Int64(()) => 5,
+        type_::Uint8(()) => 6,
+        type_::Uint16(()) => 7,
+        type_::Uint32(()) => 8,
+        type_::Uint64(()) => 9,
+        type_::Float32(()) => 10,
+        type_::Float64(()) => 11,
+        type_::Text(()) => 12,
+        type_::Data(()) => 13,
+        _ => return None,
+    })
+}
+
+/// The bits that a primitive default value is XORed with on the wire, or `None` for pointers.
+fn primitive_default(v: value::Reader) -> ::capnp::Result<Option<u64>> {
+    Ok(Some(match v.which()? {
+        value::Void(()) => 0,
+        value::Bool(b) => b as u64,
+        value::Int8(n) => n as u8 as u64,
+        value::Int16(n) => n as u16 as u64,
+        value::Int32(n) => n as u32 as u64,
+        value::Int64(n) => n as u64,
+        value::Uint8(n) => n as u64,
+        value::Uint16(n) => n as u64,
+        value::Uint32(n) => n as u64,
+        value::Uint64(n) => n,
+        value::Float32(n) => n.to_bits() as u64,
+        value::Float64(n) => n.to_bits(),
+        value::Enum(n) => n as u64,
+        _ => return Ok(None),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use capnp::message;
+    use crate::schema_capnp::code_generator_request;
+
+    /// Builds a request holding a struct `Foo` with the given `(name, ordinal, offset, is_text)`
+    /// fields, where non-text fields are UInt32s, and an enum `Bar` with the given enumerants.
+    fn build(foo_id: u64, fields: &[(&str, u16, u32, bool)], enumerants: &[&str])
+             -> message::Builder<message::HeapAllocator>
+    {
+        let mut message = message::Builder::new_default();
+        {
+            let request = message.init_root::<code_generator_request::Builder>();
+            let mut nodes = request.init_nodes(2);
+            {
+                let mut node = nodes.reborrow().get(0);
+                node.set_id(foo_id);
+                node.set_display_name("foo.capnp:Foo");
+                let mut struct_ = node.init_struct();
+                struct_.set_data_word_count(1);
+                struct_.set_pointer_count(1);
+                let mut field_list = struct_.init_fields(fields.len() as u32);
+                for (idx, &(name, ordinal, offset, is_text)) in fields.iter().enumerate() {
+                    let mut field = field_list.reborrow().get(idx as u32);
+                    field.set_name(name);
+                    field.set_discriminant_value(0xffff);
+                    field.reborrow().init_ordinal().set_explicit(ordinal);
+                    let mut slot = field.init_slot();
+                    slot.set_offset(offset);
+                    if is_text {
+                        slot.reborrow().init_type().set_text(());
+                        slot.init_default_value().set_text("");
+                    } else {
+                        slot.reborrow().init_type().set_uint32(());
+                        slot.init_default_value().set_uint32(0);
+                    }
+                }
+            }
+            let mut node = nodes.get(1);
+            node.set_id(0x200);
+            node.set_display_name("foo.capnp:Bar");
+            let mut enumerant_list = node.init_enum().init_enumerants(enumerants.len() as u32);
+            for (idx, name) in enumerants.iter().enumerate() {
+                enumerant_list.reborrow().get(idx as u32).set_name(name);
+            }
+        }
+        message
+    }
+
+    fn check(old: &message::Builder<message::HeapAllocator>,
+             new: &message::Builder<message::HeapAllocator>) -> Vec<String> {
+        super::check(old.get_root_as_reader().unwrap(), new.get_root_as_reader().unwrap())
+            .unwrap().iter().map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn compatible_additions() {
+        let old = build(0x100, &[("a", 0, 0, false)], &["x"]);
+        let new = build(0x100, &[("renamed", 0, 0, false), ("b", 1, 0, true)], &["x", "y"]);
+        assert_eq!(check(&old, &new), Vec::<String>::new());
+    }
+
+    #[test]
+    fn field_type_changed() {
+        let old = build(0x100, &[("a", 0, 0, false), ("b", 1, 0, true)], &[]);
+        let new = build(0x100, &[("a", 0, 0, true), ("b", 1, 1, true)], &[]);
+        assert_eq!(check(&old, &new), vec!["foo.capnp:Foo: type of field a changed",
+                                           "foo.capnp:Foo: offset of field b changed"]);
+    }
+
+    #[test]
+    fn ordinal_reused() {
+        let old = build(0x100, &[("a", 0, 0, false)], &[]);
+        let new = build(0x100, &[("c", 0, 0, true)], &[]);
+        assert_eq!(check(&old, &new), vec!["foo.capnp:Foo: ordinal of field a reused by field c"]);
+    }
+
+    #[test]
+    fn removals() {
+        let old = build(0x100, &[("a", 0, 0, false), ("b", 1, 0, true)], &["x", "y"]);
+        let new = build(0x100, &[("a", 0, 0, false)], &["x"]);
+        assert_eq!(check(&old, &new), vec!["foo.capnp:Foo: field b removed",
+                                           "foo.capnp:Bar: enumerant y (@1) removed"]);
+    }
+
+    #[test]
+    fn type_id_changed() {
+        let old = build(0x100, &[("a", 0, 0, false)], &[]);
+        let new = build(0x101, &[("a", 0, 0, false)], &[]);
+        assert_eq!(check(&old, &new), vec!["foo.capnp:Foo: type ID changed from @0x100 to @0x101"]);
+    }
+}
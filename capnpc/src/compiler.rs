@@ -0,0 +1,2418 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Compiles `.capnp` schema files into a `CodeGeneratorRequest`, like `capnp compile -o-`
+//! does, without needing the `capnp` tool.
+//!
+//! Node IDs, struct layouts and default values match the ones the C++ compiler produces, so
+//! code generated from either request is interchangeable.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+
+use capnp::{message, Error, Result};
+use capnp::private::layout::{self, ElementSize, PrimitiveElement};
+
+use crate::dynamic::RawPointerBuilder;
+use crate::parser::{self, Decl, DeclKind, ParamList};
+use crate::schema_capnp::{annotation, brand, code_generator_request, field, node, type_,
+                          value, ElementSize as SchemaElementSize};
+use crate::text_format::Expr;
+
+/// Compiles `files` and everything they import into a `CodeGeneratorRequest` message, with
+/// `files` as the requested files.
+///
+/// As with `capnp compile`, a file's name in the request has the first matching prefix in
+/// `src_prefixes` removed, and imports of absolute paths are looked up in `import_paths`, in order.
+/// The standard imports that the C++ implementation installs, such as `/capnp/c++.capnp` and
+/// `/capnp/schema.capnp`, are built in, and are used before any import path is searched.
+pub fn compile<P>(files: &[P], src_prefixes: &[PathBuf], import_paths: &[PathBuf])
+                  -> Result<message::Builder<message::HeapAllocator>>
+    where P: AsRef<Path>
+{
+    let paths: Vec<PathBuf> = files.iter().map(|f| f.as_ref().to_path_buf()).collect();
+    compile_with(&paths, src_prefixes, import_paths, &|path| {
+        std::fs::read_to_string(path).map_err(crate::convert_io_err)
+    })
+}
+
+/// Like `compile()`, but reads files with `read`.
+pub(crate) fn compile_with(files: &[PathBuf], src_prefixes: &[PathBuf], import_paths: &[PathBuf],
+                           read: &dyn Fn(&Path) -> Result<String>)
+                           -> Result<message::Builder<message::HeapAllocator>>
+{
+    let mut loader = Loader { files: Vec::new(), by_path: HashMap::new(), import_paths: import_paths, read: read };
+    let mut requested = Vec::new();
+    for path in files {
+        let path = normalize(path);
+        let mut name = path.clone();
+        for prefix in src_prefixes {
+            if let Ok(stripped) = path.strip_prefix(normalize(prefix)) {
+                name = stripped.to_path_buf();
+                break;
+            }
+        }
+        requested.push(loader.load(path, display_path(&name))?);
+    }
+    let mut next = 0;
+    while next < loader.files.len() {
+        loader.load_imports(next)?;
+        next += 1;
+    }
+
+    let files = loader.files;
+    let mut compiler = Compiler::new(&files);
+    for idx in 0..files.len() {
+        compiler.add_file(idx)?;
+    }
+    for idx in 0..compiler.nodes.len() {
+        if let Kind::Struct | Kind::Params = compiler.nodes[idx].kind {
+            compiler.lay_out(idx)?;
+        }
+    }
+    compiler.check_ids()?;
+
+    let mut message = message::Builder::new_default();
+    compiler.write_request(&requested, message.init_root())?;
+    Ok(message)
+}
+
+/// The files that the C++ implementation installs under `/capnp/`.
+const STANDARD_IMPORTS: &[(&str, &str)] = &[
+    ("/capnp/c++.capnp", include_str!("../schema/capnp/c++.capnp")),
+    ("/capnp/persistent.capnp", include_str!("../schema/capnp/persistent.capnp")),
+    ("/capnp/rpc-twoparty.capnp", include_str!("../schema/capnp/rpc-twoparty.capnp")),
+    ("/capnp/rpc.capnp", include_str!("../schema/capnp/rpc.capnp")),
+    ("/capnp/schema.capnp", include_str!("../schema/capnp/schema.capnp")),
+];
+
+fn standard_import(path: &Path) -> Option<&'static str> {
+    STANDARD_IMPORTS.iter().find(|&&(name, _)| Path::new(name) == path).map(|&(_, text)| text)
+}
+
+pub(crate) struct SourceFile {
+    name: String,
+    path: PathBuf,
+    decl: Decl,
+    /// Imports as written, and the files they refer to.
+    imports: Vec<(String, usize)>,
+}
+
+struct Loader<'a> {
+    files: Vec<SourceFile>,
+    by_path: HashMap<PathBuf, usize>,
+    import_paths: &'a [PathBuf],
+    read: &'a dyn Fn(&Path) -> Result<String>,
+}
+
+impl <'a> Loader<'a> {
+    fn load(&mut self, path: PathBuf, name: String) -> Result<usize> {
+        if let Some(&idx) = self.by_path.get(&path) {
+            return Ok(idx);
+        }
+        let text = match standard_import(&path) {
+            Some(text) => text.to_string(),
+            None => (self.read)(&path).map_err(|e| {
+                Error::failed(format!("{}: could not read file: {}", name, e.description))
+            })?,
+        };
+        let decl = parser::parse_file(&name, &text)?;
+        let idx = self.files.len();
+        self.files.push(SourceFile { name: name, path: path.clone(), decl: decl, imports: Vec::new() });
+        self.by_path.insert(path, idx);
+        Ok(idx)
+    }
+
+    fn load_imports(&mut self, idx: usize) -> Result<()> {
+        let mut imports = Vec::new();
+        parser::for_each_import(&self.files[idx].decl, &mut |import| {
+            if !imports.iter().any(|i: &String| i == import) {
+                imports.push(import.to_string());
+            }
+        });
+        for import in imports {
+            let file = if import.starts_with('/') {
+                let relative = import.trim_start_matches('/');
+                let standard = normalize(Path::new(&import));
+                let mut found = None;
+                if standard_import(&standard).is_some() {
+                    found = Some(self.load(standard, relative.to_string())?);
+                } else {
+                    for dir in self.import_paths {
+                        let path = normalize(&dir.join(relative));
+                        if self.by_path.contains_key(&path) || (self.read)(&path).is_ok() {
+                            found = Some(self.load(path, relative.to_string())?);
+                            break;
+                        }
+                    }
+                }
+                match found {
+                    Some(file) => file,
+                    None => return Err(Error::failed(format!(
+                        "{}: import {:?} not found in any import path", self.files[idx].name, import))),
+                }
+            } else {
+                let path = normalize(&dir_of(&self.files[idx].path).join(&import));
+                let name = display_path(&normalize(&dir_of(Path::new(&self.files[idx].name)).join(&import)));
+                self.load(path, name)?
+            };
+            self.files[idx].imports.push((import, file));
+        }
+        Ok(())
+    }
+}
+
+fn dir_of(path: &Path) -> PathBuf {
+    path.parent().map(|p| p.to_path_buf()).unwrap_or_default()
+}
+
+/// Removes `.` and `..` components from `path`, without consulting the file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                if !result.pop() {
+                    result.push("..");
+                }
+            }
+            c => result.push(c.as_os_str()),
+        }
+    }
+    result
+}
+
+fn display_path(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    File,
+    Struct,
+    /// A group or a named union.
+    Group,
+    /// The parameter or result struct of a method.
+    Params,
+    Enum,
+    Interface,
+    Const,
+    Annotation,
+}
+
+enum Member<'a> {
+    Node(usize),
+    Using(&'a Expr),
+}
+
+struct Node<'a> {
+    id: u64,
+    kind: Kind,
+    decl: &'a Decl,
+    /// The fields of a struct, group or parameter list.
+    body: &'a [Decl],
+    /// The enclosing scope. For parameter structs, this is the interface, even though their
+    /// scope ID is zero.
+    parent: Option<usize>,
+    file: usize,
+    display_name: String,
+    prefix_len: usize,
+    params: Vec<String>,
+    members: HashMap<&'a str, Member<'a>>,
+    /// Nested nodes, in declaration order.
+    nested: Vec<usize>,
+    struct_info: Option<StructInfo<'a>>,
+}
+
+struct StructInfo<'a> {
+    data_words: u16,
+    pointers: u16,
+    discriminant_count: u16,
+    discriminant_offset: u32,
+    /// The struct whose scope the fields' types and default values are resolved in.
+    scope: usize,
+    fields: Vec<FieldInfo<'a>>,
+}
+
+struct FieldInfo<'a> {
+    decl: &'a Decl,
+    code_order: u16,
+    discriminant: Option<u16>,
+    kind: FieldKind<'a>,
+}
+
+enum FieldKind<'a> {
+    Slot { typ: Type, offset: u32, default: Option<&'a Expr> },
+    Group(usize),
+}
+
+#[derive(Clone, Debug)]
+enum Type {
+    Void,
+    Bool,
+    Int8,
+    Int16,
+    Int32,
+    Int64,
+    UInt8,
+    UInt16,
+    UInt32,
+    UInt64,
+    Float32,
+    Float64,
+    Text,
+    Data,
+    List(Box<Type>),
+    Enum(usize, Brand),
+    Struct(usize, Brand),
+    Interface(usize, Brand),
+    AnyPointer,
+    AnyStruct,
+    AnyList,
+    Capability,
+    /// A generic parameter: its scope's node and its index.
+    Param(usize, u16),
+    ImplicitParam(u16),
+}
+
+#[derive(Clone, Debug)]
+enum Binding {
+    Bind(Vec<Type>),
+    Inherit,
+}
+
+/// Bindings of generic scopes, by scope node. Scopes that are not mentioned are unbound.
+type Brand = Vec<(usize, Binding)>;
+
+/// What a name refers to.
+#[derive(Clone, Debug)]
+enum Entity {
+    Node(usize, Brand),
+    Type(Type),
+    /// `List`, before it is given an element type.
+    List,
+}
+
+/// The value that an annotation is applied with.
+enum AnnotationValue<'a> {
+    None,
+    Expr(&'a Expr),
+    /// `$foo(a = 1, b = 2)`, which is short for `$foo((a = 1, b = 2))`.
+    Fields(&'a [(Option<String>, Expr)]),
+}
+
+/// Where names are looked up: a scope, and the implicit parameters of the method being
+/// compiled, if any.
+#[derive(Clone, Copy)]
+struct Ctx<'a> {
+    node: usize,
+    implicit: &'a [String],
+}
+
+struct Compiler<'a> {
+    files: &'a [SourceFile],
+    file_nodes: Vec<usize>,
+    nodes: Vec<Node<'a>>,
+    /// Parameter structs, by interface, method ordinal and whether they are the results.
+    param_structs: HashMap<(usize, u16, bool), usize>,
+    usings: RefCell<HashMap<(usize, &'a str), Entity>>,
+    resolving: RefCell<HashSet<(usize, &'a str)>>,
+    evaluating: RefCell<HashSet<usize>>,
+}
+
+impl <'a> Compiler<'a> {
+    fn new(files: &'a [SourceFile]) -> Compiler<'a> {
+        Compiler {
+            files: files,
+            file_nodes: Vec::new(),
+            nodes: Vec::new(),
+            param_structs: HashMap::new(),
+            usings: RefCell::new(HashMap::new()),
+            resolving: RefCell::new(HashSet::new()),
+            evaluating: RefCell::new(HashSet::new()),
+        }
+    }
+
+    fn error<T>(&self, node: usize, line: usize, message: &str) -> Result<T> {
+        Err(Error::failed(format!("{}:{}: {}", self.files[self.nodes[node].file].name, line, message)))
+    }
+
+    /// Adds the location of `decl` to errors that do not have a location yet.
+    fn locate(&self, node: usize, decl: &Decl, error: Error) -> Error {
+        if self.files.iter().any(|f| error.description.starts_with(&format!("{}:", f.name))) {
+            error
+        } else {
+            let file = &self.files[self.nodes[node].file].name;
+            Error::failed(format!("{}:{}: {}", file, decl.line, error.description))
+        }
+    }
+
+    // ---------------------------------------------------------------------------------------
+    // Nodes
+
+    fn add_node(&mut self, decl: &'a Decl, kind: Kind, parent: Option<usize>, file: usize,
+                id: u64, name: &str) -> usize
+    {
+        let display_name = match parent {
+            None => self.files[file].name.clone(),
+            Some(p) if self.nodes[p].kind == Kind::File => format!("{}:{}", self.nodes[p].display_name, name),
+            Some(p) => format!("{}.{}", self.nodes[p].display_name, name),
+        };
+        let body: &'a [Decl] = match kind {
+            Kind::Struct | Kind::Group => &decl.nested,
+            _ => &[],
+        };
+        self.nodes.push(Node {
+            id: id,
+            kind: kind,
+            decl: decl,
+            body: body,
+            parent: parent,
+            file: file,
+            prefix_len: display_name.len() - name.len(),
+            display_name: display_name,
+            params: decl.params.clone(),
+            members: HashMap::new(),
+            nested: Vec::new(),
+            struct_info: None,
+        });
+        self.nodes.len() - 1
+    }
+
+    fn add_file(&mut self, file: usize) -> Result<()> {
+        let files = self.files;
+        let decl = &files[file].decl;
+        let id = match decl.id {
+            Some(id) => id,
+            None => return Err(Error::failed(format!(
+                "{}:1: file has no ID; add a line like `@0x{:016x};`, with an ID generated by `capnp id`",
+                files[file].name, generate_child_id(0, &files[file].name)))),
+        };
+        let idx = self.add_node(decl, Kind::File, None, file, id, "");
+        self.nodes[idx].params.clear();
+        self.file_nodes.push(idx);
+        self.add_members(idx)
+    }
+
+    /// Adds the nodes and aliases declared inside the node `idx`.
+    fn add_members(&mut self, idx: usize) -> Result<()> {
+        let decl = self.nodes[idx].decl;
+        let file = self.nodes[idx].file;
+        for nested in &decl.nested {
+            let kind = match nested.kind {
+                DeclKind::Struct => Kind::Struct,
+                DeclKind::Enum => Kind::Enum,
+                DeclKind::Interface { .. } => Kind::Interface,
+                DeclKind::Const { .. } => Kind::Const,
+                DeclKind::Annotation { .. } => Kind::Annotation,
+                DeclKind::Using(ref target) => {
+                    self.add_member(idx, nested, Member::Using(target))?;
+                    continue;
+                }
+                _ => continue,
+            };
+            let id = match nested.id {
+                Some(id) => id,
+                None => generate_child_id(self.nodes[idx].id, &nested.name),
+            };
+            let child = self.add_node(nested, kind, Some(idx), file, id, &nested.name);
+            self.add_member(idx, nested, Member::Node(child))?;
+            self.nodes[idx].nested.push(child);
+            match kind {
+                Kind::Struct | Kind::Enum => self.add_members(child)?,
+                Kind::Interface => {
+                    self.add_members(child)?;
+                    self.add_param_structs(child)?;
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn add_member(&mut self, idx: usize, decl: &'a Decl, member: Member<'a>) -> Result<()> {
+        if self.nodes[idx].members.contains_key(&decl.name[..]) {
+            return self.error(idx, decl.line, &format!("{} is already defined", decl.name));
+        }
+        self.nodes[idx].members.insert(&decl.name, member);
+        Ok(())
+    }
+
+    /// Adds nodes for the parameter and result structs that `interface`'s methods declare as
+    /// lists of parameters.
+    fn add_param_structs(&mut self, interface: usize) -> Result<()> {
+        let decl = self.nodes[interface].decl;
+        let file = self.nodes[interface].file;
+        for method in &decl.nested {
+            if let DeclKind::Method { ref implicit_params, ref params, ref results } = method.kind {
+                let ordinal = method.ordinal.unwrap_or(0);
+                let lists = [(Some(params), false), (results.as_ref(), true)];
+                for &(list, is_results) in lists.iter() {
+                    let fields: &'a [Decl] = match list {
+                        Some(&ParamList::Type(_)) => continue,
+                        Some(&ParamList::Fields(ref fields)) => fields,
+                        None => &[],
+                    };
+                    let id = generate_method_params_id(self.nodes[interface].id, ordinal, is_results);
+                    let name = format!("{}${}", method.name, if is_results { "Results" } else { "Params" });
+                    let idx = self.add_node(method, Kind::Params, Some(interface), file, id, &name);
+                    self.nodes[idx].body = fields;
+                    self.nodes[idx].params = implicit_params.clone();
+                    self.param_structs.insert((interface, ordinal, is_results), idx);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_ids(&self) -> Result<()> {
+        let mut ids: HashMap<u64, usize> = HashMap::new();
+        for (idx, node) in self.nodes.iter().enumerate() {
+            if let Some(&other) = ids.get(&node.id) {
+                return self.error(idx, node.decl.line, &format!(
+                    "ID @0x{:016x} of {} is already used by {}",
+                    node.id, node.display_name, self.nodes[other].display_name));
+            }
+            ids.insert(node.id, idx);
+        }
+        Ok(())
+    }
+
+    fn is_generic(&self, idx: usize) -> bool {
+        let mut current = Some(idx);
+        while let Some(n) = current {
+            if !self.nodes[n].params.is_empty() {
+                return true;
+            }
+            current = self.nodes[n].parent;
+        }
+        false
+    }
+
+    /// A brand in which every generic scope enclosing `idx`, and `idx` itself, is inherited.
+    fn inherited_brand(&self, idx: usize) -> Brand {
+        let mut brand = Vec::new();
+        let mut current = Some(idx);
+        while let Some(n) = current {
+            if !self.nodes[n].params.is_empty() {
+                brand.push((n, Binding::Inherit));
+            }
+            current = self.nodes[n].parent;
+        }
+        brand
+    }
+
+    // ---------------------------------------------------------------------------------------
+    // Names
+
+    fn resolve(&self, expr: &'a Expr, ctx: Ctx) -> Result<Entity> {
+        match *expr {
+            Expr::Ident(ref name) => self.lookup(name, ctx),
+            Expr::AbsoluteName(ref name) => {
+                let file = self.file_nodes[self.nodes[ctx.node].file];
+                match self.nodes[file].members.get(&name[..]) {
+                    Some(member) => self.member_entity(file, name, member, &Vec::new()),
+                    None => Err(Error::failed(format!("{} is not defined at the top level", name))),
+                }
+            }
+            Expr::Import(ref path) => {
+                let file = &self.files[self.nodes[ctx.node].file];
+                match file.imports.iter().find(|&&(ref p, _)| p == path) {
+                    Some(&(_, idx)) => Ok(Entity::Node(self.file_nodes[idx], Vec::new())),
+                    None => Err(Error::failed(format!("import {:?} was not loaded", path))),
+                }
+            }
+            Expr::Member(ref base, ref name) => match self.resolve(base, ctx)? {
+                Entity::Node(n, brand) => match self.nodes[n].members.get(&name[..]) {
+                    Some(member) => self.member_entity(n, name, member, &brand),
+                    None => Err(Error::failed(format!(
+                        "{} has no member named {}", self.nodes[n].display_name, name))),
+                },
+                _ => Err(Error::failed(format!("{:?} has no members", base))),
+            },
+            Expr::Apply(ref base, ref args) => {
+                let mut types = Vec::new();
+                for &(ref name, ref arg) in args {
+                    if name.is_some() {
+                        return Err(Error::failed("generic parameters cannot be named".to_string()));
+                    }
+                    types.push(self.resolve_type(arg, ctx)?);
+                }
+                match self.resolve(base, ctx)? {
+                    Entity::List if types.len() == 1 => Ok(Entity::Type(Type::List(Box::new(types.remove(0))))),
+                    Entity::List => Err(Error::failed("List takes exactly one parameter".to_string())),
+                    Entity::Node(n, mut brand) => {
+                        let node = &self.nodes[n];
+                        if node.params.is_empty() {
+                            return Err(Error::failed(format!("{} is not generic", node.display_name)));
+                        }
+                        if node.params.len() != types.len() {
+                            return Err(Error::failed(format!(
+                                "{} takes {} parameters, not {}", node.display_name, node.params.len(), types.len())));
+                        }
+                        brand.retain(|&(s, _)| s != n);
+                        brand.push((n, Binding::Bind(types)));
+                        Ok(Entity::Node(n, brand))
+                    }
+                    Entity::Type(_) => Err(Error::failed(format!("{:?} is not generic", base))),
+                }
+            }
+            _ => Err(Error::failed(format!("expected a name, got {:?}", expr))),
+        }
+    }
+
+    fn lookup(&self, name: &'a str, ctx: Ctx) -> Result<Entity> {
+        if let Some(idx) = ctx.implicit.iter().position(|p| p == name) {
+            return Ok(Entity::Type(Type::ImplicitParam(idx as u16)));
+        }
+        let mut current = Some(ctx.node);
+        while let Some(n) = current {
+            let node = &self.nodes[n];
+            if let Some(idx) = node.params.iter().position(|p| p == name) {
+                return Ok(Entity::Type(Type::Param(n, idx as u16)));
+            }
+            if let Some(member) = node.members.get(name) {
+                return self.member_entity(n, name, member, &self.inherited_brand(n));
+            }
+            current = node.parent;
+        }
+        let typ = match name {
+            "Void" => Type::Void,
+            "Bool" => Type::Bool,
+            "Int8" => Type::Int8,
+            "Int16" => Type::Int16,
+            "Int32" => Type::Int32,
+            "Int64" => Type::Int64,
+            "UInt8" => Type::UInt8,
+            "UInt16" => Type::UInt16,
+            "UInt32" => Type::UInt32,
+            "UInt64" => Type::UInt64,
+            "Float32" => Type::Float32,
+            "Float64" => Type::Float64,
+            "Text" => Type::Text,
+            "Data" => Type::Data,
+            "AnyPointer" => Type::AnyPointer,
+            "AnyStruct" => Type::AnyStruct,
+            "AnyList" => Type::AnyList,
+            "Capability" => Type::Capability,
+            "List" => return Ok(Entity::List),
+            _ => return Err(Error::failed(format!("{} is not defined", name))),
+        };
+        Ok(Entity::Type(typ))
+    }
+
+    /// The entity `member` of the node `scope` refers to, when `scope` is branded with `brand`.
+    fn member_entity(&self, scope: usize, name: &'a str, member: &Member<'a>, brand: &Brand)
+                     -> Result<Entity>
+    {
+        match *member {
+            Member::Node(n) => Ok(Entity::Node(n, brand.clone())),
+            Member::Using(target) => {
+                let key = (scope, name);
+                let cached = self.usings.borrow().get(&key).cloned();
+                let entity = match cached {
+                    Some(entity) => entity,
+                    None => {
+                        if !self.resolving.borrow_mut().insert(key) {
+                            return Err(Error::failed(format!("alias {} refers to itself", name)));
+                        }
+                        let entity = self.resolve(target, scope_ctx(scope));
+                        self.resolving.borrow_mut().remove(&key);
+                        let entity = entity?;
+                        self.usings.borrow_mut().insert(key, entity.clone());
+                        entity
+                    }
+                };
+                Ok(match entity {
+                    Entity::Node(n, b) => Entity::Node(n, substitute_brand(&b, brand)),
+                    Entity::Type(t) => Entity::Type(substitute(&t, brand)),
+                    Entity::List => Entity::List,
+                })
+            }
+        }
+    }
+
+    fn resolve_type(&self, expr: &'a Expr, ctx: Ctx) -> Result<Type> {
+        match self.resolve(expr, ctx)? {
+            Entity::Type(t) => Ok(t),
+            Entity::List => Err(Error::failed("List needs an element type, as in List(T)".to_string())),
+            Entity::Node(n, brand) => match self.nodes[n].kind {
+                Kind::Struct => Ok(Type::Struct(n, brand)),
+                Kind::Enum => Ok(Type::Enum(n, brand)),
+                Kind::Interface => Ok(Type::Interface(n, brand)),
+                _ => Err(Error::failed(format!("{} is not a type", self.nodes[n].display_name))),
+            },
+        }
+    }
+
+    fn resolve_node(&self, expr: &'a Expr, ctx: Ctx, kind: Kind) -> Result<(usize, Brand)> {
+        match self.resolve(expr, ctx)? {
+            Entity::Node(n, brand) if self.nodes[n].kind == kind => Ok((n, brand)),
+            _ => Err(Error::failed(format!("{:?} is not {}", expr, match kind {
+                Kind::Struct => "a struct",
+                Kind::Interface => "an interface",
+                Kind::Annotation => "an annotation",
+                _ => "the right kind of declaration",
+            }))),
+        }
+    }
+
+    /// The type of a constant or annotation declaration.
+    fn declared_type(&self, idx: usize) -> Result<Type> {
+        let node = &self.nodes[idx];
+        let typ = match node.decl.kind {
+            DeclKind::Const { ref typ, .. } | DeclKind::Annotation { ref typ, .. } => typ,
+            _ => return Err(Error::failed(format!("{} has no type", node.display_name))),
+        };
+        let ctx = scope_ctx(node.parent.unwrap_or(idx));
+        self.resolve_type(typ, ctx).map_err(|e| self.locate(idx, node.decl, e))
+    }
+
+    // ---------------------------------------------------------------------------------------
+    // Struct layout
+
+    fn lay_out(&mut self, idx: usize) -> Result<()> {
+        let mut translator = StructTranslator {
+            compiler: self,
+            layout: Layout::new(),
+            members: Vec::new(),
+            by_ordinal: BTreeMap::new(),
+            groups: Vec::new(),
+        };
+        let root = translator.add_member(None, self.nodes[idx].decl, MemberKind::Root, 0, false, Scope::Top);
+        translator.members[root].node = Some(idx);
+        let body = self.nodes[idx].body;
+        translator.traverse_top_or_group(root, body, Scope::Top)?;
+        let (infos, groups) = translator.finish(idx)?;
+        for node in groups {
+            self.nodes.push(node);
+        }
+        for (node, info) in infos {
+            self.nodes[node].struct_info = Some(info);
+        }
+        Ok(())
+    }
+
+    fn struct_info(&self, idx: usize) -> &StructInfo<'a> {
+        self.nodes[idx].struct_info.as_ref().expect("struct has been laid out")
+    }
+
+    // ---------------------------------------------------------------------------------------
+    // Values
+
+    /// If `expr` names a constant, returns the constant's value and the scope it is written
+    /// in.
+    fn constant(&self, expr: &'a Expr, ctx: Ctx) -> Result<Option<(usize, &'a Expr, Ctx<'static>)>> {
+        match *expr {
+            Expr::Ident(_) | Expr::AbsoluteName(_) | Expr::Member(..) => (),
+            _ => return Ok(None),
+        }
+        match self.resolve(expr, ctx) {
+            Ok(Entity::Node(n, _)) => match self.nodes[n].decl.kind {
+                DeclKind::Const { ref value, .. } => {
+                    Ok(Some((n, value, scope_ctx(self.nodes[n].parent.unwrap_or(n)))))
+                }
+                _ => Err(Error::failed(format!("{} is not a constant", self.nodes[n].display_name))),
+            },
+            Ok(_) => Err(Error::failed(format!("{:?} is not a constant", expr))),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Evaluates `expr` with `f`, following it to the value of the constant it names, if it
+    /// names one.
+    fn with_constant<T, F>(&self, expr: &'a Expr, ctx: Ctx, f: F) -> Result<T>
+        where F: FnOnce(&'a Expr, Ctx) -> Result<T>
+    {
+        match self.constant(expr, ctx)? {
+            None => f(expr, ctx),
+            Some((n, value, const_ctx)) => {
+                if !self.evaluating.borrow_mut().insert(n) {
+                    return Err(Error::failed(format!("{} refers to itself", self.nodes[n].display_name)));
+                }
+                let result = f(value, const_ctx);
+                self.evaluating.borrow_mut().remove(&n);
+                result
+            }
+        }
+    }
+
+    /// The bits of a value of primitive type `typ`, as stored in a struct's data section.
+    fn primitive_bits(&self, typ: &Type, expr: &'a Expr, ctx: Ctx) -> Result<u64> {
+        match (typ, expr) {
+            (&Type::Void, &Expr::Ident(ref name)) if name == "void" => return Ok(0),
+            (&Type::Bool, &Expr::Ident(ref name)) if name == "true" => return Ok(1),
+            (&Type::Bool, &Expr::Ident(ref name)) if name == "false" => return Ok(0),
+            (&Type::Float32, &Expr::Ident(ref name)) | (&Type::Float64, &Expr::Ident(ref name))
+                if name == "inf" || name == "nan" =>
+            {
+                let n = if name == "inf" { std::f64::INFINITY } else { std::f64::NAN };
+                return Ok(float_bits(typ, n));
+            }
+            (&Type::Enum(n, _), &Expr::Ident(ref name)) => {
+                for enumerant in &self.nodes[n].decl.nested {
+                    if let DeclKind::Enumerant = enumerant.kind {
+                        if enumerant.name == *name {
+                            return Ok(enumerant.ordinal.unwrap_or(0) as u64);
+                        }
+                    }
+                }
+            }
+            _ => (),
+        }
+        self.with_constant(expr, ctx, |expr, ctx| {
+            if let (&Type::Enum(..), &Expr::Ident(_)) = (typ, expr) {
+                return self.primitive_bits(typ, expr, ctx);
+            }
+            let bits = match (typ, expr) {
+                (&Type::Void, &Expr::Ident(ref name)) if name == "void" => 0,
+                (&Type::Bool, &Expr::Ident(_)) => return self.primitive_bits(typ, expr, ctx),
+                (&Type::Int8, _) => integer(expr, i8::min_value() as i128, i8::max_value() as i128)? as u8 as u64,
+                (&Type::Int16, _) => integer(expr, i16::min_value() as i128, i16::max_value() as i128)? as u16 as u64,
+                (&Type::Int32, _) => integer(expr, i32::min_value() as i128, i32::max_value() as i128)? as u32 as u64,
+                (&Type::Int64, _) => integer(expr, i64::min_value() as i128, i64::max_value() as i128)? as u64,
+                (&Type::UInt8, _) => integer(expr, 0, u8::max_value() as i128)? as u64,
+                (&Type::UInt16, _) => integer(expr, 0, u16::max_value() as i128)? as u64,
+                (&Type::UInt32, _) => integer(expr, 0, u32::max_value() as i128)? as u64,
+                (&Type::UInt64, _) => integer(expr, 0, u64::max_value() as i128)? as u64,
+                (&Type::Float32, _) | (&Type::Float64, _) => match *expr {
+                    Expr::Int(negative, n) => float_bits(typ, if negative { -(n as f64) } else { n as f64 }),
+                    Expr::Float(n) => float_bits(typ, n),
+                    Expr::Ident(_) => return self.primitive_bits(typ, expr, ctx),
+                    _ => return Err(Error::failed(format!("expected a number, got {:?}", expr))),
+                },
+                _ => return Err(Error::failed(format!("value {:?} does not match its type", expr))),
+            };
+            Ok(bits)
+        })
+    }
+
+    /// The bits of the default value of the slot `field` of a struct whose fields are resolved
+    /// in `scope`.
+    fn default_bits(&self, field: &FieldInfo<'a>, scope: usize) -> Result<u64> {
+        match field.kind {
+            FieldKind::Slot { ref typ, default: Some(default), .. } if lg_size(typ).is_some() => {
+                self.primitive_bits(typ, default, scope_ctx(scope))
+            }
+            _ => Ok(0),
+        }
+    }
+
+    fn write_pointer(&self, pointer: layout::PointerBuilder, typ: &Type, expr: &'a Expr, ctx: Ctx)
+                     -> Result<()>
+    {
+        self.with_constant(expr, ctx, |expr, ctx| {
+            match (typ, expr) {
+                (&Type::Text, &Expr::String(ref bytes)) => match std::str::from_utf8(bytes) {
+                    Ok(s) => pointer.set_text(s),
+                    Err(_) => return Err(Error::failed("text is not valid UTF-8".to_string())),
+                },
+                (&Type::Data, &Expr::String(ref bytes)) | (&Type::Data, &Expr::Binary(ref bytes)) => {
+                    pointer.set_data(bytes)
+                }
+                (&Type::Struct(n, ref brand), &Expr::Tuple(ref items)) => {
+                    let size = self.struct_size(n);
+                    self.fill_struct(n, brand, pointer.init_struct(size), items, ctx)?
+                }
+                (&Type::List(ref element), &Expr::List(ref items)) => {
+                    let count = items.len() as u32;
+                    if let Type::Struct(n, ref brand) = **element {
+                        let list = pointer.init_struct_list(count, self.struct_size(n));
+                        for (idx, item) in items.iter().enumerate() {
+                            let element = list.get_struct_element(idx as u32);
+                            self.with_constant(item, ctx, |item, ctx| match *item {
+                                Expr::Tuple(ref fields) => self.fill_struct(n, brand, element, fields, ctx),
+                                _ => Err(Error::failed(format!("expected a struct, got {:?}", item))),
+                            })?;
+                        }
+                    } else {
+                        let list = pointer.init_list(element_size(element), count);
+                        for (idx, item) in items.iter().enumerate() {
+                            self.set_element(&list, idx as u32, element, item, ctx)?;
+                        }
+                    }
+                }
+                (&Type::Interface(..), _) => {
+                    return Err(Error::failed("interfaces cannot have values".to_string()))
+                }
+                (&Type::AnyPointer, _) | (&Type::AnyStruct, _) | (&Type::AnyList, _) |
+                (&Type::Capability, _) | (&Type::Param(..), _) | (&Type::ImplicitParam(_), _) => {
+                    return Err(Error::failed("values of unconstrained types are not supported".to_string()))
+                }
+                _ => return Err(Error::failed(format!("value {:?} does not match its type", expr))),
+            }
+            Ok(())
+        })
+    }
+
+    fn set_element(&self, list: &layout::ListBuilder, idx: u32, typ: &Type, expr: &'a Expr, ctx: Ctx)
+                   -> Result<()>
+    {
+        match lg_size(typ) {
+            None => self.write_pointer(list.get_pointer_element(idx), typ, expr, ctx)?,
+            Some(lg) => {
+                let bits = self.primitive_bits(typ, expr, ctx)?;
+                match lg {
+                    -1 => (),
+                    0 => PrimitiveElement::set(list, idx, bits != 0),
+                    3 => PrimitiveElement::set(list, idx, bits as u8),
+                    4 => PrimitiveElement::set(list, idx, bits as u16),
+                    5 => PrimitiveElement::set(list, idx, bits as u32),
+                    _ => PrimitiveElement::set(list, idx, bits),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn fill_struct(&self, idx: usize, brand: &Brand, builder: layout::StructBuilder,
+                   items: &'a [(Option<String>, Expr)], ctx: Ctx) -> Result<()>
+    {
+        let info = self.struct_info(idx);
+        for &(ref name, ref expr) in items {
+            let name = match *name {
+                Some(ref name) => name,
+                None => return Err(Error::failed("struct fields must be named".to_string())),
+            };
+            let field = match info.fields.iter().find(|f| f.decl.name == *name) {
+                Some(field) => field,
+                None => return Err(Error::failed(format!(
+                    "{} has no field named {}", self.nodes[idx].display_name, name))),
+            };
+            if let Some(discriminant) = field.discriminant {
+                builder.set_data_field::<u16>(info.discriminant_offset as usize, discriminant);
+            }
+            match field.kind {
+                FieldKind::Group(group) => self.with_constant(expr, ctx, |expr, ctx| match *expr {
+                    Expr::Tuple(ref fields) => self.fill_struct(group, brand, builder, fields, ctx),
+                    _ => Err(Error::failed(format!("expected a group for field {}", name))),
+                })?,
+                FieldKind::Slot { ref typ, offset, .. } => {
+                    let typ = substitute(typ, brand);
+                    let offset = offset as usize;
+                    match lg_size(&typ) {
+                        None => self.write_pointer(builder.get_pointer_field(offset), &typ, expr, ctx)?,
+                        Some(lg) => {
+                            let bits = self.primitive_bits(&typ, expr, ctx)? ^
+                                self.default_bits(field, info.scope)?;
+                            match lg {
+                                -1 => (),
+                                0 => builder.set_bool_field(offset, bits != 0),
+                                3 => builder.set_data_field::<u8>(offset, bits as u8),
+                                4 => builder.set_data_field::<u16>(offset, bits as u16),
+                                5 => builder.set_data_field::<u32>(offset, bits as u32),
+                                _ => builder.set_data_field::<u64>(offset, bits),
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn struct_size(&self, idx: usize) -> layout::StructSize {
+        let info = self.struct_info(idx);
+        layout::StructSize { data: info.data_words, pointers: info.pointers }
+    }
+
+    /// Writes `expr`, or the zero value if there is none, to `builder`. Which member of the
+    /// value union is set depends on `declared`; the value itself is written as `actual`, which
+    /// is `declared` with generic parameters substituted.
+    fn write_value(&self, declared: &Type, actual: &Type, expr: Option<&'a Expr>, ctx: Ctx,
+                   mut builder: value::Builder) -> Result<()>
+    {
+        if lg_size(declared).is_some() {
+            let bits = match expr {
+                Some(expr) => self.primitive_bits(declared, expr, ctx)?,
+                None => 0,
+            };
+            match *declared {
+                Type::Void => builder.set_void(()),
+                Type::Bool => builder.set_bool(bits != 0),
+                Type::Int8 => builder.set_int8(bits as i8),
+                Type::Int16 => builder.set_int16(bits as i16),
+                Type::Int32 => builder.set_int32(bits as i32),
+                Type::Int64 => builder.set_int64(bits as i64),
+                Type::UInt8 => builder.set_uint8(bits as u8),
+                Type::UInt16 => builder.set_uint16(bits as u16),
+                Type::UInt32 => builder.set_uint32(bits as u32),
+                Type::UInt64 => builder.set_uint64(bits),
+                Type::Float32 => builder.set_float32(f32::from_bits(bits as u32)),
+                Type::Float64 => builder.set_float64(f64::from_bits(bits)),
+                _ => builder.set_enum(bits as u16),
+            }
+            return Ok(());
+        }
+        match *declared {
+            Type::Text | Type::Data => {
+                let bytes = match expr {
+                    Some(expr) => self.with_constant(expr, ctx, |expr, _| match *expr {
+                        Expr::String(ref bytes) => Ok(&bytes[..]),
+                        Expr::Binary(ref bytes) if declared_is_data(declared) => Ok(&bytes[..]),
+                        _ => Err(Error::failed(format!("value {:?} does not match its type", expr))),
+                    })?,
+                    None => &[],
+                };
+                if let Type::Data = *declared {
+                    builder.set_data(bytes);
+                } else {
+                    match std::str::from_utf8(bytes) {
+                        Ok(text) => builder.set_text(text),
+                        Err(_) => return Err(Error::failed("text is not valid UTF-8".to_string())),
+                    }
+                }
+                return Ok(());
+            }
+            Type::Interface(..) => {
+                builder.set_interface(());
+                return Ok(());
+            }
+            _ => (),
+        }
+        let pointer = match *declared {
+            Type::List(_) => builder.init_list(),
+            Type::Struct(..) => builder.init_struct(),
+            _ => builder.init_any_pointer(),
+        };
+        if let Some(expr) = expr {
+            self.write_pointer(pointer.init_as::<RawPointerBuilder>().0, actual, expr, ctx)?;
+        }
+        Ok(())
+    }
+
+    // ---------------------------------------------------------------------------------------
+    // Output
+
+    fn write_request(&self, requested: &[usize], mut request: code_generator_request::Builder)
+                     -> Result<()>
+    {
+        {
+            let mut nodes = request.reborrow().init_nodes(self.nodes.len() as u32);
+            for idx in 0..self.nodes.len() {
+                self.write_node(idx, nodes.reborrow().get(idx as u32))?;
+            }
+        }
+        let mut files = request.init_requested_files(requested.len() as u32);
+        for (i, &file) in requested.iter().enumerate() {
+            let mut builder = files.reborrow().get(i as u32);
+            builder.set_id(self.nodes[self.file_nodes[file]].id);
+            builder.set_filename(&self.files[file].name);
+            let imports = &self.files[file].imports;
+            let mut list = builder.init_imports(imports.len() as u32);
+            for (j, &(ref name, idx)) in imports.iter().enumerate() {
+                let mut import = list.reborrow().get(j as u32);
+                import.set_id(self.nodes[self.file_nodes[idx]].id);
+                import.set_name(name);
+            }
+        }
+        Ok(())
+    }
+
+    fn write_node(&self, idx: usize, mut builder: node::Builder) -> Result<()> {
+        let node = &self.nodes[idx];
+        builder.set_id(node.id);
+        builder.set_display_name(&node.display_name);
+        builder.set_display_name_prefix_length(node.prefix_len as u32);
+        let scope_id = match (node.kind, node.parent) {
+            (Kind::Params, _) | (_, None) => 0,
+            (_, Some(parent)) => self.nodes[parent].id,
+        };
+        builder.set_scope_id(scope_id);
+        builder.set_is_generic(self.is_generic(idx));
+        {
+            let mut params = builder.reborrow().init_parameters(node.params.len() as u32);
+            for (i, param) in node.params.iter().enumerate() {
+                params.reborrow().get(i as u32).set_name(param);
+            }
+        }
+        {
+            let mut nested = builder.reborrow().init_nested_nodes(node.nested.len() as u32);
+            for (i, &child) in node.nested.iter().enumerate() {
+                let mut entry = nested.reborrow().get(i as u32);
+                entry.set_name(&self.nodes[child].decl.name);
+                entry.set_id(self.nodes[child].id);
+            }
+        }
+        let target = match node.kind {
+            Kind::File => "file",
+            Kind::Struct => "struct",
+            Kind::Enum => "enum",
+            Kind::Interface => "interface",
+            Kind::Const => "const",
+            Kind::Annotation => "annotation",
+            Kind::Group | Kind::Params => "",
+        };
+        if !target.is_empty() {
+            self.write_annotations(idx, node.decl, target, |n| builder.reborrow().init_annotations(n))?;
+        }
+
+        match node.kind {
+            Kind::File => builder.set_file(()),
+            Kind::Struct | Kind::Group | Kind::Params => self.write_struct(idx, builder.init_struct())?,
+            Kind::Enum => {
+                let mut enumerants: Vec<(u16, usize, &Decl)> = Vec::new();
+                for decl in &node.decl.nested {
+                    if let DeclKind::Enumerant = decl.kind {
+                        enumerants.push((decl.ordinal.unwrap_or(0), enumerants.len(), decl));
+                    }
+                }
+                enumerants.sort_by_key(|e| e.0);
+                self.check_ordinals(idx, enumerants.iter().map(|e| (e.0, e.2)))?;
+                let mut list = builder.init_enum().init_enumerants(enumerants.len() as u32);
+                for (i, &(_, code_order, decl)) in enumerants.iter().enumerate() {
+                    let mut enumerant = list.reborrow().get(i as u32);
+                    enumerant.set_name(&decl.name);
+                    enumerant.set_code_order(code_order as u16);
+                    self.write_annotations(idx, decl, "enumerant", |n| enumerant.init_annotations(n))?;
+                }
+            }
+            Kind::Interface => self.write_interface(idx, builder.init_interface())?,
+            Kind::Const => {
+                let value = match node.decl.kind {
+                    DeclKind::Const { ref value, .. } => value,
+                    _ => unreachable!(),
+                };
+                let typ = self.declared_type(idx)?;
+                let mut c = builder.init_const();
+                self.write_type(&typ, c.reborrow().init_type())?;
+                let ctx = scope_ctx(node.parent.unwrap_or(idx));
+                self.evaluating.borrow_mut().insert(idx);
+                let result = self.write_value(&typ, &typ, Some(value), ctx, c.init_value());
+                self.evaluating.borrow_mut().remove(&idx);
+                result.map_err(|e| self.locate(idx, node.decl, e))?;
+            }
+            Kind::Annotation => {
+                let targets = match node.decl.kind {
+                    DeclKind::Annotation { ref targets, .. } => targets,
+                    _ => unreachable!(),
+                };
+                let typ = self.declared_type(idx)?;
+                let mut a = builder.init_annotation();
+                self.write_type(&typ, a.reborrow().init_type())?;
+                for target in targets {
+                    let all = target == "*";
+                    match &target[..] {
+                        "*" | "file" | "const" | "enum" | "enumerant" | "struct" | "field" | "union" |
+                        "group" | "interface" | "method" | "param" | "annotation" => (),
+                        _ => return self.error(idx, node.decl.line, &format!("unknown annotation target {}", target)),
+                    }
+                    if all || target == "file" { a.set_targets_file(true); }
+                    if all || target == "const" { a.set_targets_const(true); }
+                    if all || target == "enum" { a.set_targets_enum(true); }
+                    if all || target == "enumerant" { a.set_targets_enumerant(true); }
+                    if all || target == "struct" { a.set_targets_struct(true); }
+                    if all || target == "field" { a.set_targets_field(true); }
+                    if all || target == "union" { a.set_targets_union(true); }
+                    if all || target == "group" { a.set_targets_group(true); }
+                    if all || target == "interface" { a.set_targets_interface(true); }
+                    if all || target == "method" { a.set_targets_method(true); }
+                    if all || target == "param" { a.set_targets_param(true); }
+                    if all || target == "annotation" { a.set_targets_annotation(true); }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_struct(&self, idx: usize, mut builder: node::struct_::Builder) -> Result<()> {
+        let node = &self.nodes[idx];
+        let info = self.struct_info(idx);
+        builder.set_data_word_count(info.data_words);
+        builder.set_pointer_count(info.pointers);
+        builder.set_preferred_list_encoding(SchemaElementSize::InlineComposite);
+        builder.set_is_group(node.kind == Kind::Group);
+        builder.set_discriminant_count(info.discriminant_count);
+        builder.set_discriminant_offset(info.discriminant_offset);
+        let mut fields = builder.init_fields(info.fields.len() as u32);
+        for (i, field) in info.fields.iter().enumerate() {
+            let mut builder = fields.reborrow().get(i as u32);
+            builder.set_name(&field.decl.name);
+            builder.set_code_order(field.code_order);
+            builder.set_discriminant_value(field.discriminant.unwrap_or(field::NO_DISCRIMINANT));
+            let target = match (&field.decl.kind, node.kind) {
+                (&DeclKind::Union, _) => "union",
+                (&DeclKind::Group, _) => "group",
+                (_, Kind::Params) => "param",
+                _ => "field",
+            };
+            self.write_annotations(info.scope, field.decl, target, |n| builder.reborrow().init_annotations(n))?;
+            match field.kind {
+                FieldKind::Group(group) => {
+                    builder.reborrow().init_ordinal().set_implicit(());
+                    builder.init_group().set_type_id(self.nodes[group].id);
+                }
+                FieldKind::Slot { ref typ, offset, default } => {
+                    builder.reborrow().init_ordinal().set_explicit(field.decl.ordinal.unwrap_or(0));
+                    let mut slot = builder.init_slot();
+                    slot.set_offset(offset);
+                    slot.set_had_explicit_default(default.is_some());
+                    self.write_type(typ, slot.reborrow().init_type())?;
+                    self.write_value(typ, typ, default, scope_ctx(info.scope), slot.init_default_value())
+                        .map_err(|e| self.locate(info.scope, field.decl, e))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_interface(&self, idx: usize, mut builder: node::interface::Builder) -> Result<()> {
+        let node = &self.nodes[idx];
+        let superclasses = match node.decl.kind {
+            DeclKind::Interface { ref superclasses } => superclasses,
+            _ => unreachable!(),
+        };
+        let ctx = scope_ctx(idx);
+        {
+            let mut list = builder.reborrow().init_superclasses(superclasses.len() as u32);
+            for (i, expr) in superclasses.iter().enumerate() {
+                let (n, brand) = self.resolve_node(expr, ctx, Kind::Interface)
+                    .map_err(|e| self.locate(idx, node.decl, e))?;
+                let mut superclass = list.reborrow().get(i as u32);
+                superclass.set_id(self.nodes[n].id);
+                self.write_brand(n, &brand, superclass.init_brand())?;
+            }
+        }
+
+        let mut methods: Vec<(u16, usize, &'a Decl)> = Vec::new();
+        for decl in &node.decl.nested {
+            if let DeclKind::Method { .. } = decl.kind {
+                methods.push((decl.ordinal.unwrap_or(0), methods.len(), decl));
+            }
+        }
+        methods.sort_by_key(|m| m.0);
+        self.check_ordinals(idx, methods.iter().map(|m| (m.0, m.2)))?;
+        let mut list = builder.init_methods(methods.len() as u32);
+        for (i, &(ordinal, code_order, decl)) in methods.iter().enumerate() {
+            let (implicit_params, params, results) = match decl.kind {
+                DeclKind::Method { ref implicit_params, ref params, ref results } => (implicit_params, params, results),
+                _ => unreachable!(),
+            };
+            let mut method = list.reborrow().get(i as u32);
+            method.set_name(&decl.name);
+            method.set_code_order(code_order as u16);
+            {
+                let mut implicit = method.reborrow().init_implicit_parameters(implicit_params.len() as u32);
+                for (j, name) in implicit_params.iter().enumerate() {
+                    implicit.reborrow().get(j as u32).set_name(name);
+                }
+            }
+            let ctx = Ctx { node: idx, implicit: implicit_params };
+            let (id, brand) = self.param_struct(idx, ordinal, false, Some(params), ctx)
+                .map_err(|e| self.locate(idx, decl, e))?;
+            method.set_param_struct_type(self.nodes[id].id);
+            self.write_brand(id, &brand, method.reborrow().init_param_brand())?;
+            let (id, brand) = self.param_struct(idx, ordinal, true, results.as_ref(), ctx)
+                .map_err(|e| self.locate(idx, decl, e))?;
+            method.set_result_struct_type(self.nodes[id].id);
+            self.write_brand(id, &brand, method.reborrow().init_result_brand())?;
+            self.write_annotations(idx, decl, "method", |n| method.init_annotations(n))?;
+        }
+        Ok(())
+    }
+
+    /// The struct, and its brand, used for a method's parameters or results.
+    fn param_struct(&self, interface: usize, ordinal: u16, is_results: bool,
+                    list: Option<&'a ParamList>, ctx: Ctx) -> Result<(usize, Brand)>
+    {
+        match list {
+            Some(&ParamList::Type(ref expr)) => self.resolve_node(expr, ctx, Kind::Struct),
+            _ => {
+                let idx = self.param_structs[&(interface, ordinal, is_results)];
+                let mut brand = self.inherited_brand(interface);
+                if !ctx.implicit.is_empty() {
+                    let params = (0..ctx.implicit.len()).map(|i| Type::ImplicitParam(i as u16)).collect();
+                    brand.insert(0, (idx, Binding::Bind(params)));
+                }
+                Ok((idx, brand))
+            }
+        }
+    }
+
+    fn check_ordinals<'b, I>(&self, idx: usize, ordinals: I) -> Result<()>
+        where I: Iterator<Item = (u16, &'b Decl)>
+    {
+        for (expected, (ordinal, decl)) in ordinals.enumerate() {
+            if ordinal as usize != expected {
+                let message = if (ordinal as usize) < expected {
+                    format!("duplicate ordinal @{}", ordinal)
+                } else {
+                    format!("skipped ordinal @{}; ordinals must be sequential with no holes", expected)
+                };
+                return self.error(idx, decl.line, &message);
+            }
+        }
+        Ok(())
+    }
+
+    fn write_annotations<'b, F>(&self, scope: usize, decl: &'a Decl, target: &str, init: F) -> Result<()>
+        where F: FnOnce(u32) -> ::capnp::struct_list::Builder<'b, annotation::Owned>
+    {
+        let ctx = scope_ctx(scope);
+        let mut applied = Vec::new();
+        for expr in &decl.annotations {
+            let (n, brand, value) = self.resolve_annotation(expr, ctx)
+                .map_err(|e| self.locate(scope, decl, e))?;
+            let targets = match self.nodes[n].decl.kind {
+                DeclKind::Annotation { ref targets, .. } => targets,
+                _ => unreachable!(),
+            };
+            if !targets.iter().any(|t| t == "*" || t == target) {
+                return self.error(scope, decl.line, &format!(
+                    "{} cannot be applied to a {}", self.nodes[n].display_name, target));
+            }
+            applied.push((n, brand, value));
+        }
+
+        let mut list = init(applied.len() as u32);
+        for (i, (n, brand, value)) in applied.into_iter().enumerate() {
+            let mut annotation = list.reborrow().get(i as u32);
+            annotation.set_id(self.nodes[n].id);
+            self.write_brand(n, &brand, annotation.reborrow().init_brand())?;
+            let declared = self.declared_type(n)?;
+            let actual = substitute(&declared, &brand);
+            let result = match value {
+                AnnotationValue::None => {
+                    if let Type::Void = declared {} else {
+                        return self.error(scope, decl.line, &format!(
+                            "{} needs a value", self.nodes[n].display_name));
+                    }
+                    self.write_value(&declared, &actual, None, ctx, annotation.init_value())
+                }
+                AnnotationValue::Expr(expr) =>
+                    self.write_value(&declared, &actual, Some(expr), ctx, annotation.init_value()),
+                AnnotationValue::Fields(items) =>
+                    self.write_fields_value(&declared, &actual, items, ctx, annotation.init_value()),
+            };
+            result.map_err(|e| self.locate(scope, decl, e))?;
+        }
+        Ok(())
+    }
+
+    /// Splits an annotation application such as `$foo(1)` into the annotation and its value.
+    fn resolve_annotation(&self, expr: &'a Expr, ctx: Ctx) -> Result<(usize, Brand, AnnotationValue<'a>)> {
+        if let Expr::Apply(ref base, ref args) = *expr {
+            if let Ok(Entity::Node(n, brand)) = self.resolve(base, ctx) {
+                if self.nodes[n].kind == Kind::Annotation {
+                    let value = match args.len() {
+                        0 => AnnotationValue::None,
+                        1 if args[0].0.is_none() => AnnotationValue::Expr(&args[0].1),
+                        _ if args.iter().all(|arg| arg.0.is_some()) => AnnotationValue::Fields(args),
+                        _ => return Err(Error::failed(format!(
+                            "{} takes a single value, or named struct fields", self.nodes[n].display_name))),
+                    };
+                    return Ok((n, brand, value));
+                }
+            }
+        }
+        let (n, brand) = self.resolve_node(expr, ctx, Kind::Annotation)?;
+        Ok((n, brand, AnnotationValue::None))
+    }
+
+    /// Writes the struct value of an annotation that was given as named fields.
+    fn write_fields_value(&self, declared: &Type, actual: &Type, items: &'a [(Option<String>, Expr)],
+                          ctx: Ctx, builder: value::Builder) -> Result<()>
+    {
+        match *actual {
+            Type::Struct(n, ref brand) => {
+                let pointer = match *declared {
+                    Type::Struct(..) => builder.init_struct(),
+                    _ => builder.init_any_pointer(),
+                };
+                let size = self.struct_size(n);
+                let struct_builder = pointer.init_as::<RawPointerBuilder>().0.init_struct(size);
+                self.fill_struct(n, brand, struct_builder, items, ctx)
+            }
+            _ => Err(Error::failed(format!("named fields given for a value of type {:?}", actual))),
+        }
+    }
+
+    fn write_type(&self, typ: &Type, mut builder: type_::Builder) -> Result<()> {
+        match *typ {
+            Type::Void => builder.set_void(()),
+            Type::Bool => builder.set_bool(()),
+            Type::Int8 => builder.set_int8(()),
+            Type::Int16 => builder.set_int16(()),
+            Type::Int32 => builder.set_int32(()),
+            Type::Int64 => builder.set_int64(()),
+            Type::UInt8 => builder.set_uint8(()),
+            Type::UInt16 => builder.set_uint16(()),
+            Type::UInt32 => builder.set_uint32(()),
+            Type::UInt64 => builder.set_uint64(()),
+            Type::Float32 => builder.set_float32(()),
+            Type::Float64 => builder.set_float64(()),
+            Type::Text => builder.set_text(()),
+            Type::Data => builder.set_data(()),
+            Type::List(ref element) => self.write_type(element, builder.init_list().init_element_type())?,
+            Type::Enum(n, ref brand) => {
+                let mut e = builder.init_enum();
+                e.set_type_id(self.nodes[n].id);
+                self.write_brand(n, brand, e.init_brand())?;
+            }
+            Type::Struct(n, ref brand) => {
+                let mut s = builder.init_struct();
+                s.set_type_id(self.nodes[n].id);
+                self.write_brand(n, brand, s.init_brand())?;
+            }
+            Type::Interface(n, ref brand) => {
+                let mut i = builder.init_interface();
+                i.set_type_id(self.nodes[n].id);
+                self.write_brand(n, brand, i.init_brand())?;
+            }
+            Type::AnyPointer => builder.init_any_pointer().init_unconstrained().set_any_kind(()),
+            Type::AnyStruct => builder.init_any_pointer().init_unconstrained().set_struct(()),
+            Type::AnyList => builder.init_any_pointer().init_unconstrained().set_list(()),
+            Type::Capability => builder.init_any_pointer().init_unconstrained().set_capability(()),
+            Type::Param(scope, index) => {
+                let mut p = builder.init_any_pointer().init_parameter();
+                p.set_scope_id(self.nodes[scope].id);
+                p.set_parameter_index(index);
+            }
+            Type::ImplicitParam(index) => {
+                builder.init_any_pointer().init_implicit_method_parameter().set_parameter_index(index);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the scopes of `brand` that apply to `target`, innermost first.
+    fn write_brand(&self, target: usize, brand: &Brand, builder: brand::Builder) -> Result<()> {
+        let mut scopes = Vec::new();
+        let mut current = Some(target);
+        while let Some(n) = current {
+            if !self.nodes[n].params.is_empty() {
+                if let Some(&(_, ref binding)) = brand.iter().find(|&&(s, _)| s == n) {
+                    scopes.push((n, binding));
+                }
+            }
+            current = self.nodes[n].parent;
+        }
+        if scopes.is_empty() {
+            return Ok(());
+        }
+        let mut list = builder.init_scopes(scopes.len() as u32);
+        for (i, &(n, binding)) in scopes.iter().enumerate() {
+            let mut scope = list.reborrow().get(i as u32);
+            scope.set_scope_id(self.nodes[n].id);
+            match *binding {
+                Binding::Inherit => scope.set_inherit(()),
+                Binding::Bind(ref types) => {
+                    let mut bindings = scope.init_bind(types.len() as u32);
+                    for (j, typ) in types.iter().enumerate() {
+                        self.write_type(typ, bindings.reborrow().get(j as u32).init_type())?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn scope_ctx(node: usize) -> Ctx<'static> {
+    Ctx { node: node, implicit: &[] }
+}
+
+/// Replaces the generic parameters in `typ` that `brand` binds.
+fn substitute(typ: &Type, brand: &Brand) -> Type {
+    match *typ {
+        Type::Param(scope, index) => match brand.iter().find(|&&(s, _)| s == scope) {
+            Some(&(_, Binding::Bind(ref types))) => types[index as usize].clone(),
+            Some(&(_, Binding::Inherit)) => typ.clone(),
+            None => Type::AnyPointer,
+        },
+        Type::List(ref element) => Type::List(Box::new(substitute(element, brand))),
+        Type::Enum(n, ref b) => Type::Enum(n, substitute_brand(b, brand)),
+        Type::Struct(n, ref b) => Type::Struct(n, substitute_brand(b, brand)),
+        Type::Interface(n, ref b) => Type::Interface(n, substitute_brand(b, brand)),
+        _ => typ.clone(),
+    }
+}
+
+fn substitute_brand(inner: &Brand, outer: &Brand) -> Brand {
+    inner.iter().filter_map(|&(scope, ref binding)| match *binding {
+        Binding::Inherit => outer.iter().find(|&&(s, _)| s == scope).cloned(),
+        Binding::Bind(ref types) => {
+            Some((scope, Binding::Bind(types.iter().map(|t| substitute(t, outer)).collect())))
+        }
+    }).collect()
+}
+
+/// The log2 of the size in bits of a value of type `typ`, -1 for `Void`, or `None` for
+/// pointers.
+fn lg_size(typ: &Type) -> Option<i32> {
+    match *typ {
+        Type::Void => Some(-1),
+        Type::Bool => Some(0),
+        Type::Int8 | Type::UInt8 => Some(3),
+        Type::Int16 | Type::UInt16 | Type::Enum(..) => Some(4),
+        Type::Int32 | Type::UInt32 | Type::Float32 => Some(5),
+        Type::Int64 | Type::UInt64 | Type::Float64 => Some(6),
+        _ => None,
+    }
+}
+
+fn element_size(typ: &Type) -> ElementSize {
+    match lg_size(typ) {
+        Some(-1) => ElementSize::Void,
+        Some(0) => ElementSize::Bit,
+        Some(3) => ElementSize::Byte,
+        Some(4) => ElementSize::TwoBytes,
+        Some(5) => ElementSize::FourBytes,
+        Some(_) => ElementSize::EightBytes,
+        None => match *typ {
+            Type::Struct(..) => ElementSize::InlineComposite,
+            _ => ElementSize::Pointer,
+        },
+    }
+}
+
+fn declared_is_data(typ: &Type) -> bool {
+    match *typ {
+        Type::Data => true,
+        _ => false,
+    }
+}
+
+fn integer(expr: &Expr, min: i128, max: i128) -> Result<i128> {
+    match *expr {
+        Expr::Int(negative, n) => {
+            let n = if negative { -(n as i128) } else { n as i128 };
+            if n < min || n > max {
+                Err(Error::failed(format!("integer {} is out of range", n)))
+            } else {
+                Ok(n)
+            }
+        }
+        _ => Err(Error::failed(format!("expected an integer, got {:?}", expr))),
+    }
+}
+
+fn float_bits(typ: &Type, n: f64) -> u64 {
+    match *typ {
+        Type::Float32 => (n as f32).to_bits() as u64,
+        _ => n.to_bits(),
+    }
+}
+
+// -------------------------------------------------------------------------------------------
+// Struct layout, following the C++ compiler's so that the same schema always has the same
+// layout.
+
+/// Holes in a data section: at most one of each size from 1 to 32 bits. `holes[n]` is the
+/// offset of the hole of size 2^n in multiples of its size, or 0 if there is none; no hole
+/// can start at offset 0, because the first field is always allocated there.
+#[derive(Clone, Copy, Default)]
+struct HoleSet {
+    holes: [u32; 6],
+}
+
+impl HoleSet {
+    fn try_allocate(&mut self, lg: u32) -> Option<u32> {
+        if lg >= 6 {
+            None
+        } else if self.holes[lg as usize] != 0 {
+            let result = self.holes[lg as usize];
+            self.holes[lg as usize] = 0;
+            Some(result)
+        } else {
+            let next = self.try_allocate(lg + 1)?;
+            let result = next * 2;
+            self.holes[lg as usize] = result + 1;
+            Some(result)
+        }
+    }
+
+    /// Adds holes of sizes from 2^`lg` up to 2^(`limit` - 1), the first at `offset`, after an
+    /// allocation of size 2^`lg` at the start of a new space of size 2^`limit`.
+    fn add_holes_at_end(&mut self, mut lg: u32, mut offset: u32, limit: u32) {
+        while lg < limit {
+            self.holes[lg as usize] = offset;
+            lg += 1;
+            offset = (offset + 1) / 2;
+        }
+    }
+
+    /// Tries to grow the value at `old_offset` to 2^`factor` times its size by merging it with
+    /// the holes after it.
+    fn try_expand(&mut self, old_lg: u32, old_offset: u32, factor: u32) -> bool {
+        if factor == 0 {
+            return true;
+        }
+        if old_lg >= 6 || self.holes[old_lg as usize] != old_offset + 1 {
+            return false;
+        }
+        if self.try_expand(old_lg + 1, old_offset >> 1, factor - 1) {
+            self.holes[old_lg as usize] = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn smallest_at_least(&self, lg: u32) -> Option<u32> {
+        (lg..6).find(|&i| self.holes[i as usize] != 0)
+    }
+}
+
+/// Where fields are allocated: the struct itself, or a group of a union.
+#[derive(Clone, Copy)]
+enum Scope {
+    Top,
+    Group(usize),
+}
+
+#[derive(Clone, Copy)]
+struct DataLocation {
+    lg: u32,
+    offset: u32,
+}
+
+struct UnionLayout {
+    parent: Scope,
+    group_count: u32,
+    discriminant_offset: Option<u32>,
+    data_locations: Vec<DataLocation>,
+    pointer_locations: Vec<u32>,
+}
+
+/// How much of one of its union's data locations a group uses.
+#[derive(Clone, Copy, Default)]
+struct Usage {
+    used: bool,
+    lg_used: u32,
+    holes: HoleSet,
+}
+
+struct GroupLayout {
+    union: usize,
+    usage: Vec<Usage>,
+    pointer_usage: usize,
+    has_members: bool,
+}
+
+struct Layout {
+    data_words: u32,
+    pointers: u32,
+    holes: HoleSet,
+    unions: Vec<UnionLayout>,
+    groups: Vec<GroupLayout>,
+}
+
+impl Layout {
+    fn new() -> Layout {
+        Layout { data_words: 0, pointers: 0, holes: HoleSet::default(), unions: Vec::new(), groups: Vec::new() }
+    }
+
+    fn new_union(&mut self, parent: Scope) -> usize {
+        self.unions.push(UnionLayout {
+            parent: parent,
+            group_count: 0,
+            discriminant_offset: None,
+            data_locations: Vec::new(),
+            pointer_locations: Vec::new(),
+        });
+        self.unions.len() - 1
+    }
+
+    fn new_group(&mut self, union: usize) -> usize {
+        self.groups.push(GroupLayout { union: union, usage: Vec::new(), pointer_usage: 0, has_members: false });
+        self.groups.len() - 1
+    }
+
+    fn add_void(&mut self, scope: Scope) {
+        if let Scope::Group(g) = scope {
+            self.add_group_member(g);
+            // The union's discriminant is allocated when its second member is added, even if
+            // that member takes no space, so tell enclosing unions too.
+            let parent = self.unions[self.groups[g].union].parent;
+            self.add_void(parent);
+        }
+    }
+
+    fn add_data(&mut self, scope: Scope, lg: u32) -> u32 {
+        match scope {
+            Scope::Top => match self.holes.try_allocate(lg) {
+                Some(offset) => offset,
+                None => {
+                    let offset = self.data_words << (6 - lg);
+                    self.data_words += 1;
+                    self.holes.add_holes_at_end(lg, offset + 1, 6);
+                    offset
+                }
+            },
+            Scope::Group(g) => self.group_add_data(g, lg),
+        }
+    }
+
+    fn add_pointer(&mut self, scope: Scope) -> u32 {
+        match scope {
+            Scope::Top => {
+                self.pointers += 1;
+                self.pointers - 1
+            }
+            Scope::Group(g) => {
+                self.add_group_member(g);
+                let u = self.groups[g].union;
+                let used = self.groups[g].pointer_usage;
+                self.groups[g].pointer_usage += 1;
+                if used < self.unions[u].pointer_locations.len() {
+                    self.unions[u].pointer_locations[used]
+                } else {
+                    let parent = self.unions[u].parent;
+                    let offset = self.add_pointer(parent);
+                    self.unions[u].pointer_locations.push(offset);
+                    offset
+                }
+            }
+        }
+    }
+
+    fn try_expand_data(&mut self, scope: Scope, old_lg: u32, old_offset: u32, factor: u32) -> bool {
+        match scope {
+            Scope::Top => self.holes.try_expand(old_lg, old_offset, factor),
+            Scope::Group(g) => {
+                let u = self.groups[g].union;
+                for i in 0..self.groups[g].usage.len() {
+                    let location = self.unions[u].data_locations[i];
+                    if location.lg >= old_lg && old_offset >> (location.lg - old_lg) == location.offset {
+                        let local_offset = old_offset - (location.offset << (location.lg - old_lg));
+                        return self.usage_try_expand(g, i, old_lg, local_offset, factor);
+                    }
+                }
+                false
+            }
+        }
+    }
+
+    fn add_group_member(&mut self, g: usize) {
+        if !self.groups[g].has_members {
+            self.groups[g].has_members = true;
+            let u = self.groups[g].union;
+            self.unions[u].group_count += 1;
+            if self.unions[u].group_count == 2 {
+                self.add_discriminant(u);
+            }
+        }
+    }
+
+    fn add_discriminant(&mut self, u: usize) -> bool {
+        if self.unions[u].discriminant_offset.is_some() {
+            return false;
+        }
+        let parent = self.unions[u].parent;
+        let offset = self.add_data(parent, 4);
+        self.unions[u].discriminant_offset = Some(offset);
+        true
+    }
+
+    fn location_try_expand_to(&mut self, u: usize, i: usize, new_lg: u32) -> bool {
+        let location = self.unions[u].data_locations[i];
+        if new_lg <= location.lg {
+            return true;
+        }
+        let parent = self.unions[u].parent;
+        if self.try_expand_data(parent, location.lg, location.offset, new_lg - location.lg) {
+            let location = &mut self.unions[u].data_locations[i];
+            location.offset >>= new_lg - location.lg;
+            location.lg = new_lg;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn group_add_data(&mut self, g: usize, lg: u32) -> u32 {
+        self.add_group_member(g);
+        let u = self.groups[g].union;
+
+        let mut best: Option<(u32, usize)> = None;
+        for i in 0..self.unions[u].data_locations.len() {
+            if self.groups[g].usage.len() == i {
+                self.groups[g].usage.push(Usage::default());
+            }
+            let usage = self.groups[g].usage[i];
+            let location = self.unions[u].data_locations[i];
+            if let Some(hole) = smallest_hole_at_least(&usage, location, lg) {
+                if best.map_or(true, |(size, _)| hole < size) {
+                    best = Some((hole, i));
+                }
+            }
+        }
+        if let Some((_, i)) = best {
+            return self.allocate_from_hole(g, i, lg);
+        }
+
+        // There is no hole big enough anywhere, so try to expand a location.
+        for i in 0..self.unions[u].data_locations.len() {
+            if let Some(offset) = self.try_allocate_by_expanding(g, i, lg) {
+                return offset;
+            }
+        }
+
+        let parent = self.unions[u].parent;
+        let offset = self.add_data(parent, lg);
+        self.unions[u].data_locations.push(DataLocation { lg: lg, offset: offset });
+        self.groups[g].usage.push(Usage { used: true, lg_used: lg, holes: HoleSet::default() });
+        offset
+    }
+
+    fn allocate_from_hole(&mut self, g: usize, i: usize, lg: u32) -> u32 {
+        let location = self.unions[self.groups[g].union].data_locations[i];
+        let base = location.offset << (location.lg - lg);
+        let usage = &mut self.groups[g].usage[i];
+        if !usage.used {
+            usage.used = true;
+            usage.lg_used = lg;
+            base
+        } else if lg >= usage.lg_used {
+            // Double the used space, and allocate the second half.
+            usage.holes.add_holes_at_end(usage.lg_used, 1, lg);
+            usage.lg_used = lg + 1;
+            base + 1
+        } else if let Some(offset) = usage.holes.try_allocate(lg) {
+            base + offset
+        } else {
+            let offset = 1 << (usage.lg_used - lg);
+            usage.holes.add_holes_at_end(lg, offset + 1, usage.lg_used);
+            usage.lg_used += 1;
+            base + offset
+        }
+    }
+
+    fn try_allocate_by_expanding(&mut self, g: usize, i: usize, lg: u32) -> Option<u32> {
+        let u = self.groups[g].union;
+        let usage = self.groups[g].usage[i];
+        if !usage.used {
+            if !self.location_try_expand_to(u, i, lg) {
+                return None;
+            }
+            let usage = &mut self.groups[g].usage[i];
+            usage.used = true;
+            usage.lg_used = lg;
+            let location = self.unions[u].data_locations[i];
+            Some(location.offset << (location.lg - lg))
+        } else {
+            let new_lg = std::cmp::max(usage.lg_used, lg) + 1;
+            if !self.usage_try_expand_usage(g, i, new_lg, true) {
+                return None;
+            }
+            let offset = self.groups[g].usage[i].holes.try_allocate(lg)?;
+            let location = self.unions[u].data_locations[i];
+            Some((location.offset << (location.lg - lg)) + offset)
+        }
+    }
+
+    fn usage_try_expand(&mut self, g: usize, i: usize, old_lg: u32, old_offset: u32, factor: u32) -> bool {
+        let usage = self.groups[g].usage[i];
+        if old_offset == 0 && usage.lg_used == old_lg {
+            // The value takes up all of the used space, so the used space itself can grow.
+            self.usage_try_expand_usage(g, i, old_lg + factor, false)
+        } else {
+            self.groups[g].usage[i].holes.try_expand(old_lg, old_offset, factor)
+        }
+    }
+
+    fn usage_try_expand_usage(&mut self, g: usize, i: usize, desired: u32, new_holes: bool) -> bool {
+        let u = self.groups[g].union;
+        if desired > self.unions[u].data_locations[i].lg && !self.location_try_expand_to(u, i, desired) {
+            return false;
+        }
+        let usage = &mut self.groups[g].usage[i];
+        if new_holes {
+            usage.holes.add_holes_at_end(usage.lg_used, 1, desired);
+        }
+        usage.lg_used = desired;
+        true
+    }
+}
+
+fn smallest_hole_at_least(usage: &Usage, location: DataLocation, lg: u32) -> Option<u32> {
+    if !usage.used {
+        if lg <= location.lg { Some(location.lg) } else { None }
+    } else if lg >= usage.lg_used {
+        if lg < location.lg { Some(lg) } else { None }
+    } else if let Some(hole) = usage.holes.smallest_at_least(lg) {
+        Some(hole)
+    } else if usage.lg_used < location.lg {
+        Some(usage.lg_used)
+    } else {
+        None
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum MemberKind {
+    Root,
+    Field,
+    Union,
+    Group,
+}
+
+struct MemberInfo<'a> {
+    parent: Option<usize>,
+    decl: &'a Decl,
+    kind: MemberKind,
+    code_order: u16,
+    is_in_union: bool,
+    /// Where a field is allocated.
+    scope: Scope,
+    /// The union of a named union, or the unnamed union of a struct or group.
+    union: Option<usize>,
+    /// The node of the root struct or of a group.
+    node: Option<usize>,
+    /// The position of the member's field in its parent's list of fields. Fields are added to
+    /// the list in ordinal order, so this is only known once the member is first needed.
+    index: Option<u16>,
+    children: Vec<usize>,
+    discriminant: Option<u16>,
+    discriminant_count: u16,
+    typ: Option<Type>,
+    offset: u32,
+}
+
+struct StructTranslator<'c, 'a> {
+    compiler: &'c Compiler<'a>,
+    layout: Layout,
+    members: Vec<MemberInfo<'a>>,
+    by_ordinal: BTreeMap<u16, Vec<usize>>,
+    /// Nodes for groups, which will be added to the compiler once the struct is done.
+    groups: Vec<Node<'a>>,
+}
+
+impl <'c, 'a> StructTranslator<'c, 'a> {
+    fn add_member(&mut self, parent: Option<usize>, decl: &'a Decl, kind: MemberKind, code_order: u16,
+                  is_in_union: bool, scope: Scope) -> usize
+    {
+        self.members.push(MemberInfo {
+            parent: parent,
+            decl: decl,
+            kind: kind,
+            code_order: code_order,
+            is_in_union: is_in_union,
+            scope: scope,
+            union: None,
+            node: None,
+            index: None,
+            children: Vec::new(),
+            discriminant: None,
+            discriminant_count: 0,
+            typ: None,
+            offset: 0,
+        });
+        self.members.len() - 1
+    }
+
+    fn add_group(&mut self, parent: usize, decl: &'a Decl, kind: MemberKind, code_order: u16,
+                 is_in_union: bool) -> usize
+    {
+        let m = self.add_member(Some(parent), decl, kind, code_order, is_in_union, Scope::Top);
+        let parent_node = self.members[parent].node.expect("parent has a node");
+        let base = self.compiler.nodes.len();
+        let (parent_name, file) = if parent_node < base {
+            let node = &self.compiler.nodes[parent_node];
+            (node.display_name.clone(), node.file)
+        } else {
+            let node = &self.groups[parent_node - base];
+            (node.display_name.clone(), node.file)
+        };
+        let display_name = format!("{}.{}", parent_name, decl.name);
+        self.groups.push(Node {
+            id: 0,
+            kind: Kind::Group,
+            decl: decl,
+            body: &decl.nested,
+            parent: Some(parent_node),
+            file: file,
+            prefix_len: display_name.len() - decl.name.len(),
+            display_name: display_name,
+            params: Vec::new(),
+            members: HashMap::new(),
+            nested: Vec::new(),
+            struct_info: None,
+        });
+        self.members[m].node = Some(base + self.groups.len() - 1);
+        m
+    }
+
+    fn add_ordinal(&mut self, ordinal: u16, member: usize) {
+        self.by_ordinal.entry(ordinal).or_insert_with(Vec::new).push(member);
+    }
+
+    fn error<T>(&self, decl: &Decl, message: &str) -> Result<T> {
+        let root = self.members[0].node.expect("root has a node");
+        self.compiler.error(root, decl.line, message)
+    }
+
+    fn traverse_top_or_group(&mut self, parent: usize, body: &'a [Decl], scope: Scope) -> Result<()> {
+        let mut code_order = 0;
+        for decl in body {
+            match decl.kind {
+                DeclKind::Field { .. } => {
+                    let m = self.add_member(Some(parent), decl, MemberKind::Field, code_order, false, scope);
+                    code_order += 1;
+                    self.add_ordinal(decl.ordinal.unwrap_or(0), m);
+                }
+                DeclKind::Union => {
+                    let union = self.layout.new_union(scope);
+                    let member = if decl.name.is_empty() {
+                        if self.members[parent].union.is_some() {
+                            return self.error(decl, "a struct can only have one unnamed union");
+                        }
+                        self.members[parent].union = Some(union);
+                        self.traverse_union(decl, parent, union, &mut code_order)?;
+                        parent
+                    } else {
+                        let m = self.add_group(parent, decl, MemberKind::Union, code_order, false);
+                        code_order += 1;
+                        self.members[m].union = Some(union);
+                        let mut sub_code_order = 0;
+                        self.traverse_union(decl, m, union, &mut sub_code_order)?;
+                        m
+                    };
+                    if let Some(ordinal) = decl.ordinal {
+                        self.add_ordinal(ordinal, member);
+                    }
+                }
+                DeclKind::Group => {
+                    let m = self.add_group(parent, decl, MemberKind::Group, code_order, false);
+                    code_order += 1;
+                    self.traverse_group(m, &decl.nested, scope)?;
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn traverse_group(&mut self, parent: usize, body: &'a [Decl], scope: Scope) -> Result<()> {
+        if body.is_empty() {
+            return self.error(self.members[parent].decl, "a group must have at least one member");
+        }
+        self.traverse_top_or_group(parent, body, scope)
+    }
+
+    fn traverse_union(&mut self, decl: &'a Decl, parent: usize, union: usize, code_order: &mut u16)
+                      -> Result<()>
+    {
+        if decl.nested.len() < 2 {
+            return self.error(decl, "a union must have at least two members");
+        }
+        for member in &decl.nested {
+            match member.kind {
+                DeclKind::Field { .. } => {
+                    // Each member of a union is laid out as if it was in a group of its own.
+                    let group = self.layout.new_group(union);
+                    let m = self.add_member(Some(parent), member, MemberKind::Field, *code_order, true,
+                                            Scope::Group(group));
+                    *code_order += 1;
+                    self.add_ordinal(member.ordinal.unwrap_or(0), m);
+                }
+                DeclKind::Union => {
+                    if member.name.is_empty() {
+                        return self.error(member, "unions cannot contain unnamed unions");
+                    }
+                    let group = self.layout.new_group(union);
+                    let inner = self.layout.new_union(Scope::Group(group));
+                    let m = self.add_group(parent, member, MemberKind::Union, *code_order, true);
+                    *code_order += 1;
+                    self.members[m].union = Some(inner);
+                    let mut sub_code_order = 0;
+                    self.traverse_union(member, m, inner, &mut sub_code_order)?;
+                    if let Some(ordinal) = member.ordinal {
+                        self.add_ordinal(ordinal, m);
+                    }
+                }
+                DeclKind::Group => {
+                    let group = self.layout.new_group(union);
+                    let m = self.add_group(parent, member, MemberKind::Group, *code_order, true);
+                    *code_order += 1;
+                    self.traverse_group(m, &member.nested, Scope::Group(group))?;
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds `m`'s field to its parent's list of fields, if that has not happened yet.
+    fn touch(&mut self, m: usize) {
+        if self.members[m].index.is_some() {
+            return;
+        }
+        let parent = match self.members[m].parent {
+            Some(parent) => parent,
+            None => return,
+        };
+        self.touch(parent);
+        self.members[m].index = Some(self.members[parent].children.len() as u16);
+        self.members[parent].children.push(m);
+        if self.members[m].is_in_union {
+            self.members[m].discriminant = Some(self.members[parent].discriminant_count);
+            self.members[parent].discriminant_count += 1;
+        }
+    }
+
+    fn finish(mut self, root_node: usize) -> Result<(Vec<(usize, StructInfo<'a>)>, Vec<Node<'a>>)> {
+        let compiler = self.compiler;
+        let ctx = scope_ctx(root_node);
+
+        let by_ordinal = std::mem::replace(&mut self.by_ordinal, BTreeMap::new());
+        for (expected, (&ordinal, members)) in by_ordinal.iter().enumerate() {
+            let decl = self.members[members[0]].decl;
+            if members.len() > 1 {
+                return self.error(self.members[members[1]].decl, &format!("duplicate ordinal @{}", ordinal));
+            }
+            if ordinal as usize != expected {
+                return self.error(decl, &format!(
+                    "skipped ordinal @{}; ordinals must be sequential with no holes", expected));
+            }
+            let m = members[0];
+            match self.members[m].kind {
+                MemberKind::Field => {
+                    self.touch(m);
+                    let typ = match decl.kind {
+                        DeclKind::Field { ref typ, .. } => {
+                            compiler.resolve_type(typ, ctx).map_err(|e| compiler.locate(root_node, decl, e))?
+                        }
+                        _ => unreachable!(),
+                    };
+                    let scope = self.members[m].scope;
+                    let offset = match lg_size(&typ) {
+                        None => self.layout.add_pointer(scope),
+                        Some(-1) => {
+                            self.layout.add_void(scope);
+                            0
+                        }
+                        Some(lg) => self.layout.add_data(scope, lg as u32),
+                    };
+                    self.members[m].typ = Some(typ);
+                    self.members[m].offset = offset;
+                }
+                _ => {
+                    let union = self.members[m].union.expect("union member has a union");
+                    if !self.layout.add_discriminant(union) {
+                        return self.error(decl, "a union's ordinal, if specified, must be greater than \
+                                                 no more than one of its members' ordinals");
+                    }
+                }
+            }
+        }
+
+        // Allocate discriminants that have not been allocated yet, and assign group IDs. Groups
+        // come after their parents in `members`, so their parents' IDs are known by then.
+        let base = compiler.nodes.len();
+        for m in 0..self.members.len() {
+            if let Some(union) = self.members[m].union {
+                self.layout.add_discriminant(union);
+            }
+            if self.members[m].kind == MemberKind::Group || self.members[m].kind == MemberKind::Union {
+                if self.members[m].index.is_none() {
+                    return self.error(self.members[m].decl, "a group must have at least one member");
+                }
+                let parent = self.members[m].parent.expect("group has a parent");
+                let parent_node = self.members[parent].node.expect("parent has a node");
+                let parent_id = if parent_node < base {
+                    compiler.nodes[parent_node].id
+                } else {
+                    self.groups[parent_node - base].id
+                };
+                let node = self.members[m].node.expect("group has a node");
+                self.groups[node - base].id = generate_group_id(parent_id, self.members[m].index.unwrap_or(0));
+            }
+        }
+
+        let mut infos = Vec::new();
+        for m in 0..self.members.len() {
+            let node = match self.members[m].node {
+                Some(node) => node,
+                None => continue,
+            };
+            let discriminant_offset = match self.members[m].union {
+                Some(union) => self.layout.unions[union].discriminant_offset.unwrap_or(0),
+                None => 0,
+            };
+            let mut fields = Vec::new();
+            for &child in &self.members[m].children {
+                let member = &self.members[child];
+                let kind = match member.kind {
+                    MemberKind::Field => {
+                        let default = match member.decl.kind {
+                            DeclKind::Field { ref default, .. } => default.as_ref(),
+                            _ => None,
+                        };
+                        FieldKind::Slot {
+                            typ: member.typ.clone().expect("field has a type"),
+                            offset: member.offset,
+                            default: default,
+                        }
+                    }
+                    _ => FieldKind::Group(member.node.expect("group has a node")),
+                };
+                fields.push(FieldInfo {
+                    decl: member.decl,
+                    code_order: member.code_order,
+                    discriminant: member.discriminant,
+                    kind: kind,
+                });
+            }
+            infos.push((node, StructInfo {
+                data_words: self.layout.data_words as u16,
+                pointers: self.layout.pointers as u16,
+                discriminant_count: self.members[m].discriminant_count,
+                discriminant_offset: discriminant_offset,
+                scope: root_node,
+                fields: fields,
+            }));
+        }
+        Ok((infos, self.groups))
+    }
+}
+
+// -------------------------------------------------------------------------------------------
+// IDs
+
+/// The ID of a node named `name` declared in the node `parent_id`, when it does not declare
+/// an ID itself.
+pub fn generate_child_id(parent_id: u64, name: &str) -> u64 {
+    let mut bytes = parent_id.to_le_bytes().to_vec();
+    bytes.extend_from_slice(name.as_bytes());
+    id_from_digest(&md5(&bytes))
+}
+
+/// The ID of the group or named union that is the `index`th field of `parent_id`.
+pub fn generate_group_id(parent_id: u64, index: u16) -> u64 {
+    let mut bytes = parent_id.to_le_bytes().to_vec();
+    bytes.extend_from_slice(&index.to_le_bytes());
+    id_from_digest(&md5(&bytes))
+}
+
+/// The ID of the parameter or result struct of method `ordinal` of the interface `parent_id`.
+pub fn generate_method_params_id(parent_id: u64, ordinal: u16, is_results: bool) -> u64 {
+    let mut bytes = parent_id.to_le_bytes().to_vec();
+    bytes.extend_from_slice(&ordinal.to_le_bytes());
+    bytes.push(is_results as u8);
+    id_from_digest(&md5(&bytes))
+}
+
+fn id_from_digest(digest: &[u8; 16]) -> u64 {
+    let mut result = 0u64;
+    for &b in &digest[..8] {
+        result = (result << 8) | b as u64;
+    }
+    result | (1 << 63)
+}
+
+fn md5(data: &[u8]) -> [u8; 16] {
+    const SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+    let mut k = [0u32; 64];
+    for (i, k) in k.iter_mut().enumerate() {
+        *k = ((i as f64 + 1.0).sin().abs() * 4294967296.0) as u32;
+    }
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    for chunk in message.chunks(64) {
+        let mut m = [0u32; 16];
+        for (i, word) in m.iter_mut().enumerate() {
+            *word = u32::from_le_bytes([chunk[4 * i], chunk[4 * i + 1], chunk[4 * i + 2], chunk[4 * i + 3]]);
+        }
+        let (mut a, mut b, mut c, mut d) = (state[0], state[1], state[2], state[3]);
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f.wrapping_add(a).wrapping_add(k[i]).wrapping_add(m[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(SHIFTS[(i / 16) * 4 + i % 4]));
+        }
+        state[0] = state[0].wrapping_add(a);
+        state[1] = state[1].wrapping_add(b);
+        state[2] = state[2].wrapping_add(c);
+        state[3] = state[3].wrapping_add(d);
+    }
+
+    let mut digest = [0u8; 16];
+    for (i, word) in state.iter().enumerate() {
+        digest[4 * i..4 * i + 4].copy_from_slice(&word.to_le_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use capnp::Result;
+    use crate::schema_capnp::{field, node, value};
+    use crate::schema_loader::SchemaLoader;
+    use super::{compile_with, generate_child_id, generate_group_id, md5};
+
+    fn compile_text(text: &str) -> Result<SchemaLoader> {
+        let text = text.to_string();
+        let message = compile_with(&[PathBuf::from("foo.capnp")], &[], &[], &move |path: &Path| {
+            if path == Path::new("foo.capnp") {
+                Ok(text.clone())
+            } else {
+                Err(::capnp::Error::failed("no such file".to_string()))
+            }
+        })?;
+        let mut loader = SchemaLoader::new();
+        loader.load_request(message.into_reader())?;
+        Ok(loader)
+    }
+
+    /// The `(name, discriminant, offset)` of each slot of the struct `name`.
+    fn slots(loader: &SchemaLoader, name: &str) -> Vec<(String, u16, u32)> {
+        let struct_ = match loader.find(name).unwrap().which().unwrap() {
+            node::Struct(s) => s,
+            _ => panic!("not a struct"),
+        };
+        struct_.get_fields().unwrap().iter().filter_map(|field| match field.which().unwrap() {
+            field::Slot(slot) => Some((
+                field.get_name().unwrap().to_string(), field.get_discriminant_value(), slot.get_offset())),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn md5_digest() {
+        let hex = |digest: [u8; 16]| digest.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        assert_eq!(hex(md5(b"")), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(hex(md5(b"The quick brown fox jumps over the lazy dog")),
+                   "9e107d9d372bb6826bd81d3542a419d6");
+    }
+
+    #[test]
+    fn ids() {
+        // rpc.capnp:Message and schema.capnp:Node.struct.
+        assert_eq!(generate_child_id(0xb312981b2552a250, "Message"), 0x91b79f1f808db032);
+        assert_eq!(generate_group_id(0xe682ab4cf923a417, 7), 0x9ea0b19b37fb4435);
+    }
+
+    #[test]
+    fn union_layout() {
+        let loader = compile_text("
+            @0xd6f1b1e1f6b1c9a5;
+            struct Foo {
+              a @0 :UInt32;
+              union {
+                b @1 :UInt16;
+                c @2 :UInt64;
+              }
+              d @3 :Text;
+            }").unwrap();
+        assert_eq!(slots(&loader, "Foo"), vec![
+            ("a".to_string(), 0xffff, 0),
+            ("b".to_string(), 0, 2),
+            ("c".to_string(), 1, 1),
+            ("d".to_string(), 0xffff, 0)]);
+        match loader.find("Foo").unwrap().which().unwrap() {
+            node::Struct(s) => {
+                assert_eq!(s.get_data_word_count(), 2);
+                assert_eq!(s.get_pointer_count(), 1);
+                assert_eq!(s.get_discriminant_count(), 2);
+                assert_eq!(s.get_discriminant_offset(), 3);
+            }
+            _ => panic!("not a struct"),
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let error = compile_text("struct Foo {}").err().unwrap().description;
+        assert!(error.starts_with("foo.capnp:1: file has no ID"), "{}", error);
+        let error = compile_text("@0xd6f1b1e1f6b1c9a5;\nstruct Foo { a @0 :UInt32; b @0 :Text; }")
+            .err().unwrap().description;
+        assert_eq!(error, "foo.capnp:2: duplicate ordinal @0");
+        let error = compile_text("@0xd6f1b1e1f6b1c9a5;\nstruct Foo { a @0 :Bar; }")
+            .err().unwrap().description;
+        assert_eq!(error, "foo.capnp:2: Bar is not defined");
+    }
+
+    #[test]
+    fn annotation_fields() {
+        let header = "@0xd6f1b1e1f6b1c9a5;
+                      struct Range { min @0 :Int32; max @1 :Int32; }
+                      annotation range(field) :Range;
+                      struct Foo { a @0 :Int32; }";
+        let loader = compile_text(&format!("{}
+            struct Bar {{ b @0 :Int32 $range(min = 1, max = 9); }}", header)).unwrap();
+        let field = match loader.find("Bar").unwrap().which().unwrap() {
+            node::Struct(s) => s.get_fields().unwrap().get(0),
+            _ => panic!("not a struct"),
+        };
+        let range = field.get_annotations().unwrap().get(0).get_value().unwrap();
+        match range.which().unwrap() {
+            value::Struct(p) => {
+                let range = p.get_as::<crate::dynamic::RawPointer>().unwrap().0.get_struct(None).unwrap();
+                assert_eq!((range.get_data_field::<i32>(0), range.get_data_field::<i32>(1)), (1, 9));
+            }
+            _ => panic!("not a struct value"),
+        }
+
+        // Only annotations take fields like this; elsewhere a struct value is a tuple.
+        let error = compile_text(&format!("{}\nconst c :Foo = bar(a = 1);", header)).err().unwrap().description;
+        assert!(error.contains("does not match its type"), "{}", error);
+        assert!(compile_text(&format!("{}\nconst c :Foo = (a = 1);", header)).is_ok());
+        assert!(compile_text(&format!("{}\nstruct Bar {{ b @0 :Int32 $range(1, 9); }}", header)).is_err());
+    }
+
+    #[test]
+    fn standard_imports() {
+        let loader = compile_text("
+            @0xd6f1b1e1f6b1c9a5;
+            using Schema = import \"/capnp/schema.capnp\";
+            using Rpc = import \"/capnp/rpc.capnp\";
+            using TwoParty = import \"/capnp/rpc-twoparty.capnp\";
+            using Persistent = import \"/capnp/persistent.capnp\";
+            struct Foo {
+              node @0 :Schema.Node;
+              message @1 :Rpc.Message;
+              side @2 :TwoParty.Side;
+            }").unwrap();
+
+        // The built-in files lay out their nodes just like the checked-in generated code.
+        let node = loader.get(0xe682ab4cf923a417).unwrap();
+        match node.which().unwrap() {
+            node::Struct(s) => {
+                assert_eq!(s.get_data_word_count(), 5);
+                assert_eq!(s.get_pointer_count(), 6);
+            }
+            _ => panic!("not a struct"),
+        }
+        assert!(loader.get(0x9aad50a41f4af45f).is_some());
+        assert!(loader.get(0x91b79f1f808db032).is_some());
+        assert!(loader.get(0xc8cb212fcd9f5691).is_some());
+    }
+}
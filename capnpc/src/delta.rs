@@ -0,0 +1,248 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Deltas between successive versions of a message, for sending a large, slowly changing state
+//! without re-sending all of it each time.
+//!
+//! A delta is itself a message. Its root is a list of two pointers: the paths of the fields
+//! that changed, such as `config.name`, as a `List(Text)`, and a sparse struct of the state's
+//! type that holds the new values at those paths. A field that became a null pointer is null
+//! in the sparse struct too. Lists, and structs that became null or non-null or switched union
+//! members, are sent whole. Capability fields are not carried.
+
+use capnp::{any_pointer, any_pointer_list, message, text_list, Error, Result};
+
+use crate::diff::values_equal;
+use crate::dynamic::{StructBuilder, StructReader, Value};
+use crate::schema_capnp::{field, type_};
+
+fn is_interface(field: field::Reader) -> Result<bool> {
+    Ok(match field.which()? {
+        field::Slot(slot) => match slot.get_type()?.which()? {
+            type_::Interface(_) => true,
+            _ => false,
+        },
+        field::Group(_) => false,
+    })
+}
+
+fn get_struct<'a>(reader: &StructReader<'a>, field: field::Reader<'a>) -> Result<StructReader<'a>> {
+    match reader.get_field(field)? {
+        Value::Struct(s) => Ok(s),
+        _ => Err(Error::failed(format!("{} is not a struct field", field.get_name()?))),
+    }
+}
+
+fn changed_paths<'a>(paths: &mut Vec<String>, prefix: &str, old: StructReader<'a>,
+                     new: StructReader<'a>) -> Result<()>
+{
+    for field in new.get_fields()?.iter() {
+        if !new.is_active(field)? || is_interface(field)? {
+            continue;
+        }
+        let name = field.get_name()?;
+        let path = if prefix.is_empty() { name.to_string() } else { format!("{}.{}", prefix, name) };
+        if !old.is_active(field)? {
+            paths.push(path);
+            continue;
+        }
+        let is_struct = match field.which()? {
+            field::Group(_) => true,
+            field::Slot(slot) => match slot.get_type()?.which()? {
+                type_::Struct(_) => true,
+                _ => false,
+            },
+        };
+        if old.has_field(field)? != new.has_field(field)? {
+            paths.push(path);
+        } else if is_struct {
+            if new.has_field(field)? {
+                changed_paths(paths, &path, get_struct(&old, field)?, get_struct(&new, field)?)?;
+            }
+        } else if !values_equal(Some(old.get_field(field)?), Some(new.get_field(field)?))? {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Copies the active fields of `from` into `to`, which must be of the same type and freshly
+/// initialized.
+fn copy_fields<'a>(to: &mut StructBuilder<'a>, from: &StructReader<'a>) -> Result<()> {
+    for field in from.get_fields()?.iter() {
+        if !from.has_field(field)? || is_interface(field)? {
+            continue;
+        }
+        match field.which()? {
+            field::Group(_) => copy_fields(&mut to.init_field(field)?, &get_struct(from, field)?)?,
+            field::Slot(_) => to.set_field(field, from.get_field(field)?)?,
+        }
+    }
+    // The active union member may be one whose value is null.
+    if let Some(field) = from.which()? {
+        if !from.has_field(field)? {
+            to.clear_field(field)?;
+        }
+    }
+    Ok(())
+}
+
+/// Sets the field at `path` in `to` to its value in `from`.
+fn copy_path<'a>(to: &mut StructBuilder<'a>, from: &StructReader<'a>, path: &[&str]) -> Result<()> {
+    let field = from.find_field(path[0])?;
+    if path.len() > 1 {
+        return copy_path(&mut to.get_struct_field(field)?, &get_struct(from, field)?, &path[1..]);
+    }
+    if !from.has_field(field)? {
+        to.clear_field(field)
+    } else if let field::Group(_) = field.which()? {
+        copy_fields(&mut to.init_field(field)?, &get_struct(from, field)?)
+    } else {
+        to.set_field(field, from.get_field(field)?)
+    }
+}
+
+/// Computes the delta that turns `old` into `new`, which must be structs of the same type.
+pub fn delta(old: StructReader, new: StructReader) -> Result<message::Builder<message::HeapAllocator>> {
+    if old.get_schema().get_id() != new.get_schema().get_id() {
+        return Err(Error::failed(format!(
+            "cannot compute a delta from a {} to a {}",
+            old.get_schema().get_display_name()?, new.get_schema().get_display_name()?)));
+    }
+    let mut paths = Vec::new();
+    changed_paths(&mut paths, "", old, new)?;
+
+    let mut message = message::Builder::new_default();
+    {
+        let mut root = message.init_root::<any_pointer::Builder>().initn_as::<any_pointer_list::Builder>(2);
+        {
+            let mut list = root.reborrow().get(0).initn_as::<text_list::Builder>(paths.len() as u32);
+            for (idx, path) in paths.iter().enumerate() {
+                list.set(idx as u32, path);
+            }
+        }
+        let mut patch = StructBuilder::init_any_pointer(new.get_loader(), new.get_schema(), root.get(1))?;
+        for path in &paths {
+            copy_path(&mut patch, &new, &path.split('.').collect::<Vec<_>>())?;
+        }
+    }
+    Ok(message)
+}
+
+/// Applies `delta`, the root of a message made by `delta()`, to `state`, turning the old
+/// version of the state into the new one.
+pub fn apply(state: &mut StructBuilder, delta: any_pointer::Reader) -> Result<()> {
+    let root: any_pointer_list::Reader = delta.get_as()?;
+    if root.len() != 2 {
+        return Err(Error::failed(format!("expected a delta of 2 pointers, got {}", root.len())));
+    }
+    let paths: text_list::Reader = root.get(0).get_as()?;
+    let patch = StructReader::from_any_pointer(state.get_loader(), state.get_schema(), root.get(1))?;
+    for path in paths.iter() {
+        copy_path(&mut state.reborrow(), &patch, &path?.split('.').collect::<Vec<_>>())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use capnp::{any_pointer, message};
+    use crate::compiler::compile_with;
+    use crate::dynamic::{struct_size, RawPointerBuilder, StructBuilder, StructReader, Value};
+    use crate::schema_loader::SchemaLoader;
+    use crate::text_format;
+
+    const SCHEMA: &'static str = "
+        @0xb7d2e4f1a3c5068b;
+        struct State {
+          name @0 :Text;
+          counters @1 :List(UInt32);
+          union {
+            idle @2 :Void;
+            busy :group { task @3 :Text; progress @4 :Float32; }
+          }
+          config @5 :Config;
+          note @6 :Text;
+        }
+        struct Config { retries @0 :UInt8; host @1 :Text; }";
+
+    fn loader() -> SchemaLoader {
+        let message = compile_with(&[PathBuf::from("state.capnp")], &[], &[],
+                                   &|_: &Path| Ok(SCHEMA.to_string())).unwrap();
+        let mut loader = SchemaLoader::new();
+        loader.load_request(message.into_reader()).unwrap();
+        loader
+    }
+
+    fn encode(loader: &SchemaLoader, text: &str) -> message::Builder<message::HeapAllocator> {
+        let mut message = message::Builder::new_default();
+        text_format::read_struct(loader, loader.find("State").unwrap(), text, message.init_root()).unwrap();
+        message
+    }
+
+    fn reader<'a>(loader: &'a SchemaLoader, message: &'a message::Builder<message::HeapAllocator>) -> StructReader<'a> {
+        StructReader::from_any_pointer(loader, loader.find("State").unwrap(),
+                                       message.get_root_as_reader::<any_pointer::Reader>().unwrap()).unwrap()
+    }
+
+    fn round_trip(old: &str, new: &str) -> Vec<String> {
+        let loader = loader();
+        let (old_message, new_message) = (encode(&loader, old), encode(&loader, new));
+        let delta = super::delta(reader(&loader, &old_message), reader(&loader, &new_message)).unwrap();
+        let delta_root = delta.get_root_as_reader::<any_pointer::Reader>().unwrap();
+
+        let mut state = encode(&loader, old);
+        {
+            let schema = loader.find("State").unwrap();
+            let pointer = state.get_root::<RawPointerBuilder>().unwrap().0;
+            let mut builder = StructBuilder::new(
+                &loader, schema, pointer.get_struct(struct_size(schema).unwrap(), None).unwrap());
+            super::apply(&mut builder, delta_root).unwrap();
+        }
+        assert_eq!(text_format::to_string(Value::Struct(reader(&loader, &state))).unwrap(),
+                   text_format::to_string(Value::Struct(reader(&loader, &new_message))).unwrap());
+
+        let paths: capnp::text_list::Reader = delta_root.get_as::<capnp::any_pointer_list::Reader>().unwrap()
+            .get(0).get_as().unwrap();
+        paths.iter().map(|p| p.unwrap().to_string()).collect()
+    }
+
+    #[test]
+    fn unchanged_state_has_empty_delta() {
+        let text = "(name = \"a\", counters = [1, 2], config = (retries = 3))";
+        assert!(round_trip(text, text).is_empty());
+    }
+
+    #[test]
+    fn only_changes_are_sent() {
+        assert_eq!(
+            round_trip("(name = \"a\", counters = [1, 2], config = (retries = 3, host = \"h\"), note = \"n\")",
+                       "(name = \"a\", counters = [1, 5], busy = (task = \"t\"), config = (retries = 4, host = \"h\"))"),
+            vec!["counters", "busy", "config.retries", "note"]);
+        assert_eq!(
+            round_trip("(busy = (task = \"t\", progress = 0.5), config = (host = \"h\"))",
+                       "(busy = (task = \"t\", progress = 0.75))"),
+            vec!["busy.progress", "config"]);
+        assert_eq!(round_trip("(busy = (task = \"t\"))", "(idle = void)"), vec!["idle"]);
+    }
+}
@@ -0,0 +1,226 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Compares two messages of the same type, field by field.
+//!
+//! Both messages are read through the dynamic API, so no generated code is needed. The result
+//! lists every leaf value that differs, which is handy in test assertions and for emitting
+//! only what changed between two versions of a record.
+
+use std::fmt;
+
+use capnp::{any_pointer, message, Error, Result};
+
+use crate::dynamic::{ListReader, StructReader, Value};
+use crate::text_format;
+
+/// A value that differs between two messages.
+#[derive(Clone)]
+pub struct Difference<'a> {
+    /// Where the value is, such as `friends[2].name`. Empty for the root struct itself.
+    pub path: String,
+    /// The value in the first message, or `None` if it is absent there: the field is an
+    /// inactive union member, or the list is shorter.
+    pub left: Option<Value<'a>>,
+    /// The value in the second message, or `None` if it is absent there.
+    pub right: Option<Value<'a>>,
+}
+
+impl <'a> fmt::Display for Difference<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let show = |value: Option<Value>| match value {
+            Some(value) => text_format::to_string(value).unwrap_or_else(|e| format!("<{}>", e.description)),
+            None => "(absent)".to_string(),
+        };
+        write!(fmt, "{}: {} -> {}", self.path, show(self.left), show(self.right))
+    }
+}
+
+/// Lists the differences between `a` and `b`, which must be structs of the same type, in
+/// the order of the struct's fields. Lists are compared element by element; pointers whose
+/// types are unknown to the schema are compared by their canonical encoding.
+pub fn diff<'a>(a: StructReader<'a>, b: StructReader<'a>) -> Result<Vec<Difference<'a>>> {
+    let mut result = Vec::new();
+    diff_structs(&mut result, "", a, b)?;
+    Ok(result)
+}
+
+fn diff_structs<'a>(result: &mut Vec<Difference<'a>>, path: &str, a: StructReader<'a>,
+                    b: StructReader<'a>) -> Result<()>
+{
+    if a.get_schema().get_id() != b.get_schema().get_id() {
+        return Err(Error::failed(format!(
+            "cannot compare a {} with a {}",
+            a.get_schema().get_display_name()?, b.get_schema().get_display_name()?)));
+    }
+    for field in a.get_fields()?.iter() {
+        let name = field.get_name()?;
+        let path = if path.is_empty() { name.to_string() } else { format!("{}.{}", path, name) };
+        if a.is_active(field)? && b.is_active(field)? && !a.has_field(field)? && !b.has_field(field)? {
+            // Both are null. Comparing their defaults would never end for a struct that
+            // contains itself.
+            continue;
+        }
+        let left = if a.is_active(field)? { Some(a.get_field(field)?) } else { None };
+        let right = if b.is_active(field)? { Some(b.get_field(field)?) } else { None };
+        diff_values(result, path, left, right)?;
+    }
+    Ok(())
+}
+
+fn diff_lists<'a>(result: &mut Vec<Difference<'a>>, path: &str, a: ListReader<'a>,
+                  b: ListReader<'a>) -> Result<()>
+{
+    for idx in 0..std::cmp::max(a.len(), b.len()) {
+        let left = if idx < a.len() { Some(a.get(idx)?) } else { None };
+        let right = if idx < b.len() { Some(b.get(idx)?) } else { None };
+        diff_values(result, format!("{}[{}]", path, idx), left, right)?;
+    }
+    Ok(())
+}
+
+fn diff_values<'a>(result: &mut Vec<Difference<'a>>, path: String, left: Option<Value<'a>>,
+                   right: Option<Value<'a>>) -> Result<()>
+{
+    let equal = match (left, right) {
+        (None, None) => true,
+        (Some(Value::Struct(a)), Some(Value::Struct(b))) => return diff_structs(result, &path, a, b),
+        (Some(Value::List(a)), Some(Value::List(b))) => return diff_lists(result, &path, a, b),
+        (Some(a), Some(b)) => equal(a, b)?,
+        _ => false,
+    };
+    if !equal {
+        result.push(Difference { path: path, left: left, right: right });
+    }
+    Ok(())
+}
+
+/// Whether `a` and `b`, either of which may be absent, hold the same value.
+pub(crate) fn values_equal<'a>(a: Option<Value<'a>>, b: Option<Value<'a>>) -> Result<bool> {
+    let mut result = Vec::new();
+    diff_values(&mut result, String::new(), a, b)?;
+    Ok(result.is_empty())
+}
+
+/// Whether two values that are neither structs nor lists are equal. Floats are compared
+/// bit by bit, so that a NaN equals itself.
+fn equal(a: Value, b: Value) -> Result<bool> {
+    Ok(match (a, b) {
+        (Value::Void, Value::Void) => true,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::Int8(a), Value::Int8(b)) => a == b,
+        (Value::Int16(a), Value::Int16(b)) => a == b,
+        (Value::Int32(a), Value::Int32(b)) => a == b,
+        (Value::Int64(a), Value::Int64(b)) => a == b,
+        (Value::Uint8(a), Value::Uint8(b)) => a == b,
+        (Value::Uint16(a), Value::Uint16(b)) => a == b,
+        (Value::Uint32(a), Value::Uint32(b)) => a == b,
+        (Value::Uint64(a), Value::Uint64(b)) => a == b,
+        (Value::Float32(a), Value::Float32(b)) => a.to_bits() == b.to_bits(),
+        (Value::Float64(a), Value::Float64(b)) => a.to_bits() == b.to_bits(),
+        (Value::Enum(a), Value::Enum(b)) => a.get_value() == b.get_value(),
+        (Value::Text(a), Value::Text(b)) => a == b,
+        (Value::Data(a), Value::Data(b)) => a == b,
+        (Value::AnyPointer(a), Value::AnyPointer(b)) => canonical(a)? == canonical(b)?,
+        (Value::Capability, Value::Capability) => true,
+        _ => false,
+    })
+}
+
+fn canonical(pointer: any_pointer::Reader) -> Result<Vec<u8>> {
+    if pointer.is_null() {
+        return Ok(Vec::new());
+    }
+    let mut message = message::Builder::new_default();
+    message.set_root_canonical(pointer)?;
+    Ok(::capnp::serialize::write_message_to_words(&message))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use capnp::{any_pointer, message};
+    use crate::compiler::compile_with;
+    use crate::dynamic::StructReader;
+    use crate::schema_loader::SchemaLoader;
+    use crate::text_format;
+
+    const SCHEMA: &'static str = "
+        @0xe1f3c8b8a4b2d6c1;
+        struct Person {
+          name @0 :Text;
+          scores @1 :List(Int32);
+          union {
+            unemployed @2 :Void;
+            school @3 :Text;
+          }
+          address :group {
+            city @4 :Text;
+          }
+          ratio @5 :Float64;
+          friend @6 :Person;
+        }";
+
+    fn loader() -> SchemaLoader {
+        let message = compile_with(&[PathBuf::from("person.capnp")], &[], &[],
+                                   &|_: &Path| Ok(SCHEMA.to_string())).unwrap();
+        let mut loader = SchemaLoader::new();
+        loader.load_request(message.into_reader()).unwrap();
+        loader
+    }
+
+    fn encode(loader: &SchemaLoader, text: &str) -> message::Builder<message::HeapAllocator> {
+        let mut message = message::Builder::new_default();
+        text_format::read_struct(loader, loader.find("Person").unwrap(), text, message.init_root()).unwrap();
+        message
+    }
+
+    fn diff(a: &str, b: &str) -> Vec<String> {
+        let loader = loader();
+        let schema = loader.find("Person").unwrap();
+        let (a, b) = (encode(&loader, a), encode(&loader, b));
+        let a = a.get_root_as_reader::<any_pointer::Reader>().unwrap();
+        let b = b.get_root_as_reader::<any_pointer::Reader>().unwrap();
+        super::diff(StructReader::from_any_pointer(&loader, schema, a).unwrap(),
+                    StructReader::from_any_pointer(&loader, schema, b).unwrap()).unwrap().iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn equal_messages() {
+        let text = "(name = \"Ann\", scores = [1, 2], school = \"X\", ratio = nan)";
+        assert!(diff(text, text).is_empty());
+    }
+
+    #[test]
+    fn differences() {
+        assert_eq!(
+            diff("(name = \"Ann\", scores = [1, 2], address = (city = \"Oslo\"))",
+                 "(name = \"Bob\", scores = [1, 3, 4], school = \"X\", address = (city = \"Oslo\"), \
+                   friend = (name = \"Cy\"))"),
+            vec!["name: \"Ann\" -> \"Bob\"",
+                 "scores[1]: 2 -> 3",
+                 "scores[2]: (absent) -> 4",
+                 "unemployed: void -> (absent)",
+                 "school: (absent) -> \"X\"",
+                 "friend.name: \"\" -> \"Cy\""]);
+    }
+}
@@ -0,0 +1,1103 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Reading and building messages whose types are only known at runtime.
+//!
+//! Instead of generated accessors, values are read and written through schema nodes held by a
+//! `SchemaLoader`. Roughly corresponds to dynamic.h in the C++ implementation.
+//!
+//! `Capability` does the same for RPC: it makes calls by method name, with parameters and results
+//! accessed as dynamic structs, on capabilities whose interface was loaded at runtime.
+
+use std::convert::TryFrom;
+
+use capnp::{any_pointer, capability, data, text, Error, Result};
+use capnp::private::layout::{self, ElementSize, PointerReader, PrimitiveElement};
+use capnp::traits::{FromPointerReader, Reflect};
+
+use crate::schema_capnp::{enumerant, field, method, node, type_, value};
+use crate::schema_loader::SchemaLoader;
+
+/// A value of any Cap'n Proto type.
+#[derive(Clone, Copy)]
+pub enum Value<'a> {
+    Void,
+    Bool(bool),
+    Int8(i8),
+    Int16(i16),
+    Int32(i32),
+    Int64(i64),
+    Uint8(u8),
+    Uint16(u16),
+    Uint32(u32),
+    Uint64(u64),
+    Float32(f32),
+    Float64(f64),
+    Enum(Enum<'a>),
+    Text(text::Reader<'a>),
+    Data(data::Reader<'a>),
+    List(ListReader<'a>),
+    Struct(StructReader<'a>),
+    AnyPointer(any_pointer::Reader<'a>),
+    Capability,
+}
+
+impl <'a> Value<'a> {
+    /// Interprets a value from a schema, such as the value of a constant or the default value
+    /// of a field, as a value of type `typ`.
+    pub fn from_schema_value(loader: &'a SchemaLoader, typ: type_::Reader<'a>,
+                             value: value::Reader<'a>) -> Result<Value<'a>>
+    {
+        Ok(match value.which()? {
+            value::Void(()) => Value::Void,
+            value::Bool(b) => Value::Bool(b),
+            value::Int8(n) => Value::Int8(n),
+            value::Int16(n) => Value::Int16(n),
+            value::Int32(n) => Value::Int32(n),
+            value::Int64(n) => Value::Int64(n),
+            value::Uint8(n) => Value::Uint8(n),
+            value::Uint16(n) => Value::Uint16(n),
+            value::Uint32(n) => Value::Uint32(n),
+            value::Uint64(n) => Value::Uint64(n),
+            value::Float32(n) => Value::Float32(n),
+            value::Float64(n) => Value::Float64(n),
+            value::Enum(n) => match typ.which()? {
+                type_::Enum(e) => Value::Enum(Enum::new(n, loader.require(e.get_type_id())?)),
+                _ => return Err(Error::failed("enum value for a non-enum type".to_string())),
+            },
+            value::Text(t) => Value::Text(t?),
+            value::Data(d) => Value::Data(d?),
+            value::Interface(()) => Value::Capability,
+            value::List(p) | value::Struct(p) | value::AnyPointer(p) => {
+                read_pointer(loader, p.get_as::<RawPointer>()?.0, typ, None)?
+            }
+        })
+    }
+}
+
+/// The value of an enum, along with the schema of the enum.
+#[derive(Clone, Copy)]
+pub struct Enum<'a> {
+    value: u16,
+    schema: node::Reader<'a>,
+}
+
+impl <'a> Enum<'a> {
+    pub fn new(value: u16, schema: node::Reader<'a>) -> Enum<'a> {
+        Enum { value: value, schema: schema }
+    }
+
+    pub fn get_value(&self) -> u16 { self.value }
+
+    pub fn get_schema(&self) -> node::Reader<'a> { self.schema }
+
+    /// Gets the enumerant for the value, or `None` if the value is not in the schema, which
+    /// happens when the message was written using a newer version of the schema.
+    pub fn get_enumerant(&self) -> Result<Option<enumerant::Reader<'a>>> {
+        match self.schema.which()? {
+            node::Enum(e) => {
+                let enumerants = e.get_enumerants()?;
+                if (self.value as u32) < enumerants.len() {
+                    Ok(Some(enumerants.get(self.value as u32)))
+                } else {
+                    Ok(None)
+                }
+            }
+            _ => Err(Error::failed("schema of an enum value is not an enum".to_string())),
+        }
+    }
+}
+
+/// Converts generated struct readers and builders into their dynamic counterparts, and back.
+/// Implemented for every type that implements `capnp::traits::Reflect`.
+pub trait ToDynamic<'a>: Sized {
+    /// `StructReader` for generated readers and `StructBuilder` for generated builders.
+    type Dynamic;
+
+    /// Fails if the schema of the type has not been loaded into `loader`.
+    fn to_dynamic(self, loader: &'a SchemaLoader) -> Result<Self::Dynamic>;
+
+    /// Fails if `value` is a struct of some other type.
+    fn from_dynamic(value: Self::Dynamic) -> Result<Self>;
+}
+
+/// Pairs the untyped layout structs with the dynamic structs that wrap them.
+#[doc(hidden)]
+pub trait DynamicStruct<'a>: Sized {
+    type Dynamic;
+
+    fn wrap(self, loader: &'a SchemaLoader, schema: node::Reader<'a>) -> Self::Dynamic;
+    fn unwrap(value: Self::Dynamic) -> (node::Reader<'a>, Self);
+}
+
+impl <'a> DynamicStruct<'a> for layout::StructReader<'a> {
+    type Dynamic = StructReader<'a>;
+
+    fn wrap(self, loader: &'a SchemaLoader, schema: node::Reader<'a>) -> StructReader<'a> {
+        StructReader::new(loader, schema, self)
+    }
+
+    fn unwrap(value: StructReader<'a>) -> (node::Reader<'a>, Self) {
+        (value.schema, value.reader)
+    }
+}
+
+impl <'a> DynamicStruct<'a> for layout::StructBuilder<'a> {
+    type Dynamic = StructBuilder<'a>;
+
+    fn wrap(self, loader: &'a SchemaLoader, schema: node::Reader<'a>) -> StructBuilder<'a> {
+        StructBuilder::new(loader, schema, self)
+    }
+
+    fn unwrap(value: StructBuilder<'a>) -> (node::Reader<'a>, Self) {
+        (value.schema, value.builder)
+    }
+}
+
+impl <'a, T> ToDynamic<'a> for T
+    where T: Reflect<'a>, T::Internal: DynamicStruct<'a>
+{
+    type Dynamic = <T::Internal as DynamicStruct<'a>>::Dynamic;
+
+    fn to_dynamic(self, loader: &'a SchemaLoader) -> Result<Self::Dynamic> {
+        let schema = loader.require(T::type_id())?;
+        Ok(self.into_internal().wrap(loader, schema))
+    }
+
+    fn from_dynamic(value: Self::Dynamic) -> Result<T> {
+        let (schema, internal) = T::Internal::unwrap(value);
+        if schema.get_id() != T::type_id() {
+            return Err(Error::failed(format!(
+                "expected a struct of a different type than {}", schema.get_display_name()?)));
+        }
+        Ok(T::from_internal(internal))
+    }
+}
+
+/// A struct, read according to its schema.
+#[derive(Clone, Copy)]
+pub struct StructReader<'a> {
+    loader: &'a SchemaLoader,
+    schema: node::Reader<'a>,
+    reader: layout::StructReader<'a>,
+}
+
+impl <'a> StructReader<'a> {
+    pub fn new(loader: &'a SchemaLoader, schema: node::Reader<'a>,
+               reader: layout::StructReader<'a>) -> StructReader<'a> {
+        StructReader { loader: loader, schema: schema, reader: reader }
+    }
+
+    /// Reads the struct that `reader` points to, interpreting it as a `schema` struct.
+    pub fn from_any_pointer(loader: &'a SchemaLoader, schema: node::Reader<'a>,
+                            reader: any_pointer::Reader<'a>) -> Result<StructReader<'a>> {
+        struct_node(schema)?;
+        Ok(StructReader::new(loader, schema, reader.get_as::<RawPointer>()?.0.get_struct(None)?))
+    }
+
+    pub fn get_schema(&self) -> node::Reader<'a> { self.schema }
+
+    pub fn get_loader(&self) -> &'a SchemaLoader { self.loader }
+
+    /// Gets the struct's fields, in code order.
+    pub fn get_fields(&self) -> Result<::capnp::struct_list::Reader<'a, field::Owned>> {
+        struct_node(self.schema)?.get_fields()
+    }
+
+    /// Looks up a field of the struct by name.
+    pub fn find_field(&self, name: &str) -> Result<field::Reader<'a>> {
+        for field in self.get_fields()?.iter() {
+            if field.get_name()? == name {
+                return Ok(field);
+            }
+        }
+        Err(Error::failed(format!("{} has no field named {}",
+                                  self.schema.get_display_name()?, name)))
+    }
+
+    /// Gets the member of the struct's unnamed union that is currently set, or `None` if the
+    /// struct has no such union or the member is unknown to the schema.
+    pub fn which(&self) -> Result<Option<field::Reader<'a>>> {
+        let s = struct_node(self.schema)?;
+        if s.get_discriminant_count() == 0 {
+            return Ok(None);
+        }
+        let discriminant = self.reader.get_data_field::<u16>(s.get_discriminant_offset() as usize);
+        for field in s.get_fields()?.iter() {
+            if field.get_discriminant_value() == discriminant {
+                return Ok(Some(field));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Whether `field` is either not a union member or is the union member that is set.
+    pub fn is_active(&self, field: field::Reader<'a>) -> Result<bool> {
+        if field.get_discriminant_value() == field::NO_DISCRIMINANT {
+            Ok(true)
+        } else {
+            Ok(self.which()?.map(|w| w.get_discriminant_value()) == Some(field.get_discriminant_value()))
+        }
+    }
+
+    /// Whether `field` is active and, if it is of a pointer type, non-null.
+    pub fn has_field(&self, field: field::Reader<'a>) -> Result<bool> {
+        if !self.is_active(field)? {
+            return Ok(false);
+        }
+        match field.which()? {
+            field::Group(_) => Ok(true),
+            field::Slot(slot) => {
+                if is_pointer_type(slot.get_type()?)? {
+                    Ok(!self.reader.get_pointer_field(slot.get_offset() as usize).is_null())
+                } else {
+                    Ok(true)
+                }
+            }
+        }
+    }
+
+    /// Gets the value of the field with the given name.
+    pub fn get(&self, name: &str) -> Result<Value<'a>> {
+        self.get_field(self.find_field(name)?)
+    }
+
+    /// Gets the value of `field`, which must be one of this struct's fields. If the field is a
+    /// pointer field and is null, its default value is returned.
+    pub fn get_field(&self, field: field::Reader<'a>) -> Result<Value<'a>> {
+        match field.which()? {
+            field::Group(group) => {
+                let schema = self.loader.require(group.get_type_id())?;
+                Ok(Value::Struct(StructReader::new(self.loader, schema, self.reader)))
+            }
+            field::Slot(slot) => {
+                let typ = slot.get_type()?;
+                let offset = slot.get_offset() as usize;
+                let default = slot.get_default_value()?;
+                let bits = default_bits(default)?;
+                let r = &self.reader;
+                Ok(match typ.which()? {
+                    type_::Void(()) => Value::Void,
+                    type_::Bool(()) => Value::Bool(r.get_bool_field(offset) ^ (bits != 0)),
+                    type_::Int8(()) => Value::Int8(r.get_data_field::<i8>(offset) ^ bits as i8),
+                    type_::Int16(()) => Value::Int16(r.get_data_field::<i16>(offset) ^ bits as i16),
+                    type_::Int32(()) => Value::Int32(r.get_data_field::<i32>(offset) ^ bits as i32),
+                    type_::Int64(()) => Value::Int64(r.get_data_field::<i64>(offset) ^ bits as i64),
+                    type_::Uint8(()) => Value::Uint8(r.get_data_field::<u8>(offset) ^ bits as u8),
+                    type_::Uint16(()) => Value::Uint16(r.get_data_field::<u16>(offset) ^ bits as u16),
+                    type_::Uint32(()) => Value::Uint32(r.get_data_field::<u32>(offset) ^ bits as u32),
+                    type_::Uint64(()) => Value::Uint64(r.get_data_field::<u64>(offset) ^ bits),
+                    type_::Float32(()) => Value::Float32(
+                        f32::from_bits(r.get_data_field::<u32>(offset) ^ bits as u32)),
+                    type_::Float64(()) => Value::Float64(
+                        f64::from_bits(r.get_data_field::<u64>(offset) ^ bits)),
+                    type_::Enum(e) => Value::Enum(Enum::new(
+                        r.get_data_field::<u16>(offset) ^ bits as u16,
+                        self.loader.require(e.get_type_id())?)),
+                    _ => read_pointer(self.loader, r.get_pointer_field(offset), typ, Some(default))?,
+                })
+            }
+        }
+    }
+}
+
+/// A list, read according to the type of its elements.
+#[derive(Clone, Copy)]
+pub struct ListReader<'a> {
+    loader: &'a SchemaLoader,
+    element_type: type_::Reader<'a>,
+    reader: layout::ListReader<'a>,
+}
+
+impl <'a> ListReader<'a> {
+    pub fn get_element_type(&self) -> type_::Reader<'a> { self.element_type }
+
+    pub fn len(&self) -> u32 { self.reader.len() }
+
+    pub fn get(&self, index: u32) -> Result<Value<'a>> {
+        if index >= self.len() {
+            return Err(Error::failed(format!("list index {} out of bounds", index)));
+        }
+        let r = &self.reader;
+        Ok(match self.element_type.which()? {
+            type_::Void(()) => Value::Void,
+            type_::Bool(()) => Value::Bool(PrimitiveElement::get(r, index)),
+            type_::Int8(()) => Value::Int8(PrimitiveElement::get(r, index)),
+            type_::Int16(()) => Value::Int16(PrimitiveElement::get(r, index)),
+            type_::Int32(()) => Value::Int32(PrimitiveElement::get(r, index)),
+            type_::Int64(()) => Value::Int64(PrimitiveElement::get(r, index)),
+            type_::Uint8(()) => Value::Uint8(PrimitiveElement::get(r, index)),
+            type_::Uint16(()) => Value::Uint16(PrimitiveElement::get(r, index)),
+            type_::Uint32(()) => Value::Uint32(PrimitiveElement::get(r, index)),
+            type_::Uint64(()) => Value::Uint64(PrimitiveElement::get(r, index)),
+            type_::Float32(()) => Value::Float32(PrimitiveElement::get(r, index)),
+            type_::Float64(()) => Value::Float64(PrimitiveElement::get(r, index)),
+            type_::Enum(e) => Value::Enum(Enum::new(PrimitiveElement::get(r, index),
+                                                    self.loader.require(e.get_type_id())?)),
+            type_::Struct(s) => Value::Struct(StructReader::new(
+                self.loader, self.loader.require(s.get_type_id())?, r.get_struct_element(index))),
+            _ => read_pointer(self.loader, r.get_pointer_element(index), self.element_type, None)?,
+        })
+    }
+
+    pub fn iter(self) -> impl Iterator<Item = Result<Value<'a>>> {
+        (0..self.len()).map(move |idx| self.get(idx))
+    }
+}
+
+/// Reads the value at `path` below `reader`, where each element of the path is the name of a
+/// field or, below a list, the index of an element. Only the pointers along the path are
+/// followed, so the rest of the message is neither traversed nor checked.
+///
+/// Returns `None` if the path passes through a union member that is not set or through a null
+/// pointer, or ends at one.
+pub fn extract<'a>(reader: StructReader<'a>, path: &[&str]) -> Result<Option<Value<'a>>> {
+    let mut value = Value::Struct(reader);
+    for (depth, step) in path.iter().enumerate() {
+        value = match value {
+            Value::Struct(s) => {
+                let field = s.find_field(step)?;
+                if !s.has_field(field)? {
+                    return Ok(None);
+                }
+                s.get_field(field)?
+            }
+            Value::List(l) => {
+                let index = step.parse::<u32>().map_err(|_| Error::failed(format!(
+                    "{} is not a list index", step)))?;
+                l.get(index)?
+            }
+            _ => return Err(Error::failed(format!(
+                "{} is neither a struct nor a list", path[..depth].join(".")))),
+        };
+    }
+    Ok(Some(value))
+}
+
+/// A pointer that has not been interpreted yet. Lets the dynamic API reach the layout-level
+/// reader behind an `any_pointer::Reader`.
+#[derive(Clone, Copy)]
+pub(crate) struct RawPointer<'a>(pub(crate) PointerReader<'a>);
+
+impl <'a> FromPointerReader<'a> for RawPointer<'a> {
+    fn get_from_pointer(reader: &PointerReader<'a>, _default: Option<&'a [::capnp::Word]>)
+                        -> Result<RawPointer<'a>> {
+        Ok(RawPointer(*reader))
+    }
+}
+
+pub(crate) fn struct_node(schema: node::Reader) -> Result<node::struct_::Reader> {
+    match schema.which()? {
+        node::Struct(s) => Ok(s),
+        _ => Err(Error::failed(format!("{} is not a struct", schema.get_display_name()?))),
+    }
+}
+
+pub(crate) fn is_pointer_type(typ: type_::Reader) -> Result<bool> {
+    let size = element_size(typ)?;
+    Ok(size == ElementSize::Pointer || size == ElementSize::InlineComposite)
+}
+
+/// The size of an element of a list of `typ`.
+pub(crate) fn element_size(typ: type_::Reader) -> Result<ElementSize> {
+    Ok(match typ.which()? {
+        type_::Void(()) => ElementSize::Void,
+        type_::Bool(()) => ElementSize::Bit,
+        type_::Int8(()) | type_::Uint8(()) => ElementSize::Byte,
+        type_::Int16(()) | type_::Uint16(()) | type_::Enum(_) => ElementSize::TwoBytes,
+        type_::Int32(()) | type_::Uint32(()) | type_::Float32(()) => ElementSize::FourBytes,
+        type_::Int64(()) | type_::Uint64(()) | type_::Float64(()) => ElementSize::EightBytes,
+        type_::Text(()) | type_::Data(()) | type_::List(_) | type_::Interface(_) |
+        type_::AnyPointer(_) => ElementSize::Pointer,
+        type_::Struct(_) => ElementSize::InlineComposite,
+    })
+}
+
+/// The bits that the stored value of a primitive field is XORed with, which are those of the
+/// field's default value.
+pub(crate) fn default_bits(default: value::Reader) -> Result<u64> {
+    Ok(match default.which()? {
+        value::Bool(b) => b as u64,
+        value::Int8(n) => n as u8 as u64,
+        value::Int16(n) => n as u16 as u64,
+        value::Int32(n) => n as u32 as u64,
+        value::Int64(n) => n as u64,
+        value::Uint8(n) => n as u64,
+        value::Uint16(n) => n as u64,
+        value::Uint32(n) => n as u64,
+        value::Uint64(n) => n,
+        value::Float32(n) => n.to_bits() as u64,
+        value::Float64(n) => n.to_bits(),
+        value::Enum(n) => n as u64,
+        _ => 0,
+    })
+}
+
+fn read_pointer<'a>(loader: &'a SchemaLoader, mut pointer: PointerReader<'a>,
+                    typ: type_::Reader<'a>, default: Option<value::Reader<'a>>) -> Result<Value<'a>>
+{
+    if pointer.is_null() {
+        if let Some(default) = default {
+            match default.which()? {
+                value::Text(t) => return Ok(Value::Text(t?)),
+                value::Data(d) => return Ok(Value::Data(d?)),
+                value::List(p) | value::Struct(p) | value::AnyPointer(p) => {
+                    pointer = p.get_as::<RawPointer>()?.0;
+                }
+                _ => (),
+            }
+        }
+    }
+    Ok(match typ.which()? {
+        type_::Text(()) => Value::Text(pointer.get_text(None)?),
+        type_::Data(()) => Value::Data(pointer.get_data(None)?),
+        type_::List(l) => {
+            let element_type = l.get_element_type()?;
+            Value::List(ListReader {
+                loader: loader,
+                element_type: element_type,
+                reader: pointer.get_list(element_size(element_type)?, None)?,
+            })
+        }
+        type_::Struct(s) => Value::Struct(StructReader::new(
+            loader, loader.require(s.get_type_id())?, pointer.get_struct(None)?)),
+        type_::AnyPointer(_) => Value::AnyPointer(any_pointer::Reader::new(pointer)),
+        type_::Interface(_) => Value::Capability,
+        _ => return Err(Error::failed("not a pointer type".to_string())),
+    })
+}
+
+/// A struct that is being built, written according to its schema.
+///
+/// Values are type-checked against the schema: integers must fit in the field's type, and
+/// enums, structs and lists must be of the field's type. The builders returned by `init()` and
+/// `init_list()` borrow this one mutably, so only one view of any part of the message can be
+/// written through at a time.
+pub struct StructBuilder<'a> {
+    loader: &'a SchemaLoader,
+    schema: node::Reader<'a>,
+    builder: layout::StructBuilder<'a>,
+}
+
+impl <'a> StructBuilder<'a> {
+    pub fn new(loader: &'a SchemaLoader, schema: node::Reader<'a>,
+               builder: layout::StructBuilder<'a>) -> StructBuilder<'a> {
+        StructBuilder { loader: loader, schema: schema, builder: builder }
+    }
+
+    /// Initializes the pointer `builder` to a new `schema` struct, such as the root of a
+    /// message, and returns it.
+    pub fn init_any_pointer(loader: &'a SchemaLoader, schema: node::Reader<'a>,
+                            builder: any_pointer::Builder<'a>) -> Result<StructBuilder<'a>> {
+        let size = struct_size(schema)?;
+        let pointer = builder.get_as::<RawPointerBuilder>()?.0;
+        Ok(StructBuilder::new(loader, schema, pointer.init_struct(size)))
+    }
+
+    pub fn get_schema(&self) -> node::Reader<'a> { self.schema }
+
+    pub fn get_loader(&self) -> &'a SchemaLoader { self.loader }
+
+    /// Returns a builder for the same struct that borrows this one.
+    pub fn reborrow(&mut self) -> StructBuilder<'_> {
+        StructBuilder::new(self.loader, self.schema, self.builder)
+    }
+
+    /// Views the struct built so far as a reader, for as long as it is not being written.
+    pub fn as_reader(&self) -> StructReader<'_> {
+        StructReader::new(self.loader, self.schema, self.builder.into_reader())
+    }
+
+    pub fn into_reader(self) -> StructReader<'a> {
+        StructReader::new(self.loader, self.schema, self.builder.into_reader())
+    }
+
+    /// Looks up a field of the struct by name.
+    pub fn find_field(&self, name: &str) -> Result<field::Reader<'a>> {
+        StructReader::new(self.loader, self.schema, self.builder.into_reader()).find_field(name)
+    }
+
+    /// Sets the field with the given name to `value`.
+    pub fn set(&mut self, name: &str, value: Value) -> Result<()> {
+        let field = self.find_field(name)?;
+        self.set_field(field, value)
+    }
+
+    /// Sets `field`, which must be one of this struct's fields, to `value`. If the field is a
+    /// member of a union, it becomes the union's active member.
+    pub fn set_field(&mut self, field: field::Reader<'a>, value: Value) -> Result<()> {
+        self.set_discriminant(field)?;
+        match field.which()? {
+            field::Group(_) => Err(Error::failed(format!(
+                "{} is a group; use init() to set its fields", field.get_name()?))),
+            field::Slot(slot) => {
+                let typ = slot.get_type()?;
+                let offset = slot.get_offset() as usize;
+                let bits = default_bits(slot.get_default_value()?)?;
+                if is_pointer_type(typ)? {
+                    set_pointer(self.builder.get_pointer_field(offset), typ, value)
+                } else {
+                    let b = &self.builder;
+                    match typ.which()? {
+                        type_::Void(()) => (),
+                        type_::Bool(()) => b.set_bool_field(offset, as_bool(value)? ^ (bits != 0)),
+                        type_::Float32(()) => {
+                            b.set_data_field::<u32>(offset, (as_float(value)? as f32).to_bits() ^ bits as u32)
+                        }
+                        type_::Float64(()) => b.set_data_field::<u64>(offset, as_float(value)?.to_bits() ^ bits),
+                        _ => {
+                            let raw = primitive_bits(typ, value)? ^ bits;
+                            match element_size(typ)? {
+                                ElementSize::Byte => b.set_data_field::<u8>(offset, raw as u8),
+                                ElementSize::TwoBytes => b.set_data_field::<u16>(offset, raw as u16),
+                                ElementSize::FourBytes => b.set_data_field::<u32>(offset, raw as u32),
+                                _ => b.set_data_field::<u64>(offset, raw),
+                            }
+                        }
+                    }
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    /// Initializes the struct or group field with the given name and returns it.
+    pub fn init(&mut self, name: &str) -> Result<StructBuilder<'_>> {
+        let field = self.find_field(name)?;
+        self.init_field(field)
+    }
+
+    /// Initializes `field`, which must be a struct or group field of this struct, and returns
+    /// it. A group's fields are reset to their defaults.
+    pub fn init_field(&mut self, field: field::Reader<'a>) -> Result<StructBuilder<'_>> {
+        self.set_discriminant(field)?;
+        match field.which()? {
+            field::Group(group) => {
+                let mut result = StructBuilder::new(self.loader, self.loader.require(group.get_type_id())?,
+                                                    self.builder);
+                result.clear_fields()?;
+                Ok(result)
+            }
+            field::Slot(slot) => match slot.get_type()?.which()? {
+                type_::Struct(s) => {
+                    let schema = self.loader.require(s.get_type_id())?;
+                    let pointer = self.builder.get_pointer_field(slot.get_offset() as usize);
+                    Ok(StructBuilder::new(self.loader, schema, pointer.init_struct(struct_size(schema)?)))
+                }
+                _ => Err(Error::failed(format!("{} is not a struct field", field.get_name()?))),
+            },
+        }
+    }
+
+    /// Gets `field`, which must be a struct or group field of this struct, for writing. Unlike
+    /// `init_field()`, this keeps the contents it already has; a null struct is initialized.
+    pub fn get_struct_field(&mut self, field: field::Reader<'a>) -> Result<StructBuilder<'_>> {
+        self.set_discriminant(field)?;
+        match field.which()? {
+            field::Group(group) => {
+                Ok(StructBuilder::new(self.loader, self.loader.require(group.get_type_id())?, self.builder))
+            }
+            field::Slot(slot) => match slot.get_type()?.which()? {
+                type_::Struct(s) => {
+                    let schema = self.loader.require(s.get_type_id())?;
+                    let pointer = self.builder.get_pointer_field(slot.get_offset() as usize);
+                    Ok(StructBuilder::new(self.loader, schema, pointer.get_struct(struct_size(schema)?, None)?))
+                }
+                _ => Err(Error::failed(format!("{} is not a struct field", field.get_name()?))),
+            },
+        }
+    }
+
+    /// Initializes the list field with the given name to a list of `length` elements and
+    /// returns it.
+    pub fn init_list(&mut self, name: &str, length: u32) -> Result<ListBuilder<'_>> {
+        let field = self.find_field(name)?;
+        self.init_list_field(field, length)
+    }
+
+    /// Initializes `field`, which must be a list field of this struct, to a list of `length`
+    /// elements and returns it.
+    pub fn init_list_field(&mut self, field: field::Reader<'a>, length: u32) -> Result<ListBuilder<'_>> {
+        self.set_discriminant(field)?;
+        match field.which()? {
+            field::Slot(slot) => {
+                let pointer = self.builder.get_pointer_field(slot.get_offset() as usize);
+                init_list(self.loader, pointer, slot.get_type()?, length)
+            }
+            field::Group(_) => Err(Error::failed(format!("{} is not a list field", field.get_name()?))),
+        }
+    }
+
+    /// Makes the member of the struct's unnamed union with the given name the active one, with
+    /// its default value.
+    pub fn init_union_field(&mut self, name: &str) -> Result<()> {
+        let field = self.find_field(name)?;
+        if field.get_discriminant_value() == field::NO_DISCRIMINANT {
+            return Err(Error::failed(format!("{} is not a union member", name)));
+        }
+        self.clear_field(field)
+    }
+
+    fn set_discriminant(&mut self, field: field::Reader<'a>) -> Result<()> {
+        if field.get_discriminant_value() != field::NO_DISCRIMINANT {
+            let s = struct_node(self.schema)?;
+            self.builder.set_data_field::<u16>(s.get_discriminant_offset() as usize,
+                                               field.get_discriminant_value());
+        }
+        Ok(())
+    }
+
+    /// Resets `field` to its default value, leaving a pointer field null. If the field is a
+    /// member of a union, it becomes the union's active member.
+    pub fn clear_field(&mut self, field: field::Reader<'a>) -> Result<()> {
+        self.set_discriminant(field)?;
+        match field.which()? {
+            field::Group(group) => {
+                let schema = self.loader.require(group.get_type_id())?;
+                StructBuilder::new(self.loader, schema, self.builder).clear_fields()
+            }
+            field::Slot(slot) => {
+                let typ = slot.get_type()?;
+                let offset = slot.get_offset() as usize;
+                let b = &self.builder;
+                match element_size(typ)? {
+                    ElementSize::Void => (),
+                    ElementSize::Bit => b.set_bool_field(offset, false),
+                    ElementSize::Byte => b.set_data_field::<u8>(offset, 0),
+                    ElementSize::TwoBytes => b.set_data_field::<u16>(offset, 0),
+                    ElementSize::FourBytes => b.set_data_field::<u32>(offset, 0),
+                    ElementSize::EightBytes => b.set_data_field::<u64>(offset, 0),
+                    ElementSize::Pointer | ElementSize::InlineComposite => b.get_pointer_field(offset).clear(),
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Resets every field to its default value, making the first union member active.
+    fn clear_fields(&mut self) -> Result<()> {
+        let s = struct_node(self.schema)?;
+        if s.get_discriminant_count() > 0 {
+            self.builder.set_data_field::<u16>(s.get_discriminant_offset() as usize, 0);
+        }
+        for field in s.get_fields()?.iter() {
+            let value = field.get_discriminant_value();
+            if value == field::NO_DISCRIMINANT || value == 0 {
+                self.clear_field(field)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A list that is being built, written according to the type of its elements.
+pub struct ListBuilder<'a> {
+    loader: &'a SchemaLoader,
+    element_type: type_::Reader<'a>,
+    builder: layout::ListBuilder<'a>,
+}
+
+impl <'a> ListBuilder<'a> {
+    pub fn get_loader(&self) -> &'a SchemaLoader { self.loader }
+
+    pub fn get_element_type(&self) -> type_::Reader<'a> { self.element_type }
+
+    pub fn len(&self) -> u32 { self.builder.len() }
+
+    /// Returns a builder for the same list that borrows this one.
+    pub fn reborrow(&mut self) -> ListBuilder<'_> {
+        ListBuilder { loader: self.loader, element_type: self.element_type, builder: self.builder }
+    }
+
+    /// Views the list built so far as a reader, for as long as it is not being written.
+    pub fn as_reader(&self) -> ListReader<'_> {
+        ListReader { loader: self.loader, element_type: self.element_type, reader: self.builder.into_reader() }
+    }
+
+    pub fn into_reader(self) -> ListReader<'a> {
+        ListReader { loader: self.loader, element_type: self.element_type, reader: self.builder.into_reader() }
+    }
+
+    /// Sets the element at `index` to `value`. Struct elements cannot be set, only
+    /// initialized in place with `init()`.
+    pub fn set(&mut self, index: u32, value: Value) -> Result<()> {
+        self.check_index(index)?;
+        let typ = self.element_type;
+        let b = &self.builder;
+        match typ.which()? {
+            type_::Void(()) => (),
+            type_::Bool(()) => PrimitiveElement::set(b, index, as_bool(value)?),
+            type_::Float32(()) => PrimitiveElement::set(b, index, as_float(value)? as f32),
+            type_::Float64(()) => PrimitiveElement::set(b, index, as_float(value)?),
+            type_::Struct(_) => {
+                return Err(Error::failed("struct list elements cannot be set; use init()".to_string()))
+            }
+            _ if is_pointer_type(typ)? => set_pointer(b.get_pointer_element(index), typ, value)?,
+            _ => {
+                let raw = primitive_bits(typ, value)?;
+                match element_size(typ)? {
+                    ElementSize::Byte => PrimitiveElement::set(b, index, raw as u8),
+                    ElementSize::TwoBytes => PrimitiveElement::set(b, index, raw as u16),
+                    ElementSize::FourBytes => PrimitiveElement::set(b, index, raw as u32),
+                    _ => PrimitiveElement::set(b, index, raw),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Gets the struct element at `index`, or initializes the struct that the element at
+    /// `index` points to, for a list of structs or a list of struct pointers respectively.
+    pub fn init(&mut self, index: u32) -> Result<StructBuilder<'_>> {
+        self.check_index(index)?;
+        match self.element_type.which()? {
+            type_::Struct(s) => {
+                let schema = self.loader.require(s.get_type_id())?;
+                Ok(StructBuilder::new(self.loader, schema, self.builder.get_struct_element(index)))
+            }
+            _ => Err(Error::failed("not a list of structs".to_string())),
+        }
+    }
+
+    /// Initializes the element at `index`, for a list of lists, to a list of `length`
+    /// elements and returns it.
+    pub fn init_list(&mut self, index: u32, length: u32) -> Result<ListBuilder<'_>> {
+        self.check_index(index)?;
+        init_list(self.loader, self.builder.get_pointer_element(index), self.element_type, length)
+    }
+
+    fn check_index(&self, index: u32) -> Result<()> {
+        if index >= self.len() {
+            Err(Error::failed(format!("list index {} out of bounds", index)))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn init_list<'a>(loader: &'a SchemaLoader, pointer: layout::PointerBuilder<'a>, typ: type_::Reader<'a>,
+                 length: u32) -> Result<ListBuilder<'a>>
+{
+    let element_type = match typ.which()? {
+        type_::List(l) => l.get_element_type()?,
+        _ => return Err(Error::failed("not a list type".to_string())),
+    };
+    let builder = match element_type.which()? {
+        type_::Struct(s) => pointer.init_struct_list(length, struct_size(loader.require(s.get_type_id())?)?),
+        _ => pointer.init_list(element_size(element_type)?, length),
+    };
+    Ok(ListBuilder { loader: loader, element_type: element_type, builder: builder })
+}
+
+fn as_bool(value: Value) -> Result<bool> {
+    match value {
+        Value::Bool(b) => Ok(b),
+        _ => Err(Error::failed("expected a Bool value".to_string())),
+    }
+}
+
+fn as_integer(value: Value) -> Option<i128> {
+    Some(match value {
+        Value::Int8(n) => n as i128,
+        Value::Int16(n) => n as i128,
+        Value::Int32(n) => n as i128,
+        Value::Int64(n) => n as i128,
+        Value::Uint8(n) => n as i128,
+        Value::Uint16(n) => n as i128,
+        Value::Uint32(n) => n as i128,
+        Value::Uint64(n) => n as i128,
+        _ => return None,
+    })
+}
+
+fn as_float(value: Value) -> Result<f64> {
+    match value {
+        Value::Float32(n) => Ok(n as f64),
+        Value::Float64(n) => Ok(n),
+        _ => match as_integer(value) {
+            Some(n) => Ok(n as f64),
+            None => Err(Error::failed("expected a number".to_string())),
+        },
+    }
+}
+
+/// The bits of `value`, an integer or enum value, as a value of type `typ`.
+fn primitive_bits(typ: type_::Reader, value: Value) -> Result<u64> {
+    fn fit<T: TryFrom<i128>>(n: i128) -> Result<T> {
+        T::try_from(n).map_err(|_| Error::failed(format!("integer {} is out of range", n)))
+    }
+    if let type_::Enum(e) = typ.which()? {
+        return match value {
+            Value::Enum(v) if v.get_schema().get_id() == e.get_type_id() => Ok(v.get_value() as u64),
+            Value::Enum(v) => Err(Error::failed(format!(
+                "expected an enum of a different type than {}", v.get_schema().get_display_name()?))),
+            _ => match as_integer(value) {
+                Some(n) => Ok(fit::<u16>(n)? as u64),
+                None => Err(Error::failed("expected an enum value".to_string())),
+            },
+        };
+    }
+    let n = match as_integer(value) {
+        Some(n) => n,
+        None => return Err(Error::failed("expected an integer".to_string())),
+    };
+    Ok(match typ.which()? {
+        type_::Int8(()) => fit::<i8>(n)? as u8 as u64,
+        type_::Int16(()) => fit::<i16>(n)? as u16 as u64,
+        type_::Int32(()) => fit::<i32>(n)? as u32 as u64,
+        type_::Int64(()) => fit::<i64>(n)? as u64,
+        type_::Uint8(()) => fit::<u8>(n)? as u64,
+        type_::Uint16(()) => fit::<u16>(n)? as u64,
+        type_::Uint32(()) => fit::<u32>(n)? as u64,
+        type_::Uint64(()) => fit::<u64>(n)?,
+        _ => return Err(Error::failed("not an integer type".to_string())),
+    })
+}
+
+fn set_pointer(mut pointer: layout::PointerBuilder, typ: type_::Reader, value: Value) -> Result<()> {
+    match (typ.which()?, value) {
+        (type_::Text(()), Value::Text(t)) => pointer.set_text(t),
+        (type_::Data(()), Value::Data(d)) => pointer.set_data(d),
+        (type_::Struct(s), Value::Struct(v)) => {
+            if v.get_schema().get_id() != s.get_type_id() {
+                return Err(Error::failed(format!(
+                    "expected a struct of a different type than {}", v.get_schema().get_display_name()?)));
+            }
+            pointer.set_struct(&v.reader, false)?
+        }
+        (type_::List(l), Value::List(v)) => {
+            if !same_type(l.get_element_type()?, v.get_element_type())? {
+                return Err(Error::failed("list has the wrong element type".to_string()));
+            }
+            pointer.set_list(&v.reader, false)?
+        }
+        (type_::AnyPointer(_), Value::AnyPointer(p)) => {
+            pointer.copy_from(p.get_as::<RawPointer>()?.0, false)?
+        }
+        (type_::AnyPointer(_), Value::Struct(v)) => pointer.set_struct(&v.reader, false)?,
+        (type_::AnyPointer(_), Value::List(v)) => pointer.set_list(&v.reader, false)?,
+        (type_::AnyPointer(_), Value::Text(t)) => pointer.set_text(t),
+        (type_::AnyPointer(_), Value::Data(d)) => pointer.set_data(d),
+        (type_::Interface(_), _) | (_, Value::Capability) => {
+            return Err(Error::failed("capabilities cannot be set through the dynamic API".to_string()))
+        }
+        _ => return Err(Error::failed("value does not match the type".to_string())),
+    }
+    Ok(())
+}
+
+/// Whether `a` and `b` are the same type, ignoring generic brands.
+fn same_type(a: type_::Reader, b: type_::Reader) -> Result<bool> {
+    Ok(match (a.which()?, b.which()?) {
+        (type_::List(a), type_::List(b)) => same_type(a.get_element_type()?, b.get_element_type()?)?,
+        (type_::Enum(a), type_::Enum(b)) => a.get_type_id() == b.get_type_id(),
+        (type_::Struct(a), type_::Struct(b)) => a.get_type_id() == b.get_type_id(),
+        (type_::Interface(a), type_::Interface(b)) => a.get_type_id() == b.get_type_id(),
+        (type_::AnyPointer(_), type_::AnyPointer(_)) => true,
+        _ => ::std::mem::discriminant(&a.which()?) == ::std::mem::discriminant(&b.which()?),
+    })
+}
+
+/// A pointer within a message that is being built, not yet interpreted. Lets the dynamic API
+/// reach the layout-level builder behind an `any_pointer::Builder`.
+pub(crate) struct RawPointerBuilder<'a>(pub(crate) layout::PointerBuilder<'a>);
+
+impl <'a> capnp::traits::FromPointerBuilder<'a> for RawPointerBuilder<'a> {
+    fn init_pointer(builder: layout::PointerBuilder<'a>, _length: u32) -> RawPointerBuilder<'a> {
+        RawPointerBuilder(builder)
+    }
+
+    fn get_from_pointer(builder: layout::PointerBuilder<'a>, _default: Option<&'a [::capnp::Word]>)
+                        -> Result<RawPointerBuilder<'a>> {
+        Ok(RawPointerBuilder(builder))
+    }
+}
+
+/// The size of the data and pointer sections of a `schema` struct.
+pub(crate) fn struct_size(schema: node::Reader) -> Result<layout::StructSize> {
+    let s = struct_node(schema)?;
+    Ok(layout::StructSize { data: s.get_data_word_count(), pointers: s.get_pointer_count() })
+}
+
+/// A capability whose interface is only known at runtime.
+pub struct Capability<'a> {
+    loader: &'a SchemaLoader,
+    schema: node::Reader<'a>,
+    client: capability::Client,
+}
+
+impl <'a> Capability<'a> {
+    /// Treats `client` as a capability of the `schema` interface.
+    pub fn new(loader: &'a SchemaLoader, schema: node::Reader<'a>, client: capability::Client)
+               -> Result<Capability<'a>> {
+        interface_node(schema)?;
+        Ok(Capability { loader: loader, schema: schema, client: client })
+    }
+
+    pub fn get_schema(&self) -> node::Reader<'a> { self.schema }
+
+    /// Finds the method called `name`, looking through superclasses too. Returns the id of the
+    /// interface that declares the method, and the method itself.
+    pub fn find_method(&self, name: &str) -> Result<(u64, u16, method::Reader<'a>)> {
+        match find_method(self.loader, self.schema, name)? {
+            Some(found) => Ok(found),
+            None => Err(Error::failed(format!("{} has no method named {}",
+                                              self.schema.get_display_name()?, name))),
+        }
+    }
+
+    /// Starts a call to the method called `name`.
+    pub fn new_request(&self, name: &str) -> Result<Request<'a>> {
+        let (interface_id, method_id, method) = self.find_method(name)?;
+        let param_schema = self.loader.require(method.get_param_struct_type())?;
+        let result_schema = self.loader.require(method.get_result_struct_type())?;
+        let mut request = self.client.new_call(interface_id, method_id, None);
+        StructBuilder::init_any_pointer(self.loader, param_schema, request.get())?;
+        Ok(Request { loader: self.loader, param_schema: param_schema, result_schema: result_schema,
+                     request: request })
+    }
+}
+
+fn find_method<'a>(loader: &'a SchemaLoader, schema: node::Reader<'a>, name: &str)
+                   -> Result<Option<(u64, u16, method::Reader<'a>)>> {
+    let interface = interface_node(schema)?;
+    let methods = interface.get_methods()?;
+    for ordinal in 0..methods.len() {
+        let method = methods.get(ordinal);
+        if method.get_name()? == name {
+            return Ok(Some((schema.get_id(), ordinal as u16, method)));
+        }
+    }
+    for superclass in interface.get_superclasses()?.iter() {
+        if let Some(found) = find_method(loader, loader.require(superclass.get_id())?, name)? {
+            return Ok(Some(found));
+        }
+    }
+    Ok(None)
+}
+
+fn interface_node(schema: node::Reader) -> Result<node::interface::Reader> {
+    match schema.which()? {
+        node::Interface(i) => Ok(i),
+        _ => Err(Error::failed(format!("{} is not an interface", schema.get_display_name()?))),
+    }
+}
+
+/// A call that is being set up through a `Capability`.
+pub struct Request<'a> {
+    loader: &'a SchemaLoader,
+    param_schema: node::Reader<'a>,
+    result_schema: node::Reader<'a>,
+    request: capability::Request<any_pointer::Owned, any_pointer::Owned>,
+}
+
+impl <'a> Request<'a> {
+    /// The parameters of the call.
+    pub fn get(&mut self) -> Result<StructBuilder<'_>> {
+        let size = struct_size(self.param_schema)?;
+        let pointer = self.request.get().get_as::<RawPointerBuilder>()?.0;
+        Ok(StructBuilder::new(self.loader, self.param_schema, pointer.get_struct(size, None)?))
+    }
+
+    /// Sends the call and waits for its results.
+    pub async fn send(self) -> Result<Response<'a>> {
+        let Request { loader, result_schema, request, .. } = self;
+        let response = request.send().promise.await?;
+        Ok(Response { loader: loader, schema: result_schema, response: response })
+    }
+}
+
+/// The results of a call made through a `Capability`.
+pub struct Response<'a> {
+    loader: &'a SchemaLoader,
+    schema: node::Reader<'a>,
+    response: capability::Response<any_pointer::Owned>,
+}
+
+impl <'a> Response<'a> {
+    pub fn get(&self) -> Result<StructReader<'_>> {
+        StructReader::from_any_pointer(self.loader, self.schema, self.response.get()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use capnp::{any_pointer, message, serialize};
+    use crate::schema_loader::SchemaLoader;
+    use super::{StructBuilder, StructReader, Value};
+
+    fn load_schema() -> SchemaLoader {
+        let text = "@0xe1f2a3b4c5d6e7f8;
+                    struct Envelope {
+                      header @0 :Header;
+                      body @1 :Data;
+                    }
+                    struct Header {
+                      id @0 :UInt64;
+                      tags @1 :List(Text);
+                      union { none @2 :Void; origin @3 :Text; }
+                    }".to_string();
+        let message = crate::compiler::compile_with(&[PathBuf::from("envelope.capnp")], &[], &[], &move |path: &Path| {
+            if path == Path::new("envelope.capnp") {
+                Ok(text.clone())
+            } else {
+                Err(::capnp::Error::failed("no such file".to_string()))
+            }
+        }).unwrap();
+        let mut loader = SchemaLoader::new();
+        loader.load_request(message.into_reader()).unwrap();
+        loader
+    }
+
+    #[test]
+    fn extract_follows_only_the_path() {
+        let loader = load_schema();
+        let schema = loader.find("Envelope").unwrap();
+        let mut message = message::Builder::new_default();
+        {
+            let mut envelope = StructBuilder::init_any_pointer(&loader, schema, message.init_root()).unwrap();
+            envelope.set("body", Value::Data(&[1, 2, 3])).unwrap();
+            let mut header = envelope.init("header").unwrap();
+            header.set("id", Value::Uint64(42)).unwrap();
+            header.init_list("tags", 2).unwrap().set(1, Value::Text("urgent")).unwrap();
+        }
+        let mut bytes = serialize::write_message_to_words(&message);
+
+        // Point `body` far outside of the segment. Reading it fails, but extracting other
+        // fields never looks at it.
+        let body_pointer: u64 = (0x1000 << 2) | 1 | (2 << 32) | (3 << 35);
+        bytes[24..32].copy_from_slice(&body_pointer.to_le_bytes());
+        let reader = serialize::read_message_from_flat_slice(&mut &bytes[..], Default::default()).unwrap();
+        let root = StructReader::from_any_pointer(
+            &loader, schema, reader.get_root::<any_pointer::Reader>().unwrap()).unwrap();
+        assert!(root.get("body").is_err());
+
+        let extract = |path: &[&str]| super::extract(root, path).unwrap();
+        match extract(&["header", "id"]) { Some(Value::Uint64(42)) => (), _ => panic!() }
+        match extract(&["header", "tags", "1"]) { Some(Value::Text(t)) => assert_eq!(t, "urgent"), _ => panic!() }
+        match extract(&["header", "tags", "0"]) { Some(Value::Text(t)) => assert_eq!(t, ""), _ => panic!() }
+        assert!(extract(&["header", "origin"]).is_none());
+        assert!(super::extract(root, &["header", "id", "x"]).is_err());
+        assert!(super::extract(root, &["header", "nonexistent"]).is_err());
+    }
+}
@@ -0,0 +1,140 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Serving Cap'n Proto capabilities to HTTP clients that speak JSON.
+//!
+//! A `Gateway` holds capabilities under service names. `handle()` takes the method, path and body
+//! of an HTTP request of the form
+//!
+//! ```text
+//! POST /<service>/<method>
+//! {"param1": ..., "param2": ...}
+//! ```
+//!
+//! makes the corresponding call with the parameters decoded from the body as described in the
+//! `json` module, and returns the HTTP response to send back: the results encoded as JSON, or an
+//! error. Wiring it up to an HTTP server is left to the application, so that it can use whichever
+//! server it already has.
+
+use std::collections::HashMap;
+
+use capnp::{capability, Error, ErrorKind, Result};
+
+use crate::dynamic::{Capability, Value};
+use crate::json;
+use crate::schema_loader::SchemaLoader;
+
+/// An HTTP response produced by a `Gateway`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl HttpResponse {
+    fn ok(body: String) -> HttpResponse {
+        HttpResponse { status: 200, content_type: "application/json", body: body }
+    }
+
+    /// An error response with a body like `{"error": "...", "kind": "failed"}`.
+    fn error(status: u16, error: &Error) -> HttpResponse {
+        let kind = match error.kind {
+            ErrorKind::Failed => "failed",
+            ErrorKind::Overloaded => "overloaded",
+            ErrorKind::Disconnected => "disconnected",
+            ErrorKind::Unimplemented => "unimplemented",
+        };
+        let description = json::to_string(Value::Text(&error.description)).unwrap_or_default();
+        let body = format!("{{\"error\":{},\"kind\":\"{}\"}}", description, kind);
+        HttpResponse { status: status, content_type: "application/json", body: body }
+    }
+}
+
+/// Maps HTTP+JSON requests onto calls to capabilities.
+pub struct Gateway<'a> {
+    loader: &'a SchemaLoader,
+    services: HashMap<String, Capability<'a>>,
+}
+
+impl <'a> Gateway<'a> {
+    /// Creates a gateway whose interfaces are looked up in `loader`.
+    pub fn new(loader: &'a SchemaLoader) -> Gateway<'a> {
+        Gateway { loader: loader, services: HashMap::new() }
+    }
+
+    /// Serves `client`, a capability of the interface named `interface_name`, under
+    /// `/<service>/`. See `SchemaLoader::find()` for the forms that the name can take.
+    pub fn add_service(&mut self, service: &str, interface_name: &str, client: capability::Client)
+                       -> Result<()>
+    {
+        let schema = match self.loader.find(interface_name) {
+            Some(schema) => schema,
+            None => return Err(Error::failed(format!("no interface named {}", interface_name))),
+        };
+        self.services.insert(service.to_string(), Capability::new(self.loader, schema, client)?);
+        Ok(())
+    }
+
+    /// Handles one HTTP request. Only `POST` is accepted. An empty body means no parameters.
+    pub async fn handle(&self, http_method: &str, path: &str, body: &str) -> HttpResponse {
+        if http_method != "POST" {
+            return HttpResponse::error(405, &Error::failed(format!("method {} is not allowed", http_method)));
+        }
+        let mut parts = path.trim_start_matches('/').splitn(2, '/');
+        let (service, method) = match (parts.next(), parts.next()) {
+            (Some(service), Some(method)) if !method.contains('/') => (service, method),
+            _ => return HttpResponse::error(404, &Error::failed(format!("no such path: {}", path))),
+        };
+        let capability = match self.services.get(service) {
+            Some(capability) => capability,
+            None => return HttpResponse::error(404, &Error::failed(format!("no such service: {}", service))),
+        };
+        let mut request = match capability.new_request(method) {
+            Ok(request) => request,
+            Err(e) => return HttpResponse::error(404, &e),
+        };
+        if !body.trim().is_empty() {
+            let mut params = match request.get() {
+                Ok(params) => params,
+                Err(e) => return HttpResponse::error(500, &e),
+            };
+            if let Err(e) = json::read_struct_into(&mut params, body) {
+                return HttpResponse::error(400, &e);
+            }
+        }
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(e) => {
+                let status = match e.kind {
+                    ErrorKind::Failed => 500,
+                    ErrorKind::Unimplemented => 501,
+                    ErrorKind::Overloaded | ErrorKind::Disconnected => 503,
+                };
+                return HttpResponse::error(status, &e);
+            }
+        };
+        match response.get().and_then(|results| json::to_string(Value::Struct(results))) {
+            Ok(body) => HttpResponse::ok(body),
+            Err(e) => HttpResponse::error(500, &e),
+        }
+    }
+}
@@ -0,0 +1,524 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! JSON encoding of Cap'n Proto values, following the conventions of the C++ `JsonCodec`.
+//!
+//! A struct becomes an object with a member for each field that is set, keyed by the field's
+//! name, and a list becomes an array. Enums are written as the names of their enumerants, `Void`
+//! as `null`, and `Data` as an array of byte values. 64-bit integers are written as strings,
+//! because many JSON implementations can't represent them exactly, and non-finite floats as the
+//! strings `"NaN"`, `"Infinity"` and `"-Infinity"`. When reading, both forms are accepted for
+//! those types, and members whose value is `null` are skipped.
+//!
+//! ```text
+//! {"name": "Alice", "scores": [3, 5], "kind": "student", "id": "12345678901"}
+//! ```
+
+use std::convert::TryFrom;
+
+use capnp::{any_pointer, Error, Result};
+
+use crate::dynamic::{self, ListBuilder, StructBuilder, Value};
+use crate::schema_capnp::{field, node, type_};
+use crate::schema_loader::SchemaLoader;
+use crate::text_format::printed_fields;
+
+/// Writes `value` as JSON.
+pub fn to_string(value: Value) -> Result<String> {
+    let mut out = String::new();
+    write_value(&mut out, value)?;
+    Ok(out)
+}
+
+/// Parses `json`, an object, and writes it to `builder` as a struct of type `schema`.
+pub fn read_struct(loader: &SchemaLoader, schema: node::Reader, json: &str,
+                   builder: any_pointer::Builder) -> Result<()>
+{
+    read_struct_into(&mut StructBuilder::init_any_pointer(loader, schema, builder)?, json)
+}
+
+/// Parses `json`, an object, and sets the fields that it names in `builder`.
+pub fn read_struct_into(builder: &mut StructBuilder, json: &str) -> Result<()> {
+    let mut parser = Parser { bytes: json.as_bytes(), pos: 0, depth: 0 };
+    match parser.parse_all()? {
+        Json::Object(members) => fill_struct(builder, members),
+        _ => json_error("expected an object"),
+    }
+}
+
+fn write_value(out: &mut String, value: Value) -> Result<()> {
+    match value {
+        Value::Void => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if b { "true" } else { "false" }),
+        Value::Int8(n) => out.push_str(&n.to_string()),
+        Value::Int16(n) => out.push_str(&n.to_string()),
+        Value::Int32(n) => out.push_str(&n.to_string()),
+        Value::Int64(n) => write_string(out, &n.to_string()),
+        Value::Uint8(n) => out.push_str(&n.to_string()),
+        Value::Uint16(n) => out.push_str(&n.to_string()),
+        Value::Uint32(n) => out.push_str(&n.to_string()),
+        Value::Uint64(n) => write_string(out, &n.to_string()),
+        Value::Float32(n) => write_float(out, n as f64),
+        Value::Float64(n) => write_float(out, n),
+        Value::Enum(e) => match e.get_enumerant()? {
+            Some(enumerant) => write_string(out, enumerant.get_name()?),
+            None => out.push_str(&e.get_value().to_string()),
+        },
+        Value::Text(t) => write_string(out, t),
+        Value::Data(d) => {
+            out.push('[');
+            for (idx, b) in d.iter().enumerate() {
+                if idx > 0 { out.push(','); }
+                out.push_str(&b.to_string());
+            }
+            out.push(']');
+        }
+        Value::List(list) => {
+            out.push('[');
+            for idx in 0..list.len() {
+                if idx > 0 { out.push(','); }
+                write_value(out, list.get(idx)?)?;
+            }
+            out.push(']');
+        }
+        Value::Struct(reader) => {
+            out.push('{');
+            for (idx, (name, value)) in printed_fields(&reader)?.into_iter().enumerate() {
+                if idx > 0 { out.push(','); }
+                write_string(out, name);
+                out.push(':');
+                write_value(out, value)?;
+            }
+            out.push('}');
+        }
+        Value::AnyPointer(_) | Value::Capability => {
+            return Err(Error::failed("AnyPointer and capability values have no JSON encoding".to_string()))
+        }
+    }
+    Ok(())
+}
+
+fn write_float(out: &mut String, n: f64) {
+    if n.is_nan() {
+        out.push_str("\"NaN\"");
+    } else if n.is_infinite() {
+        out.push_str(if n > 0.0 { "\"Infinity\"" } else { "\"-Infinity\"" });
+    } else {
+        out.push_str(&format!("{:?}", n));
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// A parsed JSON value. Numbers are kept as written, so that 64-bit integers survive intact.
+#[derive(Clone, Debug, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+fn json_error<T>(message: &str) -> Result<T> {
+    Err(Error::failed(format!("JSON: {}", message)))
+}
+
+/// How deeply arrays and objects may nest, matching the default `ReaderOptions::nesting_limit`.
+const NESTING_LIMIT: u32 = 64;
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    depth: u32,
+}
+
+impl <'a> Parser<'a> {
+    fn parse_all(&mut self) -> Result<Json> {
+        let value = self.parse_value()?;
+        self.skip_whitespace();
+        if self.pos < self.bytes.len() {
+            return self.error("unexpected trailing characters");
+        }
+        Ok(value)
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T> {
+        json_error(&format!("{} at offset {}", message, self.pos))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.bytes.len() && (self.bytes[self.pos] as char).is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.bytes.get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<()> {
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            self.error(&format!("expected '{}'", byte as char))
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Json> {
+        if self.depth >= NESTING_LIMIT {
+            return self.error("nesting limit exceeded");
+        }
+        self.depth += 1;
+        let result = self.parse_value_inner();
+        self.depth -= 1;
+        result
+    }
+
+    fn parse_value_inner(&mut self) -> Result<Json> {
+        match self.peek() {
+            Some(b'{') => {
+                self.pos += 1;
+                let mut members = Vec::new();
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    if self.peek() != Some(b'"') {
+                        return self.error("expected a member name");
+                    }
+                    let name = self.parse_string()?;
+                    self.expect(b':')?;
+                    members.push((name, self.parse_value()?));
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => { self.pos += 1; return Ok(Json::Object(members)) }
+                        _ => return self.error("expected ',' or '}'"),
+                    }
+                }
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.parse_value()?);
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => { self.pos += 1; return Ok(Json::Array(items)) }
+                        _ => return self.error("expected ',' or ']'"),
+                    }
+                }
+            }
+            Some(b'"') => Ok(Json::String(self.parse_string()?)),
+            Some(b't') => self.parse_literal("true", Json::Bool(true)),
+            Some(b'f') => self.parse_literal("false", Json::Bool(false)),
+            Some(b'n') => self.parse_literal("null", Json::Null),
+            Some(b) if b == b'-' || b.is_ascii_digit() => {
+                let start = self.pos;
+                while self.pos < self.bytes.len() &&
+                    (self.bytes[self.pos].is_ascii_digit() || b"+-.eE".contains(&self.bytes[self.pos]))
+                {
+                    self.pos += 1;
+                }
+                let number = std::str::from_utf8(&self.bytes[start..self.pos]).unwrap_or("");
+                if number.parse::<f64>().is_err() {
+                    return self.error(&format!("malformed number {}", number));
+                }
+                Ok(Json::Number(number.to_string()))
+            }
+            Some(_) => self.error("unexpected character"),
+            None => self.error("unexpected end of input"),
+        }
+    }
+
+    fn parse_literal(&mut self, literal: &str, value: Json) -> Result<Json> {
+        if self.bytes[self.pos..].starts_with(literal.as_bytes()) {
+            self.pos += literal.len();
+            Ok(value)
+        } else {
+            self.error("unexpected character")
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String> {
+        self.expect(b'"')?;
+        let mut result = Vec::new();
+        loop {
+            let b = match self.bytes.get(self.pos) {
+                Some(&b) => b,
+                None => return self.error("unterminated string"),
+            };
+            self.pos += 1;
+            match b {
+                b'"' => break,
+                b'\\' => {
+                    let escape = match self.bytes.get(self.pos) {
+                        Some(&e) => e,
+                        None => return self.error("unterminated string"),
+                    };
+                    self.pos += 1;
+                    match escape {
+                        b'"' | b'\\' | b'/' => result.push(escape),
+                        b'b' => result.push(8),
+                        b'f' => result.push(12),
+                        b'n' => result.push(b'\n'),
+                        b'r' => result.push(b'\r'),
+                        b't' => result.push(b'\t'),
+                        b'u' => {
+                            let mut c = self.parse_hex4()?;
+                            if (0xd800..0xdc00).contains(&c) && self.bytes[self.pos..].starts_with(b"\\u") {
+                                self.pos += 2;
+                                let low = self.parse_hex4()?;
+                                c = 0x10000 + ((c - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+                            let c = match std::char::from_u32(c) {
+                                Some(c) => c,
+                                None => return self.error("invalid \\u escape"),
+                            };
+                            let mut buf = [0; 4];
+                            result.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                        }
+                        _ => return self.error("invalid escape"),
+                    }
+                }
+                b => result.push(b),
+            }
+        }
+        match String::from_utf8(result) {
+            Ok(s) => Ok(s),
+            Err(_) => self.error("string is not valid UTF-8"),
+        }
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32> {
+        let digits = self.bytes.get(self.pos..self.pos + 4)
+            .and_then(|d| std::str::from_utf8(d).ok())
+            .and_then(|d| u32::from_str_radix(d, 16).ok());
+        match digits {
+            Some(n) => { self.pos += 4; Ok(n) }
+            None => self.error("invalid \\u escape"),
+        }
+    }
+}
+
+fn fill_struct(builder: &mut StructBuilder, members: Vec<(String, Json)>) -> Result<()> {
+    for (name, json) in members {
+        if json == Json::Null {
+            continue;
+        }
+        let field = builder.find_field(&name)?;
+        let typ = match field.which()? {
+            field::Group(_) => match json {
+                Json::Object(members) => {
+                    fill_struct(&mut builder.init_field(field)?, members)?;
+                    continue;
+                }
+                _ => return json_error(&format!("expected an object for group {}", name)),
+            },
+            field::Slot(slot) => slot.get_type()?,
+        };
+        match (typ.which()?, json) {
+            (type_::Struct(_), Json::Object(members)) => fill_struct(&mut builder.init_field(field)?, members)?,
+            (type_::List(_), Json::Array(items)) => {
+                fill_list(&mut builder.init_list_field(field, items.len() as u32)?, items)?
+            }
+            (type_::Data(()), Json::Array(items)) => builder.set_field(field, Value::Data(&bytes(&items)?))?,
+            (_, json) => builder.set_field(field, value(builder.get_loader(), typ, &json)?)?,
+        }
+    }
+    Ok(())
+}
+
+fn fill_list(list: &mut ListBuilder, items: Vec<Json>) -> Result<()> {
+    let element_type = list.get_element_type();
+    for (idx, item) in items.into_iter().enumerate() {
+        let idx = idx as u32;
+        match (element_type.which()?, item) {
+            (type_::Struct(_), Json::Object(members)) => fill_struct(&mut list.init(idx)?, members)?,
+            (type_::Struct(_), _) => return json_error("expected an object element"),
+            (type_::List(_), Json::Array(items)) => fill_list(&mut list.init_list(idx, items.len() as u32)?, items)?,
+            (type_::Data(()), Json::Array(items)) => list.set(idx, Value::Data(&bytes(&items)?))?,
+            (_, item) => list.set(idx, value(list.get_loader(), element_type, &item)?)?,
+        }
+    }
+    Ok(())
+}
+
+fn bytes(items: &[Json]) -> Result<Vec<u8>> {
+    items.iter().map(integer::<u8>).collect()
+}
+
+fn integer<T: TryFrom<i128>>(json: &Json) -> Result<T> {
+    let n = match *json {
+        Json::Number(ref n) | Json::String(ref n) => match n.parse::<i128>() {
+            Ok(n) => n,
+            Err(_) => return json_error(&format!("expected an integer, got {}", n)),
+        },
+        _ => return json_error(&format!("expected an integer, got {:?}", json)),
+    };
+    T::try_from(n).or_else(|_| json_error(&format!("integer {} is out of range", n)))
+}
+
+fn float(json: &Json) -> Result<f64> {
+    match *json {
+        Json::Number(ref n) => n.parse().or_else(|_| json_error(&format!("malformed number {}", n))),
+        Json::String(ref s) if s == "NaN" => Ok(std::f64::NAN),
+        Json::String(ref s) if s == "Infinity" => Ok(std::f64::INFINITY),
+        Json::String(ref s) if s == "-Infinity" => Ok(std::f64::NEG_INFINITY),
+        _ => json_error(&format!("expected a number, got {:?}", json)),
+    }
+}
+
+fn enumerant(loader: &SchemaLoader, type_id: u64, json: &Json) -> Result<u16> {
+    let schema = loader.require(type_id)?;
+    if let Json::String(ref name) = *json {
+        if let node::Enum(e) = schema.which()? {
+            for (idx, enumerant) in e.get_enumerants()?.iter().enumerate() {
+                if enumerant.get_name()? == name {
+                    return Ok(idx as u16);
+                }
+            }
+        }
+        return json_error(&format!("{} has no enumerant named {}", schema.get_display_name()?, name));
+    }
+    integer(json)
+}
+
+/// Interprets `json`, which is not an object or array, as a value of type `typ`.
+fn value<'a>(loader: &'a SchemaLoader, typ: type_::Reader<'a>, json: &'a Json) -> Result<Value<'a>> {
+    Ok(match (typ.which()?, json) {
+        (type_::Void(()), _) => Value::Void,
+        (type_::Bool(()), &Json::Bool(b)) => Value::Bool(b),
+        (type_::Int8(()), _) | (type_::Int16(()), _) | (type_::Int32(()), _) | (type_::Int64(()), _) => {
+            Value::Int64(integer::<i64>(json)?)
+        }
+        (type_::Uint8(()), _) | (type_::Uint16(()), _) | (type_::Uint32(()), _) | (type_::Uint64(()), _) => {
+            Value::Uint64(integer::<u64>(json)?)
+        }
+        (type_::Float32(()), _) | (type_::Float64(()), _) => Value::Float64(float(json)?),
+        (type_::Enum(e), _) => {
+            let schema = loader.require(e.get_type_id())?;
+            Value::Enum(dynamic::Enum::new(enumerant(loader, e.get_type_id(), json)?, schema))
+        }
+        (type_::Text(()), &Json::String(ref s)) => Value::Text(s),
+        (type_::AnyPointer(_), _) | (type_::Interface(_), _) => {
+            return json_error("AnyPointer and capability fields cannot be read from JSON")
+        }
+        _ => return json_error(&format!("value {:?} does not match the field's type", json)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use capnp::{any_pointer, message};
+    use crate::dynamic::{StructReader, Value};
+    use crate::schema_loader::SchemaLoader;
+
+    fn load_schema() -> SchemaLoader {
+        let text = "@0xa9b6f1c2d3e4f506;
+                    struct Person {
+                      id @0 :UInt64;
+                      name @1 :Text;
+                      scores @2 :List(Float32);
+                      kind @3 :Kind;
+                      photo @4 :Data;
+                      address :group { city @5 :Text; }
+                      union { unemployed @6 :Void; school @7 :Text; }
+                    }
+                    enum Kind { student @0; teacher @1; }".to_string();
+        let message = crate::compiler::compile_with(&[PathBuf::from("person.capnp")], &[], &[], &move |path: &Path| {
+            if path == Path::new("person.capnp") {
+                Ok(text.clone())
+            } else {
+                Err(::capnp::Error::failed("no such file".to_string()))
+            }
+        }).unwrap();
+        let mut loader = SchemaLoader::new();
+        loader.load_request(message.into_reader()).unwrap();
+        loader
+    }
+
+    fn round_trip(loader: &SchemaLoader, json: &str) -> String {
+        let schema = loader.find("Person").unwrap();
+        let mut message = message::Builder::new_default();
+        super::read_struct(loader, schema, json, message.init_root()).unwrap();
+        let reader = StructReader::from_any_pointer(
+            loader, schema, message.get_root_as_reader::<any_pointer::Reader>().unwrap()).unwrap();
+        super::to_string(Value::Struct(reader)).unwrap()
+    }
+
+    #[test]
+    fn encode_and_decode() {
+        let loader = load_schema();
+        let json = concat!(
+            r#"{"id":"18446744073709551615","name":"Al \"the\" é\n","scores":[1.5,"NaN","-Infinity"],"#,
+            r#""kind":"teacher","photo":[0,255],"address":{"city":"Oslo"},"school":"MIT"}"#);
+        assert_eq!(round_trip(&loader, json), json);
+
+        // Whitespace, nulls and numbers where strings would be written are all accepted.
+        assert_eq!(round_trip(&loader, r#" { "id" : 7 , "name" : null, "unemployed" : null } "#),
+                   r#"{"id":"7","kind":"student","address":{},"unemployed":null}"#);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let loader = load_schema();
+        let schema = loader.find("Person").unwrap();
+        for json in &[r#"{"id": }"#, r#"{"id": 1"#, r#"[1]"#, r#"{"nope": 1}"#, r#"{"kind": "pupil"}"#,
+                      r#"{"photo": [256]}"#, r#"{"name": "\q"}"#, r#"{} x"#] {
+            let mut message = message::Builder::new_default();
+            assert!(super::read_struct(&loader, schema, json, message.init_root()).is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let loader = load_schema();
+        let schema = loader.find("Person").unwrap();
+        let json = format!("{{\"photo\": {}{}}}", "[".repeat(100_000), "]".repeat(100_000));
+        let mut message = message::Builder::new_default();
+        assert!(super::read_struct(&loader, schema, &json, message.init_root()).is_err());
+    }
+}
@@ -23,9 +23,11 @@
 //!
 //! This library allows you to do
 //! [Cap'n Proto code generation](https://capnproto.org/otherlang.html#how-to-write-compiler-plugins)
-//! within a Cargo build. You still need the `capnp` binary (implemented in C++).
-//! (If you use a package manager, try looking for a package called
-//! `capnproto`.)
+//! within a Cargo build. By default, schemas are compiled by the `capnp` binary (implemented in
+//! C++). (If you use a package manager, try looking for a package called `capnproto`.) To do
+//! without it, call `CompilerCommand::builtin_compiler()`, which compiles schemas with the
+//! [`compiler`](compiler/index.html) module instead. The standard imports under `/capnp/`, such
+//! as `/capnp/c++.capnp`, are built into that module.
 //!
 //! In your Cargo.toml:
 //!
@@ -64,9 +66,29 @@ extern crate capnp;
 /// [schema.capnp](https://github.com/capnproto/capnproto/blob/master/c%2B%2B/src/capnp/schema.capnp).
 pub mod schema_capnp;
 
+pub mod archive;
+pub mod cbor;
 pub mod codegen;
 pub mod codegen_types;
+pub mod compat;
+pub mod compiler;
+pub mod delta;
+pub mod diff;
+pub mod dynamic;
+pub mod gateway;
+pub mod json;
+pub mod msgpack;
+pub mod protobuf;
+pub mod random;
+pub mod redact;
+pub mod registry;
+pub mod schema_loader;
+pub mod scope_path;
+pub mod text_format;
+pub mod validate;
+mod parser;
 mod pointer_constants;
+mod self_describing;
 
 use std::path::{Path, PathBuf};
 
@@ -86,9 +108,14 @@ pub(crate) fn convert_io_err(err: std::io::Error) -> capnp::Error {
     capnp::Error { description: format!("{}", err), kind: kind }
 }
 
-fn run_command(mut command: ::std::process::Command, path: &PathBuf) -> ::capnp::Result<()> {
+fn run_command(mut command: ::std::process::Command, path: &PathBuf, check: bool,
+               options: crate::codegen::GeneratorOptions) -> ::capnp::Result<()> {
     let mut p = command.spawn().map_err(convert_io_err)?;
-    crate::codegen::generate_code(p.stdout.take().unwrap(), path.as_path())?;
+    if check {
+        crate::codegen::check_code_with_options(p.stdout.take().unwrap(), path.as_path(), options)?;
+    } else {
+        crate::codegen::generate_code_with_options(p.stdout.take().unwrap(), path.as_path(), options)?;
+    }
     let exit_status = p.wait().map_err(convert_io_err)?;
     if !exit_status.success() {
         Err(::capnp::Error::failed(format!(
@@ -107,7 +134,10 @@ pub struct CompilerCommand {
     import_paths: Vec<PathBuf>,
     no_standard_import: bool,
     executable_path: Option<PathBuf>,
+    builtin: bool,
     output_path: Option<PathBuf>,
+    check: bool,
+    options: crate::codegen::GeneratorOptions,
 }
 
 impl CompilerCommand {
@@ -119,7 +149,10 @@ impl CompilerCommand {
             import_paths: Vec::new(),
             no_standard_import: false,
             executable_path: None,
+            builtin: false,
             output_path: None,
+            check: false,
+            options: Default::default(),
         }
     }
 
@@ -168,6 +201,28 @@ impl CompilerCommand {
         self
     }
 
+    /// Makes `run()` write nothing, and instead fail if any generated file is missing from the
+    /// output directory or differs from the one there.
+    pub fn check(&mut self) -> &mut CompilerCommand {
+        self.check = true;
+        self
+    }
+
+    /// Adds a `#[cfg(test)]` module of round-trip tests to each generated struct, which check
+    /// that its accessors agree with each other. See `codegen::GeneratorOptions::inline_tests`.
+    pub fn inline_tests(&mut self) -> &mut CompilerCommand {
+        self.options.inline_tests = true;
+        self
+    }
+
+    /// Embeds the schema node of each generated struct, and gives each generated file a
+    /// `register_schemas()` function for loading them into a `registry::Registry`. See
+    /// `codegen::GeneratorOptions::embed_schemas`.
+    pub fn embed_schemas(&mut self) -> &mut CompilerCommand {
+        self.options.embed_schemas = true;
+        self
+    }
+
     /// Specify the executable which is used for the 'capnp' tool. When this method is not called, the command looks for a name 'capnp'
     /// on the system (e.g. in working directory or in PATH environment variable).
     pub fn capnp_executable<P>(&mut self, path: P) -> &mut CompilerCommand
@@ -178,15 +233,67 @@ impl CompilerCommand {
         self
     }
 
+    /// Compiles the schemas with `compiler::compile()` instead of the 'capnp' tool, so that the tool
+    /// doesn't need to be installed. Takes precedence over `capnp_executable()`.
+    pub fn builtin_compiler(&mut self) -> &mut CompilerCommand {
+        self.builtin = true;
+        self
+    }
+
     /// Runs the command.
-    /// Returns an error if `OUT_DIR` or a custom output directory was not set, or if `capnp compile` fails.
+    /// Returns an error if `OUT_DIR` or a custom output directory was not set, or if compiling the schemas fails.
     pub fn run(&mut self) -> ::capnp::Result<()> {
-        let mut command = if let Some(executable) = &self.executable_path {
-            ::std::process::Command::new(executable)
+        let output_path = if let Some(output_path) = &self.output_path {
+            output_path.clone()
         } else {
-            ::std::process::Command::new("capnp")
+            // Try `OUT_DIR` by default
+            PathBuf::from(::std::env::var("OUT_DIR").map_err(|error| {
+                ::capnp::Error::failed(format!(
+                    "Could not access `OUT_DIR` environment variable: {}. \
+                     You might need to set it up or instead create you own output \
+                     structure using `CompilerCommand::output_path`",
+                    error
+                ))
+            })?)
         };
 
+        if self.builtin {
+            self.run_builtin(&output_path)
+        } else {
+            let executable = self.executable_path.clone().unwrap_or_else(|| PathBuf::from("capnp"));
+            self.run_executable(&executable, &output_path)
+        }
+    }
+
+    /// Like `run()`, but returns the generated files instead of writing them, as pairs of a path
+    /// relative to the output directory and the file's text. Always uses the built-in compiler.
+    pub fn generate(&self) -> ::capnp::Result<Vec<(PathBuf, String)>> {
+        let message = self.compile_builtin()?;
+        crate::codegen::generate_files_with_options(&message.into_reader(), self.options)
+    }
+
+    fn compile_builtin(&self) -> ::capnp::Result<::capnp::message::Builder<::capnp::message::HeapAllocator>> {
+        let mut import_paths = self.import_paths.clone();
+        if !self.no_standard_import {
+            import_paths.push(PathBuf::from("/usr/local/include"));
+            import_paths.push(PathBuf::from("/usr/include"));
+        }
+        compiler::compile(&self.files, &self.src_prefixes, &import_paths)
+    }
+
+    fn run_builtin(&self, output_path: &PathBuf) -> ::capnp::Result<()> {
+        let message = self.compile_builtin()?;
+        let bytes = ::capnp::serialize::write_message_to_words(&message);
+        if self.check {
+            crate::codegen::check_code_with_options(&bytes[..], output_path.as_path(), self.options)
+        } else {
+            crate::codegen::generate_code_with_options(&bytes[..], output_path.as_path(), self.options)
+        }
+    }
+
+    fn run_executable(&self, executable: &PathBuf, output_path: &PathBuf) -> ::capnp::Result<()> {
+        let mut command = ::std::process::Command::new(executable);
+
         command.arg("compile").arg("-o").arg("-");
 
         if self.no_standard_import {
@@ -205,24 +312,10 @@ impl CompilerCommand {
             command.arg(&format!("{}", file.display()));
         }
 
-        let output_path = if let Some(output_path) = &self.output_path {
-            output_path.clone()
-        } else {
-            // Try `OUT_DIR` by default
-            PathBuf::from(::std::env::var("OUT_DIR").map_err(|error| {
-                ::capnp::Error::failed(format!(
-                    "Could not access `OUT_DIR` environment variable: {}. \
-                     You might need to set it up or instead create you own output \
-                     structure using `CompilerCommand::output_path`",
-                    error
-                ))
-            })?)
-        };
-
         command.stdout(::std::process::Stdio::piped());
         command.stderr(::std::process::Stdio::inherit());
 
-        run_command(command, &output_path).map_err(|error| {
+        run_command(command, output_path, self.check, self.options).map_err(|error| {
             ::capnp::Error::failed(format!(
                 "Error while trying to execute `capnp compile`: {}.  \
                  Please verify that version 0.5.2 or higher of the capnp executable \
@@ -244,3 +337,22 @@ fn compiler_command_with_output_path_no_out_dir() {
     let error = CompilerCommand::new().output_path("foo").run().unwrap_err().description;
     assert!(error.starts_with("Error while trying to execute `capnp compile`"));
 }
+
+#[test]
+fn compiler_command_builtin_with_standard_import() {
+    let dir = ::std::env::temp_dir().join(format!("capnpc-builtin-{}", ::std::process::id()));
+    ::std::fs::create_dir_all(&dir).unwrap();
+    let schema = dir.join("foo.capnp");
+    ::std::fs::write(&schema, "@0xc8c3ed4a5a5a6c5e;\n\
+                                using Cxx = import \"/capnp/c++.capnp\";\n\
+                                $Cxx.namespace(\"foo\");\n\
+                                struct Foo { bar @0 :UInt8; }\n").unwrap();
+
+    // Neither the 'capnp' tool nor an installed copy of c++.capnp is needed.
+    let result = CompilerCommand::new().builtin_compiler().capnp_executable("no-such-capnp")
+        .no_standard_import().src_prefix(&dir).file(&schema).output_path(&dir).run();
+    let generated = ::std::fs::read_to_string(dir.join("foo_capnp.rs"));
+    let _ = ::std::fs::remove_dir_all(&dir);
+    result.unwrap();
+    assert!(generated.unwrap().contains("pub mod foo {"));
+}
@@ -0,0 +1,296 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! MessagePack encoding of Cap'n Proto values, with the same mapping as the `cbor` module.
+//!
+//! Structs are maps keyed by field name, lists are arrays, `Text` is a str, `Data` a bin, `Void`
+//! is nil and enums are the names of their enumerants. Integers use their smallest encoding.
+//! When reading, enums may also be given as numbers and members whose value is nil are skipped.
+//! Extension types are not supported.
+
+use capnp::{any_pointer, Error, Result};
+
+use crate::dynamic::{StructBuilder, Value};
+use crate::schema_capnp::node;
+use crate::schema_loader::SchemaLoader;
+use crate::self_describing::{self, Item};
+
+/// Encodes `value` as MessagePack.
+pub fn to_vec(value: Value) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    write_item(&mut out, &self_describing::from_value("MessagePack", value)?);
+    Ok(out)
+}
+
+/// Decodes `bytes`, a MessagePack map, and writes it to `builder` as a struct of type `schema`.
+pub fn read_struct(loader: &SchemaLoader, schema: node::Reader, bytes: &[u8],
+                   builder: any_pointer::Builder) -> Result<()>
+{
+    read_struct_into(&mut StructBuilder::init_any_pointer(loader, schema, builder)?, bytes)
+}
+
+/// Decodes `bytes`, a MessagePack map, and sets the fields that it names in `builder`.
+pub fn read_struct_into(builder: &mut StructBuilder, bytes: &[u8]) -> Result<()> {
+    let mut decoder = Decoder { bytes: bytes, pos: 0, depth: 0 };
+    let item = decoder.read_item()?;
+    if decoder.pos < bytes.len() {
+        return msgpack_error("unexpected trailing bytes");
+    }
+    self_describing::fill_struct("MessagePack", builder, item)
+}
+
+fn msgpack_error<T>(message: &str) -> Result<T> {
+    Err(Error::failed(format!("MessagePack: {}", message)))
+}
+
+/// Writes the header of a str, bin, array or map of length `n`. `fix` is the fixed-size form's
+/// marker and `fix_limit` its exclusive size limit, or 0 if there is none; `markers` are the
+/// 8-bit (if any), 16-bit and 32-bit forms.
+fn write_length(out: &mut Vec<u8>, n: usize, fix: u8, fix_limit: usize, markers: &[u8]) {
+    if n < fix_limit {
+        out.push(fix | n as u8);
+    } else if markers.len() == 3 && n <= 0xff {
+        out.push(markers[0]);
+        out.push(n as u8);
+    } else if n <= 0xffff {
+        out.push(markers[markers.len() - 2]);
+        out.extend_from_slice(&(n as u16).to_be_bytes());
+    } else {
+        out.push(markers[markers.len() - 1]);
+        out.extend_from_slice(&(n as u32).to_be_bytes());
+    }
+}
+
+fn write_item(out: &mut Vec<u8>, item: &Item) {
+    match *item {
+        Item::Null => out.push(0xc0),
+        Item::Bool(b) => out.push(if b { 0xc3 } else { 0xc2 }),
+        Item::Int(n) if n >= 0 && n < 0x80 => out.push(n as u8),
+        Item::Int(n) if n < 0 && n >= -32 => out.push(n as i8 as u8),
+        Item::Int(n) if n >= 0 && n <= 0xff => { out.push(0xcc); out.push(n as u8); }
+        Item::Int(n) if n >= 0 && n <= 0xffff => { out.push(0xcd); out.extend_from_slice(&(n as u16).to_be_bytes()); }
+        Item::Int(n) if n >= 0 && n <= 0xffff_ffff => { out.push(0xce); out.extend_from_slice(&(n as u32).to_be_bytes()); }
+        Item::Int(n) if n >= 0 => { out.push(0xcf); out.extend_from_slice(&(n as u64).to_be_bytes()); }
+        Item::Int(n) if n >= -0x80 => { out.push(0xd0); out.push(n as i8 as u8); }
+        Item::Int(n) if n >= -0x8000 => { out.push(0xd1); out.extend_from_slice(&(n as i16).to_be_bytes()); }
+        Item::Int(n) if n >= -0x8000_0000 => { out.push(0xd2); out.extend_from_slice(&(n as i32).to_be_bytes()); }
+        Item::Int(n) => { out.push(0xd3); out.extend_from_slice(&(n as i64).to_be_bytes()); }
+        Item::Float32(n) => {
+            out.push(0xca);
+            out.extend_from_slice(&n.to_bits().to_be_bytes());
+        }
+        Item::Float64(n) => {
+            out.push(0xcb);
+            out.extend_from_slice(&n.to_bits().to_be_bytes());
+        }
+        Item::Bytes(ref b) => {
+            write_length(out, b.len(), 0, 0, &[0xc4, 0xc5, 0xc6]);
+            out.extend_from_slice(b);
+        }
+        Item::Text(ref s) => {
+            write_length(out, s.len(), 0xa0, 32, &[0xd9, 0xda, 0xdb]);
+            out.extend_from_slice(s.as_bytes());
+        }
+        Item::Array(ref items) => {
+            write_length(out, items.len(), 0x90, 16, &[0xdc, 0xdd]);
+            for item in items {
+                write_item(out, item);
+            }
+        }
+        Item::Map(ref members) => {
+            write_length(out, members.len(), 0x80, 16, &[0xde, 0xdf]);
+            for &(ref key, ref value) in members {
+                write_item(out, key);
+                write_item(out, value);
+            }
+        }
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    depth: u32,
+}
+
+impl <'a> Decoder<'a> {
+    fn take(&mut self, n: u64) -> Result<&'a [u8]> {
+        if (self.bytes.len() - self.pos) as u64 >= n {
+            let result = &self.bytes[self.pos..self.pos + n as usize];
+            self.pos += n as usize;
+            Ok(result)
+        } else {
+            msgpack_error("unexpected end of input")
+        }
+    }
+
+    fn read_uint(&mut self, width: usize) -> Result<u64> {
+        Ok(self.take(width as u64)?.iter().fold(0, |n, &b| n << 8 | b as u64))
+    }
+
+    fn read_int(&mut self, width: usize) -> Result<i128> {
+        let n = self.read_uint(width)?;
+        let shift = 64 - 8 * width as u32;
+        Ok(((n << shift) as i64 >> shift) as i128)
+    }
+
+    fn read_array(&mut self, n: u64) -> Result<Item> {
+        if n > (self.bytes.len() - self.pos) as u64 {
+            return msgpack_error("unexpected end of input");
+        }
+        let mut items = Vec::with_capacity(n as usize);
+        for _ in 0..n {
+            items.push(self.read_item()?);
+        }
+        Ok(Item::Array(items))
+    }
+
+    fn read_map(&mut self, n: u64) -> Result<Item> {
+        if n > (self.bytes.len() - self.pos) as u64 {
+            return msgpack_error("unexpected end of input");
+        }
+        let mut members = Vec::with_capacity(n as usize);
+        for _ in 0..n {
+            let key = self.read_item()?;
+            members.push((key, self.read_item()?));
+        }
+        Ok(Item::Map(members))
+    }
+
+    fn read_str(&mut self, n: u64) -> Result<Item> {
+        match String::from_utf8(self.take(n)?.to_vec()) {
+            Ok(s) => Ok(Item::Text(s)),
+            Err(_) => msgpack_error("str is not valid UTF-8"),
+        }
+    }
+
+    fn read_item(&mut self) -> Result<Item> {
+        if self.depth >= self_describing::NESTING_LIMIT {
+            return msgpack_error("nesting limit exceeded");
+        }
+        self.depth += 1;
+        let result = self.read_item_inner();
+        self.depth -= 1;
+        result
+    }
+
+    fn read_item_inner(&mut self) -> Result<Item> {
+        let marker = self.take(1)?[0];
+        Ok(match marker {
+            0x00..=0x7f => Item::Int(marker as i128),
+            0x80..=0x8f => self.read_map((marker & 0x0f) as u64)?,
+            0x90..=0x9f => self.read_array((marker & 0x0f) as u64)?,
+            0xa0..=0xbf => self.read_str((marker & 0x1f) as u64)?,
+            0xc0 => Item::Null,
+            0xc2 => Item::Bool(false),
+            0xc3 => Item::Bool(true),
+            0xc4 => { let n = self.read_uint(1)?; Item::Bytes(self.take(n)?.to_vec()) }
+            0xc5 => { let n = self.read_uint(2)?; Item::Bytes(self.take(n)?.to_vec()) }
+            0xc6 => { let n = self.read_uint(4)?; Item::Bytes(self.take(n)?.to_vec()) }
+            0xca => Item::Float32(f32::from_bits(self.read_uint(4)? as u32)),
+            0xcb => Item::Float64(f64::from_bits(self.read_uint(8)?)),
+            0xcc => Item::Int(self.read_uint(1)? as i128),
+            0xcd => Item::Int(self.read_uint(2)? as i128),
+            0xce => Item::Int(self.read_uint(4)? as i128),
+            0xcf => Item::Int(self.read_uint(8)? as i128),
+            0xd0 => Item::Int(self.read_int(1)?),
+            0xd1 => Item::Int(self.read_int(2)?),
+            0xd2 => Item::Int(self.read_int(4)?),
+            0xd3 => Item::Int(self.read_int(8)?),
+            0xd9 => { let n = self.read_uint(1)?; self.read_str(n)? }
+            0xda => { let n = self.read_uint(2)?; self.read_str(n)? }
+            0xdb => { let n = self.read_uint(4)?; self.read_str(n)? }
+            0xdc => { let n = self.read_uint(2)?; self.read_array(n)? }
+            0xdd => { let n = self.read_uint(4)?; self.read_array(n)? }
+            0xde => { let n = self.read_uint(2)?; self.read_map(n)? }
+            0xdf => { let n = self.read_uint(4)?; self.read_map(n)? }
+            0xe0..=0xff => Item::Int(marker as i8 as i128),
+            _ => return msgpack_error(&format!("unsupported type marker {:#04x}", marker)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use capnp::{any_pointer, message};
+    use crate::dynamic::{StructReader, Value};
+    use crate::schema_loader::SchemaLoader;
+
+    fn load_schema() -> SchemaLoader {
+        let text = "@0xe1c2b3a4d5f60718;
+                    struct Reading {
+                      sensor @0 :Text;
+                      value @1 :Float32;
+                      raw @2 :Data;
+                      offsets @3 :List(Int64);
+                      count @4 :UInt32;
+                    }".to_string();
+        let message = crate::compiler::compile_with(&[PathBuf::from("reading.capnp")], &[], &[], &move |path: &Path| {
+            if path == Path::new("reading.capnp") {
+                Ok(text.clone())
+            } else {
+                Err(::capnp::Error::failed("no such file".to_string()))
+            }
+        }).unwrap();
+        let mut loader = SchemaLoader::new();
+        loader.load_request(message.into_reader()).unwrap();
+        loader
+    }
+
+    #[test]
+    fn encode_and_decode() {
+        let loader = load_schema();
+        let schema = loader.find("Reading").unwrap();
+        let bytes = [
+            0x85,
+            0xa6, b's', b'e', b'n', b's', b'o', b'r', 0xa2, b't', b'1',
+            0xa5, b'v', b'a', b'l', b'u', b'e', 0xca, 0x3f, 0xc0, 0x00, 0x00,
+            0xa3, b'r', b'a', b'w', 0xc4, 0x02, 0x00, 0xff,
+            0xa7, b'o', b'f', b'f', b's', b'e', b't', b's', 0x93, 0xff, 0xd0, 0x9c, 0xd3, 0x80, 0, 0, 0, 0, 0, 0, 0,
+            0xa5, b'c', b'o', b'u', b'n', b't', 0xce, 0xff, 0xff, 0xff, 0xff,
+        ];
+        let mut message = message::Builder::new_default();
+        super::read_struct(&loader, schema, &bytes, message.init_root()).unwrap();
+        let reader = StructReader::from_any_pointer(
+            &loader, schema, message.get_root_as_reader::<any_pointer::Reader>().unwrap()).unwrap();
+        assert_eq!(crate::json::to_string(Value::Struct(reader.clone())).unwrap(),
+                   concat!(r#"{"sensor":"t1","value":1.5,"raw":[0,255],"#,
+                           r#""offsets":["-1","-100","-9223372036854775808"],"count":4294967295}"#));
+        assert_eq!(&super::to_vec(Value::Struct(reader)).unwrap()[..], &bytes[..]);
+
+        for bytes in &[&[0x81, 0xa5, b'c', b'o', b'u', b'n', b't', 0xff][..], &[0x81, 0xa1][..], &[0xc7, 0x00][..]] {
+            let mut message = message::Builder::new_default();
+            assert!(super::read_struct(&loader, schema, bytes, message.init_root()).is_err(), "{:?}", bytes);
+        }
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let loader = load_schema();
+        let schema = loader.find("Reading").unwrap();
+        let mut bytes = vec![0x91; 100_000]; // one-element arrays
+        bytes.push(0x80);
+        let mut message = message::Builder::new_default();
+        assert!(super::read_struct(&loader, schema, &bytes, message.init_root()).is_err());
+    }
+}
@@ -0,0 +1,495 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Parses `.capnp` schema files into declarations, for the `compiler` module.
+//!
+//! Types, values and annotation applications are parsed as text format expressions; it is up
+//! to the compiler to decide what they refer to.
+
+use capnp::Result;
+
+use crate::text_format::{Expr, Parser, Token};
+
+/// A declaration, together with the declarations nested inside it.
+#[derive(Debug)]
+pub(crate) struct Decl {
+    pub(crate) name: String,
+    pub(crate) line: usize,
+    /// An explicit `@0x...` ID, for nodes, or the file ID.
+    pub(crate) id: Option<u64>,
+    /// The `@N` ordinal of a field, union, enumerant or method.
+    pub(crate) ordinal: Option<u16>,
+    /// Generic parameters of a struct or interface.
+    pub(crate) params: Vec<String>,
+    pub(crate) annotations: Vec<Expr>,
+    pub(crate) kind: DeclKind,
+    pub(crate) nested: Vec<Decl>,
+}
+
+#[derive(Debug)]
+pub(crate) enum DeclKind {
+    File,
+    Using(Expr),
+    Const { typ: Expr, value: Expr },
+    Annotation { typ: Expr, targets: Vec<String> },
+    Struct,
+    Field { typ: Expr, default: Option<Expr> },
+    /// A union; unnamed unions have an empty name.
+    Union,
+    Group,
+    Enum,
+    Enumerant,
+    Interface { superclasses: Vec<Expr> },
+    Method { implicit_params: Vec<String>, params: ParamList, results: Option<ParamList> },
+}
+
+#[derive(Debug)]
+pub(crate) enum ParamList {
+    /// A struct type, used as is.
+    Type(Expr),
+    /// A list of parameters, which become the fields of a new struct. Each is a `Field`
+    /// declaration whose ordinal is its position.
+    Fields(Vec<Decl>),
+}
+
+impl Decl {
+    fn new(name: String, line: usize, kind: DeclKind) -> Decl {
+        Decl {
+            name: name,
+            line: line,
+            id: None,
+            ordinal: None,
+            params: Vec::new(),
+            annotations: Vec::new(),
+            kind: kind,
+            nested: Vec::new(),
+        }
+    }
+}
+
+/// Parses the schema file `text`. `file` is used in error messages.
+pub(crate) fn parse_file(file: &str, text: &str) -> Result<Decl> {
+    let mut parser = SchemaParser { p: Parser::for_file(file, text)? };
+    let mut decl = Decl::new(file.to_string(), 1, DeclKind::File);
+    while parser.p.peek().is_some() {
+        match parser.p.peek() {
+            Some(Token::At) => {
+                if decl.id.is_some() {
+                    return parser.p.error("file ID declared twice");
+                }
+                decl.id = Some(parser.parse_id()?);
+                parser.p.expect(Token::Semicolon, ";")?;
+            }
+            Some(Token::Dollar) => {
+                decl.annotations.extend(parser.parse_annotations()?);
+                parser.p.expect(Token::Semicolon, ";")?;
+            }
+            _ => match parser.parse_nested_decl()? {
+                Some(nested) => decl.nested.push(nested),
+                None => return parser.p.error("expected a declaration"),
+            },
+        }
+    }
+    Ok(decl)
+}
+
+/// Calls `f` on the path of every `import` in `decl`.
+pub(crate) fn for_each_import<F>(decl: &Decl, f: &mut F) where F: FnMut(&str) {
+    fn visit<F>(expr: &Expr, f: &mut F) where F: FnMut(&str) {
+        match *expr {
+            Expr::Import(ref path) => f(path),
+            Expr::Member(ref base, _) => visit(base, f),
+            Expr::Apply(ref base, ref args) => {
+                visit(base, f);
+                for &(_, ref arg) in args { visit(arg, f); }
+            }
+            Expr::List(ref items) => for item in items { visit(item, f); },
+            Expr::Tuple(ref items) => for &(_, ref item) in items { visit(item, f); },
+            _ => (),
+        }
+    }
+    fn visit_params<F>(params: &ParamList, f: &mut F) where F: FnMut(&str) {
+        match *params {
+            ParamList::Type(ref typ) => visit(typ, f),
+            ParamList::Fields(ref fields) => for field in fields { for_each_import(field, f); },
+        }
+    }
+
+    for annotation in &decl.annotations {
+        visit(annotation, f);
+    }
+    match decl.kind {
+        DeclKind::Using(ref target) => visit(target, f),
+        DeclKind::Const { ref typ, ref value } => { visit(typ, f); visit(value, f); }
+        DeclKind::Annotation { ref typ, .. } => visit(typ, f),
+        DeclKind::Field { ref typ, ref default } => {
+            visit(typ, f);
+            if let Some(ref default) = *default { visit(default, f); }
+        }
+        DeclKind::Interface { ref superclasses } => for s in superclasses { visit(s, f); },
+        DeclKind::Method { ref params, ref results, .. } => {
+            visit_params(params, f);
+            if let Some(ref results) = *results { visit_params(results, f); }
+        }
+        _ => (),
+    }
+    for nested in &decl.nested {
+        for_each_import(nested, f);
+    }
+}
+
+struct SchemaParser {
+    p: Parser,
+}
+
+impl SchemaParser {
+    fn ident(&mut self, what: &str) -> Result<String> {
+        match self.p.next()? {
+            Token::Ident(name) => Ok(name),
+            _ => {
+                self.p.pos -= 1;
+                self.p.error(&format!("expected {}", what))
+            }
+        }
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        match self.p.peek() {
+            Some(Token::Ident(name)) => name == keyword,
+            _ => false,
+        }
+    }
+
+    /// Parses `@N`, where N is a 64-bit ID.
+    fn parse_id(&mut self) -> Result<u64> {
+        self.p.expect(Token::At, "@")?;
+        match self.p.next()? {
+            Token::Int(false, id) if id & (1 << 63) != 0 => Ok(id),
+            Token::Int(false, _) => {
+                self.p.pos -= 1;
+                self.p.error("invalid ID; the high bit of an ID must be set")
+            }
+            _ => {
+                self.p.pos -= 1;
+                self.p.error("expected an ID")
+            }
+        }
+    }
+
+    fn maybe_id(&mut self) -> Result<Option<u64>> {
+        if self.p.peek() == Some(&Token::At) { Ok(Some(self.parse_id()?)) } else { Ok(None) }
+    }
+
+    /// Parses `@N`, where N is an ordinal, allowing the trailing `!` that suppresses ordinal
+    /// warnings in the C++ compiler.
+    fn parse_ordinal(&mut self) -> Result<u16> {
+        self.p.expect(Token::At, "@ and an ordinal")?;
+        let ordinal = match self.p.next()? {
+            Token::Int(false, n) if n < 65536 => n as u16,
+            _ => {
+                self.p.pos -= 1;
+                return self.p.error("expected an ordinal between 0 and 65535");
+            }
+        };
+        if self.p.peek() == Some(&Token::Bang) {
+            self.p.pos += 1;
+        }
+        Ok(ordinal)
+    }
+
+    fn parse_annotations(&mut self) -> Result<Vec<Expr>> {
+        let mut annotations = Vec::new();
+        while self.p.peek() == Some(&Token::Dollar) {
+            self.p.pos += 1;
+            annotations.push(self.p.parse_expr()?);
+        }
+        Ok(annotations)
+    }
+
+    /// Parses `(A, B, ...)`, a list of names, if present.
+    fn parse_names(&mut self, open: Token, close: Token) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        if self.p.peek() != Some(&open) {
+            return Ok(names);
+        }
+        self.p.pos += 1;
+        while self.p.peek() != Some(&close) {
+            names.push(self.ident("a name")?);
+            if self.p.peek() == Some(&Token::Comma) {
+                self.p.pos += 1;
+            } else {
+                break;
+            }
+        }
+        self.p.expect(close, "end of list")?;
+        Ok(names)
+    }
+
+    /// Parses a declaration that can appear at file, struct or interface scope. Returns `None`
+    /// if the next tokens are not such a declaration.
+    fn parse_nested_decl(&mut self) -> Result<Option<Decl>> {
+        let keyword = match self.p.peek() {
+            Some(Token::Ident(name)) => name.clone(),
+            _ => return Ok(None),
+        };
+        // A keyword followed by an ordinal or a colon is the name of a field, group or method.
+        match self.p.peek_at(1) {
+            Some(&Token::At) | Some(&Token::Colon) => return Ok(None),
+            _ => (),
+        }
+        let line = self.p.line();
+        let decl = match &keyword[..] {
+            "using" => {
+                self.p.pos += 1;
+                let name = match (self.p.peek(), self.p.peek_at(1)) {
+                    (Some(Token::Ident(name)), Some(Token::Equals)) => {
+                        let name = name.clone();
+                        self.p.pos += 2;
+                        Some(name)
+                    }
+                    _ => None,
+                };
+                let target = self.p.parse_expr()?;
+                let name = match (name, &target) {
+                    (Some(name), _) => name,
+                    (None, &Expr::Ident(ref name)) | (None, &Expr::Member(_, ref name)) => name.clone(),
+                    _ => return self.p.error("using declaration needs a name"),
+                };
+                self.p.expect(Token::Semicolon, ";")?;
+                Decl::new(name, line, DeclKind::Using(target))
+            }
+            "const" => {
+                self.p.pos += 1;
+                let name = self.ident("a constant name")?;
+                let id = self.maybe_id()?;
+                self.p.expect(Token::Colon, ": and a type")?;
+                let typ = self.p.parse_expr()?;
+                self.p.expect(Token::Equals, "= and a value")?;
+                let value = self.p.parse_expr()?;
+                let mut decl = Decl::new(name, line, DeclKind::Const { typ: typ, value: value });
+                decl.id = id;
+                decl.annotations = self.parse_annotations()?;
+                self.p.expect(Token::Semicolon, ";")?;
+                decl
+            }
+            "annotation" => {
+                self.p.pos += 1;
+                let name = self.ident("an annotation name")?;
+                let id = self.maybe_id()?;
+                let targets = if self.p.peek_at(1) == Some(&Token::Star) {
+                    self.p.expect(Token::LParen, "(")?;
+                    self.p.expect(Token::Star, "*")?;
+                    self.p.expect(Token::RParen, ")")?;
+                    vec!["*".to_string()]
+                } else {
+                    self.parse_names(Token::LParen, Token::RParen)?
+                };
+                self.p.expect(Token::Colon, ": and a type")?;
+                let typ = self.p.parse_expr()?;
+                let mut decl = Decl::new(name, line, DeclKind::Annotation { typ: typ, targets: targets });
+                decl.id = id;
+                decl.annotations = self.parse_annotations()?;
+                self.p.expect(Token::Semicolon, ";")?;
+                decl
+            }
+            "struct" | "interface" => {
+                self.p.pos += 1;
+                let name = self.ident("a type name")?;
+                // The id may come before or after the generic parameters.
+                let mut id = self.maybe_id()?;
+                let params = self.parse_names(Token::LParen, Token::RParen)?;
+                if id.is_none() {
+                    id = self.maybe_id()?;
+                }
+                let kind = if keyword == "struct" {
+                    DeclKind::Struct
+                } else {
+                    let mut superclasses = Vec::new();
+                    if self.is_keyword("extends") {
+                        self.p.pos += 1;
+                        self.p.expect(Token::LParen, "(")?;
+                        while self.p.peek() != Some(&Token::RParen) {
+                            superclasses.push(self.p.parse_expr()?);
+                            if self.p.peek() == Some(&Token::Comma) {
+                                self.p.pos += 1;
+                            } else {
+                                break;
+                            }
+                        }
+                        self.p.expect(Token::RParen, ")")?;
+                    }
+                    DeclKind::Interface { superclasses: superclasses }
+                };
+                let mut decl = Decl::new(name, line, kind);
+                decl.id = id;
+                decl.params = params;
+                decl.annotations = self.parse_annotations()?;
+                self.p.expect(Token::LBrace, "{")?;
+                while self.p.peek() != Some(&Token::RBrace) {
+                    let member = if keyword == "struct" {
+                        self.parse_struct_member()?
+                    } else {
+                        self.parse_interface_member()?
+                    };
+                    decl.nested.push(member);
+                }
+                self.p.expect(Token::RBrace, "}")?;
+                decl
+            }
+            "enum" => {
+                self.p.pos += 1;
+                let name = self.ident("an enum name")?;
+                let mut decl = Decl::new(name, line, DeclKind::Enum);
+                decl.id = self.maybe_id()?;
+                decl.annotations = self.parse_annotations()?;
+                self.p.expect(Token::LBrace, "{")?;
+                while self.p.peek() != Some(&Token::RBrace) {
+                    match self.parse_nested_decl()? {
+                        Some(nested) => decl.nested.push(nested),
+                        None => {
+                            let line = self.p.line();
+                            let name = self.ident("an enumerant")?;
+                            let mut enumerant = Decl::new(name, line, DeclKind::Enumerant);
+                            enumerant.ordinal = Some(self.parse_ordinal()?);
+                            enumerant.annotations = self.parse_annotations()?;
+                            self.p.expect(Token::Semicolon, ";")?;
+                            decl.nested.push(enumerant);
+                        }
+                    }
+                }
+                self.p.expect(Token::RBrace, "}")?;
+                decl
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(decl))
+    }
+
+    fn parse_struct_member(&mut self) -> Result<Decl> {
+        if let Some(decl) = self.parse_nested_decl()? {
+            return Ok(decl);
+        }
+        let line = self.p.line();
+        if self.is_keyword("union") && self.p.peek_at(1) != Some(&Token::At) &&
+            self.p.peek_at(1) != Some(&Token::Colon)
+        {
+            self.p.pos += 1;
+            let decl = Decl::new(String::new(), line, DeclKind::Union);
+            return self.parse_group_body(decl);
+        }
+        let name = self.ident("a field")?;
+        let ordinal = if self.p.peek() == Some(&Token::At) { Some(self.parse_ordinal()?) } else { None };
+        self.p.expect(Token::Colon, ": and a type")?;
+        if self.is_keyword("union") || self.is_keyword("group") {
+            let kind = if self.is_keyword("union") { DeclKind::Union } else { DeclKind::Group };
+            self.p.pos += 1;
+            let mut decl = Decl::new(name, line, kind);
+            decl.ordinal = ordinal;
+            return self.parse_group_body(decl);
+        }
+        let mut decl = self.parse_field_rest(name, line)?;
+        decl.ordinal = match ordinal {
+            Some(ordinal) => Some(ordinal),
+            None => return self.p.error("field needs an ordinal"),
+        };
+        self.p.expect(Token::Semicolon, ";")?;
+        Ok(decl)
+    }
+
+    /// Parses the type, default value and annotations of a field or parameter, after its colon.
+    fn parse_field_rest(&mut self, name: String, line: usize) -> Result<Decl> {
+        let typ = self.p.parse_expr()?;
+        let default = if self.p.peek() == Some(&Token::Equals) {
+            self.p.pos += 1;
+            Some(self.p.parse_expr()?)
+        } else {
+            None
+        };
+        let mut decl = Decl::new(name, line, DeclKind::Field { typ: typ, default: default });
+        decl.annotations = self.parse_annotations()?;
+        Ok(decl)
+    }
+
+    /// Parses the optional ordinal, the annotations and the members of a union or group.
+    fn parse_group_body(&mut self, mut decl: Decl) -> Result<Decl> {
+        if self.p.peek() == Some(&Token::At) {
+            decl.ordinal = Some(self.parse_ordinal()?);
+        }
+        decl.annotations = self.parse_annotations()?;
+        self.p.expect(Token::LBrace, "{")?;
+        while self.p.peek() != Some(&Token::RBrace) {
+            let member = self.parse_struct_member()?;
+            match member.kind {
+                DeclKind::Field { .. } | DeclKind::Union | DeclKind::Group => (),
+                _ => return self.p.error("unions and groups can only contain fields"),
+            }
+            decl.nested.push(member);
+        }
+        self.p.expect(Token::RBrace, "}")?;
+        Ok(decl)
+    }
+
+    fn parse_interface_member(&mut self) -> Result<Decl> {
+        if let Some(decl) = self.parse_nested_decl()? {
+            return Ok(decl);
+        }
+        let line = self.p.line();
+        let name = self.ident("a method")?;
+        let ordinal = self.parse_ordinal()?;
+        let implicit_params = self.parse_names(Token::LBracket, Token::RBracket)?;
+        let params = self.parse_param_list()?;
+        let results = if self.p.peek() == Some(&Token::Arrow) {
+            self.p.pos += 1;
+            Some(self.parse_param_list()?)
+        } else {
+            None
+        };
+        let kind = DeclKind::Method { implicit_params: implicit_params, params: params, results: results };
+        let mut decl = Decl::new(name, line, kind);
+        decl.ordinal = Some(ordinal);
+        decl.annotations = self.parse_annotations()?;
+        self.p.expect(Token::Semicolon, ";")?;
+        Ok(decl)
+    }
+
+    fn parse_param_list(&mut self) -> Result<ParamList> {
+        if self.p.peek() != Some(&Token::LParen) {
+            return Ok(ParamList::Type(self.p.parse_expr()?));
+        }
+        self.p.pos += 1;
+        let mut fields = Vec::new();
+        while self.p.peek() != Some(&Token::RParen) {
+            let line = self.p.line();
+            let name = self.ident("a parameter name")?;
+            self.p.expect(Token::Colon, ": and a type")?;
+            let mut field = self.parse_field_rest(name, line)?;
+            field.ordinal = Some(fields.len() as u16);
+            fields.push(field);
+            if self.p.peek() == Some(&Token::Comma) {
+                self.p.pos += 1;
+            } else {
+                break;
+            }
+        }
+        self.p.expect(Token::RParen, ")")?;
+        Ok(ParamList::Fields(fields))
+    }
+}
@@ -23,7 +23,7 @@ use capnp::{any_pointer, message};
 use crate::codegen::{FormattedText, GeneratorContext};
 use crate::codegen::FormattedText::{Indent, Line, Branch};
 use crate::codegen_types::{ Leaf, RustTypeInfo };
-use crate::schema_capnp::{type_};
+use crate::schema_capnp::{node, type_};
 
 pub struct WordArrayDeclarationOptions {
     pub public: bool,
@@ -62,10 +62,23 @@ pub fn generate_pointer_constant(
     typ: type_::Reader,
     value: any_pointer::Reader)
     -> ::capnp::Result<FormattedText>
+{
+    constant_reader_declaration(styled_name, &typ.type_string(gen, Leaf::Owned)?, value)
+}
+
+/// Embeds `node` as the `SCHEMA` constant of a struct's `_private` module, for
+/// `GeneratorOptions::embed_schemas`.
+pub fn generate_schema_constant(node: node::Reader) -> ::capnp::Result<FormattedText> {
+    let mut message = message::Builder::new_default();
+    message.set_root(node)?;
+    constant_reader_declaration("SCHEMA", "::capnp::any_pointer::Owned", message.get_root_as_reader()?)
+}
+
+fn constant_reader_declaration(name: &str, owned_type: &str, value: any_pointer::Reader)
+                               -> ::capnp::Result<FormattedText>
 {
     Ok(Branch(vec![
-        Line(format!("pub static {}: ::capnp::constant::Reader<{}> = {{",
-                     styled_name, typ.type_string(gen, Leaf::Owned)?)),
+        Line(format!("pub static {}: ::capnp::constant::Reader<{}> = {{", name, owned_type)),
         Indent(Box::new(Branch(vec![
             word_array_declaration("WORDS", value, WordArrayDeclarationOptions { public: false, omit_first_word: false })?,
             Line("::capnp::constant::Reader {".into()),
@@ -0,0 +1,757 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Conversion of protobuf schemas to Cap'n Proto schemas, to help with migrating existing
+//! protobuf services.
+//!
+//! `convert()` takes a serialized `FileDescriptorSet`, as written by
+//! `protoc --include_imports --descriptor_set_out=...`, and writes a Cap'n Proto schema file for
+//! each `.proto` file in it. `Conversion::compile()` compiles the result into schema nodes, in a
+//! `CodeGeneratorRequest` that can be loaded into a `SchemaLoader` or handed to code generation.
+//!
+//! The conversion is best-effort. Messages become structs, enums become enums, oneofs become
+//! named unions, maps become lists of their entry structs, and services become interfaces whose
+//! methods take the request message as parameters and return the response message. Anything that
+//! has no Cap'n Proto equivalent, or only an approximate one, is listed in `Conversion::report`:
+//! for example streaming methods, extensions, and enums whose numbers aren't `0, 1, 2, ...`.
+//! Protobuf field numbers are kept in comments, since Cap'n Proto ordinals have to be
+//! consecutive.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use capnp::{message, Error, Result};
+
+/// The Cap'n Proto schemas converted from a `FileDescriptorSet`.
+pub struct Conversion {
+    pub files: Vec<ConvertedFile>,
+
+    /// Human-readable notes on protobuf features that could not be converted exactly.
+    pub report: Vec<String>,
+}
+
+/// One converted `.proto` file.
+pub struct ConvertedFile {
+    /// The name of the `.proto` file.
+    pub proto_name: String,
+
+    /// The name of the schema file, which is `proto_name` with `.proto` replaced by `.capnp`.
+    /// Imports between converted files refer to each other by this name, as absolute imports.
+    pub name: String,
+
+    /// The text of the schema file.
+    pub text: String,
+}
+
+impl Conversion {
+    /// Compiles all of the converted files into a `CodeGeneratorRequest`.
+    pub fn compile(&self) -> Result<message::Builder<message::HeapAllocator>> {
+        let paths: Vec<PathBuf> = self.files.iter().map(|f| PathBuf::from(&f.name)).collect();
+        crate::compiler::compile_with(&paths, &[], &[PathBuf::new()], &|path: &Path| {
+            match self.files.iter().find(|f| Path::new(&f.name) == path) {
+                Some(file) => Ok(file.text.clone()),
+                None => Err(Error::failed(format!("{} is not part of the conversion", path.display()))),
+            }
+        })
+    }
+}
+
+/// Converts `descriptor_set`, a serialized `google.protobuf.FileDescriptorSet`.
+pub fn convert(descriptor_set: &[u8]) -> Result<Conversion> {
+    let mut files = Vec::new();
+    for (number, value) in decode(descriptor_set)? {
+        if number == 1 {
+            files.push(FileDesc::parse(value.bytes()?)?);
+        }
+    }
+
+    let mut types = HashMap::new();
+    for (idx, file) in files.iter().enumerate() {
+        let prefix = if file.package.is_empty() { String::new() } else { format!(".{}", file.package) };
+        for message in &file.messages {
+            register_message(&mut types, idx, &prefix, "", message);
+        }
+        for e in &file.enums {
+            types.insert(format!("{}.{}", prefix, e.name), (idx, type_name(&e.name)));
+        }
+    }
+
+    let mut converter = Converter { files: &files, types: types, report: Vec::new() };
+    let mut converted = Vec::new();
+    for (idx, file) in files.iter().enumerate() {
+        converted.push(ConvertedFile {
+            proto_name: file.name.clone(),
+            name: capnp_file_name(&file.name),
+            text: converter.file(idx)?,
+        });
+    }
+    Ok(Conversion { files: converted, report: converter.report })
+}
+
+fn register_message(types: &mut HashMap<String, (usize, String)>, file: usize,
+                    proto_scope: &str, capnp_scope: &str, message: &MessageDesc) {
+    let proto_name = format!("{}.{}", proto_scope, message.name);
+    let capnp_name = format!("{}{}", capnp_scope, type_name(&message.name));
+    for nested in &message.nested {
+        register_message(types, file, &proto_name, &format!("{}.", capnp_name), nested);
+    }
+    for e in &message.enums {
+        types.insert(format!("{}.{}", proto_name, e.name), (file, format!("{}.{}", capnp_name, type_name(&e.name))));
+    }
+    types.insert(proto_name, (file, capnp_name));
+}
+
+fn capnp_file_name(proto_name: &str) -> String {
+    format!("{}.capnp", proto_name.trim_end_matches(".proto"))
+}
+
+/// `foo_bar`, `FOO_BAR` and `FooBar` all become `fooBar`.
+fn member_name(name: &str) -> String {
+    let mut result = String::new();
+    for part in name.split('_').filter(|p| !p.is_empty()) {
+        let part = if part.chars().any(|c| c.is_ascii_lowercase()) { part.to_string() } else { part.to_lowercase() };
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            if result.is_empty() {
+                result.extend(first.to_lowercase());
+            } else {
+                result.extend(first.to_uppercase());
+            }
+            result.extend(chars);
+        }
+    }
+    result
+}
+
+fn type_name(name: &str) -> String {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+// Protobuf wire format.
+
+enum WireValue<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+impl <'a> WireValue<'a> {
+    fn bytes(&self) -> Result<&'a [u8]> {
+        match *self {
+            WireValue::Bytes(b) => Ok(b),
+            _ => Err(Error::failed("protobuf: expected a length-delimited field".to_string())),
+        }
+    }
+
+    fn string(&self) -> Result<String> {
+        match String::from_utf8(self.bytes()?.to_vec()) {
+            Ok(s) => Ok(s),
+            Err(_) => Err(Error::failed("protobuf: string is not valid UTF-8".to_string())),
+        }
+    }
+
+    fn varint(&self) -> Result<u64> {
+        match *self {
+            WireValue::Varint(n) => Ok(n),
+            _ => Err(Error::failed("protobuf: expected a varint field".to_string())),
+        }
+    }
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> Result<u64> {
+    let mut result = 0u64;
+    for shift in (0..64).step_by(7) {
+        let b = match bytes.get(*pos) {
+            Some(&b) => b,
+            None => break,
+        };
+        *pos += 1;
+        result |= ((b & 0x7f) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Ok(result);
+        }
+    }
+    Err(Error::failed("protobuf: truncated varint".to_string()))
+}
+
+/// Splits an encoded message into its fields, as `(field number, value)` pairs.
+fn decode<'a>(bytes: &'a [u8]) -> Result<Vec<(u64, WireValue<'a>)>> {
+    let mut result = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let key = read_varint(bytes, &mut pos)?;
+        let width = match key & 7 {
+            0 => {
+                result.push((key >> 3, WireValue::Varint(read_varint(bytes, &mut pos)?)));
+                continue;
+            }
+            1 => 8,
+            2 => read_varint(bytes, &mut pos)? as usize,
+            5 => 4,
+            wire_type => return Err(Error::failed(format!("protobuf: unsupported wire type {}", wire_type))),
+        };
+        if bytes.len() - pos < width {
+            return Err(Error::failed("protobuf: truncated field".to_string()));
+        }
+        let value = if key & 7 == 2 { WireValue::Bytes(&bytes[pos..pos + width]) } else { WireValue::Fixed };
+        result.push((key >> 3, value));
+        pos += width;
+    }
+    Ok(result)
+}
+
+// The parts of descriptor.proto that the conversion uses.
+
+struct FileDesc {
+    name: String,
+    package: String,
+    messages: Vec<MessageDesc>,
+    enums: Vec<EnumDesc>,
+    services: Vec<ServiceDesc>,
+    has_extensions: bool,
+}
+
+impl FileDesc {
+    fn parse(bytes: &[u8]) -> Result<FileDesc> {
+        let mut file = FileDesc { name: String::new(), package: String::new(), messages: Vec::new(),
+                                  enums: Vec::new(), services: Vec::new(), has_extensions: false };
+        for (number, value) in decode(bytes)? {
+            match number {
+                1 => file.name = value.string()?,
+                2 => file.package = value.string()?,
+                4 => file.messages.push(MessageDesc::parse(value.bytes()?)?),
+                5 => file.enums.push(EnumDesc::parse(value.bytes()?)?),
+                6 => file.services.push(ServiceDesc::parse(value.bytes()?)?),
+                7 => file.has_extensions = true,
+                _ => (),
+            }
+        }
+        Ok(file)
+    }
+}
+
+struct MessageDesc {
+    name: String,
+    fields: Vec<FieldDesc>,
+    nested: Vec<MessageDesc>,
+    enums: Vec<EnumDesc>,
+    oneofs: Vec<String>,
+    map_entry: bool,
+    has_extensions: bool,
+}
+
+impl MessageDesc {
+    fn parse(bytes: &[u8]) -> Result<MessageDesc> {
+        let mut message = MessageDesc { name: String::new(), fields: Vec::new(), nested: Vec::new(),
+                                        enums: Vec::new(), oneofs: Vec::new(), map_entry: false,
+                                        has_extensions: false };
+        for (number, value) in decode(bytes)? {
+            match number {
+                1 => message.name = value.string()?,
+                2 => message.fields.push(FieldDesc::parse(value.bytes()?)?),
+                3 => message.nested.push(MessageDesc::parse(value.bytes()?)?),
+                4 => message.enums.push(EnumDesc::parse(value.bytes()?)?),
+                5 | 6 => message.has_extensions = true,
+                7 => {
+                    for (number, value) in decode(value.bytes()?)? {
+                        if number == 7 {
+                            message.map_entry = value.varint()? != 0;
+                        }
+                    }
+                }
+                8 => {
+                    let mut name = String::new();
+                    for (number, value) in decode(value.bytes()?)? {
+                        if number == 1 {
+                            name = value.string()?;
+                        }
+                    }
+                    message.oneofs.push(name);
+                }
+                _ => (),
+            }
+        }
+        Ok(message)
+    }
+}
+
+const LABEL_REQUIRED: u64 = 2;
+const LABEL_REPEATED: u64 = 3;
+
+const TYPE_STRING: u64 = 9;
+const TYPE_GROUP: u64 = 10;
+const TYPE_MESSAGE: u64 = 11;
+const TYPE_BYTES: u64 = 12;
+const TYPE_ENUM: u64 = 14;
+
+struct FieldDesc {
+    name: String,
+    number: u64,
+    label: u64,
+    typ: u64,
+    type_name: String,
+    default_value: Option<String>,
+    oneof_index: Option<usize>,
+    proto3_optional: bool,
+}
+
+impl FieldDesc {
+    fn parse(bytes: &[u8]) -> Result<FieldDesc> {
+        let mut field = FieldDesc { name: String::new(), number: 0, label: 1, typ: 0, type_name: String::new(),
+                                    default_value: None, oneof_index: None, proto3_optional: false };
+        for (number, value) in decode(bytes)? {
+            match number {
+                1 => field.name = value.string()?,
+                3 => field.number = value.varint()?,
+                4 => field.label = value.varint()?,
+                5 => field.typ = value.varint()?,
+                6 => field.type_name = value.string()?,
+                7 => field.default_value = Some(value.string()?),
+                9 => field.oneof_index = Some(value.varint()? as usize),
+                17 => field.proto3_optional = value.varint()? != 0,
+                _ => (),
+            }
+        }
+        Ok(field)
+    }
+}
+
+struct EnumDesc {
+    name: String,
+    values: Vec<(String, i32)>,
+}
+
+impl EnumDesc {
+    fn parse(bytes: &[u8]) -> Result<EnumDesc> {
+        let mut e = EnumDesc { name: String::new(), values: Vec::new() };
+        for (number, value) in decode(bytes)? {
+            match number {
+                1 => e.name = value.string()?,
+                2 => {
+                    let mut name = String::new();
+                    let mut n = 0;
+                    for (number, value) in decode(value.bytes()?)? {
+                        match number {
+                            1 => name = value.string()?,
+                            2 => n = value.varint()? as i32,
+                            _ => (),
+                        }
+                    }
+                    e.values.push((name, n));
+                }
+                _ => (),
+            }
+        }
+        Ok(e)
+    }
+}
+
+struct ServiceDesc {
+    name: String,
+    methods: Vec<MethodDesc>,
+}
+
+struct MethodDesc {
+    name: String,
+    input: String,
+    output: String,
+    streaming: bool,
+}
+
+impl ServiceDesc {
+    fn parse(bytes: &[u8]) -> Result<ServiceDesc> {
+        let mut service = ServiceDesc { name: String::new(), methods: Vec::new() };
+        for (number, value) in decode(bytes)? {
+            match number {
+                1 => service.name = value.string()?,
+                2 => {
+                    let mut method = MethodDesc { name: String::new(), input: String::new(),
+                                                  output: String::new(), streaming: false };
+                    for (number, value) in decode(value.bytes()?)? {
+                        match number {
+                            1 => method.name = value.string()?,
+                            2 => method.input = value.string()?,
+                            3 => method.output = value.string()?,
+                            5 | 6 => method.streaming |= value.varint()? != 0,
+                            _ => (),
+                        }
+                    }
+                    service.methods.push(method);
+                }
+                _ => (),
+            }
+        }
+        Ok(service)
+    }
+}
+
+// Writing the schema text.
+
+struct Converter<'a> {
+    files: &'a [FileDesc],
+
+    /// Maps fully qualified protobuf type names, like `.pkg.Outer.Inner`, to the index of the
+    /// file that defines them and their name within that file, like `Outer.Inner`.
+    types: HashMap<String, (usize, String)>,
+    report: Vec<String>,
+}
+
+impl <'a> Converter<'a> {
+    fn file(&mut self, idx: usize) -> Result<String> {
+        let file = &self.files[idx];
+        let mut out = format!("@{:#x};\n# Converted from {}.\n", file_id(&file.name), file.name);
+        if file.has_extensions {
+            self.report.push(format!("{}: extensions are not supported", file.name));
+        }
+        for message in &file.messages {
+            out.push('\n');
+            self.message(idx, message, &file.name, 0, &mut out)?;
+        }
+        for e in &file.enums {
+            out.push('\n');
+            self.enumeration(e, &file.name, 0, &mut out);
+        }
+        for service in &file.services {
+            out.push('\n');
+            out.push_str(&format!("interface {} {{\n", type_name(&service.name)));
+            for (ordinal, method) in service.methods.iter().enumerate() {
+                let context = format!("{}.{}.{}", file.name, service.name, method.name);
+                if method.streaming {
+                    self.report.push(format!("{}: streaming is not supported; converted as a unary method", context));
+                }
+                out.push_str(&format!("  {} @{} {} -> {};\n", member_name(&method.name), ordinal,
+                                      self.type_ref(idx, &method.input, &context),
+                                      self.type_ref(idx, &method.output, &context)));
+            }
+            out.push_str("}\n");
+        }
+        Ok(out)
+    }
+
+    fn message(&mut self, file: usize, message: &MessageDesc, scope: &str, indent: usize,
+               out: &mut String) -> Result<()> {
+        let context = format!("{}.{}", scope, message.name);
+        let pad = "  ".repeat(indent);
+        if message.has_extensions {
+            self.report.push(format!("{}: extensions are not supported", context));
+        }
+        out.push_str(&format!("{}struct {} {{\n", pad, type_name(&message.name)));
+
+        // Oneofs with only one real member, such as the ones that proto3 makes up for optional
+        // fields, are written as plain fields.
+        let mut oneof_sizes = vec![0; message.oneofs.len()];
+        for field in &message.fields {
+            if let Some(idx) = field.oneof_index {
+                if !field.proto3_optional && idx < oneof_sizes.len() {
+                    oneof_sizes[idx] += 1;
+                }
+            }
+        }
+
+        let mut ordinal = 0;
+        let mut written_oneofs = vec![false; message.oneofs.len()];
+        for field in &message.fields {
+            let union = match field.oneof_index {
+                Some(idx) if idx < oneof_sizes.len() && oneof_sizes[idx] > 1 => Some(idx),
+                Some(idx) if idx < oneof_sizes.len() && !field.proto3_optional => {
+                    self.report.push(format!("{}: oneof {} has a single member, converted as a plain field",
+                                             context, message.oneofs[idx]));
+                    None
+                }
+                _ => None,
+            };
+            match union {
+                None => self.field(file, message, field, &context, &mut ordinal, indent + 1, out)?,
+                Some(idx) if !written_oneofs[idx] => {
+                    written_oneofs[idx] = true;
+                    out.push_str(&format!("{}  {} :union {{\n", pad, member_name(&message.oneofs[idx])));
+                    for member in message.fields.iter().filter(|f| f.oneof_index == Some(idx)) {
+                        self.field(file, message, member, &context, &mut ordinal, indent + 2, out)?;
+                    }
+                    out.push_str(&format!("{}  }}\n", pad));
+                }
+                Some(_) => (),
+            }
+        }
+
+        for nested in &message.nested {
+            self.message(file, nested, &context, indent + 1, out)?;
+        }
+        for e in &message.enums {
+            self.enumeration(e, &context, indent + 1, out);
+        }
+        out.push_str(&format!("{}}}\n", pad));
+        Ok(())
+    }
+
+    fn field(&mut self, file: usize, message: &MessageDesc, field: &FieldDesc, context: &str,
+             ordinal: &mut usize, indent: usize, out: &mut String) -> Result<()> {
+        let context = format!("{}.{}", context, field.name);
+        let mut typ = match field.typ {
+            1 => "Float64".to_string(),
+            2 => "Float32".to_string(),
+            3 | 16 | 18 => "Int64".to_string(),
+            4 | 6 => "UInt64".to_string(),
+            5 | 15 | 17 => "Int32".to_string(),
+            7 | 13 => "UInt32".to_string(),
+            8 => "Bool".to_string(),
+            TYPE_STRING => "Text".to_string(),
+            TYPE_BYTES => "Data".to_string(),
+            TYPE_GROUP | TYPE_MESSAGE | TYPE_ENUM => {
+                if field.typ == TYPE_GROUP {
+                    self.report.push(format!("{}: group converted as a struct field", context));
+                }
+                self.type_ref(file, &field.type_name, &context)
+            }
+            other => return Err(Error::failed(format!("{}: unknown protobuf field type {}", context, other))),
+        };
+        if field.label == LABEL_REPEATED {
+            let is_map = field.typ == TYPE_MESSAGE && message.nested.iter().any(|n| {
+                n.map_entry && field.type_name.ends_with(&format!(".{}.{}", message.name, n.name))
+            });
+            if is_map {
+                self.report.push(format!("{}: map converted to a list of entries", context));
+            }
+            typ = format!("List({})", typ);
+        } else if field.label == LABEL_REQUIRED {
+            self.report.push(format!("{}: required is not enforced", context));
+        }
+        if field.proto3_optional && field.typ != TYPE_MESSAGE {
+            self.report.push(format!("{}: presence of optional scalar is not preserved", context));
+        }
+
+        let default = match field.default_value {
+            None => String::new(),
+            Some(ref value) => match field.typ {
+                TYPE_STRING => format!(" = \"{}\"", value.escape_default()),
+                TYPE_ENUM => format!(" = {}", member_name(value)),
+                TYPE_BYTES => {
+                    self.report.push(format!("{}: bytes default value dropped", context));
+                    String::new()
+                }
+                _ => format!(" = {}", value),
+            },
+        };
+        out.push_str(&format!("{}{} @{} :{}{};  # {} = {}\n", "  ".repeat(indent), member_name(&field.name),
+                              ordinal, typ, default, field.name, field.number));
+        *ordinal += 1;
+        Ok(())
+    }
+
+    fn enumeration(&mut self, e: &EnumDesc, scope: &str, indent: usize, out: &mut String) {
+        let pad = "  ".repeat(indent);
+        if e.values.iter().enumerate().any(|(idx, &(_, n))| n != idx as i32) {
+            self.report.push(format!("{}.{}: values renumbered in declaration order", scope, e.name));
+        }
+        out.push_str(&format!("{}enum {} {{\n", pad, type_name(&e.name)));
+        for (idx, &(ref name, n)) in e.values.iter().enumerate() {
+            out.push_str(&format!("{}  {} @{};  # {} = {}\n", pad, member_name(name), idx, name, n));
+        }
+        out.push_str(&format!("{}}}\n", pad));
+    }
+
+    /// How the schema of `file` refers to the protobuf type `proto_name`.
+    fn type_ref(&mut self, file: usize, proto_name: &str, context: &str) -> String {
+        match self.types.get(proto_name) {
+            Some(&(f, ref name)) if f == file => format!(".{}", name),
+            Some(&(f, ref name)) => format!("import \"/{}\".{}", capnp_file_name(&self.files[f].name), name),
+            None => {
+                self.report.push(format!("{}: type {} is not in the descriptor set, converted as AnyPointer",
+                                         context, proto_name));
+                "AnyPointer".to_string()
+            }
+        }
+    }
+}
+
+fn file_id(proto_name: &str) -> u64 {
+    crate::compiler::generate_child_id(0, &format!("protobuf:{}", proto_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schema_capnp::node;
+    use crate::schema_loader::SchemaLoader;
+
+    fn varint(out: &mut Vec<u8>, mut n: u64) {
+        while n >= 0x80 {
+            out.push((n as u8) | 0x80);
+            n >>= 7;
+        }
+        out.push(n as u8);
+    }
+
+    fn int(out: &mut Vec<u8>, number: u64, n: u64) {
+        varint(out, number << 3);
+        varint(out, n);
+    }
+
+    fn bytes(out: &mut Vec<u8>, number: u64, b: &[u8]) {
+        varint(out, number << 3 | 2);
+        varint(out, b.len() as u64);
+        out.extend_from_slice(b);
+    }
+
+    fn field(name: &str, number: u64, label: u64, typ: u64, type_name: &str, oneof: Option<u64>) -> Vec<u8> {
+        let mut out = Vec::new();
+        bytes(&mut out, 1, name.as_bytes());
+        int(&mut out, 3, number);
+        int(&mut out, 4, label);
+        int(&mut out, 5, typ);
+        if !type_name.is_empty() {
+            bytes(&mut out, 6, type_name.as_bytes());
+        }
+        if let Some(idx) = oneof {
+            int(&mut out, 9, idx);
+        }
+        out
+    }
+
+    fn descriptor_set() -> Vec<u8> {
+        // common.proto:
+        //   package demo;
+        //   message Timestamp { int64 seconds = 1; }
+        //   enum Status { STATUS_UNKNOWN = 0; STATUS_ACTIVE = 1; STATUS_BANNED = 5; }
+        let mut common = Vec::new();
+        bytes(&mut common, 1, b"common.proto");
+        bytes(&mut common, 2, b"demo");
+        let mut timestamp = Vec::new();
+        bytes(&mut timestamp, 1, b"Timestamp");
+        bytes(&mut timestamp, 2, &field("seconds", 1, 1, 3, "", None));
+        bytes(&mut common, 4, &timestamp);
+        let mut status = Vec::new();
+        bytes(&mut status, 1, b"Status");
+        for &(name, n) in &[("STATUS_UNKNOWN", 0), ("STATUS_ACTIVE", 1), ("STATUS_BANNED", 5)] {
+            let mut value = Vec::new();
+            bytes(&mut value, 1, name.as_bytes());
+            int(&mut value, 2, n);
+            bytes(&mut status, 2, &value);
+        }
+        bytes(&mut common, 5, &status);
+
+        // person.proto:
+        //   package demo;
+        //   import "common.proto";
+        //   message Person {
+        //     string full_name = 1;
+        //     repeated string emails = 2;
+        //     oneof contact { string phone = 3; string address = 4; }
+        //     Timestamp created = 5;
+        //     map<string, int32> counts = 6;
+        //     Status status = 7;
+        //   }
+        //   service Directory {
+        //     rpc Lookup(Person) returns (Person);
+        //     rpc Watch(Person) returns (stream Person);
+        //   }
+        let mut person_file = Vec::new();
+        bytes(&mut person_file, 1, b"person.proto");
+        bytes(&mut person_file, 2, b"demo");
+        let mut person = Vec::new();
+        bytes(&mut person, 1, b"Person");
+        bytes(&mut person, 2, &field("full_name", 1, 1, 9, "", None));
+        bytes(&mut person, 2, &field("emails", 2, 3, 9, "", None));
+        bytes(&mut person, 2, &field("phone", 3, 1, 9, "", Some(0)));
+        bytes(&mut person, 2, &field("address", 4, 1, 9, "", Some(0)));
+        bytes(&mut person, 2, &field("created", 5, 1, 11, ".demo.Timestamp", None));
+        bytes(&mut person, 2, &field("counts", 6, 3, 11, ".demo.Person.CountsEntry", None));
+        bytes(&mut person, 2, &field("status", 7, 1, 14, ".demo.Status", None));
+        let mut entry = Vec::new();
+        bytes(&mut entry, 1, b"CountsEntry");
+        bytes(&mut entry, 2, &field("key", 1, 1, 9, "", None));
+        bytes(&mut entry, 2, &field("value", 2, 1, 5, "", None));
+        let mut options = Vec::new();
+        int(&mut options, 7, 1);
+        bytes(&mut entry, 7, &options);
+        bytes(&mut person, 3, &entry);
+        let mut oneof = Vec::new();
+        bytes(&mut oneof, 1, b"contact");
+        bytes(&mut person, 8, &oneof);
+        bytes(&mut person_file, 4, &person);
+        let mut service = Vec::new();
+        bytes(&mut service, 1, b"Directory");
+        for &(name, streaming) in &[("Lookup", false), ("Watch", true)] {
+            let mut method = Vec::new();
+            bytes(&mut method, 1, name.as_bytes());
+            bytes(&mut method, 2, b".demo.Person");
+            bytes(&mut method, 3, b".demo.Person");
+            if streaming {
+                int(&mut method, 6, 1);
+            }
+            bytes(&mut service, 2, &method);
+        }
+        bytes(&mut person_file, 6, &service);
+
+        let mut set = Vec::new();
+        bytes(&mut set, 1, &common);
+        bytes(&mut set, 1, &person_file);
+        set
+    }
+
+    #[test]
+    fn convert_descriptor_set() {
+        let conversion = super::convert(&descriptor_set()).unwrap();
+        assert_eq!(conversion.files.len(), 2);
+        assert_eq!(conversion.files[1].name, "person.capnp");
+        let text = &conversion.files[1].text;
+        assert!(text.contains("fullName @0 :Text;  # full_name = 1"), "{}", text);
+        assert!(text.contains("contact :union {"), "{}", text);
+        assert!(text.contains("created @4 :import \"/common.capnp\".Timestamp;"), "{}", text);
+        assert!(text.contains("counts @5 :List(.Person.CountsEntry);"), "{}", text);
+        assert!(text.contains("watch @1 .Person -> .Person;"), "{}", text);
+
+        assert_eq!(conversion.report, vec![
+            "common.proto.Status: values renumbered in declaration order".to_string(),
+            "person.proto.Person.counts: map converted to a list of entries".to_string(),
+            "person.proto.Directory.Watch: streaming is not supported; converted as a unary method".to_string(),
+        ]);
+
+        let mut loader = SchemaLoader::new();
+        loader.load_request(conversion.compile().unwrap().into_reader()).unwrap();
+        let person = loader.find("person.capnp:Person").unwrap();
+        match person.which().unwrap() {
+            node::Struct(s) => {
+                assert_eq!(s.get_fields().unwrap().len(), 6);
+                assert_eq!(s.get_discriminant_count(), 0);
+            }
+            _ => panic!("expected a struct"),
+        }
+        let status = loader.find("common.capnp:Status").unwrap();
+        match status.which().unwrap() {
+            node::Enum(e) => {
+                let names: Vec<&str> = e.get_enumerants().unwrap().iter()
+                    .map(|e| e.get_name().unwrap()).collect();
+                assert_eq!(names, vec!["statusUnknown", "statusActive", "statusBanned"]);
+            }
+            _ => panic!("expected an enum"),
+        }
+        assert!(loader.find("person.capnp:Directory").is_some());
+    }
+
+    #[test]
+    fn truncated_input() {
+        assert!(super::convert(&[0x0a, 0x05, 0x01]).is_err());
+    }
+}
@@ -0,0 +1,367 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Random messages of a given struct type, for fuzzing consumers, load-testing RPC servers and
+//! seeding property tests.
+//!
+//! Every field gets a value except `AnyPointer` and interface fields, which are left null.
+//! Nesting stops at `Options::max_depth`, below which struct and list fields are left null.
+//! Generation is deterministic for a given seed.
+
+use capnp::{any_pointer, Error, Result};
+
+use crate::dynamic::{ListBuilder, StructBuilder, Value};
+use crate::schema_capnp::{field, node, type_};
+use crate::schema_loader::SchemaLoader;
+
+/// Options controlling the shape of generated messages.
+#[derive(Clone, Copy, Debug)]
+pub struct Options {
+    /// Seeds the generator.
+    pub seed: u64,
+
+    /// How many levels of structs and lists to generate below the root.
+    pub max_depth: u32,
+
+    /// The maximum length of lists, text and data.
+    pub max_list_length: u32,
+
+    /// When true, each union's members are chosen in turn rather than at random, so that
+    /// generating as many messages as the union has members is guaranteed to cover them all.
+    pub cycle_unions: bool,
+}
+
+impl Options {
+    pub fn new() -> Options {
+        Options { seed: 0, max_depth: 4, max_list_length: 4, cycle_unions: false }
+    }
+
+    pub fn seed(&mut self, value: u64) -> &mut Options {
+        self.seed = value;
+        self
+    }
+
+    pub fn max_depth(&mut self, value: u32) -> &mut Options {
+        self.max_depth = value;
+        self
+    }
+
+    pub fn max_list_length(&mut self, value: u32) -> &mut Options {
+        self.max_list_length = value;
+        self
+    }
+
+    pub fn cycle_unions(&mut self, value: bool) -> &mut Options {
+        self.cycle_unions = value;
+        self
+    }
+}
+
+impl Default for Options {
+    fn default() -> Options { Options::new() }
+}
+
+/// Generates a sequence of random messages.
+pub struct Generator {
+    options: Options,
+    state: u64,
+    union_counters: std::collections::HashMap<u64, u64>,
+}
+
+impl Generator {
+    pub fn new(options: Options) -> Generator {
+        Generator { options: options, state: options.seed, union_counters: std::collections::HashMap::new() }
+    }
+
+    /// Writes a random struct of type `schema` to `builder`.
+    pub fn generate(&mut self, loader: &SchemaLoader, schema: node::Reader,
+                    builder: any_pointer::Builder) -> Result<()> {
+        self.fill_struct(&mut StructBuilder::init_any_pointer(loader, schema, builder)?, 0)
+    }
+
+    /// Sets every field of `builder` to a random value.
+    pub fn fill(&mut self, builder: &mut StructBuilder) -> Result<()> {
+        self.fill_struct(builder, 0)
+    }
+
+    // splitmix64
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        if bound == 0 { 0 } else { self.next() % bound }
+    }
+
+    fn list_length(&mut self) -> u32 {
+        self.below(self.options.max_list_length as u64 + 1) as u32
+    }
+
+    fn fill_struct(&mut self, builder: &mut StructBuilder, depth: u32) -> Result<()> {
+        let schema = builder.get_schema();
+        let fields = match schema.which()? {
+            node::Struct(s) => s.get_fields()?,
+            _ => return Err(Error::failed(format!("{} is not a struct", schema.get_display_name()?))),
+        };
+        let union_members: Vec<_> = fields.iter()
+            .filter(|f| f.get_discriminant_value() != field::NO_DISCRIMINANT)
+            .collect();
+        let active = if union_members.is_empty() {
+            None
+        } else {
+            let idx = if self.options.cycle_unions {
+                let counter = self.union_counters.entry(schema.get_id()).or_insert(0);
+                *counter += 1;
+                (*counter - 1) % union_members.len() as u64
+            } else {
+                self.below(union_members.len() as u64)
+            };
+            let member = union_members[idx as usize];
+            // Activates the member even if it ends up null because of the depth limit.
+            builder.init_union_field(member.get_name()?)?;
+            Some(member.get_code_order())
+        };
+
+        for field in fields.iter() {
+            if field.get_discriminant_value() != field::NO_DISCRIMINANT &&
+                Some(field.get_code_order()) != active
+            {
+                continue;
+            }
+            let typ = match field.which()? {
+                field::Group(_) => {
+                    self.fill_struct(&mut builder.init_field(field)?, depth)?;
+                    continue;
+                }
+                field::Slot(slot) => slot.get_type()?,
+            };
+            match typ.which()? {
+                type_::Struct(_) => {
+                    if depth < self.options.max_depth {
+                        self.fill_struct(&mut builder.init_field(field)?, depth + 1)?;
+                    }
+                }
+                type_::List(_) => {
+                    if depth < self.options.max_depth {
+                        let length = self.list_length();
+                        self.fill_list(&mut builder.init_list_field(field, length)?, depth + 1)?;
+                    }
+                }
+                type_::AnyPointer(_) | type_::Interface(_) => {}
+                _ => {
+                    let text;
+                    let data;
+                    let value = match typ.which()? {
+                        type_::Text(()) => {
+                            text = self.text();
+                            Value::Text(&text[..])
+                        }
+                        type_::Data(()) => {
+                            data = self.data();
+                            Value::Data(&data[..])
+                        }
+                        _ => self.primitive(builder.get_loader(), typ)?,
+                    };
+                    builder.set_field(field, value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn fill_list(&mut self, list: &mut ListBuilder, depth: u32) -> Result<()> {
+        let element_type = list.get_element_type();
+        for idx in 0..list.len() {
+            match element_type.which()? {
+                type_::Struct(_) => {
+                    if depth < self.options.max_depth {
+                        self.fill_struct(&mut list.init(idx)?, depth + 1)?;
+                    }
+                }
+                type_::List(_) => {
+                    if depth < self.options.max_depth {
+                        let length = self.list_length();
+                        self.fill_list(&mut list.init_list(idx, length)?, depth + 1)?;
+                    }
+                }
+                type_::AnyPointer(_) | type_::Interface(_) => {}
+                type_::Text(()) => {
+                    let text = self.text();
+                    list.set(idx, Value::Text(&text[..]))?;
+                }
+                type_::Data(()) => {
+                    let data = self.data();
+                    list.set(idx, Value::Data(&data[..]))?;
+                }
+                _ => {
+                    let value = self.primitive(list.get_loader(), element_type)?;
+                    list.set(idx, value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn text(&mut self) -> String {
+        const CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789 ";
+        (0..self.list_length()).map(|_| CHARS[self.below(CHARS.len() as u64) as usize] as char).collect()
+    }
+
+    fn data(&mut self) -> Vec<u8> {
+        (0..self.list_length()).map(|_| self.next() as u8).collect()
+    }
+
+    /// A random value of a type that is neither a pointer nor a struct.
+    fn primitive<'a>(&mut self, loader: &SchemaLoader, typ: type_::Reader) -> Result<Value<'a>> {
+        let r = self.next();
+        Ok(match typ.which()? {
+            type_::Void(()) => Value::Void,
+            type_::Bool(()) => Value::Bool(r & 1 == 1),
+            type_::Int8(()) => Value::Int8(r as i8),
+            type_::Int16(()) => Value::Int16(r as i16),
+            type_::Int32(()) => Value::Int32(r as i32),
+            type_::Int64(()) => Value::Int64(r as i64),
+            type_::Uint8(()) => Value::Uint8(r as u8),
+            type_::Uint16(()) => Value::Uint16(r as u16),
+            type_::Uint32(()) => Value::Uint32(r as u32),
+            type_::Uint64(()) => Value::Uint64(r),
+            // Finite values that survive a round trip through text formats.
+            type_::Float32(()) => Value::Float32((r as i32 as f32) / 256.0),
+            type_::Float64(()) => Value::Float64((r as i32 as f64) / 256.0),
+            type_::Enum(e) => {
+                let count = match loader.require(e.get_type_id())?.which()? {
+                    node::Enum(e) => e.get_enumerants()?.len(),
+                    _ => 0,
+                };
+                Value::Uint16(self.below(count as u64) as u16)
+            }
+            _ => Value::Void,
+        })
+    }
+}
+
+/// Writes a random struct of type `schema` to `builder`. To generate a series of different
+/// messages, use a `Generator`.
+pub fn generate(loader: &SchemaLoader, schema: node::Reader, options: &Options,
+                builder: any_pointer::Builder) -> Result<()> {
+    Generator::new(*options).generate(loader, schema, builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use capnp::{any_pointer, message};
+    use crate::dynamic::{StructReader, Value};
+    use crate::schema_loader::SchemaLoader;
+
+    fn load_schema() -> SchemaLoader {
+        let text = "@0xe8a2c4f6b1d3a597;
+                    struct Tree {
+                      label @0 :Text;
+                      weight @1 :Float64;
+                      blob @2 :Data;
+                      color @3 :Color;
+                      children @4 :List(Tree);
+                      union {
+                        leaf @5 :Void;
+                        counts @6 :List(UInt8);
+                        branch :group {
+                          left @7 :Tree;
+                          right @8 :Int32;
+                        }
+                      }
+                    }
+                    enum Color { red @0; green @1; blue @2; }".to_string();
+        let message = crate::compiler::compile_with(&[PathBuf::from("tree.capnp")], &[], &[], &move |path: &Path| {
+            if path == Path::new("tree.capnp") {
+                Ok(text.clone())
+            } else {
+                Err(::capnp::Error::failed("no such file".to_string()))
+            }
+        }).unwrap();
+        let mut loader = SchemaLoader::new();
+        loader.load_request(message.into_reader()).unwrap();
+        loader
+    }
+
+    fn generate(loader: &SchemaLoader, generator: &mut super::Generator) -> message::Builder<message::HeapAllocator> {
+        let mut message = message::Builder::new_default();
+        generator.generate(loader, loader.find("Tree").unwrap(), message.init_root()).unwrap();
+        message
+    }
+
+    fn depth(reader: StructReader) -> u32 {
+        let mut result = 0;
+        if let Value::List(children) = reader.get("children").unwrap() {
+            for child in children.iter() {
+                if let Value::Struct(child) = child.unwrap() {
+                    result = result.max(1 + depth(child));
+                }
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn deterministic_for_a_seed() {
+        let loader = load_schema();
+        let to_words = |seed| {
+            let mut generator = super::Generator::new(*super::Options::new().seed(seed));
+            capnp::serialize::write_message_to_words(&generate(&loader, &mut generator))
+        };
+        assert_eq!(to_words(7), to_words(7));
+        assert_ne!(to_words(7), to_words(8));
+    }
+
+    #[test]
+    fn respects_max_depth() {
+        let loader = load_schema();
+        let schema = loader.find("Tree").unwrap();
+        let mut generator = super::Generator::new(
+            *super::Options::new().max_depth(2).max_list_length(3));
+        for _ in 0..20 {
+            let message = generate(&loader, &mut generator);
+            let reader = StructReader::from_any_pointer(
+                &loader, schema, message.get_root_as_reader::<any_pointer::Reader>().unwrap()).unwrap();
+            assert!(depth(reader) <= 2);
+        }
+    }
+
+    #[test]
+    fn cycles_through_union_members() {
+        let loader = load_schema();
+        let schema = loader.find("Tree").unwrap();
+        let mut generator = super::Generator::new(*super::Options::new().max_depth(0).cycle_unions(true));
+        let mut seen = Vec::new();
+        for _ in 0..3 {
+            let message = generate(&loader, &mut generator);
+            let reader = StructReader::from_any_pointer(
+                &loader, schema, message.get_root_as_reader::<any_pointer::Reader>().unwrap()).unwrap();
+            seen.push(reader.which().unwrap().unwrap().get_name().unwrap().to_string());
+        }
+        assert_eq!(seen, ["leaf", "counts", "branch"]);
+    }
+}
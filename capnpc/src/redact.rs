@@ -0,0 +1,183 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Copies of messages with sensitive fields left out, for logs and exports.
+//!
+//! A field is sensitive if it is annotated with `$Rust.sensitive` from rust.capnp, or if it is a
+//! group whose node carries the annotation. Sensitive fields are left at their default values
+//! in the copy; a sensitive union member stays active, so that readers can still tell which
+//! member was set. Interface fields are left null too, since capabilities cannot be copied
+//! outside of RPC, and `AnyPointer` fields are copied as they are.
+
+use capnp::{any_pointer, Result};
+
+use crate::dynamic::{ListBuilder, ListReader, StructBuilder, StructReader, Value};
+use crate::schema_capnp::{annotation, field};
+
+/// The id of the `sensitive` annotation in rust.capnp.
+pub const SENSITIVE_ANNOTATION_ID: u64 = 0xf3a9c2d4b6e81057;
+
+/// Whether `field` of `reader`'s struct is marked as sensitive.
+pub fn is_sensitive(reader: &StructReader, field: field::Reader) -> Result<bool> {
+    if has_sensitive_annotation(field.get_annotations()?) {
+        return Ok(true);
+    }
+    match field.which()? {
+        field::Group(group) => {
+            let schema = reader.get_loader().require(group.get_type_id())?;
+            Ok(has_sensitive_annotation(schema.get_annotations()?))
+        }
+        field::Slot(_) => Ok(false),
+    }
+}
+
+fn has_sensitive_annotation(annotations: ::capnp::struct_list::Reader<annotation::Owned>) -> bool {
+    annotations.iter().any(|a| a.get_id() == SENSITIVE_ANNOTATION_ID)
+}
+
+/// Writes a copy of `reader` with its sensitive fields, and those of every struct it contains,
+/// cleared to `builder`.
+pub fn copy_redacted(reader: StructReader, builder: any_pointer::Builder) -> Result<()> {
+    let mut root = StructBuilder::init_any_pointer(reader.get_loader(), reader.get_schema(), builder)?;
+    copy_struct(reader, &mut root)
+}
+
+fn copy_struct<'a>(reader: StructReader<'a>, builder: &mut StructBuilder<'a>) -> Result<()> {
+    for field in reader.get_fields()?.iter() {
+        if !reader.has_field(field)? {
+            continue;
+        }
+        if is_sensitive(&reader, field)? {
+            if field.get_discriminant_value() != field::NO_DISCRIMINANT {
+                builder.init_union_field(field.get_name()?)?;
+            }
+            continue;
+        }
+        match reader.get_field(field)? {
+            Value::Capability => (),
+            Value::Struct(value) => copy_struct(value, &mut builder.init_field(field)?)?,
+            Value::List(value) => copy_list(value, builder.init_list_field(field, value.len())?)?,
+            value => builder.set_field(field, value)?,
+        }
+    }
+    Ok(())
+}
+
+fn copy_list<'a>(reader: ListReader<'a>, mut builder: ListBuilder<'a>) -> Result<()> {
+    for index in 0..reader.len() {
+        match reader.get(index)? {
+            Value::Capability => (),
+            Value::Struct(value) => copy_struct(value, &mut builder.init(index)?)?,
+            Value::List(value) => copy_list(value, builder.init_list(index, value.len())?)?,
+            value => builder.set(index, value)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use capnp::{any_pointer, message};
+    use crate::dynamic::{StructReader, Value};
+    use crate::schema_loader::SchemaLoader;
+
+    fn load_schema() -> SchemaLoader {
+        let text = "@0xd4c3b2a1f0e9d8c7;
+                    using Rust = import \"rust.capnp\";
+                    struct Account {
+                      id @0 :UInt64;
+                      password @1 :Text $Rust.sensitive;
+                      owner @2 :Person;
+                      history @3 :List(Person);
+                      card :group $Rust.sensitive { number @4 :Text; cvv @5 :UInt16; }
+                      union { anonymous @6 :Void; email @7 :Text $Rust.sensitive; }
+                    }
+                    struct Person {
+                      name @0 :Text;
+                      birthday @1 :UInt32 = 101 $Rust.sensitive;
+                    }".to_string();
+        let message = crate::compiler::compile_with(&[PathBuf::from("account.capnp")], &[], &[], &move |path: &Path| {
+            if path == Path::new("account.capnp") {
+                Ok(text.clone())
+            } else if path == Path::new("rust.capnp") {
+                Ok(include_str!("../rust.capnp").to_string())
+            } else {
+                Err(::capnp::Error::failed("no such file".to_string()))
+            }
+        }).unwrap();
+        let mut loader = SchemaLoader::new();
+        loader.load_request(message.into_reader()).unwrap();
+        loader
+    }
+
+    fn get<'a>(reader: &StructReader<'a>, name: &str) -> Value<'a> {
+        reader.get(name).unwrap()
+    }
+
+    fn has(reader: &StructReader, name: &str) -> bool {
+        reader.has_field(reader.find_field(name).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn redacts_sensitive_fields() {
+        let loader = load_schema();
+        let schema = loader.find("Account").unwrap();
+        let mut message = message::Builder::new_default();
+        {
+            let mut account = crate::dynamic::StructBuilder::init_any_pointer(
+                &loader, schema, message.init_root()).unwrap();
+            account.set("id", Value::Uint64(7)).unwrap();
+            account.set("password", Value::Text("hunter2")).unwrap();
+            account.set("email", Value::Text("a@example.com")).unwrap();
+            account.init("card").unwrap().set("cvv", Value::Uint16(123)).unwrap();
+            let mut owner = account.init("owner").unwrap();
+            owner.set("name", Value::Text("Alice")).unwrap();
+            owner.set("birthday", Value::Uint32(19990101)).unwrap();
+            account.init_list("history", 1).unwrap().init(0).unwrap()
+                .set("birthday", Value::Uint32(20000101)).unwrap();
+        }
+        let reader = StructReader::from_any_pointer(
+            &loader, schema, message.get_root_as_reader::<any_pointer::Reader>().unwrap()).unwrap();
+        let mut redacted = message::Builder::new_default();
+        super::copy_redacted(reader, redacted.init_root()).unwrap();
+
+        let reader = StructReader::from_any_pointer(
+            &loader, schema, redacted.get_root_as_reader::<any_pointer::Reader>().unwrap()).unwrap();
+        match get(&reader, "id") { Value::Uint64(7) => (), _ => panic!() }
+        assert!(!has(&reader, "password"));
+        assert_eq!(reader.which().unwrap().unwrap().get_name().unwrap(), "email");
+        assert!(!has(&reader, "email"));
+        match get(&reader, "card") {
+            Value::Struct(card) => match get(&card, "cvv") { Value::Uint16(0) => (), _ => panic!() },
+            _ => panic!(),
+        }
+        let owner = match get(&reader, "owner") { Value::Struct(s) => s, _ => panic!() };
+        match get(&owner, "name") { Value::Text(t) => assert_eq!(t, "Alice"), _ => panic!() }
+        match get(&owner, "birthday") { Value::Uint32(101) => (), _ => panic!() }
+        let history = match get(&reader, "history") { Value::List(l) => l, _ => panic!() };
+        match history.get(0).unwrap() {
+            Value::Struct(person) => match get(&person, "birthday") { Value::Uint32(101) => (), _ => panic!() },
+            _ => panic!(),
+        }
+    }
+}
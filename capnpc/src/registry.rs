@@ -0,0 +1,199 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Routes messages of many types over one channel, by type ID.
+//!
+//! An envelope is a serialized message prefixed with the little-endian 64-bit ID of its root
+//! struct type. `write_envelope()` produces one and `Registry::dispatch()` reads one, looks up
+//! the handler registered for its type, and calls it with the message's root. The registry also
+//! answers which schema an ID refers to, from the nodes of its `SchemaLoader`.
+//!
+//! Code generated with `CompilerCommand::embed_schemas()` embeds the schema node of each struct,
+//! and each generated file has a `register_schemas()` function that `Registry::register_schemas()`
+//! loads them with. Schemas can also be loaded into the `SchemaLoader` from elsewhere, such as
+//! a `CodeGeneratorRequest` or an archive. Handlers are registered by hand.
+//!
+//! ```ignore
+//! let mut registry = Registry::new(SchemaLoader::new());
+//! registry.register_schemas(addressbook_capnp::register_schemas)?;
+//! registry.handle(<person::Reader as HasTypeId>::type_id(), |root| {
+//!     let person: person::Reader = root.get_as()?;
+//!     ...
+//! });
+//! let bytes = write_envelope(<person::Reader as HasTypeId>::type_id(), &message);
+//! registry.dispatch(&mut &bytes[..])?;
+//! ```
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+use capnp::{any_pointer, message, serialize, Error, Result};
+use crate::schema_capnp::node;
+use crate::schema_loader::SchemaLoader;
+
+/// Serializes `message` as an envelope for a root of type `type_id`.
+pub fn write_envelope<A>(type_id: u64, message: &message::Builder<A>) -> Vec<u8>
+    where A: message::Allocator
+{
+    let mut bytes = type_id.to_le_bytes().to_vec();
+    bytes.extend(serialize::write_message_to_words(message));
+    bytes
+}
+
+/// Reads an envelope from the front of `slice` and returns its type ID and message. On success,
+/// `slice` is advanced past the envelope. The alignment requirements are those of
+/// `serialize::read_message_from_flat_slice()`.
+pub fn read_envelope<'a>(slice: &mut &'a [u8], options: message::ReaderOptions)
+                         -> Result<(u64, message::Reader<serialize::SliceSegments<'a>>)>
+{
+    if slice.len() < 8 {
+        return Err(Error::failed(format!("envelope is too short for a type ID: {} bytes", slice.len())));
+    }
+    let type_id = u64::from_le_bytes(slice[..8].try_into().unwrap());
+    let mut rest = &slice[8..];
+    let message = serialize::read_message_from_flat_slice(&mut rest, options)?;
+    *slice = rest;
+    Ok((type_id, message))
+}
+
+type Handler = Box<dyn FnMut(any_pointer::Reader) -> Result<()>>;
+
+/// The signature of the `register_schemas()` function of a file generated with
+/// `CompilerCommand::embed_schemas()`.
+pub type RegisterSchemas = fn(&mut dyn FnMut(any_pointer::Reader<'static>) -> Result<()>) -> Result<()>;
+
+/// Schemas and message handlers, keyed by type ID.
+pub struct Registry {
+    loader: SchemaLoader,
+    handlers: HashMap<u64, Handler>,
+}
+
+impl Registry {
+    pub fn new(loader: SchemaLoader) -> Registry {
+        Registry { loader: loader, handlers: HashMap::new() }
+    }
+
+    pub fn get_loader(&self) -> &SchemaLoader { &self.loader }
+
+    /// Gives access to the loader, for loading more schemas.
+    pub fn get_loader_mut(&mut self) -> &mut SchemaLoader { &mut self.loader }
+
+    /// Loads the schema nodes embedded in a generated file, given the file's `register_schemas()`
+    /// function.
+    pub fn register_schemas(&mut self, register_schemas: RegisterSchemas) -> Result<()> {
+        let loader = &mut self.loader;
+        register_schemas(&mut |node| loader.load_node(node.get_as()?))
+    }
+
+    /// Gets the schema of the type with the given ID.
+    pub fn get_schema<'a>(&'a self, type_id: u64) -> Option<node::Reader<'a>> {
+        self.loader.get(type_id)
+    }
+
+    /// Calls `handler` on the root of every dispatched message of type `type_id`, replacing any
+    /// handler registered earlier for that type.
+    pub fn handle<F>(&mut self, type_id: u64, handler: F)
+        where F: FnMut(any_pointer::Reader) -> Result<()> + 'static
+    {
+        self.handlers.insert(type_id, Box::new(handler));
+    }
+
+    /// Reads an envelope from the front of `slice`, advancing it, and passes the message's root
+    /// to the handler for its type. Returns the type ID.
+    pub fn dispatch(&mut self, slice: &mut &[u8]) -> Result<u64> {
+        let (type_id, message) = read_envelope(slice, message::ReaderOptions::new())?;
+        let handler = match self.handlers.get_mut(&type_id) {
+            Some(handler) => handler,
+            None => {
+                let name = match self.loader.get(type_id) {
+                    Some(schema) => format!(" ({})", schema.get_display_name()?),
+                    None => String::new(),
+                };
+                return Err(Error::failed(format!("no handler for messages of type @0x{:x}{}", type_id, name)));
+            }
+        };
+        handler(message.get_root()?)?;
+        Ok(type_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::path::{Path, PathBuf};
+    use std::rc::Rc;
+
+    use capnp::message;
+    use crate::dynamic::{RawPointer, StructBuilder, Value};
+    use crate::schema_loader::SchemaLoader;
+    use super::{write_envelope, Registry};
+
+    fn loader() -> SchemaLoader {
+        let message = crate::compiler::compile_with(&[PathBuf::from("events.capnp")], &[], &[], &|path: &Path| {
+            if path == Path::new("events.capnp") {
+                Ok("@0xa9c6d1e4b7f2038d;
+                    struct Created { name @0 :Text; }
+                    struct Deleted { id @0 :UInt64; }".to_string())
+            } else {
+                Err(::capnp::Error::failed("no such file".to_string()))
+            }
+        }).unwrap();
+        let mut loader = SchemaLoader::new();
+        loader.load_request(message.into_reader()).unwrap();
+        loader
+    }
+
+    fn envelope(loader: &SchemaLoader, type_name: &str, field: &str, value: Value) -> Vec<u8> {
+        let schema = loader.find(type_name).unwrap();
+        let mut message = message::Builder::new_default();
+        StructBuilder::init_any_pointer(loader, schema, message.init_root()).unwrap().set(field, value).unwrap();
+        write_envelope(schema.get_id(), &message)
+    }
+
+    #[test]
+    fn dispatches_by_type_id() {
+        let loader = loader();
+        let created = loader.find("Created").unwrap().get_id();
+        let deleted = loader.find("Deleted").unwrap().get_id();
+        let mut registry = Registry::new(loader);
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let seen2 = seen.clone();
+        registry.handle(created, move |root| {
+            let created = root.get_as::<RawPointer>()?.0.get_struct(None)?;
+            seen2.borrow_mut().push(created.get_pointer_field(0).get_text(None)?.to_string());
+            Ok(())
+        });
+
+        let mut bytes = envelope(registry.get_loader(), "Created", "name", Value::Text("a"));
+        bytes.extend(envelope(registry.get_loader(), "Created", "name", Value::Text("b")));
+        let mut slice = &bytes[..];
+        assert_eq!(registry.dispatch(&mut slice).unwrap(), created);
+        assert_eq!(registry.dispatch(&mut slice).unwrap(), created);
+        assert!(slice.is_empty());
+        assert_eq!(*seen.borrow(), vec!["a".to_string(), "b".to_string()]);
+
+        let bytes = envelope(registry.get_loader(), "Deleted", "id", Value::Uint64(7));
+        let error = registry.dispatch(&mut &bytes[..]).unwrap_err();
+        assert!(error.description.contains("events.capnp:Deleted"), "{}", error.description);
+        assert_eq!(registry.get_schema(deleted).unwrap().get_display_name().unwrap(), "events.capnp:Deleted");
+    }
+}
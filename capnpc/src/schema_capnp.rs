@@ -44,7 +44,7 @@ pub mod node {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -171,10 +171,10 @@ pub mod node {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -428,7 +428,7 @@ pub mod node {
     }
 
     impl <'a,> Reader<'a,>  {
-      pub fn reborrow(&self) -> Reader<> {
+      pub fn reborrow(&self) -> Reader<'_,> {
         Reader { .. *self }
       }
 
@@ -482,10 +482,10 @@ pub mod node {
       pub fn into_reader(self) -> Reader<'a,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
-      pub fn reborrow(&mut self) -> Builder<> {
+      pub fn reborrow(&mut self) -> Builder<'_,> {
         Builder { .. *self }
       }
-      pub fn reborrow_as_reader(&self) -> Reader<> {
+      pub fn reborrow_as_reader(&self) -> Reader<'_,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
 
@@ -563,7 +563,7 @@ pub mod node {
     }
 
     impl <'a,> Reader<'a,>  {
-      pub fn reborrow(&self) -> Reader<> {
+      pub fn reborrow(&self) -> Reader<'_,> {
         Reader { .. *self }
       }
 
@@ -621,10 +621,10 @@ pub mod node {
       pub fn into_reader(self) -> Reader<'a,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
-      pub fn reborrow(&mut self) -> Builder<> {
+      pub fn reborrow(&mut self) -> Builder<'_,> {
         Builder { .. *self }
       }
-      pub fn reborrow_as_reader(&self) -> Reader<> {
+      pub fn reborrow_as_reader(&self) -> Reader<'_,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
 
@@ -710,7 +710,7 @@ pub mod node {
     }
 
     impl <'a,> Reader<'a,>  {
-      pub fn reborrow(&self) -> Reader<> {
+      pub fn reborrow(&self) -> Reader<'_,> {
         Reader { .. *self }
       }
 
@@ -775,10 +775,10 @@ pub mod node {
       pub fn into_reader(self) -> Reader<'a,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
-      pub fn reborrow(&mut self) -> Builder<> {
+      pub fn reborrow(&mut self) -> Builder<'_,> {
         Builder { .. *self }
       }
-      pub fn reborrow_as_reader(&self) -> Reader<> {
+      pub fn reborrow_as_reader(&self) -> Reader<'_,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
 
@@ -878,7 +878,7 @@ pub mod node {
       }
 
       impl <'a,> Reader<'a,>  {
-        pub fn reborrow(&self) -> Reader<> {
+        pub fn reborrow(&self) -> Reader<'_,> {
           Reader { .. *self }
         }
 
@@ -932,10 +932,10 @@ pub mod node {
         pub fn into_reader(self) -> Reader<'a,> {
           ::capnp::traits::FromStructReader::new(self.builder.into_reader())
         }
-        pub fn reborrow(&mut self) -> Builder<> {
+        pub fn reborrow(&mut self) -> Builder<'_,> {
           Builder { .. *self }
         }
-        pub fn reborrow_as_reader(&self) -> Reader<> {
+        pub fn reborrow_as_reader(&self) -> Reader<'_,> {
           ::capnp::traits::FromStructReader::new(self.builder.into_reader())
         }
 
@@ -1014,7 +1014,7 @@ pub mod node {
     }
 
     impl <'a,> Reader<'a,>  {
-      pub fn reborrow(&self) -> Reader<> {
+      pub fn reborrow(&self) -> Reader<'_,> {
         Reader { .. *self }
       }
 
@@ -1092,10 +1092,10 @@ pub mod node {
       pub fn into_reader(self) -> Reader<'a,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
-      pub fn reborrow(&mut self) -> Builder<> {
+      pub fn reborrow(&mut self) -> Builder<'_,> {
         Builder { .. *self }
       }
-      pub fn reborrow_as_reader(&self) -> Reader<> {
+      pub fn reborrow_as_reader(&self) -> Reader<'_,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
 
@@ -1221,7 +1221,7 @@ pub mod node {
     }
 
     impl <'a,> Reader<'a,>  {
-      pub fn reborrow(&self) -> Reader<> {
+      pub fn reborrow(&self) -> Reader<'_,> {
         Reader { .. *self }
       }
 
@@ -1275,10 +1275,10 @@ pub mod node {
       pub fn into_reader(self) -> Reader<'a,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
-      pub fn reborrow(&mut self) -> Builder<> {
+      pub fn reborrow(&mut self) -> Builder<'_,> {
         Builder { .. *self }
       }
-      pub fn reborrow_as_reader(&self) -> Reader<> {
+      pub fn reborrow_as_reader(&self) -> Reader<'_,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
 
@@ -1356,7 +1356,7 @@ pub mod node {
     }
 
     impl <'a,> Reader<'a,>  {
-      pub fn reborrow(&self) -> Reader<> {
+      pub fn reborrow(&self) -> Reader<'_,> {
         Reader { .. *self }
       }
 
@@ -1417,10 +1417,10 @@ pub mod node {
       pub fn into_reader(self) -> Reader<'a,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
-      pub fn reborrow(&mut self) -> Builder<> {
+      pub fn reborrow(&mut self) -> Builder<'_,> {
         Builder { .. *self }
       }
-      pub fn reborrow_as_reader(&self) -> Reader<> {
+      pub fn reborrow_as_reader(&self) -> Reader<'_,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
 
@@ -1513,7 +1513,7 @@ pub mod node {
     }
 
     impl <'a,> Reader<'a,>  {
-      pub fn reborrow(&self) -> Reader<> {
+      pub fn reborrow(&self) -> Reader<'_,> {
         Reader { .. *self }
       }
 
@@ -1574,10 +1574,10 @@ pub mod node {
       pub fn into_reader(self) -> Reader<'a,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
-      pub fn reborrow(&mut self) -> Builder<> {
+      pub fn reborrow(&mut self) -> Builder<'_,> {
         Builder { .. *self }
       }
-      pub fn reborrow_as_reader(&self) -> Reader<> {
+      pub fn reborrow_as_reader(&self) -> Reader<'_,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
 
@@ -1676,7 +1676,7 @@ pub mod node {
     }
 
     impl <'a,> Reader<'a,>  {
-      pub fn reborrow(&self) -> Reader<> {
+      pub fn reborrow(&self) -> Reader<'_,> {
         Reader { .. *self }
       }
 
@@ -1778,10 +1778,10 @@ pub mod node {
       pub fn into_reader(self) -> Reader<'a,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
-      pub fn reborrow(&mut self) -> Builder<> {
+      pub fn reborrow(&mut self) -> Builder<'_,> {
         Builder { .. *self }
       }
-      pub fn reborrow_as_reader(&self) -> Reader<> {
+      pub fn reborrow_as_reader(&self) -> Reader<'_,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
 
@@ -1961,7 +1961,7 @@ pub mod field {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -2050,10 +2050,10 @@ pub mod field {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -2212,7 +2212,7 @@ pub mod field {
     }
 
     impl <'a,> Reader<'a,>  {
-      pub fn reborrow(&self) -> Reader<> {
+      pub fn reborrow(&self) -> Reader<'_,> {
         Reader { .. *self }
       }
 
@@ -2281,10 +2281,10 @@ pub mod field {
       pub fn into_reader(self) -> Reader<'a,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
-      pub fn reborrow(&mut self) -> Builder<> {
+      pub fn reborrow(&mut self) -> Builder<'_,> {
         Builder { .. *self }
       }
-      pub fn reborrow_as_reader(&self) -> Reader<> {
+      pub fn reborrow_as_reader(&self) -> Reader<'_,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
 
@@ -2399,7 +2399,7 @@ pub mod field {
     }
 
     impl <'a,> Reader<'a,>  {
-      pub fn reborrow(&self) -> Reader<> {
+      pub fn reborrow(&self) -> Reader<'_,> {
         Reader { .. *self }
       }
 
@@ -2450,10 +2450,10 @@ pub mod field {
       pub fn into_reader(self) -> Reader<'a,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
-      pub fn reborrow(&mut self) -> Builder<> {
+      pub fn reborrow(&mut self) -> Builder<'_,> {
         Builder { .. *self }
       }
-      pub fn reborrow_as_reader(&self) -> Reader<> {
+      pub fn reborrow_as_reader(&self) -> Reader<'_,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
 
@@ -2526,7 +2526,7 @@ pub mod field {
     }
 
     impl <'a,> Reader<'a,>  {
-      pub fn reborrow(&self) -> Reader<> {
+      pub fn reborrow(&self) -> Reader<'_,> {
         Reader { .. *self }
       }
 
@@ -2589,10 +2589,10 @@ pub mod field {
       pub fn into_reader(self) -> Reader<'a,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
-      pub fn reborrow(&mut self) -> Builder<> {
+      pub fn reborrow(&mut self) -> Builder<'_,> {
         Builder { .. *self }
       }
-      pub fn reborrow_as_reader(&self) -> Reader<> {
+      pub fn reborrow_as_reader(&self) -> Reader<'_,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
 
@@ -2687,7 +2687,7 @@ pub mod enumerant {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -2752,10 +2752,10 @@ pub mod enumerant {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -2856,7 +2856,7 @@ pub mod superclass {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -2914,10 +2914,10 @@ pub mod superclass {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -3006,7 +3006,7 @@ pub mod method {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -3100,10 +3100,10 @@ pub mod method {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -3273,7 +3273,7 @@ pub mod type_ {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -3421,10 +3421,10 @@ pub mod type_ {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -3703,7 +3703,7 @@ pub mod type_ {
     }
 
     impl <'a,> Reader<'a,>  {
-      pub fn reborrow(&self) -> Reader<> {
+      pub fn reborrow(&self) -> Reader<'_,> {
         Reader { .. *self }
       }
 
@@ -3757,10 +3757,10 @@ pub mod type_ {
       pub fn into_reader(self) -> Reader<'a,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
-      pub fn reborrow(&mut self) -> Builder<> {
+      pub fn reborrow(&mut self) -> Builder<'_,> {
         Builder { .. *self }
       }
-      pub fn reborrow_as_reader(&self) -> Reader<> {
+      pub fn reborrow_as_reader(&self) -> Reader<'_,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
 
@@ -3841,7 +3841,7 @@ pub mod type_ {
     }
 
     impl <'a,> Reader<'a,>  {
-      pub fn reborrow(&self) -> Reader<> {
+      pub fn reborrow(&self) -> Reader<'_,> {
         Reader { .. *self }
       }
 
@@ -3899,10 +3899,10 @@ pub mod type_ {
       pub fn into_reader(self) -> Reader<'a,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
-      pub fn reborrow(&mut self) -> Builder<> {
+      pub fn reborrow(&mut self) -> Builder<'_,> {
         Builder { .. *self }
       }
-      pub fn reborrow_as_reader(&self) -> Reader<> {
+      pub fn reborrow_as_reader(&self) -> Reader<'_,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
 
@@ -3991,7 +3991,7 @@ pub mod type_ {
     }
 
     impl <'a,> Reader<'a,>  {
-      pub fn reborrow(&self) -> Reader<> {
+      pub fn reborrow(&self) -> Reader<'_,> {
         Reader { .. *self }
       }
 
@@ -4049,10 +4049,10 @@ pub mod type_ {
       pub fn into_reader(self) -> Reader<'a,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
-      pub fn reborrow(&mut self) -> Builder<> {
+      pub fn reborrow(&mut self) -> Builder<'_,> {
         Builder { .. *self }
       }
-      pub fn reborrow_as_reader(&self) -> Reader<> {
+      pub fn reborrow_as_reader(&self) -> Reader<'_,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
 
@@ -4141,7 +4141,7 @@ pub mod type_ {
     }
 
     impl <'a,> Reader<'a,>  {
-      pub fn reborrow(&self) -> Reader<> {
+      pub fn reborrow(&self) -> Reader<'_,> {
         Reader { .. *self }
       }
 
@@ -4199,10 +4199,10 @@ pub mod type_ {
       pub fn into_reader(self) -> Reader<'a,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
-      pub fn reborrow(&mut self) -> Builder<> {
+      pub fn reborrow(&mut self) -> Builder<'_,> {
         Builder { .. *self }
       }
-      pub fn reborrow_as_reader(&self) -> Reader<> {
+      pub fn reborrow_as_reader(&self) -> Reader<'_,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
 
@@ -4293,7 +4293,7 @@ pub mod type_ {
     }
 
     impl <'a,> Reader<'a,>  {
-      pub fn reborrow(&self) -> Reader<> {
+      pub fn reborrow(&self) -> Reader<'_,> {
         Reader { .. *self }
       }
 
@@ -4361,10 +4361,10 @@ pub mod type_ {
       pub fn into_reader(self) -> Reader<'a,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
-      pub fn reborrow(&mut self) -> Builder<> {
+      pub fn reborrow(&mut self) -> Builder<'_,> {
         Builder { .. *self }
       }
-      pub fn reborrow_as_reader(&self) -> Reader<> {
+      pub fn reborrow_as_reader(&self) -> Reader<'_,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
 
@@ -4475,7 +4475,7 @@ pub mod type_ {
       }
 
       impl <'a,> Reader<'a,>  {
-        pub fn reborrow(&self) -> Reader<> {
+        pub fn reborrow(&self) -> Reader<'_,> {
           Reader { .. *self }
         }
 
@@ -4548,10 +4548,10 @@ pub mod type_ {
         pub fn into_reader(self) -> Reader<'a,> {
           ::capnp::traits::FromStructReader::new(self.builder.into_reader())
         }
-        pub fn reborrow(&mut self) -> Builder<> {
+        pub fn reborrow(&mut self) -> Builder<'_,> {
           Builder { .. *self }
         }
-        pub fn reborrow_as_reader(&self) -> Reader<> {
+        pub fn reborrow_as_reader(&self) -> Reader<'_,> {
           ::capnp::traits::FromStructReader::new(self.builder.into_reader())
         }
 
@@ -4664,7 +4664,7 @@ pub mod type_ {
       }
 
       impl <'a,> Reader<'a,>  {
-        pub fn reborrow(&self) -> Reader<> {
+        pub fn reborrow(&self) -> Reader<'_,> {
           Reader { .. *self }
         }
 
@@ -4719,10 +4719,10 @@ pub mod type_ {
         pub fn into_reader(self) -> Reader<'a,> {
           ::capnp::traits::FromStructReader::new(self.builder.into_reader())
         }
-        pub fn reborrow(&mut self) -> Builder<> {
+        pub fn reborrow(&mut self) -> Builder<'_,> {
           Builder { .. *self }
         }
-        pub fn reborrow_as_reader(&self) -> Reader<> {
+        pub fn reborrow_as_reader(&self) -> Reader<'_,> {
           ::capnp::traits::FromStructReader::new(self.builder.into_reader())
         }
 
@@ -4801,7 +4801,7 @@ pub mod type_ {
       }
 
       impl <'a,> Reader<'a,>  {
-        pub fn reborrow(&self) -> Reader<> {
+        pub fn reborrow(&self) -> Reader<'_,> {
           Reader { .. *self }
         }
 
@@ -4852,10 +4852,10 @@ pub mod type_ {
         pub fn into_reader(self) -> Reader<'a,> {
           ::capnp::traits::FromStructReader::new(self.builder.into_reader())
         }
-        pub fn reborrow(&mut self) -> Builder<> {
+        pub fn reborrow(&mut self) -> Builder<'_,> {
           Builder { .. *self }
         }
-        pub fn reborrow_as_reader(&self) -> Reader<> {
+        pub fn reborrow_as_reader(&self) -> Reader<'_,> {
           ::capnp::traits::FromStructReader::new(self.builder.into_reader())
         }
 
@@ -4928,7 +4928,7 @@ pub mod brand {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -4982,10 +4982,10 @@ pub mod brand {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -5064,7 +5064,7 @@ pub mod brand {
     }
 
     impl <'a,> Reader<'a,>  {
-      pub fn reborrow(&self) -> Reader<> {
+      pub fn reborrow(&self) -> Reader<'_,> {
         Reader { .. *self }
       }
 
@@ -5135,10 +5135,10 @@ pub mod brand {
       pub fn into_reader(self) -> Reader<'a,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
-      pub fn reborrow(&mut self) -> Builder<> {
+      pub fn reborrow(&mut self) -> Builder<'_,> {
         Builder { .. *self }
       }
-      pub fn reborrow_as_reader(&self) -> Reader<> {
+      pub fn reborrow_as_reader(&self) -> Reader<'_,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
 
@@ -5251,7 +5251,7 @@ pub mod brand {
     }
 
     impl <'a,> Reader<'a,>  {
-      pub fn reborrow(&self) -> Reader<> {
+      pub fn reborrow(&self) -> Reader<'_,> {
         Reader { .. *self }
       }
 
@@ -5318,10 +5318,10 @@ pub mod brand {
       pub fn into_reader(self) -> Reader<'a,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
-      pub fn reborrow(&mut self) -> Builder<> {
+      pub fn reborrow(&mut self) -> Builder<'_,> {
         Builder { .. *self }
       }
-      pub fn reborrow_as_reader(&self) -> Reader<> {
+      pub fn reborrow_as_reader(&self) -> Reader<'_,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
 
@@ -5427,7 +5427,7 @@ pub mod value {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -5595,10 +5595,10 @@ pub mod value {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -5914,7 +5914,7 @@ pub mod annotation {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -5979,10 +5979,10 @@ pub mod annotation {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -6126,7 +6126,7 @@ pub mod capnp_version {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -6185,10 +6185,10 @@ pub mod capnp_version {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -6275,7 +6275,7 @@ pub mod code_generator_request {
   }
 
   impl <'a,> Reader<'a,>  {
-    pub fn reborrow(&self) -> Reader<> {
+    pub fn reborrow(&self) -> Reader<'_,> {
       Reader { .. *self }
     }
 
@@ -6350,10 +6350,10 @@ pub mod code_generator_request {
     pub fn into_reader(self) -> Reader<'a,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
-    pub fn reborrow(&mut self) -> Builder<> {
+    pub fn reborrow(&mut self) -> Builder<'_,> {
       Builder { .. *self }
     }
-    pub fn reborrow_as_reader(&self) -> Reader<> {
+    pub fn reborrow_as_reader(&self) -> Reader<'_,> {
       ::capnp::traits::FromStructReader::new(self.builder.into_reader())
     }
 
@@ -6478,7 +6478,7 @@ pub mod code_generator_request {
     }
 
     impl <'a,> Reader<'a,>  {
-      pub fn reborrow(&self) -> Reader<> {
+      pub fn reborrow(&self) -> Reader<'_,> {
         Reader { .. *self }
       }
 
@@ -6543,10 +6543,10 @@ pub mod code_generator_request {
       pub fn into_reader(self) -> Reader<'a,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
-      pub fn reborrow(&mut self) -> Builder<> {
+      pub fn reborrow(&mut self) -> Builder<'_,> {
         Builder { .. *self }
       }
-      pub fn reborrow_as_reader(&self) -> Reader<> {
+      pub fn reborrow_as_reader(&self) -> Reader<'_,> {
         ::capnp::traits::FromStructReader::new(self.builder.into_reader())
       }
 
@@ -6646,7 +6646,7 @@ pub mod code_generator_request {
       }
 
       impl <'a,> Reader<'a,>  {
-        pub fn reborrow(&self) -> Reader<> {
+        pub fn reborrow(&self) -> Reader<'_,> {
           Reader { .. *self }
         }
 
@@ -6704,10 +6704,10 @@ pub mod code_generator_request {
         pub fn into_reader(self) -> Reader<'a,> {
           ::capnp::traits::FromStructReader::new(self.builder.into_reader())
         }
-        pub fn reborrow(&mut self) -> Builder<> {
+        pub fn reborrow(&mut self) -> Builder<'_,> {
           Builder { .. *self }
         }
-        pub fn reborrow_as_reader(&self) -> Reader<> {
+        pub fn reborrow_as_reader(&self) -> Reader<'_,> {
           ::capnp::traits::FromStructReader::new(self.builder.into_reader())
         }
 
@@ -0,0 +1,144 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Holds schema nodes loaded at runtime, for use by the `dynamic` module.
+//!
+//! Nodes are loaded from `CodeGeneratorRequest`s, such as the ones produced by
+//! `capnp compile -o-` or passed to compiler plugins, or one at a time, and can then be looked
+//! up by ID or by display name.
+
+use std::collections::HashMap;
+
+use capnp::message;
+use crate::schema_capnp::{code_generator_request, node};
+
+struct Segments(Box<dyn message::ReaderSegments>);
+
+impl message::ReaderSegments for Segments {
+    fn get_segment<'a>(&'a self, idx: u32) -> Option<&'a [u8]> {
+        self.0.get_segment(idx)
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+/// A set of schema nodes, indexed by ID and by display name.
+pub struct SchemaLoader {
+    messages: Vec<message::Reader<Segments>>,
+    nodes: HashMap<u64, (usize, u32)>,
+    names: HashMap<String, u64>,
+    requested_files: Vec<u64>,
+}
+
+impl SchemaLoader {
+    pub fn new() -> SchemaLoader {
+        SchemaLoader {
+            messages: Vec::new(),
+            nodes: HashMap::new(),
+            names: HashMap::new(),
+            requested_files: Vec::new(),
+        }
+    }
+
+    /// Loads every node of a `CodeGeneratorRequest`. Nodes that are already loaded are replaced.
+    pub fn load_request<S>(&mut self, request: message::Reader<S>) -> ::capnp::Result<()>
+        where S: message::ReaderSegments + 'static
+    {
+        // Schema messages are trusted and are read repeatedly, so reading them must not run
+        // into the traversal limit.
+        let mut options = message::ReaderOptions::new();
+        options.traversal_limit_in_words(u64::max_value());
+        let message = message::Reader::new(Segments(Box::new(request.into_segments())), options);
+
+        let message_index = self.messages.len();
+        {
+            let root: code_generator_request::Reader = message.get_root()?;
+            for (idx, node) in root.get_nodes()?.iter().enumerate() {
+                self.nodes.insert(node.get_id(), (message_index, idx as u32));
+                self.names.insert(node.get_display_name()?.to_string(), node.get_id());
+            }
+            for file in root.get_requested_files()?.iter() {
+                self.requested_files.push(file.get_id());
+            }
+        }
+        self.messages.push(message);
+        Ok(())
+    }
+
+    /// Loads a single node, such as one embedded in generated code with
+    /// `CompilerCommand::embed_schemas()`. A node that is already loaded is replaced.
+    pub fn load_node(&mut self, node: node::Reader) -> ::capnp::Result<()> {
+        let mut message = message::Builder::new_default();
+        {
+            let request: code_generator_request::Builder = message.init_root();
+            request.init_nodes(1).set_with_caveats(0, node)?;
+        }
+        self.load_request(message.into_reader())
+    }
+
+    /// Reads a serialized `CodeGeneratorRequest` from `read` and loads its nodes.
+    pub fn read_request<R>(&mut self, read: R) -> ::capnp::Result<()> where R: std::io::Read {
+        let message = capnp::serialize::read_message(
+            crate::codegen::ReadWrapper { inner: read },
+            message::ReaderOptions::new())?;
+        self.load_request(message)
+    }
+
+    /// Gets the node with the given ID.
+    pub fn get<'a>(&'a self, id: u64) -> Option<node::Reader<'a>> {
+        let &(message_index, node_index) = self.nodes.get(&id)?;
+        let root: code_generator_request::Reader = self.messages[message_index].get_root().ok()?;
+        Some(root.get_nodes().ok()?.get(node_index))
+    }
+
+    /// Like `get()`, but fails if the node is not loaded.
+    pub fn require<'a>(&'a self, id: u64) -> ::capnp::Result<node::Reader<'a>> {
+        self.get(id).ok_or_else(|| {
+            ::capnp::Error::failed(format!("schema node @0x{:x} is not loaded", id))
+        })
+    }
+
+    /// Finds a node by name. `name` may be a full display name such as `foo.capnp:Foo.Bar`, or
+    /// a name relative to one of the files that were requested when the nodes were compiled,
+    /// such as `Foo.Bar`.
+    pub fn find<'a>(&'a self, name: &str) -> Option<node::Reader<'a>> {
+        if let Some(&id) = self.names.get(name) {
+            return self.get(id);
+        }
+        for &file_id in &self.requested_files {
+            if let Some(file) = self.get(file_id) {
+                if let Ok(file_name) = file.get_display_name() {
+                    if let Some(&id) = self.names.get(&format!("{}:{}", file_name, name)) {
+                        return self.get(id);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Iterates over the IDs of the files that were requested when the nodes were compiled.
+    pub fn requested_files<'a>(&'a self) -> impl Iterator<Item = u64> + 'a {
+        self.requested_files.iter().cloned()
+    }
+}
@@ -0,0 +1,162 @@
+// Copyright (c) 2013-2014 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Paths of the Rust items generated for schema nodes.
+//!
+//! Every node that gets a Rust item has a `ScopePath`, which records where the path
+//! starts and each of its segments. Rendering happens only when code is emitted, so the
+//! same path can be written absolutely, relative to another item, or with its root
+//! swapped out for the crate that actually provides the generated code.
+
+use std::fmt;
+
+/// Where a path starts.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PathRoot {
+    /// The crate being built, rendered as `crate`.
+    Crate,
+
+    /// Another crate, rendered as `::name`.
+    Extern(String),
+}
+
+/// What a path names. Structs, interfaces, groups and files generate modules; enums
+/// generate a type directly, and constants a value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ItemKind {
+    Module,
+    Type,
+    Value,
+}
+
+/// The path of a generated item.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ScopePath {
+    pub root: PathRoot,
+
+    /// The segments after the root, including any `$Rust.parentModule` modules.
+    pub segments: Vec<String>,
+
+    pub kind: ItemKind,
+}
+
+impl ScopePath {
+    /// The path of the crate root itself.
+    pub fn crate_root() -> ScopePath {
+        ScopePath { root: PathRoot::Crate, segments: Vec::new(), kind: ItemKind::Module }
+    }
+
+    /// The path of an item named `name` inside this one.
+    pub fn child(&self, name: String, kind: ItemKind) -> ScopePath {
+        let mut segments = self.segments.clone();
+        segments.push(name);
+        ScopePath { root: self.root.clone(), segments: segments, kind: kind }
+    }
+
+    /// The path of the module enclosing this item. The crate root is its own parent.
+    pub fn parent(&self) -> ScopePath {
+        let mut segments = self.segments.clone();
+        segments.pop();
+        ScopePath { root: self.root.clone(), segments: segments, kind: ItemKind::Module }
+    }
+
+    /// The last segment, which is the name the item is declared with.
+    pub fn last_name(&self) -> Option<&str> {
+        self.segments.last().map(|s| &s[..])
+    }
+
+    /// The same path, starting from `root` instead.
+    pub fn with_root(&self, root: PathRoot) -> ScopePath {
+        ScopePath { root: root, segments: self.segments.clone(), kind: self.kind }
+    }
+
+    /// Renders the path as seen from inside the module `from`, climbing with `super` to
+    /// the deepest common ancestor. Paths with different roots stay absolute.
+    pub fn relative_to(&self, from: &ScopePath) -> String {
+        if self.root != from.root {
+            return self.to_string();
+        }
+        let common = self.segments.iter().zip(from.segments.iter())
+            .take_while(|&(a, b)| a == b)
+            .count();
+        let mut parts: Vec<&str> = Vec::new();
+        if common == 0 && from.segments.len() > 0 {
+            parts.push("crate");
+        } else {
+            for _ in common..from.segments.len() {
+                parts.push("super");
+            }
+            if parts.is_empty() {
+                parts.push("self");
+            }
+        }
+        parts.extend(self.segments[common..].iter().map(|s| &s[..]));
+        parts.join("::")
+    }
+}
+
+impl fmt::Display for ScopePath {
+    /// Renders the absolute path, e.g. `crate::foo_capnp::bar`.
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.root {
+            PathRoot::Crate => fmt.write_str("crate")?,
+            PathRoot::Extern(ref name) => write!(fmt, "::{}", name)?,
+        }
+        for segment in &self.segments {
+            write!(fmt, "::{}", segment)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ItemKind, PathRoot, ScopePath};
+
+    fn path(segments: &[&str]) -> ScopePath {
+        let mut result = ScopePath::crate_root();
+        for segment in segments {
+            result = result.child(segment.to_string(), ItemKind::Module);
+        }
+        result
+    }
+
+    #[test]
+    fn absolute() {
+        assert_eq!(path(&["foo_capnp", "bar"]).to_string(), "crate::foo_capnp::bar");
+        assert_eq!(path(&["foo_capnp", "bar"]).with_root(PathRoot::Extern("schemas".into())).to_string(),
+                   "::schemas::foo_capnp::bar");
+        assert_eq!(path(&["foo_capnp", "bar"]).parent(), path(&["foo_capnp"]));
+        assert_eq!(path(&["foo_capnp", "bar"]).last_name(), Some("bar"));
+    }
+
+    #[test]
+    fn relative() {
+        let target = path(&["foo_capnp", "bar", "baz"]);
+        assert_eq!(target.relative_to(&path(&["foo_capnp", "bar"])), "self::baz");
+        assert_eq!(target.relative_to(&path(&["foo_capnp", "qux", "quux"])), "super::super::bar::baz");
+        assert_eq!(target.relative_to(&path(&["other_capnp"])), "crate::foo_capnp::bar::baz");
+        assert_eq!(target.relative_to(&path(&["foo_capnp", "bar", "baz", "inner"])), "super");
+
+        let external = target.with_root(PathRoot::Extern("schemas".into()));
+        assert_eq!(external.relative_to(&path(&["foo_capnp", "bar"])), "::schemas::foo_capnp::bar::baz");
+    }
+}
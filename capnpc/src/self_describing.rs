@@ -0,0 +1,195 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! The schema-driven part of the CBOR and MessagePack codecs. Both formats describe the same
+//! kinds of values, so each only has to convert between its bytes and an `Item` tree.
+//!
+//! A struct becomes a map keyed by the names of the fields that are set, a list becomes an
+//! array, `Text` becomes a string, `Data` a byte string, `Void` null, and enums the names of their
+//! enumerants. Unlike JSON, integers and floats keep their full range. When reading, enums may
+//! also be given as numbers, integers are accepted for floats, and members whose value is null
+//! are skipped.
+
+use std::convert::TryFrom;
+
+use capnp::{Error, Result};
+
+use crate::dynamic::{self, ListBuilder, StructBuilder, Value};
+use crate::schema_capnp::{field, node, type_};
+use crate::schema_loader::SchemaLoader;
+use crate::text_format::printed_fields;
+
+/// A decoded CBOR or MessagePack value.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Item {
+    Null,
+    Bool(bool),
+    Int(i128),
+    Float32(f32),
+    Float64(f64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Item>),
+    Map(Vec<(Item, Item)>),
+}
+
+/// How deeply arrays, maps and tags may nest when decoding, matching the default
+/// `ReaderOptions::nesting_limit`.
+pub(crate) const NESTING_LIMIT: u32 = 64;
+
+fn item_error<T>(format: &str, message: &str) -> Result<T> {
+    Err(Error::failed(format!("{}: {}", format, message)))
+}
+
+pub(crate) fn from_value(format: &str, value: Value) -> Result<Item> {
+    Ok(match value {
+        Value::Void => Item::Null,
+        Value::Bool(b) => Item::Bool(b),
+        Value::Int8(n) => Item::Int(n as i128),
+        Value::Int16(n) => Item::Int(n as i128),
+        Value::Int32(n) => Item::Int(n as i128),
+        Value::Int64(n) => Item::Int(n as i128),
+        Value::Uint8(n) => Item::Int(n as i128),
+        Value::Uint16(n) => Item::Int(n as i128),
+        Value::Uint32(n) => Item::Int(n as i128),
+        Value::Uint64(n) => Item::Int(n as i128),
+        Value::Float32(n) => Item::Float32(n),
+        Value::Float64(n) => Item::Float64(n),
+        Value::Enum(e) => match e.get_enumerant()? {
+            Some(enumerant) => Item::Text(enumerant.get_name()?.to_string()),
+            None => Item::Int(e.get_value() as i128),
+        },
+        Value::Text(t) => Item::Text(t.to_string()),
+        Value::Data(d) => Item::Bytes(d.to_vec()),
+        Value::List(list) => {
+            let mut items = Vec::with_capacity(list.len() as usize);
+            for idx in 0..list.len() {
+                items.push(from_value(format, list.get(idx)?)?);
+            }
+            Item::Array(items)
+        }
+        Value::Struct(reader) => {
+            let mut members = Vec::new();
+            for (name, value) in printed_fields(&reader)? {
+                members.push((Item::Text(name.to_string()), from_value(format, value)?));
+            }
+            Item::Map(members)
+        }
+        Value::AnyPointer(_) | Value::Capability => {
+            return item_error(format, "AnyPointer and capability values have no encoding")
+        }
+    })
+}
+
+/// Sets the fields named by `item`, which must be a map, in `builder`.
+pub(crate) fn fill_struct(format: &str, builder: &mut StructBuilder, item: Item) -> Result<()> {
+    let members = match item {
+        Item::Map(members) => members,
+        _ => return item_error(format, "expected a map"),
+    };
+    for (key, item) in members {
+        let name = match key {
+            Item::Text(name) => name,
+            _ => return item_error(format, &format!("expected a field name, got {:?}", key)),
+        };
+        if item == Item::Null {
+            continue;
+        }
+        let field = builder.find_field(&name)?;
+        let typ = match field.which()? {
+            field::Group(_) => {
+                fill_struct(format, &mut builder.init_field(field)?, item)?;
+                continue;
+            }
+            field::Slot(slot) => slot.get_type()?,
+        };
+        match (typ.which()?, item) {
+            (type_::Struct(_), item) => fill_struct(format, &mut builder.init_field(field)?, item)?,
+            (type_::List(_), Item::Array(items)) => {
+                fill_list(format, &mut builder.init_list_field(field, items.len() as u32)?, items)?
+            }
+            (_, item) => builder.set_field(field, value(format, builder.get_loader(), typ, &item)?)?,
+        }
+    }
+    Ok(())
+}
+
+fn fill_list(format: &str, list: &mut ListBuilder, items: Vec<Item>) -> Result<()> {
+    let element_type = list.get_element_type();
+    for (idx, item) in items.into_iter().enumerate() {
+        let idx = idx as u32;
+        match (element_type.which()?, item) {
+            (type_::Struct(_), item) => fill_struct(format, &mut list.init(idx)?, item)?,
+            (type_::List(_), Item::Array(items)) => {
+                fill_list(format, &mut list.init_list(idx, items.len() as u32)?, items)?
+            }
+            (_, item) => list.set(idx, value(format, list.get_loader(), element_type, &item)?)?,
+        }
+    }
+    Ok(())
+}
+
+fn enumerant(format: &str, loader: &SchemaLoader, type_id: u64, item: &Item) -> Result<u16> {
+    let schema = loader.require(type_id)?;
+    match *item {
+        Item::Text(ref name) => {
+            if let node::Enum(e) = schema.which()? {
+                for (idx, enumerant) in e.get_enumerants()?.iter().enumerate() {
+                    if enumerant.get_name()? == name {
+                        return Ok(idx as u16);
+                    }
+                }
+            }
+            item_error(format, &format!("{} has no enumerant named {}", schema.get_display_name()?, name))
+        }
+        Item::Int(n) => u16::try_from(n).or_else(|_| item_error(format, &format!("enum value {} is out of range", n))),
+        _ => item_error(format, &format!("expected an enumerant, got {:?}", item)),
+    }
+}
+
+/// Interprets `item`, which is not a map or array, as a value of type `typ`.
+fn value<'a>(format: &str, loader: &'a SchemaLoader, typ: type_::Reader<'a>, item: &'a Item) -> Result<Value<'a>> {
+    Ok(match (typ.which()?, item) {
+        (type_::Void(()), _) => Value::Void,
+        (type_::Bool(()), &Item::Bool(b)) => Value::Bool(b),
+        (type_::Int8(()), &Item::Int(n)) | (type_::Int16(()), &Item::Int(n)) |
+        (type_::Int32(()), &Item::Int(n)) | (type_::Int64(()), &Item::Int(n)) => {
+            Value::Int64(i64::try_from(n).or_else(|_| item_error(format, &format!("integer {} is out of range", n)))?)
+        }
+        (type_::Uint8(()), &Item::Int(n)) | (type_::Uint16(()), &Item::Int(n)) |
+        (type_::Uint32(()), &Item::Int(n)) | (type_::Uint64(()), &Item::Int(n)) => {
+            Value::Uint64(u64::try_from(n).or_else(|_| item_error(format, &format!("integer {} is out of range", n)))?)
+        }
+        (type_::Float32(()), &Item::Float32(n)) | (type_::Float64(()), &Item::Float32(n)) => Value::Float64(n as f64),
+        (type_::Float32(()), &Item::Float64(n)) | (type_::Float64(()), &Item::Float64(n)) => Value::Float64(n),
+        (type_::Float32(()), &Item::Int(n)) | (type_::Float64(()), &Item::Int(n)) => Value::Float64(n as f64),
+        (type_::Enum(e), _) => {
+            let schema = loader.require(e.get_type_id())?;
+            Value::Enum(dynamic::Enum::new(enumerant(format, loader, e.get_type_id(), item)?, schema))
+        }
+        (type_::Text(()), &Item::Text(ref s)) => Value::Text(s),
+        (type_::Data(()), &Item::Bytes(ref b)) => Value::Data(b),
+        (type_::AnyPointer(_), _) | (type_::Interface(_), _) => {
+            return item_error(format, "AnyPointer and capability fields cannot be read")
+        }
+        _ => return item_error(format, &format!("value {:?} does not match the field's type", item)),
+    })
+}
@@ -0,0 +1,826 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! The Cap'n Proto text format, as used by the `capnp` tool's `decode` and `encode` commands
+//! and for constants in schema files.
+//!
+//! A struct is written as a parenthesized list of `name = value` pairs, a list as a bracketed
+//! list of values, and an enum value as the name of its enumerant:
+//!
+//! ```text
+//! (name = "Alice", scores = [3, 5], kind = student, address = (city = "Oslo"))
+//! ```
+
+use std::convert::TryFrom;
+
+use capnp::{any_pointer, Error, Result};
+
+use crate::dynamic::{self, ListBuilder, StructBuilder, StructReader, Value};
+use crate::schema_capnp::{field, node, type_};
+use crate::schema_loader::SchemaLoader;
+
+/// Lines of pretty-printed output are broken when they would be longer than this.
+const LINE_WIDTH: usize = 80;
+
+/// Writes `value` in text format, on a single line.
+pub fn to_string(value: Value) -> Result<String> {
+    let mut out = String::new();
+    write_value(&mut out, value)?;
+    Ok(out)
+}
+
+/// Writes `value` in text format, spreading structs and lists over several lines when they do
+/// not fit on one.
+pub fn to_pretty_string(value: Value) -> Result<String> {
+    pretty(value, 0)
+}
+
+/// Parses `text`, a struct in text format, and writes it to `builder` as a struct of type
+/// `schema`.
+pub fn read_struct(loader: &SchemaLoader, schema: node::Reader, text: &str,
+                   builder: any_pointer::Builder) -> Result<()>
+{
+    let fields = match Parser::new(text)?.parse_all()? {
+        Expr::Tuple(fields) => fields,
+        _ => return Err(Error::failed("expected a struct, in parentheses".to_string())),
+    };
+    fill_struct(&mut StructBuilder::init_any_pointer(loader, schema, builder)?, fields)
+}
+
+pub(crate) fn printed_fields<'a>(reader: &StructReader<'a>) -> Result<Vec<(&'a str, Value<'a>)>> {
+    let mut result = Vec::new();
+    for field in reader.get_fields()?.iter() {
+        if reader.has_field(field)? {
+            result.push((field.get_name()?, reader.get_field(field)?));
+        }
+    }
+    Ok(result)
+}
+
+fn write_value(out: &mut String, value: Value) -> Result<()> {
+    match value {
+        Value::Void => out.push_str("void"),
+        Value::Bool(b) => out.push_str(if b { "true" } else { "false" }),
+        Value::Int8(n) => out.push_str(&n.to_string()),
+        Value::Int16(n) => out.push_str(&n.to_string()),
+        Value::Int32(n) => out.push_str(&n.to_string()),
+        Value::Int64(n) => out.push_str(&n.to_string()),
+        Value::Uint8(n) => out.push_str(&n.to_string()),
+        Value::Uint16(n) => out.push_str(&n.to_string()),
+        Value::Uint32(n) => out.push_str(&n.to_string()),
+        Value::Uint64(n) => out.push_str(&n.to_string()),
+        Value::Float32(n) => write_float(out, n as f64),
+        Value::Float64(n) => write_float(out, n),
+        Value::Enum(e) => match e.get_enumerant()? {
+            Some(enumerant) => out.push_str(enumerant.get_name()?),
+            None => out.push_str(&e.get_value().to_string()),
+        },
+        Value::Text(t) => write_string(out, t.chars(), false),
+        Value::Data(d) => write_string(out, d.iter().map(|&b| b as char), true),
+        Value::List(list) => {
+            out.push('[');
+            for idx in 0..list.len() {
+                if idx > 0 { out.push_str(", "); }
+                write_value(out, list.get(idx)?)?;
+            }
+            out.push(']');
+        }
+        Value::Struct(reader) => {
+            out.push('(');
+            for (idx, (name, value)) in printed_fields(&reader)?.into_iter().enumerate() {
+                if idx > 0 { out.push_str(", "); }
+                out.push_str(name);
+                out.push_str(" = ");
+                write_value(out, value)?;
+            }
+            out.push(')');
+        }
+        Value::AnyPointer(_) => out.push_str("<opaque pointer>"),
+        Value::Capability => out.push_str("<external capability>"),
+    }
+    Ok(())
+}
+
+fn write_float(out: &mut String, n: f64) {
+    // Writing to a String cannot fail.
+    let _ = capnp::stringify::write_float(out, n);
+}
+
+fn write_string<I>(out: &mut String, chars: I, binary: bool) where I: Iterator<Item = char> {
+    let _ = capnp::stringify::write_string(out, chars, binary);
+}
+
+fn pretty(value: Value, indent: usize) -> Result<String> {
+    let flat = to_string(value)?;
+    if indent + flat.len() <= LINE_WIDTH {
+        return Ok(flat);
+    }
+    let separator = format!(",\n{}", " ".repeat(indent + 2));
+    match value {
+        Value::Struct(reader) => {
+            let mut items = Vec::new();
+            for (name, value) in printed_fields(&reader)? {
+                items.push(format!("{} = {}", name, pretty(value, indent + name.len() + 5)?));
+            }
+            Ok(format!("( {} )", items.join(&separator)))
+        }
+        Value::List(list) => {
+            let mut items = Vec::new();
+            for value in list.iter() {
+                items.push(pretty(value?, indent + 2)?);
+            }
+            Ok(format!("[ {} ]", items.join(&separator)))
+        }
+        _ => Ok(flat),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Token {
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Equals,
+    Comma,
+    Dot,
+    Colon,
+    Semicolon,
+    At,
+    Dollar,
+    Bang,
+    Star,
+    Arrow,
+    Ident(String),
+    Int(bool, u64),
+    Float(f64),
+    String(Vec<u8>),
+    Binary(Vec<u8>),
+}
+
+/// A parsed value. Names, member accesses, applications and imports only appear in schema
+/// files, where they refer to constants, types and annotations.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Expr {
+    Ident(String),
+    AbsoluteName(String),
+    Import(String),
+    Member(Box<Expr>, String),
+    Apply(Box<Expr>, Vec<(Option<String>, Expr)>),
+    Int(bool, u64),
+    Float(f64),
+    String(Vec<u8>),
+    Binary(Vec<u8>),
+    List(Vec<Expr>),
+    Tuple(Vec<(Option<String>, Expr)>),
+}
+
+fn parse_error<T>(message: &str) -> Result<T> {
+    Err(Error::failed(format!("text format: {}", message)))
+}
+
+/// Splits `text` into tokens, each paired with the line it starts on.
+pub(crate) fn tokenize(text: &str) -> Result<Vec<(Token, usize)>> {
+    let bytes = text.as_bytes();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    let mut line = 1;
+    while pos < bytes.len() {
+        let c = bytes[pos];
+        let punctuation = match c {
+            b'(' => Some(Token::LParen),
+            b')' => Some(Token::RParen),
+            b'[' => Some(Token::LBracket),
+            b']' => Some(Token::RBracket),
+            b'{' => Some(Token::LBrace),
+            b'}' => Some(Token::RBrace),
+            b'=' => Some(Token::Equals),
+            b',' => Some(Token::Comma),
+            b'.' => Some(Token::Dot),
+            b':' => Some(Token::Colon),
+            b';' => Some(Token::Semicolon),
+            b'@' => Some(Token::At),
+            b'$' => Some(Token::Dollar),
+            b'!' => Some(Token::Bang),
+            b'*' => Some(Token::Star),
+            _ => None,
+        };
+        if let Some(token) = punctuation {
+            tokens.push((token, line));
+            pos += 1;
+            continue;
+        }
+        match c {
+            b'\n' => { line += 1; pos += 1; }
+            b' ' | b'\t' | b'\r' => { pos += 1; }
+            b'#' => {
+                while pos < bytes.len() && bytes[pos] != b'\n' { pos += 1; }
+            }
+            b'-' if bytes.get(pos + 1) == Some(&b'>') => {
+                tokens.push((Token::Arrow, line));
+                pos += 2;
+            }
+            b'"' => {
+                let (string, end) = read_string(bytes, pos + 1)?;
+                line += bytes[pos..end].iter().filter(|&&b| b == b'\n').count();
+                tokens.push((Token::String(string), line));
+                pos = end;
+            }
+            b'0' if bytes.get(pos + 1) == Some(&b'x') && bytes.get(pos + 2) == Some(&b'"') => {
+                let end = match bytes[pos + 3..].iter().position(|&b| b == b'"') {
+                    Some(n) => pos + 3 + n,
+                    None => return parse_error("unterminated binary literal"),
+                };
+                let hex: Vec<u8> = bytes[pos + 3..end].iter().cloned()
+                    .filter(|b| !b.is_ascii_whitespace()).collect();
+                if hex.len() % 2 != 0 {
+                    return parse_error("binary literal has an odd number of digits");
+                }
+                let mut data = Vec::new();
+                for pair in hex.chunks(2) {
+                    match u8::from_str_radix(std::str::from_utf8(pair).unwrap_or(""), 16) {
+                        Ok(b) => data.push(b),
+                        Err(_) => return parse_error("invalid digit in binary literal"),
+                    }
+                }
+                line += bytes[pos..end].iter().filter(|&&b| b == b'\n').count();
+                tokens.push((Token::Binary(data), line));
+                pos = end + 1;
+            }
+            b'-' | b'0'..=b'9' => {
+                let negative = c == b'-';
+                let start = if negative { pos + 1 } else { pos };
+                let mut end = start;
+                while end < bytes.len() &&
+                    (bytes[end].is_ascii_alphanumeric() || bytes[end] == b'.' || bytes[end] == b'_' ||
+                     ((bytes[end] == b'-' || bytes[end] == b'+') &&
+                      (bytes[end - 1] == b'e' || bytes[end - 1] == b'E') &&
+                      !bytes[start..end].starts_with(b"0x")))
+                {
+                    end += 1;
+                }
+                let literal = &text[start..end];
+                tokens.push((number_token(negative, literal)?, line));
+                pos = end;
+            }
+            c if c.is_ascii_alphabetic() || c == b'_' => {
+                let mut end = pos;
+                while end < bytes.len() && (bytes[end].is_ascii_alphanumeric() || bytes[end] == b'_') {
+                    end += 1;
+                }
+                tokens.push((Token::Ident(text[pos..end].to_string()), line));
+                pos = end;
+            }
+            _ => return parse_error(&format!("unexpected character {:?}", c as char)),
+        }
+    }
+    Ok(tokens)
+}
+
+fn number_token(negative: bool, literal: &str) -> Result<Token> {
+    if literal == "inf" {
+        return Ok(Token::Float(if negative { std::f64::NEG_INFINITY } else { std::f64::INFINITY }));
+    }
+    let parsed = if literal.starts_with("0x") || literal.starts_with("0X") {
+        u64::from_str_radix(&literal[2..], 16).ok().map(|n| Token::Int(negative, n))
+    } else if literal.contains(|c| c == '.' || c == 'e' || c == 'E') {
+        literal.parse::<f64>().ok().map(|n| Token::Float(if negative { -n } else { n }))
+    } else if literal.len() > 1 && literal.starts_with('0') {
+        u64::from_str_radix(&literal[1..], 8).ok().map(|n| Token::Int(negative, n))
+    } else {
+        literal.parse::<u64>().ok().map(|n| Token::Int(negative, n))
+    };
+    match parsed {
+        Some(token) => Ok(token),
+        None => parse_error(&format!("invalid number {}", literal)),
+    }
+}
+
+/// Reads a string literal starting just after its opening quote. Returns the string's bytes
+/// and the position just after its closing quote.
+fn read_string(bytes: &[u8], mut pos: usize) -> Result<(Vec<u8>, usize)> {
+    let mut result = Vec::new();
+    loop {
+        match bytes.get(pos) {
+            None => return parse_error("unterminated string literal"),
+            Some(b'"') => return Ok((result, pos + 1)),
+            Some(b'\\') => {
+                let escaped = match bytes.get(pos + 1) {
+                    Some(&b) => b,
+                    None => return parse_error("unterminated string literal"),
+                };
+                pos += 2;
+                match escaped {
+                    b'n' => result.push(b'\n'),
+                    b'r' => result.push(b'\r'),
+                    b't' => result.push(b'\t'),
+                    b'a' => result.push(0x07),
+                    b'b' => result.push(0x08),
+                    b'f' => result.push(0x0c),
+                    b'v' => result.push(0x0b),
+                    b'0' => result.push(0),
+                    b'x' => {
+                        let digits = bytes.get(pos..pos + 2).and_then(|d| std::str::from_utf8(d).ok());
+                        match digits.and_then(|d| u8::from_str_radix(d, 16).ok()) {
+                            Some(b) => result.push(b),
+                            None => return parse_error("invalid \\x escape"),
+                        }
+                        pos += 2;
+                    }
+                    b => result.push(b),
+                }
+            }
+            Some(&b) => {
+                result.push(b);
+                pos += 1;
+            }
+        }
+    }
+}
+
+/// Parses tokenized values. The schema parser also uses it for the declarations around them,
+/// in which case `file` names the schema file for error messages.
+pub(crate) struct Parser {
+    tokens: Vec<Token>,
+    lines: Vec<usize>,
+    pub(crate) pos: usize,
+    file: Option<String>,
+}
+
+impl Parser {
+    fn new(text: &str) -> Result<Parser> {
+        let (tokens, lines) = tokenize(text)?.into_iter().unzip();
+        Ok(Parser { tokens: tokens, lines: lines, pos: 0, file: None })
+    }
+
+    pub(crate) fn for_file(file: &str, text: &str) -> Result<Parser> {
+        let mut parser = Parser::new(text).map_err(|e| {
+            Error::failed(format!("{}: {}", file, e.description.trim_start_matches("text format: ")))
+        })?;
+        parser.file = Some(file.to_string());
+        Ok(parser)
+    }
+
+    /// Returns an error located at the current token.
+    pub(crate) fn error<T>(&self, message: &str) -> Result<T> {
+        match self.file {
+            None => parse_error(message),
+            Some(ref file) => Err(Error::failed(format!("{}:{}: {}", file, self.line(), message))),
+        }
+    }
+
+    /// The line of the current token.
+    pub(crate) fn line(&self) -> usize {
+        match self.lines.get(self.pos) {
+            Some(&line) => line,
+            None => self.lines.last().cloned().unwrap_or(1),
+        }
+    }
+
+    fn parse_all(&mut self) -> Result<Expr> {
+        let expr = self.parse_expr()?;
+        if self.pos < self.tokens.len() {
+            return self.error("unexpected input after value");
+        }
+        Ok(expr)
+    }
+
+    pub(crate) fn next(&mut self) -> Result<Token> {
+        match self.tokens.get(self.pos) {
+            Some(token) => {
+                self.pos += 1;
+                Ok(token.clone())
+            }
+            None => self.error("unexpected end of input"),
+        }
+    }
+
+    pub(crate) fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    pub(crate) fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + offset)
+    }
+
+    /// Consumes the next token, which must be `token`.
+    pub(crate) fn expect(&mut self, token: Token, what: &str) -> Result<()> {
+        if self.peek() == Some(&token) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            self.error(&format!("expected {}", what))
+        }
+    }
+
+    pub(crate) fn parse_expr(&mut self) -> Result<Expr> {
+        let mut expr = match self.next()? {
+            Token::Ident(ref name) if name == "import" => match self.next()? {
+                Token::String(path) => match String::from_utf8(path) {
+                    Ok(path) => Expr::Import(path),
+                    Err(_) => return self.error("import path is not valid UTF-8"),
+                },
+                _ => return self.error("expected a string after import"),
+            },
+            Token::Ident(name) => Expr::Ident(name),
+            Token::Dot => match self.next()? {
+                Token::Ident(name) => Expr::AbsoluteName(name),
+                _ => return self.error("expected a name after ."),
+            },
+            Token::Int(negative, n) => Expr::Int(negative, n),
+            Token::Float(n) => Expr::Float(n),
+            Token::String(s) => Expr::String(s),
+            Token::Binary(b) => Expr::Binary(b),
+            Token::LBracket => {
+                let mut items = Vec::new();
+                while self.peek() != Some(&Token::RBracket) {
+                    items.push(self.parse_expr()?);
+                    if self.peek() == Some(&Token::Comma) {
+                        self.pos += 1;
+                    } else {
+                        break;
+                    }
+                }
+                self.expect(Token::RBracket, "]")?;
+                Expr::List(items)
+            }
+            Token::LParen => Expr::Tuple(self.parse_tuple()?),
+            token => return self.error(&format!("unexpected {:?}", token)),
+        };
+        loop {
+            match (&expr, self.peek()) {
+                (Expr::Int(..), _) | (Expr::Float(_), _) | (Expr::String(_), _) |
+                (Expr::Binary(_), _) | (Expr::List(_), _) | (Expr::Tuple(_), _) => break,
+                (_, Some(Token::Dot)) => {
+                    self.pos += 1;
+                    match self.next()? {
+                        Token::Ident(name) => expr = Expr::Member(Box::new(expr), name),
+                        _ => return self.error("expected a name after ."),
+                    }
+                }
+                (_, Some(Token::LParen)) => {
+                    self.pos += 1;
+                    expr = Expr::Apply(Box::new(expr), self.parse_tuple()?);
+                }
+                _ => break,
+            }
+        }
+        Ok(expr)
+    }
+
+    /// Parses the items of a parenthesized list, after its opening parenthesis.
+    fn parse_tuple(&mut self) -> Result<Vec<(Option<String>, Expr)>> {
+        let mut items = Vec::new();
+        while self.peek() != Some(&Token::RParen) {
+            let name = match (self.tokens.get(self.pos), self.tokens.get(self.pos + 1)) {
+                (Some(Token::Ident(name)), Some(Token::Equals)) => {
+                    let name = name.clone();
+                    self.pos += 2;
+                    Some(name)
+                }
+                _ => None,
+            };
+            items.push((name, self.parse_expr()?));
+            if self.peek() == Some(&Token::Comma) {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        self.expect(Token::RParen, ")")?;
+        Ok(items)
+    }
+}
+
+fn integer<T: TryFrom<i128>>(expr: &Expr) -> Result<T> {
+    match *expr {
+        Expr::Int(negative, n) => {
+            let n = if negative { -(n as i128) } else { n as i128 };
+            T::try_from(n).or_else(|_| parse_error(&format!("integer {} is out of range", n)))
+        }
+        _ => parse_error(&format!("expected an integer, got {:?}", expr)),
+    }
+}
+
+fn float(expr: &Expr) -> Result<f64> {
+    match *expr {
+        Expr::Int(negative, n) => Ok(if negative { -(n as f64) } else { n as f64 }),
+        Expr::Float(n) => Ok(n),
+        Expr::Ident(ref name) if name == "inf" => Ok(std::f64::INFINITY),
+        Expr::Ident(ref name) if name == "nan" => Ok(std::f64::NAN),
+        _ => parse_error(&format!("expected a number, got {:?}", expr)),
+    }
+}
+
+fn boolean(expr: &Expr) -> Result<bool> {
+    match *expr {
+        Expr::Ident(ref name) if name == "true" => Ok(true),
+        Expr::Ident(ref name) if name == "false" => Ok(false),
+        _ => parse_error(&format!("expected true or false, got {:?}", expr)),
+    }
+}
+
+fn enumerant(loader: &SchemaLoader, type_id: u64, expr: &Expr) -> Result<u16> {
+    let schema = loader.require(type_id)?;
+    if let Expr::Ident(ref name) = *expr {
+        if let node::Enum(e) = schema.which()? {
+            for (idx, enumerant) in e.get_enumerants()?.iter().enumerate() {
+                if enumerant.get_name()? == name {
+                    return Ok(idx as u16);
+                }
+            }
+        }
+        return parse_error(&format!("{} has no enumerant named {}", schema.get_display_name()?, name));
+    }
+    integer(expr)
+}
+
+fn fill_struct(builder: &mut StructBuilder, fields: Vec<(Option<String>, Expr)>) -> Result<()> {
+    for (name, expr) in fields {
+        let name = match name {
+            Some(name) => name,
+            None => return parse_error("struct fields must be named"),
+        };
+        let field = builder.find_field(&name)?;
+        let typ = match field.which()? {
+            field::Group(_) => match expr {
+                Expr::Tuple(fields) => {
+                    fill_struct(&mut builder.init_field(field)?, fields)?;
+                    continue;
+                }
+                _ => return parse_error(&format!("expected a group for field {}", name)),
+            },
+            field::Slot(slot) => slot.get_type()?,
+        };
+        match (typ.which()?, expr) {
+            (type_::Struct(_), Expr::Tuple(fields)) => fill_struct(&mut builder.init_field(field)?, fields)?,
+            (type_::List(_), Expr::List(items)) => {
+                fill_list(&mut builder.init_list_field(field, items.len() as u32)?, items)?
+            }
+            (_, expr) => builder.set_field(field, value(builder.get_loader(), typ, &expr)?)?,
+        }
+    }
+    Ok(())
+}
+
+fn fill_list(list: &mut ListBuilder, items: Vec<Expr>) -> Result<()> {
+    let element_type = list.get_element_type();
+    for (idx, item) in items.into_iter().enumerate() {
+        let idx = idx as u32;
+        match (element_type.which()?, item) {
+            (type_::Struct(_), Expr::Tuple(fields)) => fill_struct(&mut list.init(idx)?, fields)?,
+            (type_::Struct(_), _) => return parse_error("expected a struct element"),
+            (type_::List(_), Expr::List(items)) => fill_list(&mut list.init_list(idx, items.len() as u32)?, items)?,
+            (_, item) => list.set(idx, value(list.get_loader(), element_type, &item)?)?,
+        }
+    }
+    Ok(())
+}
+
+/// Interprets `expr`, which is not a struct or list, as a value of type `typ`.
+fn value<'a>(loader: &'a SchemaLoader, typ: type_::Reader<'a>, expr: &'a Expr) -> Result<Value<'a>> {
+    Ok(match (typ.which()?, expr) {
+        (type_::Void(()), _) => Value::Void,
+        (type_::Bool(()), _) => Value::Bool(boolean(expr)?),
+        (type_::Int8(()), _) | (type_::Int16(()), _) | (type_::Int32(()), _) | (type_::Int64(()), _) => {
+            Value::Int64(integer::<i64>(expr)?)
+        }
+        (type_::Uint8(()), _) | (type_::Uint16(()), _) | (type_::Uint32(()), _) | (type_::Uint64(()), _) => {
+            Value::Uint64(integer::<u64>(expr)?)
+        }
+        (type_::Float32(()), _) | (type_::Float64(()), _) => Value::Float64(float(expr)?),
+        (type_::Enum(e), _) => {
+            let schema = loader.require(e.get_type_id())?;
+            Value::Enum(dynamic::Enum::new(enumerant(loader, e.get_type_id(), expr)?, schema))
+        }
+        (type_::Text(()), &Expr::String(ref bytes)) => match std::str::from_utf8(bytes) {
+            Ok(s) => Value::Text(s),
+            Err(_) => return parse_error("text is not valid UTF-8"),
+        },
+        (type_::Data(()), &Expr::String(ref bytes)) | (type_::Data(()), &Expr::Binary(ref bytes)) => {
+            Value::Data(bytes)
+        }
+        (type_::AnyPointer(_), _) | (type_::Interface(_), _) => {
+            return parse_error("AnyPointer and capability fields cannot be written in text format")
+        }
+        _ => return parse_error(&format!("value {:?} does not match the field's type", expr)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use capnp::{any_pointer, message};
+    use crate::dynamic::{StructBuilder, StructReader, Value};
+    use crate::schema_capnp::{code_generator_request, field, node};
+    use crate::schema_loader::SchemaLoader;
+
+    const PERSON: u64 = 0x8000_0000_0000_0020;
+    const KIND: u64 = 0x8000_0000_0000_0030;
+
+    fn init_field<'a>(fields: &'a mut ::capnp::struct_list::Builder<field::Owned>, idx: u32,
+                      name: &str, discriminant: u16, offset: u32) -> crate::schema_capnp::type_::Builder<'a> {
+        let mut field = fields.reborrow().get(idx);
+        field.set_name(name);
+        field.set_code_order(idx as u16);
+        field.set_discriminant_value(discriminant);
+        field.reborrow().init_ordinal().set_explicit(idx as u16);
+        let mut slot = field.init_slot();
+        slot.set_offset(offset);
+        slot.init_type()
+    }
+
+    /// Loads a schema equivalent to:
+    ///
+    /// ```text
+    /// struct Person {
+    ///   id @0 :UInt32;
+    ///   name @1 :Text;
+    ///   scores @2 :List(Int32);
+    ///   kind @3 :Kind;
+    ///   union {
+    ///     unemployed @4 :Void;
+    ///     school @5 :Text;
+    ///   }
+    ///   friends @6 :List(Person);
+    /// }
+    /// enum Kind { student @0; teacher @1; }
+    /// ```
+    fn load_schema() -> SchemaLoader {
+        let mut message = message::Builder::new_default();
+        {
+            let mut request = message.init_root::<code_generator_request::Builder>();
+            request.reborrow().init_requested_files(1).get(0).set_id(0x8000_0000_0000_0010);
+            let mut nodes = request.init_nodes(3);
+            {
+                let mut file = nodes.reborrow().get(0);
+                file.set_id(0x8000_0000_0000_0010);
+                file.set_display_name("test.capnp");
+                file.set_file(());
+            }
+            {
+                let mut person = nodes.reborrow().get(1);
+                person.set_id(PERSON);
+                person.set_display_name("test.capnp:Person");
+                let mut s = person.init_struct();
+                s.set_data_word_count(1);
+                s.set_pointer_count(4);
+                s.set_discriminant_count(2);
+                s.set_discriminant_offset(3);
+                let mut fields = s.init_fields(7);
+                let none = field::NO_DISCRIMINANT;
+                init_field(&mut fields, 0, "id", none, 0).set_uint32(());
+                init_field(&mut fields, 1, "name", none, 0).set_text(());
+                init_field(&mut fields, 2, "scores", none, 1).init_list().init_element_type().set_int32(());
+                init_field(&mut fields, 3, "kind", none, 2).init_enum().set_type_id(KIND);
+                init_field(&mut fields, 4, "unemployed", 0, 0).set_void(());
+                init_field(&mut fields, 5, "school", 1, 2).set_text(());
+                init_field(&mut fields, 6, "friends", none, 3)
+                    .init_list().init_element_type().init_struct().set_type_id(PERSON);
+            }
+            let mut kind = nodes.get(2);
+            kind.set_id(KIND);
+            kind.set_display_name("test.capnp:Kind");
+            let mut enumerants = kind.init_enum().init_enumerants(2);
+            enumerants.reborrow().get(0).set_name("student");
+            enumerants.get(1).set_name("teacher");
+        }
+        let mut loader = SchemaLoader::new();
+        loader.load_request(message.into_reader()).unwrap();
+        loader
+    }
+
+    fn round_trip(loader: &SchemaLoader, text: &str) -> String {
+        let schema = loader.find("Person").unwrap();
+        let mut message = message::Builder::new_default();
+        super::read_struct(loader, schema, text, message.init_root()).unwrap();
+        let root = message.get_root_as_reader::<any_pointer::Reader>().unwrap();
+        let reader = StructReader::from_any_pointer(loader, schema, root).unwrap();
+        super::to_string(Value::Struct(reader)).unwrap()
+    }
+
+    #[test]
+    fn encode_and_decode() {
+        let loader = load_schema();
+        assert_eq!(
+            round_trip(&loader, "(id = 7, name = \"Alice \\\"A\\\"\\n\", scores = [1, -2, 0x10], \
+                                 kind = teacher, school = \"MIT\")"),
+            "(id = 7, name = \"Alice \\\"A\\\"\\n\", scores = [1, -2, 16], kind = teacher, \
+             school = \"MIT\")");
+        assert_eq!(round_trip(&loader, "()"), "(id = 0, kind = student, unemployed = void)");
+        assert_eq!(
+            round_trip(&loader, "(friends = [(id = 1, unemployed = void), (name = \"Bob\")])"),
+            "(id = 0, kind = student, unemployed = void, friends = [(id = 1, kind = student, \
+             unemployed = void), (id = 0, name = \"Bob\", kind = student, unemployed = void)])");
+    }
+
+    #[test]
+    fn dynamic_getters() {
+        let loader = load_schema();
+        let schema = loader.find("test.capnp:Person").unwrap();
+        let mut message = message::Builder::new_default();
+        super::read_struct(&loader, schema, "(id = 12, kind = teacher, school = \"X\")",
+                           message.init_root()).unwrap();
+        let root = message.get_root_as_reader::<any_pointer::Reader>().unwrap();
+        let reader = StructReader::from_any_pointer(&loader, schema, root).unwrap();
+        match reader.get("id").unwrap() {
+            Value::Uint32(12) => (),
+            _ => panic!("expected id = 12"),
+        }
+        match reader.get("kind").unwrap() {
+            Value::Enum(e) => assert_eq!(e.get_enumerant().unwrap().unwrap().get_name().unwrap(), "teacher"),
+            _ => panic!("expected an enum"),
+        }
+        assert_eq!(reader.which().unwrap().unwrap().get_name().unwrap(), "school");
+        assert!(reader.get("nonexistent").is_err());
+        match loader.find("Kind").unwrap().which().unwrap() {
+            node::Enum(_) => (),
+            _ => panic!("expected an enum node"),
+        }
+    }
+
+    #[test]
+    fn dynamic_builder() {
+        let loader = load_schema();
+        let schema = loader.find("Person").unwrap();
+        let mut message = message::Builder::new_default();
+        {
+            let mut person = StructBuilder::init_any_pointer(&loader, schema, message.init_root()).unwrap();
+            person.set("id", Value::Uint8(7)).unwrap();
+            person.set("name", Value::Text("Bob")).unwrap();
+            let mut scores = person.init_list("scores", 2).unwrap();
+            scores.set(0, Value::Int32(-1)).unwrap();
+            scores.set(1, Value::Int64(2)).unwrap();
+            assert!(scores.set(1, Value::Int64(1 << 40)).is_err());
+            assert!(scores.set(2, Value::Int32(0)).is_err());
+            person.init_list("friends", 1).unwrap().init(0).unwrap().set("id", Value::Uint32(8)).unwrap();
+            person.set("school", Value::Text("X")).unwrap();
+            person.init_union_field("unemployed").unwrap();
+            assert!(person.set("id", Value::Int32(-1)).is_err());
+            assert!(person.set("name", Value::Uint32(1)).is_err());
+            assert!(person.init_union_field("id").is_err());
+        }
+        let root = message.get_root_as_reader::<any_pointer::Reader>().unwrap();
+        let reader = StructReader::from_any_pointer(&loader, schema, root).unwrap();
+        assert_eq!(super::to_string(Value::Struct(reader)).unwrap(),
+                   "(id = 7, name = \"Bob\", scores = [-1, 2], kind = student, unemployed = void, \
+                    friends = [(id = 8, kind = student, unemployed = void)])");
+    }
+
+    #[test]
+    fn parse_errors() {
+        let loader = load_schema();
+        let schema = loader.find("Person").unwrap();
+        for text in &["(id = -1)", "(id = 1", "(kind = nobody)", "(nope = 1)", "(name = 5)", "[]"] {
+            let mut message = message::Builder::new_default();
+            assert!(super::read_struct(&loader, schema, text, message.init_root()).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn pretty_printing() {
+        let loader = load_schema();
+        let schema = loader.find("Person").unwrap();
+        let mut message = message::Builder::new_default();
+        super::read_struct(&loader, schema,
+                           "(name = \"Alice\", friends = [(name = \"Bob Bobson\", scores = [1, 2, 3]), \
+                            (name = \"Carol Carolson\", scores = [4, 5, 6])])",
+                           message.init_root()).unwrap();
+        let root = message.get_root_as_reader::<any_pointer::Reader>().unwrap();
+        let reader = StructReader::from_any_pointer(&loader, schema, root).unwrap();
+        assert_eq!(super::to_pretty_string(Value::Struct(reader)).unwrap(),
+                   "( id = 0,\n  \
+                      name = \"Alice\",\n  \
+                      kind = student,\n  \
+                      unemployed = void,\n  \
+                      friends = [ ( id = 0,\n                \
+                                    name = \"Bob Bobson\",\n                \
+                                    scores = [1, 2, 3],\n                \
+                                    kind = student,\n                \
+                                    unemployed = void ),\n              \
+                                  ( id = 0,\n                \
+                                    name = \"Carol Carolson\",\n                \
+                                    scores = [4, 5, 6],\n                \
+                                    kind = student,\n                \
+                                    unemployed = void ) ] )");
+    }
+}
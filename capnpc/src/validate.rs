@@ -0,0 +1,158 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Checks that an untrusted message is well-formed for its schema, before handing it to code
+//! that reads it.
+//!
+//! Cap'n Proto readers check each pointer lazily, when it is first followed, so a malformed
+//! message is otherwise only noticed partway through processing it. Validation follows every
+//! pointer up front and also rejects values that the schema does not know about, which a
+//! reader would silently accept: enum values without an enumerant and union discriminants
+//! without a member. Because of that, messages written with a newer version of the schema
+//! may fail to validate.
+
+use capnp::{any_pointer, Error, Result};
+use capnp::traits::HasTypeId;
+
+use crate::dynamic::{StructReader, Value};
+use crate::schema_loader::SchemaLoader;
+
+/// Validates `root` as a struct of type `T`, which is a generated reader type such as
+/// `person::Reader`, using the schema of `T` held by `loader`.
+pub fn validate<T: HasTypeId>(loader: &SchemaLoader, root: any_pointer::Reader) -> Result<()> {
+    let schema = loader.require(T::type_id())?;
+    validate_struct(StructReader::from_any_pointer(loader, schema, root).map_err(|e| at("", e))?)
+}
+
+/// Validates the struct `reader` and everything it points to. Errors start with the path of
+/// the offending value, such as `friends[2].name`.
+pub fn validate_struct(reader: StructReader) -> Result<()> {
+    check_struct("", reader)
+}
+
+fn at(path: &str, error: Error) -> Error {
+    let path = if path.is_empty() { "(root)" } else { path };
+    Error { description: format!("{}: {}", path, error.description), kind: error.kind }
+}
+
+fn check_struct(path: &str, reader: StructReader) -> Result<()> {
+    let schema = reader.get_schema();
+    let s = crate::dynamic::struct_node(schema)?;
+    if s.get_discriminant_count() > 0 && reader.which()?.is_none() {
+        return Err(at(path, Error::failed(format!(
+            "union discriminant is not one of {}'s members", schema.get_display_name()?))));
+    }
+    for field in s.get_fields()?.iter() {
+        if !reader.has_field(field)? {
+            continue;
+        }
+        let name = field.get_name()?;
+        let path = if path.is_empty() { name.to_string() } else { format!("{}.{}", path, name) };
+        let value = reader.get_field(field).map_err(|e| at(&path, e))?;
+        check_value(&path, value)?;
+    }
+    Ok(())
+}
+
+fn check_value(path: &str, value: Value) -> Result<()> {
+    match value {
+        Value::Enum(e) => {
+            if e.get_enumerant()?.is_none() {
+                return Err(at(path, Error::failed(format!(
+                    "{} has no enumerant with value {}", e.get_schema().get_display_name()?, e.get_value()))));
+            }
+        }
+        Value::Struct(s) => check_struct(path, s)?,
+        Value::List(list) => {
+            for idx in 0..list.len() {
+                let path = format!("{}[{}]", path, idx);
+                let element = list.get(idx).map_err(|e| at(&path, e))?;
+                check_value(&path, element)?;
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use capnp::{any_pointer, message};
+    use crate::compiler::compile_with;
+    use crate::dynamic::StructReader;
+    use crate::schema_loader::SchemaLoader;
+    use crate::text_format;
+
+    // `Raw` has the same layout as `Person`, with types that can hold anything, so that it
+    // can be used to write malformed `Person`s.
+    const SCHEMA: &'static str = "
+        @0xc9d5a3b7e1f2a4c6;
+        enum Kind { student @0; teacher @1; }
+        struct Person {
+          name @0 :Text;
+          kind @1 :Kind;
+          union {
+            unemployed @2 :Void;
+            age @3 :UInt8;
+          }
+          friend @4 :Person;
+          nicknames @5 :List(Text);
+        }
+        struct Raw {
+          name @0 :Data;
+          kind @1 :UInt16;
+          discriminant @2 :UInt16;
+          age @3 :UInt8;
+          friend @4 :List(Int32);
+          nicknames @5 :List(Data);
+        }";
+
+    fn validate(raw: &str) -> Result<(), String> {
+        let message = compile_with(&[PathBuf::from("person.capnp")], &[], &[],
+                                   &|_: &Path| Ok(SCHEMA.to_string())).unwrap();
+        let mut loader = SchemaLoader::new();
+        loader.load_request(message.into_reader()).unwrap();
+        let mut message = message::Builder::new_default();
+        text_format::read_struct(&loader, loader.find("Raw").unwrap(), raw, message.init_root()).unwrap();
+        let root = message.get_root_as_reader::<any_pointer::Reader>().unwrap();
+        let reader = StructReader::from_any_pointer(&loader, loader.find("Person").unwrap(), root).unwrap();
+        super::validate_struct(reader).map_err(|e| e.description)
+    }
+
+    #[test]
+    fn valid() {
+        assert_eq!(validate("(name = 0x\"6f6b00\", kind = 1, discriminant = 1, age = 3, \
+                             nicknames = [0x\"6100\"])"), Ok(()));
+    }
+
+    #[test]
+    fn invalid() {
+        let error = |raw| validate(raw).unwrap_err();
+        assert_eq!(error("(kind = 2)"), "kind: person.capnp:Kind has no enumerant with value 2");
+        assert_eq!(error("(discriminant = 2)"),
+                   "(root): union discriminant is not one of person.capnp:Person's members");
+        assert!(error("(name = 0x\"6f6b\")").starts_with("name: "));
+        assert!(error("(friend = [1])").starts_with("friend: "));
+        assert!(error("(nicknames = [0x\"6100\", 0x\"ff00\"])").starts_with("nicknames[1]: "));
+    }
+}
@@ -2,12 +2,21 @@ extern crate capnpc;
 
 fn main() {
      capnpc::CompilerCommand::new()
+        .builtin_compiler()
         .file("test.capnp")
         .file("in-submodule.capnp")
         .file("in-other-submodule.capnp")
         .file("schema/test-in-dir.capnp")
         .file("schema-with-src-prefix/test-in-src-prefix-dir.capnp")
         .src_prefix("schema-with-src-prefix")
+        .inline_tests()
         .run()
         .expect("compiling schema");
+
+    capnpc::CompilerCommand::new()
+        .builtin_compiler()
+        .file("embedded-schemas.capnp")
+        .embed_schemas()
+        .run()
+        .expect("compiling schema with embedded nodes");
 }
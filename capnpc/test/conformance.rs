@@ -0,0 +1,244 @@
+// Copyright (c) 2013-2014 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Conformance tests against the C++ implementation. Each test round-trips messages through the
+//! `capnp` tool: messages built here are decoded to text and re-encoded by `capnp decode` and
+//! `capnp encode`, and must come back unchanged, and messages encoded by `capnp encode` from text
+//! must read back here as written.
+//!
+//! The tool is found on the `PATH`, or at the path given by the `CAPNP` environment variable.
+//! If it cannot be run, the tests print a note and pass.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use capnp::message::{self, ReaderOptions};
+use capnp::{serialize, serialize_packed, Word};
+
+fn capnp_tool() -> Option<String> {
+    let tool = ::std::env::var("CAPNP").unwrap_or("capnp".to_string());
+    match Command::new(&tool).arg("--version").output() {
+        Ok(ref output) if output.status.success() => Some(tool),
+        _ => {
+            eprintln!("skipping conformance test: could not run `{} --version`", tool);
+            None
+        }
+    }
+}
+
+// Runs `capnp <command> [--packed] test.capnp <type_name>` with `input` on stdin.
+fn run(tool: &str, command: &str, packed: bool, type_name: &str, input: &[u8]) -> Vec<u8> {
+    let mut cmd = Command::new(tool);
+    cmd.arg(command);
+    if packed {
+        cmd.arg("--packed");
+    }
+    let mut child = cmd.arg("test.capnp").arg(type_name)
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::inherit())
+        .spawn().expect("spawning capnp");
+
+    // Feed stdin from another thread, so that a large output can't deadlock us.
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_vec();
+    let writer = ::std::thread::spawn(move || stdin.write_all(&input).expect("writing to capnp"));
+    let output = child.wait_with_output().expect("waiting for capnp");
+    writer.join().unwrap();
+    assert!(output.status.success(), "capnp {} {} failed: {}", command, type_name, output.status);
+    output.stdout
+}
+
+fn read(bytes: &[u8], packed: bool) -> message::Reader<serialize::OwnedSegments> {
+    let mut options = ReaderOptions::new();
+    options.traversal_limit_in_words(u64::max_value());
+    if packed {
+        serialize_packed::read_message(&mut &bytes[..], options).unwrap()
+    } else {
+        serialize::read_message(&mut &bytes[..], options).unwrap()
+    }
+}
+
+fn write<A: message::Allocator>(message: &message::Builder<A>, packed: bool) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    if packed {
+        serialize_packed::write_message(&mut bytes, message).unwrap();
+    } else {
+        serialize::write_message(&mut bytes, message).unwrap();
+    }
+    bytes
+}
+
+fn canonical(bytes: &[u8], packed: bool) -> Vec<Word> {
+    read(bytes, packed).canonicalize().unwrap()
+}
+
+// Passes the message through `capnp decode` and `capnp encode`, checks that it comes back
+// unchanged, and returns what came back.
+fn round_trip<A: message::Allocator>(tool: &str, type_name: &str, message: &message::Builder<A>)
+                                     -> message::Reader<serialize::OwnedSegments> {
+    for &packed in &[false, true] {
+        let bytes = write(message, packed);
+        let text = run(tool, "decode", packed, type_name, &bytes);
+        let encoded = run(tool, "encode", packed, type_name, &text);
+        assert!(canonical(&bytes, packed) == canonical(&encoded, packed),
+                "{} changed in a round trip through capnp (packed: {}). Text was:\n{}",
+                type_name, packed, String::from_utf8_lossy(&text));
+    }
+    let bytes = write(message, false);
+    read(&run(tool, "encode", false, type_name, &run(tool, "decode", false, type_name, &bytes)), false)
+}
+
+#[test]
+fn all_types() {
+    use test_capnp::test_all_types;
+    let tool = match capnp_tool() { Some(tool) => tool, None => return };
+
+    let mut message = message::Builder::new_default();
+    ::test_util::init_test_message(message.init_root());
+    let reader = round_trip(&tool, "TestAllTypes", &message);
+    ::test_util::CheckTestMessage::check_test_message(reader.get_root::<test_all_types::Reader>().unwrap());
+
+    // Every pointer is a far pointer.
+    let mut message = message::Builder::new(message::HeapAllocator::new().small_segments());
+    ::test_util::init_test_message(message.init_root());
+    let reader = round_trip(&tool, "TestAllTypes", &message);
+    ::test_util::CheckTestMessage::check_test_message(reader.get_root::<test_all_types::Reader>().unwrap());
+}
+
+#[test]
+fn defaults() {
+    use test_capnp::test_defaults;
+    let tool = match capnp_tool() { Some(tool) => tool, None => return };
+
+    let mut message = message::Builder::new_default();
+    message.init_root::<test_defaults::Builder>();
+    let text = run(&tool, "decode", false, "TestDefaults", &write(&message, false));
+    assert_eq!(String::from_utf8(text).unwrap().trim(), "()");
+
+    let bytes = run(&tool, "encode", false, "TestDefaults", b"()");
+    let reader = read(&bytes, false);
+    ::test_util::CheckTestMessage::check_test_message(reader.get_root::<test_defaults::Reader>().unwrap());
+}
+
+#[test]
+fn unions_and_groups() {
+    use test_capnp::{test_groups, test_union, test_unnamed_union, TestEnum};
+    let tool = match capnp_tool() { Some(tool) => tool, None => return };
+
+    let mut message = message::Builder::new_default();
+    {
+        let mut root: test_union::Builder = message.init_root();
+        root.reborrow().init_union0().set_u0f1s32(1234567);
+        root.reborrow().init_union1().set_u1f2sp("foo");
+        root.reborrow().init_union2().set_u2f0s1(true);
+        root.reborrow().init_union3().set_u3f0s64(-1);
+        root.set_bit0(true);
+        root.set_bit7(true);
+        root.set_byte0(255);
+    }
+    round_trip(&tool, "TestUnion", &message);
+
+    let mut message = message::Builder::new_default();
+    {
+        let mut root: test_unnamed_union::Builder = message.init_root();
+        root.set_before("before");
+        root.set_bar(321);
+        root.set_middle(7);
+    }
+    round_trip(&tool, "TestUnnamedUnion", &message);
+
+    let mut message = message::Builder::new_default();
+    {
+        let root: test_groups::Builder = message.init_root();
+        let mut baz = root.init_groups().init_baz();
+        baz.set_corge(-5);
+        baz.set_grault("grault");
+        baz.set_quz(0.5);
+        baz.set_an_enum(TestEnum::Garply);
+    }
+    round_trip(&tool, "TestGroups", &message);
+
+    // And the other way around.
+    let bytes = run(&tool, "encode", false, "TestUnion",
+                    b"(union0 = (u0f0sp = \"abc\"), union1 = (u1f1s16 = -2), bit0 = true, byte0 = 9)");
+    let reader = read(&bytes, false);
+    let root: test_union::Reader = reader.get_root().unwrap();
+    match root.get_union0().which().unwrap() {
+        test_union::union0::U0f0sp(text) => assert_eq!(text.unwrap(), "abc"),
+        _ => panic!("expected u0f0sp"),
+    }
+    match root.get_union1().which().unwrap() {
+        test_union::union1::U1f1s16(v) => assert_eq!(v, -2),
+        _ => panic!("expected u1f1s16"),
+    }
+    assert!(root.get_bit0());
+    assert_eq!(root.get_byte0(), 9);
+}
+
+#[test]
+fn nested_lists() {
+    use test_capnp::test_lists;
+    let tool = match capnp_tool() { Some(tool) => tool, None => return };
+
+    let mut message = message::Builder::new_default();
+    {
+        let mut root: test_lists::Builder = message.init_root();
+        root.reborrow().init_list0(2);
+        root.reborrow().init_list1(3).get(1).set_f(true);
+        root.reborrow().init_list64(2).get(1).set_f(u64::max_value());
+        root.reborrow().init_list_p(2).get(0).set_f("p");
+        {
+            let mut lists = root.reborrow().init_int32_list_list(3);
+            lists.reborrow().init(0, 2).set(1, -1);
+            lists.reborrow().init(2, 1).set(0, 1 << 30);
+        }
+        {
+            let mut lists = root.reborrow().init_text_list_list(2);
+            lists.reborrow().init(0, 1).set(0, "first");
+            lists.reborrow().init(1, 2).set(1, "last");
+        }
+        {
+            let mut lists = root.reborrow().init_struct_list_list(2);
+            ::test_util::init_test_message(lists.reborrow().init(1, 2).get(1));
+        }
+    }
+    round_trip(&tool, "TestLists", &message);
+}
+
+#[test]
+fn large_offsets() {
+    use test_capnp::test_all_types;
+    let tool = match capnp_tool() { Some(tool) => tool, None => return };
+
+    // The list comes first, so the pointers to everything set after it have offsets of over
+    // a million words.
+    let mut message = message::Builder::new_default();
+    {
+        let mut root: test_all_types::Builder = message.init_root();
+        let mut list = root.reborrow().init_u_int64_list(1 << 20);
+        list.set((1 << 20) - 1, 42);
+        root.set_text_field("after the list");
+        root.init_struct_field().set_data_field(b"also after the list");
+    }
+    let reader = round_trip(&tool, "TestAllTypes", &message);
+    let root: test_all_types::Reader = reader.get_root().unwrap();
+    assert_eq!(root.get_u_int64_list().unwrap().get((1 << 20) - 1), 42);
+    assert_eq!(root.get_text_field().unwrap(), "after the list");
+}
@@ -22,7 +22,11 @@
 extern crate core;
 
 extern crate capnp;
+#[cfg(test)]
+extern crate capnpc;
 
+// Generated code must be usable in crates that deny warnings.
+#[deny(warnings)]
 pub mod test_capnp {
     include!(concat!(env!("OUT_DIR"), "/test_capnp.rs"));
 }
@@ -50,9 +54,17 @@ pub mod test_in_src_prefix_dir_capnp {
   include!(concat!(env!("OUT_DIR"), "/test_in_src_prefix_dir_capnp.rs"));
 }
 
+#[deny(warnings)]
+pub mod embedded_schemas_capnp {
+    include!(concat!(env!("OUT_DIR"), "/embedded_schemas_capnp.rs"));
+}
+
 #[cfg(test)]
 mod test_util;
 
+#[cfg(test)]
+mod conformance;
+
 #[cfg(test)]
 mod tests {
     use capnp::message;
@@ -384,6 +396,283 @@ mod tests {
         assert!(complex_list_reader.get_struct_list_list().unwrap().get(0).unwrap().get(0).get_int8_field() == -1);
     }
 
+    #[test]
+    fn test_text_bytes() {
+        use test_capnp::{test_all_types, test_defaults};
+
+        let message = message::Builder::new_default();
+        let reader = message.get_root_as_reader::<test_defaults::Reader>().unwrap();
+        assert_eq!(reader.get_text_field_bytes().unwrap(), b"foo");
+
+        let mut message = message::Builder::new_default();
+        message.init_root::<test_all_types::Builder>().set_text_field("hello");
+        let reader = message.get_root_as_reader::<test_all_types::Reader>().unwrap();
+        assert_eq!(reader.get_text_field_bytes().unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_or_default_getters() {
+        use capnp::serialize;
+        use test_capnp::{test_all_types, test_defaults};
+
+        let mut message = message::Builder::new_default();
+        message.init_root::<test_all_types::Builder>().set_text_field("a\u{7f}c");
+        let mut words = serialize::write_message_to_words(&message);
+        let idx = words.windows(4).position(|w| w == b"a\x7fc\0").unwrap();
+        words[idx + 1] = 0xff;
+
+        let message = serialize::read_message_from_flat_slice(
+            &mut &words[..], message::ReaderOptions::new()).unwrap();
+        let all_types = message.get_root::<test_all_types::Reader>().unwrap();
+        assert!(all_types.get_text_field().is_err());
+        assert_eq!(all_types.get_text_field_or_default(), "");
+        assert_eq!(all_types.get_struct_list_or_default().len(), 0);
+
+        let defaults = message.get_root::<test_defaults::Reader>().unwrap();
+        assert!(defaults.get_text_field().is_err());
+        assert_eq!(defaults.get_text_field_or_default(), "foo");
+        assert_eq!(defaults.get_struct_field_or_default().get_text_field_or_default(), "baz");
+    }
+
+    #[test]
+    fn collect_struct_list_field() {
+        use capnp::struct_list;
+        use capnp::traits::HasFields;
+        use test_capnp::test_defaults;
+
+        let mut message = message::Builder::new_default();
+        {
+            let mut list = message.init_root::<::capnp::any_pointer::Builder>()
+                .initn_as::<struct_list::Builder<test_defaults::Owned>>(3);
+            list.reborrow().get(1).set_int8_field(5);
+            list.reborrow().get(1).set_bool_field(false);
+            list.reborrow().get(2).set_float64_field(0.25);
+        }
+        let list = message.get_root_as_reader::<struct_list::Reader<test_defaults::Owned>>().unwrap();
+        let fields = test_defaults::Reader::fields();
+        let field = |name| fields.iter().find(|f| f.name == name).unwrap();
+
+        assert_eq!(list.collect_field::<i8>(field("int8Field")).unwrap(), vec![-123, 5, -123]);
+        assert_eq!(list.collect_field::<bool>(field("boolField")).unwrap(), vec![true, false, true]);
+        assert_eq!(list.collect_field::<f64>(field("float64Field")).unwrap(), vec![-123e45, -123e45, 0.25]);
+        assert!(list.collect_field::<u8>(field("int32Field")).is_err());
+        assert!(list.collect_field::<u32>(field("textField")).is_err());
+    }
+
+    #[test]
+    fn fluent_builders() {
+        use test_capnp::{test_fluent, TestEnum};
+
+        let message = test_fluent::build(|b| {
+            b.name("x")
+                .scores(&[1, 2])
+                .kind(TestEnum::Bar)
+                .inner(|b| b.value(0.5))
+                .tags(&["a", "b"])
+                .pair(|b| b.x(3).y(4))
+                .type_(9)
+                .with_builder(|b| b.init_plain().set_int32_field(-1))
+        });
+        let root = message.get_root_as_reader::<test_fluent::Reader>().unwrap();
+        assert_eq!(root.get_name().unwrap(), "x");
+        assert_eq!(root.get_scores().unwrap().iter().collect::<Vec<_>>(), vec![1, 2]);
+        assert!(root.get_kind().unwrap() == TestEnum::Bar);
+        assert_eq!(root.get_inner().unwrap().get_value(), 0.5);
+        assert_eq!(root.get_tags().unwrap().get(1).unwrap(), "b");
+        match root.which().unwrap() {
+            test_fluent::Pair(pair) => assert_eq!((pair.get_x(), pair.get_y()), (3, 4)),
+            test_fluent::None(()) => panic!("expected the pair"),
+        }
+        assert_eq!(root.get_type(), 9);
+        assert_eq!(root.get_plain().unwrap().get_int32_field(), -1);
+
+        let message = test_fluent::build(|b| b.pair(|b| b.x(1)).none());
+        let root = message.get_root_as_reader::<test_fluent::Reader>().unwrap();
+        match root.which().unwrap() {
+            test_fluent::None(()) => (),
+            test_fluent::Pair(_) => panic!("expected none"),
+        }
+    }
+
+    #[test]
+    fn validate_annotated_fields() {
+        use test_capnp::test_validate;
+
+        let mut message = message::Builder::new_default();
+        {
+            let mut root = message.init_root::<test_validate::Builder>();
+            root.set_count(7);
+            root.set_name("x");
+            root.reborrow().init_tags(1).set(0, "a");
+            root.set_offset(-10);
+        }
+        message.get_root_as_reader::<test_validate::Reader>().unwrap().validate().unwrap();
+
+        let error = |message: &message::Builder<message::HeapAllocator>| {
+            message.get_root_as_reader::<test_validate::Reader>().unwrap().validate().unwrap_err().description
+        };
+
+        message.get_root::<test_validate::Builder>().unwrap().set_count(101);
+        assert_eq!(error(&message), "TestValidate.count must be in the range [1, 100]");
+        message.get_root::<test_validate::Builder>().unwrap().set_count(100);
+
+        message.get_root::<test_validate::Builder>().unwrap().set_ratio(-0.5);
+        assert_eq!(error(&message), "TestValidate.ratio must be in the range [0, inf]");
+        message.get_root::<test_validate::Builder>().unwrap().set_ratio(1e30);
+
+        message.get_root::<test_validate::Builder>().unwrap().set_offset(11);
+        assert_eq!(error(&message), "TestValidate.offset must be in the range [-10, 10]");
+        message.get_root::<test_validate::Builder>().unwrap().set_none(());
+
+        message.get_root::<test_validate::Builder>().unwrap().get_limits().set_low(-6);
+        assert_eq!(error(&message), "TestValidate.limits.low must be in the range [-5, 5]");
+        message.get_root::<test_validate::Builder>().unwrap().get_limits().set_low(0);
+
+        message.get_root::<test_validate::Builder>().unwrap().init_tags(0);
+        assert_eq!(error(&message), "TestValidate.tags must not be empty");
+
+        let message = message::Builder::new_default();
+        let root = message.get_root_as_reader::<test_validate::Reader>().unwrap();
+        assert_eq!(root.validate().unwrap_err().description, "TestValidate.count must be in the range [1, 100]");
+    }
+
+    #[test]
+    fn field_layout_constants() {
+        use capnp::private::layout::ElementSize;
+        use capnp::traits::HasFields;
+        use test_capnp::{test_all_types, test_validate};
+
+        assert_eq!(test_all_types::field_info::VOID_FIELD.element_size, ElementSize::Void);
+        assert_eq!(test_all_types::field_info::BOOL_FIELD.data_bit_offset(), Some(0));
+        assert_eq!(test_all_types::field_info::INT32_FIELD.element_size, ElementSize::FourBytes);
+        assert_eq!(test_all_types::field_info::TEXT_FIELD.element_size, ElementSize::Pointer);
+        assert_eq!(test_all_types::field_info::TEXT_FIELD.data_bit_offset(), None);
+        assert_eq!(test_all_types::field_info::STRUCT_FIELD.element_size, ElementSize::Pointer);
+
+        let int32_field = test_all_types::Reader::fields()[4];
+        assert_eq!(test_all_types::field_info::INT32_FIELD.ordinal, int32_field.ordinal.unwrap());
+        assert_eq!(test_all_types::field_info::INT32_FIELD.offset, int32_field.offset);
+        assert_eq!(test_all_types::field_info::INT32_FIELD.data_bit_offset(),
+                   Some(int32_field.offset as u64 * 32));
+
+        // Groups have their own tables.
+        assert_eq!(test_validate::limits::field_info::LOW.ordinal, 6);
+        assert_eq!(test_validate::limits::field_info::LOW.element_size, ElementSize::EightBytes);
+    }
+
+    #[test]
+    fn test_field_table() {
+        use capnp::fields::{FieldInfo, FieldValue, TypeTag};
+        use capnp::traits::HasFields;
+        use test_capnp::{test_all_types, test_defaults, test_union, TestEnum};
+
+        let fields = test_all_types::Reader::fields();
+        assert_eq!(fields.len(), 34);
+        assert_eq!(fields[12], FieldInfo { name: "textField", ordinal: Some(12), offset: 0,
+                                           type_tag: TypeTag::Text, discriminant_value: None,
+                                           default_bits: 0 });
+        assert_eq!(fields[15].type_tag, TypeTag::Enum);
+
+        let message = message::Builder::new_default();
+        let defaults = message.get_root_as_reader::<test_defaults::Reader>().unwrap();
+        match defaults.get_field_by_ordinal(4).unwrap() {
+            Some(FieldValue::Int32(n)) => assert_eq!(n, -12345678),
+            _ => panic!("expected an Int32"),
+        }
+        match defaults.get_field_by_ordinal(12).unwrap() {
+            Some(FieldValue::Text(t)) => assert_eq!(t, "foo"),
+            _ => panic!("expected Text"),
+        }
+        match defaults.get_field_by_ordinal(15).unwrap() {
+            Some(FieldValue::Enum(e)) => assert_eq!(e, TestEnum::Corge as u16),
+            _ => panic!("expected an Enum"),
+        }
+        assert!(defaults.get_field_by_ordinal(100).unwrap().is_none());
+
+        let mut message = message::Builder::new_default();
+        message.init_root::<test_union::Builder>().init_union0().set_u0f0s16(7);
+        let union = message.get_root_as_reader::<test_union::Reader>().unwrap().get_union0();
+        assert!(union.get_field_by_ordinal(4).unwrap().is_none());
+        match union.get_field_by_ordinal(7).unwrap() {
+            Some(FieldValue::Int16(n)) => assert_eq!(n, 7),
+            _ => panic!("expected an Int16"),
+        }
+    }
+
+    #[test]
+    fn test_streaming_blobs() {
+        use capnp::data;
+        use test_capnp::test_all_types;
+
+        let blob: Vec<u8> = (0..100000u32).map(|n| (n % 251) as u8).collect();
+        let mut message = message::Builder::new_default();
+        {
+            let mut root = message.init_root::<test_all_types::Builder>();
+            data::fill_from(root.reborrow().init_data_field(blob.len() as u32), &blob[..]).unwrap();
+            root.reborrow().init_text_field(5).fill_from(&b"hello"[..]).unwrap();
+            let mut text = root.init_text_field(2);
+            assert!(text.fill_from(&b"\xff\xfe"[..]).is_err());
+            assert_eq!(&*text, "\0\0");
+        }
+        let reader = message.get_root_as_reader::<test_all_types::Reader>().unwrap();
+        let data = reader.get_data_field().unwrap();
+        assert_eq!(data.chunks(4096).count(), 25);
+        assert!(data.chunks(4096).zip(blob.chunks(4096)).all(|(a, b)| a == b));
+
+        let mut message = message::Builder::new_default();
+        let root = message.init_root::<test_all_types::Builder>();
+        assert!(data::fill_from(root.init_data_field(10), &b"short"[..]).is_err());
+    }
+
+    #[test]
+    fn test_ordered_key() {
+        use capnp::ordered_key;
+        use test_capnp::test_all_types;
+
+        let keys: Vec<Vec<u8>> = [(-5, "b"), (-5, "a"), (3, "")].iter().map(|&(n, t)| {
+            let mut message = message::Builder::new_default();
+            {
+                let mut root = message.init_root::<test_all_types::Builder>();
+                root.set_int32_field(n);
+                root.set_text_field(t);
+            }
+            let reader = message.get_root_as_reader::<test_all_types::Reader>().unwrap();
+            ordered_key::encode_fields(&[4, 12], |o| reader.get_field_by_ordinal(o)).unwrap()
+        }).collect();
+        assert!(keys[1] < keys[0]);
+        assert!(keys[0] < keys[2]);
+    }
+
+    #[test]
+    fn test_display() {
+        use test_capnp::{test_all_types, test_union, TestEnum};
+
+        let mut message = message::Builder::new_default();
+        {
+            let mut root = message.init_root::<test_all_types::Builder>();
+            root.set_int32_field(-7);
+            root.set_text_field("a \"quoted\"\nline");
+            root.set_data_field(&[0x61, 0xff]);
+            root.set_enum_field(TestEnum::Garply);
+            root.reborrow().init_struct_field().set_u_int8_field(3);
+            let mut list = root.init_float64_list(2);
+            list.set(0, 1.5);
+            list.set(1, ::std::f64::NAN);
+        }
+        let text = format!("{}", message.get_root_as_reader::<test_all_types::Reader>().unwrap());
+        assert!(text.starts_with("(voidField = void, boolField = false, int8Field = 0, int16Field = 0, int32Field = -7, "));
+        assert!(text.contains(", textField = \"a \\\"quoted\\\"\\nline\", dataField = \"a\\xff\", structField = (voidField = void, "));
+        assert!(text.contains(", uInt8Field = 3, "));
+        assert!(text.ends_with(", enumField = garply, interfaceField = void, float64List = [1.5, nan])"));
+
+        let mut message = message::Builder::new_default();
+        message.init_root::<test_union::Builder>().init_union1().set_u1f1sp("x");
+        let text = format!("{}", message.get_root_as_reader::<test_union::Reader>().unwrap());
+        assert_eq!(text, "(union0 = (u0f0s0 = void), bit0 = false, union1 = (u1f1sp = \"x\"), \
+                          bit2 = false, bit3 = false, bit4 = false, bit5 = false, bit6 = false, bit7 = false, \
+                          union2 = (u2f0s1 = false), union3 = (u3f0s1 = false), byte0 = 0)");
+    }
+
     #[test]
     fn test_defaults() {
         use test_capnp::test_defaults;
@@ -441,6 +730,53 @@ mod tests {
         }
     }
 
+    #[test]
+    fn enum_field_with_default() {
+        use test_capnp::{test_defaults, TestEnum};
+
+        let mut message = message::Builder::new_default();
+        let mut root = message.init_root::<test_defaults::Builder>();
+        assert!(root.reborrow().get_enum_field() == Ok(TestEnum::Corge));
+        for &value in &[TestEnum::Foo, TestEnum::Qux, TestEnum::Corge, TestEnum::Garply] {
+            root.set_enum_field(value);
+            assert!(root.reborrow().get_enum_field() == Ok(value));
+            assert!(root.reborrow().into_reader().get_enum_field() == Ok(value));
+        }
+    }
+
+    #[test]
+    fn lenient_pointer_error_policy() {
+        use std::convert::TryInto;
+        use test_capnp::test_defaults;
+
+        let mut message = message::Builder::new_default();
+        {
+            let mut root = message.init_root::<test_defaults::Builder>();
+            root.set_text_field("bar");
+            root.set_int32_field(7);
+        }
+        let mut bytes = capnp::serialize::write_message_to_words(&message);
+
+        // Skip the segment table, then follow the root pointer to textField, the first pointer field.
+        let root_pointer = &bytes[8..16];
+        let struct_offset = (u32::from_le_bytes(root_pointer[0..4].try_into().unwrap()) >> 2) as usize;
+        let data_words = u16::from_le_bytes(root_pointer[4..6].try_into().unwrap()) as usize;
+        let text_pointer = 8 * (2 + struct_offset + data_words);
+
+        // Point the text far past the end of the segment.
+        bytes[text_pointer..text_pointer + 4].copy_from_slice(&((0x1000u32 << 2) | 1).to_le_bytes());
+
+        let strict = capnp::serialize::read_message(&mut &bytes[..], ReaderOptions::new()).unwrap();
+        let root = strict.get_root::<test_defaults::Reader>().unwrap();
+        assert!(root.get_text_field().is_err());
+
+        let mut options = ReaderOptions::new();
+        options.pointer_error_policy(message::PointerErrorPolicy::Lenient);
+        let lenient = capnp::serialize::read_message(&mut &bytes[..], options).unwrap();
+        let root = lenient.get_root::<test_defaults::Reader>().unwrap();
+        assert_eq!(root.get_text_field().unwrap(), "foo");
+        assert_eq!(root.get_int32_field(), 7);
+    }
     #[test]
     fn test_default_initialization_multi_segment() {
         use test_capnp::test_defaults;
@@ -486,6 +822,28 @@ mod tests {
         }
     }
 
+    #[test]
+    fn any_pointer_typed_accessors() {
+        use test_capnp::{test_any_pointer, test_all_types};
+
+        let mut message = message::Builder::new_default();
+        let mut root = message.init_root::<test_any_pointer::Builder>();
+
+        root.set_any_pointer_field_as("xyzzy").unwrap();
+        assert_eq!(root.reborrow().into_reader().get_any_pointer_field_as::<::capnp::text::Reader>().unwrap(),
+                   "xyzzy");
+
+        root.reborrow().init_any_pointer_field_as::<test_all_types::Builder>().set_int32_field(7);
+        let mut inner = root.reborrow().get_any_pointer_field_as::<test_all_types::Builder>().unwrap();
+        assert_eq!(inner.reborrow().get_int32_field(), 7);
+        inner.set_text_field("abc");
+
+        let reader = root.into_reader();
+        let inner: test_all_types::Reader = reader.get_any_pointer_field_as().unwrap();
+        assert_eq!(inner.get_int32_field(), 7);
+        assert_eq!(inner.get_text_field().unwrap(), "abc");
+    }
+
     #[test]
     fn test_writable_struct_pointer() {
         use test_capnp::test_big_struct;
@@ -670,6 +1028,50 @@ mod tests {
         assert_eq!(union_struct.get_union0().has_u0f0sp(), true);
     }
 
+    #[test]
+    fn union_group_switch_clears_former_variant() {
+        use test_capnp::test_groups;
+
+        let mut message = message::Builder::new_default();
+        {
+            let groups = message.init_root::<test_groups::Builder>().init_groups();
+            let mut baz = groups.init_baz();
+            baz.set_corge(7);
+            baz.set_grault("grault");
+            baz.set_garply("garply");
+            baz.set_quz(1.5);
+        }
+        {
+            // foo has fewer pointer fields than baz, so it does not overlap all of them.
+            let groups = message.get_root::<test_groups::Builder>().unwrap().get_groups();
+            let mut foo = groups.init_foo();
+            assert_eq!(foo.reborrow().get_corge(), 0);
+            assert_eq!(foo.reborrow().get_grault(), 0);
+            assert_eq!(foo.reborrow().get_garply().unwrap().len(), 0);
+            foo.set_corge(1);
+        }
+
+        let mut expected = message::Builder::new_default();
+        expected.init_root::<test_groups::Builder>().init_groups().init_foo().set_corge(1);
+
+        let reader = message.get_root_as_reader::<test_groups::Reader>().unwrap();
+        let expected_reader = expected.get_root_as_reader::<test_groups::Reader>().unwrap();
+        assert_eq!(reader.total_size().unwrap().word_count,
+                   expected_reader.total_size().unwrap().word_count);
+
+        let words = capnp::serialize::write_message_to_words(&message);
+        let round_tripped =
+            capnp::serialize::read_message(&mut &words[..], ReaderOptions::new()).unwrap();
+        assert_eq!(round_tripped.canonicalize().unwrap(),
+                   capnp::serialize::read_message(
+                       &mut &capnp::serialize::write_message_to_words(&expected)[..],
+                       ReaderOptions::new()).unwrap().canonicalize().unwrap());
+        match round_tripped.get_root::<test_groups::Reader>().unwrap().get_groups().which().unwrap() {
+            test_groups::groups::Foo(foo) => assert_eq!(foo.get_corge(), 1),
+            _ => panic!("expected Foo"),
+        }
+    }
+
     #[test]
     fn test_union_defaults() {
         use test_capnp::{test_union, test_union_defaults};
@@ -796,6 +1198,46 @@ mod tests {
         }
     }
 
+    #[test]
+    fn upgrade_struct_double_far() {
+        use test_capnp::{test_old_version, test_new_version};
+
+        let mut source = message::Builder::new_default();
+        {
+            let mut old_version = source.init_root::<test_old_version::Builder>();
+            old_version.set_old1(123);
+            old_version.set_old2("foo");
+            old_version.init_old3().set_old2("bar");
+        }
+
+        // Copy into a first segment that is exactly full, so that moving the old pointers
+        // into the upgraded struct has to leave double-far pointers behind.
+        let words = source.get_segments_for_output()[0].len() / 8;
+        let allocator = message::HeapAllocator::new()
+            .first_segment_words(words as u32)
+            .allocation_strategy(::capnp::message::AllocationStrategy::FixedSize);
+        let mut message = message::Builder::new(allocator);
+        message.set_root(source.get_root_as_reader::<test_old_version::Reader>().unwrap()).unwrap();
+        assert_eq!(message.get_segments_for_output().len(), 1);
+        {
+            let mut new_version = message.get_root::<test_new_version::Builder>().unwrap();
+            new_version.set_new1(456);
+        }
+
+        let has_double_far = message.get_segments_for_output().iter()
+            .any(|segment| segment.chunks(8).any(|word| word[0] & 7 == 6 && word[4..] != [0; 4]));
+        assert!(has_double_far);
+
+        let words = ::capnp::serialize::write_message_to_words(&message);
+        let reader = ::capnp::serialize::read_message(&mut &words[..], ReaderOptions::new()).unwrap();
+        let new_version = reader.get_root::<test_new_version::Reader>().unwrap();
+        assert_eq!(new_version.get_old1(), 123);
+        assert_eq!(new_version.get_old2().unwrap(), "foo");
+        assert_eq!(new_version.get_old3().unwrap().get_old2().unwrap(), "bar");
+        assert_eq!(new_version.get_new1(), 456);
+        assert_eq!(new_version.get_new2().unwrap(), "baz");
+    }
+
     #[test]
     fn upgrade_union() {
         use test_capnp::{test_old_union_version, test_new_union_version};
@@ -934,6 +1376,20 @@ mod tests {
             message.get_root::<test_all_types::Builder>().unwrap().into_reader());
     }
 
+    #[test]
+    fn all_types_small_segments_round_trip() {
+        use test_capnp::{test_all_types};
+
+        let mut message = message::Builder::new(message::HeapAllocator::new().small_segments());
+        ::test_util::init_test_message(message.init_root());
+        assert!(message.get_segments_for_output().len() > 20);
+
+        let words = ::capnp::serialize::write_message_to_words(&message);
+        let reader = ::capnp::serialize::read_message(&mut &words[..], ReaderOptions::new()).unwrap();
+        ::test_util::CheckTestMessage::check_test_message(
+            reader.get_root::<test_all_types::Reader>().unwrap());
+    }
+
     #[test]
     fn setters() {
         use test_capnp::{test_all_types};
@@ -1328,6 +1784,75 @@ mod tests {
         assert!(builder.set_root(reader).is_err()); // read limit exceeded
     }
 
+    #[test]
+    fn deep_copy_cyclic_struct() {
+        use test_capnp::test_any_pointer;
+
+        let words: &[capnp::Word] =
+            &[capnp::word(0,0,0,0, 0,0,1,0), // struct, one pointer
+              capnp::word(0xfc,0xff,0xff,0xff, 0,0,1,0), // struct, one pointer, pointing at itself
+            ];
+        let segment_array = &[capnp::Word::words_to_bytes(words)];
+        let message_reader =
+            message::Reader::new(message::SegmentArray::new(segment_array), ReaderOptions::new());
+        let reader = message_reader.get_root::<test_any_pointer::Reader>().unwrap();
+
+        let mut builder = ::capnp::message::Builder::new_default();
+        let error = builder.set_root(reader).unwrap_err();
+        assert!(error.description.contains("too deeply-nested"), "{}", error.description);
+        assert!(message_reader.canonicalize().is_err());
+    }
+
+    #[test]
+    fn deep_copy_shared_pointers_amplification() {
+        use test_capnp::test_any_pointer;
+
+        // A chain of structs whose two pointers both point at the next struct, so that a
+        // naive deep copy does 2^depth work.
+        let depth = 40;
+        let mut words = vec![capnp::word(0,0,0,0, 0,0,2,0)];
+        for _ in 0..depth {
+            words.push(capnp::word(4,0,0,0, 0,0,2,0));
+            words.push(capnp::word(0,0,0,0, 0,0,2,0));
+        }
+        words.push(capnp::word(0,0,0,0, 0,0,0,0));
+        words.push(capnp::word(0,0,0,0, 0,0,0,0));
+        let segment_array = &[capnp::Word::words_to_bytes(&words)];
+
+        let mut options = ReaderOptions::new();
+        options.traversal_limit_in_words(1024).nesting_limit(1000);
+        let message_reader = message::Reader::new(message::SegmentArray::new(segment_array), options);
+        let reader = message_reader.get_root::<test_any_pointer::Reader>().unwrap();
+
+        let mut builder = ::capnp::message::Builder::new_default();
+        assert!(builder.set_root(reader).is_err()); // read limit exceeded
+
+        let message_reader = message::Reader::new(message::SegmentArray::new(segment_array), options);
+        assert!(message_reader.canonicalize().is_err());
+    }
+
+    #[test]
+    fn deep_copy_malformed_double_far_landing_pad() {
+        use test_capnp::test_any_pointer;
+
+        let segment0: &[capnp::Word] =
+            &[capnp::word(6,0,0,0, 1,0,0,0)]; // double-far pointer to segment 1, word 0
+        let segment1: &[capnp::Word] =
+            &[capnp::word(0,0,0,0, 1,0,0,0), // should be a far pointer, but is a struct pointer
+              capnp::word(0,0,0,0, 1,0,0,0), // tag: struct, one data word
+            ];
+        let segment_array = &[capnp::Word::words_to_bytes(segment0),
+                              capnp::Word::words_to_bytes(segment1)];
+        let message_reader =
+            message::Reader::new(message::SegmentArray::new(segment_array), ReaderOptions::new());
+        let reader = message_reader.get_root::<capnp::any_pointer::Reader>().unwrap();
+
+        let mut builder = ::capnp::message::Builder::new_default();
+        assert!(builder.set_root(reader).is_err());
+        assert!(reader.get_as::<test_any_pointer::Reader>().is_err());
+        assert!(message_reader.canonicalize().is_err());
+    }
+
     #[test]
     fn null_struct_fields() {
         use test_capnp::{test_all_types};
@@ -1438,6 +1963,45 @@ mod tests {
         ::test_util::CheckTestMessage::check_test_message(substruct);
     }
 
+    #[test]
+    fn raw_struct_view() {
+        use capnp::raw::DecodedPointer;
+        use capnp::traits::HasStructSize;
+        use test_capnp::test_all_types;
+        let mut message = message::Builder::new_default();
+        let mut root: test_all_types::Builder = message.init_root();
+        root.set_int8_field(3);
+        root.set_text_field("foo");
+        root.reborrow().init_struct_field();
+        let struct_size = <test_all_types::Builder as HasStructSize>::struct_size();
+
+        let view = ::capnp::raw::raw_struct_view(root.into_reader());
+        assert_eq!(view.data.len(), (struct_size.data * 8) as usize);
+        assert_eq!(view.data[1], 3);
+        assert_eq!(view.pointers.len(), (struct_size.pointers * 8) as usize);
+        match view.get_pointer(0) {
+            Some(DecodedPointer::List { element_size, element_count, .. }) => {
+                assert_eq!(element_size, ::capnp::private::layout::ElementSize::Byte);
+                assert_eq!(element_count, 4);
+            }
+            p => panic!("expected a list pointer, got {:?}", p),
+        }
+        assert_eq!(view.get_pointer(1), Some(DecodedPointer::Null));
+        match view.get_pointer(2) {
+            Some(DecodedPointer::Struct { data_words, pointer_count, .. }) => {
+                assert_eq!(data_words, struct_size.data);
+                assert_eq!(pointer_count, struct_size.pointers);
+            }
+            p => panic!("expected a struct pointer, got {:?}", p),
+        }
+        assert_eq!(view.get_pointer(struct_size.pointers as usize), None);
+
+        let dump = format!("{}", view);
+        assert!(dump.starts_with("data[0]: 00 03 00 00 00 00 00 00\n"), "{}", dump);
+        assert!(dump.contains("ptr[1]: 00 00 00 00 00 00 00 00  null\n"), "{}", dump);
+        assert!(dump.contains("list (offset"), "{}", dump);
+    }
+
     #[test]
     fn struct_list_iterator() {
         use test_capnp::test_all_types;
@@ -1488,6 +2052,9 @@ mod tests {
             // The previous call pushed us to the end, even though it returned None.
             assert!(overflow_iter.next().is_none());
         }
+
+        let reversed: Vec<u32> = structs.iter().rev().map(|s| s.get_u_int32_field()).collect();
+        assert_eq!(reversed, [5, 4, 3, 2, 1, 0]);
     }
 
     #[test]
@@ -1517,4 +2084,290 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn enum_constants() {
+        use test_capnp::{renamed_struct, TestEnum, RENAMED_NESTED_ENUM_CONST, FILE_SCOPE_ENUM_CONST};
+        assert!(RENAMED_NESTED_ENUM_CONST == renamed_struct::renamed_nested_struct::RenamedDeeplyNestedEnum::Garply);
+        assert!(FILE_SCOPE_ENUM_CONST == TestEnum::Garply);
+    }
+
+    #[test]
+    fn setters_accept_shorter_lived_readers() {
+        use test_capnp::test_all_types;
+
+        let mut message = message::Builder::new_default();
+        let mut root = message.init_root::<test_all_types::Builder>();
+        {
+            // Every setter copies its argument, so the source message may be dropped
+            // while `root` is still in use.
+            let mut source = message::Builder::new_default();
+            {
+                let mut source_root = source.init_root::<test_all_types::Builder>();
+                source_root.set_text_field("text");
+                source_root.set_data_field(b"data");
+                source_root.reborrow().init_int32_list(2).set(1, 5);
+                source_root.reborrow().init_text_list(1).set(0, "element");
+                source_root.reborrow().init_struct_field().set_int8_field(-3);
+            }
+            let source_root = source.get_root_as_reader::<test_all_types::Reader>().unwrap();
+            root.set_text_field(source_root.get_text_field().unwrap());
+            root.set_data_field(source_root.get_data_field().unwrap());
+            root.set_int32_list(source_root.get_int32_list().unwrap()).unwrap();
+            root.set_text_list(source_root.get_text_list().unwrap()).unwrap();
+            root.set_struct_field(source_root.get_struct_field().unwrap()).unwrap();
+        }
+        let root = root.into_reader();
+        assert_eq!(root.get_text_field().unwrap(), "text");
+        assert_eq!(root.get_data_field().unwrap(), b"data");
+        assert_eq!(root.get_int32_list().unwrap().get(1), 5);
+        assert_eq!(root.get_text_list().unwrap().get(0).unwrap(), "element");
+        assert_eq!(root.get_struct_field().unwrap().get_int8_field(), -3);
+    }
+
+    #[test]
+    fn deeply_nested_unions() {
+        use test_capnp::test_deeply_nested_unions;
+        use test_capnp::test_deeply_nested_unions::outer::middle::inner::{self, deepest::innermost};
+
+        let mut message = message::Builder::new_default();
+        {
+            let root = message.init_root::<test_deeply_nested_unions::Builder<::capnp::text::Owned>>();
+            let mut middle = root.get_outer().init_middle();
+            middle.set_tag("tag");
+            let mut deepest = middle.get_inner().init_deepest();
+            deepest.set_value("value").unwrap();
+            deepest.get_innermost().set_generic("generic").unwrap();
+        }
+
+        let root = message.get_root_as_reader::<test_deeply_nested_unions::Reader<::capnp::text::Owned>>().unwrap();
+        let middle = match root.get_outer().which().unwrap() {
+            test_deeply_nested_unions::outer::Middle(middle) => middle,
+            _ => panic!("expected middle"),
+        };
+        assert_eq!(middle.get_tag().unwrap(), "tag");
+        let deepest = match middle.get_inner().which().unwrap() {
+            inner::Deepest(deepest) => deepest,
+            _ => panic!("expected deepest"),
+        };
+        assert_eq!(deepest.get_value().unwrap(), "value");
+        match deepest.get_innermost().which().unwrap() {
+            innermost::Generic(generic) => assert_eq!(generic.unwrap(), "generic"),
+            _ => panic!("expected generic"),
+        }
+        match root.get_plain().which().unwrap() {
+            test_deeply_nested_unions::plain::First(()) => (),
+            _ => panic!("expected first"),
+        }
+    }
+
+    #[test]
+    fn consts_module() {
+        use test_capnp::{consts, test_constants, test_consts_clash, test_consts_clash_bar, TestEnum};
+        assert_eq!(consts::GLOBAL_INT, 12345);
+        assert_eq!(consts::TEST_CONSTANTS_INT64_CONST, -123456789012345);
+        assert_eq!(consts::TEST_CONSTANTS_TEXT_CONST, "foo");
+        assert!(consts::TEST_CONSTANTS_ENUM_CONST == TestEnum::Corge);
+        assert!(consts::FILE_SCOPE_ENUM_CONST == TestEnum::Garply);
+
+        // Pointer constants are the same embedded words, decoded on access.
+        let struct_const = consts::TEST_CONSTANTS_STRUCT_CONST.get().unwrap();
+        assert_eq!(struct_const.get_text_field().unwrap(), "baz");
+        assert!(::std::ptr::eq(&consts::TEST_CONSTANTS_STRUCT_CONST, &test_constants::STRUCT_CONST));
+
+        // Constants whose flattened names collide are only reachable through their scopes.
+        assert_eq!(test_consts_clash::BAR_BAZ, 1);
+        assert_eq!(test_consts_clash_bar::BAZ, 2);
+    }
+
+    #[test]
+    fn cfg_annotations() {
+        use test_capnp::{test_cfg, test_server_cfg, TestCfgEnum};
+
+        let mut message = message::Builder::new_default();
+        message.init_root::<test_cfg::Builder>().set_enabled(true);
+        assert!(message.get_root_as_reader::<test_cfg::Reader>().unwrap().get_enabled());
+        assert!(TestCfgEnum::Bar == TestCfgEnum::Bar);
+
+        // The client side of an interface stays when its server side is configured out.
+        let _ = test_server_cfg::METHODS;
+        let _: Option<test_server_cfg::Client> = None;
+    }
+
+    #[test]
+    fn interface_method_table() {
+        use capnp::traits::HasTypeId;
+        use test_capnp::{empty_interface, test_big_struct, test_extends, test_interface};
+
+        let names: Vec<&str> = test_interface::METHODS.iter().map(|m| m.name).collect();
+        assert_eq!(names, ["foo", "bar", "baz", "bazz"]);
+
+        let foo = test_interface::METHODS[0];
+        assert_eq!(foo.interface_id, test_interface::_private::TYPE_ID);
+        assert_eq!(foo.method_id, 0);
+        assert_eq!(foo.param_type_id, test_interface::foo_params::Reader::type_id());
+        assert_eq!(foo.result_type_id, test_interface::foo_results::Reader::type_id());
+
+        // Inherited methods belong to the base interface's table.
+        let corge = test_extends::METHODS[1];
+        assert_eq!(test_extends::METHODS.len(), 3);
+        assert_eq!((corge.interface_id, corge.method_id, corge.name),
+                   (test_extends::_private::TYPE_ID, 1, "corge"));
+        assert_eq!(corge.param_type_id, test_big_struct::Reader::type_id());
+
+        assert!(empty_interface::METHODS.is_empty());
+    }
+
+    #[test]
+    fn to_dynamic_and_back() {
+        use std::path::PathBuf;
+        use capnpc::dynamic::{ToDynamic, Value};
+        use capnpc::schema_loader::SchemaLoader;
+        use test_capnp::{test_all_types, test_groups};
+
+        let request = capnpc::compiler::compile(&[PathBuf::from("test.capnp")], &[], &[]).unwrap();
+        let mut loader = SchemaLoader::new();
+        loader.load_request(request.into_reader()).unwrap();
+
+        let mut message = message::Builder::new_default();
+        let mut dynamic = message.init_root::<test_all_types::Builder>().to_dynamic(&loader).unwrap();
+        dynamic.set("uInt32Field", Value::Uint32(123)).unwrap();
+        let typed = test_all_types::Builder::from_dynamic(dynamic).unwrap();
+        assert_eq!(typed.reborrow_as_reader().get_u_int32_field(), 123);
+
+        let reader = typed.into_reader().to_dynamic(&loader).unwrap();
+        match reader.get("uInt32Field").unwrap() {
+            Value::Uint32(n) => assert_eq!(n, 123),
+            _ => panic!("expected a UInt32"),
+        }
+        assert!(test_all_types::Reader::from_dynamic(reader).is_ok());
+        assert!(test_groups::Reader::from_dynamic(reader).is_err());
+    }
+
+    #[test]
+    fn interned_text() {
+        use capnp::text;
+        use test_capnp::test_all_types;
+
+        fn build(interner: Option<&mut text::Interner>) -> message::Builder<message::HeapAllocator> {
+            // Small segments, so that some fields share text in another segment.
+            let mut message = message::Builder::new(message::HeapAllocator::new().first_segment_words(16)
+                .allocation_strategy(message::AllocationStrategy::FixedSize));
+            {
+                let root = message.init_root::<test_all_types::Builder>();
+                let mut list = root.init_struct_list(8);
+                let value = "a fairly long string that repeats";
+                match interner {
+                    Some(interner) => {
+                        for i in 0..list.len() {
+                            list.reborrow().get(i).set_text_field_interned(value, interner);
+                        }
+                        // Setting a field to the value it already shares keeps it intact.
+                        list.reborrow().get(0).set_text_field_interned(value, interner);
+                        // So does switching a field that shares text to another value.
+                        list.reborrow().get(1).set_text_field_interned("another", interner);
+                        list.reborrow().get(1).set_text_field_interned(value, interner);
+                        assert_eq!(interner.len(), 2);
+                    }
+                    None => {
+                        for i in 0..list.len() {
+                            list.reborrow().get(i).set_text_field(value);
+                        }
+                    }
+                }
+            }
+            message
+        }
+
+        let plain = build(None);
+        let mut interner = text::Interner::new();
+        let interned = build(Some(&mut interner));
+        let words = |m: &message::Builder<message::HeapAllocator>| -> usize {
+            m.get_segments_for_output().iter().map(|s| s.len() / 8).sum()
+        };
+        assert!(words(&interned) < words(&plain));
+
+        let bytes = ::capnp::serialize::write_message_to_words(&interned);
+        let reader = ::capnp::serialize::read_message_from_flat_slice(
+            &mut &bytes[..], ReaderOptions::new()).unwrap();
+        let root = reader.get_root::<test_all_types::Reader>().unwrap();
+        for element in root.get_struct_list().unwrap().iter() {
+            assert_eq!(element.get_text_field().unwrap(), "a fairly long string that repeats");
+        }
+    }
+
+    #[test]
+    fn splice_from() {
+        use capnp::{any_pointer, Word};
+        use test_capnp::test_all_types;
+        use test_util::CheckTestMessage;
+
+        let mut source = message::Builder::new_default();
+        ::test_util::init_test_message(source.init_root());
+        let bytes = capnp::serialize::write_message_to_words(&source);
+        let source_reader = capnp::serialize::read_message(&mut &bytes[..], ReaderOptions::new()).unwrap();
+        let canonical = source_reader.canonicalize().unwrap();
+        let segments = [Word::words_to_bytes(&canonical[..])];
+        let canonical_reader = message::Reader::new(message::SegmentArray::new(&segments), ReaderOptions::new());
+
+        // A small first segment makes the spliced words land in a second one, behind a far pointer.
+        let mut destination = message::Builder::new(message::HeapAllocator::new().first_segment_words(4));
+        let spliced = destination.init_root::<any_pointer::Builder>()
+            .splice_from(canonical_reader.get_root().unwrap()).unwrap();
+        assert!(spliced);
+        CheckTestMessage::check_test_message(destination.get_root::<test_all_types::Builder>().unwrap());
+        let out = capnp::serialize::write_message_to_words(&destination);
+        let out_reader = capnp::serialize::read_message(&mut &out[..], ReaderOptions::new()).unwrap();
+        assert_eq!(out_reader.canonicalize().unwrap(), canonical);
+
+        // Messages that are not canonical are deep copied instead.
+        let mut destination = message::Builder::new_default();
+        let spliced = destination.init_root::<any_pointer::Builder>()
+            .splice_from(source_reader.get_root().unwrap()).unwrap();
+        assert!(!spliced);
+        CheckTestMessage::check_test_message(destination.get_root::<test_all_types::Builder>().unwrap());
+    }
+
+    #[test]
+    fn registry_populated_by_embedded_schemas() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+        use capnp::traits::HasTypeId;
+        use capnpc::registry::{write_envelope, Registry};
+        use capnpc::schema_loader::SchemaLoader;
+        use embedded_schemas_capnp::{created, deleted};
+
+        // No schemas are loaded by hand: they all come from the generated module.
+        let mut registry = Registry::new(SchemaLoader::new());
+        registry.register_schemas(::embedded_schemas_capnp::register_schemas).unwrap();
+
+        let created_id = <created::Reader as HasTypeId>::type_id();
+        let schema = registry.get_schema(created_id).unwrap();
+        assert_eq!(schema.get_display_name().unwrap(), "embedded-schemas.capnp:Created");
+        assert_eq!(created::schema().unwrap().get_as::<capnpc::schema_capnp::node::Reader>().unwrap().get_id(),
+                   created_id);
+        let group = registry.get_schema(<deleted::reason::Reader as HasTypeId>::type_id()).unwrap();
+        assert_eq!(group.get_display_name().unwrap(), "embedded-schemas.capnp:Deleted.reason");
+        assert!(registry.get_loader().find("embedded-schemas.capnp:Created.Detail").is_some());
+
+        let names = Rc::new(RefCell::new(Vec::new()));
+        let names2 = names.clone();
+        registry.handle(created_id, move |root| {
+            let created: created::Reader = root.get_as()?;
+            names2.borrow_mut().push(created.get_name()?.to_string());
+            Ok(())
+        });
+
+        let mut message = message::Builder::new_default();
+        message.init_root::<created::Builder>().set_name("a");
+        let bytes = write_envelope(created_id, &message);
+        assert_eq!(registry.dispatch(&mut &bytes[..]).unwrap(), created_id);
+        assert_eq!(*names.borrow(), vec!["a".to_string()]);
+
+        let mut message = message::Builder::new_default();
+        message.init_root::<deleted::Builder>().set_id(7);
+        let bytes = write_envelope(<deleted::Reader as HasTypeId>::type_id(), &message);
+        let error = registry.dispatch(&mut &bytes[..]).unwrap_err();
+        assert!(error.description.contains("embedded-schemas.capnp:Deleted"), "{}", error.description);
+    }
 }
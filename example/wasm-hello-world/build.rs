@@ -1,5 +1,6 @@
 fn main() {
     ::capnpc::CompilerCommand::new()
+        .builtin_compiler()
         .file("wasm-hello-world.capnp")
         .run()
         .expect("compiling schema");
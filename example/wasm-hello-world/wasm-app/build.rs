@@ -1,5 +1,6 @@
 fn main() {
     ::capnpc::CompilerCommand::new()
+        .builtin_compiler()
         .file("../wasm-hello-world.capnp")
         .src_prefix("../")
         .run()